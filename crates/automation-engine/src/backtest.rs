@@ -0,0 +1,435 @@
+//! Backtesting automations against recorded history
+//!
+//! Replays past network events (and, for schedule triggers, the times the
+//! schedule would have fired) through an automation's trigger and
+//! conditions to show when it would have run. Time-based conditions are
+//! evaluated at the historical time; device and presence conditions can only
+//! be checked against the current state, which the report points out.
+//! Times in the report are in the configured time zone.
+
+use crate::clock::Zone;
+use crate::error::AutomationError;
+use crate::evaluator::ConditionEvaluator;
+use crate::model::{Automation, Condition, ScheduleSpec, Trigger};
+use crate::template;
+use chrono::{DateTime, Datelike, FixedOffset, Local, Utc};
+use cron::Schedule;
+use serde::Serialize;
+use serde_json::json;
+use std::str::FromStr;
+use zigbee_core::NetworkEvent;
+
+/// Most firings listed in a report
+pub const MAX_FIRINGS: usize = 1000;
+
+/// A recorded network event
+#[derive(Debug, Clone)]
+pub struct HistoricalEvent {
+    pub timestamp: DateTime<Utc>,
+    pub event: NetworkEvent,
+}
+
+/// A time the trigger would have matched
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestFiring {
+    pub timestamp: DateTime<FixedOffset>,
+    /// What matched (`device_state`, `button`, `occupancy`, `sensor_value`,
+    /// `schedule`)
+    pub reason: &'static str,
+    /// Whether the conditions passed, i.e. the actions would have run
+    pub conditions_met: bool,
+}
+
+/// Result of a backtest
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestReport {
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+    /// Recorded events replayed
+    pub events_replayed: usize,
+    /// Whether there were more recorded events in the window than could be
+    /// replayed, so the newest were left out
+    pub events_truncated: bool,
+    /// Times the trigger matched
+    pub triggered: usize,
+    /// Times the trigger matched and the conditions passed
+    pub fired: usize,
+    /// Trigger matches, oldest first (at most [`MAX_FIRINGS`])
+    pub firings: Vec<BacktestFiring>,
+    /// Whether `firings` was cut off at [`MAX_FIRINGS`]
+    pub truncated: bool,
+    /// Conditions that were evaluated against the current state
+    pub current_state_conditions: Vec<&'static str>,
+    /// Zone the times are reported in
+    #[serde(skip)]
+    zone: Zone,
+}
+
+impl BacktestReport {
+    fn new(zone: Zone, from: DateTime<Utc>, to: DateTime<Utc>, conditions: &[Condition]) -> Self {
+        let mut current_state_conditions = Vec::new();
+        collect_stateful(conditions, &mut current_state_conditions);
+        Self {
+            from: zone.offset_time(from),
+            to: zone.offset_time(to),
+            events_replayed: 0,
+            events_truncated: false,
+            triggered: 0,
+            fired: 0,
+            firings: Vec::new(),
+            truncated: false,
+            current_state_conditions,
+            zone,
+        }
+    }
+
+    fn record(&mut self, timestamp: DateTime<Utc>, reason: &'static str, conditions_met: bool) {
+        self.triggered += 1;
+        if conditions_met {
+            self.fired += 1;
+        }
+        if self.firings.len() < MAX_FIRINGS {
+            self.firings.push(BacktestFiring {
+                timestamp: self.zone.offset_time(timestamp),
+                reason,
+                conditions_met,
+            });
+        } else {
+            self.truncated = true;
+        }
+    }
+}
+
+/// Names of conditions that can't be evaluated historically
+fn collect_stateful(conditions: &[Condition], out: &mut Vec<&'static str>) {
+    for condition in conditions {
+        let name = match condition {
+            Condition::DeviceAvailable { .. } => "device_available",
+            Condition::DeviceState { .. } => "device_state",
+            Condition::SensorValue { .. } => "sensor_value",
+            Condition::LastTriggered { .. } => "last_triggered",
+            Condition::Presence { .. } => "presence",
+            Condition::Script { .. } => "script",
+            Condition::And { conditions } | Condition::Or { conditions } => {
+                collect_stateful(conditions, out);
+                continue;
+            }
+            Condition::Not { condition } => {
+                collect_stateful(std::slice::from_ref(condition), out);
+                continue;
+            }
+            Condition::TimeRange { .. } | Condition::DayOfWeek { .. } => continue,
+        };
+        if !out.contains(&name) {
+            out.push(name);
+        }
+    }
+}
+
+/// Times a schedule running in `zone` would have fired in `[from, to]`,
+/// oldest first
+///
+/// Interval schedules are counted from `from`, since they run relative to
+/// when the hub started.
+pub(crate) fn schedule_times(
+    spec: &ScheduleSpec,
+    zone: Zone,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<DateTime<Utc>>, AutomationError> {
+    let mut times = Vec::new();
+    match spec {
+        ScheduleSpec::Interval { seconds } => {
+            if *seconds == 0 {
+                return Err(AutomationError::InvalidTrigger(
+                    "interval must be at least one second".to_string(),
+                ));
+            }
+            let step = chrono::Duration::seconds(i64::try_from(*seconds).unwrap_or(i64::MAX));
+            let mut at = from + step;
+            while at <= to && times.len() <= MAX_FIRINGS {
+                times.push(at);
+                at += step;
+            }
+        }
+        ScheduleSpec::TimeOfDay { time, days } => {
+            let target = crate::clock::parse_time(time)
+                .ok_or_else(|| AutomationError::InvalidTimeFormat(time.clone()))?;
+            let mut date = zone.date(from);
+            while date <= zone.date(to) && times.len() <= MAX_FIRINGS {
+                let weekday =
+                    u8::try_from(date.weekday().num_days_from_sunday()).expect("weekday is 0-6");
+                if days.is_empty() || days.contains(&weekday) {
+                    if let Some(at) = zone.at(date, target) {
+                        if at >= from && at <= to {
+                            times.push(at);
+                        }
+                    }
+                }
+                date = date.succ_opt().expect("date in range");
+            }
+        }
+        ScheduleSpec::Cron { expression } => {
+            let schedule = Schedule::from_str(expression)
+                .map_err(|e| AutomationError::InvalidCron(format!("{expression}: {e}")))?;
+            let mut after = from;
+            while times.len() <= MAX_FIRINGS {
+                match zone.next_cron(&schedule, after) {
+                    Some(at) if at <= to => {
+                        times.push(at);
+                        after = at;
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+    Ok(times)
+}
+
+/// Replay `events` and the automation's schedule through its trigger and
+/// conditions
+///
+/// `fires` decides whether a network event fires the trigger, `reverts`
+/// whether it ends the state a trigger with a `for_duration` waits on.
+/// Schedules run in `zone`; conditions are checked, and times reported, in
+/// the evaluator's.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run(
+    automation: &Automation,
+    evaluator: &ConditionEvaluator,
+    zone: Zone,
+    mut fires: impl FnMut(&Automation, &NetworkEvent) -> bool,
+    reverts: impl Fn(&Trigger, &NetworkEvent) -> bool,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    events: impl IntoIterator<Item = HistoricalEvent>,
+) -> Result<BacktestReport, AutomationError> {
+    let mut report = BacktestReport::new(evaluator.zone(), from, to, &automation.conditions);
+    let local = |at: DateTime<Utc>| at.with_timezone(&Local);
+
+    let reason = match &automation.trigger {
+        Trigger::DeviceState { .. } => "device_state",
+        Trigger::Button { .. } => "button",
+        Trigger::Occupancy { .. } => "occupancy",
+        Trigger::SensorValue { .. } => "sensor_value",
+        Trigger::Schedule { schedule, .. } => {
+            let context = json!({ "trigger": { "reason": "schedule" } });
+            for at in schedule_times(schedule, zone, from, to)? {
+                let met = evaluator.evaluate_all_at(&automation.conditions, local(at), &context)?;
+                report.record(at, "schedule", met);
+            }
+            return Ok(report);
+        }
+        Trigger::Presence { .. }
+        | Trigger::HubStartup { .. }
+        | Trigger::Webhook { .. }
+        | Trigger::Mqtt { .. }
+        | Trigger::Manual => {
+            return Err(AutomationError::InvalidTrigger(
+                "only device, button, occupancy, sensor value and schedule triggers can be backtested"
+                    .to_string(),
+            ));
+        }
+    };
+
+    let mut events: Vec<HistoricalEvent> = events
+        .into_iter()
+        .filter(|e| e.timestamp >= from && e.timestamp <= to)
+        .collect();
+    events.sort_by_key(|e| e.timestamp);
+    let hold = automation
+        .trigger
+        .for_duration()
+        .and_then(|d| chrono::Duration::from_std(d).ok());
+    // When a state held since `since` has held long enough; never if that's
+    // past the end of time
+    let held_at = |since: DateTime<Utc>| hold.and_then(|hold| since.checked_add_signed(hold));
+    // When the trigger state started to hold, and the run's context
+    let mut holding: Option<(DateTime<Utc>, serde_json::Value)> = None;
+    for event in &events {
+        report.events_replayed += 1;
+        if let Some((since, context)) = &holding {
+            match held_at(*since) {
+                Some(at) if at <= event.timestamp => {
+                    let met =
+                        evaluator.evaluate_all_at(&automation.conditions, local(at), context)?;
+                    report.record(at, reason, met);
+                    holding = None;
+                }
+                _ if reverts(&automation.trigger, &event.event) => holding = None,
+                _ => {}
+            }
+        }
+        if fires(automation, &event.event) {
+            if hold.is_some() {
+                holding.get_or_insert_with(|| {
+                    (
+                        event.timestamp,
+                        template::event_context(reason, &event.event),
+                    )
+                });
+                continue;
+            }
+            let context = template::event_context(reason, &event.event);
+            let met = evaluator.evaluate_all_at(
+                &automation.conditions,
+                local(event.timestamp),
+                &context,
+            )?;
+            report.record(event.timestamp, reason, met);
+        }
+    }
+    if let Some((since, context)) = holding {
+        if let Some(at) = held_at(since).filter(|at| *at <= to) {
+            let met = evaluator.evaluate_all_at(&automation.conditions, local(at), &context)?;
+            report.record(at, reason, met);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::request;
+    use chrono::TimeZone;
+
+    fn at(h: u32, m: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(2024, 6, 3, h, m, 0) // a Monday
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_time_of_day_schedule_times() {
+        let spec = ScheduleSpec::TimeOfDay {
+            time: "07:30".to_string(),
+            days: vec![1, 2], // Monday, Tuesday
+        };
+        let from = at(8, 0);
+        let to = from + chrono::Duration::days(7);
+        let times = schedule_times(&spec, Zone::Local, from, to).unwrap();
+        // Monday 3rd is already past 07:30; Tuesday 4th and Monday 10th fire
+        assert_eq!(times.len(), 2);
+        assert_eq!(Zone::Local.date(times[0]).day(), 4);
+        assert_eq!(Zone::Local.date(times[1]).day(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_held_state_fires_after_duration() {
+        let automation = Automation::from_request(request(
+            "Lights left on",
+            Trigger::DeviceState {
+                device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                endpoint: None,
+                state_change: crate::model::StateChange::TurnedOn,
+                for_duration: Some(3600),
+            },
+            Vec::new(),
+        ));
+        let presence =
+            crate::presence::PresenceTracker::new(std::path::Path::new("/nonexistent")).await;
+        let stats = crate::stats::StatsStore::new(std::path::Path::new("/nonexistent")).await;
+        let evaluator = ConditionEvaluator::new(
+            None,
+            std::sync::Arc::new(presence),
+            std::sync::Arc::new(stats),
+        );
+        let switched = |h, m, state_on| HistoricalEvent {
+            timestamp: at(h, m),
+            event: NetworkEvent::DeviceStateChanged {
+                ieee_address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+                endpoint: 1,
+                state_on,
+            },
+        };
+        // On for 30 minutes, then on from 19:00 until 21:00
+        let events = vec![
+            switched(18, 0, true),
+            switched(18, 30, false),
+            switched(19, 0, true),
+            switched(19, 10, true),
+            switched(21, 0, false),
+        ];
+        let report = run(
+            &automation,
+            &evaluator,
+            Zone::Local,
+            |automation, event| {
+                crate::AutomationEngine::trigger_matches(&automation.trigger, event)
+            },
+            crate::AutomationEngine::trigger_reverts,
+            at(0, 0),
+            at(23, 0),
+            events,
+        )
+        .unwrap();
+        assert_eq!(report.triggered, 1);
+        assert_eq!(report.firings[0].timestamp, at(20, 0));
+    }
+
+    #[tokio::test]
+    async fn test_times_reported_in_configured_zone() {
+        let automation = Automation::from_request(request(
+            "Morning",
+            Trigger::Schedule {
+                schedule: ScheduleSpec::TimeOfDay {
+                    time: "07:00".to_string(),
+                    days: Vec::new(),
+                },
+                catch_up_seconds: None,
+                timezone: None,
+            },
+            Vec::new(),
+        ));
+        let presence =
+            crate::presence::PresenceTracker::new(std::path::Path::new("/nonexistent")).await;
+        let stats = crate::stats::StatsStore::new(std::path::Path::new("/nonexistent")).await;
+        let madrid = Zone::parse("Europe/Madrid").unwrap();
+        let evaluator = ConditionEvaluator::new(
+            None,
+            std::sync::Arc::new(presence),
+            std::sync::Arc::new(stats),
+        )
+        .with_zone(madrid);
+        let from = Utc.with_ymd_and_hms(2024, 6, 3, 0, 0, 0).unwrap();
+        let report = run(
+            &automation,
+            &evaluator,
+            madrid,
+            |_, _| false,
+            crate::AutomationEngine::trigger_reverts,
+            from,
+            from + chrono::Duration::days(1),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(report.from.offset().local_minus_utc(), 2 * 3600);
+        assert_eq!(report.firings.len(), 1);
+        assert_eq!(
+            report.firings[0].timestamp.to_rfc3339(),
+            "2024-06-03T07:00:00+02:00"
+        );
+    }
+
+    #[test]
+    fn test_collect_stateful_conditions() {
+        let conditions = vec![
+            Condition::TimeRange {
+                start: "22:00".to_string(),
+                end: "06:00".to_string(),
+            },
+            Condition::Not {
+                condition: Box::new(Condition::Presence {
+                    person: None,
+                    home: true,
+                }),
+            },
+        ];
+        let mut names = Vec::new();
+        collect_stateful(&conditions, &mut names);
+        assert_eq!(names, vec!["presence"]);
+    }
+}
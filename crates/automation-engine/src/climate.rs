@@ -0,0 +1,609 @@
+//! Climate schedules
+//!
+//! A climate schedule is a weekly grid of heating setpoints applied to one or
+//! more thermostats/TRVs. The active setpoint is re-evaluated every minute and
+//! written only when it changes, so manual adjustments on the device stick
+//! until the next period starts. Away mode overrides every schedule with its
+//! away setpoint.
+//!
+//! A target can be linked to a room sensor. Valves that accept an external
+//! temperature (see [`zigbee_core::quirks`]) then regulate on the sensor's
+//! reading instead of the air next to the radiator; it is re-sent every
+//! [`ROOM_TEMPERATURE_INTERVAL`], or sooner when it moves.
+
+use crate::clock::Zone;
+use crate::error::AutomationError;
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Timelike};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::Notify;
+use zigbee_core::poll_control::Dispatch;
+use zigbee_core::{SensorKind, ZigbeeNetwork};
+
+/// Lowest setpoint accepted in a schedule (°C)
+const MIN_SETPOINT: f32 = 5.0;
+/// Highest setpoint accepted in a schedule (°C)
+const MAX_SETPOINT: f32 = 35.0;
+
+/// How often a room sensor's temperature is re-sent to its valve
+pub const ROOM_TEMPERATURE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Change (hundredths of a degree) that is sent without waiting for the
+/// interval
+const ROOM_TEMPERATURE_CHANGE: u16 = 30;
+/// Least time between two writes to a valve
+const ROOM_TEMPERATURE_MIN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A weekly heating schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClimateSchedule {
+    /// Unique identifier
+    pub id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Whether the schedule is applied
+    pub enabled: bool,
+    /// Thermostats controlled by this schedule
+    pub targets: Vec<ClimateTarget>,
+    /// Setpoint periods; each applies from its start time until the next period
+    pub periods: Vec<SetpointPeriod>,
+    /// Setpoint used while away mode is on (°C)
+    pub away_setpoint: f32,
+    /// Creation timestamp (ISO 8601)
+    pub created_at: String,
+    /// Last modification timestamp
+    pub updated_at: String,
+}
+
+/// A thermostat controlled by a schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClimateTarget {
+    /// IEEE address of the device
+    pub device_ieee: String,
+    /// Thermostat cluster endpoint
+    #[serde(default = "default_endpoint")]
+    pub endpoint: u8,
+    /// IEEE address of a temperature sensor in the same room, whose reading
+    /// is sent to the valve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_sensor: Option<String>,
+}
+
+fn default_endpoint() -> u8 {
+    1
+}
+
+/// One cell of the weekly grid
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetpointPeriod {
+    /// Days of week (0=Sunday, 1=Monday, ..., 6=Saturday)
+    /// Empty means every day
+    #[serde(default)]
+    pub days: Vec<u8>,
+    /// Start time in HH:MM format (24-hour)
+    pub start: String,
+    /// Heating setpoint (°C)
+    pub setpoint: f32,
+}
+
+/// Request to create a climate schedule
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateClimateScheduleRequest {
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub targets: Vec<ClimateTarget>,
+    pub periods: Vec<SetpointPeriod>,
+    #[serde(default = "default_away_setpoint")]
+    pub away_setpoint: f32,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_away_setpoint() -> f32 {
+    16.0
+}
+
+/// Request to update a climate schedule
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpdateClimateScheduleRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    #[serde(default)]
+    pub targets: Option<Vec<ClimateTarget>>,
+    #[serde(default)]
+    pub periods: Option<Vec<SetpointPeriod>>,
+    #[serde(default)]
+    pub away_setpoint: Option<f32>,
+}
+
+impl ClimateSchedule {
+    /// Create a new schedule from a create request
+    #[must_use]
+    pub fn from_request(request: CreateClimateScheduleRequest) -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: request.name,
+            enabled: request.enabled,
+            targets: request.targets,
+            periods: request.periods,
+            away_setpoint: request.away_setpoint,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    /// Apply an update request to this schedule
+    pub fn apply_update(&mut self, update: UpdateClimateScheduleRequest) {
+        if let Some(name) = update.name {
+            self.name = name;
+        }
+        if let Some(enabled) = update.enabled {
+            self.enabled = enabled;
+        }
+        if let Some(targets) = update.targets {
+            self.targets = targets;
+        }
+        if let Some(periods) = update.periods {
+            self.periods = periods;
+        }
+        if let Some(away_setpoint) = update.away_setpoint {
+            self.away_setpoint = away_setpoint;
+        }
+        self.updated_at = chrono::Utc::now().to_rfc3339();
+    }
+
+    /// Check times, days and setpoint ranges
+    #[allow(clippy::missing_errors_doc)]
+    pub fn validate(&self) -> Result<(), AutomationError> {
+        check_setpoint(self.away_setpoint)?;
+        for period in &self.periods {
+            NaiveTime::parse_from_str(&period.start, "%H:%M")
+                .map_err(|_| AutomationError::InvalidTimeFormat(period.start.clone()))?;
+            if let Some(day) = period.days.iter().find(|&&d| d > 6) {
+                return Err(AutomationError::InvalidClimateSchedule(format!(
+                    "invalid day of week {day}"
+                )));
+            }
+            check_setpoint(period.setpoint)?;
+        }
+        Ok(())
+    }
+
+    /// Setpoint in effect at `now` (the most recent period start, looking back up to a week)
+    #[must_use]
+    pub fn active_setpoint(&self, now: NaiveDateTime) -> Option<f32> {
+        let today = u8::try_from(now.weekday().num_days_from_sunday()).unwrap_or(0);
+        let time = now.time();
+
+        let periods: Vec<(NaiveTime, &SetpointPeriod)> = self
+            .periods
+            .iter()
+            .filter_map(|p| Some((NaiveTime::parse_from_str(&p.start, "%H:%M").ok()?, p)))
+            .collect();
+
+        for days_back in 0..=7u8 {
+            let day = (today + 7 - days_back % 7) % 7;
+            let latest = periods
+                .iter()
+                .filter(|(_, p)| p.days.is_empty() || p.days.contains(&day))
+                .filter(|(start, _)| match days_back {
+                    0 => *start <= time,
+                    // A full week back only covers what today hasn't reached yet
+                    7 => *start > time,
+                    _ => true,
+                })
+                .max_by_key(|(start, _)| *start);
+            if let Some((_, period)) = latest {
+                return Some(period.setpoint);
+            }
+        }
+        None
+    }
+}
+
+/// Whether a room temperature has to be sent, given the last one sent and
+/// how long ago
+fn room_temperature_due(last: Option<(Option<i16>, Duration)>, centi_degrees: i16) -> bool {
+    match last {
+        None => true,
+        Some((_, ago)) if ago >= ROOM_TEMPERATURE_INTERVAL => true,
+        Some((_, ago)) if ago < ROOM_TEMPERATURE_MIN_INTERVAL => false,
+        Some((Some(sent), _)) => centi_degrees.abs_diff(sent) >= ROOM_TEMPERATURE_CHANGE,
+        // A failed write waits out the full interval
+        Some((None, _)) => false,
+    }
+}
+
+fn check_setpoint(setpoint: f32) -> Result<(), AutomationError> {
+    if (MIN_SETPOINT..=MAX_SETPOINT).contains(&setpoint) {
+        Ok(())
+    } else {
+        Err(AutomationError::InvalidClimateSchedule(format!(
+            "setpoint {setpoint} outside {MIN_SETPOINT}-{MAX_SETPOINT}°C"
+        )))
+    }
+}
+
+/// On-disk format of `climate.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ClimateFile {
+    #[serde(default)]
+    away: bool,
+    #[serde(default)]
+    schedules: Vec<ClimateSchedule>,
+}
+
+/// Manages climate schedules and applies their setpoints
+pub struct ClimateScheduler {
+    schedules: DashMap<String, ClimateSchedule>,
+    away: AtomicBool,
+    network: Option<Arc<ZigbeeNetwork>>,
+    /// Last setpoint written per target, in hundredths of a degree
+    applied: DashMap<(String, u8), i16>,
+    /// Last room temperature sent per target, in hundredths of a degree
+    /// (`None` if sending failed)
+    fed: DashMap<(String, u8), (Option<i16>, Instant)>,
+    /// Wakes the apply loop after a change
+    changed: Notify,
+    data_path: PathBuf,
+}
+
+impl ClimateScheduler {
+    /// Create a scheduler and load `climate.json`
+    pub async fn new(network: Option<Arc<ZigbeeNetwork>>, data_dir: &Path) -> Self {
+        let scheduler = Self {
+            schedules: DashMap::new(),
+            away: AtomicBool::new(false),
+            network,
+            applied: DashMap::new(),
+            fed: DashMap::new(),
+            changed: Notify::new(),
+            data_path: data_dir.join("climate.json"),
+        };
+        scheduler.load().await;
+        scheduler
+    }
+
+    async fn load(&self) {
+        let file = match fs::read_to_string(&self.data_path).await {
+            Ok(contents) => match serde_json::from_str::<ClimateFile>(&contents) {
+                Ok(file) => file,
+                Err(e) => {
+                    tracing::warn!("Failed to parse climate file {:?}: {}", self.data_path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!("Failed to read climate file {:?}: {}", self.data_path, e);
+                return;
+            }
+        };
+
+        tracing::info!(
+            "Loaded {} climate schedules (away: {})",
+            file.schedules.len(),
+            file.away
+        );
+        self.away.store(file.away, Ordering::Relaxed);
+        for schedule in file.schedules {
+            self.schedules.insert(schedule.id.clone(), schedule);
+        }
+    }
+
+    async fn save(&self) -> Result<(), AutomationError> {
+        let file = ClimateFile {
+            away: self.is_away(),
+            schedules: self.list(),
+        };
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string_pretty(&file)?;
+        let tmp_path = self.data_path.with_extension("json.tmp");
+        fs::write(&tmp_path, &json).await?;
+        fs::rename(&tmp_path, &self.data_path).await?;
+        Ok(())
+    }
+
+    /// Start applying schedules in the background
+    pub fn start(self: &Arc<Self>) {
+        let scheduler = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                scheduler.apply().await;
+
+                // Re-evaluate at the top of the next minute, or right after a change
+                let wait = 60 - u64::from(Local::now().second());
+                tokio::select! {
+                    () = tokio::time::sleep(std::time::Duration::from_secs(wait)) => {}
+                    () = scheduler.changed.notified() => {}
+                }
+            }
+        });
+    }
+
+    /// Write the active setpoint to every target whose setpoint changed
+    async fn apply(&self) {
+        let Some(network) = &self.network else {
+            return;
+        };
+        self.feed_room_temperatures(network).await;
+        let now = Zone::configured().now();
+        let away = self.is_away();
+
+        for schedule in self.list() {
+            if !schedule.enabled {
+                continue;
+            }
+            let setpoint = if away {
+                Some(schedule.away_setpoint)
+            } else {
+                schedule.active_setpoint(now)
+            };
+            let Some(setpoint) = setpoint else {
+                continue;
+            };
+            #[allow(clippy::cast_possible_truncation)]
+            let centi_degrees = (setpoint * 100.0).round() as i16;
+
+            for target in &schedule.targets {
+                let key = (target.device_ieee.clone(), target.endpoint);
+                if self.applied.get(&key).is_some_and(|v| *v == centi_degrees) {
+                    continue;
+                }
+                let Ok(ieee) = crate::ieee::parse_ieee_address(&target.device_ieee) else {
+                    tracing::warn!(
+                        "Climate schedule '{}' has invalid target {}",
+                        schedule.name,
+                        target.device_ieee
+                    );
+                    continue;
+                };
+                match network
+                    .set_heating_setpoint(&ieee, target.endpoint, setpoint)
+                    .await
+                {
+                    Ok(dispatch) => {
+                        tracing::info!(
+                            "Climate schedule '{}' {} {} to {:.1}°C",
+                            schedule.name,
+                            if dispatch == Dispatch::Queued {
+                                "queued setting"
+                            } else {
+                                "set"
+                            },
+                            target.device_ieee,
+                            setpoint
+                        );
+                        self.applied.insert(key, centi_degrees);
+                    }
+                    Err(e) => tracing::warn!(
+                        "Climate schedule '{}' failed to set {}: {}",
+                        schedule.name,
+                        target.device_ieee,
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Send linked room sensors' temperatures to their valves when due
+    async fn feed_room_temperatures(&self, network: &ZigbeeNetwork) {
+        for schedule in self.list() {
+            if !schedule.enabled {
+                continue;
+            }
+            for target in &schedule.targets {
+                let Some(sensor) = &target.room_sensor else {
+                    continue;
+                };
+                let (Ok(ieee), Ok(sensor_ieee)) = (
+                    crate::ieee::parse_ieee_address(&target.device_ieee),
+                    crate::ieee::parse_ieee_address(sensor),
+                ) else {
+                    continue;
+                };
+                // An offline sensor's last reading goes stale; let the valve
+                // fall back to its own
+                let Some(celsius) = network
+                    .get_device(&sensor_ieee)
+                    .filter(|d| d.available)
+                    .and_then(|d| d.sensor_values.get(&SensorKind::Temperature).copied())
+                else {
+                    continue;
+                };
+                #[allow(clippy::cast_possible_truncation)]
+                let centi_degrees = (celsius * 100.0).round() as i16;
+
+                let key = (target.device_ieee.clone(), target.endpoint);
+                let last = self.fed.get(&key).map(|v| (v.0, v.1.elapsed()));
+                if !room_temperature_due(last, centi_degrees) {
+                    continue;
+                }
+                match network
+                    .set_external_temperature(&ieee, target.endpoint, celsius)
+                    .await
+                {
+                    Ok(_) => {
+                        self.fed.insert(key, (Some(centi_degrees), Instant::now()));
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Climate schedule '{}' failed to send room temperature to {}: {}",
+                            schedule.name,
+                            target.device_ieee,
+                            e
+                        );
+                        // Don't retry every minute (e.g. a valve without support)
+                        self.fed.insert(key, (None, Instant::now()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get all schedules
+    #[must_use]
+    pub fn list(&self) -> Vec<ClimateSchedule> {
+        self.schedules.iter().map(|r| r.value().clone()).collect()
+    }
+
+    /// Get a schedule by ID
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<ClimateSchedule> {
+        self.schedules.get(id).map(|r| r.value().clone())
+    }
+
+    /// Whether away mode is on
+    #[must_use]
+    pub fn is_away(&self) -> bool {
+        self.away.load(Ordering::Relaxed)
+    }
+
+    /// Turn away mode on or off
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_away(&self, away: bool) -> Result<(), AutomationError> {
+        self.away.store(away, Ordering::Relaxed);
+        self.save().await?;
+        self.changed.notify_one();
+        tracing::info!("Climate away mode {}", if away { "on" } else { "off" });
+        Ok(())
+    }
+
+    /// Create a new schedule
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn create(
+        &self,
+        request: CreateClimateScheduleRequest,
+    ) -> Result<ClimateSchedule, AutomationError> {
+        let schedule = ClimateSchedule::from_request(request);
+        schedule.validate()?;
+
+        self.schedules.insert(schedule.id.clone(), schedule.clone());
+        self.save().await?;
+        self.changed.notify_one();
+
+        tracing::info!(
+            "Created climate schedule: {} ({})",
+            schedule.name,
+            schedule.id
+        );
+        Ok(schedule)
+    }
+
+    /// Update a schedule
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn update(
+        &self,
+        id: &str,
+        request: UpdateClimateScheduleRequest,
+    ) -> Result<ClimateSchedule, AutomationError> {
+        let mut schedule = self
+            .get(id)
+            .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
+        schedule.apply_update(request);
+        schedule.validate()?;
+
+        self.schedules.insert(id.to_string(), schedule.clone());
+        self.save().await?;
+        self.changed.notify_one();
+
+        tracing::info!("Updated climate schedule: {}", id);
+        Ok(schedule)
+    }
+
+    /// Delete a schedule
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn delete(&self, id: &str) -> Result<ClimateSchedule, AutomationError> {
+        let (_, schedule) = self
+            .schedules
+            .remove(id)
+            .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
+        self.save().await?;
+
+        tracing::info!("Deleted climate schedule: {} ({})", schedule.name, id);
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn period(days: &[u8], start: &str, setpoint: f32) -> SetpointPeriod {
+        SetpointPeriod {
+            days: days.to_vec(),
+            start: start.to_string(),
+            setpoint,
+        }
+    }
+
+    fn schedule(periods: Vec<SetpointPeriod>) -> ClimateSchedule {
+        ClimateSchedule::from_request(CreateClimateScheduleRequest {
+            name: "test".to_string(),
+            enabled: true,
+            targets: Vec::new(),
+            periods,
+            away_setpoint: 16.0,
+        })
+    }
+
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        // 2024-06-02 is a Sunday
+        NaiveDate::from_ymd_opt(2024, 6, 2 + day)
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    #[test]
+    fn test_active_setpoint() {
+        let s = schedule(vec![
+            period(&[1, 2, 3, 4, 5], "06:30", 21.0),
+            period(&[1, 2, 3, 4, 5], "22:00", 17.0),
+            period(&[0, 6], "08:00", 20.0),
+        ]);
+        // Monday morning
+        assert_eq!(s.active_setpoint(at(1, "07:00")), Some(21.0));
+        // Monday before the first period carries over from Sunday
+        assert_eq!(s.active_setpoint(at(1, "05:00")), Some(20.0));
+        // Tuesday night
+        assert_eq!(s.active_setpoint(at(2, "23:30")), Some(17.0));
+        // Saturday before 08:00 carries over from Friday night
+        assert_eq!(s.active_setpoint(at(6, "07:59")), Some(17.0));
+    }
+
+    #[test]
+    fn test_single_period_wraps_week() {
+        let s = schedule(vec![period(&[3], "12:00", 19.0)]);
+        assert_eq!(s.active_setpoint(at(3, "11:00")), Some(19.0));
+        assert_eq!(schedule(Vec::new()).active_setpoint(at(0, "12:00")), None);
+    }
+
+    #[test]
+    fn test_room_temperature_due() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        assert!(room_temperature_due(None, 2100));
+        // Unchanged: only once the interval is up
+        assert!(!room_temperature_due(Some((Some(2100), minutes(29))), 2100));
+        assert!(room_temperature_due(Some((Some(2100), minutes(30))), 2100));
+        // Moved: sooner, but not right after the last write
+        assert!(room_temperature_due(Some((Some(2100), minutes(6))), 2060));
+        assert!(!room_temperature_due(Some((Some(2100), minutes(6))), 2080));
+        assert!(!room_temperature_due(Some((Some(2100), minutes(1))), 2000));
+        // A failed write waits out the full interval
+        assert!(!room_temperature_due(Some((None, minutes(10))), 2100));
+    }
+}
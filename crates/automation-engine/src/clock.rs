@@ -0,0 +1,353 @@
+//! Times and day names as people write them
+//!
+//! Schedules and time conditions are stored as 24-hour `HH:MM` with days
+//! numbered 0 (Sunday) to 6 (Saturday), but the API also accepts 12-hour
+//! times (`7:30 PM`, `7pm`) and day names in English, Spanish, French and
+//! German (`"monday"`, `"mié"`, `"Samstag"`). They are normalized when an
+//! automation is saved.
+//!
+//! Schedules fire at wall-clock times in a [`Zone`]: the system's, or the
+//! one named by the `TIMEZONE` env var or the schedule itself.
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use std::sync::OnceLock;
+
+/// Day names and abbreviations, indexed by day number (0 = Sunday)
+const DAY_NAMES: [&[&str]; 7] = [
+    &[
+        "sunday", "sun", "domingo", "dom", "dimanche", "dim", "sonntag", "so",
+    ],
+    &["monday", "mon", "lunes", "lun", "lundi", "montag", "mo"],
+    &[
+        "tuesday", "tue", "tues", "martes", "mar", "mardi", "dienstag", "di",
+    ],
+    &[
+        "wednesday",
+        "wed",
+        "miercoles",
+        "mie",
+        "mercredi",
+        "mer",
+        "mittwoch",
+        "mi",
+    ],
+    &[
+        "thursday",
+        "thu",
+        "thur",
+        "thurs",
+        "jueves",
+        "jue",
+        "jeudi",
+        "jeu",
+        "donnerstag",
+        "do",
+    ],
+    &[
+        "friday", "fri", "viernes", "vie", "vendredi", "ven", "freitag", "fr",
+    ],
+    &[
+        "saturday", "sat", "sabado", "sab", "samedi", "sam", "samstag", "sa",
+    ],
+];
+
+/// Hint appended to time validation errors
+pub const TIME_FORMAT_HINT: &str = "expected HH:MM or h:MM AM/PM";
+
+/// Parse a time of day: `19:30`, `7:30 PM`, `7:30pm`, `7 p.m.` or `12 AM`
+#[must_use]
+pub fn parse_time(input: &str) -> Option<NaiveTime> {
+    let lower = input.trim().to_lowercase().replace('.', "");
+    let (clock, pm) = if let Some(rest) = lower.strip_suffix("pm") {
+        (rest.trim_end(), Some(true))
+    } else if let Some(rest) = lower.strip_suffix("am") {
+        (rest.trim_end(), Some(false))
+    } else {
+        (lower.as_str(), None)
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((h, m)) if m.len() == 2 => (h, m),
+        Some(_) => return None,
+        // "7 PM" needs no minutes; a bare "19" is too ambiguous
+        None if pm.is_some() => (clock, "00"),
+        None => return None,
+    };
+    if hour.is_empty() || hour.len() > 2 {
+        return None;
+    }
+    let hour: u32 = hour.parse().ok()?;
+    let minute: u32 = minute.parse().ok()?;
+    let hour = match pm {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(true) => hour % 12 + 12,
+        Some(false) => hour % 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+/// Normalize a time to `HH:MM`, or `None` if it can't be parsed
+#[must_use]
+pub fn normalize_time(input: &str) -> Option<String> {
+    parse_time(input).map(|t| t.format("%H:%M").to_string())
+}
+
+/// Day number (0 = Sunday) for a day name or abbreviation, in any case and
+/// with or without accents
+#[must_use]
+pub fn parse_day(input: &str) -> Option<u8> {
+    let name: String = input
+        .trim()
+        .trim_end_matches('.')
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' => 'a',
+            'é' | 'è' | 'ê' => 'e',
+            'í' | 'î' => 'i',
+            'ó' | 'ô' => 'o',
+            'ú' | 'û' => 'u',
+            c => c,
+        })
+        .collect();
+    DAY_NAMES
+        .iter()
+        .position(|names| names.contains(&name.as_str()))
+        .and_then(|day| u8::try_from(day).ok())
+}
+
+/// Deserialize a list of days given as numbers or names
+pub(crate) fn deserialize_days<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Day {
+        Number(u8),
+        Name(String),
+    }
+
+    Vec::<Day>::deserialize(deserializer)?
+        .into_iter()
+        .map(|day| match day {
+            Day::Number(n) => Ok(n),
+            Day::Name(name) => parse_day(&name)
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown day '{name}'"))),
+        })
+        .collect()
+}
+
+/// Time zone of schedules, time conditions, `{{now}}` and climate schedules
+///
+/// The system's by default, which in containers without `TZ` is usually
+/// UTC; `TIMEZONE` (an IANA name like `Europe/Madrid`) sets one for all of
+/// them. A schedule trigger's `timezone` sets one for when that trigger
+/// fires only.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Zone {
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    /// A zone by its IANA name
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(name: &str) -> Result<Self, String> {
+        name.trim()
+            .parse::<Tz>()
+            .map(Self::Named)
+            .map_err(|_| format!("unknown time zone '{name}'"))
+    }
+
+    /// The zone named by the `TIMEZONE` env var, the system's if it isn't
+    /// set or valid (read once)
+    #[must_use]
+    pub fn configured() -> Self {
+        static CONFIGURED: OnceLock<Zone> = OnceLock::new();
+        *CONFIGURED.get_or_init(Self::from_env)
+    }
+
+    fn from_env() -> Self {
+        match std::env::var("TIMEZONE") {
+            Ok(name) if !name.trim().is_empty() => Self::parse(&name).unwrap_or_else(|e| {
+                tracing::warn!("{} in TIMEZONE, using the system time zone", e);
+                Self::Local
+            }),
+            _ => Self::Local,
+        }
+    }
+
+    /// The zone called `name` if there is one, this one otherwise
+    #[must_use]
+    pub fn or_named(self, name: Option<&str>) -> Self {
+        name.and_then(|name| Self::parse(name).ok()).unwrap_or(self)
+    }
+
+    /// The wall-clock time here at `at`
+    #[must_use]
+    pub fn wall_clock(self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Self::Local => at.with_timezone(&chrono::Local).naive_local(),
+            Self::Named(tz) => at.with_timezone(&tz).naive_local(),
+        }
+    }
+
+    /// The wall-clock time here now
+    #[must_use]
+    pub fn now(self) -> NaiveDateTime {
+        self.wall_clock(Utc::now())
+    }
+
+    /// `at` with this zone's offset
+    #[must_use]
+    pub fn offset_time(self, at: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Self::Local => at.with_timezone(&chrono::Local).fixed_offset(),
+            Self::Named(tz) => at.with_timezone(&tz).fixed_offset(),
+        }
+    }
+
+    /// The date here at `at`
+    #[must_use]
+    pub fn date(self, at: DateTime<Utc>) -> NaiveDate {
+        self.wall_clock(at).date()
+    }
+
+    /// When it's `time` on `date` here: the first of the two on a daylight
+    /// saving fall-back, none if the clocks skip it
+    #[must_use]
+    pub fn at(self, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+        let local = date.and_time(time);
+        match self {
+            Self::Local => chrono::Local
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|at| at.with_timezone(&Utc)),
+            Self::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|at| at.with_timezone(&Utc)),
+        }
+    }
+
+    /// The first time after `after` that it's `time` here, on one of `days`
+    /// (every day if empty)
+    #[must_use]
+    pub fn next_time_of_day(
+        self,
+        time: NaiveTime,
+        days: &[u8],
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let mut date = self.date(after);
+        // Today and the week after
+        for _ in 0..8 {
+            let weekday =
+                u8::try_from(date.weekday().num_days_from_sunday()).expect("weekday is 0-6");
+            if days.is_empty() || days.contains(&weekday) {
+                if let Some(at) = self.at(date, time).filter(|&at| at > after) {
+                    return Some(at);
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    /// The first time after `after` that a cron schedule fires here
+    #[must_use]
+    pub fn next_cron(
+        self,
+        schedule: &cron::Schedule,
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Local => schedule
+                .after(&after.with_timezone(&chrono::Local))
+                .next()
+                .map(|at| at.with_timezone(&Utc)),
+            Self::Named(tz) => schedule
+                .after(&after.with_timezone(&tz))
+                .next()
+                .map(|at| at.with_timezone(&Utc)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_formats() {
+        let hm = |h, m| NaiveTime::from_hms_opt(h, m, 0);
+        assert_eq!(parse_time("19:30"), hm(19, 30));
+        assert_eq!(parse_time("7:05"), hm(7, 5));
+        assert_eq!(parse_time("7:30 PM"), hm(19, 30));
+        assert_eq!(parse_time("7:30pm"), hm(19, 30));
+        assert_eq!(parse_time("7 p.m."), hm(19, 0));
+        assert_eq!(parse_time("12:15 AM"), hm(0, 15));
+        assert_eq!(parse_time("12 PM"), hm(12, 0));
+        assert_eq!(parse_time("13:00 PM"), None);
+        assert_eq!(parse_time("25:00"), None);
+        assert_eq!(parse_time("7:3"), None);
+        assert_eq!(parse_time("19"), None);
+        assert_eq!(normalize_time(" 9:00 am ").as_deref(), Some("09:00"));
+    }
+
+    #[test]
+    fn test_parse_day_names() {
+        assert_eq!(parse_day("Monday"), Some(1));
+        assert_eq!(parse_day("mié"), Some(3));
+        assert_eq!(parse_day("Miércoles"), Some(3));
+        assert_eq!(parse_day("Samstag"), Some(6));
+        assert_eq!(parse_day("dim."), Some(0));
+        assert_eq!(parse_day("someday"), None);
+
+        #[derive(Deserialize)]
+        struct Days {
+            #[serde(deserialize_with = "deserialize_days")]
+            days: Vec<u8>,
+        }
+        let days: Days = serde_json::from_str(r#"{"days": [0, "tue", "Sábado"]}"#).unwrap();
+        assert_eq!(days.days, vec![0, 2, 6]);
+        assert!(serde_json::from_str::<Days>(r#"{"days": ["someday"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_zone_wall_clock_times() {
+        let madrid = Zone::parse("Europe/Madrid").unwrap();
+        assert!(Zone::parse("Mars/Olympus_Mons").is_err());
+        assert_eq!(Zone::Local.or_named(Some("nowhere")), Zone::Local);
+
+        // 18:00 in Madrid is 16:00 UTC in summer
+        let after = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap(); // a Monday
+        let six_pm = NaiveTime::from_hms_opt(18, 0, 0).unwrap();
+        assert_eq!(
+            madrid.next_time_of_day(six_pm, &[], after),
+            Some(Utc.with_ymd_and_hms(2024, 6, 3, 16, 0, 0).unwrap())
+        );
+        // Only on Wednesdays
+        assert_eq!(
+            madrid.next_time_of_day(six_pm, &[3], after),
+            Some(Utc.with_ymd_and_hms(2024, 6, 5, 16, 0, 0).unwrap())
+        );
+        // Skipped on the night the clocks go forward
+        let spring_forward = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        assert_eq!(
+            madrid.at(spring_forward, NaiveTime::from_hms_opt(2, 30, 0).unwrap()),
+            None
+        );
+
+        let cron: cron::Schedule = "0 0 7 * * *".parse().unwrap();
+        assert_eq!(
+            madrid.next_cron(&cron, after),
+            Some(Utc.with_ymd_and_hms(2024, 6, 4, 5, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Test runs of automations
+//!
+//! Checks an automation against the current state without running it: the
+//! trigger and conditions are evaluated now, and the actions are walked
+//! step by step with placeholders filled in, but no device command, message
+//! or delay actually happens. `if` actions follow the branch their
+//! condition picks now and loops are walked once, so "edit, wait until
+//! 22:00, find out it's broken" becomes one request.
+
+use crate::backtest::schedule_times;
+use crate::clock::Zone;
+use crate::error::AutomationError;
+use crate::evaluator::ConditionEvaluator;
+use crate::ieee::parse_ieee_address;
+use crate::model::{Action, Automation, Condition, PresenceChange, StateChange, Trigger};
+use crate::template;
+use crate::trace::type_name;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use zigbee_core::ZigbeeNetwork;
+
+/// Result of a test run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DryRunReport {
+    pub automation_id: String,
+    /// Whether triggers would run it now (enabled and not snoozed)
+    pub active: bool,
+    /// Whether the state the trigger watches holds now (device on, reading
+    /// past the threshold, person home...), for triggers that watch one
+    pub trigger_state: Option<bool>,
+    /// When a schedule trigger fires next (within a week)
+    pub next_scheduled: Option<DateTime<Local>>,
+    /// Result of each condition
+    pub conditions: Vec<ConditionCheck>,
+    /// Whether a trigger now would run the actions
+    pub conditions_met: bool,
+    /// What each action would do, in order
+    pub steps: Vec<DryRunStep>,
+}
+
+/// Result of one of the automation's conditions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionCheck {
+    pub index: usize,
+    pub met: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One action of a test run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DryRunStep {
+    /// Position in the actions, e.g. `2` or `2.then_actions.0`
+    pub path: String,
+    /// Action type (`device_control`, `delay`...)
+    pub action: String,
+    /// What it would do
+    pub description: String,
+    /// Why it would fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Walks an automation without side effects
+pub(crate) struct DryRun<'a, F> {
+    pub evaluator: &'a ConditionEvaluator,
+    pub network: Option<&'a Arc<ZigbeeNetwork>>,
+    /// Time zone of the automation's schedule
+    pub zone: Zone,
+    /// Whether an automation ID exists, for `trigger_automation` actions
+    pub automation_exists: F,
+}
+
+impl<F: Fn(&str) -> bool> DryRun<'_, F> {
+    pub fn run(&self, automation: &Automation) -> DryRunReport {
+        let context = json!({ "trigger": { "reason": "test" } });
+        let conditions: Vec<ConditionCheck> = automation
+            .conditions
+            .iter()
+            .enumerate()
+            .map(
+                |(index, condition)| match self.evaluator.evaluate(condition, &context) {
+                    Ok(met) => ConditionCheck {
+                        index,
+                        met,
+                        error: None,
+                    },
+                    Err(e) => ConditionCheck {
+                        index,
+                        met: false,
+                        error: Some(e.to_string()),
+                    },
+                },
+            )
+            .collect();
+        let next_scheduled = match &automation.trigger {
+            Trigger::Schedule { schedule, .. } => {
+                let now = Utc::now();
+                schedule_times(schedule, self.zone, now, now + chrono::Duration::days(7))
+                    .ok()
+                    .and_then(|times| times.into_iter().find(|&at| at > now))
+                    .map(|at| at.with_timezone(&Local))
+            }
+            _ => None,
+        };
+
+        let mut steps = Vec::new();
+        self.walk(&automation.actions, "", &context, &mut steps);
+        DryRunReport {
+            automation_id: automation.id.clone(),
+            active: automation.is_active(),
+            trigger_state: trigger_condition(&automation.trigger)
+                .and_then(|condition| self.evaluator.evaluate(&condition, &context).ok()),
+            next_scheduled,
+            conditions_met: conditions.iter().all(|check| check.met),
+            conditions,
+            steps,
+        }
+    }
+
+    fn walk(&self, actions: &[Action], prefix: &str, context: &Value, steps: &mut Vec<DryRunStep>) {
+        for (i, action) in actions.iter().enumerate() {
+            let path = format!("{prefix}{i}");
+            let mut step = DryRunStep {
+                path: path.clone(),
+                action: type_name(action),
+                description: String::new(),
+                error: None,
+            };
+            let mut nested: Option<(&[Action], &str)> = None;
+            match action {
+                Action::DeviceControl {
+                    device_ieee,
+                    endpoint,
+                    command,
+                } => {
+                    let device_ieee = template::render(device_ieee, context);
+                    step.description = format!(
+                        "Would send {} to {} endpoint {}",
+                        serde_json::to_string(command).unwrap_or_default(),
+                        device_ieee,
+                        endpoint
+                    );
+                    step.error = self.check_device(&device_ieee);
+                }
+                Action::Delay { seconds } => {
+                    step.description = format!("Would wait {seconds}s");
+                }
+                Action::RandomDelay {
+                    min_seconds,
+                    max_seconds,
+                } => {
+                    step.description = format!("Would wait {min_seconds}-{max_seconds}s");
+                }
+                Action::TriggerAutomation { automation_id } => {
+                    step.description = format!("Would trigger automation {automation_id}");
+                    if !(self.automation_exists)(automation_id) {
+                        step.error = Some(format!("Automation not found: {automation_id}"));
+                    }
+                }
+                Action::MqttPublish { topic, payload, .. } => {
+                    step.description = format!(
+                        "Would publish '{}' to {}",
+                        template::render(payload, context),
+                        template::render(topic, context)
+                    );
+                }
+                Action::WaitForState {
+                    device_ieee,
+                    state_on,
+                    timeout_seconds,
+                    ..
+                } => {
+                    let device_ieee = template::render(device_ieee, context);
+                    let state = if *state_on { "on" } else { "off" };
+                    let already = self
+                        .network
+                        .zip(parse_ieee_address(&device_ieee).ok())
+                        .is_some_and(|(network, ieee)| {
+                            network
+                                .get_device(&ieee)
+                                .is_some_and(|device| device.state_on == Some(*state_on))
+                        });
+                    step.description = if already {
+                        format!("{device_ieee} is already {state}, would go on at once")
+                    } else {
+                        format!(
+                            "Would wait up to {timeout_seconds}s for {device_ieee} to turn {state}"
+                        )
+                    };
+                    step.error = self.check_device(&device_ieee);
+                }
+                Action::Repeat { count, actions } => {
+                    step.description =
+                        format!("Would run {} action(s) {count} times", actions.len());
+                    nested = Some((actions, "actions"));
+                }
+                Action::RepeatWhile {
+                    condition,
+                    actions,
+                    max_iterations,
+                } => match self.evaluator.evaluate(condition, context) {
+                    Ok(true) => {
+                        step.description = format!(
+                            "Condition holds now: would run {} action(s) up to {max_iterations} times",
+                            actions.len()
+                        );
+                        nested = Some((actions, "actions"));
+                    }
+                    Ok(false) => {
+                        step.description = "Condition doesn't hold now: would skip".to_string();
+                    }
+                    Err(e) => step.error = Some(e.to_string()),
+                },
+                Action::If {
+                    condition,
+                    then_actions,
+                    else_actions,
+                } => match self.evaluator.evaluate(condition, context) {
+                    Ok(true) => {
+                        step.description =
+                            "Condition holds now: would run then_actions".to_string();
+                        nested = Some((then_actions, "then_actions"));
+                    }
+                    Ok(false) => {
+                        step.description =
+                            "Condition doesn't hold now: would run else_actions".to_string();
+                        nested = Some((else_actions, "else_actions"));
+                    }
+                    Err(e) => step.error = Some(e.to_string()),
+                },
+                Action::Script { source } => {
+                    self.walk_script(source, &path, context, &mut step, steps);
+                    continue;
+                }
+                Action::PermitJoin { duration } => {
+                    step.description =
+                        format!("Would open the network for joining for {duration}s");
+                }
+                Action::Log { message, .. } => {
+                    step.description =
+                        format!("Would log '{}'", template::render(message, context));
+                }
+            }
+            steps.push(step);
+            if let Some((actions, field)) = nested {
+                self.walk(actions, &format!("{path}.{field}."), context, steps);
+            }
+        }
+    }
+
+    /// Run an action script with its side effects held back, and list the
+    /// device commands it would send and the messages it would log
+    #[cfg(feature = "scripting")]
+    fn walk_script(
+        &self,
+        source: &str,
+        path: &str,
+        context: &Value,
+        step: &mut DryRunStep,
+        steps: &mut Vec<DryRunStep>,
+    ) {
+        match crate::script::dry_run(source, self.network.cloned(), context) {
+            Ok((commands, messages)) => {
+                step.description = format!(
+                    "Would send {} device command(s) and log {} message(s)",
+                    commands.len(),
+                    messages.len()
+                );
+                steps.push(step.clone());
+                for (i, command) in commands.into_iter().enumerate() {
+                    steps.push(DryRunStep {
+                        path: format!("{path}.commands.{i}"),
+                        action: "device_control".to_string(),
+                        description: format!(
+                            "Would send {} to {} endpoint {}",
+                            serde_json::to_string(&command.command).unwrap_or_default(),
+                            command.device_ieee,
+                            command.endpoint
+                        ),
+                        error: self.check_device(&command.device_ieee),
+                    });
+                }
+                for (i, message) in messages.into_iter().enumerate() {
+                    steps.push(DryRunStep {
+                        path: format!("{path}.logs.{i}"),
+                        action: "log".to_string(),
+                        description: format!("Would log '{message}'"),
+                        error: None,
+                    });
+                }
+            }
+            Err(e) => {
+                step.error = Some(e.to_string());
+                steps.push(step.clone());
+            }
+        }
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    #[allow(clippy::unused_self)]
+    fn walk_script(
+        &self,
+        _source: &str,
+        _path: &str,
+        _context: &Value,
+        step: &mut DryRunStep,
+        steps: &mut Vec<DryRunStep>,
+    ) {
+        step.error = Some(
+            AutomationError::Script("built without scripting support".to_string()).to_string(),
+        );
+        steps.push(step.clone());
+    }
+
+    /// Why commands to a device would fail, if they would
+    fn check_device(&self, device_ieee: &str) -> Option<String> {
+        let ieee = match parse_ieee_address(device_ieee) {
+            Ok(ieee) => ieee,
+            Err(e) => return Some(e.to_string()),
+        };
+        match self.network {
+            None => Some("No network available".to_string()),
+            Some(network) if network.get_device(&ieee).is_none() => {
+                Some(AutomationError::DeviceNotFound(device_ieee.to_string()).to_string())
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// The condition that holds while a trigger's watched state does
+fn trigger_condition(trigger: &Trigger) -> Option<Condition> {
+    Some(match trigger {
+        Trigger::DeviceState {
+            device_ieee,
+            endpoint,
+            state_change,
+            ..
+        } => match state_change {
+            StateChange::TurnedOn | StateChange::TurnedOff => Condition::DeviceState {
+                device_ieee: device_ieee.clone(),
+                endpoint: *endpoint,
+                state_on: matches!(state_change, StateChange::TurnedOn),
+            },
+            StateChange::Available | StateChange::Unavailable => Condition::DeviceAvailable {
+                device_ieee: device_ieee.clone(),
+                available: matches!(state_change, StateChange::Available),
+            },
+            _ => return None,
+        },
+        Trigger::SensorValue {
+            device_ieee,
+            attribute,
+            operator,
+            value,
+            ..
+        } => Condition::SensorValue {
+            device_ieee: device_ieee.clone(),
+            attribute: *attribute,
+            operator: *operator,
+            value: *value,
+        },
+        Trigger::Presence { person, change } => Condition::Presence {
+            person: person.clone(),
+            home: *change == PresenceChange::Arrived,
+        },
+        _ => return None,
+    })
+}
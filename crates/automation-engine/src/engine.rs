@@ -1,17 +1,36 @@
 //! Core automation engine
 
+use crate::backtest::{self, BacktestReport, HistoricalEvent};
+use crate::climate::ClimateScheduler;
+use crate::clock::Zone;
+use crate::dry_run::{DryRun, DryRunReport};
 use crate::error::AutomationError;
 use crate::evaluator::ConditionEvaluator;
-use crate::executor::ActionExecutor;
+use crate::executor::{ActionExecutor, RunningExecution};
+use crate::folder::{AutomationFolder, FolderStore};
+use crate::ieee::format_ieee;
 use crate::model::{
-    Automation, CreateAutomationRequest, StateChange, Trigger, UpdateAutomationRequest,
+    Automation, BulkAction, BulkReport, BulkRequest, CreateAutomationRequest, ImportConflict,
+    ImportReport, PresenceChange, ScheduleSpec, SnoozeRequest, StateChange, Trigger,
+    UpdateAutomationRequest,
 };
-use crate::persistence;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::{self, MqttClient, MqttMessage};
+use crate::presence::{PresenceEvent, PresenceTracker};
+use crate::run_history::{self, RunHistory, RunRecord};
+use crate::schedule_log::ScheduleLog;
 use crate::scheduler::Scheduler;
+use crate::stats::StatsStore;
+use crate::template;
+use crate::trace::{ActionTracer, RunTrace};
+use chrono::{DateTime, Local, Utc};
 use dashmap::DashMap;
-use std::path::PathBuf;
+use serde_json::{json, Value};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
+use zigbee_core::storage::{self, Storage};
 use zigbee_core::{network::NetworkEvent, ZigbeeNetwork};
 
 /// Events emitted by the automation engine
@@ -52,10 +71,32 @@ pub struct AutomationEngine {
     executor: Arc<ActionExecutor>,
     /// Time-based scheduler
     scheduler: Arc<Scheduler>,
+    /// Climate (heating) schedules
+    climate: Arc<ClimateScheduler>,
+    /// Phone-based presence
+    presence: Arc<PresenceTracker>,
+    /// Folders for grouping automations
+    folders: Arc<FolderStore>,
+    /// Recent runs of each automation
+    runs: Arc<RunHistory>,
+    /// Run counters of each automation
+    stats: Arc<StatsStore>,
+    /// When catching-up schedules last came due
+    schedule_log: ScheduleLog,
     /// Event broadcaster
     event_tx: broadcast::Sender<AutomationEvent>,
-    /// Path for persistence
-    data_path: PathBuf,
+    /// Where automations are saved
+    storage: Arc<dyn Storage<Automation>>,
+    /// Whether the last reading was past the threshold, for each automation
+    /// with a sensor value trigger
+    sensor_states: DashMap<String, bool>,
+    /// Timers of triggers waiting for their state to hold, by automation ID
+    pending: DashMap<String, tokio::task::AbortHandle>,
+    /// When the cooldown after the last run ends, by automation ID
+    cooldowns: DashMap<String, tokio::time::Instant>,
+    /// Shared MQTT connection, if a broker is configured
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<Arc<MqttClient>>,
 }
 
 impl AutomationEngine {
@@ -66,11 +107,19 @@ impl AutomationEngine {
         data_dir: &std::path::Path,
     ) -> Result<Self, AutomationError> {
         let (event_tx, _) = broadcast::channel(64);
-        let data_path = data_dir.join("automations.json");
+        let storage = storage::open(data_dir, storage::Backend::from_env())?;
 
-        let evaluator = Arc::new(ConditionEvaluator::new(network.clone()));
-        let executor = Arc::new(ActionExecutor::new(network.clone()));
-        let scheduler = Arc::new(Scheduler::new());
+        let presence = Arc::new(PresenceTracker::new(data_dir).await);
+        let runs = Arc::new(RunHistory::new(data_dir).await);
+        let stats = Arc::new(StatsStore::new(data_dir).await);
+        let evaluator = Arc::new(
+            ConditionEvaluator::new(network.clone(), presence.clone(), stats.clone())
+                .with_zone(Zone::configured()),
+        );
+        let executor = Arc::new(ActionExecutor::new(network.clone(), evaluator.clone()));
+        let scheduler = Arc::new(Scheduler::new().with_zone(Zone::configured()));
+        let climate = Arc::new(ClimateScheduler::new(network.clone(), data_dir).await);
+        let folders = Arc::new(FolderStore::new(data_dir).await);
 
         let engine = Self {
             automations: Arc::new(DashMap::new()),
@@ -78,8 +127,19 @@ impl AutomationEngine {
             evaluator,
             executor,
             scheduler,
+            climate,
+            presence,
+            folders,
+            runs,
+            stats,
+            schedule_log: ScheduleLog::new(data_dir).await,
             event_tx,
-            data_path,
+            storage,
+            sensor_states: DashMap::new(),
+            pending: DashMap::new(),
+            cooldowns: DashMap::new(),
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
         };
 
         // Load persisted automations
@@ -88,9 +148,29 @@ impl AutomationEngine {
         Ok(engine)
     }
 
+    /// Use a shared MQTT connection for MQTT triggers and `mqtt_publish`
+    /// actions
+    #[cfg(feature = "mqtt")]
+    #[must_use]
+    pub fn with_mqtt(mut self, mqtt: Arc<MqttClient>) -> Self {
+        self.executor.set_mqtt(Arc::clone(&mqtt));
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// The shared MQTT connection, if a broker is configured
+    #[cfg(feature = "mqtt")]
+    #[must_use]
+    pub fn mqtt(&self) -> Option<&Arc<MqttClient>> {
+        self.mqtt.as_ref()
+    }
+
     /// Load automations from disk
     async fn load(&self) -> Result<(), AutomationError> {
-        let automations = persistence::load_automations(&self.data_path).await;
+        let automations = self.storage.load().unwrap_or_else(|e| {
+            tracing::warn!("Failed to load automations: {}", e);
+            Vec::new()
+        });
         for automation in automations {
             // Register with scheduler if needed
             if let Err(e) = self.scheduler.register(&automation) {
@@ -103,9 +183,11 @@ impl AutomationEngine {
 
     /// Save automations to disk
     async fn save(&self) -> Result<(), AutomationError> {
-        let automations: Vec<Automation> =
-            self.automations.iter().map(|r| r.value().clone()).collect();
-        persistence::save_automations(&self.data_path, &automations).await?;
+        let automations = self.snapshot();
+        let storage = Arc::clone(&self.storage);
+        tokio::task::spawn_blocking(move || storage.save(&automations))
+            .await
+            .map_err(std::io::Error::other)??;
         Ok(())
     }
 
@@ -118,6 +200,38 @@ impl AutomationEngine {
 
         // Start scheduler event listener
         self.start_scheduler_listener();
+
+        self.start_presence_listener();
+
+        self.start_mqtt_listener();
+
+        self.run_startup_automations();
+
+        let engine = Arc::clone(self);
+        tokio::spawn(async move {
+            engine.wait_for_network().await;
+            engine.catch_up_missed_schedules().await;
+        });
+
+        self.climate.start();
+    }
+
+    /// Climate schedules
+    #[must_use]
+    pub fn climate(&self) -> &Arc<ClimateScheduler> {
+        &self.climate
+    }
+
+    /// Phone-based presence
+    #[must_use]
+    pub fn presence(&self) -> &Arc<PresenceTracker> {
+        &self.presence
+    }
+
+    /// Automation folders
+    #[must_use]
+    pub fn folders(&self) -> &Arc<FolderStore> {
+        &self.folders
     }
 
     /// Subscribe to automation events
@@ -126,16 +240,51 @@ impl AutomationEngine {
         self.event_tx.subscribe()
     }
 
-    /// Get all automations
+    /// Copies of all automations, without snoozes that have run out
+    fn snapshot(&self) -> Vec<Automation> {
+        let now = Utc::now();
+        self.automations
+            .iter()
+            .map(|r| {
+                let mut automation = r.value().clone();
+                automation.expire_snooze(now);
+                automation
+            })
+            .collect()
+    }
+
+    /// Get all automations, ordered by `sort_order` then name
     #[must_use]
     pub fn list(&self) -> Vec<Automation> {
-        self.automations.iter().map(|r| r.value().clone()).collect()
+        let mut automations = self.snapshot();
+        for automation in &mut automations {
+            automation.cooldown_remaining_seconds = self.cooldown_remaining_seconds(&automation.id);
+            automation.stats = Some(self.stats.get(&automation.id));
+        }
+        automations.sort_by(|a, b| (a.sort_order, &a.name).cmp(&(b.sort_order, &b.name)));
+        automations
     }
 
     /// Get automation by ID
     #[must_use]
     pub fn get(&self, id: &str) -> Option<Automation> {
-        self.automations.get(id).map(|r| r.value().clone())
+        self.automations.get(id).map(|r| {
+            let mut automation = r.value().clone();
+            automation.expire_snooze(Utc::now());
+            automation.cooldown_remaining_seconds = self.cooldown_remaining_seconds(id);
+            automation.stats = Some(self.stats.get(id));
+            automation
+        })
+    }
+
+    /// Whole seconds (rounded up) until the automation's cooldown ends
+    fn cooldown_remaining_seconds(&self, id: &str) -> Option<u64> {
+        let remaining = self
+            .cooldowns
+            .get(id)?
+            .saturating_duration_since(tokio::time::Instant::now());
+        let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        (secs > 0).then_some(secs)
     }
 
     /// Create a new automation
@@ -144,13 +293,17 @@ impl AutomationEngine {
         &self,
         request: CreateAutomationRequest,
     ) -> Result<Automation, AutomationError> {
-        let automation = Automation::from_request(request);
+        let mut automation = Automation::from_request(request);
+        automation.normalize()?;
+        self.check_folder(automation.folder.as_deref())?;
 
         // Register with scheduler if needed
         self.scheduler.register(&automation)?;
 
         self.automations
             .insert(automation.id.clone(), automation.clone());
+        #[cfg(feature = "mqtt")]
+        self.sync_trigger_topics();
         self.save().await?;
 
         let _ = self.event_tx.send(AutomationEvent::Created {
@@ -172,11 +325,42 @@ impl AutomationEngine {
         id: &str,
         request: UpdateAutomationRequest,
     ) -> Result<Automation, AutomationError> {
+        self.update_checked(id, request, None).await
+    }
+
+    /// Update an automation only if it is still at `expected_revision`
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn update_checked(
+        &self,
+        id: &str,
+        mut request: UpdateAutomationRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Automation, AutomationError> {
+        request.normalize()?;
+        if let Some(folder) = &request.folder {
+            self.check_folder(folder.as_deref())?;
+        }
         let mut automation = self
             .automations
             .get_mut(id)
             .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
 
+        if let Some(expected) = expected_revision {
+            if automation.revision != expected {
+                return Err(AutomationError::RevisionMismatch {
+                    expected,
+                    current: automation.revision,
+                });
+            }
+        }
+
+        if request.trigger.is_some() {
+            self.sensor_states.remove(id);
+            self.cancel_pending(id);
+        }
+        if request.cooldown_seconds.is_some() {
+            self.cooldowns.remove(id);
+        }
         automation.apply_update(request);
 
         // Update scheduler
@@ -184,6 +368,8 @@ impl AutomationEngine {
 
         let updated = automation.clone();
         drop(automation);
+        #[cfg(feature = "mqtt")]
+        self.sync_trigger_topics();
 
         self.save().await?;
 
@@ -195,6 +381,138 @@ impl AutomationEngine {
         Ok(updated)
     }
 
+    /// All automations, in a stable order, for backups and moving to
+    /// another install (see [`Self::import`])
+    #[must_use]
+    pub fn export(&self) -> Vec<Automation> {
+        let mut automations = self.snapshot();
+        automations
+            .sort_by(|a, b| (a.sort_order, &a.name, &a.id).cmp(&(b.sort_order, &b.name, &b.id)));
+        automations
+    }
+
+    /// Add exported automations, keeping their IDs
+    ///
+    /// All of them are checked before any is added. One whose ID is taken
+    /// is skipped, replaces the existing automation or is added under a new
+    /// ID, as `on_conflict` says. Folders that don't exist here are dropped.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn import(
+        &self,
+        mut automations: Vec<Automation>,
+        on_conflict: ImportConflict,
+    ) -> Result<ImportReport, AutomationError> {
+        let mut ids = HashSet::new();
+        for (i, automation) in automations.iter_mut().enumerate() {
+            let in_file = |e| match e {
+                AutomationError::InvalidField { field, message } => AutomationError::InvalidField {
+                    field: format!("automations[{i}].{field}"),
+                    message,
+                },
+                e => e,
+            };
+            if automation.id.is_empty() {
+                automation.id = uuid::Uuid::new_v4().to_string();
+            }
+            if !ids.insert(automation.id.clone()) {
+                return Err(in_file(AutomationError::InvalidField {
+                    field: "id".to_string(),
+                    message: format!("'{}' appears more than once", automation.id),
+                }));
+            }
+            automation.normalize().map_err(in_file)?;
+            Scheduler::check(automation)?;
+            if self.check_folder(automation.folder.as_deref()).is_err() {
+                automation.folder = None;
+            }
+            automation.cooldown_remaining_seconds = None;
+            automation.stats = None;
+        }
+
+        let mut report = ImportReport::default();
+        for mut automation in automations {
+            let current_revision = self.automations.get(&automation.id).map(|a| a.revision);
+            match (current_revision, on_conflict) {
+                (None, _) => report.created.push(automation.id.clone()),
+                (Some(_), ImportConflict::Skip) => {
+                    report.skipped.push(automation.id);
+                    continue;
+                }
+                (Some(revision), ImportConflict::Replace) => {
+                    automation.revision = revision + 1;
+                    automation.updated_at = Utc::now().to_rfc3339();
+                    self.scheduler.remove(&automation.id);
+                    self.sensor_states.remove(&automation.id);
+                    self.cancel_pending(&automation.id);
+                    self.cooldowns.remove(&automation.id);
+                    report.replaced.push(automation.id.clone());
+                }
+                (Some(_), ImportConflict::Duplicate) => {
+                    automation.id = uuid::Uuid::new_v4().to_string();
+                    report.created.push(automation.id.clone());
+                }
+            }
+            self.scheduler.register(&automation)?;
+            self.automations.insert(automation.id.clone(), automation);
+        }
+        #[cfg(feature = "mqtt")]
+        self.sync_trigger_topics();
+        self.save().await?;
+
+        for automation_id in &report.created {
+            let _ = self.event_tx.send(AutomationEvent::Created {
+                automation_id: automation_id.clone(),
+            });
+        }
+        for automation_id in &report.replaced {
+            let _ = self.event_tx.send(AutomationEvent::Updated {
+                automation_id: automation_id.clone(),
+            });
+        }
+        tracing::info!(
+            "Imported automations: {} created, {} replaced, {} skipped",
+            report.created.len(),
+            report.replaced.len(),
+            report.skipped.len()
+        );
+        Ok(report)
+    }
+
+    fn check_folder(&self, folder: Option<&str>) -> Result<(), AutomationError> {
+        match folder {
+            Some(id) if self.folders.get(id).is_none() => Err(AutomationError::InvalidFolder(
+                format!("no folder with ID {id}"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delete a folder, moving its automations out of it
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn delete_folder(&self, id: &str) -> Result<AutomationFolder, AutomationError> {
+        let folder = self.folders.delete(id).await?;
+
+        let mut moved = Vec::new();
+        for mut automation in self.automations.iter_mut() {
+            if automation.folder.as_deref() == Some(id) {
+                automation.apply_update(UpdateAutomationRequest {
+                    folder: Some(None),
+                    ..Default::default()
+                });
+                moved.push(automation.id.clone());
+            }
+        }
+        if !moved.is_empty() {
+            self.save().await?;
+        }
+        for automation_id in moved {
+            let _ = self
+                .event_tx
+                .send(AutomationEvent::Updated { automation_id });
+        }
+        Ok(folder)
+    }
+
     /// Delete an automation
     #[allow(clippy::missing_errors_doc)]
     pub async fn delete(&self, id: &str) -> Result<Automation, AutomationError> {
@@ -204,7 +522,20 @@ impl AutomationEngine {
             .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
 
         self.scheduler.remove(id);
+        self.sensor_states.remove(id);
+        self.cancel_pending(id);
+        self.cooldowns.remove(id);
+        #[cfg(feature = "mqtt")]
+        self.sync_trigger_topics();
         self.save().await?;
+        self.runs.remove(id).await;
+        self.stats.remove(id);
+        self.schedule_log.remove(id).await;
+        if let Some(network) = &self.network {
+            for device in network.get_devices() {
+                network.clear_automation_failure(&device.ieee_address, id);
+            }
+        }
 
         let _ = self.event_tx.send(AutomationEvent::Deleted {
             automation_id: id.to_string(),
@@ -240,6 +571,105 @@ impl AutomationEngine {
         .await
     }
 
+    /// Enable, disable or run several automations at once: those listed by
+    /// ID and those with the tag
+    ///
+    /// Fails without changing anything if an ID doesn't exist. Triggered
+    /// runs go on in the background.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn bulk(
+        self: &Arc<Self>,
+        request: BulkRequest,
+    ) -> Result<BulkReport, AutomationError> {
+        if request.ids.is_empty() && request.tag.is_none() {
+            return Err(AutomationError::InvalidField {
+                field: "ids".to_string(),
+                message: "give automation IDs or a tag".to_string(),
+            });
+        }
+        if let Some(id) = request
+            .ids
+            .iter()
+            .find(|id| !self.automations.contains_key(*id))
+        {
+            return Err(AutomationError::NotFound(id.clone()));
+        }
+        let mut ids = request.ids;
+        if let Some(tag) = &request.tag {
+            ids.extend(
+                self.export()
+                    .into_iter()
+                    .filter(|automation| automation.has_tag(tag))
+                    .map(|automation| automation.id),
+            );
+        }
+        let mut seen = HashSet::new();
+        ids.retain(|id| seen.insert(id.clone()));
+
+        for id in &ids {
+            match request.action {
+                BulkAction::Enable => {
+                    self.enable(id).await?;
+                }
+                BulkAction::Disable => {
+                    self.disable(id).await?;
+                }
+                BulkAction::Trigger => {
+                    let engine = Arc::clone(self);
+                    let id = id.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = engine.trigger(&id).await {
+                            tracing::error!("Failed to execute automation {}: {}", id, e);
+                        }
+                    });
+                }
+            }
+        }
+        Ok(BulkReport { automations: ids })
+    }
+
+    /// Ignore an automation's triggers for a while, without disabling it
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn snooze(
+        &self,
+        id: &str,
+        request: &SnoozeRequest,
+    ) -> Result<Automation, AutomationError> {
+        let until = request.resolve(Local::now(), Zone::configured())?;
+        self.set_snooze(id, Some(until)).await
+    }
+
+    /// End a snooze early
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn unsnooze(&self, id: &str) -> Result<Automation, AutomationError> {
+        self.set_snooze(id, None).await
+    }
+
+    async fn set_snooze(
+        &self,
+        id: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Automation, AutomationError> {
+        let mut automation = self
+            .automations
+            .get_mut(id)
+            .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
+        automation.snoozed_until = until;
+        let updated = automation.clone();
+        drop(automation);
+
+        self.save().await?;
+        let _ = self.event_tx.send(AutomationEvent::Updated {
+            automation_id: id.to_string(),
+        });
+
+        match until {
+            Some(until) => tracing::info!("Snoozed automation {} until {}", id, until),
+            None => tracing::info!("Unsnoozed automation {}", id),
+        }
+        Ok(updated)
+    }
+
     /// Manually trigger an automation
     #[allow(clippy::missing_errors_doc)]
     pub async fn trigger(&self, id: &str) -> Result<(), AutomationError> {
@@ -256,12 +686,178 @@ impl AutomationEngine {
         self.execute_automation(&automation, "manual").await
     }
 
+    /// Recent runs of an automation, newest first
+    #[allow(clippy::missing_errors_doc)]
+    pub fn run_history(&self, id: &str) -> Result<Vec<RunRecord>, AutomationError> {
+        if !self.automations.contains_key(id) {
+            return Err(AutomationError::NotFound(id.to_string()));
+        }
+        Ok(self.runs.get(id))
+    }
+
+    /// A recent run of an automation with its trace (see [`crate::trace`]),
+    /// `None` if the run is unknown or too old to have kept it
+    #[allow(clippy::missing_errors_doc)]
+    pub fn run_trace(&self, id: &str, run_id: &str) -> Result<Option<RunRecord>, AutomationError> {
+        if !self.automations.contains_key(id) {
+            return Err(AutomationError::NotFound(id.to_string()));
+        }
+        Ok(self.runs.trace(id, run_id))
+    }
+
+    /// Automation runs in progress
+    #[must_use]
+    pub fn running(&self) -> Vec<RunningExecution> {
+        self.executor.running()
+    }
+
+    /// Abort the runs of an automation, e.g. one stuck in a long delay,
+    /// returning how many were cancelled
+    #[allow(clippy::missing_errors_doc)]
+    pub fn cancel(&self, id: &str) -> Result<usize, AutomationError> {
+        if !self.automations.contains_key(id) {
+            return Err(AutomationError::NotFound(id.to_string()));
+        }
+        match self.executor.cancel(id) {
+            0 => Err(AutomationError::NotRunning(id.to_string())),
+            cancelled => Ok(cancelled),
+        }
+    }
+
+    /// Replay recorded events through an automation's trigger and conditions
+    /// to see when it would have fired between `from` and `to`
+    #[allow(clippy::missing_errors_doc)]
+    pub fn backtest(
+        &self,
+        id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        events: impl IntoIterator<Item = HistoricalEvent>,
+    ) -> Result<BacktestReport, AutomationError> {
+        let automation = self
+            .get(id)
+            .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
+        let sensor_states = DashMap::new();
+        backtest::run(
+            &automation,
+            &self.evaluator,
+            self.scheduler.zone_for(&automation.trigger),
+            |automation, event| Self::trigger_fires(&sensor_states, automation, event),
+            Self::trigger_reverts,
+            from,
+            to,
+            events,
+        )
+    }
+
+    /// Check an automation against the current state without running it:
+    /// whether the trigger's state and the conditions hold now, and what
+    /// each action would do (see [`crate::dry_run`])
+    #[allow(clippy::missing_errors_doc)]
+    pub fn dry_run(&self, id: &str) -> Result<DryRunReport, AutomationError> {
+        let automation = self
+            .get(id)
+            .ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
+        let dry_run = DryRun {
+            evaluator: &self.evaluator,
+            network: self.network.as_ref(),
+            zone: self.scheduler.zone_for(&automation.trigger),
+            automation_exists: |id: &str| self.automations.contains_key(id),
+        };
+        Ok(dry_run.run(&automation))
+    }
+
+    /// Run the automations listening on a webhook with the request's JSON
+    /// body, returning how many were started
+    ///
+    /// Automations with a secret run for callers presenting it, the others
+    /// only for `authenticated` callers. An unknown webhook is refused the
+    /// same way as a wrong secret, so IDs can't be probed. The runs go on in
+    /// the background so the caller gets its answer at once.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn fire_webhook(
+        self: &Arc<Self>,
+        id: &str,
+        secret: Option<&str>,
+        authenticated: bool,
+        body: Value,
+    ) -> Result<usize, AutomationError> {
+        let listening: Vec<Automation> = self
+            .automations
+            .iter()
+            .filter(
+                |entry| matches!(&entry.trigger, Trigger::Webhook { id: hook, .. } if hook == id),
+            )
+            .map(|entry| entry.value().clone())
+            .collect();
+        if listening.is_empty() {
+            return Err(AutomationError::WebhookUnauthorized(id.to_string()));
+        }
+        let allowed: Vec<Automation> = listening
+            .into_iter()
+            .filter(|automation| {
+                authenticated
+                    || matches!(
+                        (&automation.trigger, secret),
+                        (Trigger::Webhook { secret: Some(expected), .. }, Some(given))
+                            if secrets_match(expected, given)
+                    )
+            })
+            .collect();
+        if allowed.is_empty() {
+            return Err(AutomationError::WebhookUnauthorized(id.to_string()));
+        }
+
+        let context = json!({
+            "trigger": { "reason": "webhook", "webhook_id": id, "body": body }
+        });
+        let mut started = 0;
+        for automation in allowed.into_iter().filter(Automation::is_active) {
+            let engine = Arc::clone(self);
+            let context = context.clone();
+            tokio::spawn(async move {
+                if let Err(e) = engine
+                    .execute_with_context(&automation, "webhook", &context)
+                    .await
+                {
+                    tracing::error!("Failed to execute automation '{}': {}", automation.name, e);
+                }
+            });
+            started += 1;
+        }
+        Ok(started)
+    }
+
     /// Execute an automation
     async fn execute_automation(
         &self,
         automation: &Automation,
         trigger_reason: &str,
     ) -> Result<(), AutomationError> {
+        let context = json!({ "trigger": { "reason": trigger_reason } });
+        self.execute_with_context(automation, trigger_reason, &context)
+            .await
+    }
+
+    /// Execute an automation, filling placeholders in its actions from
+    /// `context`, and record the run
+    async fn execute_with_context(
+        &self,
+        automation: &Automation,
+        trigger_reason: &str,
+        context: &Value,
+    ) -> Result<(), AutomationError> {
+        if trigger_reason != "manual" {
+            if let Some(remaining) = self.cooldown_remaining_seconds(&automation.id) {
+                tracing::debug!(
+                    "Automation '{}' cooling down for {}s, ignoring {} trigger",
+                    automation.name,
+                    remaining,
+                    trigger_reason
+                );
+                return Ok(());
+            }
+        }
         tracing::info!(
             "Executing automation '{}' (trigger: {})",
             automation.name,
@@ -273,20 +869,45 @@ impl AutomationEngine {
             trigger_reason: trigger_reason.to_string(),
         });
 
-        // Evaluate conditions
-        if !self.evaluator.evaluate_all(&automation.conditions)? {
-            tracing::debug!(
-                "Automation '{}' conditions not met, skipping",
-                automation.name
-            );
-            return Ok(());
-        }
-
-        // Execute actions
-        let result = self
-            .executor
-            .execute_actions(&automation.id, &automation.actions)
-            .await;
+        let started_at = Utc::now();
+        let started = std::time::Instant::now();
+        let mut actions = Vec::new();
+        let tracer = ActionTracer::default();
+        let (conditions, condition_traces) =
+            self.evaluator.trace_all(&automation.conditions, context);
+        let conditions_met = matches!(conditions, Ok(true));
+        let result = match conditions {
+            Ok(true) => {
+                if let Some(cooldown) = automation.cooldown_seconds {
+                    match tokio::time::Instant::now().checked_add(Duration::from_secs(cooldown)) {
+                        Some(until) => {
+                            self.cooldowns.insert(automation.id.clone(), until);
+                        }
+                        None => tracing::warn!(
+                            "Cooldown of automation '{}' is too long, ignoring it",
+                            automation.name
+                        ),
+                    }
+                }
+                self.executor
+                    .execute_actions(
+                        &automation.id,
+                        &automation.actions,
+                        context,
+                        &mut actions,
+                        &tracer,
+                    )
+                    .await
+            }
+            Ok(false) => {
+                tracing::debug!(
+                    "Automation '{}' conditions not met, skipping",
+                    automation.name
+                );
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
 
         if let Err(ref e) = result {
             let _ = self.event_tx.send(AutomationEvent::Failed {
@@ -294,6 +915,24 @@ impl AutomationEngine {
                 error: e.to_string(),
             });
         }
+        let run = RunRecord {
+            id: run_history::new_run_id(),
+            automation_id: automation.id.clone(),
+            started_at,
+            trigger_reason: trigger_reason.to_string(),
+            conditions_met,
+            actions,
+            duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            error: result.as_ref().err().map(ToString::to_string),
+            trace: Some(RunTrace {
+                trigger: context.get("trigger").cloned().unwrap_or_default(),
+                conditions: condition_traces,
+                truncated: tracer.truncated(),
+                actions: tracer.into_actions(),
+            }),
+        };
+        self.stats.record(&run);
+        self.runs.record(run).await;
 
         result
     }
@@ -322,27 +961,221 @@ impl AutomationEngine {
     }
 
     /// Handle a network event
-    async fn handle_network_event(&self, event: NetworkEvent) {
+    async fn handle_network_event(self: &Arc<Self>, event: NetworkEvent) {
         for entry in self.automations.iter() {
             let automation = entry.value();
-            if !automation.enabled {
+            if !automation.is_active() {
                 continue;
             }
 
-            if Self::trigger_matches(&automation.trigger, &event) {
-                if let Err(e) = self.execute_automation(automation, "device_state").await {
+            if Self::trigger_reverts(&automation.trigger, &event)
+                && self.cancel_pending(&automation.id)
+            {
+                tracing::debug!(
+                    "Automation '{}' state didn't hold, not running",
+                    automation.name
+                );
+            }
+            if Self::trigger_fires(&self.sensor_states, automation, &event) {
+                let reason = match automation.trigger {
+                    Trigger::Button { .. } => "button",
+                    Trigger::Occupancy { .. } => "occupancy",
+                    Trigger::SensorValue { .. } => "sensor_value",
+                    _ => "device_state",
+                };
+                let context = template::event_context(reason, &event);
+                if let Some(duration) = automation.trigger.for_duration() {
+                    self.run_when_held(automation, duration, reason, context);
+                    continue;
+                }
+                if let Err(e) = self
+                    .execute_with_context(automation, reason, &context)
+                    .await
+                {
                     tracing::error!("Failed to execute automation '{}': {}", automation.name, e);
                 }
             }
         }
     }
 
-    fn trigger_matches(trigger: &Trigger, event: &NetworkEvent) -> bool {
+    /// Run an automation once its trigger state has held for `duration`
+    ///
+    /// A timer that is already running keeps going: the state has held
+    /// since it started.
+    fn run_when_held(
+        self: &Arc<Self>,
+        automation: &Automation,
+        duration: Duration,
+        reason: &'static str,
+        context: Value,
+    ) {
+        let Some(deadline) = tokio::time::Instant::now().checked_add(duration) else {
+            tracing::warn!(
+                "Automation '{}' waits too long for its trigger state, not running",
+                automation.name
+            );
+            return;
+        };
+        let dashmap::mapref::entry::Entry::Vacant(slot) = self.pending.entry(automation.id.clone())
+        else {
+            return;
+        };
+        tracing::debug!(
+            "Automation '{}' runs if its trigger state holds for {:?}",
+            automation.name,
+            duration
+        );
+        let engine = Arc::clone(self);
+        let id = automation.id.clone();
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            engine.pending.remove(&id);
+            let Some(automation) = engine.get(&id).filter(Automation::is_active) else {
+                return;
+            };
+            if let Err(e) = engine
+                .execute_with_context(&automation, reason, &context)
+                .await
+            {
+                tracing::error!("Failed to execute automation '{}': {}", automation.name, e);
+            }
+        });
+        slot.insert(timer.abort_handle());
+    }
+
+    /// Stop waiting for an automation's trigger state to hold, returning
+    /// whether it was waiting
+    fn cancel_pending(&self, id: &str) -> bool {
+        match self.pending.remove(id) {
+            Some((_, timer)) => {
+                timer.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether an event shows the state a trigger waits on has ended: the
+    /// light was turned off again, motion was detected again...
+    pub(crate) fn trigger_reverts(trigger: &Trigger, event: &NetworkEvent) -> bool {
+        match (trigger, event) {
+            (
+                Trigger::DeviceState {
+                    device_ieee,
+                    endpoint: trigger_endpoint,
+                    state_change,
+                    ..
+                },
+                NetworkEvent::DeviceStateChanged {
+                    ieee_address,
+                    endpoint,
+                    state_on,
+                },
+            ) => {
+                let reverted = match state_change {
+                    StateChange::TurnedOn => !*state_on,
+                    StateChange::TurnedOff => *state_on,
+                    _ => false,
+                };
+                reverted
+                    && format_ieee(*ieee_address) == *device_ieee
+                    && trigger_endpoint.is_none_or(|ep| ep == *endpoint)
+            }
+            (
+                Trigger::DeviceState {
+                    device_ieee,
+                    state_change,
+                    ..
+                },
+                NetworkEvent::AvailabilityChanged {
+                    ieee_address,
+                    available,
+                },
+            ) => {
+                let reverted = match state_change {
+                    StateChange::Available => !*available,
+                    StateChange::Unavailable => *available,
+                    _ => false,
+                };
+                reverted && format_ieee(*ieee_address) == *device_ieee
+            }
+            (
+                Trigger::DeviceState {
+                    device_ieee,
+                    state_change,
+                    ..
+                },
+                NetworkEvent::DeviceLeft { ieee_address },
+            ) => {
+                !matches!(state_change, StateChange::Left | StateChange::Any)
+                    && format_ieee(*ieee_address) == *device_ieee
+            }
+            (
+                Trigger::DeviceState {
+                    device_ieee,
+                    state_change: StateChange::Left,
+                    ..
+                },
+                NetworkEvent::DeviceJoined(device),
+            ) => format_ieee(device.ieee_address) == *device_ieee,
+            (
+                Trigger::Occupancy {
+                    device_ieee,
+                    occupied: Some(trigger_occupied),
+                    ..
+                },
+                NetworkEvent::OccupancyChanged {
+                    ieee_address,
+                    occupied,
+                    ..
+                },
+            ) => occupied != trigger_occupied && format_ieee(*ieee_address) == *device_ieee,
+            _ => false,
+        }
+    }
+
+    /// Whether an event fires an automation's trigger
+    ///
+    /// Sensor value triggers fire when a reading crosses the threshold, not
+    /// on every reading past it; `sensor_states` remembers which side the
+    /// last one was on. The first reading only records that.
+    pub(crate) fn trigger_fires(
+        sensor_states: &DashMap<String, bool>,
+        automation: &Automation,
+        event: &NetworkEvent,
+    ) -> bool {
+        let Trigger::SensorValue {
+            operator,
+            value: threshold,
+            hysteresis,
+            ..
+        } = &automation.trigger
+        else {
+            return Self::trigger_matches(&automation.trigger, event);
+        };
+        let Some(reading) = sensor_reading(&automation.trigger, event) else {
+            return false;
+        };
+        let was_past = sensor_states.get(&automation.id).map(|past| *past);
+        let past = operator.past(
+            reading,
+            *threshold,
+            *hysteresis,
+            was_past.unwrap_or_default(),
+        );
+        sensor_states.insert(automation.id.clone(), past);
+        past && was_past == Some(false)
+    }
+
+    /// Whether an event satisfies a trigger; sensor value triggers match
+    /// any reading past their threshold
+    pub(crate) fn trigger_matches(trigger: &Trigger, event: &NetworkEvent) -> bool {
         match trigger {
             Trigger::DeviceState {
                 device_ieee,
                 endpoint: trigger_endpoint,
                 state_change,
+                ..
             } => match event {
                 NetworkEvent::DeviceJoined(device) => {
                     let ieee_str = format_ieee(device.ieee_address);
@@ -358,7 +1191,28 @@ impl AutomationEngine {
                     let ieee_str = format_ieee(*ieee_address);
                     matches!(state_change, StateChange::Any) && ieee_str == *device_ieee
                 }
-                NetworkEvent::NetworkStateChanged { .. } => false,
+                NetworkEvent::AvailabilityChanged {
+                    ieee_address,
+                    available,
+                } => {
+                    let wanted = match state_change {
+                        StateChange::Any => true,
+                        StateChange::Available => *available,
+                        StateChange::Unavailable => !*available,
+                        _ => false,
+                    };
+                    wanted && format_ieee(*ieee_address) == *device_ieee
+                }
+                NetworkEvent::NetworkStateChanged { .. }
+                | NetworkEvent::AdapterDisconnected
+                | NetworkEvent::PermitJoinChanged { .. }
+                | NetworkEvent::ButtonEvent { .. }
+                | NetworkEvent::OccupancyChanged { .. }
+                | NetworkEvent::SensorValue { .. }
+                | NetworkEvent::PowerMeasurement { .. }
+                | NetworkEvent::IasAlarm { .. }
+                | NetworkEvent::ChannelCongestion { .. }
+                | NetworkEvent::OtaProgress { .. } => false,
                 NetworkEvent::DeviceStateChanged {
                     ieee_address,
                     endpoint,
@@ -383,21 +1237,382 @@ impl AutomationEngine {
                     }
                 }
             },
-            _ => false, // Schedule and Manual triggers are handled separately
+            Trigger::Button {
+                device_ieee,
+                endpoint: trigger_endpoint,
+                action: trigger_action,
+            } => match event {
+                NetworkEvent::ButtonEvent {
+                    ieee_address,
+                    endpoint,
+                    action,
+                } => {
+                    format_ieee(*ieee_address) == *device_ieee
+                        && trigger_endpoint.is_none_or(|ep| ep == *endpoint)
+                        && trigger_action.is_none_or(|a| a == *action)
+                }
+                _ => false,
+            },
+            Trigger::Occupancy {
+                device_ieee,
+                occupied: trigger_occupied,
+                ..
+            } => match event {
+                NetworkEvent::OccupancyChanged {
+                    ieee_address,
+                    occupied,
+                    ..
+                } => {
+                    format_ieee(*ieee_address) == *device_ieee
+                        && trigger_occupied.is_none_or(|o| o == *occupied)
+                }
+                _ => false,
+            },
+            Trigger::SensorValue {
+                operator, value, ..
+            } => sensor_reading(trigger, event)
+                .is_some_and(|reading| operator.past(reading, *value, 0.0, false)),
+            _ => false, // Schedule, Presence, HubStartup, Webhook, MQTT and Manual triggers are handled separately
         }
     }
 
-    /// Start listening for scheduler events
-    fn start_scheduler_listener(self: &Arc<Self>) {
-        let engine = Arc::clone(self);
-        let mut rx = self.scheduler.subscribe();
+    /// Run `HubStartup` automations once the network is up
+    fn run_startup_automations(self: &Arc<Self>) {
+        let startup: Vec<(Automation, u64)> = self
+            .automations
+            .iter()
+            .filter_map(|entry| match entry.value().trigger {
+                Trigger::HubStartup { delay_seconds } if entry.value().is_active() => {
+                    Some((entry.value().clone(), delay_seconds))
+                }
+                _ => None,
+            })
+            .collect();
+        if startup.is_empty() {
+            return;
+        }
 
+        let engine = Arc::clone(self);
         tokio::spawn(async move {
-            loop {
+            engine.wait_for_network().await;
+            for (automation, delay_seconds) in startup {
+                let engine = Arc::clone(&engine);
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(delay_seconds)).await;
+                    if let Err(e) = engine.execute_automation(&automation, "startup").await {
+                        tracing::error!(
+                            "Failed to execute startup automation '{}': {}",
+                            automation.name,
+                            e
+                        );
+                    }
+                });
+            }
+        });
+    }
+
+    /// Run the schedule slots missed while the hub was down, for automations
+    /// that catch up (see `catch_up_seconds`), returning once the runs end
+    ///
+    /// Each runs at most once however many of its slots were missed, with
+    /// the slot in the trigger context as `missed_at`.
+    async fn catch_up_missed_schedules(self: &Arc<Self>) {
+        let now = Local::now();
+        let missed: Vec<(Automation, DateTime<Local>)> = self
+            .list()
+            .into_iter()
+            .filter_map(|automation| {
+                let slot = self.missed_slot(&automation, now)?;
+                Some((automation, slot))
+            })
+            .collect();
+
+        let mut runs = Vec::new();
+        for (automation, slot) in missed {
+            tracing::info!(
+                "Catching up on the {} run of automation '{}' missed while down",
+                slot.format("%Y-%m-%d %H:%M"),
+                automation.name
+            );
+            self.schedule_log.record(&automation.id, Utc::now()).await;
+            let engine = Arc::clone(self);
+            runs.push(tokio::spawn(async move {
+                let context = json!({
+                    "trigger": { "reason": "schedule", "missed_at": slot.to_rfc3339() }
+                });
+                if let Err(e) = engine
+                    .execute_with_context(&automation, "schedule", &context)
+                    .await
+                {
+                    tracing::error!(
+                        "Failed to execute scheduled automation '{}': {}",
+                        automation.name,
+                        e
+                    );
+                }
+            }));
+        }
+        for run in runs {
+            let _ = run.await;
+        }
+    }
+
+    /// The latest slot of an active, catching-up schedule that came due
+    /// after it last did (or after the automation last changed) and at most
+    /// `catch_up_seconds` (a week at most) before `now`
+    fn missed_slot(
+        &self,
+        automation: &Automation,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        let Trigger::Schedule {
+            schedule,
+            catch_up_seconds: Some(catch_up_seconds),
+            ..
+        } = &automation.trigger
+        else {
+            return None;
+        };
+        if !automation.is_active() || matches!(schedule, ScheduleSpec::Interval { .. }) {
+            return None;
+        }
+        let last_run = self
+            .runs
+            .get(&automation.id)
+            .into_iter()
+            .find(|run| run.trigger_reason == "schedule")
+            .map(|run| run.started_at);
+        let updated_at = DateTime::parse_from_rfc3339(&automation.updated_at)
+            .ok()
+            .map(|at| at.with_timezone(&Utc));
+        let last_due = [
+            self.schedule_log.last_due(&automation.id),
+            last_run,
+            updated_at,
+        ]
+        .into_iter()
+        .flatten()
+        .max()?;
+
+        let window = chrono::Duration::seconds(
+            i64::try_from((*catch_up_seconds).min(7 * 24 * 3600)).expect("at most a week"),
+        );
+        let zone = self.scheduler.zone_for(&automation.trigger);
+        let now = now.with_timezone(&Utc);
+        backtest::schedule_times(schedule, zone, now - window, now)
+            .ok()?
+            .into_iter()
+            .rev()
+            .find(|&slot| slot > last_due)
+            .map(|slot| slot.with_timezone(&Local))
+    }
+
+    /// Wait until the Zigbee network reports it is connected (returns at once without a network)
+    async fn wait_for_network(&self) {
+        let Some(network) = &self.network else {
+            return;
+        };
+        let mut rx = network.subscribe();
+        loop {
+            if network.get_status().await.is_ok_and(|s| s.connected) {
+                return;
+            }
+            // Re-check on the next state change, or periodically in case it was missed
+            let _ = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    match rx.recv().await {
+                        Ok(NetworkEvent::NetworkStateChanged { connected: true }) => break,
+                        Err(broadcast::error::RecvError::Closed) => {
+                            std::future::pending::<()>().await;
+                        }
+                        _ => {}
+                    }
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Start listening for arrivals and departures
+    fn start_presence_listener(self: &Arc<Self>) {
+        let engine = Arc::clone(self);
+        let mut rx = self.presence.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => engine.handle_presence_event(&event).await,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Presence listener lagged by {} events", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Run automations triggered by a presence change
+    async fn handle_presence_event(&self, event: &PresenceEvent) {
+        let matching: Vec<Automation> = self
+            .automations
+            .iter()
+            .filter(|entry| {
+                let automation = entry.value();
+                automation.is_active() && Self::presence_trigger_matches(&automation.trigger, event)
+            })
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        let context = json!({
+            "trigger": { "reason": "presence", "person": event.person, "value": event.home }
+        });
+        for automation in matching {
+            if let Err(e) = self
+                .execute_with_context(&automation, "presence", &context)
+                .await
+            {
+                tracing::error!("Failed to execute automation '{}': {}", automation.name, e);
+            }
+        }
+    }
+
+    fn presence_trigger_matches(trigger: &Trigger, event: &PresenceEvent) -> bool {
+        let Trigger::Presence { person, change } = trigger else {
+            return false;
+        };
+        let expected_home = *change == PresenceChange::Arrived;
+        person.as_ref().is_none_or(|p| *p == event.person) && event.home == expected_home
+    }
+
+    /// Subscribe to the topics of MQTT triggers and run their automations
+    /// on matching messages
+    fn start_mqtt_listener(self: &Arc<Self>) {
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+            let mut rx = mqtt.subscribe();
+            self.sync_trigger_topics();
+
+            let engine = Arc::clone(self);
+            tokio::spawn(async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(message) => engine.handle_mqtt_message(&message),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            tracing::warn!("MQTT listener lagged by {} messages", n);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+            return;
+        }
+
+        let unconnected = self
+            .automations
+            .iter()
+            .filter(|entry| matches!(entry.trigger, Trigger::Mqtt { .. }))
+            .count();
+        if unconnected > 0 {
+            tracing::warn!(
+                "{} automation(s) with MQTT triggers won't run: no MQTT broker configured",
+                unconnected
+            );
+        }
+    }
+
+    /// Subscribe to the topics of the MQTT triggers, and only those
+    #[cfg(feature = "mqtt")]
+    fn sync_trigger_topics(&self) {
+        if let Some(mqtt) = &self.mqtt {
+            let topics = self
+                .automations
+                .iter()
+                .filter_map(|entry| match &entry.trigger {
+                    Trigger::Mqtt { topic, .. } => Some(topic.clone()),
+                    _ => None,
+                })
+                .collect();
+            mqtt.set_topics(topics);
+        }
+    }
+
+    /// Run automations triggered by an MQTT message, with its topic and
+    /// payload (parsed if it is JSON) as `trigger.topic` and
+    /// `trigger.payload`
+    ///
+    /// The runs go on in the background, so a slow one doesn't hold up the
+    /// messages after it.
+    #[cfg(feature = "mqtt")]
+    fn handle_mqtt_message(self: &Arc<Self>, message: &MqttMessage) {
+        let matching: Vec<Automation> = self
+            .automations
+            .iter()
+            .filter(|entry| {
+                let automation = entry.value();
+                automation.is_active() && Self::mqtt_trigger_matches(&automation.trigger, message)
+            })
+            .map(|entry| entry.value().clone())
+            .collect();
+        if matching.is_empty() {
+            return;
+        }
+
+        let payload = serde_json::from_str(&message.payload)
+            .unwrap_or_else(|_| Value::String(message.payload.clone()));
+        let context = json!({
+            "trigger": { "reason": "mqtt", "topic": message.topic, "payload": payload }
+        });
+        for automation in matching {
+            let engine = Arc::clone(self);
+            let context = context.clone();
+            tokio::spawn(async move {
+                if let Err(e) = engine
+                    .execute_with_context(&automation, "mqtt", &context)
+                    .await
+                {
+                    tracing::error!("Failed to execute automation '{}': {}", automation.name, e);
+                }
+            });
+        }
+    }
+
+    #[cfg(feature = "mqtt")]
+    fn mqtt_trigger_matches(trigger: &Trigger, message: &MqttMessage) -> bool {
+        let Trigger::Mqtt {
+            topic,
+            payload_match,
+        } = trigger
+        else {
+            return false;
+        };
+        mqtt::topic_matches(topic, &message.topic)
+            && payload_match
+                .as_ref()
+                .is_none_or(|text| message.payload.contains(text.as_str()))
+    }
+
+    /// Start listening for scheduler events
+    fn start_scheduler_listener(self: &Arc<Self>) {
+        let engine = Arc::clone(self);
+        let mut rx = self.scheduler.subscribe();
+
+        tokio::spawn(async move {
+            loop {
                 match rx.recv().await {
                     Ok(event) => {
                         if let Some(automation) = engine.get(&event.automation_id) {
-                            if automation.enabled {
+                            // Only needed to catch up after a restart (a
+                            // change of the setting bumps `updated_at`)
+                            if matches!(
+                                automation.trigger,
+                                Trigger::Schedule {
+                                    catch_up_seconds: Some(_),
+                                    ..
+                                }
+                            ) {
+                                engine.schedule_log.record(&automation.id, Utc::now()).await;
+                            }
+                            if automation.is_active() {
                                 if let Err(e) =
                                     engine.execute_automation(&automation, "schedule").await
                                 {
@@ -423,10 +1638,1616 @@ impl AutomationEngine {
     }
 }
 
-fn format_ieee(ieee: [u8; 8]) -> String {
-    ieee.iter()
-        .rev()
-        .map(|b| format!("{b:02x}"))
-        .collect::<Vec<_>>()
-        .join(":")
+/// Compare webhook secrets in time independent of where they differ
+fn secrets_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The reading a sensor value trigger watches, if the event carries it
+fn sensor_reading(trigger: &Trigger, event: &NetworkEvent) -> Option<f64> {
+    let Trigger::SensorValue {
+        device_ieee,
+        attribute,
+        ..
+    } = trigger
+    else {
+        return None;
+    };
+    match event {
+        NetworkEvent::SensorValue {
+            ieee_address,
+            kind,
+            value,
+            ..
+        }
+        | NetworkEvent::PowerMeasurement {
+            ieee_address,
+            kind,
+            value,
+            ..
+        } if kind == attribute && format_ieee(*ieee_address) == *device_ieee => Some(*value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        request, Action, Condition, DeviceCommand, LogLevel, OnTimeout, ScheduleSpec,
+        MAX_ACTION_DEPTH, MAX_LOOP_ITERATIONS,
+    };
+    use crate::run_history::ActionStatus;
+    use crate::stats::AutomationStats;
+    use chrono::TimeZone;
+    use deconz_protocol::{mock::MockTransport, CommandId, DeconzEvent, Status};
+    use std::ops::Deref;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// An empty data directory, unique to the test
+    fn test_dir() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "automation-engine-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// An engine whose data directory is removed when it is dropped
+    struct TestEngine {
+        engine: Arc<AutomationEngine>,
+        network: Option<Arc<ZigbeeNetwork>>,
+        data_dir: PathBuf,
+    }
+
+    impl TestEngine {
+        async fn open(network: Option<Arc<ZigbeeNetwork>>, data_dir: PathBuf) -> Self {
+            let engine = AutomationEngine::new(network.clone(), &data_dir)
+                .await
+                .unwrap();
+            Self {
+                engine: Arc::new(engine),
+                network,
+                data_dir,
+            }
+        }
+
+        /// Stop the engine and start a new one on the same data
+        async fn restart(mut self) -> Self {
+            let network = self.network.take();
+            let data_dir = std::mem::take(&mut self.data_dir);
+            drop(self);
+            Self::open(network, data_dir).await
+        }
+    }
+
+    impl Deref for TestEngine {
+        type Target = Arc<AutomationEngine>;
+
+        fn deref(&self) -> &Self::Target {
+            &self.engine
+        }
+    }
+
+    impl Drop for TestEngine {
+        fn drop(&mut self) {
+            if !self.data_dir.as_os_str().is_empty() {
+                let _ = std::fs::remove_dir_all(&self.data_dir);
+            }
+        }
+    }
+
+    async fn test_engine(network: Option<Arc<ZigbeeNetwork>>) -> TestEngine {
+        TestEngine::open(network, test_dir()).await
+    }
+
+    #[tokio::test]
+    async fn test_device_joined_triggers_automation() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        let engine = test_engine(Some(network)).await;
+        engine.start();
+        let mut events = engine.subscribe();
+
+        let automation = engine
+            .create(request(
+                "Welcome",
+                Trigger::DeviceState {
+                    device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                    endpoint: None,
+                    state_change: StateChange::Joined,
+                    for_duration: None,
+                },
+                vec![Action::Log {
+                    message: "joined".to_string(),
+                    level: LogLevel::Info,
+                }],
+            ))
+            .await
+            .unwrap();
+        let _ = events.recv().await; // Created
+
+        mock.emit(DeconzEvent::DeviceAnnounced {
+            ieee_addr: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            short_addr: 0x1234,
+            capability: 0x8e,
+        });
+
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            event,
+            AutomationEvent::Triggered { automation_id, .. } if automation_id == automation.id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_aborts_delay() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request(
+                "Slow",
+                Trigger::Manual,
+                vec![
+                    Action::Log {
+                        message: "start".to_string(),
+                        level: LogLevel::Info,
+                    },
+                    Action::Delay { seconds: 7200 },
+                ],
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.cancel(&automation.id),
+            Err(AutomationError::NotRunning(_))
+        ));
+
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            let id = automation.id.clone();
+            async move { engine.trigger(&id).await }
+        });
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while engine.running().first().map(|run| run.action_index) != Some(1) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(engine.running()[0].automation_id, automation.id);
+        assert_eq!(engine.running()[0].action_count, 2);
+
+        assert_eq!(engine.cancel(&automation.id).unwrap(), 1);
+        let result = tokio::time::timeout(Duration::from_secs(1), run)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(AutomationError::Cancelled(_))));
+        assert!(engine.running().is_empty());
+
+        let runs = engine.run_history(&automation.id).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].trigger_reason, "manual");
+        assert!(runs[0].conditions_met);
+        let statuses: Vec<_> = runs[0].actions.iter().map(|a| a.status).collect();
+        assert_eq!(statuses, [ActionStatus::Succeeded, ActionStatus::Cancelled]);
+        assert!(runs[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_fires_with_secret() {
+        let engine = test_engine(None).await;
+        let mut events = engine.subscribe();
+        let automation = engine
+            .create(request(
+                "Doorbell",
+                Trigger::Webhook {
+                    id: "doorbell".to_string(),
+                    secret: Some("s3cret".to_string()),
+                },
+                vec![Action::Log {
+                    message: "{{trigger.body.visitor}} is at the door".to_string(),
+                    level: LogLevel::Info,
+                }],
+            ))
+            .await
+            .unwrap();
+        let _ = events.recv().await; // Created
+
+        let body = serde_json::json!({ "visitor": "Sam" });
+        assert!(matches!(
+            engine.fire_webhook("garage", Some("s3cret"), false, body.clone()),
+            Err(AutomationError::WebhookUnauthorized(_))
+        ));
+        assert!(matches!(
+            engine.fire_webhook("doorbell", Some("guess"), false, body.clone()),
+            Err(AutomationError::WebhookUnauthorized(_))
+        ));
+        assert!(matches!(
+            engine.fire_webhook("doorbell", None, false, body.clone()),
+            Err(AutomationError::WebhookUnauthorized(_))
+        ));
+        assert_eq!(
+            engine
+                .fire_webhook("doorbell", Some("s3cret"), false, body.clone())
+                .unwrap(),
+            1
+        );
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            event,
+            AutomationEvent::Triggered { automation_id, trigger_reason }
+                if automation_id == automation.id && trigger_reason == "webhook"
+        ));
+
+        // The owner doesn't need the secret
+        assert_eq!(
+            engine.fire_webhook("doorbell", None, true, body).unwrap(),
+            1
+        );
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[tokio::test]
+    async fn test_mqtt_message_triggers_automation() {
+        let engine = test_engine(None).await;
+        let mut events = engine.subscribe();
+        let automation = engine
+            .create(request(
+                "Person at the door",
+                Trigger::Mqtt {
+                    topic: "frigate/+/events".to_string(),
+                    payload_match: Some("person".to_string()),
+                },
+                vec![Action::Log {
+                    message: "{{trigger.payload.label}} on {{trigger.topic}}".to_string(),
+                    level: LogLevel::Info,
+                }],
+            ))
+            .await
+            .unwrap();
+        let _ = events.recv().await; // Created
+
+        let message = |topic: &str, payload: &str| MqttMessage {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        };
+        engine.handle_mqtt_message(&message("frigate/front/events", r#"{"label":"car"}"#));
+        engine.handle_mqtt_message(&message("zigbee2mqtt/front", r#"{"label":"person"}"#));
+        assert!(events.try_recv().is_err());
+
+        engine.handle_mqtt_message(&message("frigate/front/events", r#"{"label":"person"}"#));
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv()).await;
+        assert!(matches!(
+            event,
+            Ok(Ok(AutomationEvent::Triggered { automation_id, trigger_reason }))
+                if automation_id == automation.id && trigger_reason == "mqtt"
+        ));
+
+        assert!(matches!(
+            engine
+                .update(
+                    &automation.id,
+                    UpdateAutomationRequest {
+                        trigger: Some(Trigger::Mqtt {
+                            topic: "frigate/#/events".to_string(),
+                            payload_match: None,
+                        }),
+                        ..Default::default()
+                    },
+                )
+                .await,
+            Err(AutomationError::InvalidField { .. })
+        ));
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[tokio::test]
+    async fn test_mqtt_topics_follow_triggers() {
+        let data_dir = test_dir();
+        let mqtt = MqttClient::connect(&mqtt::MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1,
+            client_id: "test".to_string(),
+            username: None,
+            password: None,
+        });
+        let engine = AutomationEngine::new(None, &data_dir)
+            .await
+            .unwrap()
+            .with_mqtt(Arc::clone(&mqtt));
+        let subscriber = |topic: &str| {
+            request(
+                topic,
+                Trigger::Mqtt {
+                    topic: topic.to_string(),
+                    payload_match: None,
+                },
+                Vec::new(),
+            )
+        };
+        let first = engine.create(subscriber("doors/front")).await.unwrap();
+        let second = engine.create(subscriber("doors/front")).await.unwrap();
+        assert_eq!(mqtt.topics(), ["doors/front"]);
+
+        engine
+            .update(
+                &first.id,
+                UpdateAutomationRequest {
+                    trigger: Some(Trigger::Mqtt {
+                        topic: "doors/back".to_string(),
+                        payload_match: None,
+                    }),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(mqtt.topics(), ["doors/back", "doors/front"]);
+
+        engine.delete(&second.id).await.unwrap();
+        assert_eq!(mqtt.topics(), ["doors/back"]);
+        engine.delete(&first.id).await.unwrap();
+        assert!(mqtt.topics().is_empty());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cooldown_ignores_repeated_triggers() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(CreateAutomationRequest {
+                cooldown_seconds: Some(60),
+                ..request(
+                    "Hall motion",
+                    Trigger::Manual,
+                    vec![Action::Log {
+                        message: "motion".to_string(),
+                        level: LogLevel::Info,
+                    }],
+                )
+            })
+            .await
+            .unwrap();
+        let runs = || engine.run_history(&automation.id).unwrap().len();
+
+        engine
+            .execute_automation(&automation, "occupancy")
+            .await
+            .unwrap();
+        engine
+            .execute_automation(&automation, "occupancy")
+            .await
+            .unwrap();
+        assert_eq!(runs(), 1);
+        assert_eq!(
+            engine
+                .get(&automation.id)
+                .unwrap()
+                .cooldown_remaining_seconds,
+            Some(60)
+        );
+
+        // Manual runs aren't held back
+        engine.trigger(&automation.id).await.unwrap();
+        assert_eq!(runs(), 2);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(
+            engine
+                .get(&automation.id)
+                .unwrap()
+                .cooldown_remaining_seconds,
+            None
+        );
+        engine
+            .execute_automation(&automation, "occupancy")
+            .await
+            .unwrap();
+        assert_eq!(runs(), 3);
+
+        // `null` removes the cooldown
+        let update: UpdateAutomationRequest =
+            serde_json::from_str(r#"{"cooldown_seconds": null}"#).unwrap();
+        assert_eq!(update.cooldown_seconds, Some(None));
+        let automation = engine.update(&automation.id, update).await.unwrap();
+        assert_eq!(automation.cooldown_seconds, None);
+        engine
+            .execute_automation(&automation, "occupancy")
+            .await
+            .unwrap();
+        assert_eq!(runs(), 4);
+
+        assert!(matches!(
+            engine
+                .update(
+                    &automation.id,
+                    UpdateAutomationRequest {
+                        cooldown_seconds: Some(Some(u64::MAX)),
+                        ..Default::default()
+                    },
+                )
+                .await,
+            Err(AutomationError::InvalidField { field, .. }) if field == "cooldown_seconds"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_last_triggered_limits_runs() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request("Notify", Trigger::Manual, Vec::new()))
+            .await
+            .unwrap();
+        engine
+            .update(
+                &automation.id,
+                UpdateAutomationRequest {
+                    conditions: Some(vec![Condition::LastTriggered {
+                        automation_id: automation.id.clone(),
+                        older_than_seconds: 3600,
+                    }]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        engine.trigger(&automation.id).await.unwrap();
+        engine.trigger(&automation.id).await.unwrap();
+        let runs = engine.run_history(&automation.id).unwrap();
+        let met: Vec<bool> = runs.iter().map(|run| run.conditions_met).collect();
+        assert_eq!(met, [false, true]);
+
+        assert!(matches!(
+            engine
+                .update(
+                    &automation.id,
+                    UpdateAutomationRequest {
+                        conditions: Some(vec![Condition::LastTriggered {
+                            automation_id: automation.id.clone(),
+                            older_than_seconds: u64::MAX,
+                        }]),
+                        ..Default::default()
+                    },
+                )
+                .await,
+            Err(AutomationError::InvalidField { field, .. })
+                if field == "conditions[0].older_than_seconds"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_light_actions() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(zigbee_core::ZigbeeDevice::new(
+            [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            0x1234,
+        ));
+        let engine = test_engine(Some(network.clone())).await;
+        let automation = engine
+            .create(request(
+                "Dim for the evening",
+                Trigger::Manual,
+                vec![
+                    Action::DeviceControl {
+                        device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                        endpoint: 1,
+                        command: DeviceCommand::SetLevel {
+                            level: 80,
+                            transition_time: 600,
+                        },
+                    },
+                    Action::DeviceControl {
+                        device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                        endpoint: 1,
+                        command: DeviceCommand::SetColorTemp {
+                            mireds: 370,
+                            transition: 10,
+                        },
+                    },
+                ],
+            ))
+            .await
+            .unwrap();
+
+        engine.trigger(&automation.id).await.unwrap();
+        let requests = mock.aps_requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].dest_short_addr, 0x1234);
+        assert_eq!(requests[0].cluster_id, 0x0008);
+        // Move to Level (with On/Off): level, then transition time (LE)
+        assert_eq!(requests[0].asdu[2..], [0x04, 80, 0x58, 0x02]);
+        assert_eq!(requests[1].cluster_id, 0x0300);
+        // Move to Color Temperature: mireds, then transition time (LE)
+        assert_eq!(requests[1].asdu[2..], [0x0A, 0x72, 0x01, 10, 0]);
+        let device = network
+            .get_device(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])
+            .unwrap();
+        assert_eq!(device.brightness, Some(80));
+        assert_eq!(
+            device.color,
+            Some(zigbee_core::LightColor::ColorTemp { mireds: 370 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_permit_join_action_opens_network() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond_always(CommandId::WriteParameter, Status::Success, vec![]);
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        let mut network_events = network.subscribe();
+        let engine = test_engine(Some(network)).await;
+        let automation = engine
+            .create(request(
+                "Pairing button",
+                Trigger::Manual,
+                vec![Action::PermitJoin { duration: 120 }],
+            ))
+            .await
+            .unwrap();
+
+        engine.trigger(&automation.id).await.unwrap();
+        assert!(matches!(
+            network_events.try_recv(),
+            Ok(NetworkEvent::PermitJoinChanged {
+                permit_join: true,
+                duration_secs: 120
+            })
+        ));
+        // Routers are opened with a Mgmt_Permit_Joining_req broadcast
+        assert!(mock.aps_requests().iter().any(|r| r.cluster_id == 0x0036));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_state_action() {
+        let ieee = [8u8, 7, 6, 5, 4, 3, 2, 1];
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(ZigbeeNetwork::with_transport(mock, None).await.unwrap());
+        network.upsert_device(zigbee_core::ZigbeeDevice::new(ieee, 0x1234));
+        let engine = test_engine(Some(network.clone())).await;
+        let wait = |state_on, on_timeout| Action::WaitForState {
+            device_ieee: "01:02:03:04:05:06:07:08".to_string(),
+            endpoint: None,
+            state_on,
+            timeout_seconds: 30,
+            on_timeout,
+        };
+        let automation = engine
+            .create(request(
+                "Heater on",
+                Trigger::Manual,
+                vec![
+                    wait(true, OnTimeout::Abort),
+                    Action::Log {
+                        message: "heater confirmed on".to_string(),
+                        level: LogLevel::Info,
+                    },
+                ],
+            ))
+            .await
+            .unwrap();
+
+        // Nothing reported: the run gives up after the timeout
+        assert!(matches!(
+            engine.trigger(&automation.id).await,
+            Err(AutomationError::WaitTimedOut(_))
+        ));
+
+        // The plug confirms while the run waits
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            let id = automation.id.clone();
+            async move { engine.trigger(&id).await }
+        });
+        while engine.running().first().map(|run| run.action_index) != Some(0) {
+            tokio::task::yield_now().await;
+        }
+        network.inject_event(NetworkEvent::DeviceStateChanged {
+            ieee_address: ieee,
+            endpoint: 1,
+            state_on: true,
+        });
+        run.await.unwrap().unwrap();
+        let runs = engine.run_history(&automation.id).unwrap();
+        assert_eq!(runs[0].actions.len(), 2);
+        assert!(runs[0].error.is_none());
+
+        // With `continue` a timeout doesn't stop the run
+        engine
+            .update(
+                &automation.id,
+                UpdateAutomationRequest {
+                    actions: Some(vec![wait(false, OnTimeout::Continue)]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        engine.trigger(&automation.id).await.unwrap();
+
+        // The change is among events the run fell behind on
+        let automation = engine
+            .create(request(
+                "Heater on",
+                Trigger::Manual,
+                vec![wait(true, OnTimeout::Abort)],
+            ))
+            .await
+            .unwrap();
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            let id = automation.id.clone();
+            async move { engine.trigger(&id).await }
+        });
+        while engine.running().first().map(|run| run.action_index) != Some(0) {
+            tokio::task::yield_now().await;
+        }
+        network
+            .update_device_with(&ieee, |device| device.state_on = Some(true))
+            .unwrap();
+        for _ in 0..100 {
+            network.inject_event(NetworkEvent::NetworkStateChanged { connected: true });
+        }
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_repeat_actions() {
+        let engine = test_engine(None).await;
+        let always = Condition::And {
+            conditions: Vec::new(),
+        };
+        let blink = |actions| request("Blink", Trigger::Manual, actions);
+        let automation = engine
+            .create(blink(vec![
+                Action::Repeat {
+                    count: 3,
+                    actions: vec![Action::Delay { seconds: 10 }],
+                },
+                Action::RepeatWhile {
+                    condition: always.clone(),
+                    actions: vec![Action::Delay { seconds: 1 }],
+                    max_iterations: 5,
+                },
+                Action::RepeatWhile {
+                    condition: Condition::Not {
+                        condition: Box::new(always),
+                    },
+                    actions: vec![Action::Delay { seconds: 100 }],
+                    max_iterations: 5,
+                },
+            ]))
+            .await
+            .unwrap();
+
+        let started = tokio::time::Instant::now();
+        engine.trigger(&automation.id).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(35));
+
+        assert!(matches!(
+            engine
+                .create(blink(vec![Action::Repeat {
+                    count: 5000,
+                    actions: Vec::new(),
+                }]))
+                .await,
+            Err(AutomationError::InvalidField { field, .. }) if field == "actions[0].count"
+        ));
+
+        // Nested loops share one budget of passes
+        let nested = engine
+            .create(blink(vec![Action::Repeat {
+                count: MAX_LOOP_ITERATIONS,
+                actions: vec![Action::Repeat {
+                    count: MAX_LOOP_ITERATIONS,
+                    actions: Vec::new(),
+                }],
+            }]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.trigger(&nested.id).await,
+            Err(AutomationError::InvalidAction(message)) if message.contains("loop passes")
+        ));
+
+        let mut deep = Vec::new();
+        for _ in 0..=MAX_ACTION_DEPTH + 1 {
+            deep = vec![Action::Repeat {
+                count: 1,
+                actions: deep,
+            }];
+        }
+        assert!(matches!(
+            engine.create(blink(deep)).await,
+            Err(AutomationError::InvalidField { message, .. }) if message.contains("nested")
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failing_commands_in_loops_get_health_notes() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        let ieee = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        network.upsert_device(zigbee_core::ZigbeeDevice::new(ieee, 0x1234));
+        let engine = test_engine(Some(network.clone())).await;
+        let automation = engine
+            .create(request(
+                "Blink",
+                Trigger::Manual,
+                vec![Action::Repeat {
+                    count: 2,
+                    actions: vec![Action::DeviceControl {
+                        device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                        endpoint: 1,
+                        command: DeviceCommand::TurnOn,
+                    }],
+                }],
+            ))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            for _ in 0..10 {
+                mock.aps_result(Err(deconz_protocol::ProtocolError::Timeout));
+            }
+            assert!(engine.trigger(&automation.id).await.is_err());
+        }
+        let device = network.get_device(&ieee).unwrap();
+        assert_eq!(device.health_notes.len(), 1);
+        assert_eq!(device.health_notes[0].automation_id, automation.id);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_if_action_picks_a_branch() {
+        let engine = test_engine(None).await;
+        let branch = |condition| Action::If {
+            condition,
+            then_actions: vec![Action::Delay { seconds: 10 }],
+            else_actions: vec![Action::Delay { seconds: 20 }],
+        };
+        let always = Condition::And {
+            conditions: Vec::new(),
+        };
+        let automation = engine
+            .create(request(
+                "Day or night scene",
+                Trigger::Manual,
+                vec![
+                    branch(always.clone()),
+                    branch(Condition::Not {
+                        condition: Box::new(always),
+                    }),
+                    // Times in nested conditions are normalized like the others
+                    branch(Condition::TimeRange {
+                        start: "7am".to_string(),
+                        end: "7pm".to_string(),
+                    }),
+                ],
+            ))
+            .await
+            .unwrap();
+        let Action::If { condition, .. } = &automation.actions[2] else {
+            panic!("expected an if action");
+        };
+        assert!(matches!(
+            condition,
+            Condition::TimeRange { start, end } if start == "07:00" && end == "19:00"
+        ));
+
+        // Without the time range: the then branch, then the else branch
+        engine
+            .update(
+                &automation.id,
+                UpdateAutomationRequest {
+                    actions: Some(automation.actions[..2].to_vec()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let started = tokio::time::Instant::now();
+        engine.trigger(&automation.id).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_random_delay_action() {
+        let engine = test_engine(None).await;
+        let random_delay = |min_seconds, max_seconds| {
+            request(
+                "Vacation lights",
+                Trigger::Manual,
+                vec![Action::RandomDelay {
+                    min_seconds,
+                    max_seconds,
+                }],
+            )
+        };
+        let automation = engine.create(random_delay(60, 900)).await.unwrap();
+        for _ in 0..5 {
+            let started = tokio::time::Instant::now();
+            engine.trigger(&automation.id).await.unwrap();
+            let elapsed = started.elapsed();
+            assert!(elapsed >= Duration::from_secs(60) && elapsed <= Duration::from_secs(900));
+        }
+
+        assert!(matches!(
+            engine.create(random_delay(900, 60)).await,
+            Err(AutomationError::InvalidField { field, .. }) if field == "actions[0].max_seconds"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_conditions_use_the_triggering_device() {
+        let ieee = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(Arc::new(MockTransport::new()), None)
+                .await
+                .unwrap(),
+        );
+        let mut device = zigbee_core::ZigbeeDevice::new(ieee, 0x1234);
+        device.state_on = Some(true);
+        network.upsert_device(device);
+        let engine = test_engine(Some(network)).await;
+        let automation = engine
+            .create(CreateAutomationRequest {
+                conditions: vec![Condition::DeviceState {
+                    device_ieee: "{{trigger.device}}".to_string(),
+                    endpoint: None,
+                    state_on: true,
+                }],
+                ..request(
+                    "Announce",
+                    Trigger::DeviceState {
+                        device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                        endpoint: None,
+                        state_change: StateChange::Any,
+                        for_duration: None,
+                    },
+                    vec![Action::Log {
+                        message: "{{trigger.device}} is now {{trigger.value}} ({{now}})"
+                            .to_string(),
+                        level: LogLevel::Info,
+                    }],
+                )
+            })
+            .await
+            .unwrap();
+
+        engine
+            .handle_network_event(NetworkEvent::DeviceStateChanged {
+                ieee_address: ieee,
+                endpoint: 1,
+                state_on: true,
+            })
+            .await;
+        let runs = engine.run_history(&automation.id).unwrap();
+        assert_eq!(runs[0].trigger_reason, "device_state");
+        assert!(runs[0].conditions_met);
+        assert!(runs[0].error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let source = test_engine(None).await;
+        let target = test_engine(None).await;
+        for name in ["Porch light", "Night lock"] {
+            source
+                .create(request(name, Trigger::Manual, Vec::new()))
+                .await
+                .unwrap();
+        }
+        let exported = source.export();
+        let names: Vec<&str> = exported.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Night lock", "Porch light"]);
+
+        let report = target
+            .import(exported.clone(), ImportConflict::Skip)
+            .await
+            .unwrap();
+        assert_eq!(report.created.len(), 2);
+        assert!(target.get(&exported[0].id).is_some());
+
+        let report = target
+            .import(exported.clone(), ImportConflict::Skip)
+            .await
+            .unwrap();
+        assert_eq!(report.skipped.len(), 2);
+
+        let mut renamed = exported.clone();
+        renamed[0].name = "Night lock (v2)".to_string();
+        let report = target
+            .import(renamed[..1].to_vec(), ImportConflict::Replace)
+            .await
+            .unwrap();
+        assert_eq!(report.replaced, [exported[0].id.clone()]);
+        let replaced = target.get(&exported[0].id).unwrap();
+        assert_eq!(replaced.name, "Night lock (v2)");
+        assert_eq!(replaced.revision, exported[0].revision + 1);
+
+        let report = target
+            .import(exported[..1].to_vec(), ImportConflict::Duplicate)
+            .await
+            .unwrap();
+        assert_ne!(report.created[0], exported[0].id);
+        assert_eq!(target.list().len(), 3);
+
+        // A bad file changes nothing
+        let mut bad = exported.clone();
+        bad[0].id = "new".to_string();
+        bad[1].id = "new".to_string();
+        assert!(matches!(
+            target.import(bad, ImportConflict::Replace).await,
+            Err(AutomationError::InvalidField { field, .. }) if field == "automations[1].id"
+        ));
+        assert_eq!(target.list().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_by_tag() {
+        let engine = test_engine(None).await;
+        let mut ids = Vec::new();
+        for (name, tags) in [
+            ("Vacation lights", vec![" vacation ", "lights", "vacation"]),
+            ("Vacation blinds", vec!["vacation"]),
+            ("Porch light", vec!["lights"]),
+        ] {
+            let automation = engine
+                .create(CreateAutomationRequest {
+                    tags: tags.into_iter().map(String::from).collect(),
+                    ..request(name, Trigger::Manual, Vec::new())
+                })
+                .await
+                .unwrap();
+            ids.push(automation.id);
+        }
+        assert_eq!(engine.get(&ids[0]).unwrap().tags, ["vacation", "lights"]);
+
+        let report = engine
+            .bulk(BulkRequest {
+                action: BulkAction::Disable,
+                ids: vec![ids[2].clone()],
+                tag: Some("vacation".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.automations.len(), 3);
+        assert!(engine.list().iter().all(|a| !a.enabled));
+
+        // An unknown ID stops the whole request
+        assert!(matches!(
+            engine
+                .bulk(BulkRequest {
+                    action: BulkAction::Enable,
+                    ids: vec!["missing".to_string()],
+                    tag: Some("vacation".to_string()),
+                })
+                .await,
+            Err(AutomationError::NotFound(_))
+        ));
+        assert!(engine.list().iter().all(|a| !a.enabled));
+
+        let report = engine
+            .bulk(BulkRequest {
+                action: BulkAction::Enable,
+                ids: Vec::new(),
+                tag: Some("lights".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.automations.len(), 2);
+        assert!(!engine.get(&ids[1]).unwrap().enabled);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_sends_nothing() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        let mut device = zigbee_core::ZigbeeDevice::new([8u8, 7, 6, 5, 4, 3, 2, 1], 0x1234);
+        device.state_on = Some(true);
+        network.upsert_device(device);
+        let engine = test_engine(Some(network)).await;
+        let always = Condition::And {
+            conditions: Vec::new(),
+        };
+        let turn_on = |device_ieee: &str| Action::DeviceControl {
+            device_ieee: device_ieee.to_string(),
+            endpoint: 1,
+            command: DeviceCommand::TurnOn,
+        };
+        let automation = engine
+            .create(CreateAutomationRequest {
+                conditions: vec![
+                    always.clone(),
+                    Condition::Not {
+                        condition: Box::new(always.clone()),
+                    },
+                ],
+                ..request(
+                    "Hall light",
+                    Trigger::DeviceState {
+                        device_ieee: "01:02:03:04:05:06:07:08".to_string(),
+                        endpoint: None,
+                        state_change: StateChange::TurnedOn,
+                        for_duration: None,
+                    },
+                    vec![
+                        turn_on("01:02:03:04:05:06:07:08"),
+                        turn_on("11:11:11:11:11:11:11:11"),
+                        Action::If {
+                            condition: always,
+                            then_actions: vec![Action::Delay { seconds: 600 }],
+                            else_actions: Vec::new(),
+                        },
+                        Action::TriggerAutomation {
+                            automation_id: "missing".to_string(),
+                        },
+                    ],
+                )
+            })
+            .await
+            .unwrap();
+
+        let report = engine.dry_run(&automation.id).unwrap();
+        assert!(report.active);
+        assert_eq!(report.trigger_state, Some(true));
+        let met: Vec<bool> = report.conditions.iter().map(|check| check.met).collect();
+        assert_eq!(met, [true, false]);
+        assert!(!report.conditions_met);
+        let paths: Vec<&str> = report.steps.iter().map(|step| step.path.as_str()).collect();
+        assert_eq!(paths, ["0", "1", "2", "2.then_actions.0", "3"]);
+        assert_eq!(report.steps[0].action, "device_control");
+        assert!(report.steps[0].error.is_none());
+        assert!(report.steps[1].error.is_some());
+        assert_eq!(report.steps[3].action, "delay");
+        assert!(report.steps[4].error.is_some());
+        // Nothing went out and nothing ran
+        assert!(mock.aps_requests().is_empty());
+        assert!(engine.run_history(&automation.id).unwrap().is_empty());
+        assert!(matches!(
+            engine.dry_run("missing"),
+            Err(AutomationError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_trace() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(ZigbeeNetwork::with_transport(mock, None).await.unwrap());
+        let mut device = zigbee_core::ZigbeeDevice::new([1, 2, 3, 4, 5, 6, 7, 8], 0x1234);
+        device.state_on = Some(true);
+        network.upsert_device(device);
+        let engine = test_engine(Some(network)).await;
+        let light_is = |state_on| Condition::DeviceState {
+            device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+            endpoint: None,
+            state_on,
+        };
+        let automation = engine
+            .create(CreateAutomationRequest {
+                conditions: vec![Condition::Or {
+                    conditions: vec![light_is(false), light_is(true)],
+                }],
+                ..request(
+                    "Lights out",
+                    Trigger::Manual,
+                    vec![
+                        Action::If {
+                            condition: light_is(true),
+                            then_actions: vec![Action::DeviceControl {
+                                device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                                endpoint: 1,
+                                command: DeviceCommand::TurnOff,
+                            }],
+                            else_actions: Vec::new(),
+                        },
+                        Action::Delay { seconds: 5 },
+                        Action::Log {
+                            message: "{{trigger.reason}} run done".to_string(),
+                            level: LogLevel::Info,
+                        },
+                    ],
+                )
+            })
+            .await
+            .unwrap();
+        engine.trigger(&automation.id).await.unwrap();
+
+        let runs = engine.run_history(&automation.id).unwrap();
+        assert!(runs[0].trace.is_none());
+        let run = engine
+            .run_trace(&automation.id, &runs[0].id)
+            .unwrap()
+            .unwrap();
+        let trace = run.trace.unwrap();
+        assert_eq!(trace.trigger["reason"], "manual");
+        assert!(!trace.truncated);
+
+        let conditions: Vec<(&str, bool)> = trace
+            .conditions
+            .iter()
+            .map(|check| (check.path.as_str(), check.met))
+            .collect();
+        assert_eq!(
+            conditions,
+            [
+                ("0", true),
+                ("0.conditions.0", false),
+                ("0.conditions.1", true)
+            ]
+        );
+        assert_eq!(trace.conditions[1].actual, Some(json!(true)));
+
+        let paths: Vec<&str> = trace.actions.iter().map(|a| a.path.as_str()).collect();
+        assert_eq!(paths, ["0", "0.then_actions.0", "1", "2"]);
+        assert_eq!(trace.actions[0].details, ["run then_actions"]);
+        assert!(trace.actions[1].details[0].contains("turn_off"));
+        assert_eq!(trace.actions[1].status, ActionStatus::Succeeded);
+        assert_eq!(trace.actions[2].duration_ms, 5000);
+        assert_eq!(trace.actions[3].details, ["manual run done"]);
+
+        assert!(engine
+            .run_trace(&automation.id, "unknown")
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stats_survive_restart() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request(
+                "Porch light",
+                Trigger::Manual,
+                vec![Action::Log {
+                    message: "porch".to_string(),
+                    level: LogLevel::Info,
+                }],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(engine.list()[0].stats, Some(AutomationStats::default()));
+        engine.trigger(&automation.id).await.unwrap();
+        engine.trigger(&automation.id).await.unwrap();
+        let engine = engine.restart().await;
+        let stats = engine.list()[0].stats.clone().unwrap();
+        assert_eq!(stats.trigger_count, 2);
+        assert_eq!(stats.success_count, 2);
+        assert_eq!(stats.failure_count, 0);
+        assert!(stats.last_triggered.is_some());
+        // Counters aren't part of what's stored or exported
+        assert!(engine.export()[0].stats.is_none());
+
+        engine.delete(&automation.id).await.unwrap();
+        assert_eq!(engine.stats.get(&automation.id), AutomationStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_missed_schedule_is_caught_up() {
+        let engine = test_engine(None).await;
+        // Every minute, so a slot came due in the last two
+        let every_minute = |name: &str, catch_up_seconds| {
+            request(
+                name,
+                Trigger::Schedule {
+                    schedule: ScheduleSpec::Cron {
+                        expression: "0 * * * * *".to_string(),
+                    },
+                    catch_up_seconds,
+                    timezone: None,
+                },
+                Vec::new(),
+            )
+        };
+        let catching_up = engine
+            .create(every_minute("Lights on", Some(120)))
+            .await
+            .unwrap();
+        let skipping = engine.create(every_minute("Blinds", None)).await.unwrap();
+        let runs = |id: &str| engine.run_history(id).unwrap();
+
+        // Just created: nothing was missed
+        engine.catch_up_missed_schedules().await;
+        assert!(runs(&catching_up.id).is_empty());
+
+        // As if the hub had been down for the last hour
+        let an_hour_ago = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        for id in [&catching_up.id, &skipping.id] {
+            engine.automations.get_mut(id).unwrap().updated_at = an_hour_ago.clone();
+        }
+        engine.catch_up_missed_schedules().await;
+        let caught_up = runs(&catching_up.id);
+        assert_eq!(caught_up.len(), 1);
+        assert_eq!(caught_up[0].trigger_reason, "schedule");
+        assert!(runs(&skipping.id).is_empty());
+        assert!(engine.schedule_log.last_due(&catching_up.id).is_some());
+        assert!(engine.schedule_log.last_due(&skipping.id).is_none());
+
+        // Once only
+        engine.catch_up_missed_schedules().await;
+        assert_eq!(runs(&catching_up.id).len(), 1);
+
+        let mut interval = every_minute("Poll", Some(60));
+        interval.trigger = Trigger::Schedule {
+            schedule: ScheduleSpec::Interval { seconds: 30 },
+            catch_up_seconds: Some(60),
+            timezone: None,
+        };
+        assert!(engine.create(interval).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_runs_count_as_caught_up() {
+        let engine = test_engine(None).await;
+        let every_minute = |name: &str| {
+            request(
+                name,
+                Trigger::Schedule {
+                    schedule: ScheduleSpec::Cron {
+                        expression: "0 * * * * *".to_string(),
+                    },
+                    catch_up_seconds: Some(120),
+                    timezone: None,
+                },
+                Vec::new(),
+            )
+        };
+        let scheduled = engine.create(every_minute("Lights on")).await.unwrap();
+        let manual = engine.create(every_minute("Blinds")).await.unwrap();
+        let an_hour_ago = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        for id in [&scheduled.id, &manual.id] {
+            engine.automations.get_mut(id).unwrap().updated_at = an_hour_ago.clone();
+        }
+
+        // The schedule ran it after the latest slot; running it by hand
+        // doesn't count
+        let automation = engine.get(&scheduled.id).unwrap();
+        engine
+            .execute_automation(&automation, "schedule")
+            .await
+            .unwrap();
+        engine.trigger(&manual.id).await.unwrap();
+        assert!(engine.schedule_log.last_due(&scheduled.id).is_none());
+
+        engine.catch_up_missed_schedules().await;
+        assert_eq!(engine.run_history(&scheduled.id).unwrap().len(), 1);
+        let runs = engine.run_history(&manual.id).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].trigger_reason, "schedule");
+    }
+
+    #[tokio::test]
+    async fn test_mqtt_publish_needs_a_broker() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request(
+                "Signal",
+                Trigger::Manual,
+                vec![Action::MqttPublish {
+                    topic: "casita/{{trigger.reason}}".to_string(),
+                    payload: "on".to_string(),
+                    retain: false,
+                    qos: 0,
+                }],
+            ))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            engine.trigger(&automation.id).await,
+            Err(AutomationError::Mqtt(_))
+        ));
+        let runs = engine.run_history(&automation.id).unwrap();
+        assert_eq!(runs[0].actions[0].status, ActionStatus::Failed);
+
+        let bad_qos = request(
+            "Signal",
+            Trigger::Manual,
+            vec![Action::MqttPublish {
+                topic: "casita/signal".to_string(),
+                payload: String::new(),
+                retain: false,
+                qos: 3,
+            }],
+        );
+        assert!(matches!(
+            engine.create(bad_qos).await,
+            Err(AutomationError::InvalidField { field, .. }) if field == "actions[0].qos"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_update_checked_rejects_stale_revision() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request("Night", Trigger::Manual, Vec::new()))
+            .await
+            .unwrap();
+        assert_eq!(automation.revision, 1);
+
+        let rename = |name: &str| UpdateAutomationRequest {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let updated = engine
+            .update_checked(&automation.id, rename("Night lights"), Some(1))
+            .await
+            .unwrap();
+        assert_eq!(updated.revision, 2);
+
+        let stale = engine
+            .update_checked(&automation.id, rename("Evening"), Some(1))
+            .await;
+        assert!(matches!(
+            stale,
+            Err(AutomationError::RevisionMismatch {
+                expected: 1,
+                current: 2
+            })
+        ));
+        assert_eq!(engine.get(&automation.id).unwrap().name, "Night lights");
+    }
+
+    #[tokio::test]
+    async fn test_delete_folder_moves_automations_out() {
+        let engine = test_engine(None).await;
+        let folder = engine
+            .folders()
+            .create(crate::folder::CreateFolderRequest {
+                name: "Lights".to_string(),
+                sort_order: 0,
+            })
+            .await
+            .unwrap();
+        let in_folder = |name: &str, folder: Option<String>, sort_order| CreateAutomationRequest {
+            folder,
+            sort_order,
+            ..request(name, Trigger::Manual, Vec::new())
+        };
+        assert!(matches!(
+            engine
+                .create(in_folder("Orphan", Some("missing".to_string()), 0))
+                .await,
+            Err(AutomationError::InvalidFolder(_))
+        ));
+        let filed = engine
+            .create(in_folder("Porch", Some(folder.id.clone()), 2))
+            .await
+            .unwrap();
+        engine.create(in_folder("Hall", None, 1)).await.unwrap();
+
+        let names: Vec<_> = engine.list().into_iter().map(|a| a.name).collect();
+        assert_eq!(names, vec!["Hall", "Porch"]);
+
+        engine.delete_folder(&folder.id).await.unwrap();
+        assert!(engine.folders().list().is_empty());
+        assert_eq!(engine.get(&filed.id).unwrap().folder, None);
+    }
+
+    #[test]
+    fn test_availability_triggers() {
+        let trigger = |state_change| Trigger::DeviceState {
+            device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+            endpoint: None,
+            state_change,
+            for_duration: None,
+        };
+        let event = |available| NetworkEvent::AvailabilityChanged {
+            ieee_address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            available,
+        };
+        let unavailable = trigger(StateChange::Unavailable);
+        assert!(AutomationEngine::trigger_matches(
+            &unavailable,
+            &event(false)
+        ));
+        assert!(!AutomationEngine::trigger_matches(
+            &unavailable,
+            &event(true)
+        ));
+        assert!(AutomationEngine::trigger_matches(
+            &trigger(StateChange::Available),
+            &event(true)
+        ));
+        assert!(!AutomationEngine::trigger_matches(
+            &trigger(StateChange::TurnedOn),
+            &event(true)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_for_duration_waits_for_state_to_hold() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request(
+                "Hall lights off",
+                Trigger::Occupancy {
+                    device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                    occupied: Some(false),
+                    for_duration: Some(600),
+                },
+                Vec::new(),
+            ))
+            .await
+            .unwrap();
+        let mut events = engine.subscribe();
+        let occupancy = |occupied| NetworkEvent::OccupancyChanged {
+            ieee_address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            endpoint: 1,
+            occupied,
+        };
+        let settle = || async {
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+        };
+
+        tokio::time::pause();
+        engine.handle_network_event(occupancy(false)).await;
+        tokio::time::advance(Duration::from_secs(300)).await;
+        // Motion again restarts the wait
+        engine.handle_network_event(occupancy(true)).await;
+        engine.handle_network_event(occupancy(false)).await;
+        tokio::time::advance(Duration::from_secs(599)).await;
+        settle().await;
+        assert!(events.try_recv().is_err());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        settle().await;
+        assert!(matches!(
+            events.try_recv(),
+            Ok(AutomationEvent::Triggered { automation_id, .. }) if automation_id == automation.id
+        ));
+        assert!(engine.pending.is_empty());
+
+        assert!(matches!(
+            engine
+                .update(
+                    &automation.id,
+                    UpdateAutomationRequest {
+                        trigger: Some(Trigger::Occupancy {
+                            device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                            occupied: Some(false),
+                            for_duration: Some(u64::MAX),
+                        }),
+                        ..Default::default()
+                    },
+                )
+                .await,
+            Err(AutomationError::InvalidField { field, .. }) if field == "trigger.for_duration"
+        ));
+    }
+
+    #[test]
+    fn test_sensor_value_trigger_fires_on_crossing() {
+        let mut automation = Automation::from_request(request(
+            "Too warm",
+            Trigger::SensorValue {
+                device_ieee: "08:07:06:05:04:03:02:01".to_string(),
+                attribute: zigbee_core::SensorKind::Temperature,
+                operator: crate::model::Comparison::Above,
+                value: 25.0,
+                hysteresis: 0.5,
+            },
+            Vec::new(),
+        ));
+        automation.id = "warm".to_string();
+        let reading = |kind, value| NetworkEvent::SensorValue {
+            ieee_address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            endpoint: 1,
+            kind,
+            value,
+        };
+        let states = DashMap::new();
+        let fired: Vec<bool> = [24.0, 25.5, 26.0, 24.8, 25.2, 24.4, 25.1]
+            .into_iter()
+            .map(|value| {
+                AutomationEngine::trigger_fires(
+                    &states,
+                    &automation,
+                    &reading(zigbee_core::SensorKind::Temperature, value),
+                )
+            })
+            .collect();
+        // Dipping to 24.8 is within the hysteresis; 24.4 re-arms the trigger
+        assert_eq!(fired, [false, true, false, false, false, false, true]);
+        assert!(!AutomationEngine::trigger_fires(
+            &states,
+            &automation,
+            &reading(zigbee_core::SensorKind::Humidity, 10.0)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_snooze_expires() {
+        let engine = test_engine(None).await;
+        let automation = engine
+            .create(request("Motion light", Trigger::Manual, Vec::new()))
+            .await
+            .unwrap();
+
+        let snoozed = engine
+            .snooze(
+                &automation.id,
+                &SnoozeRequest {
+                    minutes: Some(30),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(!snoozed.is_active());
+        assert!(engine.list()[0].snoozed_until.is_some());
+        assert!(engine
+            .snooze(&automation.id, &SnoozeRequest::default())
+            .await
+            .is_err());
+
+        // Runs out
+        engine
+            .automations
+            .get_mut(&automation.id)
+            .unwrap()
+            .snoozed_until = Some(Utc::now() - chrono::Duration::minutes(1));
+        assert!(engine.list()[0].snoozed_until.is_none());
+        assert!(engine.get(&automation.id).unwrap().is_active());
+    }
+
+    #[test]
+    fn test_snooze_until_time_of_day() {
+        let now = Local.with_ymd_and_hms(2024, 3, 10, 21, 0, 0).unwrap();
+        let until = |until: &str| {
+            SnoozeRequest {
+                until: Some(until.to_string()),
+                minutes: None,
+            }
+            .resolve(now, Zone::Local)
+        };
+        let tomorrow = Local.with_ymd_and_hms(2024, 3, 11, 7, 0, 0).unwrap();
+        assert_eq!(until("7am").unwrap(), tomorrow);
+        assert_eq!(
+            until(&tomorrow.to_rfc3339()).unwrap(),
+            tomorrow.with_timezone(&Utc)
+        );
+        assert!(until("2024-03-09T12:00:00Z").is_err());
+        assert!(until("someday").is_err());
+    }
 }
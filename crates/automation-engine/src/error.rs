@@ -13,6 +13,10 @@ pub enum AutomationError {
     #[error("Automation is disabled: {0}")]
     Disabled(String),
 
+    /// The automation changed since the client read it
+    #[error("Automation was modified (current revision {current}, expected {expected})")]
+    RevisionMismatch { expected: u64, current: u64 },
+
     /// Invalid trigger configuration
     #[error("Invalid trigger: {0}")]
     InvalidTrigger(String),
@@ -29,10 +33,35 @@ pub enum AutomationError {
     #[error("Invalid cron expression: {0}")]
     InvalidCron(String),
 
+    /// Invalid value in a specific field (e.g. `conditions[0].start`)
+    #[error("Invalid {field}: {message}")]
+    InvalidField { field: String, message: String },
+
     /// Invalid time format
     #[error("Invalid time format: {0}")]
     InvalidTimeFormat(String),
 
+    /// Invalid climate schedule
+    #[error("Invalid climate schedule: {0}")]
+    InvalidClimateSchedule(String),
+
+    /// A webhook was called without its secret, or no automation listens
+    /// on it (callers aren't told which)
+    #[error("Missing or wrong secret for webhook: {0}")]
+    WebhookUnauthorized(String),
+
+    /// Folder not found
+    #[error("Folder not found: {0}")]
+    FolderNotFound(String),
+
+    /// Invalid folder request or reference
+    #[error("Invalid folder: {0}")]
+    InvalidFolder(String),
+
+    /// Invalid presence update
+    #[error("Invalid presence update: {0}")]
+    InvalidPresence(String),
+
     /// Device not found for action
     #[error("Device not found: {0}")]
     DeviceNotFound(String),
@@ -41,10 +70,30 @@ pub enum AutomationError {
     #[error("Device control failed: {0}")]
     DeviceControlFailed(String),
 
+    /// The run was cancelled before it finished
+    #[error("Automation run cancelled: {0}")]
+    Cancelled(String),
+
+    /// A wait action timed out with `on_timeout: abort`
+    #[error("Timed out waiting for device: {0}")]
+    WaitTimedOut(String),
+
+    /// Cancel requested for an automation with no run in progress
+    #[error("Automation is not running: {0}")]
+    NotRunning(String),
+
     /// Circular automation reference detected
     #[error("Circular automation reference detected: {0}")]
     CircularReference(String),
 
+    /// A script failed to run (or the build has no scripting)
+    #[error("Script error: {0}")]
+    Script(String),
+
+    /// MQTT not configured or publishing failed
+    #[error("MQTT error: {0}")]
+    Mqtt(String),
+
     /// IO error (persistence)
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -1,28 +1,88 @@
 //! Condition evaluator for automations
 
+use crate::clock::Zone;
 use crate::error::AutomationError;
-use crate::model::Condition;
-use chrono::{Datelike, Local, NaiveTime};
+use crate::ieee::parse_ieee_address;
+use crate::model::{Comparison, Condition};
+use crate::presence::PresenceTracker;
+use crate::stats::StatsStore;
+use crate::template;
+use crate::trace::{type_name, ConditionTrace};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, NaiveTime, Utc};
+use serde_json::{json, Value};
 use std::sync::Arc;
-use zigbee_core::ZigbeeNetwork;
+use zigbee_core::{SensorKind, ZigbeeDevice, ZigbeeNetwork};
 
 /// Evaluator for automation conditions
 pub struct ConditionEvaluator {
     network: Option<Arc<ZigbeeNetwork>>,
+    presence: Arc<PresenceTracker>,
+    stats: Arc<StatsStore>,
+    /// Time zone of time range and day of week conditions
+    zone: Zone,
 }
 
 impl ConditionEvaluator {
     /// Create a new condition evaluator
     #[must_use]
-    pub fn new(network: Option<Arc<ZigbeeNetwork>>) -> Self {
-        Self { network }
+    pub fn new(
+        network: Option<Arc<ZigbeeNetwork>>,
+        presence: Arc<PresenceTracker>,
+        stats: Arc<StatsStore>,
+    ) -> Self {
+        Self {
+            network,
+            presence,
+            stats,
+            zone: Zone::Local,
+        }
+    }
+
+    /// Check time range and day of week conditions in `zone` instead of
+    /// the system's
+    #[must_use]
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    /// Time zone of time range and day of week conditions
+    #[must_use]
+    pub fn zone(&self) -> Zone {
+        self.zone
+    }
+
+    /// The wall-clock time at `now` in the evaluator's zone
+    fn wall_clock(&self, now: DateTime<Local>) -> NaiveDateTime {
+        self.zone.wall_clock(now.with_timezone(&Utc))
     }
 
     /// Evaluate all conditions (all must pass for AND semantics)
+    ///
+    /// Placeholders in device IEEE addresses, automation IDs and people are
+    /// filled from the run's `context` (see [`crate::template`]).
     #[allow(clippy::missing_errors_doc)]
-    pub fn evaluate_all(&self, conditions: &[Condition]) -> Result<bool, AutomationError> {
+    pub fn evaluate_all(
+        &self,
+        conditions: &[Condition],
+        context: &Value,
+    ) -> Result<bool, AutomationError> {
+        self.evaluate_all_at(conditions, Local::now(), context)
+    }
+
+    /// Evaluate all conditions with time-based ones checked at `now`
+    ///
+    /// Device, presence and last-run conditions always use the current
+    /// state.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn evaluate_all_at(
+        &self,
+        conditions: &[Condition],
+        now: DateTime<Local>,
+        context: &Value,
+    ) -> Result<bool, AutomationError> {
         for condition in conditions {
-            if !self.evaluate(condition)? {
+            if !self.evaluate_at(condition, now, context)? {
                 return Ok(false);
             }
         }
@@ -31,17 +91,79 @@ impl ConditionEvaluator {
 
     /// Evaluate a single condition
     #[allow(clippy::missing_errors_doc)]
-    pub fn evaluate(&self, condition: &Condition) -> Result<bool, AutomationError> {
+    pub fn evaluate(
+        &self,
+        condition: &Condition,
+        context: &Value,
+    ) -> Result<bool, AutomationError> {
+        self.evaluate_at(condition, Local::now(), context)
+    }
+
+    /// Evaluate a single condition with time-based checks at `now`
+    #[allow(clippy::missing_errors_doc)]
+    pub fn evaluate_at(
+        &self,
+        condition: &Condition,
+        now: DateTime<Local>,
+        context: &Value,
+    ) -> Result<bool, AutomationError> {
         match condition {
-            Condition::TimeRange { start, end } => Self::evaluate_time_range(start, end),
-            Condition::DayOfWeek { days } => Ok(Self::evaluate_day_of_week(days)),
+            Condition::TimeRange { start, end } => {
+                Self::evaluate_time_range(start, end, self.wall_clock(now))
+            }
+            Condition::DayOfWeek { days } => {
+                Ok(Self::evaluate_day_of_week(days, self.wall_clock(now)))
+            }
             Condition::DeviceAvailable {
                 device_ieee,
                 available,
-            } => self.evaluate_device_available(device_ieee, *available),
+            } => {
+                self.evaluate_device_available(&template::render(device_ieee, context), *available)
+            }
+            Condition::DeviceState {
+                device_ieee,
+                endpoint,
+                state_on,
+            } => self.evaluate_device_state(
+                &template::render(device_ieee, context),
+                *endpoint,
+                *state_on,
+            ),
+            Condition::SensorValue {
+                device_ieee,
+                attribute,
+                operator,
+                value,
+            } => self.evaluate_sensor_value(
+                &template::render(device_ieee, context),
+                *attribute,
+                *operator,
+                *value,
+            ),
+            Condition::LastTriggered {
+                automation_id,
+                older_than_seconds,
+            } => Ok(self
+                .stats
+                .get(&template::render(automation_id, context))
+                .last_ran_actions
+                .is_none_or(|at| {
+                    // Never older than an unrepresentable span
+                    i64::try_from(*older_than_seconds)
+                        .ok()
+                        .and_then(chrono::TimeDelta::try_seconds)
+                        .is_some_and(|older_than| {
+                            now.with_timezone(&chrono::Utc) - at >= older_than
+                        })
+                })),
+            Condition::Presence { person, home } => Ok(match person {
+                Some(person) => self.presence.is_home(&template::render(person, context)) == *home,
+                None => self.presence.anyone_home() == *home,
+            }),
+            Condition::Script { source } => self.evaluate_script(source, context),
             Condition::And { conditions } => {
                 for c in conditions {
-                    if !self.evaluate(c)? {
+                    if !self.evaluate_at(c, now, context)? {
                         return Ok(false);
                     }
                 }
@@ -49,20 +171,144 @@ impl ConditionEvaluator {
             }
             Condition::Or { conditions } => {
                 for c in conditions {
-                    if self.evaluate(c)? {
+                    if self.evaluate_at(c, now, context)? {
                         return Ok(true);
                     }
                 }
                 Ok(false)
             }
-            Condition::Not { condition } => Ok(!self.evaluate(condition)?),
+            Condition::Not { condition } => Ok(!self.evaluate_at(condition, now, context)?),
+        }
+    }
+
+    /// Evaluate all conditions like [`Self::evaluate_all`], noting each
+    /// check and the value it looked at
+    pub fn trace_all(
+        &self,
+        conditions: &[Condition],
+        context: &Value,
+    ) -> (Result<bool, AutomationError>, Vec<ConditionTrace>) {
+        let now = Local::now();
+        let mut traces = Vec::new();
+        for (i, condition) in conditions.iter().enumerate() {
+            match self.trace_at(condition, &i.to_string(), now, context, &mut traces) {
+                Ok(true) => {}
+                result => return (result, traces),
+            }
+        }
+        (Ok(true), traces)
+    }
+
+    /// Evaluate a condition like [`Self::evaluate_at`], appending its check
+    /// (and those of nested conditions) to `traces`
+    fn trace_at(
+        &self,
+        condition: &Condition,
+        path: &str,
+        now: DateTime<Local>,
+        context: &Value,
+        traces: &mut Vec<ConditionTrace>,
+    ) -> Result<bool, AutomationError> {
+        let entry = traces.len();
+        traces.push(ConditionTrace {
+            path: path.to_string(),
+            condition: type_name(condition),
+            met: false,
+            actual: self.observe(condition, now, context),
+            error: None,
+        });
+        let result = match condition {
+            Condition::And { conditions } | Condition::Or { conditions } => {
+                let any = matches!(condition, Condition::Or { .. });
+                let mut result = Ok(!any);
+                for (i, c) in conditions.iter().enumerate() {
+                    match self.trace_at(c, &format!("{path}.conditions.{i}"), now, context, traces)
+                    {
+                        Ok(met) if met != any => {}
+                        other => {
+                            result = other;
+                            break;
+                        }
+                    }
+                }
+                result
+            }
+            Condition::Not { condition } => self
+                .trace_at(
+                    condition,
+                    &format!("{path}.condition"),
+                    now,
+                    context,
+                    traces,
+                )
+                .map(|met| !met),
+            _ => self.evaluate_at(condition, now, context),
+        };
+        let trace = &mut traces[entry];
+        match &result {
+            Ok(met) => trace.met = *met,
+            Err(e) => trace.error = Some(e.to_string()),
+        }
+        result
+    }
+
+    /// The value a condition looks at: the time or weekday, the device's
+    /// availability, state or reading, when the automation last ran, or
+    /// whether the person is home
+    fn observe(
+        &self,
+        condition: &Condition,
+        now: DateTime<Local>,
+        context: &Value,
+    ) -> Option<Value> {
+        let device = |device_ieee: &str| {
+            let ieee = parse_ieee_address(&template::render(device_ieee, context)).ok()?;
+            self.network.as_ref()?.get_device(&ieee)
+        };
+        match condition {
+            Condition::TimeRange { .. } => {
+                Some(json!(self.wall_clock(now).format("%H:%M:%S").to_string()))
+            }
+            Condition::DayOfWeek { .. } => {
+                Some(json!(self.wall_clock(now).weekday().num_days_from_sunday()))
+            }
+            Condition::DeviceAvailable { device_ieee, .. } => {
+                Some(json!(device(device_ieee).map(|d| d.available)))
+            }
+            Condition::DeviceState { device_ieee, .. } => {
+                Some(json!(device(device_ieee).and_then(|d| d.state_on)))
+            }
+            Condition::SensorValue {
+                device_ieee,
+                attribute,
+                ..
+            } => Some(json!(
+                device(device_ieee).and_then(|d| d.sensor_values.get(attribute).copied())
+            )),
+            Condition::LastTriggered { automation_id, .. } => Some(json!(
+                self.stats
+                    .get(&template::render(automation_id, context))
+                    .last_ran_actions
+            )),
+            Condition::Presence { person, .. } => Some(json!(match person {
+                Some(person) => self.presence.is_home(&template::render(person, context)),
+                None => self.presence.anyone_home(),
+            })),
+            Condition::Script { .. }
+            | Condition::And { .. }
+            | Condition::Or { .. }
+            | Condition::Not { .. } => None,
         }
     }
 
-    fn evaluate_time_range(start: &str, end: &str) -> Result<bool, AutomationError> {
+    fn evaluate_time_range(
+        start: &str,
+        end: &str,
+        now: NaiveDateTime,
+    ) -> Result<bool, AutomationError> {
         let start_time = parse_time(start)?;
         let end_time = parse_time(end)?;
-        let now = Local::now().time();
+        let now = now.time();
 
         // Handle wrap-around (e.g., 22:00 to 06:00)
         let in_range = if start_time <= end_time {
@@ -76,13 +322,12 @@ impl ConditionEvaluator {
         Ok(in_range)
     }
 
-    fn evaluate_day_of_week(days: &[u8]) -> bool {
+    fn evaluate_day_of_week(days: &[u8], now: NaiveDateTime) -> bool {
         if days.is_empty() {
             return true; // Empty means every day
         }
 
-        let today =
-            u8::try_from(Local::now().weekday().num_days_from_sunday()).expect("weekday is 0-6");
+        let today = u8::try_from(now.weekday().num_days_from_sunday()).expect("weekday is 0-6");
         days.contains(&today)
     }
 
@@ -102,35 +347,89 @@ impl ConditionEvaluator {
 
         Ok(is_available == should_be_available)
     }
-}
 
-/// Parse a time string in HH:MM format
-fn parse_time(s: &str) -> Result<NaiveTime, AutomationError> {
-    NaiveTime::parse_from_str(s, "%H:%M")
-        .map_err(|_| AutomationError::InvalidTimeFormat(s.to_string()))
-}
+    /// Evaluate device on/off state condition
+    fn evaluate_device_state(
+        &self,
+        device_ieee: &str,
+        endpoint: Option<u8>,
+        state_on: bool,
+    ) -> Result<bool, AutomationError> {
+        let Some(network) = &self.network else {
+            return Ok(false);
+        };
 
-/// Parse an IEEE address string (e.g., "00:11:22:33:44:55:66:77")
-fn parse_ieee_address(s: &str) -> Result<[u8; 8], AutomationError> {
-    let bytes: Vec<u8> = s
-        .split(':')
-        .map(|part| u8::from_str_radix(part, 16))
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|_| AutomationError::InvalidAction(format!("Invalid IEEE address: {s}")))?;
+        let ieee = parse_ieee_address(device_ieee)?;
+        Ok(device_in_state(
+            network.get_device(&ieee).as_ref(),
+            endpoint,
+            state_on,
+        ))
+    }
 
-    if bytes.len() != 8 {
-        return Err(AutomationError::InvalidAction(format!(
-            "IEEE address must have 8 bytes, got {}",
-            bytes.len()
-        )));
+    /// Run a condition script
+    #[cfg(feature = "scripting")]
+    fn evaluate_script(&self, source: &str, context: &Value) -> Result<bool, AutomationError> {
+        crate::script::evaluate(source, self.network.clone(), context)
     }
 
-    // Reverse to match internal representation
-    let mut arr = [0u8; 8];
-    for (i, &b) in bytes.iter().rev().enumerate() {
-        arr[i] = b;
+    #[cfg(not(feature = "scripting"))]
+    #[allow(clippy::unused_self)]
+    fn evaluate_script(&self, _source: &str, _context: &Value) -> Result<bool, AutomationError> {
+        Err(AutomationError::Script(
+            "built without scripting support".to_string(),
+        ))
     }
-    Ok(arr)
+
+    /// Evaluate sensor reading condition
+    fn evaluate_sensor_value(
+        &self,
+        device_ieee: &str,
+        attribute: SensorKind,
+        operator: Comparison,
+        value: f64,
+    ) -> Result<bool, AutomationError> {
+        let Some(network) = &self.network else {
+            return Ok(false);
+        };
+
+        let ieee = parse_ieee_address(device_ieee)?;
+        Ok(reading_past(
+            network.get_device(&ieee).as_ref(),
+            attribute,
+            operator,
+            value,
+        ))
+    }
+}
+
+/// Whether a device is known to be on (or off), with the endpoint if one is
+/// given (devices not yet interviewed have no endpoints listed)
+fn device_in_state(device: Option<&ZigbeeDevice>, endpoint: Option<u8>, state_on: bool) -> bool {
+    let Some(device) = device else {
+        return false;
+    };
+    let has_endpoint = endpoint.is_none_or(|ep| {
+        device.endpoints.is_empty() || device.endpoints.iter().any(|e| e.id == ep)
+    });
+    has_endpoint && device.state_on == Some(state_on)
+}
+
+/// Whether a device's latest reading of `attribute` is past `value`
+fn reading_past(
+    device: Option<&ZigbeeDevice>,
+    attribute: SensorKind,
+    operator: Comparison,
+    value: f64,
+) -> bool {
+    device
+        .and_then(|d| d.sensor_values.get(&attribute).copied())
+        .is_some_and(|reading| operator.past(reading, value, 0.0, false))
+}
+
+/// Parse a time string (HH:MM, or 12-hour for automations saved unnormalized)
+fn parse_time(s: &str) -> Result<NaiveTime, AutomationError> {
+    crate::clock::parse_time(s).ok_or_else(|| AutomationError::InvalidTimeFormat(s.to_string()))
 }
 
 #[cfg(test)]
@@ -138,13 +437,137 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_parse_ieee_address() {
-        let result = parse_ieee_address("00:11:22:33:44:55:66:77").unwrap();
-        assert_eq!(result, [0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00]);
+    fn test_device_in_state() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        assert!(!device_in_state(Some(&device), None, true));
+        assert!(!device_in_state(Some(&device), None, false));
+
+        device.state_on = Some(false);
+        assert!(device_in_state(Some(&device), None, false));
+        assert!(device_in_state(Some(&device), Some(1), false));
+        assert!(!device_in_state(Some(&device), None, true));
+        assert!(!device_in_state(None, None, false));
+
+        device.endpoints.push(zigbee_core::device::Endpoint {
+            id: 1,
+            profile_id: 0x0104,
+            device_id: 0x0100,
+            in_clusters: vec![0x0006],
+            out_clusters: Vec::new(),
+        });
+        assert!(device_in_state(Some(&device), Some(1), false));
+        assert!(!device_in_state(Some(&device), Some(2), false));
+    }
+
+    #[test]
+    fn test_reading_past() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        let temperature = |device: &ZigbeeDevice, operator| {
+            reading_past(Some(device), SensorKind::Temperature, operator, 25.0)
+        };
+        assert!(!temperature(&device, Comparison::Above));
+        assert!(!temperature(&device, Comparison::Below));
+
+        device.sensor_values.insert(SensorKind::Temperature, 26.5);
+        device.sensor_values.insert(SensorKind::Humidity, 10.0);
+        assert!(temperature(&device, Comparison::Above));
+        assert!(!temperature(&device, Comparison::Below));
+        assert!(reading_past(
+            Some(&device),
+            SensorKind::Humidity,
+            Comparison::Below,
+            25.0
+        ));
+        assert!(!reading_past(
+            None,
+            SensorKind::Temperature,
+            Comparison::Above,
+            25.0
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_time_conditions_use_zone() {
+        use chrono::TimeZone;
+        let presence = PresenceTracker::new(std::path::Path::new("/nonexistent")).await;
+        let stats = StatsStore::new(std::path::Path::new("/nonexistent")).await;
+        let evaluator = ConditionEvaluator::new(None, Arc::new(presence), Arc::new(stats))
+            .with_zone(Zone::parse("Europe/Madrid").unwrap());
+        let condition = Condition::TimeRange {
+            start: "17:00".to_string(),
+            end: "19:00".to_string(),
+        };
+        // 16:00 UTC is 18:00 in Madrid in summer
+        let at = Utc
+            .with_ymd_and_hms(2024, 7, 1, 16, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        assert!(evaluator.evaluate_at(&condition, at, &Value::Null).unwrap());
+        let at = at + chrono::Duration::hours(2);
+        assert!(!evaluator.evaluate_at(&condition, at, &Value::Null).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_last_triggered_uses_stats() {
+        let data_dir = std::env::temp_dir().join(format!(
+            "automation-evaluator-last-triggered-{}",
+            std::process::id()
+        ));
+        let presence = PresenceTracker::new(std::path::Path::new("/nonexistent")).await;
+        let stats = Arc::new(StatsStore::new(&data_dir).await);
+        let evaluator = ConditionEvaluator::new(None, Arc::new(presence), stats.clone());
+        let condition = |older_than_seconds| Condition::LastTriggered {
+            automation_id: "a".to_string(),
+            older_than_seconds,
+        };
+        let now = Local::now();
+        assert!(evaluator
+            .evaluate_at(&condition(3600), now, &Value::Null)
+            .unwrap());
+
+        // Only runs that got past their conditions count, however many
+        // others came after
+        let run = |conditions_met| crate::run_history::RunRecord {
+            id: crate::run_history::new_run_id(),
+            automation_id: "a".to_string(),
+            started_at: Utc::now(),
+            trigger_reason: "manual".to_string(),
+            conditions_met,
+            actions: Vec::new(),
+            duration_ms: 0,
+            error: None,
+            trace: None,
+        };
+        stats.record(&run(true));
+        for _ in 0..60 {
+            stats.record(&run(false));
+        }
+        assert!(!evaluator
+            .evaluate_at(
+                &condition(3600),
+                now + chrono::Duration::minutes(59),
+                &Value::Null
+            )
+            .unwrap());
+        assert!(evaluator
+            .evaluate_at(
+                &condition(3600),
+                now + chrono::Duration::minutes(61),
+                &Value::Null
+            )
+            .unwrap());
+        // Saved before the bound existed
+        assert!(!evaluator
+            .evaluate_at(&condition(u64::MAX), now, &Value::Null)
+            .unwrap());
+        let _ = std::fs::remove_dir_all(&data_dir);
     }
 
     #[test]
     fn test_day_of_week_empty() {
-        assert!(ConditionEvaluator::evaluate_day_of_week(&[]));
+        assert!(ConditionEvaluator::evaluate_day_of_week(
+            &[],
+            Local::now().naive_local()
+        ));
     }
 }
@@ -1,10 +1,30 @@
 //! Action executor for automations
 
 use crate::error::AutomationError;
-use crate::model::{Action, DeviceCommand, LogLevel};
+use crate::evaluator::ConditionEvaluator;
+use crate::ieee::parse_ieee_address;
+use crate::model::{
+    Action, DeviceCommand, LogLevel, OnTimeout, MAX_LOOP_ITERATIONS, MAX_LOOP_PASSES_PER_RUN,
+};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttClient;
+use crate::run_history::{ActionOutcome, ActionStatus};
+use crate::template;
+use crate::trace::ActionTracer;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+#[cfg(feature = "mqtt")]
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
-use zigbee_core::ZigbeeNetwork;
+use tokio_util::sync::CancellationToken;
+use zigbee_core::{network::NetworkEvent, LightColor, ZigbeeNetwork};
 
 /// Events emitted during action execution
 #[derive(Debug, Clone)]
@@ -27,18 +47,108 @@ pub enum ExecutorEvent {
     },
 }
 
+/// Failed runs in a row after which a device gets a health note
+const NOTE_AFTER_FAILURES: u32 = 3;
+
+/// An automation run in progress
+#[derive(Debug, Clone, Serialize)]
+pub struct RunningExecution {
+    pub run_id: u64,
+    pub automation_id: String,
+    pub started_at: DateTime<Utc>,
+    pub elapsed_secs: i64,
+    /// Index of the action being executed
+    pub action_index: usize,
+    pub action_count: usize,
+}
+
+/// Book-keeping of a run, removed when it ends
+struct Run {
+    automation_id: String,
+    started_at: DateTime<Utc>,
+    action_index: usize,
+    action_count: usize,
+    cancel: CancellationToken,
+}
+
+/// What the actions of one run share
+struct RunScope<'a> {
+    automation_id: &'a str,
+    context: &'a Value,
+    trace: &'a ActionTracer,
+    /// Loop passes the run has left, so nested loops can't multiply their
+    /// limits
+    passes_left: AtomicU32,
+}
+
+impl RunScope<'_> {
+    /// Take one loop pass from the run's budget
+    fn take_pass(&self) -> Result<(), AutomationError> {
+        self.passes_left
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .map(|_| ())
+            .map_err(|_| {
+                AutomationError::InvalidAction(format!(
+                    "more than {MAX_LOOP_PASSES_PER_RUN} loop passes in one run"
+                ))
+            })
+    }
+}
+
+/// Removes a run from the table however `execute_actions` returns
+struct RunGuard<'a> {
+    runs: &'a DashMap<u64, Run>,
+    run_id: u64,
+}
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        self.runs.remove(&self.run_id);
+    }
+}
+
 /// Executor for automation actions
 pub struct ActionExecutor {
     network: Option<Arc<ZigbeeNetwork>>,
+    /// Checks the conditions of `repeat_while` and `if` actions
+    evaluator: Arc<ConditionEvaluator>,
     event_tx: broadcast::Sender<ExecutorEvent>,
+    /// Failures in a row by automation and device
+    failures: DashMap<(String, [u8; 8]), u32>,
+    /// Runs in progress by run ID
+    runs: DashMap<u64, Run>,
+    next_run_id: AtomicU64,
+    /// Connection for `mqtt_publish` actions, set once at startup
+    #[cfg(feature = "mqtt")]
+    mqtt: OnceLock<Arc<MqttClient>>,
 }
 
 impl ActionExecutor {
     /// Create a new action executor
     #[must_use]
-    pub fn new(network: Option<Arc<ZigbeeNetwork>>) -> Self {
+    pub fn new(network: Option<Arc<ZigbeeNetwork>>, evaluator: Arc<ConditionEvaluator>) -> Self {
         let (event_tx, _) = broadcast::channel(64);
-        Self { network, event_tx }
+        Self {
+            network,
+            evaluator,
+            event_tx,
+            failures: DashMap::new(),
+            runs: DashMap::new(),
+            next_run_id: AtomicU64::new(1),
+            #[cfg(feature = "mqtt")]
+            mqtt: OnceLock::new(),
+        }
+    }
+
+    /// Publish `mqtt_publish` actions on a shared MQTT connection; only
+    /// the first connection set is used
+    #[cfg(feature = "mqtt")]
+    pub fn set_mqtt(&self, mqtt: Arc<MqttClient>) {
+        if self.mqtt.set(mqtt).is_err() {
+            tracing::warn!("MQTT connection already set for actions");
+        }
     }
 
     /// Subscribe to executor events
@@ -47,20 +157,115 @@ impl ActionExecutor {
         self.event_tx.subscribe()
     }
 
+    /// Runs in progress, oldest first
+    #[must_use]
+    pub fn running(&self) -> Vec<RunningExecution> {
+        let now = Utc::now();
+        let mut running: Vec<RunningExecution> = self
+            .runs
+            .iter()
+            .map(|run| RunningExecution {
+                run_id: *run.key(),
+                automation_id: run.automation_id.clone(),
+                started_at: run.started_at,
+                elapsed_secs: (now - run.started_at).num_seconds(),
+                action_index: run.action_index,
+                action_count: run.action_count,
+            })
+            .collect();
+        running.sort_by_key(|run| run.run_id);
+        running
+    }
+
+    /// Abort the runs of an automation at their current action, returning
+    /// how many were running
+    pub fn cancel(&self, automation_id: &str) -> usize {
+        let mut cancelled = 0;
+        for run in self.runs.iter() {
+            if run.automation_id == automation_id {
+                run.cancel.cancel();
+                cancelled += 1;
+            }
+        }
+        if cancelled > 0 {
+            tracing::info!(
+                "Cancelled {} run(s) of automation {}",
+                cancelled,
+                automation_id
+            );
+        }
+        cancelled
+    }
+
     /// Execute a list of actions for an automation
+    ///
+    /// The run is listed by [`Self::running`] until it ends and can be
+    /// aborted with [`Self::cancel`]. `context` fills the placeholders in
+    /// action text (see [`crate::template`]). How each action went is
+    /// appended to `outcomes`, and what each one (nested ones included) did
+    /// to `trace`.
     #[allow(clippy::missing_errors_doc)]
     pub async fn execute_actions(
         &self,
         automation_id: &str,
         actions: &[Action],
+        context: &Value,
+        outcomes: &mut Vec<ActionOutcome>,
+        trace: &ActionTracer,
     ) -> Result<(), AutomationError> {
+        let run_id = self.next_run_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = CancellationToken::new();
+        self.runs.insert(
+            run_id,
+            Run {
+                automation_id: automation_id.to_string(),
+                started_at: Utc::now(),
+                action_index: 0,
+                action_count: actions.len(),
+                cancel: cancel.clone(),
+            },
+        );
+        let _guard = RunGuard {
+            runs: &self.runs,
+            run_id,
+        };
+        let scope = RunScope {
+            automation_id,
+            context,
+            trace,
+            passes_left: AtomicU32::new(MAX_LOOP_PASSES_PER_RUN),
+        };
+
         for (index, action) in actions.iter().enumerate() {
+            if let Some(mut run) = self.runs.get_mut(&run_id) {
+                run.action_index = index;
+            }
             let _ = self.event_tx.send(ExecutorEvent::ActionStarted {
                 automation_id: automation_id.to_string(),
                 action_index: index,
             });
 
-            match self.execute_action(action).await {
+            let started = Instant::now();
+            let path = index.to_string();
+            let entry = trace.start(path.clone(), action);
+            let result = tokio::select! {
+                result = self.execute_action(action, &path, entry, &scope) => result,
+                () = cancel.cancelled() => {
+                    Err(AutomationError::Cancelled(automation_id.to_string()))
+                }
+            };
+            trace.finish(entry, &result);
+            outcomes.push(ActionOutcome {
+                index,
+                status: match &result {
+                    Ok(()) => ActionStatus::Succeeded,
+                    Err(AutomationError::Cancelled(_)) => ActionStatus::Cancelled,
+                    Err(_) => ActionStatus::Failed,
+                },
+                duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+            match result {
                 Ok(()) => {
                     let _ = self.event_tx.send(ExecutorEvent::ActionCompleted {
                         automation_id: automation_id.to_string(),
@@ -80,20 +285,70 @@ impl ActionExecutor {
         Ok(())
     }
 
-    /// Execute a single action
-    async fn execute_action(&self, action: &Action) -> Result<(), AutomationError> {
+    /// Count failures of an automation to control a device and attach a
+    /// health note to the device once they repeat (see
+    /// [`zigbee_core::health`])
+    fn track_device_health(
+        &self,
+        automation_id: &str,
+        device_ieee: &str,
+        error: Option<&AutomationError>,
+    ) {
+        let (Some(network), Ok(ieee)) = (&self.network, parse_ieee_address(device_ieee)) else {
+            return;
+        };
+        let key = (automation_id.to_string(), ieee);
+        let Some(error) = error else {
+            self.failures.remove(&key);
+            // Also clears notes left before a restart
+            network.clear_automation_failure(&ieee, automation_id);
+            return;
+        };
+
+        let failures = {
+            let mut failures = self.failures.entry(key).or_insert(0);
+            *failures += 1;
+            *failures
+        };
+        if failures >= NOTE_AFTER_FAILURES {
+            network.note_automation_failure(&ieee, automation_id, failures, &error.to_string());
+        }
+    }
+
+    /// Execute a single action at `path`, noting what it does in its trace
+    /// `entry`
+    async fn execute_action(
+        &self,
+        action: &Action,
+        path: &str,
+        entry: Option<usize>,
+        run: &RunScope<'_>,
+    ) -> Result<(), AutomationError> {
+        let (context, trace) = (run.context, run.trace);
         match action {
             Action::DeviceControl {
                 device_ieee,
                 endpoint,
                 command,
             } => {
-                self.execute_device_control(device_ieee, *endpoint, command)
+                let device_ieee = template::render(device_ieee, context);
+                trace.note(entry, command_detail(&device_ieee, *endpoint, command));
+                self.control_device(run, &device_ieee, *endpoint, command)
                     .await
             }
             Action::Delay { seconds } => {
                 tracing::debug!("Delaying for {} seconds", seconds);
-                tokio::time::sleep(std::time::Duration::from_secs(*seconds)).await;
+                tokio::time::sleep(Duration::from_secs(*seconds)).await;
+                Ok(())
+            }
+            Action::RandomDelay {
+                min_seconds,
+                max_seconds,
+            } => {
+                let delay = random_delay(*min_seconds, *max_seconds);
+                tracing::debug!("Delaying for {:?}", delay);
+                trace.note(entry, format!("wait {delay:?}"));
+                tokio::time::sleep(delay).await;
                 Ok(())
             }
             Action::TriggerAutomation { automation_id } => {
@@ -105,13 +360,121 @@ impl ActionExecutor {
                 );
                 Ok(())
             }
+            Action::MqttPublish {
+                topic,
+                payload,
+                retain,
+                qos,
+            } => {
+                let topic = template::render(topic, context);
+                let payload = template::render(payload, context);
+                trace.note(entry, format!("publish '{payload}' to {topic}"));
+                self.execute_mqtt_publish(&topic, payload, *qos, *retain)
+                    .await
+            }
+            Action::WaitForState {
+                device_ieee,
+                endpoint,
+                state_on,
+                timeout_seconds,
+                on_timeout,
+            } => {
+                self.wait_for_state(
+                    &template::render(device_ieee, context),
+                    *endpoint,
+                    *state_on,
+                    Duration::from_secs(*timeout_seconds),
+                    *on_timeout,
+                )
+                .await
+            }
+            Action::Repeat { count, actions } => {
+                let passes = (*count).min(MAX_LOOP_ITERATIONS);
+                trace.note(entry, format!("{passes} passes"));
+                let prefix = format!("{path}.actions");
+                for _ in 0..passes {
+                    run.take_pass()?;
+                    self.execute_block(actions, &prefix, run).await?;
+                }
+                Ok(())
+            }
+            Action::RepeatWhile {
+                condition,
+                actions,
+                max_iterations,
+            } => {
+                let max_iterations = (*max_iterations).min(MAX_LOOP_ITERATIONS);
+                let prefix = format!("{path}.actions");
+                for pass in 0..max_iterations {
+                    if !self.evaluator.evaluate(condition, context)? {
+                        trace.note(entry, format!("condition not met after {pass} passes"));
+                        return Ok(());
+                    }
+                    run.take_pass()?;
+                    self.execute_block(actions, &prefix, run).await?;
+                }
+                tracing::warn!(
+                    "repeat_while stopped after {} passes with its condition still true",
+                    max_iterations
+                );
+                trace.note(
+                    entry,
+                    format!("stopped after {max_iterations} passes with the condition still met"),
+                );
+                Ok(())
+            }
+            Action::Script { source } => self.execute_script(source, entry, run).await,
+            Action::If {
+                condition,
+                then_actions,
+                else_actions,
+            } => {
+                let (actions, branch) = if self.evaluator.evaluate(condition, context)? {
+                    (then_actions, "then_actions")
+                } else {
+                    (else_actions, "else_actions")
+                };
+                trace.note(entry, format!("run {branch}"));
+                self.execute_block(actions, &format!("{path}.{branch}"), run)
+                    .await
+            }
+            Action::PermitJoin { duration } => {
+                let network = self
+                    .network
+                    .as_ref()
+                    .ok_or_else(|| AutomationError::Network("No network available".to_string()))?;
+                network
+                    .permit_join(*duration)
+                    .await
+                    .map_err(|e| AutomationError::Network(e.to_string()))
+            }
             Action::Log { message, level } => {
-                Self::execute_log(message, level);
+                let message = template::render(message, context);
+                Self::execute_log(&message, level);
+                trace.note(entry, message);
                 Ok(())
             }
         }
     }
 
+    /// Send a device command of the run, keeping track of the device's
+    /// health (wherever the command is, loops and scripts included)
+    async fn control_device(
+        &self,
+        run: &RunScope<'_>,
+        device_ieee: &str,
+        endpoint: u8,
+        command: &DeviceCommand,
+    ) -> Result<(), AutomationError> {
+        // Not reached when the run is cancelled, which says nothing about
+        // the device
+        let result = self
+            .execute_device_control(device_ieee, endpoint, command)
+            .await;
+        self.track_device_health(run.automation_id, device_ieee, result.as_ref().err());
+        result
+    }
+
     /// Execute a device control action
     async fn execute_device_control(
         &self,
@@ -129,11 +492,181 @@ impl ActionExecutor {
             DeviceCommand::TurnOn => network.turn_on(&ieee, endpoint).await,
             DeviceCommand::TurnOff => network.turn_off(&ieee, endpoint).await,
             DeviceCommand::Toggle => network.toggle_device(&ieee, endpoint).await,
+            DeviceCommand::SetLevel {
+                level,
+                transition_time,
+            } => {
+                network
+                    .set_level(&ieee, endpoint, *level, *transition_time)
+                    .await
+            }
+            DeviceCommand::SetColorTemp { mireds, transition } => {
+                let color = LightColor::ColorTemp { mireds: *mireds };
+                network.set_color(&ieee, endpoint, color, *transition).await
+            }
+            DeviceCommand::SetColorXy { x, y, transition } => {
+                let color = LightColor::Xy { x: *x, y: *y };
+                network.set_color(&ieee, endpoint, color, *transition).await
+            }
         };
 
         result.map_err(|e| AutomationError::DeviceControlFailed(e.to_string()))
     }
 
+    /// Run the actions nested in a loop or branch (at `prefix` in the
+    /// trace), in order, stopping at the first failure
+    fn execute_block<'a>(
+        &'a self,
+        actions: &'a [Action],
+        prefix: &'a str,
+        run: &'a RunScope<'a>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AutomationError>> + Send + 'a>> {
+        Box::pin(async move {
+            for (i, action) in actions.iter().enumerate() {
+                let path = format!("{prefix}.{i}");
+                let entry = run.trace.start(path.clone(), action);
+                let result = self.execute_action(action, &path, entry, run).await;
+                run.trace.finish(entry, &result);
+                result?;
+            }
+            // Lets a cancel through even if none of the actions waits
+            tokio::task::yield_now().await;
+            Ok(())
+        })
+    }
+
+    /// Wait for a device to report being on or off
+    async fn wait_for_state(
+        &self,
+        device_ieee: &str,
+        endpoint: Option<u8>,
+        state_on: bool,
+        timeout: Duration,
+        on_timeout: OnTimeout,
+    ) -> Result<(), AutomationError> {
+        let network = self.network.as_ref().ok_or_else(|| {
+            AutomationError::DeviceControlFailed("No network available".to_string())
+        })?;
+        let ieee = parse_ieee_address(device_ieee)?;
+
+        // Subscribe before checking so a change in between isn't missed
+        let mut events = network.subscribe();
+        let in_state = || {
+            network
+                .get_device(&ieee)
+                .is_some_and(|device| device.state_on == Some(state_on))
+        };
+        if in_state() {
+            return Ok(());
+        }
+        let reached = async {
+            loop {
+                match events.recv().await {
+                    Ok(NetworkEvent::DeviceStateChanged {
+                        ieee_address,
+                        endpoint: changed_endpoint,
+                        state_on: on,
+                    }) if ieee_address == ieee
+                        && on == state_on
+                        && endpoint.is_none_or(|ep| ep == changed_endpoint) =>
+                    {
+                        return true;
+                    }
+                    Ok(_) => {}
+                    // The change may have been among the missed events
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        if in_state() {
+                            return true;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return false,
+                }
+            }
+        };
+        if tokio::time::timeout(timeout, reached).await == Ok(true) {
+            return Ok(());
+        }
+        match on_timeout {
+            OnTimeout::Continue => {
+                tracing::info!(
+                    "Device {} didn't turn {} within {:?}, continuing",
+                    device_ieee,
+                    if state_on { "on" } else { "off" },
+                    timeout
+                );
+                Ok(())
+            }
+            OnTimeout::Abort => Err(AutomationError::WaitTimedOut(device_ieee.to_string())),
+        }
+    }
+
+    /// Run an action script, then send the device commands it queued
+    #[cfg(feature = "scripting")]
+    async fn execute_script(
+        &self,
+        source: &str,
+        entry: Option<usize>,
+        run: &RunScope<'_>,
+    ) -> Result<(), AutomationError> {
+        let commands = crate::script::run(source, self.network.clone(), run.context)?;
+        for command in commands {
+            run.trace.note(
+                entry,
+                command_detail(&command.device_ieee, command.endpoint, &command.command),
+            );
+            self.control_device(
+                run,
+                &command.device_ieee,
+                command.endpoint,
+                &command.command,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    #[allow(clippy::unused_async)]
+    async fn execute_script(
+        &self,
+        _source: &str,
+        _entry: Option<usize>,
+        _run: &RunScope<'_>,
+    ) -> Result<(), AutomationError> {
+        Err(AutomationError::Script(
+            "built without scripting support".to_string(),
+        ))
+    }
+
+    #[cfg(feature = "mqtt")]
+    async fn execute_mqtt_publish(
+        &self,
+        topic: &str,
+        payload: String,
+        qos: u8,
+        retain: bool,
+    ) -> Result<(), AutomationError> {
+        let mqtt = self
+            .mqtt
+            .get()
+            .ok_or_else(|| AutomationError::Mqtt("no MQTT broker configured".to_string()))?;
+        mqtt.publish(topic, payload, qos, retain).await
+    }
+
+    #[cfg(not(feature = "mqtt"))]
+    #[allow(clippy::unused_async)]
+    async fn execute_mqtt_publish(
+        &self,
+        _topic: &str,
+        _payload: String,
+        _qos: u8,
+        _retain: bool,
+    ) -> Result<(), AutomationError> {
+        Err(AutomationError::Mqtt(
+            "built without MQTT support".to_string(),
+        ))
+    }
+
     fn execute_log(message: &str, level: &LogLevel) {
         match level {
             LogLevel::Debug => tracing::debug!(target: "automation", "{}", message),
@@ -144,25 +677,21 @@ impl ActionExecutor {
     }
 }
 
-/// Parse an IEEE address string (e.g., "00:11:22:33:44:55:66:77")
-fn parse_ieee_address(s: &str) -> Result<[u8; 8], AutomationError> {
-    let bytes: Vec<u8> = s
-        .split(':')
-        .map(|part| u8::from_str_radix(part, 16))
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|_| AutomationError::InvalidAction(format!("Invalid IEEE address: {s}")))?;
-
-    if bytes.len() != 8 {
-        return Err(AutomationError::InvalidAction(format!(
-            "IEEE address must have 8 bytes, got {}",
-            bytes.len()
-        )));
-    }
+/// A device command as noted in a trace
+fn command_detail(device_ieee: &str, endpoint: u8, command: &DeviceCommand) -> String {
+    format!(
+        "send {} to {} endpoint {}",
+        serde_json::to_string(command).unwrap_or_default(),
+        device_ieee,
+        endpoint
+    )
+}
 
-    // Reverse to match internal representation (little-endian)
-    let mut arr = [0u8; 8];
-    for (i, &b) in bytes.iter().rev().enumerate() {
-        arr[i] = b;
-    }
-    Ok(arr)
+/// A random time between `min_seconds` and `max_seconds` (to the
+/// millisecond), drawn from a v4 UUID's random bits
+fn random_delay(min_seconds: u64, max_seconds: u64) -> Duration {
+    let (min, max) = (min_seconds.min(max_seconds), min_seconds.max(max_seconds));
+    let span_ms = u128::from(max - min) * 1000;
+    let offset_ms = uuid::Uuid::new_v4().as_u128() % (span_ms + 1);
+    Duration::from_secs(min) + Duration::from_millis(u64::try_from(offset_ms).unwrap_or(0))
 }
@@ -0,0 +1,178 @@
+//! Automation folders
+//!
+//! Folders group automations for display. They are kept in
+//! `automation_folders.json`; automations refer to them by ID through their
+//! `folder` field, and both are ordered by `sort_order`, then name.
+
+use crate::error::AutomationError;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// A named group of automations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutomationFolder {
+    pub id: String,
+    pub name: String,
+    /// Position among folders (lowest first)
+    #[serde(default)]
+    pub sort_order: i32,
+}
+
+/// Request to create a folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateFolderRequest {
+    pub name: String,
+    #[serde(default)]
+    pub sort_order: i32,
+}
+
+/// Request to update a folder
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateFolderRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_order: Option<i32>,
+}
+
+/// Folder storage
+pub struct FolderStore {
+    folders: DashMap<String, AutomationFolder>,
+    data_path: PathBuf,
+}
+
+impl FolderStore {
+    /// Create a store and load `automation_folders.json`
+    pub async fn new(data_dir: &Path) -> Self {
+        let store = Self {
+            folders: DashMap::new(),
+            data_path: data_dir.join("automation_folders.json"),
+        };
+        store.load().await;
+        store
+    }
+
+    async fn load(&self) {
+        let folders = match fs::read_to_string(&self.data_path).await {
+            Ok(contents) => match serde_json::from_str::<Vec<AutomationFolder>>(&contents) {
+                Ok(folders) => folders,
+                Err(e) => {
+                    tracing::warn!("Failed to parse folders file {:?}: {}", self.data_path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!("Failed to read folders file {:?}: {}", self.data_path, e);
+                return;
+            }
+        };
+        tracing::info!("Loaded {} automation folders", folders.len());
+        for folder in folders {
+            self.folders.insert(folder.id.clone(), folder);
+        }
+    }
+
+    async fn save(&self) -> Result<(), AutomationError> {
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string_pretty(&self.list())?;
+        let tmp_path = self.data_path.with_extension("json.tmp");
+        fs::write(&tmp_path, &json).await?;
+        fs::rename(&tmp_path, &self.data_path).await?;
+        Ok(())
+    }
+
+    /// All folders in display order
+    #[must_use]
+    pub fn list(&self) -> Vec<AutomationFolder> {
+        let mut folders: Vec<_> = self.folders.iter().map(|r| r.value().clone()).collect();
+        folders.sort_by(|a, b| (a.sort_order, &a.name).cmp(&(b.sort_order, &b.name)));
+        folders
+    }
+
+    /// Get a folder by ID
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<AutomationFolder> {
+        self.folders.get(id).map(|r| r.value().clone())
+    }
+
+    /// Names must be non-empty and unique (ignoring case)
+    fn check_name(&self, name: &str, id: &str) -> Result<(), AutomationError> {
+        if name.trim().is_empty() {
+            return Err(AutomationError::InvalidFolder(
+                "name must not be empty".to_string(),
+            ));
+        }
+        if self
+            .folders
+            .iter()
+            .any(|f| f.id != id && f.name.eq_ignore_ascii_case(name.trim()))
+        {
+            return Err(AutomationError::InvalidFolder(format!(
+                "a folder named '{}' already exists",
+                name.trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Create a folder
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn create(
+        &self,
+        request: CreateFolderRequest,
+    ) -> Result<AutomationFolder, AutomationError> {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.check_name(&request.name, &id)?;
+        let folder = AutomationFolder {
+            id,
+            name: request.name.trim().to_string(),
+            sort_order: request.sort_order,
+        };
+        self.folders.insert(folder.id.clone(), folder.clone());
+        self.save().await?;
+
+        tracing::info!("Created automation folder: {} ({})", folder.name, folder.id);
+        Ok(folder)
+    }
+
+    /// Rename or reorder a folder
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn update(
+        &self,
+        id: &str,
+        request: UpdateFolderRequest,
+    ) -> Result<AutomationFolder, AutomationError> {
+        let mut folder = self
+            .get(id)
+            .ok_or_else(|| AutomationError::FolderNotFound(id.to_string()))?;
+        if let Some(name) = request.name {
+            self.check_name(&name, id)?;
+            folder.name = name.trim().to_string();
+        }
+        if let Some(sort_order) = request.sort_order {
+            folder.sort_order = sort_order;
+        }
+        self.folders.insert(id.to_string(), folder.clone());
+        self.save().await?;
+
+        tracing::info!("Updated automation folder: {}", id);
+        Ok(folder)
+    }
+
+    /// Remove a folder; see [`crate::AutomationEngine::delete_folder`]
+    pub(crate) async fn delete(&self, id: &str) -> Result<AutomationFolder, AutomationError> {
+        let (_, folder) = self
+            .folders
+            .remove(id)
+            .ok_or_else(|| AutomationError::FolderNotFound(id.to_string()))?;
+        self.save().await?;
+
+        tracing::info!("Deleted automation folder: {} ({})", folder.name, id);
+        Ok(folder)
+    }
+}
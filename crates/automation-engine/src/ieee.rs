@@ -0,0 +1,51 @@
+//! IEEE addresses as automations write them
+//!
+//! Automations name devices by their IEEE address in the usual colon
+//! separated, most significant byte first form; the network keeps them
+//! least significant byte first.
+
+use crate::error::AutomationError;
+
+/// Format an IEEE address (e.g., "00:11:22:33:44:55:66:77")
+pub(crate) fn format_ieee(ieee: [u8; 8]) -> String {
+    ieee.iter()
+        .rev()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Parse an IEEE address string (e.g., "00:11:22:33:44:55:66:77")
+pub(crate) fn parse_ieee_address(s: &str) -> Result<[u8; 8], AutomationError> {
+    let bytes: Vec<u8> = s
+        .split(':')
+        .map(|part| u8::from_str_radix(part, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AutomationError::InvalidAction(format!("Invalid IEEE address: {s}")))?;
+
+    if bytes.len() != 8 {
+        return Err(AutomationError::InvalidAction(format!(
+            "IEEE address must have 8 bytes, got {}",
+            bytes.len()
+        )));
+    }
+
+    // Reverse to match internal representation (little-endian)
+    let mut arr = [0u8; 8];
+    for (i, &b) in bytes.iter().rev().enumerate() {
+        arr[i] = b;
+    }
+    Ok(arr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ieee_address() {
+        let result = parse_ieee_address("00:11:22:33:44:55:66:77").unwrap();
+        assert_eq!(result, [0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00]);
+        assert_eq!(format_ieee(result), "00:11:22:33:44:55:66:77");
+    }
+}
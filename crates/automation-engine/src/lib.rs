@@ -3,14 +3,37 @@
 //! Provides rule-based automation with triggers, conditions, and actions
 //! for controlling smart home devices.
 
+pub mod backtest;
+pub mod climate;
+pub mod clock;
+pub mod dry_run;
 pub mod engine;
 pub mod error;
 pub mod evaluator;
 pub mod executor;
+pub mod folder;
+mod ieee;
 pub mod model;
-pub mod persistence;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod presence;
+pub mod run_history;
+pub mod schedule_log;
 pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod stats;
+pub mod template;
+pub mod trace;
 
+pub use backtest::{BacktestReport, HistoricalEvent};
+pub use climate::ClimateScheduler;
+pub use dry_run::DryRunReport;
 pub use engine::{AutomationEngine, AutomationEvent};
 pub use error::AutomationError;
+pub use folder::{AutomationFolder, FolderStore};
 pub use model::*;
+pub use presence::PresenceTracker;
+pub use run_history::RunRecord;
+pub use stats::AutomationStats;
+pub use trace::RunTrace;
@@ -1,6 +1,23 @@
 //! Data models for the automation engine
 
+use crate::clock;
+use crate::error::AutomationError;
+use crate::stats::AutomationStats;
+use chrono::{DateTime, Duration, Local, Utc};
 use serde::{Deserialize, Serialize};
+use zigbee_core::{ButtonAction, SensorKind};
+
+/// Most passes of a `repeat` or `repeat_while` action
+pub const MAX_LOOP_ITERATIONS: u32 = 1000;
+
+/// Most loop passes of one run, all loops together
+pub const MAX_LOOP_PASSES_PER_RUN: u32 = 10_000;
+
+/// Deepest nesting of `repeat`, `repeat_while` and `if` actions
+pub const MAX_ACTION_DEPTH: usize = 8;
+
+/// Longest span, in seconds, of a duration field (a year)
+pub const MAX_DURATION_SECONDS: u64 = 366 * 24 * 60 * 60;
 
 /// A complete automation rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +42,42 @@ pub struct Automation {
     pub created_at: String,
     /// Last modification timestamp
     pub updated_at: String,
+    /// Bumped on every change; clients send it back in `If-Match`
+    #[serde(default)]
+    pub revision: u64,
+    /// ID of the folder this automation is filed under
+    #[serde(default)]
+    pub folder: Option<String>,
+    /// Position within its folder (lowest first)
+    #[serde(default)]
+    pub sort_order: i32,
+    /// Free-form labels ("vacation", "lights") to filter by and act on
+    /// together
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Triggers are ignored until then; cleared once it has passed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    /// After a run, triggers are ignored for this many seconds (manual runs
+    /// still go ahead)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_seconds: Option<u64>,
+    /// Seconds left until triggers can run it again; filled in by the engine
+    /// when the automation is read, never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_remaining_seconds: Option<u64>,
+    /// Run counters; filled in by the engine when the automation is read,
+    /// never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<AutomationStats>,
+}
+
+impl zigbee_core::storage::Record for Automation {
+    const KIND: &'static str = "automations";
+
+    fn key(&self) -> String {
+        self.id.clone()
+    }
 }
 
 /// Trigger types that can initiate an automation
@@ -40,16 +93,134 @@ pub enum Trigger {
         endpoint: Option<u8>,
         /// State change to watch for
         state_change: StateChange,
+        /// Only fire once the state has held this many seconds, at most
+        /// [`MAX_DURATION_SECONDS`]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        for_duration: Option<u64>,
     },
     /// Time-based schedule trigger
     Schedule {
         /// Schedule specification
         schedule: ScheduleSpec,
+        /// Run a time of day or cron slot missed while the hub was down
+        /// once it's back, if it's at most this many seconds late (missed
+        /// slots are skipped if not set)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        catch_up_seconds: Option<u64>,
+        /// IANA time zone (`Europe/Madrid`) the schedule's times are in,
+        /// instead of the hub's (see [`clock::Zone`]); conditions still
+        /// use the hub's
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timezone: Option<String>,
+    },
+    /// Button press on a remote or switch
+    Button {
+        /// IEEE address of the device
+        device_ieee: String,
+        /// Optional endpoint (button number for Green Power switches)
+        #[serde(default)]
+        endpoint: Option<u8>,
+        /// Optional action filter (any action if not set)
+        #[serde(default)]
+        action: Option<ButtonAction>,
+    },
+    /// Occupancy (motion) sensor change
+    Occupancy {
+        /// IEEE address of the sensor
+        device_ieee: String,
+        /// Only fire when occupancy becomes this value (any change if not set)
+        #[serde(default)]
+        occupied: Option<bool>,
+        /// Only fire once occupancy has stayed this way this many seconds
+        /// ("no motion for 10 minutes"), at most [`MAX_DURATION_SECONDS`]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        for_duration: Option<u64>,
+    },
+    /// A sensor reading crossed a threshold (temperature rose above 25 °C,
+    /// humidity dropped below 40 %)
+    SensorValue {
+        /// IEEE address of the sensor
+        device_ieee: String,
+        /// Measured quantity, in its unit (see [`SensorKind::unit`])
+        attribute: SensorKind,
+        /// Which side of the threshold fires the trigger
+        operator: Comparison,
+        /// Threshold
+        value: f64,
+        /// How far the reading has to move back before the trigger can fire
+        /// again, so a value hovering around the threshold fires once
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// A person arrived home or left (phone-based presence)
+    Presence {
+        /// Person to watch (anyone if not set)
+        #[serde(default)]
+        person: Option<String>,
+        /// Arrival or departure
+        change: PresenceChange,
+    },
+    /// Fired once after the hub boots and the Zigbee network is up
+    HubStartup {
+        /// Extra delay after the network comes up, in seconds
+        #[serde(default)]
+        delay_seconds: u64,
+    },
+    /// A request to `POST /api/v1/webhooks/:id` from another system
+    /// (doorbell, CI, IFTTT); its JSON body is passed to the actions as
+    /// `trigger.body`
+    Webhook {
+        /// Webhook ID, the last segment of the URL
+        id: String,
+        /// Secret the caller sends in `X-Webhook-Secret` or `?secret=`;
+        /// without one only authenticated callers can fire the webhook
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret: Option<String>,
+    },
+    /// A message from the MQTT broker (Frigate detections, Tasmota
+    /// devices); needs the server's MQTT connection
+    Mqtt {
+        /// Topic filter, `+` and `#` wildcards allowed
+        topic: String,
+        /// Only fire when the payload contains this text
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload_match: Option<String>,
     },
     /// Manual trigger (API call only)
     Manual,
 }
 
+/// Comparison of a sensor reading with a threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    /// Whether `reading` is past `threshold`
+    ///
+    /// A reading that `was_past` only counts as back once it has moved
+    /// `hysteresis` beyond the threshold.
+    #[must_use]
+    pub fn past(self, reading: f64, threshold: f64, hysteresis: f64, was_past: bool) -> bool {
+        let margin = if was_past { hysteresis } else { 0.0 };
+        match self {
+            Comparison::Above => reading > threshold - margin,
+            Comparison::Below => reading < threshold + margin,
+        }
+    }
+}
+
+/// Presence changes to watch for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceChange {
+    Arrived,
+    Left,
+}
+
 /// State changes to monitor for device triggers
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -78,11 +249,11 @@ pub enum StateChange {
 pub enum ScheduleSpec {
     /// Run at specific time(s) of day
     TimeOfDay {
-        /// Time in HH:MM format (24-hour)
+        /// Time in HH:MM format (24-hour); `7:30 PM` is accepted and normalized
         time: String,
-        /// Days of week (0=Sunday, 1=Monday, ..., 6=Saturday)
+        /// Days of week (0=Sunday, 1=Monday, ..., 6=Saturday), or day names
         /// Empty means every day
-        #[serde(default)]
+        #[serde(default, deserialize_with = "crate::clock::deserialize_days")]
         days: Vec<u8>,
     },
     /// Run at fixed interval
@@ -103,14 +274,15 @@ pub enum ScheduleSpec {
 pub enum Condition {
     /// Time range condition (actions only run within this time window)
     TimeRange {
-        /// Start time in HH:MM format
+        /// Start time in HH:MM format (12-hour times are normalized)
         start: String,
         /// End time in HH:MM format (can wrap past midnight)
         end: String,
     },
     /// Day of week condition
     DayOfWeek {
-        /// Days when condition is true (0=Sunday)
+        /// Days when condition is true (0=Sunday), or day names
+        #[serde(deserialize_with = "crate::clock::deserialize_days")]
         days: Vec<u8>,
     },
     /// Device availability condition
@@ -120,6 +292,50 @@ pub enum Condition {
         /// Whether device should be available (true) or unavailable (false)
         available: bool,
     },
+    /// Device on/off state condition ("only if the living room light is
+    /// off")
+    DeviceState {
+        /// IEEE address of the device
+        device_ieee: String,
+        /// Optional endpoint; on/off state is kept per device, so this only
+        /// requires the device to have it
+        #[serde(default)]
+        endpoint: Option<u8>,
+        /// Whether the device should be on (true) or off (false); a device
+        /// whose state isn't known yet matches neither
+        state_on: bool,
+    },
+    /// The device's latest sensor reading is above or below a value
+    SensorValue {
+        /// IEEE address of the sensor
+        device_ieee: String,
+        /// Measured quantity, in its unit (see [`SensorKind::unit`])
+        attribute: SensorKind,
+        /// Which side of `value` the reading has to be on
+        operator: Comparison,
+        /// Threshold; a device without a reading matches neither side
+        value: f64,
+    },
+    /// An automation (this one or another) last ran its actions long
+    /// enough ago, or never did ("notify at most once per hour")
+    LastTriggered {
+        /// ID of the automation, from its execution history
+        automation_id: String,
+        /// Seconds that must have passed since that run started (up to
+        /// [`MAX_DURATION_SECONDS`])
+        older_than_seconds: u64,
+    },
+    /// Presence condition
+    Presence {
+        /// Person to check; if not set, checks whether anyone is home
+        #[serde(default)]
+        person: Option<String>,
+        /// Whether the person (or anyone) should be home
+        home: bool,
+    },
+    /// A Rhai script that evaluates to true or false, for logic the other
+    /// conditions can't express (see [`crate::script`])
+    Script { source: String },
     /// Logical AND of multiple conditions
     And { conditions: Vec<Condition> },
     /// Logical OR of multiple conditions
@@ -146,14 +362,82 @@ pub enum Action {
         /// Delay in seconds
         seconds: u64,
     },
+    /// Delay for a random time in a range, so presence-simulation lights
+    /// don't switch at the same second every day
+    RandomDelay { min_seconds: u64, max_seconds: u64 },
     /// Trigger another automation (for chaining)
     TriggerAutomation {
         /// ID of automation to trigger
         automation_id: String,
     },
+    /// Publish a message to the MQTT broker, to signal other systems;
+    /// needs the server's MQTT connection
+    MqttPublish {
+        /// Topic; placeholders like `{{trigger.topic}}` are filled in
+        topic: String,
+        /// Payload, with placeholders filled in like the topic
+        #[serde(default)]
+        payload: String,
+        /// Whether the broker keeps it for later subscribers
+        #[serde(default)]
+        retain: bool,
+        /// Quality of service (0, 1 or 2)
+        #[serde(default)]
+        qos: u8,
+    },
+    /// Wait until a device is switched on or off ("turn on the heater,
+    /// wait until the plug confirms, then notify"); done at once if it
+    /// already is
+    WaitForState {
+        /// IEEE address of the device
+        device_ieee: String,
+        /// Optional endpoint filter
+        #[serde(default)]
+        endpoint: Option<u8>,
+        /// State to wait for
+        state_on: bool,
+        /// Give up after this many seconds
+        timeout_seconds: u64,
+        /// What to do when it times out
+        #[serde(default)]
+        on_timeout: OnTimeout,
+    },
+    /// Run actions a number of times, e.g. blink a light 3 times
+    Repeat {
+        /// Times to run them (up to [`MAX_LOOP_ITERATIONS`])
+        count: u32,
+        actions: Vec<Action>,
+    },
+    /// Run actions again as long as a condition holds, checked before
+    /// each pass
+    RepeatWhile {
+        condition: Condition,
+        actions: Vec<Action>,
+        /// Stop after this many passes even if the condition still holds
+        /// (up to [`MAX_LOOP_ITERATIONS`])
+        #[serde(default = "default_max_iterations")]
+        max_iterations: u32,
+    },
+    /// Take one of two paths depending on a condition checked when the
+    /// action is reached, e.g. scene A during the day and B at night
+    If {
+        condition: Condition,
+        then_actions: Vec<Action>,
+        #[serde(default)]
+        else_actions: Vec<Action>,
+    },
+    /// Run a Rhai script that may switch devices (see [`crate::script`])
+    Script { source: String },
+    /// Open the network for pairing, e.g. on a button's triple press
+    PermitJoin {
+        /// Seconds to stay open (0 closes it again)
+        #[serde(default = "default_permit_join_duration")]
+        duration: u8,
+    },
     /// Log a message (for debugging)
     Log {
-        /// Message to log
+        /// Message to log; `{{trigger.body.field}}` is replaced with values
+        /// from the trigger (see [`crate::template`])
         message: String,
         /// Log level
         #[serde(default)]
@@ -171,6 +455,42 @@ pub enum DeviceCommand {
     TurnOff,
     /// Toggle device state
     Toggle,
+    /// Dim a light to a level (1-254), switching it on; 0 switches it off
+    SetLevel {
+        level: u8,
+        /// Fade time in tenths of a second
+        #[serde(default)]
+        transition_time: u16,
+    },
+    /// Set a light's colour temperature
+    SetColorTemp {
+        /// Mireds (1,000,000 / kelvin: 153 is cool white, 370 warm white)
+        mireds: u16,
+        /// Fade time in tenths of a second
+        #[serde(default)]
+        transition: u16,
+    },
+    /// Set a colour light to a CIE 1931 colour
+    SetColorXy {
+        /// x and y in ZCL units (0-65279, i.e. times 65536), as in a
+        /// device's `state.color`
+        x: u16,
+        y: u16,
+        /// Fade time in tenths of a second
+        #[serde(default)]
+        transition: u16,
+    },
+}
+
+/// What a wait does when it times out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnTimeout {
+    /// Go on with the next action
+    #[default]
+    Continue,
+    /// Fail the run
+    Abort,
 }
 
 /// Log levels for log actions
@@ -185,7 +505,7 @@ pub enum LogLevel {
 }
 
 /// Request to create a new automation
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateAutomationRequest {
     pub name: String,
     #[serde(default)]
@@ -196,27 +516,199 @@ pub struct CreateAutomationRequest {
     #[serde(default)]
     pub conditions: Vec<Condition>,
     pub actions: Vec<Action>,
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub sort_order: i32,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub cooldown_seconds: Option<u64>,
+}
+
+impl Default for CreateAutomationRequest {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            description: None,
+            enabled: default_enabled(),
+            trigger: Trigger::Manual,
+            conditions: Vec::new(),
+            actions: Vec::new(),
+            folder: None,
+            sort_order: 0,
+            tags: Vec::new(),
+            cooldown_seconds: None,
+        }
+    }
+}
+
+/// Request for an enabled automation without conditions
+#[cfg(test)]
+pub(crate) fn request(
+    name: &str,
+    trigger: Trigger,
+    actions: Vec<Action>,
+) -> CreateAutomationRequest {
+    CreateAutomationRequest {
+        name: name.to_string(),
+        trigger,
+        actions,
+        ..CreateAutomationRequest::default()
+    }
 }
 
 fn default_enabled() -> bool {
     true
 }
 
+fn default_max_iterations() -> u32 {
+    100
+}
+
+fn default_permit_join_duration() -> u8 {
+    60
+}
+
+/// Request to snooze an automation; give exactly one of `until` and
+/// `minutes`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnoozeRequest {
+    /// RFC 3339 timestamp, or a time of day (`07:00`, `7am`) meaning its
+    /// next occurrence
+    #[serde(default)]
+    pub until: Option<String>,
+    /// Snooze for this long from now
+    #[serde(default)]
+    pub minutes: Option<u32>,
+}
+
+impl SnoozeRequest {
+    /// When the snooze ends, relative to `now`, with times of day in `zone`
+    #[allow(clippy::missing_errors_doc)]
+    pub fn resolve(
+        &self,
+        now: DateTime<Local>,
+        zone: clock::Zone,
+    ) -> Result<DateTime<Utc>, AutomationError> {
+        let invalid = |field: &str, message: &str| AutomationError::InvalidField {
+            field: field.to_string(),
+            message: message.to_string(),
+        };
+        let until = match (&self.until, self.minutes) {
+            (Some(until), None) => {
+                if let Ok(at) = DateTime::parse_from_rfc3339(until.trim()) {
+                    at.with_timezone(&Utc)
+                } else {
+                    let time = clock::parse_time(until).ok_or_else(|| {
+                        invalid(
+                            "until",
+                            &format!(
+                                "'{until}' is not a timestamp or time of day, {}",
+                                clock::TIME_FORMAT_HINT
+                            ),
+                        )
+                    })?;
+                    zone.next_time_of_day(time, &[], now.with_timezone(&Utc))
+                        .ok_or_else(|| invalid("until", "that time doesn't exist"))?
+                }
+            }
+            (None, Some(minutes)) => now.with_timezone(&Utc) + Duration::minutes(minutes.into()),
+            _ => return Err(invalid("snooze", "give either `until` or `minutes`")),
+        };
+        if until <= now {
+            return Err(invalid("until", "must be in the future"));
+        }
+        Ok(until)
+    }
+}
+
+/// What an import does with an automation whose ID is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportConflict {
+    /// Keep the existing automation
+    #[default]
+    Skip,
+    /// Overwrite the existing automation
+    Replace,
+    /// Keep both, adding the imported one under a new ID
+    Duplicate,
+}
+
+/// IDs of the automations an import added, replaced or skipped
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub created: Vec<String>,
+    pub replaced: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// What a bulk request does to the automations it selects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    Enable,
+    Disable,
+    /// Run their actions now, like a manual trigger
+    Trigger,
+}
+
+/// Act on several automations at once, e.g. disable all tagged "vacation"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkRequest {
+    pub action: BulkAction,
+    /// Automations to act on
+    #[serde(default)]
+    pub ids: Vec<String>,
+    /// Also act on every automation with this tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// IDs of the automations a bulk request acted on
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BulkReport {
+    pub automations: Vec<String>,
+}
+
 /// Request to update an automation
-#[derive(Debug, Clone, Default, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UpdateAutomationRequest {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
-    #[serde(default)]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_some"
+    )]
     pub description: Option<Option<String>>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub enabled: Option<bool>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub trigger: Option<Trigger>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub conditions: Option<Vec<Condition>>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub actions: Option<Vec<Action>>,
+    /// `null` removes the automation from its folder
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_some"
+    )]
+    pub folder: Option<Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_order: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// `null` (or `0`) removes the cooldown
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_some"
+    )]
+    pub cooldown_seconds: Option<Option<u64>>,
 }
 
 impl Automation {
@@ -234,9 +726,36 @@ impl Automation {
             actions: request.actions,
             created_at: now.clone(),
             updated_at: now,
+            revision: 1,
+            folder: request.folder,
+            sort_order: request.sort_order,
+            tags: request.tags,
+            snoozed_until: None,
+            cooldown_seconds: request.cooldown_seconds.filter(|&secs| secs > 0),
+            cooldown_remaining_seconds: None,
+            stats: None,
+        }
+    }
+
+    /// Whether triggers are ignored at `now`
+    #[must_use]
+    pub fn is_snoozed(&self, now: DateTime<Utc>) -> bool {
+        self.snoozed_until.is_some_and(|until| until > now)
+    }
+
+    /// Drop a snooze that has run out
+    pub fn expire_snooze(&mut self, now: DateTime<Utc>) {
+        if !self.is_snoozed(now) {
+            self.snoozed_until = None;
         }
     }
 
+    /// Whether a trigger should run the automation now
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.is_snoozed(Utc::now())
+    }
+
     /// Apply an update request to this automation
     pub fn apply_update(&mut self, update: UpdateAutomationRequest) {
         if let Some(name) = update.name {
@@ -257,6 +776,351 @@ impl Automation {
         if let Some(actions) = update.actions {
             self.actions = actions;
         }
+        if let Some(folder) = update.folder {
+            self.folder = folder;
+        }
+        if let Some(sort_order) = update.sort_order {
+            self.sort_order = sort_order;
+        }
+        if let Some(tags) = update.tags {
+            self.tags = tags;
+        }
+        if let Some(cooldown_seconds) = update.cooldown_seconds {
+            self.cooldown_seconds = cooldown_seconds.filter(|&secs| secs > 0);
+        }
         self.updated_at = chrono::Utc::now().to_rfc3339();
+        self.revision += 1;
+    }
+
+    /// Whether the automation has a tag
+    #[must_use]
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Normalize 12-hour times and day names in the trigger and conditions
+    #[allow(clippy::missing_errors_doc)]
+    pub fn normalize(&mut self) -> Result<(), AutomationError> {
+        normalize_tags(&mut self.tags);
+        if let Some(seconds) = self.cooldown_seconds {
+            check_duration(seconds, "cooldown_seconds")?;
+        }
+        self.trigger.normalize()?;
+        normalize_conditions(&mut self.conditions, "conditions")?;
+        normalize_actions(&mut self.actions, "actions")
+    }
+}
+
+impl UpdateAutomationRequest {
+    /// Normalize 12-hour times and day names in the trigger and conditions
+    #[allow(clippy::missing_errors_doc)]
+    pub fn normalize(&mut self) -> Result<(), AutomationError> {
+        if let Some(tags) = &mut self.tags {
+            normalize_tags(tags);
+        }
+        if let Some(Some(seconds)) = self.cooldown_seconds {
+            check_duration(seconds, "cooldown_seconds")?;
+        }
+        if let Some(trigger) = &mut self.trigger {
+            trigger.normalize()?;
+        }
+        if let Some(conditions) = &mut self.conditions {
+            normalize_conditions(conditions, "conditions")?;
+        }
+        if let Some(actions) = &mut self.actions {
+            normalize_actions(actions, "actions")?;
+        }
+        Ok(())
+    }
+}
+
+impl Trigger {
+    /// How long the triggering state has to hold before the automation runs
+    #[must_use]
+    pub fn for_duration(&self) -> Option<std::time::Duration> {
+        match self {
+            Trigger::DeviceState { for_duration, .. } | Trigger::Occupancy { for_duration, .. } => {
+                for_duration
+                    .filter(|&secs| secs > 0)
+                    .map(std::time::Duration::from_secs)
+            }
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) -> Result<(), AutomationError> {
+        if let Trigger::Schedule {
+            timezone: Some(name),
+            ..
+        } = self
+        {
+            let Ok(clock::Zone::Named(tz)) = clock::Zone::parse(name) else {
+                return Err(AutomationError::InvalidField {
+                    field: "trigger.timezone".to_string(),
+                    message: format!(
+                        "unknown time zone '{name}', expected a name like Europe/Madrid"
+                    ),
+                });
+            };
+            *name = tz.name().to_string();
+        }
+        match self {
+            Trigger::Schedule {
+                schedule: ScheduleSpec::TimeOfDay { time, days },
+                ..
+            } => {
+                normalize_time(time, "trigger.schedule.time")?;
+                check_days(days, "trigger.schedule.days")?;
+            }
+            Trigger::Schedule {
+                schedule: ScheduleSpec::Interval { .. },
+                catch_up_seconds: Some(_),
+                ..
+            } => {
+                return Err(AutomationError::InvalidField {
+                    field: "trigger.catch_up_seconds".to_string(),
+                    message: "only time of day and cron schedules can catch up".to_string(),
+                });
+            }
+            Trigger::DeviceState {
+                for_duration: Some(seconds),
+                ..
+            }
+            | Trigger::Occupancy {
+                for_duration: Some(seconds),
+                ..
+            } => check_duration(*seconds, "trigger.for_duration")?,
+            Trigger::SensorValue {
+                value, hysteresis, ..
+            } => {
+                if !value.is_finite() {
+                    return Err(AutomationError::InvalidField {
+                        field: "trigger.value".to_string(),
+                        message: "must be a number".to_string(),
+                    });
+                }
+                if !hysteresis.is_finite() || *hysteresis < 0.0 {
+                    return Err(AutomationError::InvalidField {
+                        field: "trigger.hysteresis".to_string(),
+                        message: "must be 0 or more".to_string(),
+                    });
+                }
+            }
+            Trigger::Webhook { id, secret } => {
+                if id.is_empty()
+                    || !id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_".contains(c))
+                {
+                    return Err(AutomationError::InvalidField {
+                        field: "trigger.id".to_string(),
+                        message: "must be letters, digits, '-' or '_'".to_string(),
+                    });
+                }
+                if secret.as_ref().is_some_and(String::is_empty) {
+                    *secret = None;
+                }
+            }
+            Trigger::Mqtt { topic, .. } => {
+                let levels: Vec<&str> = topic.split('/').collect();
+                let valid = !topic.is_empty()
+                    && levels.iter().enumerate().all(|(i, level)| {
+                        (!level.contains('+') || *level == "+")
+                            && (!level.contains('#') || (*level == "#" && i == levels.len() - 1))
+                    });
+                if !valid {
+                    return Err(AutomationError::InvalidField {
+                        field: "trigger.topic".to_string(),
+                        message: "must be an MQTT topic filter ('+' and '#' only as whole levels, '#' last)"
+                            .to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Condition {
+    fn normalize(&mut self, field: &str) -> Result<(), AutomationError> {
+        match self {
+            Condition::TimeRange { start, end } => {
+                normalize_time(start, &format!("{field}.start"))?;
+                normalize_time(end, &format!("{field}.end"))
+            }
+            Condition::DayOfWeek { days } => check_days(days, &format!("{field}.days")),
+            Condition::And { conditions } | Condition::Or { conditions } => {
+                normalize_conditions(conditions, &format!("{field}.conditions"))
+            }
+            Condition::Not { condition } => condition.normalize(&format!("{field}.condition")),
+            Condition::SensorValue { value, .. } => {
+                if value.is_finite() {
+                    Ok(())
+                } else {
+                    Err(AutomationError::InvalidField {
+                        field: format!("{field}.value"),
+                        message: "must be a number".to_string(),
+                    })
+                }
+            }
+            Condition::Script { source } => check_script(source, &format!("{field}.source")),
+            Condition::LastTriggered {
+                older_than_seconds, ..
+            } => check_duration(*older_than_seconds, &format!("{field}.older_than_seconds")),
+            Condition::DeviceAvailable { .. }
+            | Condition::DeviceState { .. }
+            | Condition::Presence { .. } => Ok(()),
+        }
+    }
+}
+
+fn normalize_conditions(conditions: &mut [Condition], field: &str) -> Result<(), AutomationError> {
+    for (i, condition) in conditions.iter_mut().enumerate() {
+        condition.normalize(&format!("{field}[{i}]"))?;
+    }
+    Ok(())
+}
+
+/// Trim tags and drop empty and repeated ones
+fn normalize_tags(tags: &mut Vec<String>) {
+    let mut seen = std::collections::HashSet::new();
+    tags.retain_mut(|tag| {
+        *tag = tag.trim().to_string();
+        !tag.is_empty() && seen.insert(tag.clone())
+    });
+}
+
+/// Check loop limits, nesting depth and delay ranges, and normalize the
+/// conditions of nested actions
+fn normalize_actions(actions: &mut [Action], field: &str) -> Result<(), AutomationError> {
+    normalize_nested_actions(actions, field, 0)
+}
+
+fn normalize_nested_actions(
+    actions: &mut [Action],
+    field: &str,
+    depth: usize,
+) -> Result<(), AutomationError> {
+    if depth > MAX_ACTION_DEPTH {
+        return Err(AutomationError::InvalidField {
+            field: field.to_string(),
+            message: format!("actions can be nested at most {MAX_ACTION_DEPTH} deep"),
+        });
+    }
+    for (i, action) in actions.iter_mut().enumerate() {
+        let field = format!("{field}[{i}]");
+        match action {
+            Action::Repeat { count, actions } => {
+                check_iterations(*count, &format!("{field}.count"))?;
+                normalize_nested_actions(actions, &format!("{field}.actions"), depth + 1)?;
+            }
+            Action::RepeatWhile {
+                condition,
+                actions,
+                max_iterations,
+            } => {
+                check_iterations(*max_iterations, &format!("{field}.max_iterations"))?;
+                condition.normalize(&format!("{field}.condition"))?;
+                normalize_nested_actions(actions, &format!("{field}.actions"), depth + 1)?;
+            }
+            Action::RandomDelay {
+                min_seconds,
+                max_seconds,
+            } if *max_seconds < *min_seconds => {
+                return Err(AutomationError::InvalidField {
+                    field: format!("{field}.max_seconds"),
+                    message: "must be at least min_seconds".to_string(),
+                });
+            }
+            Action::MqttPublish { qos, .. } if *qos > 2 => {
+                return Err(AutomationError::InvalidField {
+                    field: format!("{field}.qos"),
+                    message: "must be 0, 1 or 2".to_string(),
+                });
+            }
+            Action::Script { source } => check_script(source, &format!("{field}.source"))?,
+            Action::If {
+                condition,
+                then_actions,
+                else_actions,
+            } => {
+                condition.normalize(&format!("{field}.condition"))?;
+                normalize_nested_actions(
+                    then_actions,
+                    &format!("{field}.then_actions"),
+                    depth + 1,
+                )?;
+                normalize_nested_actions(
+                    else_actions,
+                    &format!("{field}.else_actions"),
+                    depth + 1,
+                )?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reject scripts that don't parse (checked when they run in builds
+/// without scripting)
+#[cfg_attr(not(feature = "scripting"), allow(clippy::unnecessary_wraps))]
+fn check_script(source: &str, field: &str) -> Result<(), AutomationError> {
+    #[cfg(feature = "scripting")]
+    crate::script::check(source).map_err(|message| AutomationError::InvalidField {
+        field: field.to_string(),
+        message,
+    })?;
+    #[cfg(not(feature = "scripting"))]
+    let _ = (source, field);
+    Ok(())
+}
+
+fn check_iterations(iterations: u32, field: &str) -> Result<(), AutomationError> {
+    if iterations > MAX_LOOP_ITERATIONS {
+        return Err(AutomationError::InvalidField {
+            field: field.to_string(),
+            message: format!("must be at most {MAX_LOOP_ITERATIONS}"),
+        });
+    }
+    Ok(())
+}
+
+/// Deserialize a present field, even `null`, as `Some`, so that updates can
+/// tell a `null` apart from a missing field
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+fn check_duration(seconds: u64, field: &str) -> Result<(), AutomationError> {
+    if seconds > MAX_DURATION_SECONDS {
+        return Err(AutomationError::InvalidField {
+            field: field.to_string(),
+            message: format!("must be at most {MAX_DURATION_SECONDS} seconds (a year)"),
+        });
+    }
+    Ok(())
+}
+
+fn normalize_time(time: &mut String, field: &str) -> Result<(), AutomationError> {
+    *time = clock::normalize_time(time).ok_or_else(|| AutomationError::InvalidField {
+        field: field.to_string(),
+        message: format!("'{time}' is not a valid time, {}", clock::TIME_FORMAT_HINT),
+    })?;
+    Ok(())
+}
+
+fn check_days(days: &[u8], field: &str) -> Result<(), AutomationError> {
+    match days.iter().position(|&d| d > 6) {
+        Some(i) => Err(AutomationError::InvalidField {
+            field: format!("{field}[{i}]"),
+            message: format!("day {} is out of range (0=Sunday to 6=Saturday)", days[i]),
+        }),
+        None => Ok(()),
     }
 }
@@ -0,0 +1,193 @@
+//! MQTT connection shared by automations
+//!
+//! One client connects to the broker from the server config and reconnects
+//! whenever the connection drops. It subscribes to the topics of the
+//! automations with an MQTT trigger, and drops those no automation uses any
+//! more; every message on a subscribed topic is broadcast as an
+//! [`MqttMessage`]. Whether it is connected can be watched with
+//! [`MqttClient::connection`].
+
+use crate::error::AutomationError;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+
+/// Wait before reconnecting after the connection failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Broker to connect to
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// A message received on a subscribed topic
+#[derive(Debug, Clone)]
+pub struct MqttMessage {
+    pub topic: String,
+    /// Payload as text (invalid UTF-8 replaced)
+    pub payload: String,
+}
+
+/// Client of the shared MQTT connection
+pub struct MqttClient {
+    client: AsyncClient,
+    /// Topic filters to subscribe to again after reconnecting
+    topics: Arc<Mutex<HashSet<String>>>,
+    message_tx: broadcast::Sender<MqttMessage>,
+    /// Whether the broker connection is up
+    connected: watch::Sender<bool>,
+}
+
+impl MqttClient {
+    /// Connect to the broker in the background (must be called within a
+    /// Tokio runtime)
+    #[must_use]
+    pub fn connect(config: &MqttConfig) -> Arc<Self> {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+        let (client, mut event_loop) = AsyncClient::new(options, 64);
+        let (message_tx, _) = broadcast::channel(256);
+        let (connected, _) = watch::channel(false);
+        let mqtt = Arc::new(Self {
+            client: client.clone(),
+            topics: Arc::new(Mutex::new(HashSet::new())),
+            message_tx: message_tx.clone(),
+            connected: connected.clone(),
+        });
+
+        let topics = Arc::clone(&mqtt.topics);
+        let (host, port) = (config.host.clone(), config.port);
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        tracing::info!("Connected to MQTT broker {}:{}", host, port);
+                        connected.send_replace(true);
+                        let topics: Vec<String> = lock(&topics).iter().cloned().collect();
+                        for topic in topics {
+                            if let Err(e) = client.subscribe(&topic, QoS::AtMostOnce).await {
+                                tracing::warn!("Failed to subscribe to {}: {}", topic, e);
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let _ = message_tx.send(MqttMessage {
+                            topic: publish.topic,
+                            payload: String::from_utf8_lossy(&publish.payload).into_owned(),
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("MQTT connection to {}:{} failed: {}", host, port, e);
+                        connected.send_if_modified(|up| std::mem::replace(up, false));
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+        mqtt
+    }
+
+    /// Subscribe to messages
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<MqttMessage> {
+        self.message_tx.subscribe()
+    }
+
+    /// Watch whether the broker connection is up; it goes from `true` to
+    /// `false` each time an established connection drops
+    #[must_use]
+    pub fn connection(&self) -> watch::Receiver<bool> {
+        self.connected.subscribe()
+    }
+
+    /// Publish a message (QoS 0-2), queued while the connection is down
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn publish(
+        &self,
+        topic: &str,
+        payload: String,
+        qos: u8,
+        retain: bool,
+    ) -> Result<(), AutomationError> {
+        let qos = rumqttc::qos(qos).map_err(|e| AutomationError::Mqtt(e.to_string()))?;
+        self.client
+            .publish(topic, qos, retain, payload)
+            .await
+            .map_err(|e| AutomationError::Mqtt(e.to_string()))
+    }
+
+    /// Receive messages on exactly these topic filters (`+` and `#`
+    /// wildcards allowed), kept across reconnects
+    pub fn set_topics(&self, filters: HashSet<String>) {
+        let previous = std::mem::replace(&mut *lock(&self.topics), filters.clone());
+        for filter in filters.difference(&previous) {
+            if let Err(e) = self.client.try_subscribe(filter, QoS::AtMostOnce) {
+                // Subscribed again once the connection is back
+                tracing::debug!("Deferred subscribing to {}: {}", filter, e);
+            }
+        }
+        for filter in previous.difference(&filters) {
+            // A new connection only has the topics in the set
+            if let Err(e) = self.client.try_unsubscribe(filter) {
+                tracing::debug!("Failed to unsubscribe from {}: {}", filter, e);
+            }
+        }
+    }
+
+    /// Topic filters subscribed to, sorted
+    #[must_use]
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = lock(&self.topics).iter().cloned().collect();
+        topics.sort();
+        topics
+    }
+}
+
+fn lock(topics: &Mutex<HashSet<String>>) -> std::sync::MutexGuard<'_, HashSet<String>> {
+    topics
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Whether `topic` matches a subscription filter with `+` (one level) and
+/// `#` (the rest) wildcards
+#[must_use]
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(actual)) if level == actual => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("frigate/events", "frigate/events"));
+        assert!(!topic_matches("frigate/events", "frigate/events/extra"));
+        assert!(topic_matches("tele/+/SENSOR", "tele/plug1/SENSOR"));
+        assert!(!topic_matches("tele/+/SENSOR", "tele/plug1/STATE"));
+        assert!(topic_matches("frigate/#", "frigate/front/person"));
+        assert!(topic_matches("frigate/#", "frigate"));
+        assert!(!topic_matches("frigate/+", "frigate"));
+    }
+}
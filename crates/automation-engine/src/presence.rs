@@ -0,0 +1,544 @@
+//! Phone-based presence
+//!
+//! Tracks whether each person is home from location reports sent by phone
+//! apps. Payloads follow the `OwnTracks` HTTP format: `location` messages are
+//! checked against the home zone (or the app's own `inregions` list) and
+//! `transition` messages report entering/leaving a region directly. Changes in
+//! who is home are broadcast for presence triggers. Reports with coordinates
+//! off the globe are rejected.
+
+use crate::error::AutomationError;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::broadcast;
+
+/// Default home zone radius in meters
+const DEFAULT_HOME_RADIUS_M: f64 = 100.0;
+
+/// Mean earth radius in meters
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Last known presence of a person
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonPresence {
+    /// Person name (from the URL)
+    pub name: String,
+    /// Whether the person is home
+    pub home: bool,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// Reported accuracy in meters
+    #[serde(default)]
+    pub accuracy: Option<f64>,
+    /// Distance from the home zone center in meters
+    #[serde(default)]
+    pub distance_m: Option<f64>,
+    /// Last update timestamp (ISO 8601)
+    pub updated_at: String,
+}
+
+impl PersonPresence {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            home: false,
+            latitude: None,
+            longitude: None,
+            accuracy: None,
+            distance_m: None,
+            updated_at: String::new(),
+        }
+    }
+}
+
+/// The home zone
+#[derive(Debug, Clone)]
+pub struct HomeZone {
+    /// Region name phone apps use for home (matched case-insensitively)
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_m: f64,
+}
+
+impl HomeZone {
+    /// Read the zone from `HOME_LATITUDE`/`HOME_LONGITUDE` (required) plus
+    /// `HOME_RADIUS_M` and `HOME_ZONE_NAME`
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let coord = |key: &str| std::env::var(key).ok().and_then(|v| v.parse::<f64>().ok());
+        let (latitude, longitude) = (coord("HOME_LATITUDE")?, coord("HOME_LONGITUDE")?);
+        if let Err(e) = check_position(latitude, longitude, None) {
+            tracing::warn!("Ignoring the home zone: {}", e);
+            return None;
+        }
+        Some(Self {
+            name: std::env::var("HOME_ZONE_NAME").unwrap_or_else(|_| "home".to_string()),
+            latitude,
+            longitude,
+            radius_m: coord("HOME_RADIUS_M")
+                .filter(|r| r.is_finite() && *r > 0.0)
+                .unwrap_or(DEFAULT_HOME_RADIUS_M),
+        })
+    }
+
+    /// Distance from the zone center in meters
+    #[must_use]
+    pub fn distance_m(&self, latitude: f64, longitude: f64) -> f64 {
+        haversine_m(self.latitude, self.longitude, latitude, longitude)
+    }
+
+    fn is_named(&self, region: &str) -> bool {
+        region.eq_ignore_ascii_case(&self.name)
+    }
+}
+
+/// Check that a position is on the globe and its accuracy is a distance
+fn check_position(lat: f64, lon: f64, acc: Option<f64>) -> Result<(), AutomationError> {
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(AutomationError::InvalidPresence(format!(
+            "latitude {lat} is not between -90 and 90"
+        )));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(AutomationError::InvalidPresence(format!(
+            "longitude {lon} is not between -180 and 180"
+        )));
+    }
+    if acc.is_some_and(|acc| !acc.is_finite() || acc < 0.0) {
+        return Err(AutomationError::InvalidPresence(
+            "accuracy must be a distance in meters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Great-circle distance between two points in meters
+fn haversine_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Location report from a phone app (`OwnTracks` compatible)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "_type", rename_all = "snake_case")]
+pub enum LocationUpdate {
+    /// Periodic position fix
+    Location {
+        lat: f64,
+        lon: f64,
+        /// Accuracy in meters
+        #[serde(default)]
+        acc: Option<f64>,
+        /// Regions the app considers the device to be in
+        #[serde(default)]
+        inregions: Option<Vec<String>>,
+    },
+    /// Region enter/leave event
+    Transition {
+        event: TransitionEvent,
+        /// Region name (the home zone if omitted)
+        #[serde(default)]
+        desc: Option<String>,
+        #[serde(default)]
+        lat: Option<f64>,
+        #[serde(default)]
+        lon: Option<f64>,
+        #[serde(default)]
+        acc: Option<f64>,
+    },
+    /// Any other message type (waypoints, cards, ...), ignored
+    #[serde(other)]
+    Other,
+}
+
+/// Region transition direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionEvent {
+    Enter,
+    #[serde(alias = "exit")]
+    Leave,
+}
+
+/// A person arrived home or left
+#[derive(Debug, Clone)]
+pub struct PresenceEvent {
+    pub person: String,
+    pub home: bool,
+}
+
+/// Tracks presence per person and persists it to `presence.json`
+pub struct PresenceTracker {
+    people: DashMap<String, PersonPresence>,
+    zone: Option<HomeZone>,
+    event_tx: broadcast::Sender<PresenceEvent>,
+    data_path: PathBuf,
+}
+
+impl PresenceTracker {
+    /// Create a tracker and load `presence.json`; the home zone comes from the environment
+    pub async fn new(data_dir: &Path) -> Self {
+        Self::with_zone(data_dir, HomeZone::from_env()).await
+    }
+
+    /// Create a tracker with a home zone and load `presence.json`
+    pub async fn with_zone(data_dir: &Path, zone: Option<HomeZone>) -> Self {
+        let (event_tx, _) = broadcast::channel(16);
+        if zone.is_none() {
+            tracing::debug!("No home zone configured, presence relies on app-reported regions");
+        }
+        let tracker = Self {
+            people: DashMap::new(),
+            zone,
+            event_tx,
+            data_path: data_dir.join("presence.json"),
+        };
+        tracker.load().await;
+        tracker
+    }
+
+    async fn load(&self) {
+        let people = match fs::read_to_string(&self.data_path).await {
+            Ok(contents) => match serde_json::from_str::<Vec<PersonPresence>>(&contents) {
+                Ok(people) => people,
+                Err(e) => {
+                    tracing::warn!("Failed to parse presence file {:?}: {}", self.data_path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!("Failed to read presence file {:?}: {}", self.data_path, e);
+                return;
+            }
+        };
+        for person in people {
+            self.people.insert(person.name.clone(), person);
+        }
+    }
+
+    async fn save(&self) -> Result<(), AutomationError> {
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string_pretty(&self.list())?;
+        let tmp_path = self.data_path.with_extension("json.tmp");
+        fs::write(&tmp_path, &json).await?;
+        fs::rename(&tmp_path, &self.data_path).await?;
+        Ok(())
+    }
+
+    /// Subscribe to arrivals and departures
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Everyone with a known presence
+    #[must_use]
+    pub fn list(&self) -> Vec<PersonPresence> {
+        let mut people: Vec<PersonPresence> =
+            self.people.iter().map(|r| r.value().clone()).collect();
+        people.sort_by(|a, b| a.name.cmp(&b.name));
+        people
+    }
+
+    /// Presence of one person
+    #[must_use]
+    pub fn get(&self, person: &str) -> Option<PersonPresence> {
+        self.people.get(person).map(|r| r.value().clone())
+    }
+
+    /// Whether a person is home (`false` if never seen)
+    #[must_use]
+    pub fn is_home(&self, person: &str) -> bool {
+        self.people.get(person).is_some_and(|p| p.home)
+    }
+
+    /// Whether anyone is home
+    #[must_use]
+    pub fn anyone_home(&self) -> bool {
+        self.people.iter().any(|p| p.home)
+    }
+
+    /// Apply a location report for a person
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn update(
+        &self,
+        person: &str,
+        update: LocationUpdate,
+    ) -> Result<PersonPresence, AutomationError> {
+        if person.trim().is_empty() {
+            return Err(AutomationError::InvalidPresence(
+                "person name is empty".to_string(),
+            ));
+        }
+
+        match &update {
+            LocationUpdate::Location { lat, lon, acc, .. } => check_position(*lat, *lon, *acc)?,
+            LocationUpdate::Transition {
+                lat: Some(lat),
+                lon: Some(lon),
+                acc,
+                ..
+            } => check_position(*lat, *lon, *acc)?,
+            LocationUpdate::Transition { .. } => {}
+            LocationUpdate::Other => {
+                return Ok(self
+                    .get(person)
+                    .unwrap_or_else(|| PersonPresence::new(person)));
+            }
+        }
+
+        // Applied under the entry's lock so concurrent reports for one
+        // person don't overwrite each other
+        let (presence, was_home) = {
+            let mut presence = self
+                .people
+                .entry(person.to_string())
+                .or_insert_with(|| PersonPresence::new(person));
+            let was_home = presence.home;
+            self.apply(&mut presence, update);
+            presence.updated_at = chrono::Utc::now().to_rfc3339();
+            (presence.clone(), was_home)
+        };
+        self.save().await?;
+
+        if presence.home != was_home {
+            tracing::info!(
+                "{} {}",
+                person,
+                if presence.home {
+                    "arrived home"
+                } else {
+                    "left home"
+                }
+            );
+            let _ = self.event_tx.send(PresenceEvent {
+                person: person.to_string(),
+                home: presence.home,
+            });
+        }
+
+        Ok(presence)
+    }
+
+    /// Update a person's presence from a checked report
+    fn apply(&self, presence: &mut PersonPresence, update: LocationUpdate) {
+        match update {
+            LocationUpdate::Location {
+                lat,
+                lon,
+                acc,
+                inregions,
+            } => {
+                self.record_position(presence, lat, lon, acc);
+                let in_home_region = inregions.as_ref().map(|regions| {
+                    regions.iter().any(|r| match &self.zone {
+                        Some(zone) => zone.is_named(r),
+                        None => r.eq_ignore_ascii_case("home"),
+                    })
+                });
+                if in_home_region == Some(true) {
+                    presence.home = true;
+                } else if let (Some(zone), Some(distance)) = (&self.zone, presence.distance_m) {
+                    presence.home = distance <= zone.radius_m;
+                } else if let Some(in_home) = in_home_region {
+                    presence.home = in_home;
+                }
+            }
+            LocationUpdate::Transition {
+                event,
+                desc,
+                lat,
+                lon,
+                acc,
+            } => {
+                if let (Some(lat), Some(lon)) = (lat, lon) {
+                    self.record_position(presence, lat, lon, acc);
+                }
+                let is_home_region = desc.as_deref().is_none_or(|d| match &self.zone {
+                    Some(zone) => zone.is_named(d),
+                    None => d.eq_ignore_ascii_case("home"),
+                });
+                if is_home_region {
+                    presence.home = event == TransitionEvent::Enter;
+                }
+            }
+            LocationUpdate::Other => {}
+        }
+    }
+
+    fn record_position(&self, presence: &mut PersonPresence, lat: f64, lon: f64, acc: Option<f64>) {
+        presence.latitude = Some(lat);
+        presence.longitude = Some(lon);
+        presence.accuracy = acc;
+        presence.distance_m = self.zone.as_ref().map(|zone| zone.distance_m(lat, lon));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_distance() {
+        // One degree of latitude is ~111.2 km
+        let d = haversine_m(52.0, 13.0, 53.0, 13.0);
+        assert!((d - 111_195.0).abs() < 100.0, "got {d}");
+        assert!(haversine_m(52.5, 13.4, 52.5, 13.4).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_parse_owntracks_payloads() {
+        let location: LocationUpdate = serde_json::from_str(
+            r#"{"_type":"location","lat":52.5,"lon":13.4,"acc":12,"tst":1700000000,"inregions":["Home"]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            location,
+            LocationUpdate::Location { acc: Some(_), .. }
+        ));
+
+        let transition: LocationUpdate = serde_json::from_str(
+            r#"{"_type":"transition","event":"leave","desc":"home","tst":1700000000}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            transition,
+            LocationUpdate::Transition {
+                event: TransitionEvent::Leave,
+                ..
+            }
+        ));
+
+        let other: LocationUpdate =
+            serde_json::from_str(r#"{"_type":"waypoints","waypoints":[]}"#).unwrap();
+        assert!(matches!(other, LocationUpdate::Other));
+    }
+
+    async fn tracker(name: &str) -> (PresenceTracker, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("casita-presence-{name}-{}", std::process::id()));
+        let zone = HomeZone {
+            name: "Home".to_string(),
+            latitude: 52.5,
+            longitude: 13.4,
+            radius_m: 100.0,
+        };
+        (PresenceTracker::with_zone(&dir, Some(zone)).await, dir)
+    }
+
+    fn location(lat: f64, lon: f64, inregions: Option<Vec<String>>) -> LocationUpdate {
+        LocationUpdate::Location {
+            lat,
+            lon,
+            acc: Some(10.0),
+            inregions,
+        }
+    }
+
+    fn transition(event: TransitionEvent, desc: Option<&str>) -> LocationUpdate {
+        LocationUpdate::Transition {
+            event,
+            desc: desc.map(str::to_string),
+            lat: None,
+            lon: None,
+            acc: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_arrivals_and_departures() {
+        let (tracker, dir) = tracker("moves").await;
+        let mut events = tracker.subscribe();
+
+        // Away at first, so no event
+        let away = tracker
+            .update("ana", location(52.6, 13.4, None))
+            .await
+            .unwrap();
+        assert!(!away.home);
+        assert!(away.distance_m.unwrap() > 10_000.0);
+        assert!(events.try_recv().is_err());
+
+        let home = tracker
+            .update("ana", location(52.5, 13.4, None))
+            .await
+            .unwrap();
+        assert!(home.home);
+        assert!(events.try_recv().unwrap().home);
+        assert!(tracker.anyone_home());
+
+        // Leaving another region doesn't change anything
+        tracker
+            .update("ana", transition(TransitionEvent::Leave, Some("Work")))
+            .await
+            .unwrap();
+        assert!(tracker.is_home("ana"));
+        assert!(events.try_recv().is_err());
+
+        tracker
+            .update("ana", transition(TransitionEvent::Leave, Some("home")))
+            .await
+            .unwrap();
+        assert!(!tracker.is_home("ana"));
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.person, "ana");
+        assert!(!event.home);
+
+        // The app says it's in the home region even though the fix is off
+        let home = tracker
+            .update("ana", location(52.6, 13.4, Some(vec!["HOME".to_string()])))
+            .await
+            .unwrap();
+        assert!(home.home);
+
+        // Saved
+        let reloaded = PresenceTracker::with_zone(&dir, None).await;
+        assert!(reloaded.is_home("ana"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reports_rejected() {
+        let (tracker, dir) = tracker("invalid").await;
+        tracker
+            .update("ana", location(52.5, 13.4, None))
+            .await
+            .unwrap();
+
+        for update in [
+            location(91.0, 13.4, None),
+            location(52.5, -180.5, None),
+            location(f64::NAN, 13.4, None),
+            LocationUpdate::Location {
+                lat: 52.5,
+                lon: 13.4,
+                acc: Some(-1.0),
+                inregions: None,
+            },
+        ] {
+            assert!(tracker.update("ana", update).await.is_err());
+        }
+        assert!(tracker.is_home("ana"));
+        assert_eq!(tracker.get("ana").unwrap().latitude, Some(52.5));
+
+        // Other messages don't create anyone
+        assert!(tracker.update("bo", LocationUpdate::Other).await.is_ok());
+        assert!(tracker.get("bo").is_none());
+        assert!(tracker
+            .update(" ", location(52.5, 13.4, None))
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
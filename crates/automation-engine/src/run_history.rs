@@ -0,0 +1,245 @@
+//! Execution history of automations
+//!
+//! Every run is recorded with what triggered it, whether its conditions
+//! passed, how each action went and how long it all took, so "why didn't my
+//! automation run last night" has an answer. The last
+//! [`MAX_RUNS_PER_AUTOMATION`] runs of each automation are kept in
+//! `automation_runs.json`, the newest of them with their trace (see
+//! [`crate::trace`]).
+
+use crate::error::AutomationError;
+use crate::trace::{RunTrace, MAX_TRACES_PER_AUTOMATION};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs;
+
+/// Runs kept per automation
+pub const MAX_RUNS_PER_AUTOMATION: usize = 50;
+
+/// How an action of a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionStatus {
+    Succeeded,
+    Failed,
+    /// The run was cancelled while the action was in progress
+    Cancelled,
+}
+
+/// Outcome of one action of a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionOutcome {
+    /// Index in the automation's actions
+    pub index: usize,
+    pub status: ActionStatus,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One execution of an automation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// ID of the run, to look up its trace
+    #[serde(default = "new_run_id")]
+    pub id: String,
+    pub automation_id: String,
+    pub started_at: DateTime<Utc>,
+    /// `manual`, `schedule`, `device_state`...
+    pub trigger_reason: String,
+    /// Whether the conditions passed; no actions ran if they didn't
+    pub conditions_met: bool,
+    /// Actions that ran, in order; a failed action ends the run
+    pub actions: Vec<ActionOutcome>,
+    pub duration_ms: u64,
+    /// Why the run failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// What happened in detail; only kept for the newest runs and left out
+    /// of [`RunHistory::get`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<RunTrace>,
+}
+
+/// A new run ID (runs recorded before they had one get one on load)
+#[must_use]
+pub fn new_run_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Recent runs of every automation
+pub struct RunHistory {
+    runs: Mutex<HashMap<String, VecDeque<RunRecord>>>,
+    data_path: PathBuf,
+    /// Serializes writes of the file
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl RunHistory {
+    /// Create the history and load `automation_runs.json`
+    pub async fn new(data_dir: &Path) -> Self {
+        let history = Self {
+            runs: Mutex::new(HashMap::new()),
+            data_path: data_dir.join("automation_runs.json"),
+            save_lock: tokio::sync::Mutex::new(()),
+        };
+        history.load().await;
+        history
+    }
+
+    async fn load(&self) {
+        let runs = match fs::read_to_string(&self.data_path).await {
+            Ok(contents) => {
+                match serde_json::from_str::<HashMap<String, VecDeque<RunRecord>>>(&contents) {
+                    Ok(runs) => runs,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to parse automation runs file {:?}: {}",
+                            self.data_path,
+                            e
+                        );
+                        return;
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to read automation runs file {:?}: {}",
+                    self.data_path,
+                    e
+                );
+                return;
+            }
+        };
+        *self.lock() = runs;
+    }
+
+    async fn save(&self) -> Result<(), AutomationError> {
+        let _guard = self.save_lock.lock().await;
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string(&*self.lock())?;
+        let tmp_path = self.data_path.with_extension("json.tmp");
+        fs::write(&tmp_path, &json).await?;
+        fs::rename(&tmp_path, &self.data_path).await?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, VecDeque<RunRecord>>> {
+        self.runs
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Add a run, dropping the oldest of its automation beyond the limit
+    pub async fn record(&self, run: RunRecord) {
+        {
+            let mut runs = self.lock();
+            let automation_runs = runs.entry(run.automation_id.clone()).or_default();
+            automation_runs.push_back(run);
+            while automation_runs.len() > MAX_RUNS_PER_AUTOMATION {
+                automation_runs.pop_front();
+            }
+            let traced = automation_runs
+                .len()
+                .saturating_sub(MAX_TRACES_PER_AUTOMATION);
+            for run in automation_runs.iter_mut().take(traced) {
+                run.trace = None;
+            }
+        }
+        if let Err(e) = self.save().await {
+            tracing::warn!("Failed to save automation runs: {}", e);
+        }
+    }
+
+    /// Runs of an automation, newest first, without their traces
+    #[must_use]
+    pub fn get(&self, automation_id: &str) -> Vec<RunRecord> {
+        self.lock()
+            .get(automation_id)
+            .map(|runs| {
+                runs.iter()
+                    .rev()
+                    .map(|run| RunRecord {
+                        trace: None,
+                        ..run.clone()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// A run of an automation with its trace, if it's still kept
+    #[must_use]
+    pub fn trace(&self, automation_id: &str, run_id: &str) -> Option<RunRecord> {
+        self.lock()
+            .get(automation_id)?
+            .iter()
+            .find(|run| run.id == run_id && run.trace.is_some())
+            .cloned()
+    }
+
+    /// Forget the runs of a deleted automation
+    pub async fn remove(&self, automation_id: &str) {
+        if self.lock().remove(automation_id).is_none() {
+            return;
+        }
+        if let Err(e) = self.save().await {
+            tracing::warn!("Failed to save automation runs: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_history_is_bounded_and_persisted() {
+        let data_dir = std::env::temp_dir().join(format!("automation-runs-{}", std::process::id()));
+        let history = RunHistory::new(&data_dir).await;
+        for i in 0..MAX_RUNS_PER_AUTOMATION + 5 {
+            history
+                .record(RunRecord {
+                    id: format!("run-{i}"),
+                    automation_id: "a".to_string(),
+                    started_at: Utc::now(),
+                    trigger_reason: format!("run {i}"),
+                    conditions_met: true,
+                    actions: Vec::new(),
+                    duration_ms: 0,
+                    error: None,
+                    trace: Some(RunTrace::default()),
+                })
+                .await;
+        }
+
+        let reloaded = RunHistory::new(&data_dir).await;
+        let runs = reloaded.get("a");
+        assert_eq!(runs.len(), MAX_RUNS_PER_AUTOMATION);
+        assert_eq!(
+            runs[0].trigger_reason,
+            format!("run {}", MAX_RUNS_PER_AUTOMATION + 4)
+        );
+        assert_eq!(runs.last().unwrap().trigger_reason, "run 5");
+        assert!(runs.iter().all(|run| run.trace.is_none()));
+        let newest = MAX_RUNS_PER_AUTOMATION + 4;
+        assert!(reloaded.trace("a", &format!("run-{newest}")).is_some());
+        let oldest_traced = newest + 1 - MAX_TRACES_PER_AUTOMATION;
+        assert!(reloaded
+            .trace("a", &format!("run-{oldest_traced}"))
+            .is_some());
+        assert!(reloaded
+            .trace("a", &format!("run-{}", oldest_traced - 1))
+            .is_none());
+
+        reloaded.remove("a").await;
+        assert!(RunHistory::new(&data_dir).await.get("a").is_empty());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
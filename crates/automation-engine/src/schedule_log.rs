@@ -0,0 +1,95 @@
+//! When schedules last came due
+//!
+//! Schedules that catch up on slots missed while the hub was down need to
+//! know the last slot they saw, whether or not it ran the automation (its
+//! conditions may have failed). That is scheduler state rather than a
+//! counter users look at, so it is kept apart from the stats, in
+//! `automation_schedule.json`, and only for catching-up schedules.
+
+use crate::error::AutomationError;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs;
+
+/// When each catching-up schedule last came due
+pub struct ScheduleLog {
+    due: Mutex<HashMap<String, DateTime<Utc>>>,
+    data_path: PathBuf,
+    /// Serializes writes of the file
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl ScheduleLog {
+    /// Create the log and load `automation_schedule.json`
+    pub async fn new(data_dir: &Path) -> Self {
+        let log = Self {
+            due: Mutex::new(HashMap::new()),
+            data_path: data_dir.join("automation_schedule.json"),
+            save_lock: tokio::sync::Mutex::new(()),
+        };
+        log.load().await;
+        log
+    }
+
+    async fn load(&self) {
+        let due = match fs::read_to_string(&self.data_path).await {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(due) => due,
+                Err(e) => {
+                    tracing::warn!("Failed to parse schedule file {:?}: {}", self.data_path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!("Failed to read schedule file {:?}: {}", self.data_path, e);
+                return;
+            }
+        };
+        *self.lock() = due;
+    }
+
+    async fn save(&self) -> Result<(), AutomationError> {
+        let _guard = self.save_lock.lock().await;
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string(&*self.lock())?;
+        let tmp_path = self.data_path.with_extension("json.tmp");
+        fs::write(&tmp_path, &json).await?;
+        fs::rename(&tmp_path, &self.data_path).await?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, DateTime<Utc>>> {
+        self.due
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Note that an automation's schedule came due
+    pub async fn record(&self, automation_id: &str, at: DateTime<Utc>) {
+        self.lock().insert(automation_id.to_string(), at);
+        if let Err(e) = self.save().await {
+            tracing::warn!("Failed to save schedule log: {}", e);
+        }
+    }
+
+    /// When an automation's schedule last came due, if it was noted
+    #[must_use]
+    pub fn last_due(&self, automation_id: &str) -> Option<DateTime<Utc>> {
+        self.lock().get(automation_id).copied()
+    }
+
+    /// Forget a deleted automation
+    pub async fn remove(&self, automation_id: &str) {
+        if self.lock().remove(automation_id).is_none() {
+            return;
+        }
+        if let Err(e) = self.save().await {
+            tracing::warn!("Failed to save schedule log: {}", e);
+        }
+    }
+}
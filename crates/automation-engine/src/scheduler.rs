@@ -1,8 +1,9 @@
 //! Scheduler for time-based automation triggers
 
+use crate::clock::Zone;
 use crate::error::AutomationError;
 use crate::model::{Automation, ScheduleSpec, Trigger};
-use chrono::{Datelike, Local, NaiveTime};
+use chrono::Utc;
 use cron::Schedule;
 use dashmap::DashMap;
 use std::str::FromStr;
@@ -22,6 +23,8 @@ pub struct Scheduler {
     timers: Arc<DashMap<String, JoinHandle<()>>>,
     /// Event sender for scheduled triggers
     event_tx: broadcast::Sender<SchedulerEvent>,
+    /// Time zone of schedules that don't name their own
+    zone: Zone,
 }
 
 impl Default for Scheduler {
@@ -38,6 +41,24 @@ impl Scheduler {
         Self {
             timers: Arc::new(DashMap::new()),
             event_tx,
+            zone: Zone::Local,
+        }
+    }
+
+    /// Run schedules that don't name a time zone in `zone` instead of the
+    /// system's
+    #[must_use]
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    /// Time zone a trigger's schedule runs in
+    #[must_use]
+    pub fn zone_for(&self, trigger: &Trigger) -> Zone {
+        match trigger {
+            Trigger::Schedule { timezone, .. } => self.zone.or_named(timezone.as_deref()),
+            _ => self.zone,
         }
     }
 
@@ -50,7 +71,7 @@ impl Scheduler {
     /// Register an automation with a schedule trigger
     #[allow(clippy::missing_errors_doc)]
     pub fn register(&self, automation: &Automation) -> Result<(), AutomationError> {
-        let Trigger::Schedule { schedule } = &automation.trigger else {
+        let Trigger::Schedule { schedule, .. } = &automation.trigger else {
             return Ok(());
         };
 
@@ -64,21 +85,36 @@ impl Scheduler {
         self.remove(&automation.id);
 
         // Create new timer based on schedule type
+        let zone = self.zone_for(&automation.trigger);
         match schedule {
             ScheduleSpec::Interval { seconds } => {
                 self.schedule_interval(&automation.id, *seconds);
             }
             ScheduleSpec::TimeOfDay { time, days } => {
-                self.schedule_time_of_day(&automation.id, time, days)?;
+                self.schedule_time_of_day(&automation.id, time, days, zone)?;
             }
             ScheduleSpec::Cron { expression } => {
-                self.schedule_cron(&automation.id, expression)?;
+                self.schedule_cron(&automation.id, expression, zone)?;
             }
         }
 
         Ok(())
     }
 
+    /// Check that an automation's schedule can be registered
+    #[allow(clippy::missing_errors_doc)]
+    pub fn check(automation: &Automation) -> Result<(), AutomationError> {
+        if let Trigger::Schedule {
+            schedule: ScheduleSpec::Cron { expression },
+            ..
+        } = &automation.trigger
+        {
+            Schedule::from_str(expression)
+                .map_err(|e| AutomationError::InvalidCron(format!("{expression}: {e}")))?;
+        }
+        Ok(())
+    }
+
     /// Remove an automation from the scheduler
     pub fn remove(&self, automation_id: &str) {
         if let Some((_, handle)) = self.timers.remove(automation_id) {
@@ -127,9 +163,10 @@ impl Scheduler {
         automation_id: &str,
         time_str: &str,
         days: &[u8],
+        zone: Zone,
     ) -> Result<(), AutomationError> {
-        let target_time = NaiveTime::parse_from_str(time_str, "%H:%M")
-            .map_err(|_| AutomationError::InvalidTimeFormat(time_str.to_string()))?;
+        let target_time = crate::clock::parse_time(time_str)
+            .ok_or_else(|| AutomationError::InvalidTimeFormat(time_str.to_string()))?;
 
         let id = automation_id.to_string();
         let event_tx = self.event_tx.clone();
@@ -139,39 +176,19 @@ impl Scheduler {
         let handle = tokio::spawn(async move {
             loop {
                 // Calculate time until next trigger
-                let now = Local::now();
-                let today = now.date_naive();
-                let mut target_datetime = today.and_time(target_time);
-
-                // If we've passed today's time, move to tomorrow
-                if target_datetime <= now.naive_local() {
-                    target_datetime += chrono::Duration::days(1);
-                }
-
-                // Check day-of-week filter
-                if !days_filter.is_empty() {
-                    let mut dt = target_datetime;
-                    let mut attempts = 0;
-                    while !days_filter
-                        .contains(&u8::try_from(dt.weekday().num_days_from_sunday()).unwrap())
-                        && attempts < 7
-                    {
-                        dt += chrono::Duration::days(1);
-                        attempts += 1;
-                    }
-                    target_datetime = dt;
-                }
-
-                // Calculate sleep duration
-                let target_instant = target_datetime.and_local_timezone(Local).unwrap();
-                let duration = (target_instant - now)
+                let now = Utc::now();
+                let Some(next_time) = zone.next_time_of_day(target_time, &days_filter, now) else {
+                    tracing::warn!("No upcoming times for time-of-day schedule {}", id);
+                    break;
+                };
+                let duration = (next_time - now)
                     .to_std()
                     .unwrap_or(std::time::Duration::from_secs(1));
 
                 tracing::debug!(
                     "Next time-of-day trigger for {} at {} (in {:?})",
                     id,
-                    target_datetime,
+                    next_time,
                     duration
                 );
 
@@ -189,16 +206,22 @@ impl Scheduler {
 
         self.timers.insert(automation_id.to_string(), handle);
         tracing::info!(
-            "Scheduled time-of-day trigger at {} (days: {:?}) for automation {}",
+            "Scheduled time-of-day trigger at {} (days: {:?}, time zone: {:?}) for automation {}",
             time_str,
             days_log,
+            zone,
             automation_id
         );
         Ok(())
     }
 
     /// Schedule a cron-based trigger
-    fn schedule_cron(&self, automation_id: &str, expression: &str) -> Result<(), AutomationError> {
+    fn schedule_cron(
+        &self,
+        automation_id: &str,
+        expression: &str,
+        zone: Zone,
+    ) -> Result<(), AutomationError> {
         let schedule = Schedule::from_str(expression)
             .map_err(|e| AutomationError::InvalidCron(format!("{expression}: {e}")))?;
 
@@ -208,10 +231,8 @@ impl Scheduler {
         let handle = tokio::spawn(async move {
             loop {
                 // Find next scheduled time
-                let now = Local::now();
-                let next = schedule.upcoming(Local).next();
-
-                let Some(next_time) = next else {
+                let now = Utc::now();
+                let Some(next_time) = zone.next_cron(&schedule, now) else {
                     tracing::warn!("No upcoming times for cron schedule {}", id);
                     break;
                 };
@@ -241,8 +262,9 @@ impl Scheduler {
 
         self.timers.insert(automation_id.to_string(), handle);
         tracing::info!(
-            "Scheduled cron trigger '{}' for automation {}",
+            "Scheduled cron trigger '{}' (time zone: {:?}) for automation {}",
             expression,
+            zone,
             automation_id
         );
         Ok(())
@@ -0,0 +1,278 @@
+//! Rhai scripts in conditions and actions
+//!
+//! For logic that doesn't fit the declarative model. Scripts run in a
+//! sandboxed [Rhai](https://rhai.rs) engine: no modules, no `eval`, and a
+//! cap on the work, nesting and data a script may use. Besides the language
+//! itself they get:
+//!
+//! - `trigger`: the run's trigger context (see [`crate::template`])
+//! - `is_on(ieee)`, `is_available(ieee)` and `sensor(ieee, "temperature")`
+//!   (`()` without a reading) to read devices
+//! - `log(message)`
+//! - in actions, `turn_on(ieee, endpoint)`, `turn_off(ieee, endpoint)` and
+//!   `toggle(ieee, endpoint)`
+//!
+//! A condition script evaluates to a bool. Device commands of an action
+//! script are sent once it has finished, in order. A dry run of an action
+//! script collects its commands and log messages without sending or
+//! logging anything.
+
+use crate::error::AutomationError;
+use crate::ieee::parse_ieee_address;
+use crate::model::DeviceCommand;
+use rhai::{Dynamic, Engine, EvalAltResult, Scope};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use zigbee_core::{SensorKind, ZigbeeDevice, ZigbeeNetwork};
+
+/// Most operations a script may run
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+/// A device command queued by an action script
+#[derive(Debug, Clone)]
+pub struct ScriptCommand {
+    pub device_ieee: String,
+    pub endpoint: u8,
+    pub command: DeviceCommand,
+}
+
+type Commands = Arc<Mutex<Vec<ScriptCommand>>>;
+
+/// Messages a dry run would have logged
+type Messages = Arc<Mutex<Vec<String>>>;
+
+/// Check that a script parses
+#[allow(clippy::missing_errors_doc)]
+pub fn check(source: &str) -> Result<(), String> {
+    sandbox(None, None, None)
+        .compile(source)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Run a condition script
+#[allow(clippy::missing_errors_doc)]
+pub fn evaluate(
+    source: &str,
+    network: Option<Arc<ZigbeeNetwork>>,
+    context: &Value,
+) -> Result<bool, AutomationError> {
+    let engine = sandbox(network, None, None);
+    let mut scope = scope(&engine, context)?;
+    engine
+        .eval_with_scope::<bool>(&mut scope, source)
+        .map_err(|e| AutomationError::Script(e.to_string()))
+}
+
+/// Run an action script, returning the device commands it queued
+#[allow(clippy::missing_errors_doc)]
+pub fn run(
+    source: &str,
+    network: Option<Arc<ZigbeeNetwork>>,
+    context: &Value,
+) -> Result<Vec<ScriptCommand>, AutomationError> {
+    let commands = Commands::default();
+    let engine = sandbox(network, Some(commands.clone()), None);
+    let mut scope = scope(&engine, context)?;
+    engine
+        .run_with_scope(&mut scope, source)
+        .map_err(|e| AutomationError::Script(e.to_string()))?;
+    let queued = std::mem::take(&mut *lock(&commands));
+    Ok(queued)
+}
+
+/// Run an action script without logging, returning the device commands it
+/// queued and the messages it would have logged
+#[allow(clippy::missing_errors_doc)]
+pub fn dry_run(
+    source: &str,
+    network: Option<Arc<ZigbeeNetwork>>,
+    context: &Value,
+) -> Result<(Vec<ScriptCommand>, Vec<String>), AutomationError> {
+    let commands = Commands::default();
+    let messages = Messages::default();
+    let engine = sandbox(network, Some(commands.clone()), Some(messages.clone()));
+    let mut scope = scope(&engine, context)?;
+    engine
+        .run_with_scope(&mut scope, source)
+        .map_err(|e| AutomationError::Script(e.to_string()))?;
+    let queued = std::mem::take(&mut *lock(&commands));
+    let logged = std::mem::take(&mut *lock(&messages));
+    Ok((queued, logged))
+}
+
+/// An engine with the restricted API; `commands` is where device commands
+/// are queued, `None` leaves them out (conditions). With `messages`, log
+/// output goes there instead of the log.
+fn sandbox(
+    network: Option<Arc<ZigbeeNetwork>>,
+    commands: Option<Commands>,
+    messages: Option<Messages>,
+) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000)
+        .disable_symbol("eval");
+    if let Some(messages) = messages {
+        let collect = messages.clone();
+        engine.on_print(move |message| lock(&collect).push(message.to_string()));
+        let collect = messages.clone();
+        engine.on_debug(move |message, _, _| lock(&collect).push(message.to_string()));
+        engine.register_fn("log", move |message: &str| {
+            lock(&messages).push(message.to_string());
+        });
+    } else {
+        engine.on_print(|message| tracing::info!(target: "automation", "{}", message));
+        engine.on_debug(|message, _, _| tracing::debug!(target: "automation", "{}", message));
+        engine.register_fn("log", |message: &str| {
+            tracing::info!(target: "automation", "{}", message);
+        });
+    }
+
+    let net = network.clone();
+    engine.register_fn("is_on", move |ieee: &str| {
+        device(net.as_ref(), ieee).map(|d| d.is_some_and(|d| d.state_on == Some(true)))
+    });
+    let net = network.clone();
+    engine.register_fn("is_available", move |ieee: &str| {
+        device(net.as_ref(), ieee).map(|d| d.is_some_and(|d| d.available))
+    });
+    engine.register_fn(
+        "sensor",
+        move |ieee: &str, kind: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            let kind: SensorKind = serde_json::from_value(Value::String(kind.to_string()))
+                .map_err(|_| format!("Unknown sensor kind: {kind}"))?;
+            Ok(device(network.as_ref(), ieee)?
+                .and_then(|d| d.sensor_values.get(&kind).copied())
+                .map_or(Dynamic::UNIT, Dynamic::from_float))
+        },
+    );
+
+    if let Some(commands) = commands {
+        for (name, command) in [
+            ("turn_on", DeviceCommand::TurnOn),
+            ("turn_off", DeviceCommand::TurnOff),
+            ("toggle", DeviceCommand::Toggle),
+        ] {
+            let commands = commands.clone();
+            engine.register_fn(
+                name,
+                move |ieee: &str, endpoint: i64| -> Result<(), Box<EvalAltResult>> {
+                    parse_ieee_address(ieee).map_err(|e| e.to_string())?;
+                    let endpoint = u8::try_from(endpoint)
+                        .map_err(|_| format!("Invalid endpoint: {endpoint}"))?;
+                    lock(&commands).push(ScriptCommand {
+                        device_ieee: ieee.to_string(),
+                        endpoint,
+                        command: command.clone(),
+                    });
+                    Ok(())
+                },
+            );
+        }
+    }
+    engine
+}
+
+/// Scope with the run's trigger context as `trigger`
+fn scope(engine: &Engine, context: &Value) -> Result<Scope<'static>, AutomationError> {
+    let mut scope = Scope::new();
+    let trigger = match context.get("trigger") {
+        Some(trigger @ Value::Object(_)) => engine
+            .parse_json(trigger.to_string(), true)
+            .map_err(|e| AutomationError::Script(e.to_string()))?,
+        _ => rhai::Map::new(),
+    };
+    scope.push_constant("trigger", trigger);
+    Ok(scope)
+}
+
+fn device(
+    network: Option<&Arc<ZigbeeNetwork>>,
+    ieee: &str,
+) -> Result<Option<ZigbeeDevice>, Box<EvalAltResult>> {
+    let ieee = parse_ieee_address(ieee).map_err(|e| e.to_string())?;
+    Ok(network.and_then(|network| network.get_device(&ieee)))
+}
+
+fn lock<T>(list: &Mutex<Vec<T>>) -> std::sync::MutexGuard<'_, Vec<T>> {
+    list.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_condition_script() {
+        let context = json!({ "trigger": { "reason": "sensor_value", "value": 17.5 } });
+        assert!(evaluate("trigger.value < 18.0", None, &context).unwrap());
+        // Unknown devices are off and have no readings
+        assert!(!evaluate(
+            r#"is_on("00:11:22:33:44:55:66:77") || sensor("00:11:22:33:44:55:66:77", "temperature") != ()"#,
+            None,
+            &context
+        )
+        .unwrap());
+        // No device commands in conditions
+        assert!(evaluate(
+            r#"turn_on("00:11:22:33:44:55:66:77", 1); true"#,
+            None,
+            &context
+        )
+        .is_err());
+        assert!(evaluate("42", None, &context).is_err());
+    }
+
+    #[test]
+    fn test_action_script_queues_commands() {
+        let commands = run(
+            r#"
+                for ep in [1, 2] {
+                    if ep == 1 { turn_on("00:11:22:33:44:55:66:77", ep) } else { toggle("00:11:22:33:44:55:66:77", ep) }
+                }
+                log(`ran for ${trigger.reason}`);
+            "#,
+            None,
+            &json!({ "trigger": { "reason": "manual" } }),
+        )
+        .unwrap();
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(commands[0].command, DeviceCommand::TurnOn));
+        assert_eq!(commands[1].endpoint, 2);
+        assert!(matches!(commands[1].command, DeviceCommand::Toggle));
+    }
+
+    #[test]
+    fn test_dry_run_collects_log_messages() {
+        let (commands, messages) = dry_run(
+            r#"
+                turn_off("00:11:22:33:44:55:66:77", 1);
+                log(`ran for ${trigger.reason}`);
+                print("done");
+            "#,
+            None,
+            &json!({ "trigger": { "reason": "manual" } }),
+        )
+        .unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(messages, ["ran for manual", "done"]);
+    }
+
+    #[test]
+    fn test_script_is_sandboxed() {
+        let context = json!({});
+        assert!(run("loop {}", None, &context).is_err());
+        assert!(run(r#"import "secrets" as s;"#, None, &context).is_err());
+        assert!(check(r#"eval("1 + 1")"#).is_err());
+        assert!(check("let x = ;").is_err());
+    }
+}
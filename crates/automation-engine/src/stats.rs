@@ -0,0 +1,274 @@
+//! Per-automation counters
+//!
+//! Unlike the run history, which forgets all but the latest runs, these
+//! count every run since the automation was created, so the UI can show
+//! which rules actually do work and which never fire. They are kept in
+//! `automation_stats.json`, written at most every [`SAVE_DELAY`] (and when
+//! the store is dropped) so a rule running every second doesn't rewrite the
+//! file every second.
+
+use crate::error::AutomationError;
+use crate::run_history::RunRecord;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs;
+
+/// How long changes wait to be saved, so runs close together share a write
+pub const SAVE_DELAY: Duration = Duration::from_secs(10);
+
+/// Counters of an automation's runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutomationStats {
+    /// Runs started by a trigger or by hand, including those whose
+    /// conditions weren't met
+    pub trigger_count: u64,
+    /// Runs whose actions all went through
+    pub success_count: u64,
+    /// Runs that failed or were cancelled
+    pub failure_count: u64,
+    /// When the latest run started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_triggered: Option<DateTime<Utc>>,
+    /// When the latest run whose conditions passed started (what
+    /// `last_triggered` conditions check)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_ran_actions: Option<DateTime<Utc>>,
+    /// Average duration of the runs that ran their actions
+    pub average_duration_ms: u64,
+}
+
+impl AutomationStats {
+    /// Count a run
+    fn add(&mut self, run: &RunRecord) {
+        self.trigger_count += 1;
+        self.last_triggered = Some(run.started_at);
+        if run.conditions_met {
+            self.last_ran_actions = Some(run.started_at);
+        }
+        if run.error.is_some() {
+            self.failure_count += 1;
+        } else if run.conditions_met {
+            self.success_count += 1;
+        } else {
+            return;
+        }
+        let runs = u128::from(self.success_count + self.failure_count);
+        let total = u128::from(self.average_duration_ms) * (runs - 1) + u128::from(run.duration_ms);
+        self.average_duration_ms = u64::try_from(total / runs).unwrap_or(u64::MAX);
+    }
+}
+
+/// Counters of every automation
+pub struct StatsStore {
+    stats: Mutex<HashMap<String, AutomationStats>>,
+    data_path: PathBuf,
+    /// Serializes writes of the file
+    save_lock: tokio::sync::Mutex<()>,
+    /// Whether changes are waiting for a save
+    unsaved: AtomicBool,
+}
+
+impl StatsStore {
+    /// Create the store and load `automation_stats.json`
+    pub async fn new(data_dir: &Path) -> Self {
+        let store = Self {
+            stats: Mutex::new(HashMap::new()),
+            data_path: data_dir.join("automation_stats.json"),
+            save_lock: tokio::sync::Mutex::new(()),
+            unsaved: AtomicBool::new(false),
+        };
+        store.load().await;
+        store
+    }
+
+    async fn load(&self) {
+        let stats = match fs::read_to_string(&self.data_path).await {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to parse automation stats file {:?}: {}",
+                        self.data_path,
+                        e
+                    );
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to read automation stats file {:?}: {}",
+                    self.data_path,
+                    e
+                );
+                return;
+            }
+        };
+        *self.lock() = stats;
+    }
+
+    async fn save(&self) -> Result<(), AutomationError> {
+        let _guard = self.save_lock.lock().await;
+        if let Some(parent) = self.data_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string(&*self.lock())?;
+        let tmp_path = self.data_path.with_extension("json.tmp");
+        fs::write(&tmp_path, &json).await?;
+        fs::rename(&tmp_path, &self.data_path).await?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, AutomationStats>> {
+        self.stats
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Save after [`SAVE_DELAY`], along with any other changes by then
+    fn save_soon(self: &Arc<Self>) {
+        if self.unsaved.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let store = Arc::downgrade(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(SAVE_DELAY).await;
+            // Once dropped, the store has saved itself
+            let Some(store) = store.upgrade() else {
+                return;
+            };
+            store.unsaved.store(false, Ordering::Release);
+            if let Err(e) = store.save().await {
+                tracing::warn!("Failed to save automation stats: {}", e);
+            }
+        });
+    }
+
+    /// Count a run of its automation
+    pub fn record(self: &Arc<Self>, run: &RunRecord) {
+        self.lock()
+            .entry(run.automation_id.clone())
+            .or_default()
+            .add(run);
+        self.save_soon();
+    }
+
+    /// Counters of an automation (all zero if it never ran)
+    #[must_use]
+    pub fn get(&self, automation_id: &str) -> AutomationStats {
+        self.lock().get(automation_id).cloned().unwrap_or_default()
+    }
+
+    /// Forget the counters of a deleted automation
+    pub fn remove(self: &Arc<Self>, automation_id: &str) {
+        if self.lock().remove(automation_id).is_some() {
+            self.save_soon();
+        }
+    }
+}
+
+impl Drop for StatsStore {
+    fn drop(&mut self) {
+        if !*self.unsaved.get_mut() {
+            return;
+        }
+        let result = serde_json::to_string(&*self.lock())
+            .map_err(std::io::Error::from)
+            .and_then(|json| {
+                if let Some(parent) = self.data_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let tmp_path = self.data_path.with_extension("json.tmp");
+                std::fs::write(&tmp_path, json)?;
+                std::fs::rename(&tmp_path, &self.data_path)
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to save automation stats: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stats_count_runs_and_persist() {
+        let data_dir =
+            std::env::temp_dir().join(format!("automation-stats-{}", std::process::id()));
+        let store = Arc::new(StatsStore::new(&data_dir).await);
+        let run = |conditions_met, error: Option<&str>, duration_ms| RunRecord {
+            id: crate::run_history::new_run_id(),
+            automation_id: "a".to_string(),
+            started_at: Utc::now(),
+            trigger_reason: "manual".to_string(),
+            conditions_met,
+            actions: Vec::new(),
+            duration_ms,
+            error: error.map(ToString::to_string),
+            trace: None,
+        };
+        store.record(&run(true, None, 100));
+        store.record(&run(false, None, 1));
+        store.record(&run(true, Some("Device not found"), 300));
+        // Saved together later, or when the store goes away
+        assert!(!store.data_path.exists());
+        drop(store);
+
+        let store = Arc::new(StatsStore::new(&data_dir).await);
+        let stats = store.get("a");
+        assert_eq!(stats.trigger_count, 3);
+        assert_eq!(stats.success_count, 1);
+        assert_eq!(stats.failure_count, 1);
+        assert_eq!(stats.average_duration_ms, 200);
+        assert!(stats.last_triggered.is_some());
+        assert!(stats.last_ran_actions <= stats.last_triggered);
+        assert_eq!(store.get("b"), AutomationStats::default());
+
+        store.remove("a");
+        drop(store);
+        assert_eq!(
+            StatsStore::new(&data_dir).await.get("a"),
+            AutomationStats::default()
+        );
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runs_share_a_save() {
+        let data_dir =
+            std::env::temp_dir().join(format!("automation-stats-save-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let store = Arc::new(StatsStore::new(&data_dir).await);
+        let run = RunRecord {
+            id: crate::run_history::new_run_id(),
+            automation_id: "a".to_string(),
+            started_at: Utc::now(),
+            trigger_reason: "schedule".to_string(),
+            conditions_met: true,
+            actions: Vec::new(),
+            duration_ms: 5,
+            error: None,
+            trace: None,
+        };
+        for _ in 0..5 {
+            store.record(&run);
+            tokio::time::sleep(SAVE_DELAY / 10).await;
+        }
+        assert!(!store.data_path.exists());
+
+        tokio::time::sleep(SAVE_DELAY).await;
+        while !store.data_path.exists() {
+            tokio::task::yield_now().await;
+        }
+        let saved = StatsStore::new(&data_dir).await;
+        assert_eq!(saved.get("a").trigger_count, 5);
+        drop(saved);
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
@@ -0,0 +1,155 @@
+//! Placeholders in action text and conditions
+//!
+//! `{{trigger.body.visitor}}` in a message is replaced with the value at
+//! that path in the run's context, e.g. a field of the JSON a webhook was
+//! called with. Strings are inserted as they are, other values as JSON, and
+//! paths that don't exist as nothing. Array elements are reached by index
+//! (`{{trigger.body.items.0}}`).
+//!
+//! Runs started by a device carry `{{trigger.device}}` (its IEEE address),
+//! `{{trigger.endpoint}}` and `{{trigger.value}}` (the state, reading or
+//! button action it reported), so one automation can act on "the device
+//! that triggered me". `{{now}}` is the time when the text is filled in,
+//! in the configured [`Zone`].
+
+use crate::clock::Zone;
+use crate::ieee::format_ieee;
+use serde_json::{json, Value};
+use zigbee_core::NetworkEvent;
+
+/// Replace every `{{path}}` in `template` with its value in `context`
+#[must_use]
+pub fn render(template: &str, context: &Value) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let path = rest[start + 2..start + 2 + len].trim();
+        if path == "now" {
+            rendered.push_str(
+                &Zone::configured()
+                    .now()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+            );
+            rest = &rest[start + 2 + len + 2..];
+            continue;
+        }
+        match lookup(context, path) {
+            Some(Value::String(s)) => rendered.push_str(s),
+            Some(Value::Null) | None => {}
+            Some(value) => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Context of a run started by a network event
+#[must_use]
+pub fn event_context(reason: &str, event: &NetworkEvent) -> Value {
+    let (ieee_address, endpoint, value) = match event {
+        NetworkEvent::DeviceStateChanged {
+            ieee_address,
+            endpoint,
+            state_on,
+        } => (ieee_address, Some(endpoint), json!(state_on)),
+        NetworkEvent::ButtonEvent {
+            ieee_address,
+            endpoint,
+            action,
+        } => (ieee_address, Some(endpoint), json!(action)),
+        NetworkEvent::OccupancyChanged {
+            ieee_address,
+            endpoint,
+            occupied,
+        } => (ieee_address, Some(endpoint), json!(occupied)),
+        NetworkEvent::SensorValue {
+            ieee_address,
+            endpoint,
+            value,
+            ..
+        }
+        | NetworkEvent::PowerMeasurement {
+            ieee_address,
+            endpoint,
+            value,
+            ..
+        } => (ieee_address, Some(endpoint), json!(value)),
+        NetworkEvent::AvailabilityChanged {
+            ieee_address,
+            available,
+        } => (ieee_address, None, json!(available)),
+        _ => return json!({ "trigger": { "reason": reason } }),
+    };
+    json!({
+        "trigger": {
+            "reason": reason,
+            "device": format_ieee(*ieee_address),
+            "endpoint": endpoint,
+            "value": value,
+        }
+    })
+}
+
+fn lookup<'a>(context: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(context, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_trigger_body() {
+        let context = json!({
+            "trigger": {
+                "reason": "webhook",
+                "body": { "visitor": "Sam", "count": 2, "tags": ["front", "door"] }
+            }
+        });
+        assert_eq!(
+            render(
+                "{{ trigger.body.visitor }} rang the {{trigger.body.tags.0}} bell ({{trigger.body.count}})",
+                &context
+            ),
+            "Sam rang the front bell (2)"
+        );
+        assert_eq!(
+            render("missing: [{{trigger.body.nope}}]", &context),
+            "missing: []"
+        );
+        assert_eq!(render("unclosed {{trigger", &context), "unclosed {{trigger");
+    }
+
+    #[test]
+    fn test_render_device_event() {
+        let context = event_context(
+            "sensor_value",
+            &NetworkEvent::SensorValue {
+                ieee_address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+                endpoint: 1,
+                kind: zigbee_core::SensorKind::Temperature,
+                value: 17.5,
+            },
+        );
+        assert_eq!(
+            render(
+                "{{trigger.device}}/{{trigger.endpoint}} reads {{trigger.value}}",
+                &context
+            ),
+            "08:07:06:05:04:03:02:01/1 reads 17.5"
+        );
+        let now = render("{{now}}", &context);
+        assert!(chrono::NaiveDateTime::parse_from_str(&now, "%Y-%m-%d %H:%M:%S").is_ok());
+    }
+}
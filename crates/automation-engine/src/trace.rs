@@ -0,0 +1,202 @@
+//! Traces of automation runs
+//!
+//! Where the run history says *that* a run stopped, its trace says why: the
+//! trigger context it ran with, every condition checked with the value it
+//! saw (device state, sensor reading, time of day...), and every action
+//! including nested ones, with timings and what it did (command sent,
+//! branch taken, passes of a loop). The newest
+//! [`MAX_TRACES_PER_AUTOMATION`] runs of each automation keep their trace,
+//! with up to [`MAX_TRACE_ACTIONS`] actions each.
+
+use crate::error::AutomationError;
+use crate::model::Action;
+use crate::run_history::ActionStatus;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::time::Instant;
+
+/// Runs of each automation that keep their trace
+pub const MAX_TRACES_PER_AUTOMATION: usize = 10;
+
+/// Actions traced per run; loops can run many more
+pub const MAX_TRACE_ACTIONS: usize = 500;
+
+/// What happened during one run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunTrace {
+    /// The run's trigger context (see [`crate::template`])
+    pub trigger: Value,
+    /// Conditions in the order they were checked; checking stops at the
+    /// first one not met
+    pub conditions: Vec<ConditionTrace>,
+    /// Actions in the order they started
+    pub actions: Vec<ActionTrace>,
+    /// Whether the run had more than [`MAX_TRACE_ACTIONS`] actions and the
+    /// rest were left out
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// One condition check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionTrace {
+    /// Position in the conditions, e.g. `0` or `0.conditions.1`
+    pub path: String,
+    /// Condition type (`time_range`, `device_state`...)
+    pub condition: String,
+    pub met: bool,
+    /// The value the condition looked at, where there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One action of a run; actions in loops appear once per pass
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionTrace {
+    /// Position in the actions, e.g. `2` or `2.then_actions.0`
+    pub path: String,
+    /// Action type (`device_control`, `delay`...)
+    pub action: String,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub status: ActionStatus,
+    /// What it did
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Collects the action traces of a run as it goes
+///
+/// Entries are added when an action starts, so actions still going when
+/// the run is cancelled end up in the trace as cancelled. Actions after the
+/// first [`MAX_TRACE_ACTIONS`] aren't traced.
+#[derive(Default)]
+pub struct ActionTracer {
+    entries: Mutex<Vec<(ActionTrace, Option<Instant>)>>,
+    truncated: AtomicBool,
+}
+
+impl ActionTracer {
+    /// Note that an action started, returning its entry, or `None` if the
+    /// trace is full
+    pub(crate) fn start(&self, path: String, action: &Action) -> Option<usize> {
+        let mut entries = self.lock();
+        if entries.len() >= MAX_TRACE_ACTIONS {
+            self.truncated.store(true, Ordering::Relaxed);
+            return None;
+        }
+        entries.push((
+            ActionTrace {
+                path,
+                action: type_name(action),
+                started_at: Utc::now(),
+                duration_ms: 0,
+                status: ActionStatus::Cancelled,
+                details: Vec::new(),
+                error: None,
+            },
+            Some(Instant::now()),
+        ));
+        Some(entries.len() - 1)
+    }
+
+    /// Add what an action did to its entry
+    pub(crate) fn note(&self, entry: Option<usize>, detail: String) {
+        let Some(entry) = entry else {
+            return;
+        };
+        if let Some((trace, _)) = self.lock().get_mut(entry) {
+            trace.details.push(detail);
+        }
+    }
+
+    /// Note how an action ended
+    pub(crate) fn finish(&self, entry: Option<usize>, result: &Result<(), AutomationError>) {
+        let Some(entry) = entry else {
+            return;
+        };
+        if let Some((trace, started)) = self.lock().get_mut(entry) {
+            trace.duration_ms = elapsed_ms(started.take());
+            trace.status = match result {
+                Ok(()) => ActionStatus::Succeeded,
+                Err(AutomationError::Cancelled(_)) => ActionStatus::Cancelled,
+                Err(_) => ActionStatus::Failed,
+            };
+            trace.error = result.as_ref().err().map(ToString::to_string);
+        }
+    }
+
+    /// Whether actions were left out of the trace
+    #[must_use]
+    pub fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// The traces, with actions that never finished as cancelled
+    #[must_use]
+    pub fn into_actions(self) -> Vec<ActionTrace> {
+        self.entries
+            .into_inner()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .into_iter()
+            .map(|(mut trace, started)| {
+                if started.is_some() {
+                    trace.duration_ms = elapsed_ms(started);
+                }
+                trace
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(ActionTrace, Option<Instant>)>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn elapsed_ms(started: Option<Instant>) -> u64 {
+    started.map_or(0, |started| {
+        u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+    })
+}
+
+/// The `type` an action or condition is serialized with
+pub(crate) fn type_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.get("type")?.as_str().map(ToString::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_is_capped() {
+        let tracer = ActionTracer::default();
+        let action = Action::Delay { seconds: 1 };
+        for i in 0..MAX_TRACE_ACTIONS {
+            let entry = tracer.start(i.to_string(), &action);
+            tracer.finish(entry, &Ok(()));
+        }
+        assert!(!tracer.truncated());
+
+        let entry = tracer.start("full".to_string(), &action);
+        assert_eq!(entry, None);
+        tracer.note(entry, "ignored".to_string());
+        tracer.finish(entry, &Ok(()));
+        assert!(tracer.truncated());
+        let actions = tracer.into_actions();
+        assert_eq!(actions.len(), MAX_TRACE_ACTIONS);
+        assert!(actions.iter().all(|a| a.status == ActionStatus::Succeeded));
+    }
+}
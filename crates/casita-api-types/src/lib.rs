@@ -0,0 +1,129 @@
+//! Types shared by the Casita Assistant server and its clients
+//!
+//! The server serializes these and `casita-client` deserializes the same
+//! types, so the two can't drift apart.
+
+use serde::{Deserialize, Serialize};
+use zigbee_core::{ButtonAction, OtaStatus, SensorKind};
+
+/// Server and adapter firmware versions (`GET /api/v1/system/info`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    pub name: String,
+    pub version: String,
+    pub firmware: Option<String>,
+}
+
+/// Adapter firmware update progress
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareProgress {
+    /// `entering_bootloader`, `writing`, `restarting`, `done` or `failed`
+    pub stage: String,
+    pub bytes_written: usize,
+    pub total_bytes: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Event pushed to WebSocket clients (`/ws`)
+///
+/// Automation events are only sent by servers built with automations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsEvent {
+    Connected,
+    DeviceJoined {
+        ieee_address: String,
+    },
+    DeviceLeft {
+        ieee_address: String,
+    },
+    DeviceUpdated {
+        ieee_address: String,
+    },
+    NetworkStateChanged {
+        connected: bool,
+    },
+    AdapterDisconnected,
+    PermitJoinChanged {
+        permit_join: bool,
+        duration: u8,
+    },
+    // Device state events
+    DeviceStateChanged {
+        ieee_address: String,
+        endpoint: u8,
+        state_on: bool,
+    },
+    ButtonEvent {
+        ieee_address: String,
+        endpoint: u8,
+        action: ButtonAction,
+    },
+    OccupancyChanged {
+        ieee_address: String,
+        endpoint: u8,
+        occupied: bool,
+    },
+    SensorValue {
+        ieee_address: String,
+        endpoint: u8,
+        kind: SensorKind,
+        value: f64,
+        #[serde(default)]
+        unit: String,
+    },
+    PowerMeasurement {
+        ieee_address: String,
+        endpoint: u8,
+        kind: SensorKind,
+        value: f64,
+        #[serde(default)]
+        unit: String,
+    },
+    IasAlarm {
+        ieee_address: String,
+        zone_status: u16,
+        alarm: bool,
+    },
+    ChannelCongestion {
+        channel: u8,
+        congested: bool,
+        failure_rate: f64,
+    },
+    AvailabilityChanged {
+        ieee_address: String,
+        available: bool,
+    },
+    OtaProgress {
+        ieee_address: String,
+        status: OtaStatus,
+        percent: u8,
+    },
+    FirmwareProgress(FirmwareProgress),
+    // Automation events
+    AutomationTriggered {
+        automation_id: String,
+        trigger_reason: String,
+    },
+    AutomationActionExecuted {
+        automation_id: String,
+        action_index: usize,
+    },
+    AutomationFailed {
+        automation_id: String,
+        error: String,
+    },
+    AutomationCreated {
+        automation_id: String,
+    },
+    AutomationUpdated {
+        automation_id: String,
+    },
+    AutomationDeleted {
+        automation_id: String,
+    },
+    /// Event type this version doesn't know; never sent
+    #[serde(other)]
+    Unknown,
+}
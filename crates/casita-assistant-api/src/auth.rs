@@ -0,0 +1,674 @@
+//! Access control
+//!
+//! Two roles exist: the owner and guests. The owner authenticates with the
+//! `API_TOKEN` env var as a bearer token (when unset, unauthenticated requests
+//! are treated as the owner, as before). Guests get time-limited tokens scoped
+//! to a set of devices, created via `POST /api/v1/auth/guests`; they may only
+//! read and switch those devices. Guest management and every guest request are
+//! recorded in `audit.log`.
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Write};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::{ApiResponse, AppState};
+
+/// Prefix that marks a bearer token as a guest token
+const GUEST_TOKEN_PREFIX: &str = "casita_guest_";
+/// Default guest token lifetime
+const DEFAULT_EXPIRY_HOURS: u64 = 48;
+/// Longest guest token lifetime
+const MAX_EXPIRY_HOURS: u64 = 90 * 24;
+
+/// A guest token (the secret itself is only stored hashed)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestToken {
+    pub id: String,
+    pub name: String,
+    /// IEEE addresses of the devices the guest may use
+    pub devices: Vec<String>,
+    #[serde(skip)]
+    token_hash: String,
+    /// Unix seconds
+    pub created_at: u64,
+    /// Unix seconds
+    pub expires_at: u64,
+}
+
+impl GuestToken {
+    fn allows_device(&self, ieee: &str) -> bool {
+        self.devices.iter().any(|d| d.eq_ignore_ascii_case(ieee))
+    }
+}
+
+/// Stored form of a guest token
+#[derive(Serialize, Deserialize)]
+struct StoredGuest {
+    #[serde(flatten)]
+    guest: GuestToken,
+    token_hash: String,
+}
+
+/// An audit log entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix seconds
+    pub timestamp: u64,
+    /// `owner` or `guest:<id>`
+    pub actor: String,
+    pub action: String,
+    pub detail: String,
+}
+
+/// Caller identity attached to each API request
+#[derive(Debug, Clone)]
+pub enum Principal {
+    Owner,
+    Guest(GuestToken),
+}
+
+pub struct AuthManager {
+    owner_token: Option<String>,
+    guests: DashMap<String, GuestToken>,
+    data_path: PathBuf,
+    audit_path: PathBuf,
+    audit_lock: Mutex<()>,
+}
+
+impl AuthManager {
+    pub fn new(data_dir: &FsPath) -> Self {
+        Self {
+            owner_token: std::env::var("API_TOKEN").ok().filter(|t| !t.is_empty()),
+            guests: DashMap::new(),
+            data_path: data_dir.join("guests.json"),
+            audit_path: data_dir.join("audit.log"),
+            audit_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn load(&self) -> anyhow::Result<()> {
+        if self.data_path.exists() {
+            let content = std::fs::read_to_string(&self.data_path)?;
+            let stored: Vec<StoredGuest> = serde_json::from_str(&content)?;
+            let now = unix_now();
+            for StoredGuest {
+                mut guest,
+                token_hash,
+            } in stored
+            {
+                if guest.expires_at > now {
+                    guest.token_hash = token_hash;
+                    self.guests.insert(guest.id.clone(), guest);
+                }
+            }
+            tracing::info!(
+                "Loaded {} guest tokens from {:?}",
+                self.guests.len(),
+                self.data_path
+            );
+        }
+        if self.owner_token.is_none() && !self.guests.is_empty() {
+            tracing::warn!("Guest tokens exist but API_TOKEN is not set - the API is open to anyone on the network");
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let stored: Vec<StoredGuest> = self
+            .guests
+            .iter()
+            .map(|r| StoredGuest {
+                guest: r.value().clone(),
+                token_hash: r.value().token_hash.clone(),
+            })
+            .collect();
+        let content = serde_json::to_string_pretty(&stored)?;
+        if let Some(parent) = self.data_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.data_path, content)?;
+        Ok(())
+    }
+
+    /// Create a guest token; returns the guest and the secret (shown once)
+    pub fn create_guest(
+        &self,
+        name: String,
+        devices: Vec<String>,
+        expires_in_hours: u64,
+    ) -> anyhow::Result<(GuestToken, String)> {
+        let secret = format!(
+            "{GUEST_TOKEN_PREFIX}{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let now = unix_now();
+        let guest = GuestToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            devices,
+            token_hash: hash_token(&secret),
+            created_at: now,
+            expires_at: now + expires_in_hours.clamp(1, MAX_EXPIRY_HOURS) * 3600,
+        };
+        self.guests.insert(guest.id.clone(), guest.clone());
+        self.save()?;
+        Ok((guest, secret))
+    }
+
+    pub fn revoke_guest(&self, id: &str) -> anyhow::Result<Option<GuestToken>> {
+        let removed = self.guests.remove(id).map(|(_, g)| g);
+        if removed.is_some() {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Active (unexpired) guest tokens
+    pub fn list_guests(&self) -> Vec<GuestToken> {
+        let now = unix_now();
+        self.guests
+            .iter()
+            .filter(|r| r.expires_at > now)
+            .map(|r| r.value().clone())
+            .collect()
+    }
+
+    /// Resolve the caller from a bearer token (`None` if the token is invalid or expired)
+    fn authenticate(&self, bearer: Option<&str>) -> Option<Principal> {
+        match bearer {
+            Some(token) if token.starts_with(GUEST_TOKEN_PREFIX) => {
+                let hash = hash_token(token);
+                let now = unix_now();
+                self.guests
+                    .iter()
+                    .find(|r| r.token_hash == hash && r.expires_at > now)
+                    .map(|r| Principal::Guest(r.value().clone()))
+            }
+            Some(token) => match &self.owner_token {
+                Some(owner) => tokens_match(owner, token).then_some(Principal::Owner),
+                None => Some(Principal::Owner),
+            },
+            None => self.owner_token.is_none().then_some(Principal::Owner),
+        }
+    }
+
+    /// Append an entry to the audit log
+    pub fn audit(&self, actor: &str, action: &str, detail: impl Into<String>) {
+        let entry = AuditEntry {
+            timestamp: unix_now(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            detail: detail.into(),
+        };
+        let _guard = self
+            .audit_lock
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(&entry).map_err(std::io::Error::other)?;
+                writeln!(file, "{line}")
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to write audit log: {}", e);
+        }
+    }
+
+    /// Most recent audit entries, newest first
+    pub fn recent_audit(&self, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+        let file = match std::fs::File::open(&self.audit_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries: Vec<AuditEntry> = std::io::BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect();
+        entries.reverse();
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    /// Drop audit entries older than `cutoff`, then the oldest until the
+    /// log fits in `max_bytes`; returns entries deleted and bytes freed
+    pub fn prune_audit(
+        &self,
+        cutoff: Option<SystemTime>,
+        max_bytes: Option<u64>,
+    ) -> anyhow::Result<(u64, u64)> {
+        let _guard = self
+            .audit_lock
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let content = match std::fs::read_to_string(&self.audit_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+            Err(e) => return Err(e.into()),
+        };
+        let cutoff = cutoff.map(|t| {
+            t.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+        let lines: Vec<&str> = content.lines().collect();
+        // Lines that don't parse are kept with the entries around them
+        let mut start = lines
+            .iter()
+            .position(|line| {
+                serde_json::from_str::<AuditEntry>(line)
+                    .map_or(true, |e| cutoff.is_none_or(|cutoff| e.timestamp >= cutoff))
+            })
+            .unwrap_or(lines.len());
+        let mut size: u64 = lines[start..].iter().map(|l| l.len() as u64 + 1).sum();
+        if let Some(max) = max_bytes {
+            while size > max && start < lines.len() {
+                size -= lines[start].len() as u64 + 1;
+                start += 1;
+            }
+        }
+        if start == 0 {
+            return Ok((0, 0));
+        }
+
+        let mut kept = lines[start..].join("\n");
+        if !kept.is_empty() {
+            kept.push('\n');
+        }
+        let tmp_path = self.audit_path.with_extension("log.tmp");
+        std::fs::write(&tmp_path, &kept)?;
+        std::fs::rename(&tmp_path, &self.audit_path)?;
+        Ok((
+            start as u64,
+            (content.len() as u64).saturating_sub(kept.len() as u64),
+        ))
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Compare tokens in constant time, by their digests so the length of the
+/// owner token doesn't leak either
+fn tokens_match(expected: &str, given: &str) -> bool {
+    Sha256::digest(expected.as_bytes())
+        .iter()
+        .zip(Sha256::digest(given.as_bytes()).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Whether a guest may make this request
+///
+/// Guests can read and switch their devices; everything else is owner-only.
+fn guest_allowed(guest: &GuestToken, method: &Method, path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["api", "v1", "guest"]) => true,
+        (&Method::GET, ["api", "v1", "devices", ieee]) => guest.allows_device(ieee),
+        (
+            &Method::POST,
+            ["api", "v1", "devices", ieee, "endpoints", _, "toggle" | "on" | "off"],
+        ) => guest.allows_device(ieee),
+        _ => false,
+    }
+}
+
+/// A request URI for logs, with the values of `?token=` and `?secret=`
+/// hidden
+pub fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name @ ("token" | "secret"), _)) => format!("{name}=redacted"),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+/// Middleware enforcing owner/guest access on API and WebSocket routes
+pub async fn require_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    if !(path.starts_with("/api/") || path == "/ws") {
+        return next.run(request).await;
+    }
+
+    // Browsers can't set headers on WebSocket upgrades, so `?token=` is accepted too
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .or_else(|| {
+            request
+                .uri()
+                .query()?
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token="))
+        })
+        .map(ToString::to_string);
+
+    // Outside systems call webhooks with the webhook's own secret instead
+    // of a token; the handler decides who may fire them
+    if path.starts_with("/api/v1/webhooks/") {
+        if let Some(Principal::Owner) = state.auth.authenticate(bearer.as_deref()) {
+            request.extensions_mut().insert(Principal::Owner);
+        }
+        return next.run(request).await;
+    }
+
+    let Some(principal) = state.auth.authenticate(bearer.as_deref()) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error("Invalid or expired token")),
+        )
+            .into_response();
+    };
+
+    let Principal::Guest(guest) = &principal else {
+        request.extensions_mut().insert(principal);
+        return next.run(request).await;
+    };
+
+    let actor = format!("guest:{}", guest.id);
+    let method = request.method().clone();
+    if !guest_allowed(guest, &method, &path) {
+        state
+            .auth
+            .audit(&actor, "denied", format!("{method} {path}"));
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Not permitted for guest access")),
+        )
+            .into_response();
+    }
+
+    request.extensions_mut().insert(principal.clone());
+    let response = next.run(request).await;
+    state.auth.audit(
+        &actor,
+        "request",
+        format!("{method} {path} -> {}", response.status().as_u16()),
+    );
+    response
+}
+
+// =============================================================================
+// HTTP Handlers
+// =============================================================================
+
+/// Request body for creating a guest token
+#[derive(Deserialize)]
+pub struct CreateGuestRequest {
+    name: String,
+    devices: Vec<String>,
+    #[serde(default = "default_expiry_hours")]
+    expires_in_hours: u64,
+}
+
+fn default_expiry_hours() -> u64 {
+    DEFAULT_EXPIRY_HOURS
+}
+
+/// Query parameters for the audit log
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+pub async fn create_guest(
+    State(state): State<AppState>,
+    Json(req): Json<CreateGuestRequest>,
+) -> impl IntoResponse {
+    if req.devices.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("At least one device is required")),
+        );
+    }
+    if let Some(bad) = req
+        .devices
+        .iter()
+        .find(|d| crate::parse_ieee_address(d).is_err())
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!("Invalid IEEE address: {bad}"))),
+        );
+    }
+
+    match state
+        .auth
+        .create_guest(req.name, req.devices, req.expires_in_hours)
+    {
+        Ok((guest, token)) => {
+            state.auth.audit(
+                "owner",
+                "guest_created",
+                format!(
+                    "{} ({}) devices={} expires_at={}",
+                    guest.name,
+                    guest.id,
+                    guest.devices.join(","),
+                    guest.expires_at
+                ),
+            );
+            (
+                StatusCode::CREATED,
+                Json(ApiResponse::success(serde_json::json!({
+                    "guest": guest,
+                    "token": token,
+                }))),
+            )
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+pub async fn list_guests(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.auth.list_guests()))
+}
+
+pub async fn revoke_guest(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.auth.revoke_guest(&id) {
+        Ok(Some(guest)) => {
+            state.auth.audit(
+                "owner",
+                "guest_revoked",
+                format!("{} ({})", guest.name, guest.id),
+            );
+            (StatusCode::OK, Json(ApiResponse::success(guest)))
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Guest not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+pub async fn audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    match state.auth.recent_audit(query.limit) {
+        Ok(entries) => (StatusCode::OK, Json(ApiResponse::success(entries))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// The calling guest's token info and devices
+pub async fn guest_info(
+    State(state): State<AppState>,
+    axum::Extension(principal): axum::Extension<Principal>,
+) -> impl IntoResponse {
+    let Principal::Guest(guest) = principal else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Not a guest token")),
+        );
+    };
+    let devices: Vec<_> = guest
+        .devices
+        .iter()
+        .filter_map(|ieee| {
+            let ieee = crate::parse_ieee_address(ieee).ok()?;
+            state.network.as_ref()?.get_device(&ieee)
+        })
+        .collect();
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(serde_json::json!({
+            "guest": guest,
+            "devices": devices,
+        }))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guest(devices: &[&str]) -> GuestToken {
+        GuestToken {
+            id: "g".to_string(),
+            name: "guest".to_string(),
+            devices: devices.iter().map(ToString::to_string).collect(),
+            token_hash: String::new(),
+            created_at: 0,
+            expires_at: u64::MAX,
+        }
+    }
+
+    #[test]
+    fn test_guest_allowed() {
+        let g = guest(&["00:11:22:33:44:55:66:77"]);
+        assert!(guest_allowed(
+            &g,
+            &Method::POST,
+            "/api/v1/devices/00:11:22:33:44:55:66:77/endpoints/1/toggle"
+        ));
+        assert!(guest_allowed(
+            &g,
+            &Method::GET,
+            "/api/v1/devices/00:11:22:33:44:55:66:77"
+        ));
+        assert!(!guest_allowed(
+            &g,
+            &Method::PUT,
+            "/api/v1/devices/00:11:22:33:44:55:66:77"
+        ));
+        assert!(!guest_allowed(
+            &g,
+            &Method::POST,
+            "/api/v1/devices/aa:11:22:33:44:55:66:77/endpoints/1/on"
+        ));
+        assert!(!guest_allowed(&g, &Method::GET, "/api/v1/devices"));
+        assert!(!guest_allowed(&g, &Method::POST, "/api/v1/auth/guests"));
+    }
+
+    #[test]
+    fn test_redacted_uri() {
+        let uri: Uri = "/api/v1/webhooks/door?secret=s3cret&x=1&token=t0ken"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            redacted_uri(&uri),
+            "/api/v1/webhooks/door?secret=redacted&x=1&token=redacted"
+        );
+        let uri: Uri = "/api/v1/devices".parse().unwrap();
+        assert_eq!(redacted_uri(&uri), "/api/v1/devices");
+    }
+
+    #[test]
+    fn test_prune_audit() {
+        let data_dir = std::env::temp_dir().join(format!("casita-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let auth = AuthManager::new(&data_dir);
+        let line = |timestamp| {
+            serde_json::to_string(&AuditEntry {
+                timestamp,
+                actor: "guest:g".to_string(),
+                action: "request".to_string(),
+                detail: "GET /api/v1/guest -> 200".to_string(),
+            })
+            .unwrap()
+        };
+        let now = unix_now();
+        let lines = [line(now - 3 * 86_400), line(now - 60), line(now)];
+        std::fs::write(&auth.audit_path, lines.join("\n") + "\n").unwrap();
+
+        let cutoff = SystemTime::now() - std::time::Duration::from_secs(86_400);
+        let (deleted, freed) = auth.prune_audit(Some(cutoff), None).unwrap();
+        assert_eq!(deleted, 1);
+        assert_eq!(freed, lines[0].len() as u64 + 1);
+        assert_eq!(auth.recent_audit(10).unwrap().len(), 2);
+
+        // Only the newest entry fits
+        let (deleted, _) = auth
+            .prune_audit(None, Some(lines[2].len() as u64 + 1))
+            .unwrap();
+        assert_eq!(deleted, 1);
+        assert_eq!(auth.recent_audit(10).unwrap()[0].timestamp, now);
+        assert_eq!(auth.prune_audit(Some(cutoff), None).unwrap(), (0, 0));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+    }
+}
@@ -0,0 +1,603 @@
+//! Automation HTTP handlers
+
+use automation_engine::folder::{CreateFolderRequest, UpdateFolderRequest};
+use automation_engine::{
+    Automation, AutomationError, BulkRequest, CreateAutomationRequest, ImportConflict,
+    SnoozeRequest, UpdateAutomationRequest,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+
+use crate::auth::Principal;
+use crate::{revision, ApiResponse, AppState};
+
+/// Query parameters for listing automations
+#[derive(Debug, serde::Deserialize)]
+pub struct ListQuery {
+    /// Only automations with this tag
+    pub tag: Option<String>,
+}
+
+/// List all automations (`?tag=` to filter)
+pub async fn list_automations(
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> impl IntoResponse {
+    let mut automations = state.automations.list();
+    if let Some(tag) = &query.tag {
+        automations.retain(|automation| automation.has_tag(tag));
+    }
+    Json(ApiResponse::success(automations))
+}
+
+/// Enable, disable or trigger several automations (by ID and/or tag)
+pub async fn bulk_automations(
+    State(state): State<AppState>,
+    Json(request): Json<BulkRequest>,
+) -> impl IntoResponse {
+    match state.automations.bulk(request).await {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => {
+            let status = match e {
+                AutomationError::NotFound(_) => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Automations file for export/import
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AutomationsFile {
+    automations: Vec<Automation>,
+}
+
+/// Export all automations as JSON, one field per line so it diffs well
+pub async fn export_automations(State(state): State<AppState>) -> Response {
+    let file = AutomationsFile {
+        automations: state.automations.export(),
+    };
+    match serde_json::to_string_pretty(&file) {
+        Ok(json) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "application/json"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"automations.json\"",
+                ),
+            ],
+            json,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Query parameters for importing automations
+#[derive(Debug, serde::Deserialize)]
+pub struct ImportQuery {
+    /// `skip` (default), `replace` or `duplicate` automations whose ID is
+    /// already taken
+    #[serde(default)]
+    pub on_conflict: ImportConflict,
+}
+
+/// Import automations from an export
+pub async fn import_automations(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let file: AutomationsFile = match serde_json::from_slice(&body) {
+        Ok(file) => file,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(format!("Invalid automations file: {e}"))),
+            )
+        }
+    };
+    match state
+        .automations
+        .import(file.automations, query.on_conflict)
+        .await
+    {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Get a specific automation
+pub async fn get_automation(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.automations.get(&id) {
+        Some(automation) => revision::tagged(
+            StatusCode::OK,
+            automation.revision,
+            ApiResponse::success(automation),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Automation not found")),
+        )
+            .into_response(),
+    }
+}
+
+/// Create a new automation
+pub async fn create_automation(
+    State(state): State<AppState>,
+    Json(request): Json<CreateAutomationRequest>,
+) -> impl IntoResponse {
+    match state.automations.create(request).await {
+        Ok(automation) => (StatusCode::CREATED, Json(ApiResponse::success(automation))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Update an automation (honours `If-Match`)
+pub async fn update_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateAutomationRequest>,
+) -> Response {
+    let expected = match revision::if_match(&headers) {
+        Ok(expected) => expected,
+        Err(response) => return response.into_response(),
+    };
+    match state
+        .automations
+        .update_checked(&id, request, expected)
+        .await
+    {
+        Ok(automation) => revision::tagged(
+            StatusCode::OK,
+            automation.revision,
+            ApiResponse::success(automation),
+        ),
+        Err(e @ AutomationError::RevisionMismatch { current, .. }) => {
+            revision::conflict(state.automations.get(&id), current, e.to_string())
+        }
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            (status, Json(ApiResponse::error(e.to_string()))).into_response()
+        }
+    }
+}
+
+/// Delete an automation
+pub async fn delete_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.delete(&id).await {
+        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Manually trigger an automation
+pub async fn trigger_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.trigger(&id).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "status": "triggered",
+                "automation_id": id
+            }))),
+        ),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else if e.to_string().contains("disabled") {
+                StatusCode::CONFLICT
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Query parameters of a webhook call
+#[derive(serde::Deserialize)]
+pub struct WebhookQuery {
+    secret: Option<String>,
+}
+
+/// Fire the automations listening on a webhook
+///
+/// The JSON body (if any) is passed to their actions as `trigger.body`.
+/// Callers without an owner token authenticate with the webhook's secret,
+/// sent in `X-Webhook-Secret` or `?secret=`. Every call is audited.
+pub async fn fire_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<WebhookQuery>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let body = if body.iter().all(u8::is_ascii_whitespace) {
+        serde_json::Value::Null
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error(format!("Invalid JSON body: {e}"))),
+                );
+            }
+        }
+    };
+    let secret = headers
+        .get("x-webhook-secret")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string)
+        .or(query.secret);
+    let authenticated = matches!(principal, Some(Extension(Principal::Owner)));
+    let actor = if authenticated { "owner" } else { "webhook" };
+
+    let result = state
+        .automations
+        .fire_webhook(&id, secret.as_deref(), authenticated, body);
+    match &result {
+        Ok(started) => state.auth.audit(
+            actor,
+            "webhook_fired",
+            format!("{id}: {started} automation(s)"),
+        ),
+        Err(_) => state.auth.audit(actor, "webhook_refused", id.as_str()),
+    }
+    match result {
+        Ok(started) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "status": "triggered",
+                "webhook_id": id,
+                "automations": started
+            }))),
+        ),
+        Err(e) => {
+            let status = match e {
+                AutomationError::WebhookUnauthorized(_) => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Recent runs of an automation, newest first
+pub async fn automation_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.run_history(&id) {
+        Ok(runs) => (StatusCode::OK, Json(ApiResponse::success(runs))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// A recent run of an automation with its trace: conditions with the
+/// values they saw, and each action's timing and result
+pub async fn automation_trace(
+    State(state): State<AppState>,
+    Path((id, run_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    match state.automations.run_trace(&id, &run_id) {
+        Ok(Some(run)) => (StatusCode::OK, Json(ApiResponse::success(run))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Trace not found: {run_id}"))),
+        ),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// List automation runs in progress
+pub async fn running_automations(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.automations.running()))
+}
+
+/// Abort the runs of an automation
+pub async fn cancel_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.cancel(&id) {
+        Ok(cancelled) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "status": "cancelled",
+                "automation_id": id,
+                "runs": cancelled
+            }))),
+        ),
+        Err(e) => {
+            let status = match e {
+                AutomationError::NotFound(_) => StatusCode::NOT_FOUND,
+                AutomationError::NotRunning(_) => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Enable an automation
+pub async fn enable_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.enable(&id).await {
+        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Disable an automation
+pub async fn disable_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.disable(&id).await {
+        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Ignore an automation's triggers until a time or for some minutes
+pub async fn snooze_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<SnoozeRequest>,
+) -> impl IntoResponse {
+    match state.automations.snooze(&id, &request).await {
+        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
+        Err(e @ AutomationError::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e @ AutomationError::InvalidField { .. }) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// End an automation's snooze early
+pub async fn unsnooze_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.unsnooze(&id).await {
+        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Check an automation against the current state without running it
+pub async fn test_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.dry_run(&id) {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e @ AutomationError::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// List automation folders in display order
+pub async fn list_folders(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.automations.folders().list()))
+}
+
+/// Create an automation folder
+pub async fn create_folder(
+    State(state): State<AppState>,
+    Json(request): Json<CreateFolderRequest>,
+) -> impl IntoResponse {
+    match state.automations.folders().create(request).await {
+        Ok(folder) => (StatusCode::CREATED, Json(ApiResponse::success(folder))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Rename or reorder an automation folder
+pub async fn update_folder(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<UpdateFolderRequest>,
+) -> impl IntoResponse {
+    match state.automations.folders().update(&id, request).await {
+        Ok(folder) => (StatusCode::OK, Json(ApiResponse::success(folder))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Delete an automation folder (its automations are kept, unfiled)
+pub async fn delete_folder(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.delete_folder(&id).await {
+        Ok(folder) => (StatusCode::OK, Json(ApiResponse::success(folder))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Request body for a backtest (RFC 3339 timestamps)
+#[cfg(feature = "history")]
+#[derive(serde::Deserialize)]
+pub struct BacktestRequest {
+    /// Start of the window (default: 7 days before `to`)
+    #[serde(default)]
+    from: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// End of the window (default: now)
+    #[serde(default)]
+    to: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+/// Most history events replayed by one backtest
+#[cfg(feature = "history")]
+const MAX_BACKTEST_EVENTS: usize = 200_000;
+
+/// Replay recorded history to see when an automation would have fired
+#[cfg(feature = "history")]
+pub async fn backtest_automation(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<BacktestRequest>,
+) -> impl IntoResponse {
+    use chrono::{TimeZone, Utc};
+
+    let Some(history) = state.history.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("History is not available")),
+        );
+    };
+    let to = request.to.map_or_else(Utc::now, |t| t.with_timezone(&Utc));
+    let from = request
+        .from
+        .map_or_else(|| to - chrono::Duration::days(7), |t| t.with_timezone(&Utc));
+    if from >= to {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("`from` must be before `to`")),
+        );
+    }
+
+    let (from_ms, to_ms) = (from.timestamp_millis(), to.timestamp_millis());
+    // One more than is replayed, to tell whether any were left out
+    let stored = tokio::task::spawn_blocking(move || {
+        history.events_between(from_ms, to_ms, MAX_BACKTEST_EVENTS + 1)
+    })
+    .await;
+    let mut stored = match stored {
+        Ok(Ok(stored)) => stored,
+        Ok(Err(e)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!("Failed to read history: {e}"))),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(e.to_string())),
+            );
+        }
+    };
+    let events_truncated = stored.len() > MAX_BACKTEST_EVENTS;
+    stored.truncate(MAX_BACKTEST_EVENTS);
+    let events = stored.iter().filter_map(|record| {
+        Some(automation_engine::HistoricalEvent {
+            timestamp: Utc.timestamp_millis_opt(record.timestamp).single()?,
+            event: record.to_network_event()?,
+        })
+    });
+
+    match state.automations.backtest(&id, from, to, events) {
+        Ok(mut report) => {
+            report.events_truncated = events_truncated;
+            (StatusCode::OK, Json(ApiResponse::success(report)))
+        }
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
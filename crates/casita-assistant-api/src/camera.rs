@@ -13,6 +13,8 @@ use tokio::sync::broadcast;
 use uuid::Uuid;
 
 use crate::rtsp::{Fmp4Writer, RtspClient};
+use crate::secrets::{self, SecretKey};
+use crate::watchdog::{Subsystem, Watchdog};
 use crate::{ApiResponse, AppState};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -80,11 +82,28 @@ impl CameraManager {
         }
     }
 
+    /// Key for stored passwords (see [`crate::secrets`])
+    fn secret_key(&self) -> anyhow::Result<Option<SecretKey>> {
+        let data_dir = self.data_path.parent().unwrap_or(std::path::Path::new("."));
+        Ok(SecretKey::load(data_dir)?)
+    }
+
     pub fn load(&self) -> anyhow::Result<()> {
         if self.data_path.exists() {
             let content = std::fs::read_to_string(&self.data_path)?;
             let cameras: Vec<Camera> = serde_json::from_str(&content)?;
-            for camera in cameras {
+            let key = self.secret_key()?;
+            for mut camera in cameras {
+                if let Some(password) = camera
+                    .password
+                    .as_deref()
+                    .filter(|p| secrets::is_encrypted(p))
+                {
+                    let Some(plain) = key.as_ref().and_then(|key| key.decrypt(password)) else {
+                        anyhow::bail!("Can't decrypt the password of camera {}", camera.name);
+                    };
+                    camera.password = Some(plain);
+                }
                 self.cameras.insert(camera.id.clone(), camera);
             }
             tracing::info!(
@@ -97,7 +116,12 @@ impl CameraManager {
     }
 
     pub fn save(&self) -> anyhow::Result<()> {
-        let cameras: Vec<Camera> = self.cameras.iter().map(|r| r.value().clone()).collect();
+        let mut cameras: Vec<Camera> = self.cameras.iter().map(|r| r.value().clone()).collect();
+        if let Some(key) = self.secret_key()? {
+            for camera in &mut cameras {
+                camera.password = camera.password.as_deref().map(|p| key.encrypt(p));
+            }
+        }
         let content = serde_json::to_string_pretty(&cameras)?;
 
         // Ensure parent directory exists
@@ -252,7 +276,7 @@ pub async fn stream_proxy(
     let format = query.format.as_deref().unwrap_or("auto");
 
     match camera.stream_type {
-        StreamType::Mjpeg => stream_mjpeg(&camera).await,
+        StreamType::Mjpeg => stream_mjpeg(&camera, &state.watchdog).await,
         StreamType::Rtsp => {
             // For RTSP, default to fMP4 for efficient H.264 passthrough
             match format {
@@ -265,7 +289,7 @@ pub async fn stream_proxy(
                     )
                         .into_response()
                 }
-                _ => stream_rtsp_fmp4(&camera),
+                _ => stream_rtsp_fmp4(&camera, state.watchdog.clone()),
             }
         }
         StreamType::WebRtc => (
@@ -276,7 +300,7 @@ pub async fn stream_proxy(
     }
 }
 
-async fn stream_mjpeg(camera: &Camera) -> axum::response::Response {
+async fn stream_mjpeg(camera: &Camera, watchdog: &Watchdog) -> axum::response::Response {
     tracing::info!(
         "Proxying MJPEG stream from {} for camera {}",
         camera.stream_url,
@@ -288,6 +312,10 @@ async fn stream_mjpeg(camera: &Camera) -> axum::response::Response {
         Ok(resp) => resp,
         Err(e) => {
             tracing::error!("Failed to connect to camera: {}", e);
+            watchdog.record(
+                Subsystem::Camera,
+                format!("Failed to connect to camera {}: {e}", camera.name),
+            );
             return (
                 StatusCode::BAD_GATEWAY,
                 format!("Failed to connect to camera: {e}"),
@@ -297,6 +325,10 @@ async fn stream_mjpeg(camera: &Camera) -> axum::response::Response {
     };
 
     if !response.status().is_success() {
+        watchdog.record(
+            Subsystem::Camera,
+            format!("Camera {} returned {}", camera.name, response.status()),
+        );
         return (
             StatusCode::BAD_GATEWAY,
             format!("Camera returned error: {}", response.status()),
@@ -320,7 +352,7 @@ async fn stream_mjpeg(camera: &Camera) -> axum::response::Response {
     (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], body).into_response()
 }
 
-fn stream_rtsp_fmp4(camera: &Camera) -> axum::response::Response {
+fn stream_rtsp_fmp4(camera: &Camera, watchdog: Arc<Watchdog>) -> axum::response::Response {
     // Parse RTSP URL (without credentials - retina doesn't support embedded credentials)
     let rtsp_url = match url::Url::parse(&camera.stream_url) {
         Ok(url) => url,
@@ -346,6 +378,10 @@ fn stream_rtsp_fmp4(camera: &Camera) -> axum::response::Response {
             Ok(result) => result,
             Err(e) => {
                 tracing::error!("Failed to connect to RTSP stream: {}", e);
+                watchdog.record(
+                    Subsystem::Camera,
+                    format!("Failed to connect to RTSP stream of camera {camera_name}: {e}"),
+                );
                 return;
             }
         };
@@ -394,7 +430,7 @@ fn stream_rtsp_fmp4(camera: &Camera) -> axum::response::Response {
                     frame_count += 1;
 
                     // Log first few segments and then periodically
-                    if frame_count <= 3 || frame_count % 300 == 0 {
+                    if frame_count <= 3 || frame_count.is_multiple_of(300) {
                         tracing::info!(
                             "Sending segment {} for camera {} (keyframe={}, data_len={}, segment_len={})",
                             frame_count, camera_name, frame.is_keyframe, frame.data.len(), segment.len()
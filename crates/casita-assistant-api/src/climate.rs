@@ -0,0 +1,114 @@
+//! Climate schedule HTTP handlers
+
+use automation_engine::climate::{CreateClimateScheduleRequest, UpdateClimateScheduleRequest};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{ApiResponse, AppState};
+
+/// Request body for toggling away mode
+#[derive(Deserialize)]
+pub struct AwayRequest {
+    away: bool,
+}
+
+/// List all climate schedules
+pub async fn list_schedules(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.automations.climate().list()))
+}
+
+/// Get a specific climate schedule
+pub async fn get_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.climate().get(&id) {
+        Some(schedule) => (StatusCode::OK, Json(ApiResponse::success(schedule))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Climate schedule not found")),
+        ),
+    }
+}
+
+/// Create a climate schedule
+pub async fn create_schedule(
+    State(state): State<AppState>,
+    Json(request): Json<CreateClimateScheduleRequest>,
+) -> impl IntoResponse {
+    match state.automations.climate().create(request).await {
+        Ok(schedule) => (StatusCode::CREATED, Json(ApiResponse::success(schedule))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Update a climate schedule
+pub async fn update_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<UpdateClimateScheduleRequest>,
+) -> impl IntoResponse {
+    match state.automations.climate().update(&id, request).await {
+        Ok(schedule) => (StatusCode::OK, Json(ApiResponse::success(schedule))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Delete a climate schedule
+pub async fn delete_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.climate().delete(&id).await {
+        Ok(schedule) => (StatusCode::OK, Json(ApiResponse::success(schedule))),
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Get away mode
+pub async fn get_away(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(serde_json::json!({
+        "away": state.automations.climate().is_away()
+    })))
+}
+
+/// Turn away mode on or off
+pub async fn set_away(
+    State(state): State<AppState>,
+    Json(request): Json<AwayRequest>,
+) -> impl IntoResponse {
+    match state.automations.climate().set_away(request.away).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "away": request.away
+            }))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
@@ -0,0 +1,126 @@
+//! Cross-origin request policy
+//!
+//! The embedded frontend (and the Vite dev server, through its proxy) is
+//! served from the API's own origin and needs no CORS headers, so by default
+//! no other origin may call the API from a browser. Dashboards hosted
+//! elsewhere are opted in with env vars:
+//!
+//! - `CORS_ALLOWED_ORIGINS`: comma-separated origins
+//!   (`http://dashboard.local:8080`), or `*` for any origin
+//! - `CORS_ALLOWED_METHODS`: defaults to `GET,POST,PUT,DELETE`
+//! - `CORS_ALLOWED_HEADERS`: defaults to `authorization,content-type,if-match`
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+const DEFAULT_METHODS: &str = "GET,POST,PUT,DELETE";
+const DEFAULT_HEADERS: &str = "authorization,content-type,if-match";
+
+fn split(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Build the CORS layer from the three settings (`None` means unset)
+fn build(
+    origins: Option<&str>,
+    methods: Option<&str>,
+    headers: Option<&str>,
+) -> anyhow::Result<CorsLayer> {
+    let origins = origins.unwrap_or_default();
+    if split(origins).next().is_none() {
+        return Ok(CorsLayer::new());
+    }
+
+    let allow_origin = if origins.trim() == "*" {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = split(origins)
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .map_err(|_| anyhow::anyhow!("Invalid CORS origin: {origin}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+    let methods = split(methods.unwrap_or(DEFAULT_METHODS))
+        .map(|method| {
+            Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid CORS method: {method}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let headers = split(headers.unwrap_or(DEFAULT_HEADERS))
+        .map(|name| {
+            HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid CORS header: {name}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        // Revisions for `If-Match`
+        .expose_headers([header::ETAG]))
+}
+
+/// CORS layer configured from the environment
+pub fn layer_from_env() -> anyhow::Result<CorsLayer> {
+    let var = |name| std::env::var(name).ok();
+    let origins = var("CORS_ALLOWED_ORIGINS");
+    if let Some(origins) = origins.as_deref().filter(|o| !o.trim().is_empty()) {
+        tracing::info!("Allowing cross-origin requests from {}", origins);
+    }
+    build(
+        origins.as_deref(),
+        var("CORS_ALLOWED_METHODS").as_deref(),
+        var("CORS_ALLOWED_HEADERS").as_deref(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn preflight(layer: CorsLayer, origin: &str) -> Option<HeaderValue> {
+        let app = Router::new().route("/", get(|| async {})).layer(layer);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/")
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .cloned()
+    }
+
+    #[tokio::test]
+    async fn test_cors_policy() {
+        // Nothing cross-origin by default
+        let layer = build(None, None, None).unwrap();
+        assert!(preflight(layer, "http://evil.example").await.is_none());
+
+        let layer = build(Some("http://dash.local:8080/, http://other"), None, None).unwrap();
+        assert_eq!(
+            preflight(layer.clone(), "http://dash.local:8080")
+                .await
+                .unwrap(),
+            "http://dash.local:8080"
+        );
+        assert!(preflight(layer, "http://evil.example").await.is_none());
+
+        let layer = build(Some("*"), Some("get"), None).unwrap();
+        assert_eq!(preflight(layer, "http://any").await.unwrap(), "*");
+
+        assert!(build(Some("http://a"), Some("NOT A METHOD"), None).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Debug HTTP handlers
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use deconz_protocol::capture::DEFAULT_CAPTURE_MAX_BYTES;
+use deconz_protocol::CaptureStatus;
+use serde::{Deserialize, Serialize};
+use zigbee_core::network::NetworkEvent;
+use zigbee_core::{ButtonAction, SensorKind};
+
+use crate::{ApiResponse, AppState};
+
+/// Capture file name inside `DATA_DIR/capture`
+const CAPTURE_FILE: &str = "frames.jsonl";
+
+/// Request body for toggling protocol capture
+#[derive(Deserialize)]
+pub struct CaptureRequest {
+    enabled: bool,
+    /// Rotate the file at this size (MB)
+    #[serde(default)]
+    max_size_mb: Option<u64>,
+}
+
+/// Protocol capture state
+#[derive(Serialize)]
+struct CaptureInfo {
+    active: bool,
+    path: Option<String>,
+    frames: u64,
+    bytes: u64,
+    dropped: u64,
+}
+
+impl From<CaptureStatus> for CaptureInfo {
+    fn from(status: CaptureStatus) -> Self {
+        Self {
+            active: status.active,
+            path: status.path.map(|p| p.display().to_string()),
+            frames: status.frames,
+            bytes: status.bytes,
+            dropped: status.dropped,
+        }
+    }
+}
+
+/// Get protocol capture state
+pub async fn get_capture(State(state): State<AppState>) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let status = network.transport().capture().status();
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(CaptureInfo::from(status))),
+    )
+}
+
+/// Start or stop logging every adapter frame to `DATA_DIR/capture/frames.jsonl`
+pub async fn set_capture(
+    State(state): State<AppState>,
+    Json(request): Json<CaptureRequest>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let max_bytes = match request.max_size_mb {
+        None => DEFAULT_CAPTURE_MAX_BYTES,
+        Some(mb) => match mb.checked_mul(1024 * 1024) {
+            Some(bytes) => bytes,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::error("max_size_mb is too large")),
+                );
+            }
+        },
+    };
+    let path = state.data_dir.join("capture").join(CAPTURE_FILE);
+
+    // Opening the file and waiting for queued frames block
+    let network = network.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let capture = network.transport().capture();
+        if request.enabled {
+            capture.start(&path, max_bytes)?;
+        } else {
+            capture.stop();
+        }
+        Ok::<_, std::io::Error>(capture.status())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(status)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(CaptureInfo::from(status))),
+        ),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(format!("Failed to start capture: {e}"))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// A synthetic event; `type` and fields match the WebSocket event of the same
+/// name, so a recorded event can be replayed as is
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InjectedEvent {
+    DeviceStateChanged {
+        ieee_address: String,
+        endpoint: u8,
+        state_on: bool,
+    },
+    ButtonEvent {
+        ieee_address: String,
+        endpoint: u8,
+        action: ButtonAction,
+    },
+    OccupancyChanged {
+        ieee_address: String,
+        endpoint: u8,
+        occupied: bool,
+    },
+    /// An attribute report of a measurement
+    SensorValue {
+        ieee_address: String,
+        endpoint: u8,
+        kind: SensorKind,
+        value: f64,
+    },
+    AvailabilityChanged {
+        ieee_address: String,
+        available: bool,
+    },
+}
+
+impl InjectedEvent {
+    fn ieee_address(&self) -> &str {
+        match self {
+            Self::DeviceStateChanged { ieee_address, .. }
+            | Self::ButtonEvent { ieee_address, .. }
+            | Self::OccupancyChanged { ieee_address, .. }
+            | Self::SensorValue { ieee_address, .. }
+            | Self::AvailabilityChanged { ieee_address, .. } => ieee_address,
+        }
+    }
+
+    fn into_network_event(self, ieee_address: [u8; 8]) -> NetworkEvent {
+        match self {
+            Self::DeviceStateChanged {
+                endpoint, state_on, ..
+            } => NetworkEvent::DeviceStateChanged {
+                ieee_address,
+                endpoint,
+                state_on,
+            },
+            Self::ButtonEvent {
+                endpoint, action, ..
+            } => NetworkEvent::ButtonEvent {
+                ieee_address,
+                endpoint,
+                action,
+            },
+            Self::OccupancyChanged {
+                endpoint, occupied, ..
+            } => NetworkEvent::OccupancyChanged {
+                ieee_address,
+                endpoint,
+                occupied,
+            },
+            Self::SensorValue {
+                endpoint,
+                kind,
+                value,
+                ..
+            } => NetworkEvent::SensorValue {
+                ieee_address,
+                endpoint,
+                kind,
+                value,
+            },
+            Self::AvailabilityChanged { available, .. } => NetworkEvent::AvailabilityChanged {
+                ieee_address,
+                available,
+            },
+        }
+    }
+}
+
+/// Put a synthetic event on the network event bus (owner only, audited)
+pub async fn inject_event(
+    State(state): State<AppState>,
+    Json(event): Json<InjectedEvent>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_address) = crate::parse_ieee_address(event.ieee_address()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    state
+        .auth
+        .audit("owner", "event_injected", format!("{event:?}"));
+    network.inject_event(event.into_network_event(ieee_address));
+    (
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(
+            serde_json::json!({ "injected": true }),
+        )),
+    )
+}
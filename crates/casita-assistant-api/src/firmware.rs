@@ -0,0 +1,170 @@
+//! Adapter firmware updates
+//!
+//! `POST /api/v1/system/firmware/update?file=<name>` takes a `.GCF` file as
+//! the request body and flashes it in the background through the running
+//! transport. The file name carries the firmware version, which is checked
+//! against the adapter before it is reset. Progress is broadcast to
+//! WebSocket clients as `firmware_progress` events.
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+pub use casita_api_types::FirmwareProgress;
+use deconz_protocol::firmware::{self, FlashProgress, FlashStage, GcfFile};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::{ApiResponse, AppState};
+
+/// Progress reported by the flasher, as sent to clients
+fn reported(progress: FlashProgress) -> FirmwareProgress {
+    let stage = match progress.stage {
+        FlashStage::EnteringBootloader => "entering_bootloader",
+        FlashStage::Writing => "writing",
+        FlashStage::Restarting => "restarting",
+        FlashStage::Done => "done",
+    };
+    FirmwareProgress {
+        stage: stage.to_string(),
+        bytes_written: progress.bytes_written,
+        total_bytes: progress.total_bytes,
+        error: None,
+    }
+}
+
+/// Runs one firmware update at a time and tracks its progress
+pub struct FirmwareUpdater {
+    running: AtomicBool,
+    last: Mutex<Option<FirmwareProgress>>,
+    progress_tx: broadcast::Sender<FirmwareProgress>,
+}
+
+impl FirmwareUpdater {
+    pub fn new() -> Self {
+        let (progress_tx, _) = broadcast::channel(64);
+        Self {
+            running: AtomicBool::new(false),
+            last: Mutex::new(None),
+            progress_tx,
+        }
+    }
+
+    /// Subscribe to progress updates
+    pub fn subscribe(&self) -> broadcast::Receiver<FirmwareProgress> {
+        self.progress_tx.subscribe()
+    }
+
+    /// Progress of the current or most recent update
+    pub fn status(&self) -> Option<FirmwareProgress> {
+        self.last
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    fn publish(&self, progress: FirmwareProgress) {
+        *self
+            .last
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(progress.clone());
+        let _ = self.progress_tx.send(progress);
+    }
+}
+
+#[derive(Serialize)]
+struct FirmwareUpdateStarted {
+    file_type: u8,
+    version: String,
+    total_bytes: usize,
+}
+
+#[derive(Deserialize)]
+pub struct FirmwareUpdateQuery {
+    /// Original file name, e.g. `deCONZ_ConBeeII_0x26780700.bin.GCF`
+    file: String,
+}
+
+/// Flash a `.GCF` file (raw request body) onto the adapter
+pub async fn update_firmware(
+    State(state): State<AppState>,
+    Query(query): Query<FirmwareUpdateQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Some(version) = GcfFile::version_from_file_name(&query.file) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "File name has no firmware version (e.g. deCONZ_ConBeeII_0x26780700.bin.GCF)",
+            )),
+        );
+    };
+    let gcf = match GcfFile::parse(&body) {
+        Ok(gcf) => GcfFile {
+            version: Some(version),
+            ..gcf
+        },
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(format!("Invalid firmware file: {e}"))),
+            );
+        }
+    };
+    if state.firmware.running.swap(true, Ordering::AcqRel) {
+        return (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error("A firmware update is already running")),
+        );
+    }
+
+    let started = FirmwareUpdateStarted {
+        file_type: gcf.file_type,
+        version: format!("{version:#010x}"),
+        total_bytes: gcf.size(),
+    };
+    tracing::info!(
+        "Starting firmware update to {} ({} bytes, type {:#04x})",
+        started.version,
+        started.total_bytes,
+        started.file_type
+    );
+
+    let network = Arc::clone(network);
+    let updater = Arc::clone(&state.firmware);
+    tokio::spawn(async move {
+        let result = firmware::flash(network.transport(), &gcf, |progress| {
+            updater.publish(reported(progress));
+        })
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Firmware update failed: {}", e);
+            let bytes_written = updater.status().map_or(0, |p| p.bytes_written);
+            updater.publish(FirmwareProgress {
+                stage: "failed".to_string(),
+                bytes_written,
+                total_bytes: gcf.size(),
+                error: Some(e.to_string()),
+            });
+        }
+        updater.running.store(false, Ordering::Release);
+    });
+
+    (StatusCode::ACCEPTED, Json(ApiResponse::success(started)))
+}
+
+/// Progress of the current or most recent firmware update
+pub async fn get_firmware_update(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.firmware.status()))
+}
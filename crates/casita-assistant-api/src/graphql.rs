@@ -0,0 +1,418 @@
+//! GraphQL endpoint
+//!
+//! Read-only schema over devices (with endpoints and recent history) and
+//! automations, so dashboards can fetch exactly what they render in one
+//! request:
+//!
+//! ```graphql
+//! { devices(category: "light") { ieee name stateOn endpoints { id } history(limit: 5) { kind timestamp } } }
+//! ```
+//!
+//! Queries nested deeper than [`MAX_DEPTH`] or costing more than
+//! [`MAX_COMPLEXITY`] are rejected; each history entry asked for counts
+//! towards the cost. The history of all listed devices is read in one query.
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject,
+};
+use axum::{extract::State, Json};
+use std::sync::OnceLock;
+use zigbee_core::ZigbeeDevice;
+
+use crate::AppState;
+
+type CasitaSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Deepest selection accepted (introspection by common clients fits)
+const MAX_DEPTH: usize = 12;
+
+/// Highest query cost accepted: one per field, times the number of entries
+/// for history
+const MAX_COMPLEXITY: usize = 5000;
+
+/// Default number of history entries per device
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+/// Upper bound for history entries per device
+const MAX_HISTORY_LIMIT: usize = 1000;
+
+fn schema() -> &'static CasitaSchema {
+    static SCHEMA: OnceLock<CasitaSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .limit_depth(MAX_DEPTH)
+            .limit_complexity(MAX_COMPLEXITY)
+            .finish()
+    })
+}
+
+/// Entries to read per device for the `history` fields selected in
+/// `lookahead`, the largest limit asked for, or `None` if there are none
+#[cfg(feature = "history")]
+fn history_limit(lookahead: &async_graphql::Lookahead<'_>) -> Option<usize> {
+    lookahead
+        .selection_fields()
+        .iter()
+        .map(|field| {
+            field
+                .arguments()
+                .ok()
+                .and_then(|arguments| {
+                    arguments
+                        .into_iter()
+                        .find(|(name, _)| name.as_str() == "limit")
+                })
+                .and_then(|(_, value)| match value {
+                    async_graphql::Value::Number(n) => n.as_u64(),
+                    _ => None,
+                })
+                .map_or(DEFAULT_HISTORY_LIMIT, |n| {
+                    usize::try_from(n).unwrap_or(usize::MAX)
+                })
+                .min(MAX_HISTORY_LIMIT)
+        })
+        .max()
+}
+
+/// Execute a GraphQL query
+pub async fn graphql(
+    State(state): State<AppState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema().execute(request.data(state)).await)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// All devices, optionally filtered by category (e.g. `light`)
+    async fn devices(
+        &self,
+        ctx: &Context<'_>,
+        category: Option<String>,
+    ) -> async_graphql::Result<Vec<Device>> {
+        let state = ctx.data::<AppState>()?;
+        let Some(network) = &state.network else {
+            return Ok(Vec::new());
+        };
+        #[cfg_attr(not(feature = "history"), allow(unused_mut))]
+        let mut devices: Vec<Device> = network
+            .get_devices()
+            .into_iter()
+            .map(Device::from)
+            .filter(|d| category.as_deref().is_none_or(|c| d.category == c))
+            .collect();
+
+        // Read the history of every device at once rather than per device
+        #[cfg(feature = "history")]
+        if let (Some(history), Some(limit)) = (
+            state.history.clone(),
+            history_limit(&ctx.look_ahead().field("history")),
+        ) {
+            let ieees: Vec<String> = devices.iter().map(|d| d.ieee.clone()).collect();
+            let mut events =
+                tokio::task::spawn_blocking(move || history.recent_device_events(&ieees, limit))
+                    .await??;
+            for device in &mut devices {
+                let entries = events.remove(&device.ieee).unwrap_or_default();
+                device.prefetched_history =
+                    Some(entries.into_iter().map(HistoryEntry::from).collect());
+            }
+        }
+        Ok(devices)
+    }
+
+    /// One device by IEEE address
+    async fn device(
+        &self,
+        ctx: &Context<'_>,
+        ieee: String,
+    ) -> async_graphql::Result<Option<Device>> {
+        let state = ctx.data::<AppState>()?;
+        let Ok(ieee) = crate::parse_ieee_address(&ieee) else {
+            return Ok(None);
+        };
+        Ok(state
+            .network
+            .as_ref()
+            .and_then(|network| network.get_device(&ieee))
+            .map(Device::from))
+    }
+
+    /// Network status (null if the adapter isn't available)
+    async fn network(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<NetworkStatus>> {
+        let state = ctx.data::<AppState>()?;
+        let Some(network) = &state.network else {
+            return Ok(None);
+        };
+        Ok(network.get_status().await.ok().map(|status| NetworkStatus {
+            connected: status.connected,
+            channel: status.channel,
+            pan_id: status.pan_id,
+            permit_join: status.permit_join,
+            device_count: status.device_count,
+        }))
+    }
+
+    /// All automations
+    #[cfg(feature = "automation")]
+    async fn automations(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Automation>> {
+        let state = ctx.data::<AppState>()?;
+        Ok(state
+            .automations
+            .list()
+            .into_iter()
+            .map(Automation::from)
+            .collect())
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Device {
+    pub ieee: String,
+    pub nwk_address: u16,
+    pub name: String,
+    pub category: String,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub available: bool,
+    pub state_on: Option<bool>,
+    pub lqi: Option<u8>,
+    /// When a frame from the device was last received (RFC 3339)
+    pub last_seen: Option<String>,
+    pub endpoints: Vec<Endpoint>,
+    /// Automations that keep failing to control the device
+    pub health_notes: Vec<HealthNote>,
+    /// History read for a whole device list, newest first
+    #[cfg(feature = "history")]
+    #[graphql(skip)]
+    pub prefetched_history: Option<Vec<HistoryEntry>>,
+}
+
+impl From<ZigbeeDevice> for Device {
+    fn from(device: ZigbeeDevice) -> Self {
+        Self {
+            ieee: device.ieee_address_string(),
+            nwk_address: device.nwk_address,
+            name: device.display_name(),
+            category: serde_json::to_value(device.category)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            manufacturer: device.manufacturer,
+            model: device.model,
+            available: device.available,
+            state_on: device.state_on,
+            lqi: device.lqi,
+            last_seen: device.last_seen.map(|seen| seen.to_rfc3339()),
+            endpoints: device.endpoints.into_iter().map(Endpoint::from).collect(),
+            health_notes: device
+                .health_notes
+                .into_iter()
+                .map(HealthNote::from)
+                .collect(),
+            #[cfg(feature = "history")]
+            prefetched_history: None,
+        }
+    }
+}
+
+#[ComplexObject]
+impl Device {
+    /// Recent history, newest first (empty if history is disabled)
+    #[allow(clippy::unused_async)] // resolver signature is fixed by async-graphql
+    #[graphql(complexity = "history_cost(limit, child_complexity)")]
+    async fn history(
+        &self,
+        #[cfg_attr(not(feature = "history"), allow(unused_variables))] ctx: &Context<'_>,
+        #[cfg_attr(not(feature = "history"), allow(unused_variables))] limit: Option<usize>,
+    ) -> async_graphql::Result<Vec<HistoryEntry>> {
+        #[cfg(feature = "history")]
+        {
+            let limit = limit
+                .unwrap_or(DEFAULT_HISTORY_LIMIT)
+                .min(MAX_HISTORY_LIMIT);
+            if let Some(prefetched) = &self.prefetched_history {
+                return Ok(prefetched.iter().take(limit).cloned().collect());
+            }
+            let state = ctx.data::<AppState>()?;
+            let Some(history) = state.history.clone() else {
+                return Ok(Vec::new());
+            };
+            let ieee = self.ieee.clone();
+            let events =
+                tokio::task::spawn_blocking(move || history.device_events(&ieee, limit)).await??;
+            Ok(events.into_iter().map(HistoryEntry::from).collect())
+        }
+        #[cfg(not(feature = "history"))]
+        Ok(Vec::new())
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Endpoint {
+    pub id: u8,
+    pub profile_id: u16,
+    pub device_id: u16,
+    pub in_clusters: Vec<u16>,
+    pub out_clusters: Vec<u16>,
+}
+
+impl From<zigbee_core::Endpoint> for Endpoint {
+    fn from(endpoint: zigbee_core::Endpoint) -> Self {
+        Self {
+            id: endpoint.id,
+            profile_id: endpoint.profile_id,
+            device_id: endpoint.device_id,
+            in_clusters: endpoint.in_clusters,
+            out_clusters: endpoint.out_clusters,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct HealthNote {
+    pub automation_id: String,
+    pub failures: u32,
+    pub last_error: String,
+    /// RFC 3339
+    pub since: String,
+    /// RFC 3339
+    pub updated_at: String,
+}
+
+impl From<zigbee_core::HealthNote> for HealthNote {
+    fn from(note: zigbee_core::HealthNote) -> Self {
+        Self {
+            automation_id: note.automation_id,
+            failures: note.failures,
+            last_error: note.last_error,
+            since: note.since.to_rfc3339(),
+            updated_at: note.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Cost of a `history` field: its selection for every entry
+fn history_cost(limit: Option<usize>, child_complexity: usize) -> usize {
+    limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT)
+        .saturating_mul(child_complexity)
+}
+
+#[derive(SimpleObject, Clone)]
+pub struct HistoryEntry {
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    pub kind: String,
+    pub data: async_graphql::Json<serde_json::Value>,
+}
+
+#[cfg(feature = "history")]
+impl From<crate::history::StoredEvent> for HistoryEntry {
+    fn from(event: crate::history::StoredEvent) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            kind: event.kind,
+            data: async_graphql::Json(event.data),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct NetworkStatus {
+    pub connected: bool,
+    pub channel: u8,
+    pub pan_id: u16,
+    pub permit_join: bool,
+    pub device_count: usize,
+}
+
+/// Automation with trigger, conditions and actions as JSON
+#[cfg(feature = "automation")]
+#[derive(SimpleObject)]
+pub struct Automation {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub enabled: bool,
+    pub trigger: async_graphql::Json<serde_json::Value>,
+    pub conditions: async_graphql::Json<serde_json::Value>,
+    pub actions: async_graphql::Json<serde_json::Value>,
+    pub folder: Option<String>,
+    pub sort_order: i32,
+    pub tags: Vec<String>,
+    /// Triggers are ignored until then (RFC 3339)
+    pub snoozed_until: Option<String>,
+    /// Run counters (trigger, success and failure counts, last triggered,
+    /// average duration)
+    pub stats: async_graphql::Json<serde_json::Value>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[cfg(feature = "automation")]
+impl From<automation_engine::Automation> for Automation {
+    fn from(automation: automation_engine::Automation) -> Self {
+        let json = |value: serde_json::Result<serde_json::Value>| {
+            async_graphql::Json(value.unwrap_or(serde_json::Value::Null))
+        };
+        Self {
+            trigger: json(serde_json::to_value(&automation.trigger)),
+            conditions: json(serde_json::to_value(&automation.conditions)),
+            actions: json(serde_json::to_value(&automation.actions)),
+            stats: json(serde_json::to_value(&automation.stats)),
+            id: automation.id,
+            name: automation.name,
+            description: automation.description,
+            enabled: automation.enabled,
+            folder: automation.folder,
+            sort_order: automation.sort_order,
+            tags: automation.tags,
+            snoozed_until: automation.snoozed_until.map(|until| until.to_rfc3339()),
+            created_at: automation.created_at,
+            updated_at: automation.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn errors(query: &str) -> Vec<String> {
+        schema()
+            .execute(query)
+            .await
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_deep_queries_rejected() {
+        let query = format!(
+            "{{ __schema {{ types {{ fields {{ type {{ {} name {} }} }} }} }} }}",
+            "ofType { ".repeat(10),
+            "} ".repeat(10)
+        );
+        assert_eq!(errors(&query).await, vec!["Query is nested too deep."]);
+    }
+
+    #[tokio::test]
+    async fn test_costly_queries_rejected() {
+        let history = "history(limit: 1000) { kind timestamp data }";
+        let query = format!("{{ devices {{ a: {history} b: {history} }} }}");
+        assert_eq!(errors(&query).await, vec!["Query is too complex."]);
+
+        // Within the budget: only fails for want of the server state
+        let query = format!("{{ devices {{ ieee {history} }} }}");
+        let errors = errors(&query).await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("does not exist"), "{errors:?}");
+    }
+}
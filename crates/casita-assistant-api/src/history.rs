@@ -0,0 +1,1153 @@
+//! Device event history store
+//!
+//! Events are queued in memory and written to SQLite in batches (every
+//! `HISTORY_BATCH_SIZE` events or `HISTORY_FLUSH_SECS` seconds, whichever comes
+//! first) so an SD card sees a handful of transactions per minute instead of
+//! one write per Zigbee report. When the queue backs up, low-value events are
+//! dropped first.
+//!
+//! `GET /api/v1/devices/:ieee/history?attr=temperature` reads one attribute
+//! back as a series for graphs. Long ranges are downsampled into time
+//! buckets (average, min and max of numeric values) so a month of reports
+//! stays drawable; on/off style attributes keep every change.
+
+use crate::{ApiResponse, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use zigbee_core::network::NetworkEvent;
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+const DEFAULT_FLUSH_SECS: u64 = 5;
+const QUEUE_CAPACITY: usize = 4096;
+/// Most points returned for a series before it is downsampled
+const MAX_SERIES_POINTS: usize = 1000;
+/// Most rows read for a series
+const MAX_SERIES_ROWS: usize = 200_000;
+/// Low-priority events are dropped once the queue is this full (percent)
+const LOW_PRIORITY_HIGH_WATER: usize = 75;
+/// `PRAGMA user_version` of the current `history.db` layout
+pub const SCHEMA_VERSION: i32 = 1;
+
+/// How important an event is when the writer falls behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Dropped first under pressure (e.g. generic "device updated" noise)
+    Low,
+    /// Dropped only when the queue is completely full
+    Normal,
+}
+
+/// A single history record
+#[derive(Debug, Clone)]
+pub struct HistoryEvent {
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    /// Device IEEE address, if the event relates to a device
+    pub ieee: Option<String>,
+    /// Event kind (e.g. `state_changed`, `button`)
+    pub kind: String,
+    /// Event payload
+    pub data: serde_json::Value,
+    pub priority: Priority,
+}
+
+impl HistoryEvent {
+    fn new(ieee: Option<String>, kind: &str, data: serde_json::Value, priority: Priority) -> Self {
+        Self {
+            timestamp: now_millis(),
+            ieee,
+            kind: kind.to_string(),
+            data,
+            priority,
+        }
+    }
+
+    /// Map a network event to a history record
+    fn from_network_event(event: &NetworkEvent) -> Self {
+        match event {
+            NetworkEvent::DeviceJoined(device) => Self::new(
+                Some(device.ieee_address_string()),
+                "joined",
+                serde_json::Value::Null,
+                Priority::Normal,
+            ),
+            NetworkEvent::DeviceLeft { ieee_address } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "left",
+                serde_json::Value::Null,
+                Priority::Normal,
+            ),
+            NetworkEvent::DeviceUpdated { ieee_address } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "updated",
+                serde_json::Value::Null,
+                Priority::Low,
+            ),
+            NetworkEvent::NetworkStateChanged { connected } => Self::new(
+                None,
+                "network_state",
+                serde_json::json!({ "connected": connected }),
+                Priority::Normal,
+            ),
+            NetworkEvent::AdapterDisconnected => Self::new(
+                None,
+                "adapter_disconnected",
+                serde_json::Value::Null,
+                Priority::Normal,
+            ),
+            NetworkEvent::PermitJoinChanged {
+                permit_join,
+                duration_secs,
+            } => Self::new(
+                None,
+                "permit_join",
+                serde_json::json!({ "permit_join": permit_join, "duration": duration_secs }),
+                Priority::Normal,
+            ),
+            NetworkEvent::DeviceStateChanged {
+                ieee_address,
+                endpoint,
+                state_on,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "state_changed",
+                serde_json::json!({ "endpoint": endpoint, "state_on": state_on }),
+                Priority::Normal,
+            ),
+            NetworkEvent::ButtonEvent {
+                ieee_address,
+                endpoint,
+                action,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "button",
+                serde_json::json!({ "endpoint": endpoint, "action": action }),
+                Priority::Normal,
+            ),
+            NetworkEvent::OccupancyChanged {
+                ieee_address,
+                endpoint,
+                occupied,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "occupancy",
+                serde_json::json!({ "endpoint": endpoint, "occupied": occupied }),
+                Priority::Normal,
+            ),
+            NetworkEvent::SensorValue {
+                ieee_address,
+                endpoint,
+                kind,
+                value,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "sensor_value",
+                serde_json::json!({ "endpoint": endpoint, "kind": kind, "value": value }),
+                Priority::Normal,
+            ),
+            NetworkEvent::PowerMeasurement {
+                ieee_address,
+                endpoint,
+                kind,
+                value,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "power_measurement",
+                serde_json::json!({ "endpoint": endpoint, "kind": kind, "value": value }),
+                Priority::Normal,
+            ),
+            NetworkEvent::IasAlarm {
+                ieee_address,
+                zone_status,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "ias_alarm",
+                serde_json::json!({
+                    "zone_status": zone_status,
+                    "alarm": zigbee_core::ias::is_alarm(*zone_status),
+                }),
+                Priority::Normal,
+            ),
+            NetworkEvent::ChannelCongestion {
+                channel,
+                congested,
+                failure_rate,
+            } => Self::new(
+                None,
+                "channel_congestion",
+                serde_json::json!({
+                    "channel": channel,
+                    "congested": congested,
+                    "failure_rate": failure_rate,
+                }),
+                Priority::Normal,
+            ),
+            NetworkEvent::AvailabilityChanged {
+                ieee_address,
+                available,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "availability",
+                serde_json::json!({ "available": available }),
+                Priority::Normal,
+            ),
+            NetworkEvent::OtaProgress {
+                ieee_address,
+                status,
+                percent,
+            } => Self::new(
+                Some(crate::websocket::format_ieee(*ieee_address)),
+                "ota_progress",
+                serde_json::json!({ "status": status, "percent": percent }),
+                Priority::Normal,
+            ),
+        }
+    }
+}
+
+/// A stored history record
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredEvent {
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    pub ieee: Option<String>,
+    pub kind: String,
+    pub data: serde_json::Value,
+}
+
+impl StoredEvent {
+    /// Rebuild the network event this record was made from
+    ///
+    /// Returns `None` for records that don't map back (unknown kinds or
+    /// malformed data). Joined devices only carry their IEEE address.
+    #[must_use]
+    pub fn to_network_event(&self) -> Option<NetworkEvent> {
+        let ieee_address = || {
+            self.ieee
+                .as_deref()
+                .and_then(|ieee| crate::parse_ieee_address(ieee).ok())
+        };
+        let endpoint = || {
+            self.data["endpoint"]
+                .as_u64()
+                .and_then(|ep| u8::try_from(ep).ok())
+        };
+        Some(match self.kind.as_str() {
+            "joined" => {
+                NetworkEvent::DeviceJoined(zigbee_core::ZigbeeDevice::new(ieee_address()?, 0xFFFF))
+            }
+            "left" => NetworkEvent::DeviceLeft {
+                ieee_address: ieee_address()?,
+            },
+            "updated" => NetworkEvent::DeviceUpdated {
+                ieee_address: ieee_address()?,
+            },
+            "network_state" => NetworkEvent::NetworkStateChanged {
+                connected: self.data["connected"].as_bool()?,
+            },
+            "adapter_disconnected" => NetworkEvent::AdapterDisconnected,
+            "permit_join" => NetworkEvent::PermitJoinChanged {
+                permit_join: self.data["permit_join"].as_bool()?,
+                duration_secs: u8::try_from(self.data["duration"].as_u64()?).ok()?,
+            },
+            "state_changed" => NetworkEvent::DeviceStateChanged {
+                ieee_address: ieee_address()?,
+                endpoint: endpoint()?,
+                state_on: self.data["state_on"].as_bool()?,
+            },
+            "button" => NetworkEvent::ButtonEvent {
+                ieee_address: ieee_address()?,
+                endpoint: endpoint()?,
+                action: serde_json::from_value(self.data["action"].clone()).ok()?,
+            },
+            "occupancy" => NetworkEvent::OccupancyChanged {
+                ieee_address: ieee_address()?,
+                endpoint: endpoint()?,
+                occupied: self.data["occupied"].as_bool()?,
+            },
+            "sensor_value" => NetworkEvent::SensorValue {
+                ieee_address: ieee_address()?,
+                endpoint: endpoint()?,
+                kind: serde_json::from_value(self.data["kind"].clone()).ok()?,
+                value: self.data["value"].as_f64()?,
+            },
+            "power_measurement" => NetworkEvent::PowerMeasurement {
+                ieee_address: ieee_address()?,
+                endpoint: endpoint()?,
+                kind: serde_json::from_value(self.data["kind"].clone()).ok()?,
+                value: self.data["value"].as_f64()?,
+            },
+            "ias_alarm" => NetworkEvent::IasAlarm {
+                ieee_address: ieee_address()?,
+                zone_status: u16::try_from(self.data["zone_status"].as_u64()?).ok()?,
+            },
+            "channel_congestion" => NetworkEvent::ChannelCongestion {
+                channel: u8::try_from(self.data["channel"].as_u64()?).ok()?,
+                congested: self.data["congested"].as_bool()?,
+                failure_rate: self.data["failure_rate"].as_f64()?,
+            },
+            "availability" => NetworkEvent::AvailabilityChanged {
+                ieee_address: ieee_address()?,
+                available: self.data["available"].as_bool()?,
+            },
+            "ota_progress" => NetworkEvent::OtaProgress {
+                ieee_address: ieee_address()?,
+                status: serde_json::from_value(self.data["status"].clone()).ok()?,
+                percent: u8::try_from(self.data["percent"].as_u64()?).ok()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Writer statistics exposed via the metrics endpoint
+#[derive(Debug, Serialize)]
+pub struct HistoryMetrics {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub written: u64,
+    pub dropped: u64,
+    pub batches: u64,
+    pub write_errors: u64,
+}
+
+/// Result of a prune run
+#[derive(Debug, Serialize)]
+pub struct PruneStats {
+    pub rows_deleted: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub vacuumed: bool,
+}
+
+#[derive(Default)]
+struct Counters {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    batches: AtomicU64,
+    write_errors: AtomicU64,
+}
+
+/// Batching history writer backed by SQLite
+pub struct HistoryStore {
+    tx: mpsc::Sender<HistoryEvent>,
+    counters: Arc<Counters>,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl HistoryStore {
+    /// Open (or create) `history.db` in the data directory and start the writer task
+    pub fn open(data_dir: &std::path::Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(data_dir)?;
+        let conn = Connection::open(data_dir.join("history.db"))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        let fresh: bool = conn.query_row(
+            "SELECT NOT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'events')",
+            [],
+            |row| row.get(0),
+        )?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                ieee TEXT,
+                kind TEXT NOT NULL,
+                data TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_events_ieee_timestamp ON events (ieee, timestamp);",
+        )?;
+        // Databases from before versioning are stamped by a migration
+        if fresh {
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+
+        let batch_size = env_or("HISTORY_BATCH_SIZE", DEFAULT_BATCH_SIZE).max(1);
+        let flush_interval =
+            Duration::from_secs(env_or("HISTORY_FLUSH_SECS", DEFAULT_FLUSH_SECS).max(1));
+
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let counters = Arc::new(Counters::default());
+        let conn = Arc::new(Mutex::new(conn));
+
+        tokio::spawn(writer_task(
+            rx,
+            conn.clone(),
+            counters.clone(),
+            batch_size,
+            flush_interval,
+        ));
+
+        tracing::info!(
+            "History store opened (batch size {}, flush every {:?})",
+            batch_size,
+            flush_interval
+        );
+
+        Ok(Self { tx, counters, conn })
+    }
+
+    /// Queue an event for writing, dropping it if the writer is under pressure
+    pub fn record(&self, event: HistoryEvent) {
+        let depth = QUEUE_CAPACITY - self.tx.capacity();
+        if event.priority == Priority::Low
+            && depth * 100 >= QUEUE_CAPACITY * LOW_PRIORITY_HIGH_WATER
+        {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.tx.try_send(event).is_err() {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Forward network events into the history store
+    pub fn start_recording(self: &Arc<Self>, network: &zigbee_core::ZigbeeNetwork) {
+        let store = Arc::clone(self);
+        let mut rx = network.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => store.record(HistoryEvent::from_network_event(&event)),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("History recorder lagged by {} events", n);
+                        store.counters.dropped.fetch_add(n, Ordering::Relaxed);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Shared database connection (for queries)
+    pub fn connection(&self) -> Arc<Mutex<Connection>> {
+        self.conn.clone()
+    }
+
+    /// Most recent events for a device, newest first
+    ///
+    /// Blocking; call from `spawn_blocking`.
+    pub fn device_events(&self, ieee: &str, limit: usize) -> rusqlite::Result<Vec<StoredEvent>> {
+        let conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut stmt = conn.prepare_cached(
+            "SELECT timestamp, ieee, kind, data FROM events
+             WHERE ieee = ?1 ORDER BY timestamp DESC, id DESC LIMIT ?2",
+        )?;
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let rows = stmt.query_map(rusqlite::params![ieee, limit], row_to_event)?;
+        rows.collect()
+    }
+
+    /// Most recent events for each of several devices, newest first, in one
+    /// query; devices without events are left out
+    ///
+    /// Blocking; call from `spawn_blocking`.
+    pub fn recent_device_events(
+        &self,
+        ieees: &[String],
+        limit: usize,
+    ) -> rusqlite::Result<HashMap<String, Vec<StoredEvent>>> {
+        let conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut stmt = conn.prepare_cached(
+            "SELECT timestamp, ieee, kind, data FROM (
+                 SELECT id, timestamp, ieee, kind, data, ROW_NUMBER() OVER (
+                     PARTITION BY ieee ORDER BY timestamp DESC, id DESC
+                 ) AS n
+                 FROM events WHERE ieee IN (SELECT value FROM json_each(?1))
+             )
+             WHERE n <= ?2 ORDER BY ieee, timestamp DESC, id DESC",
+        )?;
+        let ieees = serde_json::to_string(ieees).unwrap_or_else(|_| "[]".to_string());
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let mut events: HashMap<String, Vec<StoredEvent>> = HashMap::new();
+        for event in stmt.query_map(rusqlite::params![ieees, limit], row_to_event)? {
+            let event = event?;
+            if let Some(ieee) = event.ieee.clone() {
+                events.entry(ieee).or_default().push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    /// Events between `from` and `to` (unix ms, inclusive), oldest first
+    ///
+    /// Blocking; call from `spawn_blocking`.
+    pub fn events_between(
+        &self,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<StoredEvent>> {
+        let conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut stmt = conn.prepare_cached(
+            "SELECT timestamp, ieee, kind, data FROM events
+             WHERE timestamp BETWEEN ?1 AND ?2 ORDER BY timestamp, id LIMIT ?3",
+        )?;
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let rows = stmt.query_map(rusqlite::params![from, to, limit], row_to_event)?;
+        rows.collect()
+    }
+
+    /// Values of one attribute of a device between `from` and `to` (unix
+    /// ms, inclusive), oldest first, and whether older values were left out
+    /// to keep to the newest `max_rows`
+    ///
+    /// Blocking; call from `spawn_blocking`.
+    pub fn attribute_series(
+        &self,
+        ieee: &str,
+        attribute: &Attribute,
+        from: i64,
+        to: i64,
+        max_rows: usize,
+    ) -> rusqlite::Result<(Vec<(i64, serde_json::Value)>, bool)> {
+        let conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        // One more than asked for tells whether there were more
+        let limit = i64::try_from(max_rows.saturating_add(1)).unwrap_or(i64::MAX);
+        let map = |row: &rusqlite::Row<'_>| {
+            let value: Option<String> = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                value
+                    .and_then(|v| serde_json::from_str(&v).ok())
+                    .unwrap_or(serde_json::Value::Null),
+            ))
+        };
+        let mut values: Vec<(i64, serde_json::Value)> = match attribute {
+            Attribute::Field { kind, field } => {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT timestamp, data -> ('$.' || ?5) FROM events
+                     WHERE ieee = ?1 AND kind = ?2 AND timestamp BETWEEN ?3 AND ?4
+                     ORDER BY timestamp DESC, id DESC LIMIT ?6",
+                )?;
+                let rows =
+                    stmt.query_map(rusqlite::params![ieee, kind, from, to, field, limit], map)?;
+                rows.collect::<rusqlite::Result<_>>()?
+            }
+            Attribute::Sensor(kind) => {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT timestamp, data -> '$.value' FROM events
+                     WHERE ieee = ?1 AND kind IN ('sensor_value', 'power_measurement')
+                       AND timestamp BETWEEN ?2 AND ?3 AND json_extract(data, '$.kind') = ?4
+                     ORDER BY timestamp DESC, id DESC LIMIT ?5",
+                )?;
+                let rows = stmt.query_map(rusqlite::params![ieee, from, to, kind, limit], map)?;
+                rows.collect::<rusqlite::Result<_>>()?
+            }
+        };
+        let truncated = values.len() > max_rows;
+        values.truncate(max_rows);
+        values.reverse();
+        Ok((values, truncated))
+    }
+
+    /// Delete events older than `older_than` (unix ms) and trim the oldest
+    /// events until the live data fits in `max_bytes`, then compact the file
+    ///
+    /// Blocking; call from `spawn_blocking`.
+    pub fn prune(
+        &self,
+        older_than: Option<i64>,
+        max_bytes: Option<u64>,
+    ) -> rusqlite::Result<PruneStats> {
+        let conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let bytes_before = live_bytes(&conn)?;
+        let mut rows_deleted = 0u64;
+
+        if let Some(cutoff) = older_than {
+            rows_deleted +=
+                conn.execute("DELETE FROM events WHERE timestamp < ?1", [cutoff])? as u64;
+        }
+
+        if let Some(max_bytes) = max_bytes {
+            while live_bytes(&conn)? > max_bytes {
+                let total: u64 = conn.query_row("SELECT COUNT(*) FROM events", [], |r| r.get(0))?;
+                if total == 0 {
+                    break;
+                }
+                let chunk = (total / 20).max(1000);
+                rows_deleted += conn.execute(
+                    "DELETE FROM events WHERE id IN (SELECT id FROM events ORDER BY id LIMIT ?1)",
+                    [chunk],
+                )? as u64;
+            }
+        }
+
+        // Reclaim space once a quarter of the file is free pages
+        let page_count: u64 = conn.query_row("PRAGMA page_count", [], |r| r.get(0))?;
+        let freelist: u64 = conn.query_row("PRAGMA freelist_count", [], |r| r.get(0))?;
+        let vacuumed = page_count > 0 && freelist * 4 >= page_count;
+        if vacuumed {
+            conn.execute_batch("VACUUM")?;
+        }
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+
+        Ok(PruneStats {
+            rows_deleted,
+            bytes_before,
+            bytes_after: live_bytes(&conn)?,
+            vacuumed,
+        })
+    }
+
+    pub fn metrics(&self) -> HistoryMetrics {
+        HistoryMetrics {
+            queue_depth: QUEUE_CAPACITY - self.tx.capacity(),
+            queue_capacity: QUEUE_CAPACITY,
+            written: self.counters.written.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            batches: self.counters.batches.load(Ordering::Relaxed),
+            write_errors: self.counters.write_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A device attribute recorded in history
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attribute {
+    /// A field of one kind of event
+    Field {
+        kind: &'static str,
+        field: &'static str,
+    },
+    /// A measurement (`temperature`, `power`...)
+    Sensor(String),
+}
+
+impl Attribute {
+    /// Attribute by the name clients use for it, if it is recorded
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        let field = |kind, field| Some(Self::Field { kind, field });
+        match name {
+            "state" => field("state_changed", "state_on"),
+            "occupancy" => field("occupancy", "occupied"),
+            "available" => field("availability", "available"),
+            "ias_alarm" => field("ias_alarm", "alarm"),
+            _ => serde_json::from_value::<zigbee_core::SensorKind>(serde_json::json!(name))
+                .ok()
+                .map(|_| Self::Sensor(name.to_string())),
+        }
+    }
+}
+
+/// One point of an attribute series
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryPoint {
+    /// Unix timestamp in milliseconds (start of the bucket when downsampled)
+    pub timestamp: i64,
+    /// The value, or the bucket average
+    pub value: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+/// Reduce numeric values to one point per `bucket_ms` (average, min, max);
+/// other values are passed through
+fn downsample(values: Vec<(i64, serde_json::Value)>, bucket_ms: i64) -> Vec<HistoryPoint> {
+    let raw = |(timestamp, value)| HistoryPoint {
+        timestamp,
+        value,
+        min: None,
+        max: None,
+    };
+    if bucket_ms <= 0 || !values.iter().all(|(_, v)| v.is_number()) {
+        return values.into_iter().map(raw).collect();
+    }
+
+    let mut points: Vec<HistoryPoint> = Vec::new();
+    let mut sum = 0.0;
+    let mut count = 0.0;
+    for (timestamp, value) in values {
+        let value = value.as_f64().unwrap_or_default();
+        let bucket = timestamp - timestamp.rem_euclid(bucket_ms);
+        match points.last_mut() {
+            Some(point) if point.timestamp == bucket => {
+                sum += value;
+                count += 1.0;
+                point.value = serde_json::json!(sum / count);
+                point.min = point.min.map(|m| m.min(value));
+                point.max = point.max.map(|m| m.max(value));
+            }
+            _ => {
+                (sum, count) = (value, 1.0);
+                points.push(HistoryPoint {
+                    timestamp: bucket,
+                    value: serde_json::json!(value),
+                    min: Some(value),
+                    max: Some(value),
+                });
+            }
+        }
+    }
+    points
+}
+
+/// Query parameters of a device history request
+#[derive(serde::Deserialize)]
+pub struct HistoryQuery {
+    /// Attribute to read (`temperature`, `state`, `occupancy`...)
+    attr: String,
+    /// Start of the range (RFC 3339, default: 24 hours before `to`)
+    #[serde(default)]
+    from: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// End of the range (RFC 3339, default: now)
+    #[serde(default)]
+    to: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Bucket size in seconds for downsampling (default: chosen so at most
+    /// `MAX_SERIES_POINTS` are returned, 0 for raw values)
+    #[serde(default)]
+    bucket: Option<i64>,
+}
+
+/// An attribute series of a device
+#[derive(Debug, Serialize)]
+pub struct HistorySeries {
+    pub ieee: String,
+    pub attr: String,
+    pub from: i64,
+    pub to: i64,
+    /// Bucket size in seconds, if the series was downsampled
+    pub bucket: Option<i64>,
+    pub points: Vec<HistoryPoint>,
+    /// Whether the range held too many values and only the newest were
+    /// read
+    pub truncated: bool,
+}
+
+/// Recorded values of one device attribute, for graphs
+pub async fn device_history(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    let Some(history) = state.history.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("History is not available")),
+        );
+    };
+    let Ok(ieee_bytes) = crate::parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+    let Some(attribute) = Attribute::parse(&query.attr) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "Unknown attribute: {}",
+                query.attr
+            ))),
+        );
+    };
+    let to = query.to.map_or_else(now_millis, |t| t.timestamp_millis());
+    let from = query
+        .from
+        .map_or_else(|| to - 24 * 60 * 60 * 1000, |t| t.timestamp_millis());
+    if from >= to {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("`from` must be before `to`")),
+        );
+    }
+
+    let ieee = crate::websocket::format_ieee(ieee_bytes);
+    let series = {
+        let ieee = ieee.clone();
+        tokio::task::spawn_blocking(move || {
+            history.attribute_series(&ieee, &attribute, from, to, MAX_SERIES_ROWS)
+        })
+        .await
+    };
+    let (values, truncated) = match series {
+        Ok(Ok(series)) => series,
+        Ok(Err(e)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!("Failed to read history: {e}"))),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(e.to_string())),
+            );
+        }
+    };
+
+    let range_secs = (to - from) / 1000;
+    let max_points = i64::try_from(MAX_SERIES_POINTS).unwrap_or(i64::MAX);
+    let bucket = match query.bucket {
+        Some(bucket) => Some(bucket.max(0)),
+        None if values.len() > MAX_SERIES_POINTS => Some((range_secs / max_points).max(1)),
+        None => None,
+    }
+    .filter(|bucket| *bucket > 0);
+    let Some(bucket_ms) = bucket.unwrap_or(0).checked_mul(1000) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("`bucket` is too large")),
+        );
+    };
+    let points = downsample(values, bucket_ms);
+    let bucket = bucket.filter(|_| points.iter().any(|p| p.min.is_some()));
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(HistorySeries {
+            ieee,
+            attr: query.attr,
+            from,
+            to,
+            bucket,
+            points,
+            truncated,
+        })),
+    )
+}
+
+/// Collect events into batches and write each batch in a single transaction
+async fn writer_task(
+    mut rx: mpsc::Receiver<HistoryEvent>,
+    conn: Arc<Mutex<Connection>>,
+    counters: Arc<Counters>,
+    batch_size: usize,
+    flush_interval: Duration,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut ticker = tokio::time::interval(flush_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let closed = tokio::select! {
+            received = rx.recv() => match received {
+                Some(event) => {
+                    batch.push(event);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = ticker.tick() => false,
+        };
+
+        if !batch.is_empty() {
+            let events = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            let count = events.len() as u64;
+            let conn = conn.clone();
+            let result = tokio::task::spawn_blocking(move || write_batch(&conn, &events)).await;
+            match result {
+                Ok(Ok(())) => {
+                    counters.written.fetch_add(count, Ordering::Relaxed);
+                    counters.batches.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!("Failed to write {} history events: {}", count, e);
+                    counters.write_errors.fetch_add(1, Ordering::Relaxed);
+                    counters.dropped.fetch_add(count, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    tracing::warn!("History writer panicked: {}", e);
+                    counters.write_errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        if closed {
+            tracing::debug!("History writer shutting down");
+            break;
+        }
+    }
+}
+
+fn write_batch(conn: &Mutex<Connection>, events: &[HistoryEvent]) -> rusqlite::Result<()> {
+    let mut conn = conn
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO events (timestamp, ieee, kind, data) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for event in events {
+            stmt.execute((
+                event.timestamp,
+                &event.ieee,
+                &event.kind,
+                event.data.to_string(),
+            ))?;
+        }
+    }
+    tx.commit()
+}
+
+/// Map a `timestamp, ieee, kind, data` row to a [`StoredEvent`]
+fn row_to_event(row: &rusqlite::Row<'_>) -> rusqlite::Result<StoredEvent> {
+    let data: String = row.get(3)?;
+    Ok(StoredEvent {
+        timestamp: row.get(0)?,
+        ieee: row.get(1)?,
+        kind: row.get(2)?,
+        data: serde_json::from_str(&data).unwrap_or(serde_json::Value::Null),
+    })
+}
+
+/// Bytes used by live (non-free) pages
+fn live_bytes(conn: &Connection) -> rusqlite::Result<u64> {
+    let page_count: u64 = conn.query_row("PRAGMA page_count", [], |r| r.get(0))?;
+    let freelist: u64 = conn.query_row("PRAGMA freelist_count", [], |r| r.get(0))?;
+    let page_size: u64 = conn.query_row("PRAGMA page_size", [], |r| r.get(0))?;
+    Ok(page_count.saturating_sub(freelist) * page_size)
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[allow(clippy::cast_possible_truncation)] // Millisecond timestamps fit in i64 for millennia
+pub(crate) fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_event_round_trip() {
+        let event = NetworkEvent::ButtonEvent {
+            ieee_address: [1, 2, 3, 4, 5, 6, 7, 8],
+            endpoint: 2,
+            action: zigbee_core::ButtonAction::Single,
+        };
+        let record = HistoryEvent::from_network_event(&event);
+        let stored = StoredEvent {
+            timestamp: record.timestamp,
+            ieee: record.ieee,
+            kind: record.kind,
+            data: record.data,
+        };
+        assert!(matches!(
+            stored.to_network_event(),
+            Some(NetworkEvent::ButtonEvent {
+                ieee_address: [1, 2, 3, 4, 5, 6, 7, 8],
+                endpoint: 2,
+                action: zigbee_core::ButtonAction::Single,
+            })
+        ));
+
+        let unknown = StoredEvent {
+            kind: "maintenance".to_string(),
+            ..stored
+        };
+        assert!(unknown.to_network_event().is_none());
+    }
+
+    #[test]
+    fn test_attribute_names() {
+        assert_eq!(
+            Attribute::parse("temperature"),
+            Some(Attribute::Sensor("temperature".to_string()))
+        );
+        assert_eq!(
+            Attribute::parse("state"),
+            Some(Attribute::Field {
+                kind: "state_changed",
+                field: "state_on"
+            })
+        );
+        assert!(Attribute::parse("colour").is_none());
+    }
+
+    #[test]
+    fn test_downsample() {
+        let values = vec![
+            (1_000, serde_json::json!(20.0)),
+            (59_000, serde_json::json!(22.0)),
+            (61_000, serde_json::json!(25.0)),
+        ];
+        let points = downsample(values.clone(), 60_000);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp, 0);
+        assert_eq!(points[0].value, serde_json::json!(21.0));
+        assert_eq!((points[0].min, points[0].max), (Some(20.0), Some(22.0)));
+        assert_eq!(points[1].timestamp, 60_000);
+
+        // On/off changes are kept as they are
+        let states = vec![
+            (1_000, serde_json::json!(true)),
+            (2_000, serde_json::json!(false)),
+        ];
+        assert_eq!(downsample(states, 60_000).len(), 2);
+        assert_eq!(downsample(values, 0).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_attribute_series() {
+        let dir = std::env::temp_dir().join(format!("casita-history-{}", std::process::id()));
+        let store = HistoryStore::open(&dir).unwrap();
+        let ieee = [1, 2, 3, 4, 5, 6, 7, 8];
+        let events = [
+            NetworkEvent::SensorValue {
+                ieee_address: ieee,
+                endpoint: 1,
+                kind: zigbee_core::SensorKind::Temperature,
+                value: 21.5,
+            },
+            NetworkEvent::SensorValue {
+                ieee_address: ieee,
+                endpoint: 1,
+                kind: zigbee_core::SensorKind::Humidity,
+                value: 40.0,
+            },
+            NetworkEvent::DeviceStateChanged {
+                ieee_address: ieee,
+                endpoint: 1,
+                state_on: true,
+            },
+        ]
+        .iter()
+        .map(HistoryEvent::from_network_event)
+        .collect::<Vec<_>>();
+        write_batch(&store.conn, &events).unwrap();
+
+        let ieee = crate::websocket::format_ieee(ieee);
+        let now = now_millis();
+        let temperature = Attribute::parse("temperature").unwrap();
+        let (series, truncated) = store
+            .attribute_series(&ieee, &temperature, now - 60_000, now + 60_000, 10)
+            .unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].1, serde_json::json!(21.5));
+        assert!(!truncated);
+
+        let state = Attribute::parse("state").unwrap();
+        let (series, _) = store
+            .attribute_series(&ieee, &state, now - 60_000, now + 60_000, 10)
+            .unwrap();
+        assert_eq!(series[0].1, serde_json::json!(true));
+
+        // Too many values: the newest are kept
+        let warmer = HistoryEvent::from_network_event(&NetworkEvent::SensorValue {
+            ieee_address: [1, 2, 3, 4, 5, 6, 7, 8],
+            endpoint: 1,
+            kind: zigbee_core::SensorKind::Temperature,
+            value: 23.0,
+        });
+        write_batch(&store.conn, &[warmer]).unwrap();
+        let (series, truncated) = store
+            .attribute_series(&ieee, &temperature, now - 60_000, now + 60_000, 1)
+            .unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].1, serde_json::json!(23.0));
+        assert!(truncated);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recent_device_events() {
+        let dir =
+            std::env::temp_dir().join(format!("casita-history-recent-{}", std::process::id()));
+        let store = HistoryStore::open(&dir).unwrap();
+        let switched = |ieee_address, state_on| {
+            HistoryEvent::from_network_event(&NetworkEvent::DeviceStateChanged {
+                ieee_address,
+                endpoint: 1,
+                state_on,
+            })
+        };
+        let (a, b, c) = ([1; 8], [2; 8], [3; 8]);
+        let events = [
+            switched(a, true),
+            switched(a, false),
+            switched(a, true),
+            switched(b, false),
+            switched(c, true),
+        ];
+        write_batch(&store.conn, &events).unwrap();
+
+        let (a, b) = (
+            crate::websocket::format_ieee(a),
+            crate::websocket::format_ieee(b),
+        );
+        let missing = crate::websocket::format_ieee([4; 8]);
+        let recent = store
+            .recent_device_events(&[a.clone(), b.clone(), missing], 2)
+            .unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[&a].len(), 2);
+        // Newest first
+        assert_eq!(recent[&a][0].data["state_on"], serde_json::json!(true));
+        assert_eq!(recent[&a][1].data["state_on"], serde_json::json!(false));
+        assert_eq!(recent[&b].len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let dir = std::env::temp_dir().join(format!("casita-history-prune-{}", std::process::id()));
+        let store = HistoryStore::open(&dir).unwrap();
+        let now = now_millis();
+        let event = |timestamp| HistoryEvent {
+            timestamp,
+            ieee: Some("01:02:03:04:05:06:07:08".to_string()),
+            kind: "state_changed".to_string(),
+            data: serde_json::json!({ "state_on": true }),
+            priority: Priority::Normal,
+        };
+        let events: Vec<HistoryEvent> = (0..3)
+            .map(|days| event(now - days * 24 * 60 * 60 * 1000))
+            .collect();
+        write_batch(&store.conn, &events).unwrap();
+
+        // Older than a day and a half
+        let stats = store.prune(Some(now - 36 * 60 * 60 * 1000), None).unwrap();
+        assert_eq!(stats.rows_deleted, 1);
+
+        // Nothing fits in no space
+        let stats = store.prune(None, Some(0)).unwrap();
+        assert_eq!(stats.rows_deleted, 2);
+        assert_eq!(store.prune(None, None).unwrap().rows_deleted, 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,37 +1,95 @@
 //! Casita Assistant - Zigbee Control API Server
 
-use automation_engine::{AutomationEngine, CreateAutomationRequest, UpdateAutomationRequest};
+#[cfg(feature = "automation")]
+use automation_engine::AutomationEngine;
 #[cfg(not(feature = "embed-frontend"))]
 use axum::response::Html;
 use axum::{
-    extract::{Path, State, WebSocketUpgrade},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{Path, Query, State, WebSocketUpgrade},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
+use casita_api_types::SystemInfo;
+use deconz_protocol::{DeconzTransport, ProtocolError};
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 #[cfg(not(feature = "embed-frontend"))]
 use tower_http::services::ServeDir;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use zigbee_core::{DeviceCategory, ZigbeeNetwork};
+use zigbee_core::cluster::DataType;
+use zigbee_core::{
+    ChannelMonitor, DeviceCategory, Expose, LightState, NetworkBackup, NetworkConfig,
+    StartupBehavior, WriteAttributes, ZclValue, ZigbeeDevice, ZigbeeNetwork,
+};
 
+mod auth;
+#[cfg(feature = "automation")]
+mod automations;
+#[cfg(feature = "cameras")]
 mod camera;
+#[cfg(feature = "automation")]
+mod climate;
+mod cors;
+mod debug;
+mod firmware;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "history")]
+mod history;
+mod maintenance;
+mod media;
+mod migrations;
+mod ota;
+mod panel;
+#[cfg(feature = "automation")]
+mod presence;
+mod revision;
+#[cfg(feature = "cameras")]
 mod rtsp;
+mod secrets;
 #[cfg(feature = "embed-frontend")]
 mod static_files;
+mod status;
+mod status_light;
+mod watchdog;
 mod websocket;
 
+use auth::AuthManager;
+#[cfg(feature = "cameras")]
 use camera::CameraManager;
+use firmware::FirmwareUpdater;
+#[cfg(feature = "history")]
+use history::HistoryStore;
+use maintenance::MaintenanceManager;
+use migrations::Migrations;
+use panel::PanelManager;
+use status_light::{HubSignal, StatusLight};
+use watchdog::Watchdog;
 
 /// Application state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
     pub network: Option<Arc<ZigbeeNetwork>>,
+    pub utilization: Option<Arc<ChannelMonitor>>,
+    #[cfg(feature = "cameras")]
     pub cameras: Arc<CameraManager>,
+    #[cfg(feature = "automation")]
     pub automations: Arc<AutomationEngine>,
+    #[cfg(feature = "history")]
+    pub history: Option<Arc<HistoryStore>>,
+    pub maintenance: Arc<MaintenanceManager>,
+    pub migrations: Arc<Migrations>,
+    pub panels: Arc<PanelManager>,
+    pub auth: Arc<AuthManager>,
+    pub watchdog: Arc<Watchdog>,
+    pub firmware: Arc<FirmwareUpdater>,
+    pub status_light: Arc<StatusLight>,
+    pub data_dir: Arc<std::path::Path>,
+    pub started_at: std::time::Instant,
 }
 
 /// API response wrapper using `serde_json::Value` for flexibility
@@ -62,12 +120,23 @@ impl ApiResponse {
     }
 }
 
-/// System info response
+/// A device as returned by the API, with what it can do
 #[derive(Serialize)]
-struct SystemInfo {
-    name: String,
-    version: String,
-    firmware: Option<String>,
+struct DeviceJson {
+    #[serde(flatten)]
+    device: ZigbeeDevice,
+    exposes: Vec<Expose>,
+    state: LightState,
+}
+
+impl From<ZigbeeDevice> for DeviceJson {
+    fn from(device: ZigbeeDevice) -> Self {
+        Self {
+            exposes: device.exposes(),
+            state: device.light_state(),
+            device,
+        }
+    }
 }
 
 /// Permit join request
@@ -81,6 +150,12 @@ fn default_duration() -> u8 {
     60
 }
 
+/// Network state change request
+#[derive(Deserialize)]
+struct NetworkStateRequest {
+    connected: bool,
+}
+
 /// Get system info
 async fn system_info(State(state): State<AppState>) -> impl IntoResponse {
     let firmware = match &state.network {
@@ -98,6 +173,25 @@ async fn system_info(State(state): State<AppState>) -> impl IntoResponse {
     }))
 }
 
+/// Internal metrics (queue depths, counters)
+async fn system_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut metrics = serde_json::Map::new();
+    if let Some(network) = &state.network {
+        metrics.insert(
+            "aps_queue".to_string(),
+            serde_json::json!({ "pending": network.transport().aps_queue_len() }),
+        );
+    }
+    #[cfg(feature = "history")]
+    if let Some(history) = &state.history {
+        metrics.insert(
+            "history".to_string(),
+            serde_json::to_value(history.metrics()).unwrap_or_default(),
+        );
+    }
+    Json(ApiResponse::success(metrics))
+}
+
 /// Get network status
 async fn network_status(State(state): State<AppState>) -> impl IntoResponse {
     let Some(network) = &state.network else {
@@ -127,12 +221,22 @@ async fn permit_join(
         );
     };
     match network.permit_join(req.duration).await {
-        Ok(()) => (
-            StatusCode::OK,
-            Json(ApiResponse::success(serde_json::json!({
-                "duration": req.duration
-            }))),
-        ),
+        Ok(()) => {
+            if req.duration == 0 {
+                state.status_light.clear(HubSignal::PermitJoin);
+            } else {
+                state.status_light.raise(
+                    HubSignal::PermitJoin,
+                    Some(std::time::Duration::from_secs(u64::from(req.duration))),
+                );
+            }
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success(serde_json::json!({
+                    "duration": req.duration
+                }))),
+            )
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::error(e.to_string())),
@@ -140,44 +244,10 @@ async fn permit_join(
     }
 }
 
-/// List all devices
-async fn list_devices(State(state): State<AppState>) -> impl IntoResponse {
-    let devices = match &state.network {
-        Some(network) => network.get_devices(),
-        None => vec![],
-    };
-    Json(ApiResponse::success(devices))
-}
-
-/// Get a specific device
-async fn get_device(State(state): State<AppState>, Path(ieee): Path<String>) -> impl IntoResponse {
-    let Some(network) = &state.network else {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(ApiResponse::error("Zigbee network not available")),
-        );
-    };
-    // Parse IEEE address from hex string
-    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("Invalid IEEE address format")),
-        );
-    };
-
-    match network.get_device(&ieee_bytes) {
-        Some(device) => (StatusCode::OK, Json(ApiResponse::success(device))),
-        None => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error("Device not found")),
-        ),
-    }
-}
-
-/// Discover endpoints for a device
-async fn discover_device(
+/// Bring the network online or offline
+async fn set_network_state(
     State(state): State<AppState>,
-    Path(ieee): Path<String>,
+    Json(req): Json<NetworkStateRequest>,
 ) -> impl IntoResponse {
     let Some(network) = &state.network else {
         return (
@@ -185,19 +255,16 @@ async fn discover_device(
             Json(ApiResponse::error("Zigbee network not available")),
         );
     };
-    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("Invalid IEEE address format")),
-        );
+    let result = if req.connected {
+        network.connect().await
+    } else {
+        network.disconnect().await
     };
-
-    match network.discover_endpoints(&ieee_bytes).await {
+    match result {
         Ok(()) => (
             StatusCode::OK,
             Json(ApiResponse::success(serde_json::json!({
-                "status": "discovery_started",
-                "ieee": ieee
+                "connected": req.connected
             }))),
         ),
         Err(e) => (
@@ -207,20 +274,63 @@ async fn discover_device(
     }
 }
 
-/// Request body for updating device metadata
+/// Network configuration request (addresses and keys as hex strings)
 #[derive(Deserialize)]
-struct UpdateDeviceRequest {
+struct ConfigureNetworkRequest {
     #[serde(default)]
-    friendly_name: Option<String>,
+    channel: Option<u8>,
     #[serde(default)]
-    category: Option<DeviceCategory>,
+    channel_mask: Option<u32>,
+    #[serde(default)]
+    pan_id: Option<u16>,
+    /// Colon-separated, displayed byte order (same as network status)
+    #[serde(default)]
+    extended_pan_id: Option<String>,
+    /// 32 hex characters (colons optional)
+    #[serde(default)]
+    network_key: Option<String>,
+    #[serde(default)]
+    security_mode: Option<u8>,
 }
 
-/// Update device metadata (friendly name and category)
-async fn update_device(
+impl ConfigureNetworkRequest {
+    fn into_config(self) -> Result<NetworkConfig, String> {
+        let extended_pan_id = self
+            .extended_pan_id
+            .map(|s| parse_ieee_address(&s).map_err(|()| "Invalid extended PAN ID format"))
+            .transpose()?;
+        let network_key = self
+            .network_key
+            .map(|s| parse_network_key(&s).ok_or("Invalid network key format"))
+            .transpose()?;
+        Ok(NetworkConfig {
+            channel: self.channel,
+            channel_mask: self.channel_mask,
+            pan_id: self.pan_id,
+            extended_pan_id,
+            network_key,
+            security_mode: self.security_mode,
+        })
+    }
+}
+
+/// Parse a 128-bit key from hex (colons optional)
+fn parse_network_key(s: &str) -> Option<[u8; 16]> {
+    let hex: String = s.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0u8; 16];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Form or reconfigure the network
+async fn configure_network(
     State(state): State<AppState>,
-    Path(ieee): Path<String>,
-    Json(request): Json<UpdateDeviceRequest>,
+    Json(req): Json<ConfigureNetworkRequest>,
 ) -> impl IntoResponse {
     let Some(network) = &state.network else {
         return (
@@ -228,107 +338,69 @@ async fn update_device(
             Json(ApiResponse::error("Zigbee network not available")),
         );
     };
-    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("Invalid IEEE address format")),
-        );
+    let config = match req.into_config() {
+        Ok(config) => config,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
     };
-
-    match network.update_device_metadata(&ieee_bytes, request.friendly_name, request.category) {
-        Ok(device) => (StatusCode::OK, Json(ApiResponse::success(device))),
-        Err(e) => {
-            let status = if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(ApiResponse::error(e.to_string())))
-        }
+    match network.configure_network(config).await {
+        Ok(status) => (StatusCode::OK, Json(ApiResponse::success(status))),
+        Err(e @ zigbee_core::network::NetworkError::InvalidConfig(_)) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
     }
 }
 
-/// Parse IEEE address from colon-separated hex string
-fn parse_ieee_address(s: &str) -> Result<[u8; 8], ()> {
-    let parts: Vec<&str> = s.split(':').collect();
-    if parts.len() != 8 {
-        // Try without colons
-        if s.len() == 16 {
-            let bytes: Result<Vec<u8>, _> = (0..8)
-                .map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16))
-                .collect();
-            if let Ok(b) = bytes {
-                let mut arr = [0u8; 8];
-                arr.copy_from_slice(&b);
-                // Reverse for internal representation
-                arr.reverse();
-                return Ok(arr);
-            }
-        }
-        return Err(());
-    }
+/// Options of a channel scan (query parameters of the passive scan, body of
+/// the active one)
+#[derive(Deserialize)]
+struct ScanOptions {
+    /// How long to collect beacons (seconds)
+    #[serde(default = "default_scan_duration")]
+    duration: u64,
+}
 
-    let bytes: Result<Vec<u8>, _> = parts.iter().map(|p| u8::from_str_radix(p, 16)).collect();
+fn default_scan_duration() -> u64 {
+    10
+}
 
-    match bytes {
-        Ok(b) if b.len() == 8 => {
-            let mut arr = [0u8; 8];
-            arr.copy_from_slice(&b);
-            // Reverse because IEEE addresses are displayed in reverse byte order
-            arr.reverse();
-            Ok(arr)
-        }
-        _ => Err(()),
-    }
+/// Scan for neighboring networks per channel from the beacons the firmware
+/// happens to hear
+async fn scan_network(
+    State(state): State<AppState>,
+    Query(options): Query<ScanOptions>,
+) -> impl IntoResponse {
+    run_scan(&state, options.duration, false).await
 }
 
-/// WebSocket upgrade handler
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| websocket::handle_socket(socket, state))
+/// Scan for neighboring networks per channel, cycling the network to force
+/// the firmware to scan (briefly interrupts the network)
+async fn active_scan_network(
+    State(state): State<AppState>,
+    options: Option<Json<ScanOptions>>,
+) -> impl IntoResponse {
+    let duration = options.map_or_else(default_scan_duration, |Json(options)| options.duration);
+    run_scan(&state, duration, true).await
 }
 
-/// Request APS data (fetch pending data from devices)
-async fn request_aps_data(State(state): State<AppState>) -> impl IntoResponse {
+async fn run_scan(
+    state: &AppState,
+    duration: u64,
+    active: bool,
+) -> (StatusCode, Json<ApiResponse>) {
     let Some(network) = &state.network else {
         return (
             StatusCode::SERVICE_UNAVAILABLE,
             Json(ApiResponse::error("Zigbee network not available")),
         );
     };
-    // First check if there's data waiting
-    let device_state = match network.transport().get_device_state().await {
-        Ok(state) => state,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(e.to_string())),
-            )
-        }
-    };
-
-    if !device_state.aps_data_indication {
-        return (
-            StatusCode::OK,
-            Json(ApiResponse::success(serde_json::json!({
-                "status": "no_data",
-                "message": "No APS data waiting"
-            }))),
-        );
-    }
-
-    match network.transport().request_aps_data().await {
-        Ok(data) => {
-            // Format the raw data as hex for visibility
-            let hex_data: Vec<String> = data.iter().map(|b| format!("{b:02X}")).collect();
-            (
-                StatusCode::OK,
-                Json(ApiResponse::success(serde_json::json!({
-                    "status": "data_received",
-                    "raw_data": hex_data,
-                    "length": data.len()
-                }))),
-            )
-        }
+    let duration = std::time::Duration::from_secs(duration.clamp(1, 60));
+    match network.scan_channels(duration, active).await {
+        Ok(scan) => (StatusCode::OK, Json(ApiResponse::success(scan))),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::error(e.to_string())),
@@ -336,10 +408,26 @@ async fn request_aps_data(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
-/// Toggle device on/off
-async fn toggle_device(
+/// Options of an identify sweep
+#[derive(Deserialize)]
+struct IdentifySweepRequest {
+    /// How long to collect answers (seconds)
+    #[serde(default = "default_sweep_duration")]
+    duration: u64,
+    /// Make every device identify (blink) for this long first, so all of
+    /// them answer; 0 only queries devices already identifying
+    #[serde(default)]
+    identify: u16,
+}
+
+fn default_sweep_duration() -> u64 {
+    5
+}
+
+/// Find which devices answer an Identify Query, and with what link quality
+async fn identify_sweep(
     State(state): State<AppState>,
-    Path((ieee, endpoint)): Path<(String, u8)>,
+    request: Option<Json<IdentifySweepRequest>>,
 ) -> impl IntoResponse {
     let Some(network) = &state.network else {
         return (
@@ -347,22 +435,15 @@ async fn toggle_device(
             Json(ApiResponse::error("Zigbee network not available")),
         );
     };
-    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("Invalid IEEE address format")),
-        );
-    };
-
-    match network.toggle_device(&ieee_bytes, endpoint).await {
-        Ok(()) => (
-            StatusCode::OK,
-            Json(ApiResponse::success(serde_json::json!({
-                "action": "toggle",
-                "ieee": ieee,
-                "endpoint": endpoint
-            }))),
-        ),
+    let request = request
+        .map(|Json(request)| request)
+        .unwrap_or(IdentifySweepRequest {
+            duration: default_sweep_duration(),
+            identify: 0,
+        });
+    let duration = std::time::Duration::from_secs(request.duration.clamp(1, 30));
+    match network.identify_sweep(duration, request.identify).await {
+        Ok(sweep) => (StatusCode::OK, Json(ApiResponse::success(sweep))),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::error(e.to_string())),
@@ -370,44 +451,65 @@ async fn toggle_device(
     }
 }
 
-/// Turn device on
-async fn device_on(
-    State(state): State<AppState>,
-    Path((ieee, endpoint)): Path<(String, u8)>,
-) -> impl IntoResponse {
+/// Crawl the network and return the neighbor/LQI graph
+async fn network_topology(State(state): State<AppState>) -> impl IntoResponse {
     let Some(network) = &state.network else {
         return (
             StatusCode::SERVICE_UNAVAILABLE,
             Json(ApiResponse::error("Zigbee network not available")),
         );
     };
-    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+    match network.crawl_topology().await {
+        Ok(topology) => (StatusCode::OK, Json(ApiResponse::success(topology))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Rolling channel utilization history and congestion state
+async fn network_utilization(State(state): State<AppState>) -> impl IntoResponse {
+    let Some(monitor) = &state.utilization else {
         return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("Invalid IEEE address format")),
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
         );
     };
+    (StatusCode::OK, Json(ApiResponse::success(monitor.report())))
+}
 
-    match network.turn_on(&ieee_bytes, endpoint).await {
-        Ok(()) => (
+/// Export a network backup (open coordinator backup format)
+async fn backup_network(State(state): State<AppState>) -> axum::response::Response {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        )
+            .into_response();
+    };
+    match network.backup().await {
+        Ok(backup) => (
             StatusCode::OK,
-            Json(ApiResponse::success(serde_json::json!({
-                "action": "on",
-                "ieee": ieee,
-                "endpoint": endpoint
-            }))),
-        ),
+            [(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"casita-network-backup.json\"",
+            )],
+            Json(backup),
+        )
+            .into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::error(e.to_string())),
-        ),
+        )
+            .into_response(),
     }
 }
 
-/// Turn device off
-async fn device_off(
+/// Restore a network backup onto the connected stick
+async fn restore_network(
     State(state): State<AppState>,
-    Path((ieee, endpoint)): Path<(String, u8)>,
+    Json(backup): Json<NetworkBackup>,
 ) -> impl IntoResponse {
     let Some(network) = &state.network else {
         return (
@@ -415,22 +517,605 @@ async fn device_off(
             Json(ApiResponse::error("Zigbee network not available")),
         );
     };
-    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+    if let Err(e) = backup.decode() {
         return (
             StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("Invalid IEEE address format")),
+            Json(ApiResponse::error(e.to_string())),
         );
+    }
+    match network.restore(&backup).await {
+        Ok(status) => (StatusCode::OK, Json(ApiResponse::success(status))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// List all devices
+async fn list_devices(State(state): State<AppState>) -> impl IntoResponse {
+    let devices = match &state.network {
+        Some(network) => network.get_devices(),
+        None => vec![],
     };
+    let devices: Vec<DeviceJson> = devices.into_iter().map(DeviceJson::from).collect();
+    Json(ApiResponse::success(devices))
+}
 
-    match network.turn_off(&ieee_bytes, endpoint).await {
+/// Link and delivery details of a device, for finding out why it doesn't
+/// respond
+async fn device_diagnostics(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+    let Some(device) = network.get_device(&ieee_bytes) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        );
+    };
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(serde_json::json!({
+            "available": device.available,
+            "last_seen": device.last_seen,
+            "lqi": device.lqi,
+            "delivery": network.delivery_stats(&ieee_bytes),
+            "queued_commands": network.queued_commands(&ieee_bytes),
+        }))),
+    )
+}
+
+/// Get a specific device
+async fn get_device(State(state): State<AppState>, Path(ieee): Path<String>) -> Response {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        )
+            .into_response();
+    };
+    // Parse IEEE address from hex string
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        )
+            .into_response();
+    };
+
+    match network.get_device(&ieee_bytes) {
+        Some(device) => revision::tagged(
+            StatusCode::OK,
+            device.revision,
+            ApiResponse::success(DeviceJson::from(device)),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        )
+            .into_response(),
+    }
+}
+
+/// Recommended UI card of a device
+async fn get_device_card(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.get_device(&ieee_bytes) {
+        Some(device) => (StatusCode::OK, Json(ApiResponse::success(device.card()))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        ),
+    }
+}
+
+/// Read a router's routing table (ZDO Mgmt_Rtg)
+async fn device_routes(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.device_routes(&ieee_bytes).await {
+        Ok(routes) => (StatusCode::OK, Json(ApiResponse::success(routes))),
+        Err(zigbee_core::network::NetworkError::DeviceNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Discover endpoints for a device
+async fn discover_device(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.discover_endpoints(&ieee_bytes).await {
         Ok(()) => (
             StatusCode::OK,
             Json(ApiResponse::success(serde_json::json!({
-                "action": "off",
+                "status": "discovery_started",
+                "ieee": ieee
+            }))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Request body for updating device metadata
+///
+/// Empty strings clear `icon`, `purchase_date` and `notes`; `custom` is merged
+/// into the existing fields, with `null` removing a key.
+#[derive(Deserialize)]
+struct UpdateDeviceRequest {
+    #[serde(default)]
+    friendly_name: Option<String>,
+    #[serde(default)]
+    category: Option<DeviceCategory>,
+    #[serde(default)]
+    icon: Option<String>,
+    #[serde(default)]
+    purchase_date: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    custom: Option<std::collections::BTreeMap<String, serde_json::Value>>,
+    /// Power-on behavior, written to the device's light endpoints
+    #[serde(default)]
+    startup: Option<StartupBehavior>,
+}
+
+/// Update device metadata (friendly name, category, icon, notes...) and
+/// power-on behavior; honours `If-Match`
+async fn update_device(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateDeviceRequest>,
+) -> Response {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        )
+            .into_response();
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        )
+            .into_response();
+    };
+    if let Some(date) = request.purchase_date.as_deref() {
+        if !date.is_empty() && !media::is_valid_date(date) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("purchase_date must be YYYY-MM-DD")),
+            )
+                .into_response();
+        }
+    }
+    if request
+        .notes
+        .as_ref()
+        .is_some_and(|n| n.len() > media::MAX_NOTES_LEN)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "notes must be at most {} bytes",
+                media::MAX_NOTES_LEN
+            ))),
+        )
+            .into_response();
+    }
+
+    let expected = match revision::if_match(&headers) {
+        Ok(expected) => expected,
+        Err(response) => return response.into_response(),
+    };
+
+    let non_empty = |value: String| if value.is_empty() { None } else { Some(value) };
+    let startup = request.startup;
+    let result = network.update_device_checked(&ieee_bytes, expected, |device| {
+        if let Some(name) = request.friendly_name {
+            device.friendly_name = non_empty(name);
+        }
+        if let Some(cat) = request.category {
+            device.category = cat;
+        }
+        if let Some(icon) = request.icon {
+            device.metadata.icon = non_empty(icon);
+        }
+        if let Some(date) = request.purchase_date {
+            device.metadata.purchase_date = non_empty(date);
+        }
+        if let Some(notes) = request.notes {
+            device.metadata.notes = non_empty(notes);
+        }
+        for (key, value) in request.custom.unwrap_or_default() {
+            if value.is_null() {
+                device.metadata.custom.remove(&key);
+            } else {
+                device.metadata.custom.insert(key, value);
+            }
+        }
+        if startup.is_some() {
+            device.startup = startup;
+        }
+    });
+
+    if let (Ok(_), Some(startup)) = (&result, startup) {
+        if let Err(e) = network.apply_startup_behavior(&ieee_bytes, startup).await {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!(
+                    "Saved, but failed to send power-on behavior to the device: {e}"
+                ))),
+            )
+                .into_response();
+        }
+    }
+
+    match result {
+        Ok(device) => revision::tagged(
+            StatusCode::OK,
+            device.revision,
+            ApiResponse::success(DeviceJson::from(device)),
+        ),
+        Err(e @ zigbee_core::network::NetworkError::RevisionMismatch { current, .. }) => {
+            revision::conflict(network.get_device(&ieee_bytes), current, e.to_string())
+        }
+        Err(e) => {
+            let status = if e.to_string().contains("not found") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string()))).into_response()
+        }
+    }
+}
+
+/// Parse IEEE address from colon-separated hex string
+fn parse_ieee_address(s: &str) -> Result<[u8; 8], ()> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() != 8 {
+        // Try without colons
+        if s.len() == 16 {
+            let bytes: Result<Vec<u8>, _> = (0..8)
+                .map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16))
+                .collect();
+            if let Ok(b) = bytes {
+                let mut arr = [0u8; 8];
+                arr.copy_from_slice(&b);
+                // Reverse for internal representation
+                arr.reverse();
+                return Ok(arr);
+            }
+        }
+        return Err(());
+    }
+
+    let bytes: Result<Vec<u8>, _> = parts.iter().map(|p| u8::from_str_radix(p, 16)).collect();
+
+    match bytes {
+        Ok(b) if b.len() == 8 => {
+            let mut arr = [0u8; 8];
+            arr.copy_from_slice(&b);
+            // Reverse because IEEE addresses are displayed in reverse byte order
+            arr.reverse();
+            Ok(arr)
+        }
+        _ => Err(()),
+    }
+}
+
+/// Parse a cluster or attribute ID, hex with `0x` (`0x0006`) or decimal
+fn parse_zcl_id(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// WebSocket upgrade handler
+async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| websocket::handle_socket(socket, state))
+}
+
+/// Request APS data (fetch pending data from devices)
+async fn request_aps_data(State(state): State<AppState>) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    // First check if there's data waiting
+    let device_state = match network.transport().get_device_state().await {
+        Ok(state) => state,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(e.to_string())),
+            )
+        }
+    };
+
+    if !device_state.aps_data_indication {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "status": "no_data",
+                "message": "No APS data waiting"
+            }))),
+        );
+    }
+
+    match network.transport().request_aps_data().await {
+        Ok(data) => {
+            // Format the raw data as hex for visibility
+            let hex_data: Vec<String> = data.iter().map(|b| format!("{b:02X}")).collect();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success(serde_json::json!({
+                    "status": "data_received",
+                    "raw_data": hex_data,
+                    "length": data.len()
+                }))),
+            )
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Status code for a failed device command: 504 when the device didn't
+/// receive it, 500 otherwise
+fn command_error_status(error: &zigbee_core::network::NetworkError) -> StatusCode {
+    match error {
+        zigbee_core::network::NetworkError::Delivery(_) => StatusCode::GATEWAY_TIMEOUT,
+        zigbee_core::network::NetworkError::Rejected(_) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Toggle device on/off
+async fn toggle_device(
+    State(state): State<AppState>,
+    Path((ieee, endpoint)): Path<(String, u8)>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.toggle_device(&ieee_bytes, endpoint).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "action": "toggle",
+                "ieee": ieee,
+                "endpoint": endpoint
+            }))),
+        ),
+        Err(e) => (
+            command_error_status(&e),
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Turn device on
+async fn device_on(
+    State(state): State<AppState>,
+    Path((ieee, endpoint)): Path<(String, u8)>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.turn_on(&ieee_bytes, endpoint).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "action": "on",
+                "ieee": ieee,
+                "endpoint": endpoint
+            }))),
+        ),
+        Err(e) => (
+            command_error_status(&e),
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Request body for making a device identify itself
+#[derive(Deserialize)]
+struct IdentifyRequest {
+    /// Defaults to the first endpoint with the Identify cluster
+    endpoint: Option<u8>,
+    /// How long to blink; 0 stops
+    #[serde(default = "default_identify_secs")]
+    seconds: u16,
+}
+
+fn default_identify_secs() -> u16 {
+    10
+}
+
+/// Make a device blink so it can be found
+async fn identify_device(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+    request: Option<Json<IdentifyRequest>>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+    let Some(device) = network.get_device(&ieee_bytes) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        );
+    };
+    let request = request.map_or_else(
+        || IdentifyRequest {
+            endpoint: None,
+            seconds: default_identify_secs(),
+        },
+        |Json(request)| request,
+    );
+    let endpoint = request.endpoint.unwrap_or_else(|| {
+        device
+            .endpoints
+            .iter()
+            .find(|e| e.in_clusters.contains(&zigbee_core::cluster::id::IDENTIFY))
+            .map_or(1, |e| e.id)
+    });
+
+    match network
+        .identify(&ieee_bytes, endpoint, request.seconds)
+        .await
+    {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "action": "identify",
+                "ieee": ieee,
+                "endpoint": endpoint,
+                "seconds": request.seconds
+            }))),
+        ),
+        Err(e) => (
+            command_error_status(&e),
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Enroll an IAS Zone sensor (contact, motion) with the coordinator
+async fn enroll_ias_zone(
+    State(state): State<AppState>,
+    Path((ieee, endpoint)): Path<(String, u8)>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.enroll_ias_zone(&ieee_bytes, endpoint).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "action": "ias_enroll",
                 "ieee": ieee,
                 "endpoint": endpoint
             }))),
         ),
+        Err(zigbee_core::network::NetworkError::DeviceNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        ),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::error(e.to_string())),
@@ -438,146 +1123,220 @@ async fn device_off(
     }
 }
 
-/// Health check
-async fn health() -> impl IntoResponse {
-    Json(serde_json::json!({ "status": "ok" }))
+/// Request body for setting a Tuya data point
+#[derive(Deserialize)]
+struct TuyaValueRequest {
+    name: String,
+    value: serde_json::Value,
+}
+
+/// Set a named data point of a Tuya (0xEF00) device
+async fn set_tuya_value(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+    Json(request): Json<TuyaValueRequest>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network
+        .set_tuya_value(&ieee_bytes, &request.name, &request.value)
+        .await
+    {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "action": "tuya",
+                "ieee": ieee,
+                "name": request.name,
+                "value": request.value
+            }))),
+        ),
+        Err(zigbee_core::network::NetworkError::DeviceNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        ),
+        Err(e @ zigbee_core::network::NetworkError::Configuration(_)) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Turn device off
+async fn device_off(
+    State(state): State<AppState>,
+    Path((ieee, endpoint)): Path<(String, u8)>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    match network.turn_off(&ieee_bytes, endpoint).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "action": "off",
+                "ieee": ieee,
+                "endpoint": endpoint
+            }))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
 }
 
-// ============================================================================
-// Automation handlers
-// ============================================================================
-
-/// List all automations
-async fn list_automations(State(state): State<AppState>) -> impl IntoResponse {
-    let automations = state.automations.list();
-    Json(ApiResponse::success(automations))
+/// Request body for setting a light's state
+#[derive(Deserialize)]
+struct LightStateRequest {
+    #[serde(flatten)]
+    state: LightState,
+    /// Transition time in tenths of a second
+    #[serde(default)]
+    transition: u16,
 }
 
-/// Get a specific automation
-async fn get_automation(
+/// Set a light's on/off state, brightness and colour, e.g. back to a
+/// `state` read from the device earlier
+async fn set_light_state(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path((ieee, endpoint)): Path<(String, u8)>,
+    Json(req): Json<LightStateRequest>,
 ) -> impl IntoResponse {
-    match state.automations.get(&id) {
-        Some(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
-        None => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error("Automation not found")),
-        ),
-    }
-}
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
 
-/// Create a new automation
-async fn create_automation(
-    State(state): State<AppState>,
-    Json(request): Json<CreateAutomationRequest>,
-) -> impl IntoResponse {
-    match state.automations.create(request).await {
-        Ok(automation) => (StatusCode::CREATED, Json(ApiResponse::success(automation))),
+    match network
+        .restore_light_state(&ieee_bytes, endpoint, &req.state, req.transition)
+        .await
+    {
+        Ok(()) => {
+            let light_state = network
+                .get_device(&ieee_bytes)
+                .map(|device| device.light_state())
+                .unwrap_or_default();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success(serde_json::json!(light_state))),
+            )
+        }
         Err(e) => (
-            StatusCode::BAD_REQUEST,
+            command_error_status(&e),
             Json(ApiResponse::error(e.to_string())),
         ),
     }
 }
 
-/// Update an automation
-async fn update_automation(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-    Json(request): Json<UpdateAutomationRequest>,
-) -> impl IntoResponse {
-    match state.automations.update(&id, request).await {
-        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
-        Err(e) => {
-            let status = if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::BAD_REQUEST
-            };
-            (status, Json(ApiResponse::error(e.to_string())))
-        }
-    }
+/// Request body for writing an attribute
+#[derive(Deserialize)]
+struct WriteAttributeRequest {
+    /// ZCL data type name (`uint8`, `int16`, `enum8`, `bool`, `string`, ...)
+    data_type: String,
+    value: serde_json::Value,
+    /// Set for manufacturer-specific attributes
+    #[serde(default)]
+    manufacturer_code: Option<u16>,
 }
 
-/// Delete an automation
-async fn delete_automation(
+/// Write an attribute of any cluster, for device settings without a
+/// dedicated endpoint
+async fn write_attribute(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path((ieee, endpoint, cluster, attribute)): Path<(String, u8, String, String)>,
+    Json(req): Json<WriteAttributeRequest>,
 ) -> impl IntoResponse {
-    match state.automations.delete(&id).await {
-        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
-        Err(e) => {
-            let status = if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(ApiResponse::error(e.to_string())))
-        }
-    }
-}
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+    let (Some(cluster_id), Some(attribute_id)) = (parse_zcl_id(&cluster), parse_zcl_id(&attribute))
+    else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid cluster or attribute ID")),
+        );
+    };
+    let Some(data_type) = DataType::from_name(&req.data_type) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "Unknown data type: {}",
+                req.data_type
+            ))),
+        );
+    };
+    let value = match ZclValue::from_json(data_type, &req.value) {
+        Ok(value) => value,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
 
-/// Manually trigger an automation
-async fn trigger_automation(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> impl IntoResponse {
-    match state.automations.trigger(&id).await {
-        Ok(()) => (
+    let mut write = WriteAttributes::new().attribute(attribute_id, value);
+    if let Some(code) = req.manufacturer_code {
+        write = write.manufacturer_code(code);
+    }
+    match network
+        .write_attributes(&ieee_bytes, endpoint, cluster_id, &write)
+        .await
+    {
+        Ok(dispatch) => (
             StatusCode::OK,
             Json(ApiResponse::success(serde_json::json!({
-                "status": "triggered",
-                "automation_id": id
+                "queued": dispatch == zigbee_core::poll_control::Dispatch::Queued,
             }))),
         ),
-        Err(e) => {
-            let status = if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else if e.to_string().contains("disabled") {
-                StatusCode::CONFLICT
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(ApiResponse::error(e.to_string())))
-        }
-    }
-}
-
-/// Enable an automation
-async fn enable_automation(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> impl IntoResponse {
-    match state.automations.enable(&id).await {
-        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
-        Err(e) => {
-            let status = if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(ApiResponse::error(e.to_string())))
-        }
+        Err(e) => (
+            command_error_status(&e),
+            Json(ApiResponse::error(e.to_string())),
+        ),
     }
 }
 
-/// Disable an automation
-async fn disable_automation(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> impl IntoResponse {
-    match state.automations.disable(&id).await {
-        Ok(automation) => (StatusCode::OK, Json(ApiResponse::success(automation))),
-        Err(e) => {
-            let status = if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(ApiResponse::error(e.to_string())))
-        }
-    }
+/// Health check
+async fn health() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
 }
 
 /// Serve the frontend (legacy mode - for development with vanilla JS)
@@ -601,68 +1360,82 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Starting Casita Assistant API server");
 
-    // Initialize camera manager first (always available)
+    // Initialize camera manager first (no hardware dependency)
     let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "./data".to_string());
-    let cameras = CameraManager::new(std::path::Path::new(&data_dir));
-    if let Err(e) = cameras.load() {
-        tracing::warn!("Failed to load cameras: {}", e);
+    #[cfg(feature = "cameras")]
+    let cameras = {
+        let cameras = CameraManager::new(std::path::Path::new(&data_dir));
+        if let Err(e) = cameras.load() {
+            tracing::warn!("Failed to load cameras: {}", e);
+        }
+        Arc::new(cameras)
+    };
+
+    let panels = PanelManager::new(std::path::Path::new(&data_dir));
+    if let Err(e) = panels.load() {
+        tracing::warn!("Failed to load panels: {}", e);
     }
 
+    // Before anything opens device or automation storage
+    let migrations = Migrations::new(std::path::Path::new(&data_dir));
+    migrations.apply_automatic()?;
+
     // Try to connect to Zigbee network (optional)
     let network = {
-        // Get serial port from env or use default
-        let serial_port = std::env::var("CONBEE_PORT").unwrap_or_else(|_| {
-            // Try udev symlink first, then common paths
-            for path in ["/dev/conbee2", "/dev/ttyACM0", "/dev/ttyUSB0"] {
-                if std::path::Path::new(path).exists() {
-                    return path.to_string();
-                }
-            }
-            String::new()
-        });
-
-        if serial_port.is_empty() {
-            tracing::warn!("No Zigbee device found - running without Zigbee support");
-            None
-        } else {
-            tracing::info!("Connecting to ConBee II at {}", serial_port);
-            match ZigbeeNetwork::new(&serial_port).await {
-                Ok(network) => {
-                    // Query and display firmware version
-                    match network.transport().get_version().await {
-                        Ok(version) => tracing::info!("ConBee II firmware: {}", version),
-                        Err(e) => tracing::warn!("Failed to query firmware version: {}", e),
-                    }
+        match find_adapter() {
+            None => None,
+            Some((serial_port, baud_rate)) => {
+                match ZigbeeNetwork::with_baud_rate(&serial_port, baud_rate).await {
+                    Ok(network) => {
+                        // Query and display firmware version
+                        match network.transport().get_version().await {
+                            Ok(version) => tracing::info!("Adapter firmware: {}", version),
+                            Err(e) => tracing::warn!("Failed to query firmware version: {}", e),
+                        }
 
-                    // Query network status
-                    match network.get_status().await {
-                        Ok(status) => {
-                            tracing::info!(
-                                "Network status: connected={}, channel={}, PAN ID={:#06x}",
-                                status.connected,
-                                status.channel,
-                                status.pan_id
-                            );
+                        // Query network status
+                        match network.get_status().await {
+                            Ok(status) => {
+                                tracing::info!(
+                                    "Network status: connected={}, channel={}, PAN ID={:#06x}",
+                                    status.connected,
+                                    status.channel,
+                                    status.pan_id
+                                );
+                            }
+                            Err(e) => tracing::warn!("Failed to query network status: {}", e),
                         }
-                        Err(e) => tracing::warn!("Failed to query network status: {}", e),
+                        Some(Arc::new(network))
                     }
-                    Some(Arc::new(network))
-                }
-                Err(e) => {
-                    tracing::warn!(
+                    // Starting without the stored devices would overwrite them
+                    Err(zigbee_core::network::NetworkError::Storage(e)) => {
+                        tracing::error!("Failed to load devices: {}", e);
+                        return Err(anyhow::anyhow!("Failed to load devices: {e}"));
+                    }
+                    Err(e) => {
+                        tracing::warn!(
                         "Failed to connect to Zigbee device: {} - running without Zigbee support",
                         e
                     );
-                    None
+                        None
+                    }
                 }
             }
         }
     };
 
     // Initialize automation engine
+    #[cfg(feature = "automation")]
     let automations =
         match AutomationEngine::new(network.clone(), std::path::Path::new(&data_dir)).await {
             Ok(engine) => {
+                #[cfg(feature = "mqtt")]
+                let engine = match mqtt_config() {
+                    Some(config) => {
+                        engine.with_mqtt(automation_engine::mqtt::MqttClient::connect(&config))
+                    }
+                    None => engine,
+                };
                 let engine = Arc::new(engine);
                 engine.start();
                 tracing::info!(
@@ -679,24 +1452,168 @@ async fn main() -> anyhow::Result<()> {
             }
         };
 
+    #[cfg(feature = "history")]
+    let history = match HistoryStore::open(std::path::Path::new(&data_dir)) {
+        Ok(store) => {
+            let store = Arc::new(store);
+            if let Some(network) = &network {
+                store.start_recording(network);
+            }
+            Some(store)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to open history store: {} - history disabled", e);
+            None
+        }
+    };
+
+    let auth = AuthManager::new(std::path::Path::new(&data_dir));
+    if let Err(e) = auth.load() {
+        tracing::warn!("Failed to load guest tokens: {}", e);
+    }
+    let auth = Arc::new(auth);
+
+    let mut maintenance = MaintenanceManager::new(
+        std::path::Path::new(&data_dir),
+        #[cfg(feature = "history")]
+        history.clone(),
+        auth.clone(),
+    );
+    if let Err(e) = maintenance.load() {
+        tracing::warn!("Failed to load retention policies: {}", e);
+    }
+    let maintenance = Arc::new(maintenance);
+    maintenance.start();
+
+    migrations.log_pending();
+
+    let mut watchdog = Watchdog::new(std::path::Path::new(&data_dir));
+    if let Err(e) = watchdog.load() {
+        tracing::warn!("Failed to load watchdog config: {}", e);
+    }
+    let watchdog = Arc::new(watchdog);
+    watchdog.start(
+        network.as_ref(),
+        #[cfg(feature = "automation")]
+        &automations,
+    );
+
+    let status_light = StatusLight::new(std::path::Path::new(&data_dir));
+    if let Err(e) = status_light.load() {
+        tracing::warn!("Failed to load status light config: {}", e);
+    }
+    let status_light = Arc::new(status_light);
+    status_light.start(network.as_ref());
+
+    let utilization = network.as_ref().map(ChannelMonitor::start);
+    if let Some(network) = &network {
+        zigbee_core::availability::start_monitor(network);
+    }
+
     let state = AppState {
         network,
-        cameras: Arc::new(cameras),
+        utilization,
+        #[cfg(feature = "cameras")]
+        cameras,
+        #[cfg(feature = "automation")]
         automations,
+        #[cfg(feature = "history")]
+        history,
+        maintenance,
+        migrations: Arc::new(migrations),
+        panels: Arc::new(panels),
+        auth,
+        watchdog,
+        firmware: Arc::new(FirmwareUpdater::new()),
+        status_light,
+        data_dir: Arc::from(std::path::Path::new(&data_dir)),
+        started_at: std::time::Instant::now(),
     };
+    status::start_heartbeat(state.clone());
 
     // Build the router - API routes first (take priority over frontend)
     let app = Router::new()
         // API routes
         .route("/health", get(health))
         .route("/api/v1/system/info", get(system_info))
+        .route("/api/v1/system/metrics", get(system_metrics))
+        .route("/api/v1/system/status.json", get(status::get_status))
+        .route(
+            "/api/v1/system/maintenance",
+            get(maintenance::get_maintenance),
+        )
+        .route("/api/v1/system/watchdog", get(watchdog::get_watchdog))
+        .route(
+            "/api/v1/system/migrations",
+            get(migrations::list_migrations),
+        )
+        .route(
+            "/api/v1/system/migrations/:id",
+            post(migrations::apply_migration),
+        )
+        .route(
+            "/api/v1/system/migrations/:id/revert",
+            post(migrations::revert_migration),
+        )
+        .route(
+            "/api/v1/system/firmware/update",
+            get(firmware::get_firmware_update),
+        )
+        .route(
+            "/api/v1/system/firmware/update",
+            post(firmware::update_firmware),
+        )
+        .route(
+            "/api/v1/system/status-light",
+            get(status_light::get_status_light),
+        )
+        .route(
+            "/api/v1/system/status-light",
+            axum::routing::put(status_light::set_status_light),
+        )
+        .route(
+            "/api/v1/system/status-light/signal",
+            post(status_light::signal_status_light),
+        )
+        .route("/api/v1/debug/capture", get(debug::get_capture))
+        .route("/api/v1/debug/capture", post(debug::set_capture))
+        .route("/api/v1/debug/events", post(debug::inject_event))
+        .route(
+            "/api/v1/system/maintenance/run",
+            post(maintenance::run_maintenance),
+        )
         .route("/api/v1/network/status", get(network_status))
         .route("/api/v1/network/permit-join", post(permit_join))
+        .route("/api/v1/network/state", post(set_network_state))
+        .route("/api/v1/network/configure", post(configure_network))
+        .route(
+            "/api/v1/network/scan",
+            get(scan_network).post(active_scan_network),
+        )
+        .route("/api/v1/network/topology", get(network_topology))
+        .route("/api/v1/network/identify-sweep", post(identify_sweep))
+        .route("/api/v1/network/utilization", get(network_utilization))
+        .route("/api/v1/network/backup", get(backup_network))
+        .route("/api/v1/network/restore", post(restore_network))
         .route("/api/v1/network/aps-data", get(request_aps_data))
         .route("/api/v1/devices", get(list_devices))
         .route("/api/v1/devices/:ieee", get(get_device))
         .route("/api/v1/devices/:ieee", axum::routing::put(update_device))
+        .route("/api/v1/devices/:ieee/card", get(get_device_card))
+        .route("/api/v1/devices/:ieee/diagnostics", get(device_diagnostics))
         .route("/api/v1/devices/:ieee/discover", post(discover_device))
+        .route("/api/v1/devices/:ieee/identify", post(identify_device))
+        .route("/api/v1/devices/:ieee/ota/update", post(ota::start_update))
+        .route("/api/v1/devices/:ieee/routes", get(device_routes))
+        .route("/api/v1/devices/:ieee/image", get(media::get_device_image))
+        .route(
+            "/api/v1/devices/:ieee/image",
+            post(media::upload_device_image),
+        )
+        .route(
+            "/api/v1/devices/:ieee/image",
+            axum::routing::delete(media::delete_device_image),
+        )
         .route(
             "/api/v1/devices/:ieee/endpoints/:endpoint/toggle",
             post(toggle_device),
@@ -709,7 +1626,33 @@ async fn main() -> anyhow::Result<()> {
             "/api/v1/devices/:ieee/endpoints/:endpoint/off",
             post(device_off),
         )
-        // Camera routes
+        .route(
+            "/api/v1/devices/:ieee/endpoints/:endpoint/state",
+            axum::routing::put(set_light_state),
+        )
+        .route(
+            "/api/v1/devices/:ieee/endpoints/:endpoint/clusters/:cluster/attributes/:attribute",
+            axum::routing::put(write_attribute),
+        )
+        .route(
+            "/api/v1/devices/:ieee/endpoints/:endpoint/ias/enroll",
+            post(enroll_ias_zone),
+        )
+        .route("/api/v1/devices/:ieee/tuya", post(set_tuya_value))
+        // Wall-panel routes
+        .route("/api/v1/panel/:panel_id", get(panel::get_panel))
+        .route("/api/v1/auth/guests", get(auth::list_guests))
+        .route("/api/v1/auth/guests", post(auth::create_guest))
+        .route(
+            "/api/v1/auth/guests/:id",
+            axum::routing::delete(auth::revoke_guest),
+        )
+        .route("/api/v1/auth/audit", get(auth::audit_log))
+        .route("/api/v1/guest", get(auth::guest_info));
+
+    // Optional subsystem routes
+    #[cfg(feature = "cameras")]
+    let app = app
         .route("/api/v1/cameras", get(camera::list_cameras))
         .route("/api/v1/cameras", post(camera::add_camera))
         .route("/api/v1/cameras/:id", get(camera::get_camera))
@@ -721,27 +1664,145 @@ async fn main() -> anyhow::Result<()> {
             "/api/v1/cameras/:id",
             axum::routing::delete(camera::delete_camera),
         )
-        .route("/api/v1/cameras/:id/stream", get(camera::stream_proxy))
-        // Automation routes
-        .route("/api/v1/automations", get(list_automations))
-        .route("/api/v1/automations", post(create_automation))
-        .route("/api/v1/automations/:id", get(get_automation))
+        .route("/api/v1/cameras/:id/stream", get(camera::stream_proxy));
+
+    #[cfg(feature = "automation")]
+    let app = app
+        .route("/api/v1/automations", get(automations::list_automations))
+        .route("/api/v1/automations", post(automations::create_automation))
+        .route(
+            "/api/v1/automations/bulk",
+            post(automations::bulk_automations),
+        )
+        .route(
+            "/api/v1/automations/export",
+            get(automations::export_automations),
+        )
+        .route(
+            "/api/v1/automations/import",
+            post(automations::import_automations),
+        )
+        .route(
+            "/api/v1/automations/running",
+            get(automations::running_automations),
+        )
+        .route("/api/v1/automations/:id", get(automations::get_automation))
         .route(
             "/api/v1/automations/:id",
-            axum::routing::put(update_automation),
+            axum::routing::put(automations::update_automation),
         )
         .route(
             "/api/v1/automations/:id",
-            axum::routing::delete(delete_automation),
+            axum::routing::delete(automations::delete_automation),
+        )
+        .route(
+            "/api/v1/automations/:id/trigger",
+            post(automations::trigger_automation),
+        )
+        .route("/api/v1/webhooks/:id", post(automations::fire_webhook))
+        .route(
+            "/api/v1/automations/:id/history",
+            get(automations::automation_history),
+        )
+        .route(
+            "/api/v1/automations/:id/traces/:run_id",
+            get(automations::automation_trace),
+        )
+        .route(
+            "/api/v1/automations/:id/cancel",
+            post(automations::cancel_automation),
+        )
+        .route(
+            "/api/v1/automations/:id/enable",
+            post(automations::enable_automation),
+        )
+        .route(
+            "/api/v1/automations/:id/disable",
+            post(automations::disable_automation),
+        )
+        .route(
+            "/api/v1/automations/:id/snooze",
+            post(automations::snooze_automation),
+        )
+        .route(
+            "/api/v1/automations/:id/snooze",
+            axum::routing::delete(automations::unsnooze_automation),
+        )
+        .route(
+            "/api/v1/automations/:id/test",
+            post(automations::test_automation),
+        )
+        .route("/api/v1/automation-folders", get(automations::list_folders))
+        .route(
+            "/api/v1/automation-folders",
+            post(automations::create_folder),
+        )
+        .route(
+            "/api/v1/automation-folders/:id",
+            axum::routing::put(automations::update_folder),
+        )
+        .route(
+            "/api/v1/automation-folders/:id",
+            axum::routing::delete(automations::delete_folder),
+        )
+        .route("/api/v1/climate/schedules", get(climate::list_schedules))
+        .route("/api/v1/climate/schedules", post(climate::create_schedule))
+        .route("/api/v1/climate/schedules/:id", get(climate::get_schedule))
+        .route(
+            "/api/v1/climate/schedules/:id",
+            axum::routing::put(climate::update_schedule),
+        )
+        .route(
+            "/api/v1/climate/schedules/:id",
+            axum::routing::delete(climate::delete_schedule),
+        )
+        .route("/api/v1/climate/away", get(climate::get_away))
+        .route(
+            "/api/v1/climate/away",
+            axum::routing::put(climate::set_away),
         )
-        .route("/api/v1/automations/:id/trigger", post(trigger_automation))
-        .route("/api/v1/automations/:id/enable", post(enable_automation))
-        .route("/api/v1/automations/:id/disable", post(disable_automation))
+        .route("/api/v1/presence", get(presence::list_presence))
+        .route("/api/v1/presence/:person", get(presence::get_presence))
+        .route(
+            "/api/v1/presence/:person/location",
+            post(presence::update_location),
+        );
+
+    #[cfg(feature = "history")]
+    let app = app.route(
+        "/api/v1/devices/:ieee/history",
+        get(history::device_history),
+    );
+
+    #[cfg(all(feature = "automation", feature = "history"))]
+    let app = app.route(
+        "/api/v1/automations/:id/backtest",
+        post(automations::backtest_automation),
+    );
+
+    #[cfg(feature = "graphql")]
+    let app = app.route("/api/v1/graphql", post(graphql::graphql));
+
+    let app = app
         // WebSocket
         .route("/ws", get(ws_handler))
         // Middleware
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ))
+        // Tokens and webhook secrets may come in the query; keep them out of logs
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %auth::redacted_uri(request.uri()),
+                    version = ?request.version(),
+                )
+            }),
+        )
+        .layer(cors::layer_from_env()?)
         .with_state(state);
 
     // Add frontend serving based on feature flags
@@ -759,12 +1820,148 @@ async fn main() -> anyhow::Result<()> {
             .nest_service("/js", ServeDir::new("webapp/js"))
     };
 
-    // Start server
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 3000));
-    tracing::info!("Listening on http://{}", addr);
+    // Start server on every configured address
+    let bind_env = std::env::var("BIND_ADDR").unwrap_or_default();
+    let addrs = parse_bind_addrs(&bind_env, DEFAULT_PORT)?;
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let mut servers = Vec::with_capacity(addrs.len());
+    for &addr in &addrs {
+        // IPv6 sockets are dual-stack unless an IPv4 address is also bound on the same port
+        let v6_only = addrs.iter().any(|a| a.is_ipv4() && a.port() == addr.port());
+        let listener = bind_listener(addr, v6_only)?;
+        tracing::info!("Listening on http://{}", listener.local_addr()?);
+        let app = app.clone();
+        servers.push(async move { axum::serve(listener, app).await });
+    }
+    futures::future::try_join_all(servers).await?;
 
     Ok(())
 }
+
+/// Bind a TCP listener, controlling `IPV6_V6ONLY` explicitly for IPv6 addresses
+fn bind_listener(addr: SocketAddr, v6_only: bool) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+/// Locate the Zigbee adapter and its baud rate
+///
+/// `CONBEE_PORT` overrides discovery and `CONBEE_BAUD` overrides the baud
+/// rate; otherwise serial ports are enumerated for a known adapter.
+fn find_adapter() -> Option<(String, u32)> {
+    let baud_override = std::env::var("CONBEE_BAUD")
+        .ok()
+        .and_then(|v| match v.parse::<u32>() {
+            Ok(baud) => Some(baud),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid CONBEE_BAUD '{}'", v);
+                None
+            }
+        });
+
+    if let Ok(path) = std::env::var("CONBEE_PORT") {
+        let baud_rate = baud_override.unwrap_or(deconz_protocol::transport::BAUD_RATE);
+        tracing::info!(
+            "Connecting to Zigbee adapter at {} ({} baud)",
+            path,
+            baud_rate
+        );
+        return Some((path, baud_rate));
+    }
+
+    match DeconzTransport::discover() {
+        Ok(adapter) => {
+            let baud_rate = baud_override.unwrap_or(adapter.model.default_baud_rate());
+            tracing::info!(
+                "Found {} at {} ({} baud)",
+                adapter.model,
+                adapter.path,
+                baud_rate
+            );
+            Some((adapter.path, baud_rate))
+        }
+        Err(ProtocolError::AdapterNotFound) => {
+            tracing::warn!("No Zigbee adapter found - running without Zigbee support");
+            None
+        }
+        Err(e) => {
+            tracing::error!("{} - running without Zigbee support", e);
+            None
+        }
+    }
+}
+
+/// MQTT broker from `MQTT_HOST` (`host` or `host:port`, port 1883 by
+/// default), with `MQTT_USERNAME`, `MQTT_PASSWORD` and `MQTT_CLIENT_ID`
+#[cfg(feature = "mqtt")]
+fn mqtt_config() -> Option<automation_engine::mqtt::MqttConfig> {
+    let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let address = var("MQTT_HOST")?;
+    // IPv6 addresses have colons of their own: `[::1]:1883`, `[::1]` or `::1`
+    let (host, port) = match address.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+            None => (address.as_str(), Some("")),
+        },
+        None if address.parse::<std::net::Ipv6Addr>().is_ok() => (address.as_str(), None),
+        None => match address.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address.as_str(), None),
+        },
+    };
+    let Ok(port) = port.map_or(Ok(1883), str::parse::<u16>) else {
+        tracing::warn!("Ignoring invalid MQTT_HOST '{}'", address);
+        return None;
+    };
+    let host = host.to_string();
+    tracing::info!("Using MQTT broker at {}:{}", host, port);
+    Some(automation_engine::mqtt::MqttConfig {
+        host,
+        port,
+        client_id: var("MQTT_CLIENT_ID").unwrap_or_else(|| "casita-assistant".to_string()),
+        username: var("MQTT_USERNAME"),
+        password: var("MQTT_PASSWORD"),
+    })
+}
+
+/// Default HTTP port when `BIND_ADDR` omits one
+const DEFAULT_PORT: u16 = 3000;
+
+/// Parse the comma-separated `BIND_ADDR` list (e.g. `0.0.0.0:3000,[::]:3000` or `192.168.1.5`)
+///
+/// Entries without a port use `default_port`. An empty list binds all IPv4 interfaces.
+fn parse_bind_addrs(value: &str, default_port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let addr = entry
+            .parse::<SocketAddr>()
+            .ok()
+            .or_else(|| {
+                entry
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .ok()
+                    .map(|ip| SocketAddr::new(ip, default_port))
+            })
+            .ok_or_else(|| anyhow::anyhow!("Invalid bind address: {entry}"))?;
+        addrs.push(addr);
+    }
+
+    if addrs.is_empty() {
+        addrs.push(SocketAddr::from(([0, 0, 0, 0], default_port)));
+    }
+    Ok(addrs)
+}
@@ -0,0 +1,396 @@
+//! Retention and maintenance
+//!
+//! Enforces per-data-class retention (age and size) configured in
+//! `retention.json`. A maintenance pass runs every `MAINTENANCE_INTERVAL` and
+//! can be triggered on demand via the API; progress is kept in memory for the
+//! status endpoint.
+//!
+//! The data classes are device history and the audit log. There is no
+//! separate event journal yet: network events are stored as device history.
+//! Cameras are only streamed, never recorded, so there are no recordings to
+//! prune. A new store gets retention by adding a step to [`STEPS`], a policy
+//! to [`RetentionConfig`] and a branch to `run_step`.
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::auth::AuthManager;
+#[cfg(feature = "history")]
+use crate::history::HistoryStore;
+use crate::{ApiResponse, AppState};
+
+/// Time between scheduled maintenance passes
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Data classes processed by a maintenance pass, in order
+const STEPS: &[&str] = &[
+    #[cfg(feature = "history")]
+    "device_history",
+    "audit_log",
+];
+
+/// Retention policy for one data class (unset limits are not enforced)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    pub max_age_days: Option<u32>,
+    #[serde(default)]
+    pub max_size_mb: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Data older than this is deleted; `None` if nothing can be that old
+    fn cutoff(&self) -> Option<SystemTime> {
+        self.max_age_days.and_then(|days| {
+            SystemTime::now().checked_sub(Duration::from_secs(u64::from(days) * 24 * 60 * 60))
+        })
+    }
+
+    /// Size limit; `None` if it is too large to count
+    fn max_bytes(&self) -> Option<u64> {
+        self.max_size_mb.and_then(|mb| mb.checked_mul(1024 * 1024))
+    }
+}
+
+/// Retention configuration (`retention.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Device event history (`history.db`)
+    #[serde(default = "default_history_policy")]
+    pub device_history: RetentionPolicy,
+    /// Guest activity log (`audit.log`)
+    #[serde(default = "default_audit_policy")]
+    pub audit_log: RetentionPolicy,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            device_history: default_history_policy(),
+            audit_log: default_audit_policy(),
+        }
+    }
+}
+
+fn default_history_policy() -> RetentionPolicy {
+    RetentionPolicy {
+        max_age_days: Some(30),
+        max_size_mb: Some(512),
+    }
+}
+
+fn default_audit_policy() -> RetentionPolicy {
+    RetentionPolicy {
+        max_age_days: Some(90),
+        max_size_mb: Some(16),
+    }
+}
+
+/// Outcome of maintenance for one data class
+#[derive(Debug, Clone, Serialize)]
+pub struct StepReport {
+    pub data_class: &'static str,
+    pub items_deleted: u64,
+    pub bytes_freed: u64,
+    pub error: Option<String>,
+}
+
+/// Maintenance progress and last result
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MaintenanceStatus {
+    pub running: bool,
+    /// Data class currently being processed
+    pub current_step: Option<&'static str>,
+    pub steps_done: usize,
+    pub steps_total: usize,
+    /// Unix seconds
+    pub last_started: Option<u64>,
+    pub last_finished: Option<u64>,
+    pub last_report: Vec<StepReport>,
+}
+
+pub struct MaintenanceManager {
+    config: RetentionConfig,
+    data_dir: PathBuf,
+    #[cfg(feature = "history")]
+    history: Option<Arc<HistoryStore>>,
+    auth: Arc<AuthManager>,
+    running: AtomicBool,
+    status: Mutex<MaintenanceStatus>,
+}
+
+impl MaintenanceManager {
+    pub fn new(
+        data_dir: &Path,
+        #[cfg(feature = "history")] history: Option<Arc<HistoryStore>>,
+        auth: Arc<AuthManager>,
+    ) -> Self {
+        Self {
+            config: RetentionConfig::default(),
+            data_dir: data_dir.to_path_buf(),
+            #[cfg(feature = "history")]
+            history,
+            auth,
+            running: AtomicBool::new(false),
+            status: Mutex::new(MaintenanceStatus::default()),
+        }
+    }
+
+    /// Load `retention.json` (defaults are used if it doesn't exist)
+    pub fn load(&mut self) -> anyhow::Result<()> {
+        let path = self.data_dir.join("retention.json");
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            self.config = serde_json::from_str(&content)?;
+            tracing::info!("Loaded retention policies from {:?}", path);
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> &RetentionConfig {
+        &self.config
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        self.lock_status().clone()
+    }
+
+    fn lock_status(&self) -> std::sync::MutexGuard<'_, MaintenanceStatus> {
+        self.status
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Run maintenance periodically in the background
+    pub fn start(self: &Arc<Self>) {
+        let manager = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(MAINTENANCE_INTERVAL);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                manager.run().await;
+            }
+        });
+    }
+
+    /// Start a maintenance pass in the background; returns `false` if one is already running
+    pub fn trigger(self: &Arc<Self>) -> bool {
+        if !self.begin() {
+            return false;
+        }
+        let manager = Arc::clone(self);
+        tokio::spawn(async move { manager.run_pass().await });
+        true
+    }
+
+    /// Run a full maintenance pass (no-op if one is already running)
+    pub async fn run(self: &Arc<Self>) {
+        if self.begin() {
+            self.run_pass().await;
+        }
+    }
+
+    /// Claim the running flag and reset progress
+    fn begin(&self) -> bool {
+        if self.running.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let mut status = self.lock_status();
+        status.running = true;
+        status.current_step = None;
+        status.steps_done = 0;
+        status.steps_total = STEPS.len();
+        status.last_started = Some(unix_now());
+        true
+    }
+
+    async fn run_pass(self: &Arc<Self>) {
+        let steps = STEPS;
+        tracing::info!("Maintenance started ({} steps)", steps.len());
+
+        let mut reports = Vec::new();
+        for &step in steps {
+            self.lock_status().current_step = Some(step);
+            let report = self.run_step(step).await;
+            if let Some(e) = &report.error {
+                tracing::warn!("Maintenance step {} failed: {}", step, e);
+            } else {
+                tracing::info!(
+                    "Maintenance step {}: {} items deleted, {} bytes freed",
+                    step,
+                    report.items_deleted,
+                    report.bytes_freed
+                );
+            }
+            reports.push(report);
+            self.lock_status().steps_done += 1;
+        }
+
+        {
+            let mut status = self.lock_status();
+            status.running = false;
+            status.current_step = None;
+            status.last_finished = Some(unix_now());
+            status.last_report = reports;
+        }
+        self.running.store(false, Ordering::Release);
+    }
+
+    async fn run_step(self: &Arc<Self>, step: &'static str) -> StepReport {
+        let manager = Arc::clone(self);
+        let result = tokio::task::spawn_blocking(move || match step {
+            #[cfg(feature = "history")]
+            "device_history" => manager.prune_history(),
+            _ => manager.auth.prune_audit(
+                manager.config.audit_log.cutoff(),
+                manager.config.audit_log.max_bytes(),
+            ),
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("maintenance task panicked: {e}"))
+        .and_then(|r| r);
+
+        match result {
+            Ok((items_deleted, bytes_freed)) => StepReport {
+                data_class: step,
+                items_deleted,
+                bytes_freed,
+                error: None,
+            },
+            Err(e) => StepReport {
+                data_class: step,
+                items_deleted: 0,
+                bytes_freed: 0,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+
+    #[cfg(feature = "history")]
+    fn prune_history(&self) -> anyhow::Result<(u64, u64)> {
+        let Some(history) = &self.history else {
+            return Ok((0, 0));
+        };
+        let policy = &self.config.device_history;
+        let cutoff = policy.cutoff().map(|t| {
+            let age = SystemTime::now()
+                .duration_since(t)
+                .unwrap_or_default()
+                .as_millis();
+            crate::history::now_millis().saturating_sub(i64::try_from(age).unwrap_or(i64::MAX))
+        });
+        let stats = history.prune(cutoff, policy.max_bytes())?;
+        Ok((
+            stats.rows_deleted,
+            stats.bytes_before.saturating_sub(stats.bytes_after),
+        ))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// =============================================================================
+// HTTP Handlers
+// =============================================================================
+
+/// Response for the maintenance status endpoint
+#[derive(Serialize)]
+struct MaintenanceResponse {
+    status: MaintenanceStatus,
+    retention: RetentionConfig,
+}
+
+pub async fn get_maintenance(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(MaintenanceResponse {
+        status: state.maintenance.status(),
+        retention: state.maintenance.config().clone(),
+    }))
+}
+
+pub async fn run_maintenance(State(state): State<AppState>) -> impl IntoResponse {
+    if state.maintenance.trigger() {
+        (
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::success(state.maintenance.status())),
+        )
+    } else {
+        (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error("Maintenance is already running")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retention_limits() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(1),
+            max_size_mb: Some(2),
+        };
+        assert!(policy.cutoff().unwrap() < SystemTime::now());
+        assert_eq!(policy.max_bytes(), Some(2 * 1024 * 1024));
+
+        // Limits too large to represent aren't enforced
+        let policy = RetentionPolicy {
+            max_age_days: Some(u32::MAX),
+            max_size_mb: Some(u64::MAX),
+        };
+        assert_eq!(policy.max_bytes(), None);
+        let _ = policy.cutoff();
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_prunes_audit_log() {
+        let data_dir =
+            std::env::temp_dir().join(format!("casita-maintenance-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let line = |timestamp| {
+            serde_json::json!({
+                "timestamp": timestamp,
+                "actor": "guest:g",
+                "action": "request",
+                "detail": "GET /api/v1/guest -> 200",
+            })
+            .to_string()
+        };
+        let now = unix_now();
+        let lines = [line(now - 100 * 86_400), line(now)];
+        std::fs::write(data_dir.join("audit.log"), lines.join("\n") + "\n").unwrap();
+
+        let manager = Arc::new(MaintenanceManager::new(
+            &data_dir,
+            #[cfg(feature = "history")]
+            None,
+            Arc::new(AuthManager::new(&data_dir)),
+        ));
+        manager.run().await;
+
+        let status = manager.status();
+        assert!(!status.running);
+        assert_eq!(status.steps_done, STEPS.len());
+        let audit = status
+            .last_report
+            .iter()
+            .find(|report| report.data_class == "audit_log")
+            .unwrap();
+        assert_eq!(audit.error, None);
+        assert_eq!(audit.items_deleted, 1);
+        assert_eq!(audit.bytes_freed, lines[0].len() as u64 + 1);
+        let kept = std::fs::read_to_string(data_dir.join("audit.log")).unwrap();
+        assert_eq!(kept, format!("{}\n", lines[1]));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
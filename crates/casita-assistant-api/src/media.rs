@@ -0,0 +1,234 @@
+//! Device images
+//!
+//! Images are uploaded as the raw request body of
+//! `POST /api/v1/devices/:ieee/image` and stored as `data/media/<ieee>.<ext>`;
+//! the file name is recorded in the device's metadata.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::path::PathBuf;
+
+use crate::{ApiResponse, AppState, DeviceJson};
+
+/// Largest accepted image upload
+pub const MAX_IMAGE_BYTES: usize = 1024 * 1024;
+
+/// Longest accepted notes text
+pub const MAX_NOTES_LEN: usize = 4096;
+
+/// Accepted image types and their file extensions
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/webp", "webp"),
+    ("image/gif", "gif"),
+];
+
+fn media_dir(state: &AppState) -> PathBuf {
+    state.data_dir.join("media")
+}
+
+fn content_type_for(file_name: &str) -> &'static str {
+    let ext = file_name.rsplit('.').next().unwrap_or_default();
+    IMAGE_TYPES
+        .iter()
+        .find(|(_, e)| *e == ext)
+        .map_or("application/octet-stream", |(t, _)| t)
+}
+
+/// Whether `date` looks like `YYYY-MM-DD`
+#[must_use]
+pub fn is_valid_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return false;
+    };
+    let number = |s: &str, len: usize| {
+        (s.len() == len && s.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| s.parse::<u32>().ok())
+            .flatten()
+    };
+    matches!(
+        (number(year, 4), number(month, 2), number(day, 2)),
+        (Some(_), Some(1..=12), Some(1..=31))
+    )
+}
+
+/// Upload a device image (PNG, JPEG, WebP or GIF, raw body)
+pub async fn upload_device_image(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = crate::parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+    let Some(device) = network.get_device(&ieee_bytes) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        );
+    };
+    if body.len() > MAX_IMAGE_BYTES {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ApiResponse::error(format!(
+                "Image exceeds {MAX_IMAGE_BYTES} bytes"
+            ))),
+        );
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let Some((_, ext)) = IMAGE_TYPES.iter().find(|(t, _)| *t == content_type) else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ApiResponse::error(
+                "Content-Type must be image/png, image/jpeg, image/webp or image/gif",
+            )),
+        );
+    };
+
+    let dir = media_dir(&state);
+    let file_name = format!("{}.{ext}", device.ieee_address_string().replace(':', ""));
+    let result = async {
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join(&file_name), &body).await
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to store device image: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(format!("Failed to store image: {e}"))),
+        );
+    }
+
+    // Drop the previous image if it had another extension
+    if let Some(old) = device.metadata.image.filter(|old| *old != file_name) {
+        let _ = tokio::fs::remove_file(dir.join(old)).await;
+    }
+
+    match network.update_device_with(&ieee_bytes, |d| d.metadata.image = Some(file_name)) {
+        Ok(device) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(DeviceJson::from(device))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Serve a device's uploaded image
+pub async fn get_device_image(State(state): State<AppState>, Path(ieee): Path<String>) -> Response {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        )
+            .into_response();
+    };
+    let image = crate::parse_ieee_address(&ieee)
+        .ok()
+        .and_then(|ieee| network.get_device(&ieee))
+        .and_then(|device| device.metadata.image);
+    let Some(file_name) = image else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device has no image")),
+        )
+            .into_response();
+    };
+
+    match tokio::fs::read(media_dir(&state).join(&file_name)).await {
+        Ok(data) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, content_type_for(&file_name)),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            data,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::warn!("Device image {} unreadable: {}", file_name, e);
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Device has no image")),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Remove a device's uploaded image
+pub async fn delete_device_image(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = crate::parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+
+    let mut removed = None;
+    match network.update_device_with(&ieee_bytes, |d| removed = d.metadata.image.take()) {
+        Ok(device) => {
+            if let Some(file_name) = removed {
+                let _ = tokio::fs::remove_file(media_dir(&state).join(file_name)).await;
+            }
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success(DeviceJson::from(device))),
+            )
+        }
+        Err(zigbee_core::network::NetworkError::DeviceNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_date() {
+        assert!(is_valid_date("2024-03-17"));
+        assert!(!is_valid_date("2024-13-01"));
+        assert!(!is_valid_date("24-03-17"));
+        assert!(!is_valid_date("2024-3-17"));
+        assert!(!is_valid_date("last tuesday"));
+    }
+}
@@ -0,0 +1,682 @@
+//! Data migrations
+//!
+//! Long-lived installs carry data files written by older versions.
+//! `GET /api/v1/system/migrations` lists every known migration with a dry
+//! run of what it would change; `POST /api/v1/system/migrations/:id` applies
+//! one and `POST /api/v1/system/migrations/:id/revert` undoes it. What is
+//! needed to undo a migration is kept in `migrations.json`, and every apply
+//! and revert is recorded in the audit log. Pending migrations are logged at
+//! startup, and the ones the server can't start without are applied then.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path as FsPath;
+use std::sync::Mutex;
+
+use crate::secrets::{self, SecretKey};
+use crate::{ApiResponse, AppState};
+
+/// Data files that hold passwords or token hashes
+const CREDENTIAL_FILES: &[&str] = &["cameras.json", "green_power.json", "guests.json"];
+
+/// A known migration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationId {
+    /// Make files with credentials readable by the owner only
+    CredentialFilePermissions,
+    /// Stamp a `history.db` from before schema versioning
+    #[cfg(feature = "history")]
+    HistorySchemaVersion,
+    /// Import devices and automations into SQLite
+    #[cfg(feature = "sqlite")]
+    JsonToSqlite,
+    /// Encrypt camera passwords (see [`crate::secrets`])
+    EncryptCredentials,
+}
+
+impl MigrationId {
+    const ALL: &'static [MigrationId] = &[
+        MigrationId::CredentialFilePermissions,
+        #[cfg(feature = "history")]
+        MigrationId::HistorySchemaVersion,
+        #[cfg(feature = "sqlite")]
+        MigrationId::JsonToSqlite,
+        MigrationId::EncryptCredentials,
+    ];
+
+    /// Applied at startup: SQLite storage doesn't open with records left
+    /// in JSON files
+    fn automatic(self) -> bool {
+        match self {
+            #[cfg(feature = "sqlite")]
+            MigrationId::JsonToSqlite => true,
+            _ => false,
+        }
+    }
+
+    fn parse(id: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(id.to_string())).ok()
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            MigrationId::CredentialFilePermissions => {
+                "Restrict files holding camera passwords and guest tokens to the owner (mode 600)"
+            }
+            #[cfg(feature = "history")]
+            MigrationId::HistorySchemaVersion => {
+                "Record the schema version in history.db so later layout changes can be detected"
+            }
+            #[cfg(feature = "sqlite")]
+            MigrationId::JsonToSqlite => {
+                "Import devices and automations into casita.db for STORAGE_BACKEND=sqlite; revert before going back to json"
+            }
+            MigrationId::EncryptCredentials => {
+                "Encrypt camera passwords in cameras.json with a key in secret.key"
+            }
+        }
+    }
+
+    /// What applying would change; empty when there is nothing to do
+    fn preview(self, data_dir: &FsPath) -> anyhow::Result<Vec<String>> {
+        match self {
+            MigrationId::CredentialFilePermissions => Ok(loose_credential_files(data_dir)?
+                .into_iter()
+                .map(|(name, mode)| format!("{name}: mode {mode:o} -> 600"))
+                .collect()),
+            #[cfg(feature = "history")]
+            MigrationId::HistorySchemaVersion => {
+                let version = history_version(data_dir)?;
+                Ok(match version {
+                    Some(0) => vec![format!(
+                        "history.db: schema version 0 -> {}",
+                        crate::history::SCHEMA_VERSION
+                    )],
+                    _ => Vec::new(),
+                })
+            }
+            #[cfg(feature = "sqlite")]
+            MigrationId::JsonToSqlite => {
+                use zigbee_core::storage::Backend;
+                if Backend::from_env() != Backend::Sqlite {
+                    return Ok(Vec::new());
+                }
+                let mut preview = Vec::new();
+                for kind in STORED_KINDS {
+                    let path = data_dir.join(format!("{kind}.json"));
+                    if path.exists() {
+                        let records: Vec<serde_json::Value> =
+                            serde_json::from_str(&std::fs::read_to_string(path)?)?;
+                        preview.push(format!(
+                            "{kind}.json: {} {kind} -> {}",
+                            records.len(),
+                            zigbee_core::storage::sqlite::DB_FILE
+                        ));
+                    }
+                }
+                Ok(preview)
+            }
+            MigrationId::EncryptCredentials => {
+                let plain = read_cameras(data_dir)?
+                    .iter()
+                    .filter(|camera| plain_password(camera).is_some())
+                    .count();
+                Ok(if plain == 0 {
+                    Vec::new()
+                } else {
+                    vec![format!(
+                        "cameras.json: {plain} camera passwords -> encrypted"
+                    )]
+                })
+            }
+        }
+    }
+
+    /// Apply, returning what [`Self::revert`] needs
+    fn apply(self, data_dir: &FsPath) -> anyhow::Result<serde_json::Value> {
+        match self {
+            MigrationId::CredentialFilePermissions => {
+                let modes = loose_credential_files(data_dir)?;
+                for name in modes.keys() {
+                    set_mode(&data_dir.join(name), 0o600)?;
+                }
+                Ok(serde_json::to_value(modes)?)
+            }
+            #[cfg(feature = "history")]
+            MigrationId::HistorySchemaVersion => {
+                let conn = rusqlite::Connection::open(data_dir.join("history.db"))?;
+                conn.pragma_update(None, "user_version", crate::history::SCHEMA_VERSION)?;
+                Ok(serde_json::Value::Null)
+            }
+            #[cfg(feature = "sqlite")]
+            MigrationId::JsonToSqlite => Ok(serde_json::to_value(import_json(data_dir)?)?),
+            MigrationId::EncryptCredentials => {
+                let key = match SecretKey::load(data_dir)? {
+                    Some(key) => key,
+                    None => SecretKey::create(data_dir)?,
+                };
+                let mut cameras = read_cameras(data_dir)?;
+                for camera in &mut cameras {
+                    if let Some(password) = plain_password(camera) {
+                        camera["password"] = key.encrypt(password).into();
+                    }
+                }
+                write_cameras(data_dir, &cameras)?;
+                Ok(serde_json::Value::Null)
+            }
+        }
+    }
+
+    fn revert(self, data_dir: &FsPath, undo: serde_json::Value) -> anyhow::Result<()> {
+        match self {
+            MigrationId::CredentialFilePermissions => {
+                let modes: BTreeMap<String, u32> = serde_json::from_value(undo)?;
+                for (name, mode) in modes {
+                    set_mode(&data_dir.join(name), mode)?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "history")]
+            MigrationId::HistorySchemaVersion => {
+                let conn = rusqlite::Connection::open(data_dir.join("history.db"))?;
+                conn.pragma_update(None, "user_version", 0)?;
+                Ok(())
+            }
+            #[cfg(feature = "sqlite")]
+            MigrationId::JsonToSqlite => {
+                let kinds: Vec<String> = serde_json::from_value(undo)?;
+                export_json(data_dir, &kinds)?;
+                Ok(())
+            }
+            MigrationId::EncryptCredentials => {
+                // Saves store passwords in the clear again once the key is gone
+                let Some(key) = SecretKey::load(data_dir)? else {
+                    return Ok(());
+                };
+                let mut cameras = read_cameras(data_dir)?;
+                for camera in &mut cameras {
+                    let Some(password) = camera["password"].as_str() else {
+                        continue;
+                    };
+                    if secrets::is_encrypted(password) {
+                        let plain = key.decrypt(password).ok_or_else(|| {
+                            anyhow::anyhow!("Can't decrypt a password in cameras.json")
+                        })?;
+                        camera["password"] = plain.into();
+                    }
+                }
+                write_cameras(data_dir, &cameras)?;
+                std::fs::remove_file(data_dir.join(secrets::KEY_FILE))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Kinds of records in `zigbee_core::storage`
+#[cfg(feature = "sqlite")]
+const STORED_KINDS: &[&str] = &[
+    <zigbee_core::ZigbeeDevice as zigbee_core::storage::Record>::KIND,
+    #[cfg(feature = "automation")]
+    <automation_engine::model::Automation as zigbee_core::storage::Record>::KIND,
+];
+
+/// Import every kind with a JSON file, returning the kinds imported; a
+/// failure puts back the ones already done
+#[cfg(feature = "sqlite")]
+fn import_json(data_dir: &FsPath) -> anyhow::Result<Vec<String>> {
+    use zigbee_core::storage::{sqlite::SqliteStorage, JsonFile};
+
+    let db = SqliteStorage::open(&data_dir.join(zigbee_core::storage::sqlite::DB_FILE))?;
+    let mut imported = Vec::new();
+    for &kind in STORED_KINDS {
+        let json = JsonFile::new(data_dir.join(format!("{kind}.json")));
+        if !json.path().exists() {
+            continue;
+        }
+        let result = match kind {
+            #[cfg(feature = "automation")]
+            "automations" => db.import::<automation_engine::model::Automation>(&json),
+            _ => db.import::<zigbee_core::ZigbeeDevice>(&json),
+        };
+        if let Err(e) = result {
+            if let Err(undo) = export_json(data_dir, &imported) {
+                tracing::error!("Failed to undo a partial import: {}", undo);
+            }
+            return Err(anyhow::anyhow!("Failed to import {kind}.json: {e}"));
+        }
+        imported.push(kind.to_string());
+    }
+    Ok(imported)
+}
+
+/// Write imported kinds back to their JSON files
+#[cfg(feature = "sqlite")]
+fn export_json(data_dir: &FsPath, kinds: &[String]) -> anyhow::Result<()> {
+    use zigbee_core::storage::{sqlite::SqliteStorage, JsonFile};
+
+    let db = SqliteStorage::open(&data_dir.join(zigbee_core::storage::sqlite::DB_FILE))?;
+    for kind in kinds {
+        let json = JsonFile::new(data_dir.join(format!("{kind}.json")));
+        match kind.as_str() {
+            #[cfg(feature = "automation")]
+            "automations" => db.export::<automation_engine::model::Automation>(&json)?,
+            _ => db.export::<zigbee_core::ZigbeeDevice>(&json)?,
+        };
+    }
+    Ok(())
+}
+
+/// `cameras.json` as plain JSON, so passwords can be rewritten without the
+/// camera feature
+fn read_cameras(data_dir: &FsPath) -> anyhow::Result<Vec<serde_json::Value>> {
+    match std::fs::read_to_string(data_dir.join("cameras.json")) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Rewrite `cameras.json` in place, keeping its mode
+fn write_cameras(data_dir: &FsPath, cameras: &[serde_json::Value]) -> anyhow::Result<()> {
+    std::fs::write(
+        data_dir.join("cameras.json"),
+        serde_json::to_string_pretty(cameras)?,
+    )?;
+    Ok(())
+}
+
+fn plain_password(camera: &serde_json::Value) -> Option<&str> {
+    camera["password"]
+        .as_str()
+        .filter(|password| !secrets::is_encrypted(password))
+}
+
+/// Credential files readable by group or others, with their modes
+#[cfg(unix)]
+fn loose_credential_files(data_dir: &FsPath) -> anyhow::Result<BTreeMap<String, u32>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut loose = BTreeMap::new();
+    for name in CREDENTIAL_FILES {
+        match std::fs::metadata(data_dir.join(name)) {
+            Ok(metadata) => {
+                let mode = metadata.permissions().mode() & 0o777;
+                if mode & 0o077 != 0 {
+                    loose.insert((*name).to_string(), mode);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(loose)
+}
+
+#[cfg(not(unix))]
+fn loose_credential_files(_data_dir: &FsPath) -> anyhow::Result<BTreeMap<String, u32>> {
+    Ok(BTreeMap::new())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &FsPath, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &FsPath, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// `user_version` of `history.db`, or `None` if there is none yet
+#[cfg(feature = "history")]
+fn history_version(data_dir: &FsPath) -> anyhow::Result<Option<i32>> {
+    let path = data_dir.join("history.db");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let conn = rusqlite::Connection::open(path)?;
+    Ok(Some(
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))?,
+    ))
+}
+
+/// An applied migration in `migrations.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct Applied {
+    applied_at: DateTime<Utc>,
+    undo: serde_json::Value,
+}
+
+/// A migration as listed by the API
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    pub id: MigrationId,
+    pub description: &'static str,
+    pub pending: bool,
+    /// Dry run: what applying would change
+    pub preview: Vec<String>,
+    /// Set while the migration can be reverted
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+/// Why a migration couldn't be applied or reverted
+#[derive(Debug)]
+pub enum MigrationError {
+    NothingToDo,
+    NotApplied,
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for MigrationError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Failed(e)
+    }
+}
+
+/// Runs migrations and remembers how to undo them
+pub struct Migrations {
+    data_dir: std::path::PathBuf,
+    /// Serializes applies and reverts
+    lock: Mutex<()>,
+}
+
+impl Migrations {
+    pub fn new(data_dir: &FsPath) -> Self {
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn state_path(&self) -> std::path::PathBuf {
+        self.data_dir.join("migrations.json")
+    }
+
+    fn load(&self) -> BTreeMap<MigrationId, Applied> {
+        std::fs::read_to_string(self.state_path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn store(&self, applied: &BTreeMap<MigrationId, Applied>) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.data_dir)?;
+        std::fs::write(self.state_path(), serde_json::to_string_pretty(applied)?)?;
+        Ok(())
+    }
+
+    fn status_of(
+        &self,
+        id: MigrationId,
+        applied: &BTreeMap<MigrationId, Applied>,
+    ) -> MigrationStatus {
+        let preview = id.preview(&self.data_dir).unwrap_or_else(|e| {
+            tracing::warn!("Failed to check migration {:?}: {}", id, e);
+            Vec::new()
+        });
+        MigrationStatus {
+            id,
+            description: id.description(),
+            pending: !preview.is_empty(),
+            preview,
+            applied_at: applied.get(&id).map(|a| a.applied_at),
+        }
+    }
+
+    /// Every known migration with its dry run
+    pub fn list(&self) -> Vec<MigrationStatus> {
+        let applied = self.load();
+        MigrationId::ALL
+            .iter()
+            .map(|&id| self.status_of(id, &applied))
+            .collect()
+    }
+
+    pub fn apply(&self, id: MigrationId) -> Result<MigrationStatus, MigrationError> {
+        let _guard = self
+            .lock
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if id.preview(&self.data_dir)?.is_empty() {
+            return Err(MigrationError::NothingToDo);
+        }
+        let undo = id.apply(&self.data_dir)?;
+        let mut applied = self.load();
+        applied.insert(
+            id,
+            Applied {
+                applied_at: Utc::now(),
+                undo,
+            },
+        );
+        self.store(&applied)?;
+        tracing::info!("Applied migration {:?}", id);
+        Ok(self.status_of(id, &applied))
+    }
+
+    pub fn revert(&self, id: MigrationId) -> Result<MigrationStatus, MigrationError> {
+        let _guard = self
+            .lock
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut applied = self.load();
+        let Some(entry) = applied.remove(&id) else {
+            return Err(MigrationError::NotApplied);
+        };
+        id.revert(&self.data_dir, entry.undo)?;
+        self.store(&applied)?;
+        tracing::info!("Reverted migration {:?}", id);
+        Ok(self.status_of(id, &applied))
+    }
+
+    /// Apply the pending migrations the server can't start without
+    pub fn apply_automatic(&self) -> anyhow::Result<()> {
+        for &id in MigrationId::ALL.iter().filter(|id| id.automatic()) {
+            if let Err(MigrationError::Failed(e)) = self.apply(id) {
+                return Err(e.context(format!("Migration {id:?} failed")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Log migrations waiting to be applied
+    pub fn log_pending(&self) {
+        let pending: Vec<_> = self.list().into_iter().filter(|m| m.pending).collect();
+        for migration in &pending {
+            tracing::warn!(
+                "Data migration {:?} is pending: {}",
+                migration.id,
+                migration.preview.join("; ")
+            );
+        }
+        if !pending.is_empty() {
+            tracing::warn!("Review and apply them at /api/v1/system/migrations");
+        }
+    }
+}
+
+/// List migrations with a dry run of each
+pub async fn list_migrations(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.migrations.list()))
+}
+
+fn run(state: &AppState, id: &str, revert: bool) -> (StatusCode, Json<ApiResponse>) {
+    let Some(migration) = MigrationId::parse(id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Unknown migration: {id}"))),
+        );
+    };
+    let result = if revert {
+        state.migrations.revert(migration)
+    } else {
+        state.migrations.apply(migration)
+    };
+    match result {
+        Ok(status) => {
+            let action = if revert {
+                "migration_revert"
+            } else {
+                "migration_apply"
+            };
+            state.auth.audit("owner", action, id);
+            (StatusCode::OK, Json(ApiResponse::success(status)))
+        }
+        Err(MigrationError::NothingToDo) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(format!(
+                "Migration {id} has nothing to do"
+            ))),
+        ),
+        Err(MigrationError::NotApplied) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(format!(
+                "Migration {id} hasn't been applied"
+            ))),
+        ),
+        Err(MigrationError::Failed(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+/// Apply one migration
+pub async fn apply_migration(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    run(&state, &id, false)
+}
+
+/// Undo an applied migration
+pub async fn revert_migration(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    run(&state, &id, true)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_apply_and_revert() {
+        let dir = std::env::temp_dir().join(format!("casita-migrations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cameras = dir.join("cameras.json");
+        std::fs::write(&cameras, "[]").unwrap();
+        set_mode(&cameras, 0o644).unwrap();
+        let mode = || std::fs::metadata(&cameras).unwrap().permissions().mode() & 0o777;
+
+        let migrations = Migrations::new(&dir);
+        let id = MigrationId::CredentialFilePermissions;
+        let status = migrations.list().into_iter().find(|m| m.id == id).unwrap();
+        assert!(status.pending);
+        assert_eq!(status.preview, vec!["cameras.json: mode 644 -> 600"]);
+
+        let status = migrations.apply(id).unwrap();
+        assert!(!status.pending);
+        assert!(status.applied_at.is_some());
+        assert_eq!(mode(), 0o600);
+        assert!(matches!(
+            migrations.apply(id),
+            Err(MigrationError::NothingToDo)
+        ));
+
+        migrations.revert(id).unwrap();
+        assert_eq!(mode(), 0o644);
+        assert!(matches!(
+            migrations.revert(id),
+            Err(MigrationError::NotApplied)
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypt_credentials() {
+        let dir =
+            std::env::temp_dir().join(format!("casita-migrations-encrypt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cameras = dir.join("cameras.json");
+        std::fs::write(
+            &cameras,
+            r#"[{"id": "a", "password": "hunter2"}, {"id": "b"}]"#,
+        )
+        .unwrap();
+
+        let migrations = Migrations::new(&dir);
+        let id = MigrationId::EncryptCredentials;
+        let status = migrations.list().into_iter().find(|m| m.id == id).unwrap();
+        assert_eq!(
+            status.preview,
+            vec!["cameras.json: 1 camera passwords -> encrypted"]
+        );
+
+        migrations.apply(id).unwrap();
+        let stored = std::fs::read_to_string(&cameras).unwrap();
+        assert!(!stored.contains("hunter2"));
+        assert_eq!(mode_of(&dir.join(secrets::KEY_FILE)), 0o600);
+        let key = SecretKey::load(&dir).unwrap().unwrap();
+        let password = read_cameras(&dir).unwrap()[0]["password"]
+            .as_str()
+            .map(ToString::to_string)
+            .unwrap();
+        assert_eq!(key.decrypt(&password).as_deref(), Some("hunter2"));
+
+        migrations.revert(id).unwrap();
+        assert_eq!(read_cameras(&dir).unwrap()[0]["password"], "hunter2");
+        assert!(!dir.join(secrets::KEY_FILE).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(feature = "sqlite", feature = "automation"))]
+    #[test]
+    fn test_json_import_is_undone_on_failure() {
+        let dir =
+            std::env::temp_dir().join(format!("casita-migrations-sqlite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let device = zigbee_core::ZigbeeDevice::new([1; 8], 0x1234);
+        std::fs::write(
+            dir.join("devices.json"),
+            serde_json::to_string(&[device]).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("automations.json"), "not json").unwrap();
+
+        assert!(import_json(&dir).is_err());
+        assert!(dir.join("devices.json").exists());
+        assert!(!dir.join("devices.json.imported").exists());
+
+        std::fs::write(dir.join("automations.json"), "[]").unwrap();
+        let kinds = import_json(&dir).unwrap();
+        assert_eq!(kinds, ["devices", "automations"]);
+        assert!(!dir.join("devices.json").exists());
+
+        export_json(&dir, &kinds).unwrap();
+        let devices: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("devices.json")).unwrap())
+                .unwrap();
+        assert_eq!(devices.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn mode_of(path: &FsPath) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+}
@@ -0,0 +1,221 @@
+//! Device firmware updates over the air
+//!
+//! `POST /api/v1/devices/:ieee/ota/update` updates a device to the newest
+//! image in `DATA_DIR/ota`. For devices whose quirk names a vendor firmware
+//! index (IKEA), a newer image is downloaded there first; that needs the
+//! device's current firmware, which it reports the first time it asks for an
+//! update. The download runs in the background and the device is notified
+//! once it is done. Images are only fetched over HTTPS from the index's
+//! host. Progress is broadcast to WebSocket clients as `ota_progress`
+//! events.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use zigbee_core::network::NetworkError;
+use zigbee_core::ota::{self, ImageId};
+use zigbee_core::OtaStatus;
+
+use crate::{ApiResponse, AppState};
+
+/// How long fetching the index or an image may take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Largest index or image downloaded; device images are well under 1 MB
+const MAX_DOWNLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// One entry of a vendor firmware index
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    fw_image_type: Option<u16>,
+    fw_binary_url: String,
+}
+
+/// The image URL from an index entry, and its file name; only HTTPS URLs on
+/// the index's own host are trusted
+fn image_url(index_url: &Url, image: &str) -> anyhow::Result<(Url, String)> {
+    let url = index_url.join(image)?;
+    if url.scheme() != "https" || url.host_str() != index_url.host_str() {
+        anyhow::bail!("image URL {url} isn't on {index_url}");
+    }
+    let file_name = url
+        .path_segments()
+        .and_then(Iterator::last)
+        .filter(|name| !name.is_empty() && !name.starts_with('.'))
+        .ok_or_else(|| anyhow::anyhow!("bad image URL {url}"))?
+        .to_string();
+    Ok((url, file_name))
+}
+
+/// Fetch a URL, refusing bodies over [`MAX_DOWNLOAD_SIZE`]
+async fn fetch(client: &reqwest::Client, url: Url) -> anyhow::Result<Vec<u8>> {
+    let mut response = client
+        .get(url.clone())
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > MAX_DOWNLOAD_SIZE {
+            anyhow::bail!("{url} is larger than {MAX_DOWNLOAD_SIZE} bytes");
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Download the vendor's image for `current` into `dir`, unless a file of
+/// that name is already there
+async fn download_image(
+    index_url: &str,
+    current: ImageId,
+    dir: &std::path::Path,
+) -> anyhow::Result<()> {
+    let index_url = Url::parse(index_url)?;
+    let client = reqwest::Client::new();
+    let index = fetch(&client, index_url.clone()).await?;
+    let index: Vec<IndexEntry> = serde_json::from_slice(&index)?;
+    let Some(entry) = index
+        .iter()
+        .find(|e| e.fw_image_type == Some(current.image_type))
+    else {
+        tracing::info!("No image type {:#06x} in {}", current.image_type, index_url);
+        return Ok(());
+    };
+
+    let (url, file_name) = image_url(&index_url, &entry.fw_binary_url)?;
+    let path = dir.join(&file_name);
+    if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let data = fetch(&client, url).await?;
+    let (id, _) =
+        ota::parse_image(&data).ok_or_else(|| anyhow::anyhow!("{file_name} isn't an OTA image"))?;
+    if id.manufacturer_code != current.manufacturer_code || id.image_type != current.image_type {
+        anyhow::bail!("{file_name} is for a different device");
+    }
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(&path, &data).await?;
+    tracing::info!(
+        "Downloaded firmware {:#010x} to {}",
+        id.file_version,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Start a firmware update of a device
+pub async fn start_update(
+    State(state): State<AppState>,
+    Path(ieee): Path<String>,
+) -> impl IntoResponse {
+    let Some(network) = &state.network else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::error("Zigbee network not available")),
+        );
+    };
+    let Ok(ieee_bytes) = crate::parse_ieee_address(&ieee) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid IEEE address format")),
+        );
+    };
+    let Some(device) = network.get_device(&ieee_bytes) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Device not found")),
+        );
+    };
+
+    if ota::update_endpoint(&device).is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("device doesn't support OTA updates")),
+        );
+    }
+
+    let index_url = zigbee_core::quirks::quirk_for(&device).and_then(|q| q.ota_index_url);
+    if let (Some(index_url), Some(current), Some(dir)) =
+        (index_url, device.firmware, network.ota_dir())
+    {
+        let network = Arc::clone(network);
+        let dir = dir.to_path_buf();
+        tokio::spawn(async move {
+            // The images already in the directory are still offered
+            if let Err(e) = download_image(index_url, current, &dir).await {
+                tracing::warn!("Failed to download firmware for {}: {}", ieee, e);
+            }
+            if let Err(e) = network.start_ota_update(&ieee_bytes).await {
+                tracing::warn!("Failed to start firmware update of {}: {}", ieee, e);
+            }
+        });
+        return (
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::success(serde_json::json!({
+                "status": OtaStatus::Waiting,
+                "firmware": device.firmware,
+            }))),
+        );
+    }
+
+    match network.start_ota_update(&ieee_bytes).await {
+        Ok(()) => (
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::success(serde_json::json!({
+                "status": OtaStatus::Waiting,
+                "firmware": device.firmware,
+            }))),
+        ),
+        Err(e @ NetworkError::Configuration(_)) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_url() {
+        let index =
+            Url::parse("https://fw.ota.homesmart.ikea.com/DIRIGERA/version_info.json").unwrap();
+        let (url, file_name) = image_url(
+            &index,
+            "https://fw.ota.homesmart.ikea.com/files/rodret-dimmer_v16777287.ota",
+        )
+        .unwrap();
+        assert_eq!(url.host_str(), Some("fw.ota.homesmart.ikea.com"));
+        assert!(file_name.starts_with("rodret-dimmer"));
+
+        // Relative to the index
+        let (url, file_name) = image_url(&index, "bin/remote.ota").unwrap();
+        assert_eq!(url.path(), "/DIRIGERA/bin/remote.ota");
+        assert_eq!(file_name, "remote.ota");
+
+        for bad in [
+            "http://fw.ota.homesmart.ikea.com/files/remote.ota",
+            "https://example.com/files/remote.ota",
+            "https://fw.ota.homesmart.ikea.com/files/",
+            "https://fw.ota.homesmart.ikea.com/files/.hidden",
+        ] {
+            assert!(image_url(&index, bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Wall-panel kiosk API
+//!
+//! Panels are configured server-side in `panels.json` and served as a single
+//! pre-joined payload so low-power tablets can render a dashboard in one request.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use zigbee_core::{DeviceCategory, ZigbeeDevice};
+
+use crate::{ApiResponse, AppState};
+
+/// A device pinned to a panel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedDevice {
+    /// IEEE address of the device
+    pub ieee: String,
+    /// Endpoint to control (defaults to the first on/off capable endpoint)
+    #[serde(default)]
+    pub endpoint: Option<u8>,
+    /// Label override shown on the panel
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// Server-side panel configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Panel {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub devices: Vec<PinnedDevice>,
+    /// Pinned camera IDs
+    #[serde(default)]
+    pub cameras: Vec<String>,
+    /// Pinned automation IDs (shown as one-tap scene buttons)
+    #[serde(default)]
+    pub automations: Vec<String>,
+}
+
+/// Simplified device entry in a panel payload
+#[derive(Debug, Serialize)]
+pub struct PanelDevice {
+    pub ieee: String,
+    pub label: String,
+    pub category: DeviceCategory,
+    pub endpoint: Option<u8>,
+    pub available: bool,
+    pub state_on: Option<bool>,
+}
+
+/// Simplified camera entry in a panel payload
+#[derive(Debug, Serialize)]
+pub struct PanelCamera {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Simplified automation entry in a panel payload
+#[derive(Debug, Serialize)]
+pub struct PanelAutomation {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Pre-joined panel payload
+#[derive(Debug, Serialize)]
+pub struct PanelPayload {
+    pub id: String,
+    pub name: String,
+    pub devices: Vec<PanelDevice>,
+    pub cameras: Vec<PanelCamera>,
+    pub automations: Vec<PanelAutomation>,
+}
+
+pub struct PanelManager {
+    panels: DashMap<String, Panel>,
+    data_path: PathBuf,
+}
+
+impl PanelManager {
+    pub fn new(data_dir: &std::path::Path) -> Self {
+        Self {
+            panels: DashMap::new(),
+            data_path: data_dir.join("panels.json"),
+        }
+    }
+
+    pub fn load(&self) -> anyhow::Result<()> {
+        if self.data_path.exists() {
+            let content = std::fs::read_to_string(&self.data_path)?;
+            let panels: Vec<Panel> = serde_json::from_str(&content)?;
+            for panel in panels {
+                self.panels.insert(panel.id.clone(), panel);
+            }
+            tracing::info!(
+                "Loaded {} panels from {:?}",
+                self.panels.len(),
+                self.data_path
+            );
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<Panel> {
+        self.panels.get(id).map(|r| r.value().clone())
+    }
+}
+
+/// Pick the endpoint a panel tile should control
+fn default_endpoint(device: &ZigbeeDevice) -> Option<u8> {
+    device
+        .endpoints
+        .iter()
+        .find(|ep| ep.is_light())
+        .or_else(|| device.endpoints.first())
+        .map(|ep| ep.id)
+}
+
+// =============================================================================
+// HTTP Handlers
+// =============================================================================
+
+pub async fn get_panel(
+    State(state): State<AppState>,
+    Path(panel_id): Path<String>,
+) -> impl IntoResponse {
+    let Some(panel) = state.panels.get(&panel_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Panel not found")),
+        );
+    };
+
+    let devices = panel
+        .devices
+        .iter()
+        .map(|pinned| {
+            let device = crate::parse_ieee_address(&pinned.ieee)
+                .ok()
+                .and_then(|ieee| state.network.as_ref()?.get_device(&ieee));
+            match device {
+                Some(device) => PanelDevice {
+                    ieee: device.ieee_address_string(),
+                    label: pinned
+                        .label
+                        .clone()
+                        .unwrap_or_else(|| device.display_name()),
+                    category: device.category,
+                    endpoint: pinned.endpoint.or_else(|| default_endpoint(&device)),
+                    available: device.available,
+                    state_on: device.state_on,
+                },
+                None => PanelDevice {
+                    ieee: pinned.ieee.clone(),
+                    label: pinned.label.clone().unwrap_or_else(|| pinned.ieee.clone()),
+                    category: DeviceCategory::Other,
+                    endpoint: pinned.endpoint,
+                    available: false,
+                    state_on: None,
+                },
+            }
+        })
+        .collect();
+
+    #[cfg(feature = "cameras")]
+    let cameras = panel
+        .cameras
+        .iter()
+        .filter_map(|id| state.cameras.get(id))
+        .map(|camera| PanelCamera {
+            id: camera.id,
+            name: camera.name,
+            enabled: camera.enabled,
+        })
+        .collect();
+    #[cfg(not(feature = "cameras"))]
+    let cameras = Vec::new();
+
+    #[cfg(feature = "automation")]
+    let automations = panel
+        .automations
+        .iter()
+        .filter_map(|id| state.automations.get(id))
+        .map(|automation| PanelAutomation {
+            id: automation.id,
+            name: automation.name,
+            enabled: automation.enabled,
+        })
+        .collect();
+    #[cfg(not(feature = "automation"))]
+    let automations = Vec::new();
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(PanelPayload {
+            id: panel.id,
+            name: panel.name,
+            devices,
+            cameras,
+            automations,
+        })),
+    )
+}
@@ -0,0 +1,53 @@
+//! Presence HTTP handlers
+//!
+//! Phone apps post location reports here; `OwnTracks` in HTTP mode works by
+//! pointing it at `/api/v1/presence/<person>/location` (add `?token=` when
+//! `API_TOKEN` is set).
+
+use automation_engine::presence::LocationUpdate;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{ApiResponse, AppState};
+
+/// List everyone's presence
+pub async fn list_presence(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.automations.presence().list()))
+}
+
+/// Get one person's presence
+pub async fn get_presence(
+    State(state): State<AppState>,
+    Path(person): Path<String>,
+) -> impl IntoResponse {
+    match state.automations.presence().get(&person) {
+        Some(presence) => (StatusCode::OK, Json(ApiResponse::success(presence))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Person not found")),
+        ),
+    }
+}
+
+/// Accept a location report or region transition for a person
+pub async fn update_location(
+    State(state): State<AppState>,
+    Path(person): Path<String>,
+    Json(update): Json<LocationUpdate>,
+) -> impl IntoResponse {
+    match state.automations.presence().update(&person, update).await {
+        Ok(presence) => (StatusCode::OK, Json(ApiResponse::success(presence))),
+        Err(e) => {
+            let status = if matches!(e, automation_engine::AutomationError::InvalidPresence(_)) {
+                StatusCode::BAD_REQUEST
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Optimistic concurrency for edits
+//!
+//! Automations and devices carry a `revision` that is bumped on every edit
+//! and returned as the `ETag` of GET and PUT responses. A PUT whose
+//! `If-Match` names an older revision is rejected with 409 Conflict, and the
+//! body carries the current object so the client can merge and retry instead
+//! of silently overwriting another tab's changes.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::ApiResponse;
+
+fn etag(revision: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{revision}\"")).expect("digits are a valid header value")
+}
+
+/// Parse an `If-Match` value: `"3"`, `W/"3"`, a bare `3`, or `*` (`None`)
+fn parse_if_match(value: &str) -> Option<Option<u64>> {
+    let value = value.trim();
+    if value == "*" {
+        return Some(None);
+    }
+    let tag = value.strip_prefix("W/").unwrap_or(value);
+    let tag = tag
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(tag);
+    tag.parse().ok().map(Some)
+}
+
+/// Revision required by the request's `If-Match` header
+///
+/// `Ok(None)` when the header is absent or `*`; a 400 response when it
+/// can't be parsed.
+pub fn if_match(headers: &HeaderMap) -> Result<Option<u64>, (StatusCode, Json<ApiResponse>)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    value.to_str().ok().and_then(parse_if_match).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Invalid If-Match header")),
+        )
+    })
+}
+
+/// Respond with `body`, tagged with `revision`
+pub fn tagged(status: StatusCode, revision: u64, body: ApiResponse) -> Response {
+    (status, [(header::ETAG, etag(revision))], Json(body)).into_response()
+}
+
+/// 409 Conflict carrying the current object (if it still exists)
+pub fn conflict<T: Serialize>(current: Option<T>, revision: u64, message: String) -> Response {
+    let body = ApiResponse {
+        success: false,
+        data: current.and_then(|c| serde_json::to_value(c).ok()),
+        error: Some(message),
+    };
+    tagged(StatusCode::CONFLICT, revision, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_if_match() {
+        assert_eq!(parse_if_match("\"3\""), Some(Some(3)));
+        assert_eq!(parse_if_match("W/\"12\""), Some(Some(12)));
+        assert_eq!(parse_if_match("7"), Some(Some(7)));
+        assert_eq!(parse_if_match(" * "), Some(None));
+        assert_eq!(parse_if_match("\"abc\""), None);
+        assert_eq!(parse_if_match("\"1\", \"2\""), None);
+    }
+}
@@ -174,7 +174,7 @@ impl RtspClient {
                 Some(Ok(item)) => {
                     if let CodecItem::VideoFrame(frame) = item {
                         frame_count += 1;
-                        if frame_count <= 5 || frame_count % 100 == 0 {
+                        if frame_count <= 5 || frame_count.is_multiple_of(100) {
                             tracing::info!(
                                 "Frame {}: keyframe={}, data_len={}, has_new_params={}",
                                 frame_count,
@@ -0,0 +1,126 @@
+//! Encryption of stored credentials
+//!
+//! Once the `encrypt_credentials` migration has run, camera passwords are
+//! stored in `cameras.json` encrypted (ChaCha20-Poly1305) with a key in
+//! `secret.key`, readable by the owner only. That keeps them out of copies
+//! of the file, such as backups and bug reports, though not from someone
+//! who can read the whole data directory. Encrypted values are the nonce
+//! and ciphertext in hex after an `enc:` prefix.
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Key file in the data directory
+pub const KEY_FILE: &str = "secret.key";
+
+/// Marks an encrypted value
+const PREFIX: &str = "enc:";
+
+/// Whether a stored value is encrypted
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// Key for stored credentials
+pub struct SecretKey(LessSafeKey);
+
+impl SecretKey {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid secret key"))?;
+        Ok(Self(LessSafeKey::new(key)))
+    }
+
+    /// The key in `data_dir`, or `None` if credentials aren't encrypted
+    pub fn load(data_dir: &Path) -> io::Result<Option<Self>> {
+        match std::fs::read(data_dir.join(KEY_FILE)) {
+            Ok(bytes) => Self::from_bytes(&bytes).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Generate a key and write it to `data_dir`, failing if one exists
+    pub fn create(data_dir: &Path) -> io::Result<Self> {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| io::Error::other("No randomness for the secret key"))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(data_dir.join(KEY_FILE))?.write_all(&bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn encrypt(&self, plain: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        // The system RNG only fails on platforms without one, where the key
+        // couldn't have been generated either
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("system randomness");
+        let mut data = plain.as_bytes().to_vec();
+        self.0
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .expect("plaintext within ChaCha20-Poly1305 limits");
+        let hex: String = nonce
+            .iter()
+            .chain(&data)
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("{PREFIX}{hex}")
+    }
+
+    /// Decrypt a value from [`Self::encrypt`]; `None` if it isn't one or
+    /// was encrypted with another key
+    pub fn decrypt(&self, value: &str) -> Option<String> {
+        let hex = value.strip_prefix(PREFIX)?;
+        if hex.len() % 2 != 0 {
+            return None;
+        }
+        let mut bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        if bytes.len() < NONCE_LEN {
+            return None;
+        }
+        let mut data = bytes.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&bytes).ok()?;
+        let plain = self.0.open_in_place(nonce, Aad::empty(), &mut data).ok()?;
+        String::from_utf8(plain.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("casita-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(SecretKey::load(&dir).unwrap().is_none());
+        let key = SecretKey::create(&dir).unwrap();
+        assert!(SecretKey::create(&dir).is_err());
+
+        let encrypted = key.encrypt("hunter2");
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("hunter2"));
+        let loaded = SecretKey::load(&dir).unwrap().unwrap();
+        assert_eq!(loaded.decrypt(&encrypted).as_deref(), Some("hunter2"));
+
+        // Tampered with
+        let mut tampered = encrypted.clone();
+        tampered.pop();
+        tampered.push(if encrypted.ends_with('0') { '1' } else { '0' });
+        assert_eq!(loaded.decrypt(&tampered), None);
+        assert_eq!(loaded.decrypt("hunter2"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
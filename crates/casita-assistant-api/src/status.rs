@@ -0,0 +1,242 @@
+//! Status summary for uptime monitors
+//!
+//! `GET /api/v1/system/status.json` returns a flat, versioned document (not
+//! wrapped in the usual `ApiResponse`) that monitors like Uptime Kuma or
+//! healthchecks can key on: an overall `status`, one entry per component
+//! with its last error, and a few counts. The response is 503 when the hub
+//! is down, so a plain HTTP check works too.
+//!
+//! If `heartbeat_url` is set in `watchdog.json`, the hub also GETs that URL
+//! every `heartbeat_interval_secs` while it isn't down, for push-style
+//! monitors that alert when pings stop.
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::watchdog::Subsystem;
+use crate::AppState;
+
+/// Bumped on incompatible changes to the document
+const SCHEMA_VERSION: u32 = 1;
+
+/// How long to wait for the adapter when checking the network
+const ADAPTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health of the hub or one of its components
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Ok,
+    Degraded,
+    Down,
+    /// Not built in or not configured
+    Disabled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    pub status: Health,
+    pub last_error: Option<String>,
+    /// Unix seconds
+    pub last_error_at: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StatusCounts {
+    pub devices: usize,
+    pub devices_available: usize,
+    pub automations: usize,
+    pub automations_enabled: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusDocument {
+    pub schema_version: u32,
+    pub status: Health,
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    /// Unix seconds
+    pub timestamp: u64,
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+    pub counts: StatusCounts,
+}
+
+/// Overall health: down if Zigbee is down, degraded if anything else isn't ok
+fn overall(components: &BTreeMap<&'static str, ComponentStatus>) -> Health {
+    if components
+        .get("zigbee")
+        .is_some_and(|c| c.status == Health::Down)
+    {
+        return Health::Down;
+    }
+    if components
+        .values()
+        .any(|c| matches!(c.status, Health::Degraded | Health::Down))
+    {
+        Health::Degraded
+    } else {
+        Health::Ok
+    }
+}
+
+/// Build the status document
+pub async fn collect(state: &AppState) -> StatusDocument {
+    let budgets: HashMap<Subsystem, _> = state
+        .watchdog
+        .health()
+        .into_iter()
+        .map(|h| (h.subsystem, h))
+        .collect();
+    let component = |subsystem: Subsystem, available: bool| {
+        let budget = budgets.get(&subsystem);
+        let status = match budget {
+            _ if !available => Health::Down,
+            Some(h) if h.degraded => Health::Degraded,
+            _ => Health::Ok,
+        };
+        ComponentStatus {
+            status,
+            last_error: budget.and_then(|h| h.last_error.clone()),
+            last_error_at: budget.and_then(|h| h.last_failure_at),
+        }
+    };
+    let disabled = || ComponentStatus {
+        status: Health::Disabled,
+        last_error: None,
+        last_error_at: None,
+    };
+
+    let mut counts = StatusCounts::default();
+    let mut components = BTreeMap::new();
+
+    let zigbee_up = match &state.network {
+        Some(network) => {
+            let devices = network.get_devices();
+            counts.devices = devices.len();
+            counts.devices_available = devices.iter().filter(|d| d.available).count();
+            tokio::time::timeout(ADAPTER_TIMEOUT, network.transport().get_device_state())
+                .await
+                .is_ok_and(|s| {
+                    s.is_ok_and(|s| s.network_state == deconz_protocol::NetworkState::Connected)
+                })
+        }
+        None => false,
+    };
+    components.insert("zigbee", component(Subsystem::Zigbee, zigbee_up));
+
+    #[cfg(feature = "automation")]
+    {
+        let automations = state.automations.list();
+        counts.automations = automations.len();
+        counts.automations_enabled = automations.iter().filter(|a| a.enabled).count();
+        components.insert("automation", component(Subsystem::Automation, true));
+    }
+    #[cfg(not(feature = "automation"))]
+    components.insert("automation", disabled());
+
+    #[cfg(feature = "cameras")]
+    components.insert("camera", component(Subsystem::Camera, true));
+    #[cfg(not(feature = "cameras"))]
+    components.insert("camera", disabled());
+
+    #[cfg(feature = "history")]
+    components.insert(
+        "history",
+        match &state.history {
+            Some(_) => ComponentStatus {
+                status: Health::Ok,
+                last_error: None,
+                last_error_at: None,
+            },
+            None => disabled(),
+        },
+    );
+    #[cfg(not(feature = "history"))]
+    components.insert("history", disabled());
+
+    StatusDocument {
+        schema_version: SCHEMA_VERSION,
+        status: overall(&components),
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        components,
+        counts,
+    }
+}
+
+/// Status summary for uptime monitors
+pub async fn get_status(State(state): State<AppState>) -> impl IntoResponse {
+    let document = collect(&state).await;
+    let code = if document.status == Health::Down {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (code, Json(document))
+}
+
+/// Ping the configured heartbeat URL while the hub is up
+pub fn start_heartbeat(state: AppState) {
+    let config = state.watchdog.config();
+    let Some(url) = config.heartbeat_url.clone() else {
+        return;
+    };
+    let interval = Duration::from_secs(config.heartbeat_interval_secs.max(10));
+    tracing::info!("Sending heartbeats every {}s", interval.as_secs());
+
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let status = collect(&state).await.status;
+            if status == Health::Down {
+                // Missing pings are how push monitors notice an outage
+                tracing::debug!("Hub is down, skipping heartbeat");
+                continue;
+            }
+            let result = client
+                .get(&url)
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            if let Err(e) = result {
+                tracing::warn!("Heartbeat to {} failed: {}", url, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn component(status: Health) -> ComponentStatus {
+        ComponentStatus {
+            status,
+            last_error: None,
+            last_error_at: None,
+        }
+    }
+
+    #[test]
+    fn test_overall_status() {
+        let mut components = BTreeMap::from([
+            ("zigbee", component(Health::Ok)),
+            ("camera", component(Health::Disabled)),
+        ]);
+        assert_eq!(overall(&components), Health::Ok);
+        components.insert("automation", component(Health::Degraded));
+        assert_eq!(overall(&components), Health::Degraded);
+        components.insert("zigbee", component(Health::Down));
+        assert_eq!(overall(&components), Health::Down);
+    }
+}
@@ -0,0 +1,409 @@
+//! Hub status light
+//!
+//! Shows hub state on a designated Zigbee colour bulb: blinking red while an
+//! alarm is signalled, breathing orange while the adapter is offline and
+//! breathing blue while the network is open for joining. The highest-priority
+//! active signal wins; when none is left the effect is stopped and the bulb is
+//! put back to the on/off state, brightness and colour it had before.
+//!
+//! The `ConBee` II/III LEDs are driven by the firmware and have no network
+//! parameter, so the adapter itself can't be used as the indicator.
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use zigbee_core::network::{NetworkError, NetworkEvent};
+use zigbee_core::{IdentifyEffect, LightState, ZigbeeNetwork};
+
+use crate::{ApiResponse, AppState};
+
+/// How often a running effect is re-sent so it doesn't time out on the bulb
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Hub states shown on the status light, lowest priority first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HubSignal {
+    /// Network open for joining (breathing blue)
+    PermitJoin,
+    /// Adapter offline (breathing orange)
+    Offline,
+    /// Alarm triggered (blinking red)
+    Alarm,
+}
+
+/// How a signal looks on the bulb
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Effect {
+    Blink,
+    Breathe,
+}
+
+impl HubSignal {
+    /// Hue, saturation (ZCL units) and effect
+    fn pattern(self) -> (u8, u8, Effect) {
+        match self {
+            HubSignal::PermitJoin => (169, 254, Effect::Breathe),
+            HubSignal::Offline => (21, 254, Effect::Breathe),
+            HubSignal::Alarm => (0, 254, Effect::Blink),
+        }
+    }
+}
+
+/// Status light configuration (`status_light.json`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusLightConfig {
+    /// IEEE address of the colour bulb to drive (none disables the light)
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default = "default_endpoint")]
+    pub endpoint: u8,
+}
+
+fn default_endpoint() -> u8 {
+    1
+}
+
+/// Signal currently shown on a bulb
+#[derive(Debug, Clone, Copy)]
+struct Shown {
+    signal: HubSignal,
+    ieee: [u8; 8],
+    endpoint: u8,
+    /// What the bulb showed before the signal took over
+    was: LightState,
+}
+
+pub struct StatusLight {
+    data_path: PathBuf,
+    config: RwLock<StatusLightConfig>,
+    /// Active signals and when they expire
+    active: Mutex<HashMap<HubSignal, Option<Instant>>>,
+    changed: Notify,
+}
+
+impl StatusLight {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            data_path: data_dir.join("status_light.json"),
+            config: RwLock::new(StatusLightConfig {
+                device: None,
+                endpoint: default_endpoint(),
+            }),
+            active: Mutex::new(HashMap::new()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Load `status_light.json` (the light is disabled if it doesn't exist)
+    pub fn load(&self) -> anyhow::Result<()> {
+        if self.data_path.exists() {
+            let content = std::fs::read_to_string(&self.data_path)?;
+            *write(&self.config) = serde_json::from_str(&content)?;
+            tracing::info!("Loaded status light config from {:?}", self.data_path);
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> StatusLightConfig {
+        read(&self.config).clone()
+    }
+
+    /// Replace and persist the configuration
+    pub fn set_config(&self, config: StatusLightConfig) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(&config)?;
+        if let Some(parent) = self.data_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.data_path, content)?;
+        *write(&self.config) = config;
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// Raise a signal, optionally only for a while (one too long to
+    /// represent never expires)
+    pub fn raise(&self, signal: HubSignal, duration: Option<Duration>) {
+        let expires = duration.and_then(|d| Instant::now().checked_add(d));
+        lock(&self.active).insert(signal, expires);
+        self.changed.notify_one();
+    }
+
+    /// Clear a signal
+    pub fn clear(&self, signal: HubSignal) {
+        lock(&self.active).remove(&signal);
+        self.changed.notify_one();
+    }
+
+    /// Active signals, lowest priority first
+    pub fn active(&self) -> Vec<HubSignal> {
+        let now = Instant::now();
+        let mut active = lock(&self.active);
+        active.retain(|_, expires| expires.is_none_or(|t| t > now));
+        let mut signals: Vec<HubSignal> = active.keys().copied().collect();
+        signals.sort();
+        signals
+    }
+
+    /// Follow the adapter state and drive the bulb
+    pub fn start(self: &Arc<Self>, network: Option<&Arc<ZigbeeNetwork>>) {
+        let Some(network) = network else {
+            return;
+        };
+
+        let light = Arc::clone(self);
+        let mut rx = network.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(NetworkEvent::NetworkStateChanged { connected: false }) => {
+                        light.raise(HubSignal::Offline, None);
+                    }
+                    Ok(NetworkEvent::NetworkStateChanged { connected: true }) => {
+                        light.clear(HubSignal::Offline);
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let light = Arc::clone(self);
+        let network = Arc::clone(network);
+        tokio::spawn(async move {
+            light.run(&network).await;
+        });
+    }
+
+    async fn run(&self, network: &ZigbeeNetwork) {
+        let mut shown: Option<Shown> = None;
+        loop {
+            let desired = self.active().last().copied().zip(self.target());
+            match (shown, desired) {
+                (None, None) => {}
+                (None, Some((signal, (ieee, endpoint)))) => {
+                    let was = network
+                        .get_device(&ieee)
+                        .map(|d| d.light_state())
+                        .unwrap_or_default();
+                    log_failure(show(network, &ieee, endpoint, signal).await);
+                    shown = Some(Shown {
+                        signal,
+                        ieee,
+                        endpoint,
+                        was,
+                    });
+                }
+                (Some(current), None) => {
+                    log_failure(restore(network, &current).await);
+                    shown = None;
+                }
+                (Some(current), Some((_, target)))
+                    if target != (current.ieee, current.endpoint) =>
+                {
+                    // Bulb changed: restore the old one and start over
+                    log_failure(restore(network, &current).await);
+                    shown = None;
+                    continue;
+                }
+                (Some(mut current), Some((signal, _))) if signal != current.signal => {
+                    log_failure(show(network, &current.ieee, current.endpoint, signal).await);
+                    current.signal = signal;
+                    shown = Some(current);
+                }
+                (Some(current), Some(_)) => {
+                    log_failure(
+                        refresh(network, &current.ieee, current.endpoint, current.signal).await,
+                    );
+                }
+            }
+
+            tokio::select! {
+                () = self.changed.notified() => {}
+                () = tokio::time::sleep(REFRESH_INTERVAL) => {}
+            }
+        }
+    }
+
+    fn target(&self) -> Option<([u8; 8], u8)> {
+        let config = read(&self.config);
+        let ieee = crate::parse_ieee_address(config.device.as_deref()?).ok()?;
+        Some((ieee, config.endpoint))
+    }
+}
+
+async fn show(
+    network: &ZigbeeNetwork,
+    ieee: &[u8; 8],
+    endpoint: u8,
+    signal: HubSignal,
+) -> Result<(), NetworkError> {
+    tracing::info!("Status light: {:?}", signal);
+    let (hue, saturation, _) = signal.pattern();
+    network.turn_on(ieee, endpoint).await?;
+    network
+        .set_hue_saturation(ieee, endpoint, hue, saturation, 0)
+        .await?;
+    refresh(network, ieee, endpoint, signal).await
+}
+
+async fn refresh(
+    network: &ZigbeeNetwork,
+    ieee: &[u8; 8],
+    endpoint: u8,
+    signal: HubSignal,
+) -> Result<(), NetworkError> {
+    match signal.pattern().2 {
+        Effect::Blink => {
+            // Identify until shortly after the next refresh
+            let secs = u16::try_from(REFRESH_INTERVAL.as_secs() + 2).unwrap_or(u16::MAX);
+            network.identify(ieee, endpoint, secs).await
+        }
+        Effect::Breathe => {
+            network
+                .trigger_effect(ieee, endpoint, IdentifyEffect::Breathe)
+                .await
+        }
+    }
+}
+
+async fn restore(network: &ZigbeeNetwork, shown: &Shown) -> Result<(), NetworkError> {
+    tracing::info!("Status light: cleared");
+    let (ieee, endpoint) = (&shown.ieee, shown.endpoint);
+    network.identify(ieee, endpoint, 0).await?;
+    network
+        .trigger_effect(ieee, endpoint, IdentifyEffect::Stop)
+        .await?;
+    network
+        .restore_light_state(ieee, endpoint, &shown.was, 0)
+        .await
+}
+
+fn log_failure(result: Result<(), NetworkError>) {
+    if let Err(e) = result {
+        tracing::debug!("Status light command failed: {}", e);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn read<T>(lock: &RwLock<T>) -> std::sync::RwLockReadGuard<'_, T> {
+    lock.read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+    lock.write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[derive(Serialize)]
+struct StatusLightInfo {
+    #[serde(flatten)]
+    config: StatusLightConfig,
+    /// Active signals, lowest priority first
+    active: Vec<HubSignal>,
+    /// Whether the adapter's own LED can be driven (not on `ConBee` II/III)
+    adapter_indicator: bool,
+}
+
+/// Request body for raising or clearing a signal
+#[derive(Deserialize)]
+pub struct SignalRequest {
+    signal: HubSignal,
+    #[serde(default = "default_active")]
+    active: bool,
+    /// Clear the signal automatically after this many seconds
+    #[serde(default)]
+    duration_secs: Option<u64>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+fn info(light: &StatusLight) -> StatusLightInfo {
+    StatusLightInfo {
+        config: light.config(),
+        active: light.active(),
+        adapter_indicator: false,
+    }
+}
+
+/// Get the status light configuration and active signals
+pub async fn get_status_light(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(info(&state.status_light)))
+}
+
+/// Choose the bulb used as the status light
+pub async fn set_status_light(
+    State(state): State<AppState>,
+    Json(config): Json<StatusLightConfig>,
+) -> impl IntoResponse {
+    if let Some(device) = &config.device {
+        if crate::parse_ieee_address(device).is_err() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Invalid IEEE address format")),
+            );
+        }
+    }
+    if let Err(e) = state.status_light.set_config(config) {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(format!(
+                "Failed to save status light config: {e}"
+            ))),
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(info(&state.status_light))),
+    )
+}
+
+/// Raise or clear a signal (e.g. `{"signal": "alarm"}`)
+pub async fn signal_status_light(
+    State(state): State<AppState>,
+    Json(request): Json<SignalRequest>,
+) -> impl IntoResponse {
+    if request.active {
+        state.status_light.raise(
+            request.signal,
+            request.duration_secs.map(Duration::from_secs),
+        );
+    } else {
+        state.status_light.clear(request.signal);
+    }
+    Json(ApiResponse::success(info(&state.status_light)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highest_priority_signal_wins() {
+        let light = StatusLight::new(Path::new("/nonexistent"));
+        light.raise(HubSignal::Alarm, None);
+        light.raise(HubSignal::PermitJoin, Some(Duration::from_secs(60)));
+        assert_eq!(light.active().last(), Some(&HubSignal::Alarm));
+
+        light.clear(HubSignal::Alarm);
+        assert_eq!(light.active(), vec![HubSignal::PermitJoin]);
+
+        light.raise(HubSignal::PermitJoin, Some(Duration::ZERO));
+        assert!(light.active().is_empty());
+
+        light.raise(HubSignal::PermitJoin, Some(Duration::MAX));
+        assert_eq!(light.active(), vec![HubSignal::PermitJoin]);
+    }
+}
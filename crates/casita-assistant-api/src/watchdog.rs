@@ -0,0 +1,502 @@
+//! Error-budget watchdog
+//!
+//! Counts failures per subsystem (lost serial links to the Zigbee adapter
+//! and the network going offline, automation failures, camera connection
+//! errors, dropped MQTT connections) over a sliding window. When a
+//! subsystem exceeds its budget, one aggregated notification covering every
+//! degraded subsystem is posted to the webhook configured in `watchdog.json`.
+//! A subsystem is only reported again after it has recovered.
+
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use zigbee_core::{network::NetworkEvent, utilization, ZigbeeNetwork};
+
+#[cfg(feature = "automation")]
+use automation_engine::{AutomationEngine, AutomationEvent};
+
+use crate::{ApiResponse, AppState};
+
+/// Time between budget checks
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Subsystems whose failures are tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    /// Serial link to the adapter lost, or the network went offline
+    Zigbee,
+    /// Automation runs that failed
+    Automation,
+    /// Camera streams that couldn't be opened
+    Camera,
+    /// Broker connection dropped
+    Mqtt,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 4] = [
+        Subsystem::Zigbee,
+        Subsystem::Automation,
+        Subsystem::Camera,
+        Subsystem::Mqtt,
+    ];
+}
+
+/// Allowed failures per window for each subsystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thresholds {
+    #[serde(default = "default_zigbee_threshold")]
+    pub zigbee: usize,
+    #[serde(default = "default_automation_threshold")]
+    pub automation: usize,
+    #[serde(default = "default_camera_threshold")]
+    pub camera: usize,
+    #[serde(default = "default_mqtt_threshold")]
+    pub mqtt: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            zigbee: default_zigbee_threshold(),
+            automation: default_automation_threshold(),
+            camera: default_camera_threshold(),
+            mqtt: default_mqtt_threshold(),
+        }
+    }
+}
+
+impl Thresholds {
+    fn get(&self, subsystem: Subsystem) -> usize {
+        match subsystem {
+            Subsystem::Zigbee => self.zigbee,
+            Subsystem::Automation => self.automation,
+            Subsystem::Camera => self.camera,
+            Subsystem::Mqtt => self.mqtt,
+        }
+    }
+}
+
+fn default_zigbee_threshold() -> usize {
+    3
+}
+
+fn default_automation_threshold() -> usize {
+    5
+}
+
+fn default_camera_threshold() -> usize {
+    10
+}
+
+fn default_mqtt_threshold() -> usize {
+    3
+}
+
+fn default_window_minutes() -> u64 {
+    60
+}
+
+fn default_heartbeat_interval() -> u64 {
+    60
+}
+
+/// Watchdog configuration (`watchdog.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    /// Webhook that receives alerts as a JSON POST (alerts are only logged if unset)
+    #[serde(default)]
+    pub notify_url: Option<String>,
+    /// Link included in alerts, e.g. `http://casita.local:3000/api/v1/system/watchdog`
+    #[serde(default)]
+    pub diagnostics_url: Option<String>,
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    /// URL pinged (GET) while the hub is up, for push monitors such as
+    /// Uptime Kuma or healthchecks.io
+    #[serde(default)]
+    pub heartbeat_url: Option<String>,
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval_secs: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            notify_url: None,
+            diagnostics_url: None,
+            window_minutes: default_window_minutes(),
+            thresholds: Thresholds::default(),
+            heartbeat_url: None,
+            heartbeat_interval_secs: default_heartbeat_interval(),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    /// Longest window, a week
+    const MAX_WINDOW_MINUTES: u64 = 7 * 24 * 60;
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if !(1..=Self::MAX_WINDOW_MINUTES).contains(&self.window_minutes) {
+            anyhow::bail!(
+                "window_minutes must be between 1 and {}",
+                Self::MAX_WINDOW_MINUTES
+            );
+        }
+        Ok(())
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.window_minutes.saturating_mul(60))
+    }
+}
+
+struct Failure {
+    at: SystemTime,
+    message: String,
+}
+
+/// Error budget state of one subsystem
+#[derive(Debug, Clone, Serialize)]
+pub struct SubsystemHealth {
+    pub subsystem: Subsystem,
+    /// Failures within the window
+    pub failures: usize,
+    pub threshold: usize,
+    pub degraded: bool,
+    pub last_error: Option<String>,
+    /// Unix seconds
+    pub last_failure_at: Option<u64>,
+}
+
+/// Alert payload posted to the webhook
+#[derive(Debug, Serialize)]
+struct Alert<'a> {
+    title: &'static str,
+    message: String,
+    subsystems: Vec<SubsystemHealth>,
+    diagnostics_url: Option<&'a str>,
+}
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    data_dir: std::path::PathBuf,
+    failures: Mutex<HashMap<Subsystem, VecDeque<Failure>>>,
+    /// Subsystems already reported as degraded
+    alerted: Mutex<HashSet<Subsystem>>,
+}
+
+impl Watchdog {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            config: WatchdogConfig::default(),
+            data_dir: data_dir.to_path_buf(),
+            failures: Mutex::new(HashMap::new()),
+            alerted: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Load `watchdog.json` (defaults are used if it doesn't exist)
+    pub fn load(&mut self) -> anyhow::Result<()> {
+        let path = self.data_dir.join("watchdog.json");
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            let config: WatchdogConfig = serde_json::from_str(&content)?;
+            config.validate()?;
+            self.config = config;
+            tracing::info!("Loaded watchdog config from {:?}", path);
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> &WatchdogConfig {
+        &self.config
+    }
+
+    /// Record a failure against a subsystem's budget
+    pub fn record(&self, subsystem: Subsystem, message: impl Into<String>) {
+        let now = SystemTime::now();
+        let mut failures = lock(&self.failures);
+        let entries = failures.entry(subsystem).or_default();
+        entries.push_back(Failure {
+            at: now,
+            message: message.into(),
+        });
+        prune(entries, now, self.config.window());
+    }
+
+    /// Current budget state of every subsystem
+    pub fn health(&self) -> Vec<SubsystemHealth> {
+        let now = SystemTime::now();
+        let mut failures = lock(&self.failures);
+        Subsystem::ALL
+            .iter()
+            .map(|&subsystem| {
+                let entries = failures.entry(subsystem).or_default();
+                prune(entries, now, self.config.window());
+                let threshold = self.config.thresholds.get(subsystem);
+                let last = entries.back();
+                SubsystemHealth {
+                    subsystem,
+                    failures: entries.len(),
+                    threshold,
+                    degraded: threshold > 0 && entries.len() >= threshold,
+                    last_error: last.map(|f| f.message.clone()),
+                    last_failure_at: last.map(|f| unix_secs(f.at)),
+                }
+            })
+            .collect()
+    }
+
+    /// Watch event sources and check budgets periodically
+    pub fn start(
+        self: &Arc<Self>,
+        network: Option<&Arc<ZigbeeNetwork>>,
+        #[cfg(feature = "automation")] automations: &Arc<AutomationEngine>,
+    ) {
+        if let Some(network) = network {
+            let watchdog = Arc::clone(self);
+            let mut rx = network.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(NetworkEvent::AdapterDisconnected) => {
+                            watchdog.record(
+                                Subsystem::Zigbee,
+                                "Lost the serial connection to the adapter",
+                            );
+                        }
+                        Ok(NetworkEvent::NetworkStateChanged { connected: false }) => {
+                            watchdog.record(Subsystem::Zigbee, "Zigbee network went offline");
+                        }
+                        Ok(NetworkEvent::ChannelCongestion {
+                            channel,
+                            congested: true,
+                            failure_rate,
+                        }) => {
+                            watchdog.alert_congestion(channel, failure_rate).await;
+                        }
+                        Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+        }
+
+        #[cfg(feature = "automation")]
+        {
+            let watchdog = Arc::clone(self);
+            let mut rx = automations.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(AutomationEvent::Failed {
+                            automation_id,
+                            error,
+                        }) => {
+                            watchdog.record(
+                                Subsystem::Automation,
+                                format!("Automation {automation_id} failed: {error}"),
+                            );
+                        }
+                        Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = automations.mqtt() {
+            let watchdog = Arc::clone(self);
+            let mut connection = mqtt.connection();
+            tokio::spawn(async move {
+                while connection.changed().await.is_ok() {
+                    if !*connection.borrow_and_update() {
+                        watchdog.record(Subsystem::Mqtt, "MQTT broker connection dropped");
+                    }
+                }
+            });
+        }
+
+        let watchdog = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                watchdog.check().await;
+            }
+        });
+    }
+
+    /// Alert once for newly degraded subsystems and forget recovered ones
+    async fn check(&self) {
+        let health = self.health();
+        let degraded: Vec<SubsystemHealth> = health.into_iter().filter(|h| h.degraded).collect();
+
+        let newly_degraded = {
+            let mut alerted = lock(&self.alerted);
+            alerted.retain(|subsystem| {
+                let still_degraded = degraded.iter().any(|h| h.subsystem == *subsystem);
+                if !still_degraded {
+                    tracing::info!("Watchdog: {:?} recovered", subsystem);
+                }
+                still_degraded
+            });
+            let newly: Vec<Subsystem> = degraded
+                .iter()
+                .map(|h| h.subsystem)
+                .filter(|s| alerted.insert(*s))
+                .collect();
+            newly
+        };
+        if newly_degraded.is_empty() {
+            return;
+        }
+
+        let summary: Vec<String> = degraded
+            .iter()
+            .map(|h| {
+                format!(
+                    "{:?}: {} failures in {} min (budget {})",
+                    h.subsystem, h.failures, self.config.window_minutes, h.threshold
+                )
+            })
+            .collect();
+        let message = summary.join("; ");
+        tracing::warn!("Watchdog: subsystems degraded - {}", message);
+
+        if let Some(url) = &self.config.notify_url {
+            let alert = Alert {
+                title: "Casita Assistant: subsystems degraded",
+                message,
+                subsystems: degraded,
+                diagnostics_url: self.config.diagnostics_url.as_deref(),
+            };
+            if let Err(e) = send_alert(url, &alert).await {
+                tracing::warn!("Failed to send watchdog alert: {}", e);
+            }
+        }
+    }
+
+    /// Alert that the Zigbee channel has been congested for a while
+    async fn alert_congestion(&self, channel: u8, failure_rate: f64) {
+        let Some(url) = &self.config.notify_url else {
+            return;
+        };
+        let alert = Alert {
+            title: "Casita Assistant: Zigbee channel congested",
+            message: format!(
+                "Channel {channel} has been congested for {} minutes ({:.0}% of sends failing). \
+                 Run a channel scan and consider moving the network to a quieter channel.",
+                utilization::SAMPLE_INTERVAL.as_secs() * utilization::SUSTAINED_SAMPLES as u64 / 60,
+                failure_rate * 100.0
+            ),
+            subsystems: Vec::new(),
+            diagnostics_url: self.config.diagnostics_url.as_deref(),
+        };
+        if let Err(e) = send_alert(url, &alert).await {
+            tracing::warn!("Failed to send congestion alert: {}", e);
+        }
+    }
+}
+
+async fn send_alert(url: &str, alert: &Alert<'_>) -> anyhow::Result<()> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(alert)?)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        anyhow::bail!("webhook returned {}", response.status());
+    }
+    Ok(())
+}
+
+fn prune(entries: &mut VecDeque<Failure>, now: SystemTime, window: Duration) {
+    while entries
+        .front()
+        .is_some_and(|f| now.duration_since(f.at).unwrap_or_default() > window)
+    {
+        entries.pop_front();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct WatchdogResponse {
+    window_minutes: u64,
+    subsystems: Vec<SubsystemHealth>,
+}
+
+pub async fn get_watchdog(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(WatchdogResponse {
+        window_minutes: state.watchdog.config().window_minutes,
+        subsystems: state.watchdog.health(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_degrades_at_threshold() {
+        let watchdog = Watchdog::new(Path::new("/nonexistent"));
+        for i in 0..default_zigbee_threshold() {
+            let zigbee = &watchdog.health()[0];
+            assert_eq!(zigbee.subsystem, Subsystem::Zigbee);
+            assert!(!zigbee.degraded);
+            watchdog.record(Subsystem::Zigbee, format!("offline {i}"));
+        }
+        let zigbee = &watchdog.health()[0];
+        assert!(zigbee.degraded);
+        assert_eq!(zigbee.last_error.as_deref(), Some("offline 2"));
+    }
+
+    #[test]
+    fn test_window_is_bounded() {
+        let mut config = WatchdogConfig::default();
+        assert!(config.validate().is_ok());
+        config.window_minutes = 0;
+        assert!(config.validate().is_err());
+        config.window_minutes = u64::MAX;
+        assert!(config.validate().is_err());
+        assert_eq!(config.window(), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn test_prune_drops_old_failures() {
+        let now = SystemTime::now();
+        let mut entries: VecDeque<Failure> = [120, 30]
+            .into_iter()
+            .map(|secs| Failure {
+                at: now - Duration::from_secs(secs),
+                message: String::new(),
+            })
+            .collect();
+        prune(&mut entries, now, Duration::from_secs(60));
+        assert_eq!(entries.len(), 1);
+    }
+}
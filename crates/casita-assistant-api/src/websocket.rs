@@ -2,56 +2,9 @@
 
 use axum::extract::ws::{Message, WebSocket};
 use futures::{SinkExt, StreamExt};
-use serde::Serialize;
 
 use crate::AppState;
-
-/// WebSocket events sent to clients
-#[derive(Serialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum WsEvent {
-    Connected,
-    DeviceJoined {
-        ieee_address: String,
-    },
-    DeviceLeft {
-        ieee_address: String,
-    },
-    DeviceUpdated {
-        ieee_address: String,
-    },
-    NetworkStateChanged {
-        connected: bool,
-    },
-    // Device state events
-    DeviceStateChanged {
-        ieee_address: String,
-        endpoint: u8,
-        state_on: bool,
-    },
-    // Automation events
-    AutomationTriggered {
-        automation_id: String,
-        trigger_reason: String,
-    },
-    AutomationActionExecuted {
-        automation_id: String,
-        action_index: usize,
-    },
-    AutomationFailed {
-        automation_id: String,
-        error: String,
-    },
-    AutomationCreated {
-        automation_id: String,
-    },
-    AutomationUpdated {
-        automation_id: String,
-    },
-    AutomationDeleted {
-        automation_id: String,
-    },
-}
+pub use casita_api_types::WsEvent;
 
 #[allow(clippy::too_many_lines)] // WebSocket handler manages multiple event sources
 pub async fn handle_socket(socket: WebSocket, state: AppState) {
@@ -91,6 +44,16 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
                             zigbee_core::network::NetworkEvent::NetworkStateChanged {
                                 connected,
                             } => WsEvent::NetworkStateChanged { connected },
+                            zigbee_core::network::NetworkEvent::AdapterDisconnected => {
+                                WsEvent::AdapterDisconnected
+                            }
+                            zigbee_core::network::NetworkEvent::PermitJoinChanged {
+                                permit_join,
+                                duration_secs,
+                            } => WsEvent::PermitJoinChanged {
+                                permit_join,
+                                duration: duration_secs,
+                            },
                             zigbee_core::network::NetworkEvent::DeviceStateChanged {
                                 ieee_address,
                                 endpoint,
@@ -100,6 +63,81 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
                                 endpoint,
                                 state_on,
                             },
+                            zigbee_core::network::NetworkEvent::ButtonEvent {
+                                ieee_address,
+                                endpoint,
+                                action,
+                            } => WsEvent::ButtonEvent {
+                                ieee_address: format_ieee(ieee_address),
+                                endpoint,
+                                action,
+                            },
+                            zigbee_core::network::NetworkEvent::OccupancyChanged {
+                                ieee_address,
+                                endpoint,
+                                occupied,
+                            } => WsEvent::OccupancyChanged {
+                                ieee_address: format_ieee(ieee_address),
+                                endpoint,
+                                occupied,
+                            },
+                            zigbee_core::network::NetworkEvent::SensorValue {
+                                ieee_address,
+                                endpoint,
+                                kind,
+                                value,
+                            } => WsEvent::SensorValue {
+                                ieee_address: format_ieee(ieee_address),
+                                endpoint,
+                                kind,
+                                value,
+                                unit: kind.unit().to_string(),
+                            },
+                            zigbee_core::network::NetworkEvent::PowerMeasurement {
+                                ieee_address,
+                                endpoint,
+                                kind,
+                                value,
+                            } => WsEvent::PowerMeasurement {
+                                ieee_address: format_ieee(ieee_address),
+                                endpoint,
+                                kind,
+                                value,
+                                unit: kind.unit().to_string(),
+                            },
+                            zigbee_core::network::NetworkEvent::IasAlarm {
+                                ieee_address,
+                                zone_status,
+                            } => WsEvent::IasAlarm {
+                                ieee_address: format_ieee(ieee_address),
+                                zone_status,
+                                alarm: zigbee_core::ias::is_alarm(zone_status),
+                            },
+                            zigbee_core::network::NetworkEvent::ChannelCongestion {
+                                channel,
+                                congested,
+                                failure_rate,
+                            } => WsEvent::ChannelCongestion {
+                                channel,
+                                congested,
+                                failure_rate,
+                            },
+                            zigbee_core::network::NetworkEvent::AvailabilityChanged {
+                                ieee_address,
+                                available,
+                            } => WsEvent::AvailabilityChanged {
+                                ieee_address: format_ieee(ieee_address),
+                                available,
+                            },
+                            zigbee_core::network::NetworkEvent::OtaProgress {
+                                ieee_address,
+                                status,
+                                percent,
+                            } => WsEvent::OtaProgress {
+                                ieee_address: format_ieee(ieee_address),
+                                status,
+                                percent,
+                            },
                         };
 
                         if tx.send(ws_event).await.is_err() {
@@ -115,9 +153,33 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
         None
     };
 
+    // Spawn task to forward firmware update progress
+    let mut firmware_rx = state.firmware.subscribe();
+    let firmware_tx = tx.clone();
+    let firmware_task = tokio::spawn(async move {
+        loop {
+            match firmware_rx.recv().await {
+                Ok(progress) => {
+                    if firmware_tx
+                        .send(WsEvent::FirmwareProgress(progress))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
     // Spawn task to forward automation events
+    #[cfg(feature = "automation")]
     let mut automation_rx = state.automations.subscribe();
+    #[cfg(feature = "automation")]
     let automation_tx = tx.clone();
+    #[cfg(feature = "automation")]
     let automation_task = tokio::spawn(async move {
         loop {
             match automation_rx.recv().await {
@@ -193,11 +255,13 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
     if let Some(task) = network_task {
         task.abort();
     }
+    firmware_task.abort();
+    #[cfg(feature = "automation")]
     automation_task.abort();
     send_task.abort();
 }
 
-fn format_ieee(ieee: [u8; 8]) -> String {
+pub fn format_ieee(ieee: [u8; 8]) -> String {
     ieee.iter()
         .rev()
         .map(|b| format!("{b:02x}"))
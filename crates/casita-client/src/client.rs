@@ -0,0 +1,374 @@
+//! HTTP client for the REST API
+
+use crate::error::ClientError;
+use crate::events::EventStream;
+pub use casita_api_types::SystemInfo;
+use reqwest::{Method, RequestBuilder, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use zigbee_core::network::NetworkStatus;
+use zigbee_core::ZigbeeDevice;
+
+#[cfg(feature = "automation")]
+use automation_engine::{
+    Automation, AutomationFolder, BulkReport, BulkRequest, CreateAutomationRequest, DryRunReport,
+    SnoozeRequest, UpdateAutomationRequest,
+};
+
+/// Response envelope used by every `/api/v1` endpoint
+#[derive(Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    data: Option<T>,
+    error: Option<String>,
+}
+
+/// Async client for a Casita Assistant server
+#[derive(Clone)]
+pub struct CasitaClient {
+    http: reqwest::Client,
+    base_url: Url,
+    token: Option<String>,
+}
+
+impl CasitaClient {
+    /// Create a client for a server, e.g. `http://casita.local:3000`
+    #[allow(clippy::missing_errors_doc)]
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        let base_url = Url::parse(base_url)
+            .map_err(|e| ClientError::InvalidUrl(format!("{base_url}: {e}")))?;
+        Ok(Self {
+            http: reqwest::Client::new(),
+            base_url,
+            token: None,
+        })
+    }
+
+    /// Authenticate with an API or guest token
+    #[must_use]
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn url(&self, path: &str) -> Result<Url, ClientError> {
+        self.base_url
+            .join(path)
+            .map_err(|e| ClientError::InvalidUrl(format!("{path}: {e}")))
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, ClientError> {
+        let builder = self.http.request(method, self.url(path)?);
+        Ok(match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        })
+    }
+
+    /// Send a request and unwrap the response envelope
+    async fn send<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<T, ClientError> {
+        let response = builder.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        match serde_json::from_slice::<Envelope<T>>(&body) {
+            Ok(Envelope {
+                success: true,
+                data: Some(data),
+                ..
+            }) => Ok(data),
+            Ok(Envelope {
+                success: true,
+                data: None,
+                ..
+            }) => Ok(serde_json::from_value(serde_json::Value::Null)?),
+            Ok(Envelope { error, .. }) => Err(ClientError::Api {
+                status: status.as_u16(),
+                message: error.unwrap_or_else(|| status.to_string()),
+            }),
+            Err(_) if !status.is_success() => Err(ClientError::Api {
+                status: status.as_u16(),
+                message: String::from_utf8_lossy(&body).into_owned(),
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.send(self.request(Method::GET, path)?).await
+    }
+
+    async fn post<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        self.send(self.request(Method::POST, path)?.json(body))
+            .await
+    }
+
+    /// Server name, version and adapter firmware
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn system_info(&self) -> Result<SystemInfo, ClientError> {
+        self.get("/api/v1/system/info").await
+    }
+
+    /// Zigbee network status
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn network_status(&self) -> Result<NetworkStatus, ClientError> {
+        self.get("/api/v1/network/status").await
+    }
+
+    /// Open the network for joining (0 closes it)
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn permit_join(&self, duration_secs: u8) -> Result<(), ClientError> {
+        let _: serde_json::Value = self
+            .post(
+                "/api/v1/network/permit-join",
+                &serde_json::json!({ "duration": duration_secs }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// All known devices
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn devices(&self) -> Result<Vec<ZigbeeDevice>, ClientError> {
+        self.get("/api/v1/devices").await
+    }
+
+    /// One device by IEEE address (`00:11:22:33:44:55:66:77`)
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn device(&self, ieee: &str) -> Result<ZigbeeDevice, ClientError> {
+        self.get(&format!("/api/v1/devices/{ieee}")).await
+    }
+
+    /// Turn a device endpoint on
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn turn_on(&self, ieee: &str, endpoint: u8) -> Result<(), ClientError> {
+        self.device_command(ieee, endpoint, "on").await
+    }
+
+    /// Turn a device endpoint off
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn turn_off(&self, ieee: &str, endpoint: u8) -> Result<(), ClientError> {
+        self.device_command(ieee, endpoint, "off").await
+    }
+
+    /// Toggle a device endpoint
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn toggle(&self, ieee: &str, endpoint: u8) -> Result<(), ClientError> {
+        self.device_command(ieee, endpoint, "toggle").await
+    }
+
+    /// Make a device blink for some seconds so it can be found; 0 stops
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn identify(
+        &self,
+        ieee: &str,
+        endpoint: u8,
+        seconds: u16,
+    ) -> Result<(), ClientError> {
+        let body = serde_json::json!({"endpoint": endpoint, "seconds": seconds});
+        let _: serde_json::Value = self
+            .post(&format!("/api/v1/devices/{ieee}/identify"), &body)
+            .await?;
+        Ok(())
+    }
+
+    async fn device_command(
+        &self,
+        ieee: &str,
+        endpoint: u8,
+        command: &str,
+    ) -> Result<(), ClientError> {
+        let path = format!("/api/v1/devices/{ieee}/endpoints/{endpoint}/{command}");
+        let _: serde_json::Value = self.send(self.request(Method::POST, &path)?).await?;
+        Ok(())
+    }
+
+    /// All automations
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn automations(&self) -> Result<Vec<Automation>, ClientError> {
+        self.get("/api/v1/automations").await
+    }
+
+    /// One automation
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn automation(&self, id: &str) -> Result<Automation, ClientError> {
+        self.get(&format!("/api/v1/automations/{id}")).await
+    }
+
+    /// Create an automation
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn create_automation(
+        &self,
+        request: &CreateAutomationRequest,
+    ) -> Result<Automation, ClientError> {
+        self.post("/api/v1/automations", request).await
+    }
+
+    /// Update an automation (only the fields that are set)
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn update_automation(
+        &self,
+        id: &str,
+        request: &UpdateAutomationRequest,
+    ) -> Result<Automation, ClientError> {
+        let path = format!("/api/v1/automations/{id}");
+        self.send(self.request(Method::PUT, &path)?.json(request))
+            .await
+    }
+
+    /// Delete an automation
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn delete_automation(&self, id: &str) -> Result<(), ClientError> {
+        let path = format!("/api/v1/automations/{id}");
+        let _: serde_json::Value = self.send(self.request(Method::DELETE, &path)?).await?;
+        Ok(())
+    }
+
+    /// Run an automation's actions now
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn trigger_automation(&self, id: &str) -> Result<(), ClientError> {
+        let path = format!("/api/v1/automations/{id}/trigger");
+        let _: serde_json::Value = self.send(self.request(Method::POST, &path)?).await?;
+        Ok(())
+    }
+
+    /// Enable or disable an automation
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_automation_enabled(
+        &self,
+        id: &str,
+        enabled: bool,
+    ) -> Result<Automation, ClientError> {
+        let action = if enabled { "enable" } else { "disable" };
+        let path = format!("/api/v1/automations/{id}/{action}");
+        self.send(self.request(Method::POST, &path)?).await
+    }
+
+    /// Enable, disable or run several automations (by ID and/or tag)
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn bulk_automations(&self, request: &BulkRequest) -> Result<BulkReport, ClientError> {
+        self.post("/api/v1/automations/bulk", request).await
+    }
+
+    /// Check an automation against the current state without running it
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn test_automation(&self, id: &str) -> Result<DryRunReport, ClientError> {
+        let path = format!("/api/v1/automations/{id}/test");
+        self.send(self.request(Method::POST, &path)?).await
+    }
+
+    /// Ignore an automation's triggers until a time or for some minutes
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn snooze_automation(
+        &self,
+        id: &str,
+        request: &SnoozeRequest,
+    ) -> Result<Automation, ClientError> {
+        self.post(&format!("/api/v1/automations/{id}/snooze"), request)
+            .await
+    }
+
+    /// End an automation's snooze early
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn unsnooze_automation(&self, id: &str) -> Result<Automation, ClientError> {
+        let path = format!("/api/v1/automations/{id}/snooze");
+        self.send(self.request(Method::DELETE, &path)?).await
+    }
+
+    /// Automation folders in display order
+    #[cfg(feature = "automation")]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn automation_folders(&self) -> Result<Vec<AutomationFolder>, ClientError> {
+        self.get("/api/v1/automation-folders").await
+    }
+
+    /// Connect to the event WebSocket
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn events(&self) -> Result<EventStream, ClientError> {
+        let mut url = self.url("/ws")?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|()| ClientError::InvalidUrl(url.to_string()))?;
+
+        let mut request = url.as_str().into_client_request()?;
+        if let Some(token) = &self.token {
+            let value = HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|_| ClientError::InvalidUrl("token is not a valid header".to_string()))?;
+            request.headers_mut().insert("authorization", value);
+        }
+        let (socket, _) = tokio_tungstenite::connect_async(request).await?;
+        Ok(EventStream::new(socket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::get, Json, Router};
+
+    async fn serve(router: Router) -> CasitaClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        CasitaClient::new(&format!("http://{addr}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unwraps_envelope_and_errors() {
+        let router = Router::new()
+            .route(
+                "/api/v1/system/info",
+                get(|| async {
+                    Json(serde_json::json!({
+                        "success": true,
+                        "data": {"name": "Casita Assistant", "version": "0.1.0", "firmware": null}
+                    }))
+                }),
+            )
+            .route(
+                "/api/v1/network/status",
+                get(|| async {
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        Json(serde_json::json!({
+                            "success": false,
+                            "error": "Zigbee network not available"
+                        })),
+                    )
+                }),
+            );
+        let client = serve(router).await;
+
+        let info = client.system_info().await.unwrap();
+        assert_eq!(info.name, "Casita Assistant");
+
+        match client.network_status().await {
+            Err(ClientError::Api { status, message }) => {
+                assert_eq!(status, 503);
+                assert_eq!(message, "Zigbee network not available");
+            }
+            other => panic!("expected API error, got {other:?}"),
+        }
+        assert!(client
+            .device("00:11:22:33:44:55:66:77")
+            .await
+            .unwrap_err()
+            .is_not_found());
+    }
+}
@@ -0,0 +1,92 @@
+//! WebSocket event stream (`/ws`)
+
+use crate::error::ClientError;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Event pushed by the server; the server's own type, so unknown event
+/// types from newer servers come through as [`Event::Unknown`]
+pub use casita_api_types::WsEvent as Event;
+
+/// Stream of [`Event`]s from an open WebSocket
+///
+/// Ends when the server closes the connection.
+pub struct EventStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl EventStream {
+    pub(crate) fn new(socket: WebSocketStream<MaybeTlsStream<TcpStream>>) -> Self {
+        Self { socket }
+    }
+
+    /// Close the connection
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn close(mut self) -> Result<(), ClientError> {
+        self.socket.close(None).await?;
+        Ok(())
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let message = match self.socket.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(message))) => message,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match message {
+                Message::Text(text) => {
+                    return Poll::Ready(Some(serde_json::from_str(&text).map_err(Into::into)));
+                }
+                Message::Close(_) => return Poll::Ready(None),
+                // Pings are answered by tungstenite
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_events() {
+        let event: Event = serde_json::from_str(
+            r#"{"type":"device_state_changed","ieee_address":"00:11:22:33:44:55:66:77","endpoint":1,"state_on":true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event,
+            Event::DeviceStateChanged {
+                ieee_address: "00:11:22:33:44:55:66:77".to_string(),
+                endpoint: 1,
+                state_on: true,
+            }
+        );
+
+        let event: Event = serde_json::from_str(
+            r#"{"type":"firmware_progress","stage":"writing","bytes_written":128,"total_bytes":256}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            event,
+            Event::FirmwareProgress(casita_api_types::FirmwareProgress {
+                bytes_written: 128,
+                ..
+            })
+        ));
+
+        let event: Event = serde_json::from_str(r#"{"type":"something_new","x":1}"#).unwrap();
+        assert_eq!(event, Event::Unknown);
+    }
+}
@@ -0,0 +1,38 @@
+//! Rust client for the Casita Assistant API
+//!
+//! Wraps the REST API and the `/ws` event stream with typed requests and
+//! responses. Device and automation models are the server's own types from
+//! `zigbee-core` and `automation-engine`, and events and system info come
+//! from `casita-api-types`.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), casita_client::ClientError> {
+//! use futures::StreamExt;
+//!
+//! let client = casita_client::CasitaClient::new("http://casita.local:3000")?;
+//! for device in client.devices().await? {
+//!     println!("{} {:?}", device.ieee_address_string(), device.friendly_name);
+//! }
+//!
+//! let mut events = client.events().await?;
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod client;
+pub mod error;
+pub mod events;
+
+pub use client::{CasitaClient, SystemInfo};
+pub use error::ClientError;
+pub use events::{Event, EventStream};
+pub use zigbee_core::{network::NetworkStatus, ZigbeeDevice};
+
+#[cfg(feature = "automation")]
+pub use automation_engine::{
+    Automation, AutomationFolder, BulkAction, BulkReport, BulkRequest, CreateAutomationRequest,
+    DryRunReport, SnoozeRequest, UpdateAutomationRequest,
+};
@@ -0,0 +1,133 @@
+//! Frame parsing and SLIP throughput benchmarks
+//!
+//! Run with `cargo bench -p deconz-protocol`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use deconz_protocol::{ApsDataIndication, CommandId, Frame, SlipDecoder, SlipEncoder};
+
+/// APS data indication payload for an On/Off attribute report from a NWK-addressed device
+fn aps_indication_payload() -> Vec<u8> {
+    // ZCL report attributes: frame control, tsn, command, attr 0x0000 (bool) = 1
+    let asdu = [0x18, 0x2a, 0x0a, 0x00, 0x00, 0x10, 0x01];
+
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&[0x00, 0x00]); // payload_len (patched below)
+    payload.push(0x22); // device state
+    payload.extend_from_slice(&[0x02, 0x00, 0x00]); // dest: NWK 0x0000
+    payload.push(0x01); // dest endpoint
+    payload.extend_from_slice(&[0x02, 0x4f, 0x2a]); // src: NWK 0x2a4f
+    payload.push(0x01); // src endpoint
+    payload.extend_from_slice(&0x0104u16.to_le_bytes()); // profile
+    payload.extend_from_slice(&0x0006u16.to_le_bytes()); // cluster
+    payload.extend_from_slice(&u16::try_from(asdu.len()).unwrap().to_le_bytes());
+    payload.extend_from_slice(&asdu);
+    payload.push(0xff); // lqi
+    payload.push(0xc4); // rssi
+
+    let len = u16::try_from(payload.len() - 2).unwrap();
+    payload[..2].copy_from_slice(&len.to_le_bytes());
+    payload
+}
+
+/// A serialized APS data indication frame (before SLIP encoding)
+fn aps_indication_frame(sequence: u8) -> Vec<u8> {
+    Frame::new(
+        CommandId::ApsDataIndication,
+        sequence,
+        aps_indication_payload(),
+    )
+    .serialize()
+}
+
+/// A SLIP stream of `count` frames, as the reader would see it on the wire
+fn slip_stream(count: usize) -> Vec<u8> {
+    (0..count)
+        .flat_map(|i| SlipEncoder::encode(&aps_indication_frame((i % 256) as u8)))
+        .collect()
+}
+
+fn bench_slip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slip");
+
+    let frame = aps_indication_frame(1);
+    group.throughput(Throughput::Bytes(frame.len() as u64));
+    group.bench_function("encode", |b| {
+        b.iter(|| SlipEncoder::encode(black_box(&frame)));
+    });
+
+    // One second of heavy traffic: ~1000 frames
+    let stream = slip_stream(1000);
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("decode_1000_frames", |b| {
+        b.iter_batched(
+            SlipDecoder::new,
+            |mut decoder| decoder.feed(black_box(&stream)),
+            BatchSize::SmallInput,
+        );
+    });
+
+    // Same stream delivered in small reads, like a serial port with a short timeout
+    group.bench_function("decode_1000_frames_chunked", |b| {
+        b.iter_batched(
+            SlipDecoder::new,
+            |mut decoder| {
+                let mut frames = 0;
+                for chunk in stream.chunks(32) {
+                    frames += decoder.feed(black_box(chunk)).len();
+                }
+                frames
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    group.finish();
+}
+
+fn bench_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    let frame = aps_indication_frame(1);
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("deserialize", |b| {
+        b.iter(|| Frame::deserialize(black_box(&frame)).unwrap());
+    });
+
+    let parsed = Frame::deserialize(&frame).unwrap();
+    group.bench_function("serialize", |b| {
+        b.iter(|| black_box(&parsed).serialize());
+    });
+
+    group.finish();
+}
+
+fn bench_aps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aps");
+    let payload = aps_indication_payload();
+    let frame = aps_indication_frame(1);
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("indication_parse", |b| {
+        b.iter(|| ApsDataIndication::parse(black_box(&payload)).unwrap());
+    });
+
+    // Full receive path for one frame: SLIP decode, frame deserialize, APS parse
+    let encoded = SlipEncoder::encode(&frame);
+    group.bench_function("receive_path", |b| {
+        b.iter_batched(
+            SlipDecoder::new,
+            |mut decoder| {
+                for data in decoder.feed(black_box(&encoded)) {
+                    let frame = Frame::deserialize(&data).unwrap();
+                    ApsDataIndication::parse(&frame.payload).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_slip, bench_frame, bench_aps);
+criterion_main!(benches);
@@ -0,0 +1,118 @@
+//! Soak test for the receive path
+//!
+//! Generates APS data indication traffic at a fixed rate and pushes it through
+//! SLIP decoding, frame deserialization and APS parsing, reporting throughput
+//! and any decode failures once per second.
+//!
+//! ```text
+//! cargo run --release -p deconz-protocol --example soak -- [frames_per_sec] [seconds]
+//! ```
+
+use deconz_protocol::{ApsDataIndication, CommandId, Frame, SlipDecoder, SlipEncoder};
+use std::time::{Duration, Instant};
+
+/// Frames generated per batch (the rate is enforced per batch)
+const BATCH_SIZE: u64 = 100;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let rate: u64 = args.next().and_then(|v| v.parse().ok()).unwrap_or(5000);
+    let seconds: u64 = args.next().and_then(|v| v.parse().ok()).unwrap_or(10);
+
+    println!("Soak test: {rate} frames/s for {seconds}s");
+
+    let mut decoder = SlipDecoder::new();
+    let mut sequence: u8 = 0;
+    let mut total_frames = 0u64;
+    let mut total_errors = 0u64;
+    let mut total_bytes = 0u64;
+
+    let start = Instant::now();
+    let mut window_start = start;
+    let mut window_frames = 0u64;
+    let mut busy = Duration::ZERO;
+    let batch_interval = Duration::from_secs_f64(BATCH_SIZE as f64 / rate.max(1) as f64);
+
+    while start.elapsed() < Duration::from_secs(seconds) {
+        let batch_start = Instant::now();
+
+        let mut wire = Vec::new();
+        for _ in 0..BATCH_SIZE {
+            sequence = sequence.wrapping_add(1);
+            let frame = Frame::new(
+                CommandId::ApsDataIndication,
+                sequence,
+                indication_payload(sequence),
+            );
+            wire.extend_from_slice(&SlipEncoder::encode(&frame.serialize()));
+        }
+        total_bytes += wire.len() as u64;
+
+        // Feed in serial-sized reads
+        for chunk in wire.chunks(64) {
+            for data in decoder.feed(chunk) {
+                let ok = Frame::deserialize(&data)
+                    .and_then(|frame| ApsDataIndication::parse(&frame.payload))
+                    .is_ok();
+                if ok {
+                    total_frames += 1;
+                    window_frames += 1;
+                } else {
+                    total_errors += 1;
+                }
+            }
+        }
+
+        let elapsed = batch_start.elapsed();
+        busy += elapsed;
+        if let Some(remaining) = batch_interval.checked_sub(elapsed) {
+            std::thread::sleep(remaining);
+        }
+
+        if window_start.elapsed() >= Duration::from_secs(1) {
+            println!(
+                "{:>4}s  {:>7} frames/s  {} errors",
+                start.elapsed().as_secs(),
+                window_frames,
+                total_errors
+            );
+            window_start = Instant::now();
+            window_frames = 0;
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "Done: {total_frames} frames ({total_bytes} bytes) in {elapsed:.1}s, {total_errors} errors, \
+         {:.1}% busy, {:.2} us/frame",
+        busy.as_secs_f64() / elapsed * 100.0,
+        busy.as_secs_f64() * 1e6 / total_frames.max(1) as f64
+    );
+
+    if total_errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// On/Off report payload; the sequence is used as source address so every
+/// frame differs (and exercises SLIP escaping on 0xC0/0xDB values)
+fn indication_payload(sequence: u8) -> Vec<u8> {
+    let asdu = [0x18, sequence, 0x0a, 0x00, 0x00, 0x10, sequence & 0x01];
+
+    let mut payload = vec![0x00, 0x00]; // payload_len (patched below)
+    payload.push(0x22); // device state
+    payload.extend_from_slice(&[0x02, 0x00, 0x00]); // dest: NWK 0x0000
+    payload.push(0x01); // dest endpoint
+    payload.extend_from_slice(&[0x02, sequence, 0xc0]); // src: NWK 0xc0xx
+    payload.push(0x01); // src endpoint
+    payload.extend_from_slice(&0x0104u16.to_le_bytes()); // profile
+    payload.extend_from_slice(&0x0006u16.to_le_bytes()); // cluster
+    payload.extend_from_slice(&[asdu.len() as u8, 0x00]);
+    payload.extend_from_slice(&asdu);
+    payload.push(0xff); // lqi
+    payload.push(0xc4); // rssi
+
+    let len = (payload.len() - 2) as u16;
+    payload[..2].copy_from_slice(&len.to_le_bytes());
+    payload
+}
@@ -0,0 +1,271 @@
+//! Protocol capture
+//!
+//! Optional tap that logs every frame sent to or received from the adapter
+//! (before SLIP encoding) as JSON lines:
+//!
+//! ```text
+//! {"ts":1760000000.123456,"dir":"tx","cmd":"0x0d","seq":5,"frame":"0d0500..."}
+//! ```
+//!
+//! The file is rotated once it reaches the configured size, keeping one
+//! previous file with a `.1` suffix. Lines are written by a thread of their
+//! own so the transport never waits for the disk; if it falls behind by
+//! [`QUEUE_CAPACITY`] frames, frames are dropped and counted.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default size at which the capture file is rotated
+pub const DEFAULT_CAPTURE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Frames waiting to be written before new ones are dropped
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Frame direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Host to adapter
+    Tx,
+    /// Adapter to host
+    Rx,
+}
+
+/// Current capture state
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStatus {
+    pub active: bool,
+    pub path: Option<PathBuf>,
+    /// Frames written since the capture started
+    pub frames: u64,
+    /// Bytes written to the current file
+    pub bytes: u64,
+    /// Frames dropped because the writer fell behind
+    pub dropped: u64,
+}
+
+/// Counts shared with the writer thread
+#[derive(Default)]
+struct CaptureCounts {
+    frames: AtomicU64,
+    bytes: AtomicU64,
+    dropped: AtomicU64,
+}
+
+struct CaptureFile {
+    path: PathBuf,
+    file: File,
+    max_bytes: u64,
+    bytes: u64,
+    counts: Arc<CaptureCounts>,
+}
+
+impl CaptureFile {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.bytes + line.len() as u64 > self.max_bytes && self.bytes > 0 {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.bytes += line.len() as u64;
+        self.counts.frames.fetch_add(1, Ordering::Relaxed);
+        self.counts.bytes.store(self.bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(&self.path, rotated)?;
+        self.file = File::create(&self.path)?;
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+/// A running capture: the queue to its writer thread
+struct CaptureWriter {
+    path: PathBuf,
+    tx: SyncSender<String>,
+    thread: JoinHandle<()>,
+    counts: Arc<CaptureCounts>,
+}
+
+/// Frame tap shared by the transport's reader and writer paths
+#[derive(Default)]
+pub struct FrameCapture {
+    active: AtomicBool,
+    writer: Mutex<Option<CaptureWriter>>,
+}
+
+impl FrameCapture {
+    /// Start capturing to `path`, appending if it already exists
+    ///
+    /// Blocking (it opens the file, and stops a running capture first).
+    #[allow(clippy::missing_errors_doc)]
+    pub fn start(&self, path: &Path, max_bytes: u64) -> io::Result<()> {
+        self.stop();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let counts = Arc::new(CaptureCounts::default());
+        let mut capture = CaptureFile {
+            path: path.to_path_buf(),
+            bytes: file.metadata()?.len(),
+            file,
+            max_bytes: max_bytes.max(1),
+            counts: Arc::clone(&counts),
+        };
+        counts.bytes.store(capture.bytes, Ordering::Relaxed);
+
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_CAPACITY);
+        let thread = std::thread::Builder::new()
+            .name("frame-capture".to_string())
+            .spawn(move || {
+                for line in rx {
+                    if let Err(e) = capture.write_line(&line) {
+                        // Dropping the queue tells `record` to stop
+                        tracing::warn!("Protocol capture failed, stopping: {}", e);
+                        return;
+                    }
+                }
+            })?;
+
+        *self.lock() = Some(CaptureWriter {
+            path: path.to_path_buf(),
+            tx,
+            thread,
+            counts,
+        });
+        self.active.store(true, Ordering::Release);
+        tracing::info!("Protocol capture started: {:?}", path);
+        Ok(())
+    }
+
+    /// Stop capturing, once the frames still queued are written
+    ///
+    /// Blocking.
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::Release);
+        let Some(writer) = self.lock().take() else {
+            return;
+        };
+        drop(writer.tx);
+        let _ = writer.thread.join();
+        tracing::info!(
+            "Protocol capture stopped: {:?} ({} frames)",
+            writer.path,
+            writer.counts.frames.load(Ordering::Relaxed)
+        );
+    }
+
+    /// Current capture state
+    #[must_use]
+    pub fn status(&self) -> CaptureStatus {
+        match self.lock().as_ref() {
+            Some(writer) => CaptureStatus {
+                active: true,
+                path: Some(writer.path.clone()),
+                frames: writer.counts.frames.load(Ordering::Relaxed),
+                bytes: writer.counts.bytes.load(Ordering::Relaxed),
+                dropped: writer.counts.dropped.load(Ordering::Relaxed),
+            },
+            None => CaptureStatus::default(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<CaptureWriter>> {
+        self.writer
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Queue a frame to be logged if capturing
+    pub(crate) fn record(&self, direction: Direction, frame: &[u8]) {
+        if !self.active.load(Ordering::Acquire) {
+            return;
+        }
+        let line = format_line(SystemTime::now(), direction, frame);
+
+        let mut guard = self.lock();
+        let Some(writer) = guard.as_ref() else {
+            return;
+        };
+        match writer.tx.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                writer.counts.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                // The writer thread gave up
+                *guard = None;
+                self.active.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+fn format_line(time: SystemTime, direction: Direction, frame: &[u8]) -> String {
+    let ts = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let dir = match direction {
+        Direction::Tx => "tx",
+        Direction::Rx => "rx",
+    };
+
+    let mut line = format!("{{\"ts\":{ts:.6},\"dir\":\"{dir}\"");
+    if let [cmd, seq, ..] = frame {
+        let _ = write!(line, ",\"cmd\":\"{cmd:#04x}\",\"seq\":{seq}");
+    }
+    line.push_str(",\"frame\":\"");
+    for byte in frame {
+        let _ = write!(line, "{byte:02x}");
+    }
+    line.push_str("\"}\n");
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_line() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        assert_eq!(
+            format_line(time, Direction::Rx, &[0x0d, 0x05, 0x00]),
+            "{\"ts\":1700000000.500000,\"dir\":\"rx\",\"cmd\":\"0x0d\",\"seq\":5,\"frame\":\"0d0500\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_capture_rotates() {
+        let dir = std::env::temp_dir().join(format!("deconz-capture-{}", std::process::id()));
+        let path = dir.join("frames.jsonl");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let capture = FrameCapture::default();
+        capture.start(&path, 100).unwrap();
+        for _ in 0..3 {
+            capture.record(Direction::Tx, &[0x0d, 0x01, 0x00, 0x08, 0x00]);
+        }
+        assert!(capture.status().active);
+
+        // Stopping writes what's queued
+        capture.stop();
+        assert!(!capture.status().active);
+        let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&dir.join("frames.jsonl.1")), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
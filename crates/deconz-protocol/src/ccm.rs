@@ -0,0 +1,245 @@
+//! AES-128 CCM* as used by Zigbee security
+//!
+//! Only what the host needs to check frames the firmware passes through
+//! untouched (Green Power): a MIC check with decryption, and the matching
+//! encryption for tests. Nonces are 13 bytes and lengths are 2 bytes
+//! (`L = 2`), as everywhere in Zigbee.
+
+/// AES S-box
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+/// Key schedule round constants
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// AES-128 block encryption (CCM* never needs the inverse cipher)
+struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl Aes128 {
+    fn new(key: &[u8; 16]) -> Self {
+        let mut words = [[0u8; 4]; 44];
+        for (i, word) in key.chunks_exact(4).enumerate() {
+            words[i].copy_from_slice(word);
+        }
+        for i in 4..44 {
+            let mut t = words[i - 1];
+            if i % 4 == 0 {
+                t = [
+                    SBOX[usize::from(t[1])] ^ RCON[i / 4 - 1],
+                    SBOX[usize::from(t[2])],
+                    SBOX[usize::from(t[3])],
+                    SBOX[usize::from(t[0])],
+                ];
+            }
+            for j in 0..4 {
+                words[i][j] = words[i - 4][j] ^ t[j];
+            }
+        }
+        let mut round_keys = [[0u8; 16]; 11];
+        for (round, round_key) in round_keys.iter_mut().enumerate() {
+            for column in 0..4 {
+                round_key[4 * column..4 * column + 4].copy_from_slice(&words[4 * round + column]);
+            }
+        }
+        Self { round_keys }
+    }
+
+    fn encrypt(&self, block: &mut [u8; 16]) {
+        xor(block, &self.round_keys[0]);
+        for round in 1..=10 {
+            for byte in block.iter_mut() {
+                *byte = SBOX[usize::from(*byte)];
+            }
+            shift_rows(block);
+            if round != 10 {
+                mix_columns(block);
+            }
+            xor(block, &self.round_keys[round]);
+        }
+    }
+}
+
+/// The state is column-major: byte `row + 4 * column`
+fn shift_rows(block: &mut [u8; 16]) {
+    let old = *block;
+    for row in 1..4 {
+        for column in 0..4 {
+            block[row + 4 * column] = old[row + 4 * ((column + row) % 4)];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; 16]) {
+    let double = |x: u8| (x << 1) ^ if x & 0x80 != 0 { 0x1b } else { 0 };
+    for column in block.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        column[0] = double(a0) ^ double(a1) ^ a1 ^ a2 ^ a3;
+        column[1] = a0 ^ double(a1) ^ double(a2) ^ a2 ^ a3;
+        column[2] = a0 ^ a1 ^ double(a2) ^ double(a3) ^ a3;
+        column[3] = double(a0) ^ a0 ^ a1 ^ a2 ^ double(a3);
+    }
+}
+
+/// XOR `data` into the start of `block`
+fn xor(block: &mut [u8; 16], data: &[u8]) {
+    for (b, d) in block.iter_mut().zip(data) {
+        *b ^= d;
+    }
+}
+
+/// CBC-MAC over the CCM* blocks of `a` (authenticated only) and `m`
+fn authenticate(aes: &Aes128, nonce: &[u8; 13], a: &[u8], m: &[u8], mic_len: usize) -> [u8; 16] {
+    let mut x = [0u8; 16];
+    let adata = if a.is_empty() { 0 } else { 0x40 };
+    // Both lengths fit: frames are far shorter than 64 KiB
+    #[allow(clippy::cast_possible_truncation)]
+    {
+        x[0] = adata | (((mic_len as u8).saturating_sub(2) / 2) << 3) | 0x01;
+        x[14..].copy_from_slice(&(m.len() as u16).to_be_bytes());
+    }
+    x[1..14].copy_from_slice(nonce);
+    aes.encrypt(&mut x);
+
+    let mut blocks = Vec::with_capacity(a.len() + 2);
+    if !a.is_empty() {
+        #[allow(clippy::cast_possible_truncation)]
+        blocks.extend_from_slice(&(a.len() as u16).to_be_bytes());
+        blocks.extend_from_slice(a);
+    }
+    // Zero padding of the last block is the same as XOR-ing fewer bytes
+    for chunk in blocks.chunks(16).chain(m.chunks(16)) {
+        xor(&mut x, chunk);
+        aes.encrypt(&mut x);
+    }
+    x
+}
+
+/// Key stream block `i`
+fn key_stream(aes: &Aes128, nonce: &[u8; 13], i: u16) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[0] = 0x01;
+    block[1..14].copy_from_slice(nonce);
+    block[14..].copy_from_slice(&i.to_be_bytes());
+    aes.encrypt(&mut block);
+    block
+}
+
+/// XOR `data` with the key stream from block 1 on
+fn apply_key_stream(aes: &Aes128, nonce: &[u8; 13], data: &[u8]) -> Vec<u8> {
+    data.chunks(16)
+        .zip(1u16..)
+        .flat_map(|(chunk, i)| {
+            let stream = key_stream(aes, nonce, i);
+            chunk
+                .iter()
+                .zip(stream)
+                .map(|(d, s)| d ^ s)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Encrypt `m` and authenticate it with `a`, returning the ciphertext and
+/// a MIC of `mic_len` bytes (4, 8 or 16)
+#[must_use]
+pub fn encrypt(
+    key: &[u8; 16],
+    nonce: &[u8; 13],
+    a: &[u8],
+    m: &[u8],
+    mic_len: usize,
+) -> (Vec<u8>, Vec<u8>) {
+    let aes = Aes128::new(key);
+    let tag = authenticate(&aes, nonce, a, m, mic_len);
+    let s0 = key_stream(&aes, nonce, 0);
+    let mic = tag
+        .iter()
+        .zip(s0)
+        .take(mic_len)
+        .map(|(t, s)| t ^ s)
+        .collect();
+    (apply_key_stream(&aes, nonce, m), mic)
+}
+
+/// Decrypt `c` and check `mic` over it and `a`; `None` if the MIC doesn't
+/// match (wrong key, or the frame was tampered with)
+#[must_use]
+pub fn decrypt(
+    key: &[u8; 16],
+    nonce: &[u8; 13],
+    a: &[u8],
+    c: &[u8],
+    mic: &[u8],
+) -> Option<Vec<u8>> {
+    let aes = Aes128::new(key);
+    let m = apply_key_stream(&aes, nonce, c);
+    let tag = authenticate(&aes, nonce, a, &m, mic.len());
+    let s0 = key_stream(&aes, nonce, 0);
+    let diff = tag
+        .iter()
+        .zip(s0)
+        .zip(mic)
+        .fold(0, |diff, ((t, s), m)| diff | (t ^ s ^ m));
+    (diff == 0 && !mic.is_empty()).then_some(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aes_fips_197() {
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let mut block: [u8; 16] = core::array::from_fn(|i| (i as u8) * 0x11);
+        Aes128::new(&key).encrypt(&mut block);
+        assert_eq!(
+            block,
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ccm_rfc_3610_packet_1() {
+        let key: [u8; 16] = core::array::from_fn(|i| 0xc0 + i as u8);
+        let nonce = [
+            0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5,
+        ];
+        let a: Vec<u8> = (0x00..0x08).collect();
+        let m: Vec<u8> = (0x08..0x1f).collect();
+        let (c, mic) = encrypt(&key, &nonce, &a, &m, 8);
+        assert_eq!(
+            c,
+            [
+                0x58, 0x8c, 0x97, 0x9a, 0x61, 0xc6, 0x63, 0xd2, 0xf0, 0x66, 0xd0, 0xc2, 0xc0, 0xf9,
+                0x89, 0x80, 0x6d, 0x5f, 0x6b, 0x61, 0xda, 0xc3, 0x84
+            ]
+        );
+        assert_eq!(mic, [0x17, 0xe8, 0xd1, 0x2c, 0xfd, 0xf9, 0x26, 0xe0]);
+        assert_eq!(decrypt(&key, &nonce, &a, &c, &mic), Some(m));
+
+        let mut forged = c.clone();
+        forged[0] ^= 1;
+        assert_eq!(decrypt(&key, &nonce, &a, &forged, &mic), None);
+    }
+}
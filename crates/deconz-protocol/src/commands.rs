@@ -1,7 +1,7 @@
 //! deCONZ protocol command definitions
 
 /// Command IDs for deCONZ serial protocol
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum CommandId {
     /// APS data confirm (response to APS request)
@@ -92,6 +92,8 @@ pub enum NetworkParameter {
     NwkUpdateId = 0x24,
     /// Watchdog TTL (4 bytes)
     WatchdogTtl = 0x26,
+    /// Outgoing NWK frame counter (4 bytes)
+    NwkFrameCounter = 0x27,
 }
 
 impl NetworkParameter {
@@ -115,6 +117,7 @@ impl NetworkParameter {
             0x22 => Some(NetworkParameter::ProtocolVersion),
             0x24 => Some(NetworkParameter::NwkUpdateId),
             0x26 => Some(NetworkParameter::WatchdogTtl),
+            0x27 => Some(NetworkParameter::NwkFrameCounter),
             _ => None,
         }
     }
@@ -132,7 +135,9 @@ impl NetworkParameter {
             NetworkParameter::NwkPanId
             | NetworkParameter::NwkAddress
             | NetworkParameter::ProtocolVersion => 2,
-            NetworkParameter::ChannelMask | NetworkParameter::WatchdogTtl => 4,
+            NetworkParameter::ChannelMask
+            | NetworkParameter::WatchdogTtl
+            | NetworkParameter::NwkFrameCounter => 4,
             NetworkParameter::MacAddress
             | NetworkParameter::NwkExtendedPanId
             | NetworkParameter::ApsExtendedPanId
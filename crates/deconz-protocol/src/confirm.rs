@@ -0,0 +1,303 @@
+//! APS data confirm decoding
+//!
+//! Every APS request is answered twice: right away when the firmware queues
+//! it, and later with an `APS_DATA_CONFIRM` (0x04) once delivery succeeded or
+//! failed. The firmware sets the confirm flag in the device state and the
+//! host fetches it:
+//!
+//! ```text
+//! [payload_len: 2 bytes LE]
+//! [device state: 1 byte]
+//! [request ID: 1 byte]
+//! [destination address mode: 1 byte]
+//! [destination address: 2 bytes (group/NWK) or 8 bytes (IEEE)]
+//! [destination endpoint: 1 byte]       (not for group addressing)
+//! [source endpoint: 1 byte]
+//! [confirm status: 1 byte]
+//! [reserved: 4 bytes]                  (newer firmware)
+//! ```
+//!
+//! The status comes from whichever layer gave up: APS (0xA0-0xB0), NWK
+//! (0xC1-0xD3) or MAC (0xE0-0xF1).
+
+use crate::types::{DeviceState, ProtocolError};
+use std::fmt;
+
+/// Delivery result of an APS request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmStatus {
+    Success,
+    // APS layer
+    AsduTooLong,
+    IllegalRequest,
+    InvalidBinding,
+    InvalidGroup,
+    /// No APS acknowledgement from the destination
+    NoAck,
+    NoBoundDevice,
+    NoShortAddress,
+    SecurityFail,
+    TableFull,
+    // NWK layer
+    NwkInvalidRequest,
+    NotPermitted,
+    UnknownDevice,
+    NoKey,
+    RouteDiscoveryFailed,
+    RouteError,
+    FrameNotBuffered,
+    // MAC layer
+    ChannelAccessFailure,
+    /// No MAC acknowledgement from the next hop
+    MacNoAck,
+    TransactionExpired,
+    TransactionOverflow,
+    Other(u8),
+}
+
+impl ConfirmStatus {
+    #[must_use]
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => Self::Success,
+            0xA0 => Self::AsduTooLong,
+            0xA3 => Self::IllegalRequest,
+            0xA4 => Self::InvalidBinding,
+            0xA5 => Self::InvalidGroup,
+            0xA7 => Self::NoAck,
+            0xA8 => Self::NoBoundDevice,
+            0xA9 => Self::NoShortAddress,
+            0xAD => Self::SecurityFail,
+            0xAE => Self::TableFull,
+            0xC2 => Self::NwkInvalidRequest,
+            0xC3 => Self::NotPermitted,
+            0xC8 => Self::UnknownDevice,
+            0xCD => Self::NoKey,
+            0xD0 => Self::RouteDiscoveryFailed,
+            0xD1 => Self::RouteError,
+            0xD3 => Self::FrameNotBuffered,
+            0xE1 => Self::ChannelAccessFailure,
+            0xE9 => Self::MacNoAck,
+            0xF0 => Self::TransactionExpired,
+            0xF1 => Self::TransactionOverflow,
+            other => Self::Other(other),
+        }
+    }
+
+    /// Status byte as sent by the firmware
+    #[must_use]
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Success => 0x00,
+            Self::AsduTooLong => 0xA0,
+            Self::IllegalRequest => 0xA3,
+            Self::InvalidBinding => 0xA4,
+            Self::InvalidGroup => 0xA5,
+            Self::NoAck => 0xA7,
+            Self::NoBoundDevice => 0xA8,
+            Self::NoShortAddress => 0xA9,
+            Self::SecurityFail => 0xAD,
+            Self::TableFull => 0xAE,
+            Self::NwkInvalidRequest => 0xC2,
+            Self::NotPermitted => 0xC3,
+            Self::UnknownDevice => 0xC8,
+            Self::NoKey => 0xCD,
+            Self::RouteDiscoveryFailed => 0xD0,
+            Self::RouteError => 0xD1,
+            Self::FrameNotBuffered => 0xD3,
+            Self::ChannelAccessFailure => 0xE1,
+            Self::MacNoAck => 0xE9,
+            Self::TransactionExpired => 0xF0,
+            Self::TransactionOverflow => 0xF1,
+            Self::Other(code) => code,
+        }
+    }
+
+    #[must_use]
+    pub fn is_success(self) -> bool {
+        self == Self::Success
+    }
+
+    /// Whether the device couldn't be reached at its short address, which
+    /// may be stale after it rejoined somewhere else in the mesh
+    #[must_use]
+    pub fn is_unreachable(self) -> bool {
+        matches!(
+            self,
+            Self::NoAck
+                | Self::NoShortAddress
+                | Self::UnknownDevice
+                | Self::RouteDiscoveryFailed
+                | Self::RouteError
+                | Self::MacNoAck
+        )
+    }
+
+    /// Whether the frame certainly never reached the destination, as
+    /// opposed to a missing acknowledgement for a frame that may have
+    #[must_use]
+    pub fn is_undelivered(self) -> bool {
+        matches!(
+            self,
+            Self::NoShortAddress
+                | Self::UnknownDevice
+                | Self::RouteDiscoveryFailed
+                | Self::RouteError
+        )
+    }
+
+    /// What went wrong, and usually why
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Success => "delivered",
+            Self::AsduTooLong => "the frame is too long to send",
+            Self::IllegalRequest => "the adapter rejected the request",
+            Self::InvalidBinding => "no such binding",
+            Self::InvalidGroup => "no such group",
+            Self::NoAck => {
+                "the device didn't acknowledge the frame; it is probably off, out of range or \
+                 asleep"
+            }
+            Self::NoBoundDevice => "no device is bound for this cluster",
+            Self::NoShortAddress => "the device's network address is unknown",
+            Self::SecurityFail => {
+                "the frame couldn't be secured or the device rejected its security"
+            }
+            Self::TableFull => "an adapter table (bindings, groups) is full",
+            Self::NwkInvalidRequest => "the network layer rejected the request",
+            Self::NotPermitted => "the network layer doesn't permit this request",
+            Self::UnknownDevice => "the device isn't known to the network",
+            Self::NoKey => "no network or link key to secure the frame",
+            Self::RouteDiscoveryFailed => {
+                "no route to the device was found; it may have left or lost power"
+            }
+            Self::RouteError => "a router on the way couldn't forward the frame",
+            Self::FrameNotBuffered => "the frame couldn't be buffered for a sleeping device",
+            Self::ChannelAccessFailure => {
+                "the channel was too busy to transmit; check for Wi-Fi interference"
+            }
+            Self::MacNoAck => {
+                "the next hop didn't acknowledge the frame; the link is weak or the router is gone"
+            }
+            Self::TransactionExpired => {
+                "the sleeping device didn't poll for the frame in time; wake it and retry"
+            }
+            Self::TransactionOverflow => "too many frames are waiting for sleeping devices",
+            Self::Other(_) => "unknown status",
+        }
+    }
+}
+
+impl fmt::Display for ConfirmStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#04x})", self.description(), self.code())
+    }
+}
+
+/// Where a confirmed request was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmDestination {
+    Group(u16),
+    Nwk(u16),
+    Ieee([u8; 8]),
+}
+
+/// Parsed `APS_DATA_CONFIRM` response
+#[derive(Debug, Clone)]
+pub struct ApsDataConfirm {
+    pub device_state: DeviceState,
+    pub request_id: u8,
+    pub destination: ConfirmDestination,
+    pub dest_endpoint: Option<u8>,
+    pub src_endpoint: u8,
+    pub status: ConfirmStatus,
+}
+
+impl ApsDataConfirm {
+    /// Parse a confirm payload (including the leading length)
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        let too_short = || ProtocolError::FrameTooShort(data.len());
+        if data.len() < 5 {
+            return Err(too_short());
+        }
+
+        let device_state = DeviceState::from_byte(data[2]);
+        let request_id = data[3];
+        let (destination, rest) = match data[4] {
+            mode @ (0x01 | 0x02) => {
+                let addr = data.get(5..7).ok_or_else(too_short)?;
+                let addr = u16::from_le_bytes([addr[0], addr[1]]);
+                let destination = if mode == 0x01 {
+                    ConfirmDestination::Group(addr)
+                } else {
+                    ConfirmDestination::Nwk(addr)
+                };
+                (destination, &data[7..])
+            }
+            0x03 => {
+                let ieee = data.get(5..13).ok_or_else(too_short)?;
+                let ieee = ieee.try_into().map_err(|_| too_short())?;
+                (ConfirmDestination::Ieee(ieee), &data[13..])
+            }
+            mode => {
+                return Err(ProtocolError::InvalidFrame(format!(
+                    "unknown confirm address mode {mode:#04x}"
+                )))
+            }
+        };
+
+        let (dest_endpoint, rest) = match (destination, rest) {
+            (ConfirmDestination::Group(_), rest) => (None, rest),
+            (_, [endpoint, rest @ ..]) => (Some(*endpoint), rest),
+            _ => return Err(too_short()),
+        };
+        let [src_endpoint, status, ..] = rest else {
+            return Err(too_short());
+        };
+
+        Ok(Self {
+            device_state,
+            request_id,
+            destination,
+            dest_endpoint,
+            src_endpoint: *src_endpoint,
+            status: ConfirmStatus::from_code(*status),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_confirm() {
+        // NWK destination 0x1234:1, no APS ack, 4 reserved bytes
+        let data = [
+            0x0F, 0x00, 0x26, 0x05, 0x02, 0x34, 0x12, 0x01, 0x01, 0xA7, 0, 0, 0, 0,
+        ];
+        let confirm = ApsDataConfirm::parse(&data).unwrap();
+        assert_eq!(confirm.request_id, 5);
+        assert_eq!(confirm.destination, ConfirmDestination::Nwk(0x1234));
+        assert_eq!(confirm.dest_endpoint, Some(1));
+        assert_eq!(confirm.status, ConfirmStatus::NoAck);
+        assert!(confirm.device_state.aps_request_free_slots);
+        assert!(confirm.status.to_string().ends_with("(0xa7)"));
+
+        // Group destination has no endpoint
+        let data = [0x08, 0x00, 0x22, 0x09, 0x01, 0x02, 0x00, 0x01, 0x00];
+        let confirm = ApsDataConfirm::parse(&data).unwrap();
+        assert_eq!(confirm.destination, ConfirmDestination::Group(2));
+        assert_eq!(confirm.dest_endpoint, None);
+        assert!(confirm.status.is_success());
+
+        assert!(ApsDataConfirm::parse(&data[..6]).is_err());
+        assert_eq!(
+            ConfirmStatus::from_code(0xD0),
+            ConfirmStatus::RouteDiscoveryFailed
+        );
+        assert_eq!(ConfirmStatus::from_code(0xB7).code(), 0xB7);
+    }
+}
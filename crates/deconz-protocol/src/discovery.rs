@@ -0,0 +1,211 @@
+//! Adapter discovery
+//!
+//! Enumerates serial ports and recognizes deCONZ adapters by USB VID/PID.
+//! `ConBee` I and III sit behind a generic FTDI bridge, so for those the USB
+//! product/manufacturer strings must also mention `ConBee`. `RaspBee` boards
+//! are wired to the Raspberry Pi UART and can't be identified over USB; the
+//! UART is only offered when no USB adapter is present.
+
+use crate::transport::{DeconzTransport, BAUD_RATE};
+use crate::types::ProtocolError;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::path::Path;
+
+/// dresden elektronik USB vendor ID (`ConBee` II)
+const VID_DRESDEN_ELEKTRONIK: u16 = 0x1cf1;
+/// `ConBee` II product ID
+const PID_CONBEE_II: u16 = 0x0030;
+/// FTDI USB vendor ID (`ConBee` I and III)
+const VID_FTDI: u16 = 0x0403;
+/// FTDI FT230X product ID
+const PID_FT230X: u16 = 0x6015;
+
+/// UARTs a `RaspBee` is attached to, in order of preference
+const RASPBEE_PATHS: &[&str] = &["/dev/serial0", "/dev/ttyAMA0"];
+
+/// Known deCONZ adapter models
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterModel {
+    ConBee,
+    ConBeeII,
+    ConBeeIII,
+    RaspBee,
+}
+
+impl AdapterModel {
+    /// Baud rate the adapter's firmware uses
+    #[must_use]
+    pub fn default_baud_rate(self) -> u32 {
+        match self {
+            AdapterModel::ConBee | AdapterModel::RaspBee => 38_400,
+            AdapterModel::ConBeeII | AdapterModel::ConBeeIII => BAUD_RATE,
+        }
+    }
+
+    /// Identify an adapter from its USB descriptor
+    #[must_use]
+    pub fn from_usb(
+        vid: u16,
+        pid: u16,
+        product: Option<&str>,
+        manufacturer: Option<&str>,
+    ) -> Option<Self> {
+        match (vid, pid) {
+            (VID_DRESDEN_ELEKTRONIK, PID_CONBEE_II) => Some(AdapterModel::ConBeeII),
+            (VID_FTDI, PID_FT230X) => {
+                let names = [product, manufacturer].map(|s| s.unwrap_or_default().to_lowercase());
+                if names.iter().any(|s| s.contains("conbee iii")) {
+                    Some(AdapterModel::ConBeeIII)
+                } else if names.iter().any(|s| s.contains("conbee")) {
+                    Some(AdapterModel::ConBee)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AdapterModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AdapterModel::ConBee => "ConBee",
+            AdapterModel::ConBeeII => "ConBee II",
+            AdapterModel::ConBeeIII => "ConBee III",
+            AdapterModel::RaspBee => "RaspBee",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A detected adapter
+#[derive(Debug, Clone)]
+pub struct Adapter {
+    /// Serial port path
+    pub path: String,
+    pub model: AdapterModel,
+    pub serial_number: Option<String>,
+}
+
+/// List all recognized adapters
+#[allow(clippy::missing_errors_doc)]
+pub fn list_adapters() -> Result<Vec<Adapter>, ProtocolError> {
+    let ports = serialport::available_ports()
+        .map_err(|e| ProtocolError::SerialError(std::io::Error::other(e)))?;
+    let mut adapters = usb_adapters(&ports);
+
+    if adapters.is_empty() {
+        if let Some(path) = RASPBEE_PATHS.iter().find(|p| Path::new(p).exists()) {
+            adapters.push(Adapter {
+                path: (*path).to_string(),
+                model: AdapterModel::RaspBee,
+                serial_number: None,
+            });
+        }
+    }
+
+    Ok(adapters)
+}
+
+fn usb_adapters(ports: &[SerialPortInfo]) -> Vec<Adapter> {
+    let mut adapters: Vec<Adapter> = Vec::new();
+    for port in ports {
+        let SerialPortType::UsbPort(usb) = &port.port_type else {
+            continue;
+        };
+        let Some(model) = AdapterModel::from_usb(
+            usb.vid,
+            usb.pid,
+            usb.product.as_deref(),
+            usb.manufacturer.as_deref(),
+        ) else {
+            continue;
+        };
+        // macOS lists every device twice (cu.* and tty.*); keep the call-out device
+        if let Some(existing) = adapters
+            .iter_mut()
+            .find(|a| a.serial_number.is_some() && a.serial_number == usb.serial_number)
+        {
+            if port.port_name.contains("/cu.") {
+                existing.path.clone_from(&port.port_name);
+            }
+            continue;
+        }
+        adapters.push(Adapter {
+            path: port.port_name.clone(),
+            model,
+            serial_number: usb.serial_number.clone(),
+        });
+    }
+    adapters
+}
+
+impl DeconzTransport {
+    /// Find the single attached deCONZ adapter
+    ///
+    /// Fails with [`ProtocolError::AdapterNotFound`] if none is attached and
+    /// [`ProtocolError::MultipleAdapters`] if the choice is ambiguous.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn discover() -> Result<Adapter, ProtocolError> {
+        let mut adapters = list_adapters()?;
+        match adapters.len() {
+            0 => Err(ProtocolError::AdapterNotFound),
+            1 => Ok(adapters.remove(0)),
+            _ => Err(ProtocolError::MultipleAdapters(
+                adapters
+                    .iter()
+                    .map(|a| format!("{} ({})", a.path, a.model))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb_port(name: &str, vid: u16, pid: u16, product: &str, serial: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: Some(serial.to_string()),
+                manufacturer: None,
+                product: Some(product.to_string()),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_identify_models() {
+        assert_eq!(
+            AdapterModel::from_usb(0x1cf1, 0x0030, Some("ConBee II"), None),
+            Some(AdapterModel::ConBeeII)
+        );
+        assert_eq!(
+            AdapterModel::from_usb(0x0403, 0x6015, Some("ConBee III"), None),
+            Some(AdapterModel::ConBeeIII)
+        );
+        assert_eq!(
+            AdapterModel::from_usb(0x0403, 0x6015, Some("FT230X Basic UART"), None),
+            None
+        );
+        assert_eq!(AdapterModel::from_usb(0x10c4, 0xea60, None, None), None);
+    }
+
+    #[test]
+    fn test_usb_adapters_dedup_macos() {
+        let ports = vec![
+            usb_port("/dev/tty.usbmodem1", 0x1cf1, 0x0030, "ConBee II", "DE1"),
+            usb_port("/dev/cu.usbmodem1", 0x1cf1, 0x0030, "ConBee II", "DE1"),
+            usb_port("/dev/ttyUSB0", 0x10c4, 0xea60, "CP2102", "X"),
+        ];
+        let adapters = usb_adapters(&ports);
+        assert_eq!(adapters.len(), 1);
+        assert_eq!(adapters[0].path, "/dev/cu.usbmodem1");
+    }
+}
@@ -0,0 +1,575 @@
+//! Firmware flashing (GCF files)
+//!
+//! Dresden Elektronik ships firmware as `.GCF` files: a 14-byte header
+//! followed by the raw image. Flashing follows the `GCFFlasher` protocol for
+//! the `ConBee` II / `RaspBee` II bootloader:
+//!
+//! 1. The image must be for the same platform as the running firmware
+//!    (compared on the version in the GCF file name, as `GCFFlasher` does).
+//!    The running firmware is then reset by setting a short watchdog TTL; the
+//!    bootloader listens for a few seconds after boot. A stick without a
+//!    working application stays in the bootloader.
+//! 2. The host sends an ID request until the bootloader answers.
+//! 3. The host announces the image (size, address, type, CRC-32) and the
+//!    bootloader pulls it in chunks with data requests.
+//! 4. Once the image is verified the bootloader starts the new firmware.
+//!
+//! Bootloader frames use the same SLIP framing and CRC as deCONZ frames but
+//! start with [`BTL_MAGIC`]; the transport reports them as
+//! [`DeconzEvent::BootloaderFrame`] and writes ours via [`Transport::send_raw`].
+//! The transport is reserved ([`Transport::exclusive`]) from the reset until
+//! the image is written, so no other requests reach the bootloader.
+
+use crate::commands::NetworkParameter;
+use crate::transport::{DeconzEvent, ExclusiveClaim, Transport};
+use crate::types::ProtocolError;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::{timeout, Instant};
+
+/// First byte of every bootloader frame
+pub const BTL_MAGIC: u8 = 0x81;
+
+const BTL_ID_REQUEST: u8 = 0x02;
+const BTL_ID_RESPONSE: u8 = 0x82;
+const BTL_FW_UPDATE_REQUEST: u8 = 0x03;
+const BTL_FW_UPDATE_RESPONSE: u8 = 0x83;
+const BTL_FW_DATA_REQUEST: u8 = 0x04;
+const BTL_FW_DATA_RESPONSE: u8 = 0x84;
+
+/// GCF file magic (`0xCAFEFEED`, little-endian)
+const GCF_MAGIC: u32 = 0xCAFE_FEED;
+
+/// GCF header: magic(4) + type(1) + address(4) + size(4) + crc(1)
+const GCF_HEADER_LEN: usize = 14;
+
+/// Bits of a firmware version word naming the platform (0x07 = R21, as in
+/// `ConBee` II and `RaspBee` II)
+const PLATFORM_MASK: u32 = 0x0000_FF00;
+
+/// Watchdog TTL written to reset the running firmware (seconds)
+const RESET_WATCHDOG_TTL: u32 = 2;
+
+/// How long to keep asking for the bootloader after the reset
+const BOOTLOADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval between bootloader ID requests
+const ID_REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Maximum gap between bootloader messages while flashing
+const BOOTLOADER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the new firmware gets to answer after flashing
+const RESTART_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A parsed `.GCF` firmware file
+#[derive(Debug, Clone)]
+pub struct GcfFile {
+    /// Firmware type, passed on to the bootloader which rejects mismatches
+    pub file_type: u8,
+    /// Flash address the image is written to
+    pub target_address: u32,
+    /// Header checksum byte, kept as-is
+    pub header_crc: u8,
+    /// Firmware version word from the file name (see
+    /// [`GcfFile::version_from_file_name`]), needed to flash a running stick
+    pub version: Option<u32>,
+    /// Firmware image
+    pub data: Vec<u8>,
+}
+
+impl GcfFile {
+    /// Parse a GCF file
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < GCF_HEADER_LEN {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "GCF file too short: {} bytes",
+                bytes.len()
+            )));
+        }
+        let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if magic != GCF_MAGIC {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "Not a GCF file (magic {magic:#010x})"
+            )));
+        }
+        let file_type = bytes[4];
+        let target_address = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+        let size = u32::from_le_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]) as usize;
+        let header_crc = bytes[13];
+
+        let data = &bytes[GCF_HEADER_LEN..];
+        if data.len() != size {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "GCF size mismatch: header says {size} bytes, file has {}",
+                data.len()
+            )));
+        }
+        if data.is_empty() {
+            return Err(ProtocolError::InvalidFrame(
+                "GCF image is empty".to_string(),
+            ));
+        }
+        if u32::try_from(size)
+            .ok()
+            .and_then(|size| target_address.checked_add(size))
+            .is_none()
+        {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "GCF image of {size} bytes doesn't fit at {target_address:#010x}"
+            )));
+        }
+
+        Ok(Self {
+            file_type,
+            target_address,
+            header_crc,
+            version: None,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Firmware version in a Dresden file name such as
+    /// `deCONZ_ConBeeII_0x26780700.bin.GCF`
+    #[must_use]
+    pub fn version_from_file_name(name: &str) -> Option<u32> {
+        name.match_indices("0x").find_map(|(i, _)| {
+            let hex = name.get(i + 2..i + 10)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            u32::from_str_radix(hex, 16).ok()
+        })
+    }
+
+    /// Check that the image is for the platform of the running firmware
+    #[allow(clippy::missing_errors_doc)]
+    pub fn check_platform(&self, running_version: u32) -> Result<(), ProtocolError> {
+        let Some(version) = self.version else {
+            return Err(ProtocolError::InvalidFrame(
+                "Firmware version unknown; it is part of the GCF file name (e.g. 0x26780700)"
+                    .to_string(),
+            ));
+        };
+        if version & PLATFORM_MASK != running_version & PLATFORM_MASK {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "Firmware {version:#010x} is for another adapter (running {running_version:#010x})"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Image size in bytes
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// CRC-32 of the image, checked by the bootloader after flashing
+    #[must_use]
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.data)
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Flashing stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashStage {
+    /// Resetting the stick into its bootloader
+    EnteringBootloader,
+    /// Transferring the image
+    Writing,
+    /// Waiting for the new firmware to start
+    Restarting,
+    /// The new firmware is running
+    Done,
+}
+
+/// Progress report emitted while flashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashProgress {
+    pub stage: FlashStage,
+    /// Bytes the bootloader has pulled so far
+    pub bytes_written: usize,
+    pub total_bytes: usize,
+}
+
+/// Bootloader identification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootloaderInfo {
+    pub version: u32,
+    /// CRC of the application currently in flash
+    pub app_crc: u32,
+}
+
+/// Flash a firmware image through the transport
+///
+/// `progress` is called for every stage change and every chunk written. The
+/// network is unusable while this runs: other requests fail with
+/// [`ProtocolError::Reserved`] until the image is written, and the adapter
+/// then restarts with the new firmware.
+#[allow(clippy::missing_errors_doc)]
+pub async fn flash<T, F>(transport: &T, gcf: &GcfFile, mut progress: F) -> Result<(), ProtocolError>
+where
+    T: Transport + ?Sized,
+    F: FnMut(FlashProgress) + Send,
+{
+    let total_bytes = gcf.size();
+    let mut report = |stage, bytes_written| {
+        progress(FlashProgress {
+            stage,
+            bytes_written,
+            total_bytes,
+        });
+    };
+
+    let mut events = transport.subscribe();
+    report(FlashStage::EnteringBootloader, 0);
+    let (info, claim) = enter_bootloader(transport, gcf, &mut events).await?;
+    tracing::info!(
+        "Bootloader v{:#x} ready (app CRC {:#010x}), flashing {} bytes (type {:#04x})",
+        info.version,
+        info.app_crc,
+        total_bytes,
+        gcf.file_type
+    );
+
+    let size = u32::try_from(total_bytes)
+        .map_err(|_| ProtocolError::InvalidFrame("GCF image too large".to_string()))?;
+    let mut request = vec![BTL_MAGIC, BTL_FW_UPDATE_REQUEST];
+    request.extend_from_slice(&size.to_le_bytes());
+    request.extend_from_slice(&gcf.target_address.to_le_bytes());
+    request.push(gcf.file_type);
+    request.extend_from_slice(&gcf.crc32().to_le_bytes());
+    transport.send_raw(request).await?;
+
+    let response = next_bootloader_frame(&mut events, BTL_FW_UPDATE_RESPONSE).await?;
+    match response.get(2) {
+        Some(0) => {}
+        Some(status) => {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "Bootloader rejected firmware (status {status:#04x})"
+            )));
+        }
+        None => {
+            return Err(ProtocolError::InvalidFrame(
+                "Bootloader update response too short".to_string(),
+            ));
+        }
+    }
+
+    report(FlashStage::Writing, 0);
+    let mut written = 0;
+    while written < total_bytes {
+        let request = next_bootloader_frame(&mut events, BTL_FW_DATA_REQUEST).await?;
+        let &[_, _, o0, o1, o2, o3, l0, l1, ..] = request.as_slice() else {
+            return Err(ProtocolError::InvalidFrame(
+                "Bootloader data request too short".to_string(),
+            ));
+        };
+        let offset = u32::from_le_bytes([o0, o1, o2, o3]) as usize;
+        let length = usize::from(u16::from_le_bytes([l0, l1]));
+
+        let chunk = offset
+            .checked_add(length)
+            .and_then(|end| gcf.data.get(offset..end.min(total_bytes)))
+            .ok_or_else(|| {
+                ProtocolError::InvalidFrame(format!(
+                    "Bootloader requested offset {offset} beyond image"
+                ))
+            })?;
+        let mut response = vec![BTL_MAGIC, BTL_FW_DATA_RESPONSE, 0x00];
+        response.extend_from_slice(&[o0, o1, o2, o3]);
+        #[allow(clippy::cast_possible_truncation)] // chunk length <= requested u16 length
+        response.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+        response.extend_from_slice(chunk);
+        transport.send_raw(response).await?;
+
+        written = written.max(offset + chunk.len());
+        report(FlashStage::Writing, written);
+    }
+
+    drop(claim);
+    report(FlashStage::Restarting, total_bytes);
+    wait_for_firmware(transport).await?;
+    report(FlashStage::Done, total_bytes);
+    tracing::info!("Firmware update complete");
+    Ok(())
+}
+
+/// Reset into the bootloader and wait for it to identify itself, reserving
+/// the transport
+async fn enter_bootloader<'t, T: Transport + ?Sized>(
+    transport: &'t T,
+    gcf: &GcfFile,
+    events: &mut broadcast::Receiver<DeconzEvent>,
+) -> Result<(BootloaderInfo, ExclusiveClaim<'t>), ProtocolError> {
+    let claim = || {
+        transport
+            .exclusive()
+            .try_claim()
+            .ok_or(ProtocolError::Reserved)
+    };
+
+    // A stick without a working application is already in the bootloader
+    if let Ok(info) = request_bootloader_id(transport, events).await {
+        return Ok((info, claim()?));
+    }
+
+    gcf.check_platform(transport.read_firmware_version().await?)?;
+
+    // The firmware resets itself once the watchdog expires; the write may not
+    // be acknowledged if it resets first
+    if let Err(e) = transport
+        .write_parameter(
+            NetworkParameter::WatchdogTtl,
+            &RESET_WATCHDOG_TTL.to_le_bytes(),
+        )
+        .await
+    {
+        tracing::debug!(
+            "Watchdog reset write failed ({}), trying bootloader anyway",
+            e
+        );
+    }
+    let claim = claim()?;
+
+    let deadline = Instant::now() + BOOTLOADER_TIMEOUT;
+    while Instant::now() < deadline {
+        if let Ok(info) = request_bootloader_id(transport, events).await {
+            return Ok((info, claim));
+        }
+    }
+    Err(ProtocolError::InvalidFrame(
+        "Bootloader did not respond".to_string(),
+    ))
+}
+
+async fn request_bootloader_id<T: Transport + ?Sized>(
+    transport: &T,
+    events: &mut broadcast::Receiver<DeconzEvent>,
+) -> Result<BootloaderInfo, ProtocolError> {
+    transport.send_raw(vec![BTL_MAGIC, BTL_ID_REQUEST]).await?;
+    let frame = timeout(
+        ID_REQUEST_INTERVAL,
+        next_bootloader_frame(events, BTL_ID_RESPONSE),
+    )
+    .await
+    .map_err(|_| ProtocolError::Timeout)??;
+
+    let &[_, _, v0, v1, v2, v3, c0, c1, c2, c3, ..] = frame.as_slice() else {
+        return Err(ProtocolError::InvalidFrame(
+            "Bootloader ID response too short".to_string(),
+        ));
+    };
+    Ok(BootloaderInfo {
+        version: u32::from_le_bytes([v0, v1, v2, v3]),
+        app_crc: u32::from_le_bytes([c0, c1, c2, c3]),
+    })
+}
+
+/// Wait for the next bootloader frame with the given command
+async fn next_bootloader_frame(
+    events: &mut broadcast::Receiver<DeconzEvent>,
+    command: u8,
+) -> Result<Vec<u8>, ProtocolError> {
+    let wait = async {
+        loop {
+            match events.recv().await {
+                Ok(DeconzEvent::BootloaderFrame(frame)) if frame.get(1) == Some(&command) => {
+                    return Ok(frame);
+                }
+                Ok(DeconzEvent::BootloaderFrame(frame)) => {
+                    tracing::debug!("Ignoring bootloader frame: {:02X?}", frame);
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Missed {} events while flashing", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(ProtocolError::NotConnected);
+                }
+            }
+        }
+    };
+    timeout(BOOTLOADER_RESPONSE_TIMEOUT, wait)
+        .await
+        .map_err(|_| ProtocolError::Timeout)?
+}
+
+/// Wait until the new firmware answers requests again
+async fn wait_for_firmware<T: Transport + ?Sized>(transport: &T) -> Result<(), ProtocolError> {
+    let deadline = Instant::now() + RESTART_TIMEOUT;
+    loop {
+        match transport.get_version().await {
+            Ok(version) => {
+                tracing::info!("Firmware restarted (protocol {:?})", version);
+                return Ok(());
+            }
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => tokio::time::sleep(Duration::from_millis(500)).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::CommandId;
+    use crate::mock::MockTransport;
+    use crate::types::Status;
+    use std::sync::Arc;
+
+    fn gcf_bytes(data: &[u8]) -> Vec<u8> {
+        let mut bytes = GCF_MAGIC.to_le_bytes().to_vec();
+        bytes.push(0x03);
+        bytes.extend_from_slice(&0x5000u32.to_le_bytes());
+        bytes.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+        bytes.push(0xAA);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_gcf() {
+        let gcf = GcfFile::parse(&gcf_bytes(b"123456789")).unwrap();
+        assert_eq!(gcf.file_type, 0x03);
+        assert_eq!(gcf.target_address, 0x5000);
+        assert_eq!(gcf.size(), 9);
+        // Standard CRC-32 check value
+        assert_eq!(gcf.crc32(), 0xCBF4_3926);
+
+        let mut truncated = gcf_bytes(b"123456789");
+        truncated.pop();
+        assert!(GcfFile::parse(&truncated).is_err());
+        assert!(GcfFile::parse(&[0u8; 20]).is_err());
+    }
+
+    #[test]
+    fn test_version_from_file_name() {
+        assert_eq!(
+            GcfFile::version_from_file_name("deCONZ_ConBeeII_0x26780700.bin.GCF"),
+            Some(0x2678_0700)
+        );
+        assert_eq!(GcfFile::version_from_file_name("firmware.GCF"), None);
+        assert_eq!(GcfFile::version_from_file_name("0x12"), None);
+    }
+
+    /// Wait until `count` raw frames were sent
+    async fn raw_frames_sent(mock: &MockTransport, count: usize) {
+        timeout(Duration::from_secs(5), async {
+            while mock.raw_frames().len() < count {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("flash stalled");
+    }
+
+    #[tokio::test]
+    async fn test_flash_serves_data_requests() {
+        let image: Vec<u8> = (0..=255).collect();
+        let gcf = GcfFile::parse(&gcf_bytes(&image)).unwrap();
+        let mock = Arc::new(MockTransport::new());
+        mock.respond_always(
+            CommandId::ReadParameter,
+            Status::Success,
+            vec![0x04, 0x00, 0x22, 0x00, 0x07, 0x26],
+        );
+
+        // Play the bootloader: answer the ID request, accept the update and
+        // pull the image in two chunks
+        let bootloader = Arc::clone(&mock);
+        let bootloader = tokio::spawn(async move {
+            let frame = |bytes: &[u8]| DeconzEvent::BootloaderFrame(bytes.to_vec());
+            raw_frames_sent(&bootloader, 1).await;
+            bootloader.emit(frame(&[BTL_MAGIC, BTL_ID_RESPONSE, 3, 0, 0, 0, 0, 0, 0, 0]));
+            raw_frames_sent(&bootloader, 2).await;
+            // Nothing else gets through while the image is written
+            assert!(matches!(
+                bootloader.get_device_state().await,
+                Err(ProtocolError::Reserved)
+            ));
+            bootloader.emit(frame(&[BTL_MAGIC, BTL_FW_UPDATE_RESPONSE, 0]));
+            for (offset, sent) in [(0u8, 3), (128, 4)] {
+                bootloader.emit(frame(&[
+                    BTL_MAGIC,
+                    BTL_FW_DATA_REQUEST,
+                    offset,
+                    0,
+                    0,
+                    0,
+                    128,
+                    0,
+                ]));
+                raw_frames_sent(&bootloader, sent).await;
+            }
+        });
+
+        let mut stages = Vec::new();
+        timeout(
+            Duration::from_secs(30),
+            flash(mock.as_ref(), &gcf, |p| stages.push(p)),
+        )
+        .await
+        .expect("flash stalled")
+        .unwrap();
+        bootloader.await.unwrap();
+
+        let raw = mock.raw_frames();
+        assert_eq!(raw[1][1], BTL_FW_UPDATE_REQUEST);
+        assert_eq!(&raw[3][9..], &image[128..]);
+        assert_eq!(stages.last().unwrap().stage, FlashStage::Done);
+        assert!(stages
+            .iter()
+            .any(|p| p.stage == FlashStage::Writing && p.bytes_written == 128));
+        assert!(mock.exclusive().check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flash_rejects_other_platform() {
+        let mut gcf = GcfFile::parse(&gcf_bytes(b"image")).unwrap();
+        let mock = MockTransport::new();
+        // RaspBee (AVR) firmware is running; nothing answers as a bootloader
+        mock.respond_always(
+            CommandId::Version,
+            Status::Success,
+            0x2640_0500u32.to_le_bytes().to_vec(),
+        );
+
+        let result = timeout(Duration::from_secs(5), flash(&mock, &gcf, |_| {}))
+            .await
+            .expect("flash stalled");
+        assert!(matches!(result, Err(ProtocolError::InvalidFrame(_))));
+
+        gcf.version = GcfFile::version_from_file_name("deCONZ_ConBeeII_0x26780700.bin.GCF");
+        let result = timeout(Duration::from_secs(5), flash(&mock, &gcf, |_| {}))
+            .await
+            .expect("flash stalled");
+        assert!(matches!(result, Err(ProtocolError::InvalidFrame(_))));
+
+        // The stick was never reset
+        assert!(mock
+            .requests()
+            .iter()
+            .all(|r| r.command_id != CommandId::WriteParameter));
+        assert!(mock.exclusive().check().is_ok());
+    }
+}
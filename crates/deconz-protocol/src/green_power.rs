@@ -0,0 +1,405 @@
+//! Zigbee Green Power frame parsing
+//!
+//! The `ConBee` firmware forwards Green Power Device Frames (GPDF) received on
+//! air as unsolicited `GreenPower` (0x19) frames. The payload is a 2-byte
+//! length followed by the raw GPDF, starting at the NWK frame control:
+//!
+//! ```text
+//! [payload_len: 2 bytes LE]
+//! [NWK frame control: 1 byte]
+//! [Extended NWK frame control: 1 byte] (if bit 7 of frame control is set)
+//! [GPD SrcID: 4 bytes LE]              (application ID 0, data/commissioning frames)
+//! [Security frame counter: 4 bytes LE] (security level >= 2)
+//! [GPD command ID: 1 byte]
+//! [Command payload: variable]
+//! [MIC: 4 bytes]                       (security level >= 2)
+//! ```
+//!
+//! The firmware doesn't check the MIC of GP frames, so [`GreenPowerFrame::verify`]
+//! does it on the host with the key the device sent when it was commissioned.
+//! Level 2 authenticates the whole frame; level 3 also encrypts the command.
+
+use crate::ccm;
+use crate::types::ProtocolError;
+
+/// Green Power command IDs (subset relevant to switches)
+pub mod commands {
+    pub const RECALL_SCENE_0: u8 = 0x10;
+    pub const RECALL_SCENE_7: u8 = 0x17;
+    pub const OFF: u8 = 0x20;
+    pub const ON: u8 = 0x21;
+    pub const TOGGLE: u8 = 0x22;
+    pub const PRESS_1_OF_1: u8 = 0x60;
+    pub const RELEASE_1_OF_1: u8 = 0x61;
+    pub const PRESS_1_OF_2: u8 = 0x62;
+    pub const RELEASE_1_OF_2: u8 = 0x63;
+    pub const PRESS_2_OF_2: u8 = 0x64;
+    pub const RELEASE_2_OF_2: u8 = 0x65;
+    pub const PRESS_8BIT_VECTOR: u8 = 0x69;
+    pub const RELEASE_8BIT_VECTOR: u8 = 0x6A;
+    pub const COMMISSIONING: u8 = 0xE0;
+    pub const DECOMMISSIONING: u8 = 0xE1;
+}
+
+/// GPD device IDs reported in commissioning frames
+pub mod device_ids {
+    pub const ON_OFF_SWITCH: u8 = 0x02;
+    pub const GENERIC_SWITCH: u8 = 0x07;
+}
+
+/// Default Trust Center link key, which encrypts GPD keys in commissioning
+/// frames
+pub const DEFAULT_TC_LINK_KEY: [u8; 16] = *b"ZigBeeAlliance09";
+
+/// Length of the MIC of secured frames
+const MIC_LEN: usize = 4;
+
+/// A parsed Green Power Device Frame
+#[derive(Debug, Clone)]
+pub struct GreenPowerFrame {
+    /// Frame type (0 = data, 1 = maintenance)
+    pub frame_type: u8,
+    /// Auto-commissioning flag
+    pub auto_commissioning: bool,
+    /// Application ID (0 = addressed by 32-bit SrcID)
+    pub application_id: u8,
+    /// Security level (0 = none, 2 = 4-byte counter + MIC, 3 = encrypted)
+    pub security_level: u8,
+    /// GPD source ID
+    pub source_id: u32,
+    /// Security frame counter (only present for secured frames)
+    pub frame_counter: Option<u32>,
+    /// GPD command ID (encrypted at security level 3 until verified)
+    pub command_id: u8,
+    /// Command payload (encrypted at security level 3 until verified)
+    pub payload: Vec<u8>,
+    /// Frame from the NWK frame control to the frame counter, which the MIC
+    /// covers along with the command
+    pub header: Vec<u8>,
+    /// Message integrity code of secured frames
+    pub mic: Option<[u8; MIC_LEN]>,
+}
+
+/// Contents of a commissioning frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commissioning {
+    pub device_id: u8,
+    /// Security level of the device's data frames
+    pub security_level: u8,
+    /// GPD key as sent, encrypted with the default TC link key if
+    /// `key_mic` is set
+    pub key: Option<[u8; 16]>,
+    pub key_mic: Option<[u8; MIC_LEN]>,
+    /// Frame counter the device continues from
+    pub outgoing_counter: Option<u32>,
+}
+
+impl Commissioning {
+    /// The GPD key in the clear; `None` if none was sent or the encrypted
+    /// one doesn't check out
+    #[must_use]
+    pub fn gpd_key(&self, source_id: u32) -> Option<[u8; 16]> {
+        let key = self.key?;
+        let Some(mic) = self.key_mic else {
+            return Some(key);
+        };
+        let id = source_id.to_le_bytes();
+        let mut nonce = [0x05; 13];
+        for chunk in nonce[..12].chunks_exact_mut(4) {
+            chunk.copy_from_slice(&id);
+        }
+        ccm::decrypt(&DEFAULT_TC_LINK_KEY, &nonce, &id, &key, &mic)?
+            .try_into()
+            .ok()
+    }
+}
+
+impl GreenPowerFrame {
+    /// Parse a `GreenPower` frame payload
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < 3 {
+            return Err(ProtocolError::FrameTooShort(data.len()));
+        }
+
+        let payload_len = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let end = (2 + payload_len).min(data.len());
+        let gpdf = &data[2..end];
+        let mut idx = 0;
+
+        let frame_control = *gpdf
+            .first()
+            .ok_or(ProtocolError::FrameTooShort(data.len()))?;
+        idx += 1;
+        let frame_type = frame_control & 0x03;
+        let auto_commissioning = frame_control & 0x40 != 0;
+
+        let (application_id, security_level) = if frame_control & 0x80 != 0 {
+            let ext = *gpdf
+                .get(idx)
+                .ok_or(ProtocolError::FrameTooShort(data.len()))?;
+            idx += 1;
+            (ext & 0x07, (ext >> 3) & 0x03)
+        } else {
+            (0, 0)
+        };
+
+        if application_id != 0 {
+            return Err(ProtocolError::InvalidFrame(format!(
+                "Unsupported GP application ID: {application_id}"
+            )));
+        }
+
+        // Maintenance frames carry no SrcID
+        let source_id = if frame_type == 0 {
+            let id = read_u32(gpdf, idx).ok_or(ProtocolError::FrameTooShort(data.len()))?;
+            idx += 4;
+            id
+        } else {
+            0
+        };
+
+        let frame_counter = if security_level >= 2 {
+            let counter = read_u32(gpdf, idx).ok_or(ProtocolError::FrameTooShort(data.len()))?;
+            idx += 4;
+            Some(counter)
+        } else {
+            None
+        };
+        let header = gpdf[..idx].to_vec();
+
+        let mic_len = if security_level >= 2 { MIC_LEN } else { 0 };
+        if gpdf.len() < idx + 1 + mic_len {
+            return Err(ProtocolError::FrameTooShort(data.len()));
+        }
+        let command_id = gpdf[idx];
+        idx += 1;
+        let payload_end = gpdf.len() - mic_len;
+        let payload = gpdf[idx..payload_end].to_vec();
+        let mic = gpdf[payload_end..].try_into().ok();
+
+        Ok(Self {
+            frame_type,
+            auto_commissioning,
+            application_id,
+            security_level,
+            source_id,
+            frame_counter,
+            command_id,
+            payload,
+            header,
+            mic,
+        })
+    }
+
+    /// Check the MIC with the device's key, returning the frame with its
+    /// command decrypted; `None` for unsecured frames and forgeries
+    #[must_use]
+    pub fn verify(&self, key: &[u8; 16]) -> Option<Self> {
+        let (Some(counter), Some(mic)) = (self.frame_counter, self.mic) else {
+            return None;
+        };
+        let mut nonce = [0x05; 13];
+        nonce[..4].copy_from_slice(&self.source_id.to_le_bytes());
+        nonce[4..8].copy_from_slice(&self.source_id.to_le_bytes());
+        nonce[8..12].copy_from_slice(&counter.to_le_bytes());
+
+        let mut command = vec![self.command_id];
+        command.extend_from_slice(&self.payload);
+        let command = if self.security_level == 3 {
+            ccm::decrypt(key, &nonce, &self.header, &command, &mic)?
+        } else {
+            let mut authenticated = self.header.clone();
+            authenticated.extend_from_slice(&command);
+            ccm::decrypt(key, &nonce, &authenticated, &[], &mic)?;
+            command
+        };
+        let (&command_id, payload) = command.split_first()?;
+        Some(Self {
+            command_id,
+            payload: payload.to_vec(),
+            ..self.clone()
+        })
+    }
+
+    /// Parse the payload of a commissioning frame; `None` for other frames
+    /// and for encrypted commissioning, which isn't supported
+    #[must_use]
+    pub fn commissioning(&self) -> Option<Commissioning> {
+        if !self.is_commissioning() || self.security_level == 3 {
+            return None;
+        }
+        let device_id = *self.payload.first()?;
+        let options = *self.payload.get(1)?;
+        let mut commissioning = Commissioning {
+            device_id,
+            security_level: 0,
+            key: None,
+            key_mic: None,
+            outgoing_counter: None,
+        };
+        // Extended options follow when bit 7 of the options is set
+        if options & 0x80 == 0 {
+            return Some(commissioning);
+        }
+        let ext = *self.payload.get(2)?;
+        let mut idx = 3;
+        commissioning.security_level = ext & 0x03;
+        if ext & 0x20 != 0 {
+            commissioning.key = Some(self.payload.get(idx..idx + 16)?.try_into().ok()?);
+            idx += 16;
+            if ext & 0x40 != 0 {
+                commissioning.key_mic =
+                    Some(self.payload.get(idx..idx + MIC_LEN)?.try_into().ok()?);
+                idx += MIC_LEN;
+            }
+        }
+        if ext & 0x80 != 0 {
+            commissioning.outgoing_counter = Some(read_u32(&self.payload, idx)?);
+        }
+        Some(commissioning)
+    }
+
+    /// Is this a commissioning frame?
+    #[must_use]
+    pub fn is_commissioning(&self) -> bool {
+        self.command_id == commands::COMMISSIONING
+    }
+
+    /// GPD device ID from a commissioning frame
+    #[must_use]
+    pub fn commissioning_device_id(&self) -> Option<u8> {
+        if self.is_commissioning() {
+            self.payload.first().copied()
+        } else {
+            None
+        }
+    }
+}
+
+fn read_u32(data: &[u8], idx: usize) -> Option<u32> {
+    let bytes = data.get(idx..idx + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unsecured_toggle() {
+        // Hue Tap button 1: frame control 0x0C (data, proto v3), SrcID 0x01020304, toggle
+        let data = [0x06, 0x00, 0x0C, 0x04, 0x03, 0x02, 0x01, 0x22];
+        let frame = GreenPowerFrame::parse(&data).unwrap();
+        assert_eq!(frame.source_id, 0x0102_0304);
+        assert_eq!(frame.command_id, commands::TOGGLE);
+        assert_eq!(frame.frame_counter, None);
+        assert!(frame.payload.is_empty());
+    }
+
+    #[test]
+    fn test_parse_secured_press() {
+        // Extended frame control: app ID 0, security level 2
+        let data = [
+            0x10, 0x00, 0x8C, 0x10, 0x78, 0x56, 0x34, 0x12, 0x2A, 0x00, 0x00, 0x00, 0x69, 0x01,
+            0xAA, 0xBB, 0xCC, 0xDD,
+        ];
+        let frame = GreenPowerFrame::parse(&data).unwrap();
+        assert_eq!(frame.source_id, 0x1234_5678);
+        assert_eq!(frame.security_level, 2);
+        assert_eq!(frame.frame_counter, Some(42));
+        assert_eq!(frame.command_id, commands::PRESS_8BIT_VECTOR);
+        assert_eq!(frame.payload, vec![0x01]);
+        assert_eq!(frame.mic, Some([0xAA, 0xBB, 0xCC, 0xDD]));
+    }
+
+    /// Key 00..0F, SrcID 0x01020304, frame counter 9
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F,
+    ];
+
+    #[test]
+    fn test_verify_authenticated_frame() {
+        let data = [
+            0x10, 0x00, 0x8C, 0x10, 0x04, 0x03, 0x02, 0x01, 0x09, 0x00, 0x00, 0x00, 0x69, 0x01,
+            0xC2, 0x84, 0x59, 0xCE,
+        ];
+        let frame = GreenPowerFrame::parse(&data).unwrap();
+        let verified = frame.verify(&KEY).unwrap();
+        assert_eq!(verified.command_id, commands::PRESS_8BIT_VECTOR);
+        assert_eq!(verified.payload, vec![0x01]);
+
+        // Same frame with another button pressed
+        let mut forged = data;
+        forged[13] = 0x02;
+        let frame = GreenPowerFrame::parse(&forged).unwrap();
+        assert!(frame.verify(&KEY).is_none());
+    }
+
+    #[test]
+    fn test_verify_encrypted_frame() {
+        let data = [
+            0x0F, 0x00, 0x8C, 0x18, 0x04, 0x03, 0x02, 0x01, 0x09, 0x00, 0x00, 0x00, 0xAD, 0x0A,
+            0xEA, 0xEB, 0x83,
+        ];
+        let frame = GreenPowerFrame::parse(&data).unwrap();
+        assert_eq!(frame.security_level, 3);
+        let verified = frame.verify(&KEY).unwrap();
+        assert_eq!(verified.command_id, commands::TOGGLE);
+        assert!(verified.payload.is_empty());
+        assert!(frame.verify(&[0; 16]).is_none());
+    }
+
+    #[test]
+    fn test_parse_secured_frame_without_mic() {
+        let data = [
+            0x0B, 0x00, 0x8C, 0x10, 0x04, 0x03, 0x02, 0x01, 0x09, 0x00, 0x00, 0x00, 0x22,
+        ];
+        assert!(matches!(
+            GreenPowerFrame::parse(&data),
+            Err(ProtocolError::FrameTooShort(13))
+        ));
+    }
+
+    #[test]
+    fn test_commissioning_with_encrypted_key() {
+        // Generic switch, level 3, encrypted key and outgoing counter 9
+        let mut data = vec![
+            0x00, 0x00, 0x0C, 0x04, 0x03, 0x02, 0x01, 0xE0, 0x07, 0x80, 0xE3,
+        ];
+        data.extend_from_slice(&[
+            0xE5, 0xFF, 0x01, 0xCB, 0xE7, 0x58, 0xCC, 0x28, 0x2F, 0x41, 0x8A, 0xEC, 0x97, 0x95,
+            0xE9, 0x42, 0xB5, 0x5C, 0xDD, 0x01,
+        ]);
+        data.extend_from_slice(&9u32.to_le_bytes());
+        data[0] = u8::try_from(data.len() - 2).unwrap();
+
+        let frame = GreenPowerFrame::parse(&data).unwrap();
+        let commissioning = frame.commissioning().unwrap();
+        assert_eq!(commissioning.device_id, device_ids::GENERIC_SWITCH);
+        assert_eq!(commissioning.security_level, 3);
+        assert_eq!(commissioning.outgoing_counter, Some(9));
+        assert_eq!(commissioning.gpd_key(0x0102_0304), Some(KEY));
+        assert_eq!(commissioning.gpd_key(0x0102_0305), None);
+    }
+
+    #[test]
+    fn test_parse_empty_gpdf() {
+        let data = [0x00, 0x00, 0x0C];
+        assert!(matches!(
+            GreenPowerFrame::parse(&data),
+            Err(ProtocolError::FrameTooShort(3))
+        ));
+    }
+
+    #[test]
+    fn test_parse_truncated_gpdf() {
+        // Length says 6 bytes but the SrcID is cut short
+        let data = [0x06, 0x00, 0x0C, 0x04, 0x03];
+        assert!(matches!(
+            GreenPowerFrame::parse(&data),
+            Err(ProtocolError::FrameTooShort(5))
+        ));
+    }
+}
@@ -3,14 +3,30 @@
 //! This crate implements the serial protocol used to communicate with
 //! Dresden Elektronik `ConBee` II Zigbee coordinators.
 
+pub mod capture;
+pub mod ccm;
 pub mod commands;
+pub mod confirm;
+pub mod discovery;
+pub mod firmware;
 pub mod frame;
+pub mod green_power;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod slip;
 pub mod transport;
 pub mod types;
 
-pub use commands::{CommandId, NetworkParameter};
+pub use capture::{CaptureStatus, FrameCapture};
+pub use commands::{CommandId, NetworkParameter, NetworkStateCommand};
+pub use confirm::{ApsDataConfirm, ConfirmDestination, ConfirmStatus};
+pub use discovery::{Adapter, AdapterModel};
+pub use firmware::{FlashProgress, FlashStage, GcfFile};
 pub use frame::Frame;
+pub use green_power::GreenPowerFrame;
 pub use slip::{SlipDecoder, SlipEncoder};
-pub use transport::{DeconzEvent, DeconzTransport};
+pub use transport::{
+    ApsCounters, ApsRetryPolicy, DeconzEvent, DeconzTransport, ExclusiveAccess, ExclusiveClaim,
+    SerialBackend, Transport,
+};
 pub use types::*;
@@ -0,0 +1,253 @@
+//! In-memory transport for tests
+//!
+//! [`MockTransport`] answers requests from canned responses queued per
+//! command, records every request, APS request, raw and inter-PAN frame it is given, and lets
+//! tests inject unsolicited device events. [`indication`] builds the frames
+//! devices send.
+
+use crate::capture::FrameCapture;
+use crate::commands::CommandId;
+use crate::frame::Frame;
+use crate::transport::{DeconzEvent, ExclusiveAccess, Transport};
+use crate::types::{
+    AddressMode, ApsDataIndication, ApsDataRequest, DeviceState, InterPanRequest, ProtocolError,
+    Status,
+};
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Home Automation frame from endpoint 1 of a device to the coordinator
+#[must_use]
+pub fn indication(src_short_addr: u16, cluster_id: u16, asdu: Vec<u8>) -> ApsDataIndication {
+    ApsDataIndication {
+        device_state: DeviceState::from_byte(0x02),
+        dest_addr_mode: AddressMode::Nwk,
+        dest_addr: 0x0000,
+        dest_endpoint: 1,
+        src_addr_mode: AddressMode::Nwk,
+        src_short_addr,
+        src_ieee_addr: None,
+        src_endpoint: 1,
+        profile_id: crate::profiles::HOME_AUTOMATION,
+        cluster_id,
+        asdu,
+        lqi: 255,
+        rssi: -40,
+    }
+}
+
+/// A scripted response
+type Response = Result<(Status, Vec<u8>), ProtocolError>;
+
+/// Scriptable transport that never touches a serial port
+pub struct MockTransport {
+    responses: Mutex<HashMap<CommandId, VecDeque<Response>>>,
+    /// Responses used once the queue for a command is empty
+    defaults: Mutex<HashMap<CommandId, (Status, Vec<u8>)>>,
+    requests: Mutex<Vec<Frame>>,
+    aps_requests: Mutex<Vec<ApsDataRequest>>,
+    aps_results: Mutex<VecDeque<Result<(), ProtocolError>>>,
+    raw_frames: Mutex<Vec<Vec<u8>>>,
+    inter_pan: Mutex<Vec<InterPanRequest>>,
+    sequence: AtomicU8,
+    event_tx: broadcast::Sender<DeconzEvent>,
+    capture: FrameCapture,
+    exclusive: ExclusiveAccess,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    #[must_use]
+    pub fn new() -> Self {
+        let (event_tx, _) = broadcast::channel(64);
+        Self {
+            responses: Mutex::new(HashMap::new()),
+            defaults: Mutex::new(HashMap::new()),
+            requests: Mutex::new(Vec::new()),
+            aps_requests: Mutex::new(Vec::new()),
+            aps_results: Mutex::new(VecDeque::new()),
+            raw_frames: Mutex::new(Vec::new()),
+            inter_pan: Mutex::new(Vec::new()),
+            sequence: AtomicU8::new(1),
+            event_tx,
+            capture: FrameCapture::default(),
+            exclusive: ExclusiveAccess::default(),
+        }
+    }
+
+    /// Queue a response to the next request with this command
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn respond(&self, command_id: CommandId, status: Status, payload: Vec<u8>) {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(command_id)
+            .or_default()
+            .push_back(Ok((status, payload)));
+    }
+
+    /// Queue an error for the next request with this command
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn fail(&self, command_id: CommandId, error: ProtocolError) {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(command_id)
+            .or_default()
+            .push_back(Err(error));
+    }
+
+    /// Answer every request with this command that has no queued response
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn respond_always(&self, command_id: CommandId, status: Status, payload: Vec<u8>) {
+        self.defaults
+            .lock()
+            .unwrap()
+            .insert(command_id, (status, payload));
+    }
+
+    /// Queue the result of the next APS request (they succeed by default)
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn aps_result(&self, result: Result<(), ProtocolError>) {
+        self.aps_results.lock().unwrap().push_back(result);
+    }
+
+    /// Broadcast an unsolicited device event to subscribers
+    pub fn emit(&self, event: DeconzEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    /// Requests sent so far, oldest first
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn requests(&self) -> Vec<Frame> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// APS requests sent so far, oldest first
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn aps_requests(&self) -> Vec<ApsDataRequest> {
+        self.aps_requests.lock().unwrap().clone()
+    }
+
+    /// Raw (bootloader) frames sent so far, oldest first
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn raw_frames(&self) -> Vec<Vec<u8>> {
+        self.raw_frames.lock().unwrap().clone()
+    }
+
+    /// Inter-PAN frames sent so far, oldest first
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+    pub fn inter_pan_requests(&self) -> Vec<InterPanRequest> {
+        self.inter_pan.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn request_timeout(
+        &self,
+        command_id: CommandId,
+        payload: Vec<u8>,
+        _timeout: Duration,
+    ) -> Result<Frame, ProtocolError> {
+        self.exclusive.check()?;
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.requests
+            .lock()
+            .unwrap()
+            .push(Frame::new(command_id, sequence, payload));
+
+        let queued = self
+            .responses
+            .lock()
+            .unwrap()
+            .get_mut(&command_id)
+            .and_then(VecDeque::pop_front);
+        let response = match queued {
+            Some(response) => response,
+            None => self
+                .defaults
+                .lock()
+                .unwrap()
+                .get(&command_id)
+                .cloned()
+                .ok_or(ProtocolError::Timeout),
+        };
+
+        let (status, payload) = response?;
+        Ok(Frame {
+            command_id,
+            sequence,
+            status: status as u8,
+            payload,
+        })
+    }
+
+    fn event_sender(&self) -> &broadcast::Sender<DeconzEvent> {
+        &self.event_tx
+    }
+
+    async fn send_aps_request(&self, request: ApsDataRequest) -> Result<(), ProtocolError> {
+        self.exclusive.check()?;
+        self.aps_requests.lock().unwrap().push(request);
+        self.aps_results
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Ok(()))
+    }
+
+    fn capture(&self) -> &FrameCapture {
+        &self.capture
+    }
+
+    fn exclusive(&self) -> &ExclusiveAccess {
+        &self.exclusive
+    }
+
+    async fn send_raw(&self, frame: Vec<u8>) -> Result<(), ProtocolError> {
+        self.raw_frames.lock().unwrap().push(frame);
+        Ok(())
+    }
+
+    async fn send_inter_pan(&self, request: InterPanRequest) -> Result<(), ProtocolError> {
+        self.inter_pan.lock().unwrap().push(request);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceState;
+
+    #[tokio::test]
+    async fn test_scripted_responses() {
+        let mock = MockTransport::new();
+        mock.respond(CommandId::DeviceState, Status::Success, vec![0x22]);
+
+        let state: DeviceState = mock.get_device_state().await.unwrap();
+        assert!(state.aps_request_free_slots);
+        assert!(matches!(
+            mock.get_device_state().await,
+            Err(ProtocolError::Timeout)
+        ));
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].command_id, CommandId::DeviceState);
+    }
+}
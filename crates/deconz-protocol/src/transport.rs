@@ -1,19 +1,25 @@
 //! Async serial transport for deCONZ protocol
 
-use crate::commands::{CommandId, NetworkParameter};
+use crate::capture::{Direction, FrameCapture};
+use crate::commands::{CommandId, NetworkParameter, NetworkStateCommand};
+use crate::confirm::ApsDataConfirm;
+use crate::firmware::BTL_MAGIC;
 use crate::frame::Frame;
+use crate::green_power::GreenPowerFrame;
 use crate::slip::{SlipDecoder, SlipEncoder};
 use crate::types::{
-    ApsDataIndication, ApsDataRequest, DeviceAnnouncement, DeviceState, FirmwareVersion,
-    ProtocolError, Status,
+    AddressMode, ApsDataIndication, ApsDataRequest, DeviceAnnouncement, DeviceState,
+    FirmwareVersion, InterPanIndication, InterPanRequest, MacBeaconIndication, ProtocolError,
+    Status, NO_SHORT_ADDRESS,
 };
 
+use async_trait::async_trait;
 use serial2::SerialPort;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, Notify};
 
 /// Default baud rate for `ConBee` II
 pub const BAUD_RATE: u32 = 115_200;
@@ -21,6 +27,103 @@ pub const BAUD_RATE: u32 = 115_200;
 /// Default request timeout
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// How long a queued APS request waits for a free slot before giving up
+pub const APS_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the device state is polled while waiting for a free APS slot
+const APS_SLOT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Retry behaviour for APS requests rejected as busy or timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApsRetryPolicy {
+    /// Total attempts per request, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for each further retry
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries
+    pub max_backoff: Duration,
+}
+
+impl Default for ApsRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(4),
+        }
+    }
+}
+
+impl ApsRetryPolicy {
+    /// Read the policy from `APS_RETRY_ATTEMPTS` and `APS_RETRY_BACKOFF_MS`,
+    /// falling back to the defaults
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(attempts) = std::env::var("APS_RETRY_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+        {
+            policy.max_attempts = attempts.max(1);
+        }
+        if let Some(ms) = std::env::var("APS_RETRY_BACKOFF_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            policy.initial_backoff = Duration::from_millis(ms);
+        }
+        policy
+    }
+
+    /// Delay before retrying after the given (1-based) failed attempt
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Whether a failed attempt is worth retrying
+    ///
+    /// A request refused as busy never went out. One that timed out may
+    /// have, so it is only sent again if doing so twice is harmless.
+    fn is_retryable(error: &ProtocolError, request: &ApsDataRequest) -> bool {
+        match error {
+            ProtocolError::DeviceError(Status::Busy) => true,
+            ProtocolError::Timeout | ProtocolError::DeviceError(Status::Timeout) => {
+                request.is_idempotent()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Serial I/O backend used by the transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SerialBackend {
+    /// Blocking reader thread on top of `serial2` (works everywhere `serial2` does)
+    #[default]
+    Thread,
+    /// Fully async I/O via `tokio-serial` (requires the `tokio-serial` feature)
+    Async,
+}
+
+impl SerialBackend {
+    /// Select the backend from the `CONBEE_SERIAL_BACKEND` env var (`thread` or `async`)
+    #[must_use]
+    pub fn from_env() -> Self {
+        match std::env::var("CONBEE_SERIAL_BACKEND").as_deref() {
+            Ok("async") => SerialBackend::Async,
+            Ok("thread") | Err(_) => SerialBackend::Thread,
+            Ok(other) => {
+                tracing::warn!("Unknown serial backend '{}', using thread backend", other);
+                SerialBackend::Thread
+            }
+        }
+    }
+}
+
 /// Events from the deCONZ device
 #[derive(Debug, Clone)]
 pub enum DeconzEvent {
@@ -32,6 +135,8 @@ pub enum DeconzEvent {
     ApsDataReceived { data: Vec<u8> },
     /// Parsed APS data indication
     ApsIndication(ApsDataIndication),
+    /// Delivery result of an earlier APS request
+    ApsConfirm(ApsDataConfirm),
     /// Device announced on the network
     DeviceAnnounced {
         ieee_addr: [u8; 8],
@@ -40,6 +145,16 @@ pub enum DeconzEvent {
     },
     /// MAC poll from a device
     MacPoll { short_addr: u16 },
+    /// Green Power device frame
+    GreenPower(GreenPowerFrame),
+    /// Beacon received from a nearby network during a scan
+    Beacon(MacBeaconIndication),
+    /// Frame from the firmware bootloader (CRC stripped)
+    BootloaderFrame(Vec<u8>),
+    /// Inter-PAN frame (Touchlink) from a device outside the network
+    InterPan(InterPanIndication),
+    /// The serial port closed or failed; no more frames will arrive
+    PortClosed,
 }
 
 /// Pending request waiting for response
@@ -58,6 +173,97 @@ struct ReceivedFrame {
     data: Vec<u8>,
 }
 
+/// Running totals of APS send attempts since the transport was opened
+///
+/// Each retry is a separate attempt. A rising failure share means the
+/// adapter can't get frames out, which on a busy channel shows up before
+/// anything else does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApsCounters {
+    pub attempts: u64,
+    pub failures: u64,
+}
+
+/// Reservation of the transport for a firmware update
+///
+/// While it is held, deCONZ requests and APS sends fail with
+/// [`ProtocolError::Reserved`] so nothing but bootloader frames reach the
+/// stick.
+#[derive(Debug, Default)]
+pub struct ExclusiveAccess {
+    held: AtomicBool,
+}
+
+impl ExclusiveAccess {
+    /// Reserve the transport, or `None` if it already is
+    #[must_use]
+    pub fn try_claim(&self) -> Option<ExclusiveClaim<'_>> {
+        (!self.held.swap(true, Ordering::AcqRel)).then_some(ExclusiveClaim(&self.held))
+    }
+
+    /// Fail if the transport is reserved
+    #[allow(clippy::missing_errors_doc)]
+    pub fn check(&self) -> Result<(), ProtocolError> {
+        if self.held.load(Ordering::Acquire) {
+            Err(ProtocolError::Reserved)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Held reservation; released when dropped
+#[must_use]
+pub struct ExclusiveClaim<'a>(&'a AtomicBool);
+
+impl Drop for ExclusiveClaim<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Flow control for outgoing APS requests
+///
+/// The firmware only buffers a few APS requests and reports whether it has
+/// room via `DeviceState.aps_request_free_slots`. Requests queue (FIFO) on
+/// `send_lock` and wait for a free slot before being written.
+struct ApsFlowControl {
+    free_slots: AtomicBool,
+    /// Signalled whenever slots become free
+    slots_freed: Notify,
+    /// Requests waiting in the queue (including the one being sent)
+    queued: AtomicUsize,
+    send_lock: Mutex<()>,
+    /// Send attempts (retries count separately) and how many of them failed
+    attempts: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl ApsFlowControl {
+    fn new() -> Self {
+        Self {
+            free_slots: AtomicBool::new(true),
+            slots_freed: Notify::new(),
+            queued: AtomicUsize::new(0),
+            send_lock: Mutex::new(()),
+            attempts: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    fn update(&self, state: &DeviceState) {
+        self.set_free_slots(state.aps_request_free_slots);
+    }
+
+    fn set_free_slots(&self, free: bool) {
+        let was_free = self.free_slots.swap(free, Ordering::AcqRel);
+        if free && !was_free {
+            tracing::debug!("APS request slots available again");
+            self.slots_freed.notify_waiters();
+        }
+    }
+}
+
 /// Async transport for communicating with deCONZ devices
 pub struct DeconzTransport {
     /// Channel to send data to the writer task
@@ -68,45 +274,80 @@ pub struct DeconzTransport {
     pending: Arc<Mutex<HashMap<u8, PendingRequest>>>,
     /// Event sender for unsolicited messages
     event_tx: broadcast::Sender<DeconzEvent>,
+    /// Outgoing APS request queue
+    aps_flow: Arc<ApsFlowControl>,
+    /// Retry policy for APS requests
+    aps_retry: ApsRetryPolicy,
+    /// Optional TX/RX frame log
+    capture: Arc<FrameCapture>,
+    /// Held while flashing firmware
+    exclusive: ExclusiveAccess,
 }
 
 impl DeconzTransport {
     /// Connect to a deCONZ device at the given serial port path
+    ///
+    /// The serial backend is chosen via [`SerialBackend::from_env`].
     #[allow(clippy::missing_errors_doc)]
     pub fn connect(path: &str) -> Result<Self, ProtocolError> {
-        tracing::info!("Connecting to deCONZ device at {}", path);
-
-        // Open serial port
-        let mut port = SerialPort::open(path, BAUD_RATE).map_err(ProtocolError::SerialError)?;
+        Self::connect_with_backend(path, SerialBackend::from_env())
+    }
 
-        // Set read timeout to make reads non-blocking (short timeout)
-        port.set_read_timeout(Duration::from_millis(100))
-            .map_err(ProtocolError::SerialError)?;
+    /// Connect to a deCONZ device using a specific serial backend
+    #[allow(clippy::missing_errors_doc)]
+    pub fn connect_with_backend(path: &str, backend: SerialBackend) -> Result<Self, ProtocolError> {
+        Self::connect_with_options(path, backend, BAUD_RATE)
+    }
 
-        // Clone port for reader (serial2 supports clone)
-        let reader_port = port.try_clone().map_err(ProtocolError::SerialError)?;
+    /// Connect to a deCONZ device using a specific serial backend and baud rate
+    ///
+    /// The APS retry policy is read via [`ApsRetryPolicy::from_env`].
+    #[allow(clippy::missing_errors_doc)]
+    pub fn connect_with_options(
+        path: &str,
+        backend: SerialBackend,
+        baud_rate: u32,
+    ) -> Result<Self, ProtocolError> {
+        tracing::info!(
+            "Connecting to deCONZ device at {} ({} baud, {:?} backend)",
+            path,
+            baud_rate,
+            backend
+        );
 
         let pending: Arc<Mutex<HashMap<u8, PendingRequest>>> = Arc::new(Mutex::new(HashMap::new()));
         let (event_tx, _) = broadcast::channel(64);
         let (write_tx, write_rx) = mpsc::channel(32);
         let (frame_tx, frame_rx) = mpsc::channel::<ReceivedFrame>(64);
 
-        // Spawn writer task
-        let writer_port = port;
-        tokio::spawn(Self::writer_task(writer_port, write_rx));
-
-        // Spawn reader thread (sends frames via channel)
-        std::thread::spawn(move || {
-            Self::reader_thread(reader_port, frame_tx);
-        });
+        match backend {
+            SerialBackend::Thread => {
+                Self::spawn_thread_backend(path, baud_rate, write_rx, frame_tx)?;
+            }
+            #[cfg(feature = "tokio-serial")]
+            SerialBackend::Async => {
+                Self::spawn_async_backend(path, baud_rate, write_rx, frame_tx)?;
+            }
+            #[cfg(not(feature = "tokio-serial"))]
+            SerialBackend::Async => {
+                tracing::warn!(
+                    "Async serial backend requested but the `tokio-serial` feature is disabled, using thread backend"
+                );
+                Self::spawn_thread_backend(path, baud_rate, write_rx, frame_tx)?;
+            }
+        }
 
-        // Spawn frame handler task (processes frames from reader thread)
+        // Spawn frame handler task (processes frames from the reader)
+        let aps_flow = Arc::new(ApsFlowControl::new());
+        let capture = Arc::new(FrameCapture::default());
         let pending_clone = pending.clone();
         let event_tx_clone = event_tx.clone();
         tokio::spawn(Self::frame_handler_task(
             frame_rx,
             pending_clone,
             event_tx_clone,
+            aps_flow.clone(),
+            capture.clone(),
         ));
 
         tracing::info!("Connected to deCONZ device");
@@ -116,9 +357,136 @@ impl DeconzTransport {
             sequence: AtomicU8::new(1),
             pending,
             event_tx,
+            aps_flow,
+            aps_retry: ApsRetryPolicy::from_env(),
+            capture,
+            exclusive: ExclusiveAccess::default(),
         })
     }
 
+    /// Replace the APS retry policy
+    #[must_use]
+    pub fn with_aps_retry_policy(mut self, policy: ApsRetryPolicy) -> Self {
+        self.aps_retry = policy;
+        self
+    }
+
+    /// Open the port with `serial2` and spawn the writer task and blocking reader thread
+    fn spawn_thread_backend(
+        path: &str,
+        baud_rate: u32,
+        write_rx: mpsc::Receiver<WriteCommand>,
+        frame_tx: mpsc::Sender<ReceivedFrame>,
+    ) -> Result<(), ProtocolError> {
+        // Open serial port
+        let mut port = SerialPort::open(path, baud_rate).map_err(ProtocolError::SerialError)?;
+
+        // Set read timeout to make reads non-blocking (short timeout)
+        port.set_read_timeout(Duration::from_millis(100))
+            .map_err(ProtocolError::SerialError)?;
+
+        // Clone port for reader (serial2 supports clone)
+        let reader_port = port.try_clone().map_err(ProtocolError::SerialError)?;
+
+        // Spawn writer task
+        let writer_port = port;
+        tokio::spawn(Self::writer_task(writer_port, write_rx));
+
+        // Spawn reader thread (sends frames via channel)
+        std::thread::spawn(move || {
+            Self::reader_thread(reader_port, frame_tx);
+        });
+
+        Ok(())
+    }
+
+    /// Open the port with `tokio-serial` and spawn async reader/writer tasks
+    #[cfg(feature = "tokio-serial")]
+    fn spawn_async_backend(
+        path: &str,
+        baud_rate: u32,
+        write_rx: mpsc::Receiver<WriteCommand>,
+        frame_tx: mpsc::Sender<ReceivedFrame>,
+    ) -> Result<(), ProtocolError> {
+        use tokio_serial::SerialPortBuilderExt;
+
+        let stream = tokio_serial::new(path, baud_rate)
+            .open_native_async()
+            .map_err(|e| ProtocolError::SerialError(e.into()))?;
+        let (reader, writer) = tokio::io::split(stream);
+
+        tokio::spawn(Self::async_writer_task(writer, write_rx));
+        tokio::spawn(Self::async_reader_task(reader, frame_tx));
+
+        Ok(())
+    }
+
+    /// Async writer task for the `tokio-serial` backend
+    #[cfg(feature = "tokio-serial")]
+    async fn async_writer_task(
+        mut writer: impl tokio::io::AsyncWrite + Unpin,
+        mut rx: mpsc::Receiver<WriteCommand>,
+    ) {
+        use tokio::io::AsyncWriteExt;
+
+        while let Some(cmd) = rx.recv().await {
+            match cmd {
+                WriteCommand::Send(data) => {
+                    tracing::debug!("Writing {} bytes to serial port", data.len());
+                    if let Err(e) = writer.write_all(&data).await {
+                        tracing::error!("Write error: {}", e);
+                    }
+                    if let Err(e) = writer.flush().await {
+                        tracing::error!("Flush error: {}", e);
+                    }
+                }
+                WriteCommand::Shutdown => break,
+            }
+        }
+        tracing::debug!("Writer task shutting down");
+    }
+
+    /// Async reader task for the `tokio-serial` backend
+    #[cfg(feature = "tokio-serial")]
+    async fn async_reader_task(
+        mut reader: impl tokio::io::AsyncRead + Unpin,
+        frame_tx: mpsc::Sender<ReceivedFrame>,
+    ) {
+        use tokio::io::AsyncReadExt;
+
+        tracing::debug!("Reader task started");
+        let mut buffer = [0u8; 1024];
+        let mut decoder = SlipDecoder::new();
+
+        loop {
+            match reader.read(&mut buffer).await {
+                Ok(0) => {
+                    tracing::warn!("Serial port closed");
+                    break;
+                }
+                Ok(n) => {
+                    tracing::debug!("Read {} bytes: {:02X?}", n, &buffer[..n]);
+                    for frame_data in decoder.feed(&buffer[..n]) {
+                        tracing::debug!("Decoded frame: {:02X?}", &frame_data);
+                        if frame_tx
+                            .send(ReceivedFrame { data: frame_data })
+                            .await
+                            .is_err()
+                        {
+                            tracing::warn!("Frame channel closed");
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Serial read error: {}", e);
+                    break;
+                }
+            }
+        }
+        tracing::debug!("Reader task shutting down");
+    }
+
     /// Writer task - runs in tokio runtime
     async fn writer_task(port: SerialPort, mut rx: mpsc::Receiver<WriteCommand>) {
         while let Some(cmd) = rx.recv().await {
@@ -186,12 +554,18 @@ impl DeconzTransport {
         mut frame_rx: mpsc::Receiver<ReceivedFrame>,
         pending: Arc<Mutex<HashMap<u8, PendingRequest>>>,
         event_tx: broadcast::Sender<DeconzEvent>,
+        aps_flow: Arc<ApsFlowControl>,
+        capture: Arc<FrameCapture>,
     ) {
         while let Some(received) = frame_rx.recv().await {
-            if let Err(e) = Self::handle_frame(&received.data, &pending, &event_tx).await {
+            capture.record(Direction::Rx, &received.data);
+            if let Err(e) = Self::handle_frame(&received.data, &pending, &event_tx, &aps_flow).await
+            {
                 tracing::warn!("Error handling frame: {}", e);
             }
         }
+        // The reader only stops when the port closes or fails
+        let _ = event_tx.send(DeconzEvent::PortClosed);
         tracing::debug!("Frame handler task shutting down");
     }
 
@@ -200,7 +574,12 @@ impl DeconzTransport {
         data: &[u8],
         pending: &Arc<Mutex<HashMap<u8, PendingRequest>>>,
         event_tx: &broadcast::Sender<DeconzEvent>,
+        aps_flow: &ApsFlowControl,
     ) -> Result<(), ProtocolError> {
+        if data.first() == Some(&BTL_MAGIC) {
+            return Self::handle_bootloader_frame(data, event_tx);
+        }
+
         let frame = Frame::deserialize(data)?;
         tracing::debug!(
             "Received frame: cmd={:?} seq={} payload_len={}",
@@ -223,6 +602,7 @@ impl DeconzTransport {
             CommandId::DeviceStateChanged => {
                 if !frame.payload.is_empty() {
                     let state = DeviceState::from_byte(frame.payload[0]);
+                    aps_flow.update(&state);
                     let _ = event_tx.send(DeconzEvent::DeviceStateChanged(state));
 
                     if state.aps_data_indication {
@@ -276,6 +656,34 @@ impl DeconzTransport {
                     let _ = event_tx.send(DeconzEvent::MacPoll { short_addr });
                 }
             }
+            CommandId::MacBeaconIndication => match MacBeaconIndication::parse(&frame.payload) {
+                Ok(beacon) => {
+                    tracing::debug!(
+                        "Beacon: pan={:#06x} channel={} src={:#06x}",
+                        beacon.pan_id,
+                        beacon.channel,
+                        beacon.src_addr
+                    );
+                    let _ = event_tx.send(DeconzEvent::Beacon(beacon));
+                }
+                Err(e) => {
+                    tracing::debug!("Ignoring beacon indication: {}", e);
+                }
+            },
+            CommandId::GreenPower => match GreenPowerFrame::parse(&frame.payload) {
+                Ok(gp) => {
+                    tracing::debug!(
+                        "Green Power frame: src={:#010x} cmd={:#04x} payload={:02X?}",
+                        gp.source_id,
+                        gp.command_id,
+                        gp.payload
+                    );
+                    let _ = event_tx.send(DeconzEvent::GreenPower(gp));
+                }
+                Err(e) => {
+                    tracing::debug!("Ignoring Green Power frame: {}", e);
+                }
+            },
             _ => {
                 tracing::debug!("Unhandled unsolicited frame: {:?}", frame.command_id);
             }
@@ -284,81 +692,249 @@ impl DeconzTransport {
         Ok(())
     }
 
-    /// Send a request and wait for response
-    #[allow(clippy::missing_errors_doc)]
-    pub async fn request(
-        &self,
-        command_id: CommandId,
-        payload: Vec<u8>,
-    ) -> Result<Frame, ProtocolError> {
-        self.request_timeout(command_id, payload, DEFAULT_TIMEOUT)
-            .await
+    /// Forward a bootloader frame (same framing and CRC as deCONZ frames)
+    fn handle_bootloader_frame(
+        data: &[u8],
+        event_tx: &broadcast::Sender<DeconzEvent>,
+    ) -> Result<(), ProtocolError> {
+        let [body @ .., crc_lo, crc_hi] = data else {
+            return Err(ProtocolError::FrameTooShort(data.len()));
+        };
+        let received_crc = u16::from_le_bytes([*crc_lo, *crc_hi]);
+        let calculated_crc = Frame::calculate_crc(body);
+        if received_crc != calculated_crc {
+            return Err(ProtocolError::CrcMismatch {
+                expected: calculated_crc,
+                actual: received_crc,
+            });
+        }
+        tracing::debug!("Bootloader frame: {:02X?}", body);
+        let _ = event_tx.send(DeconzEvent::BootloaderFrame(body.to_vec()));
+        Ok(())
     }
 
-    /// Send a request with custom timeout
-    #[allow(clippy::missing_errors_doc)]
-    pub async fn request_timeout(
+    async fn send_aps_request_with_retry(
         &self,
-        command_id: CommandId,
-        payload: Vec<u8>,
-        timeout: Duration,
-    ) -> Result<Frame, ProtocolError> {
-        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
-        let frame = Frame::new(command_id, sequence, payload);
-        let data = SlipEncoder::encode(&frame.serialize());
+        request: &ApsDataRequest,
+    ) -> Result<(), ProtocolError> {
+        let payload = request.serialize();
+        let deadline = tokio::time::Instant::now() + APS_QUEUE_TIMEOUT;
+        let mut attempt = 1;
 
-        // Set up response channel
-        let (response_tx, response_rx) = oneshot::channel();
-        {
-            let mut pending = self.pending.lock().await;
-            pending.insert(sequence, PendingRequest { response_tx });
+        loop {
+            let result = self
+                .send_aps_request_once(request, &payload, deadline)
+                .await;
+            self.aps_flow.attempts.fetch_add(1, Ordering::Relaxed);
+            if result.is_err() {
+                self.aps_flow.failures.fetch_add(1, Ordering::Relaxed);
+            }
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e)
+                    if ApsRetryPolicy::is_retryable(&e, request)
+                        && attempt < self.aps_retry.max_attempts
+                        && tokio::time::Instant::now() < deadline =>
+                {
+                    let backoff = self.aps_retry.backoff(attempt);
+                    tracing::debug!(
+                        "APS request to {:#06x} failed ({}), retrying in {:?} (attempt {}/{})",
+                        request.dest_short_addr,
+                        e,
+                        backoff,
+                        attempt + 1,
+                        self.aps_retry.max_attempts
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    if attempt > 1 {
+                        tracing::warn!(
+                            "APS request to {:#06x} failed after {} attempts: {}",
+                            request.dest_short_addr,
+                            attempt,
+                            e
+                        );
+                    }
+                    return Err(e);
+                }
+            }
         }
+    }
 
-        // Send the frame
-        tracing::debug!("Sending raw data: {:02X?}", &data);
+    /// Wait for our turn in the queue and a free slot, then send the request once
+    async fn send_aps_request_once(
+        &self,
+        request: &ApsDataRequest,
+        payload: &[u8],
+        deadline: tokio::time::Instant,
+    ) -> Result<(), ProtocolError> {
+        let _turn = self.aps_flow.send_lock.lock().await;
+        self.wait_for_aps_slot(deadline).await?;
 
-        self.write_tx
-            .send(WriteCommand::Send(data))
-            .await
-            .map_err(|_| ProtocolError::NotConnected)?;
+        tracing::debug!(
+            "Sending APS request to {:#06x}:{} cluster={:#06x}",
+            request.dest_short_addr,
+            request.dest_endpoint,
+            request.cluster_id
+        );
+
+        let response = self
+            .request(CommandId::ApsDataRequest, payload.to_vec())
+            .await?;
 
         tracing::debug!(
-            "Sent frame: cmd={:?} seq={} payload_len={}",
-            command_id,
-            sequence,
-            frame.payload.len()
+            "ApsDataRequest response: status={}, payload={:02X?}",
+            response.status,
+            response.payload
         );
 
-        // Wait for response with timeout
-        match tokio::time::timeout(timeout, response_rx).await {
-            Ok(Ok(result)) => result,
-            Ok(Err(_)) => Err(ProtocolError::Timeout),
-            Err(_) => {
-                // Remove pending request on timeout
-                let mut pending = self.pending.lock().await;
-                pending.remove(&sequence);
-                Err(ProtocolError::Timeout)
+        // Response payload: payload_len(2) + device_state(1) + request_id(1)
+        if let Some(&state) = response.payload.get(2) {
+            self.aps_flow.update(&DeviceState::from_byte(state));
+        }
+
+        // Check status
+        let status = Status::try_from(response.status).unwrap_or(Status::Error);
+        match status {
+            Status::Success => Ok(()),
+            Status::Busy => {
+                // The firmware filled up between our check and the send
+                self.aps_flow.set_free_slots(false);
+                Err(ProtocolError::DeviceError(status))
             }
+            _ => Err(ProtocolError::DeviceError(status)),
         }
     }
 
-    /// Subscribe to device events
-    pub fn subscribe(&self) -> broadcast::Receiver<DeconzEvent> {
-        self.event_tx.subscribe()
-    }
+    /// Wait until the firmware reports a free APS request slot
+    async fn wait_for_aps_slot(&self, deadline: tokio::time::Instant) -> Result<(), ProtocolError> {
+        loop {
+            let slots_freed = self.aps_flow.slots_freed.notified();
+            tokio::pin!(slots_freed);
+            slots_freed.as_mut().enable();
 
-    /// Query firmware version
-    #[allow(clippy::missing_errors_doc)]
-    pub async fn get_version(&self) -> Result<FirmwareVersion, ProtocolError> {
-        // Try to get version via ReadParameter(ProtocolVersion) as fallback
-        // since the Version command may not work on all firmware versions
-        let version_data = self
-            .read_parameter(NetworkParameter::ProtocolVersion)
-            .await?;
+            if self.aps_flow.free_slots.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                tracing::warn!("Timed out waiting for a free APS request slot");
+                return Err(ProtocolError::DeviceError(Status::Busy));
+            }
 
-        tracing::debug!("ProtocolVersion data: {:02X?}", version_data);
+            // Poll as a fallback in case a state change notification was missed
+            let poll_at = (tokio::time::Instant::now() + APS_SLOT_POLL_INTERVAL).min(deadline);
+            if tokio::time::timeout_at(poll_at, slots_freed).await.is_err() {
+                let state = self.get_device_state().await?;
+                self.aps_flow.update(&state);
+            }
+        }
+    }
+}
 
-        // ProtocolVersion is 2 bytes, but we need to construct a FirmwareVersion
+/// Connection to a deCONZ adapter
+///
+/// Implemented by [`DeconzTransport`] for real hardware and by
+/// `MockTransport` (with the `mock` feature) for tests. Only framing, events
+/// and APS sending are transport-specific; parameter access and the other
+/// commands are provided on top of [`Transport::request_timeout`].
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Send a request with custom timeout
+    #[allow(clippy::missing_errors_doc)]
+    async fn request_timeout(
+        &self,
+        command_id: CommandId,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Frame, ProtocolError>;
+
+    /// Sender used to broadcast device events
+    fn event_sender(&self) -> &broadcast::Sender<DeconzEvent>;
+
+    /// Send APS data request (send command to a device)
+    #[allow(clippy::missing_errors_doc)]
+    async fn send_aps_request(&self, request: ApsDataRequest) -> Result<(), ProtocolError>;
+
+    /// Number of APS requests queued or in flight
+    #[must_use]
+    fn aps_queue_len(&self) -> usize {
+        0
+    }
+
+    /// Running totals of APS send attempts and failures
+    #[must_use]
+    fn aps_counters(&self) -> ApsCounters {
+        ApsCounters::default()
+    }
+
+    /// Protocol capture (frame log), off until started
+    fn capture(&self) -> &FrameCapture;
+
+    /// Reservation taken while flashing firmware
+    fn exclusive(&self) -> &ExclusiveAccess;
+
+    /// Write a frame that isn't a deCONZ command (bootloader protocol); the
+    /// CRC and SLIP framing are added here
+    #[allow(clippy::missing_errors_doc)]
+    async fn send_raw(&self, _frame: Vec<u8>) -> Result<(), ProtocolError> {
+        Err(ProtocolError::InvalidFrame(
+            "Raw frames are not supported by this transport".to_string(),
+        ))
+    }
+
+    /// Send an inter-PAN frame (Touchlink); responses arrive as
+    /// [`DeconzEvent::InterPan`]
+    ///
+    /// The deCONZ serial protocol has no documented command for this, so
+    /// [`DeconzTransport`] doesn't support it.
+    #[allow(clippy::missing_errors_doc)]
+    async fn send_inter_pan(&self, _request: InterPanRequest) -> Result<(), ProtocolError> {
+        Err(ProtocolError::Unsupported("inter-PAN frames".to_string()))
+    }
+
+    /// Send a request and wait for response
+    #[allow(clippy::missing_errors_doc)]
+    async fn request(
+        &self,
+        command_id: CommandId,
+        payload: Vec<u8>,
+    ) -> Result<Frame, ProtocolError> {
+        self.request_timeout(command_id, payload, DEFAULT_TIMEOUT)
+            .await
+    }
+
+    /// Subscribe to device events
+    fn subscribe(&self) -> broadcast::Receiver<DeconzEvent> {
+        self.event_sender().subscribe()
+    }
+
+    /// Read the running firmware's version word (e.g. `0x26780700`; bits
+    /// 8-15 are the platform)
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_firmware_version(&self) -> Result<u32, ProtocolError> {
+        let response = self.request(CommandId::Version, vec![0; 4]).await?;
+        let &[v0, v1, v2, v3, ..] = response.payload.as_slice() else {
+            return Err(ProtocolError::InvalidFrame(
+                "Version response too short".to_string(),
+            ));
+        };
+        Ok(u32::from_le_bytes([v0, v1, v2, v3]))
+    }
+
+    /// Query firmware version
+    #[allow(clippy::missing_errors_doc)]
+    async fn get_version(&self) -> Result<FirmwareVersion, ProtocolError> {
+        // Try to get version via ReadParameter(ProtocolVersion) as fallback
+        // since the Version command may not work on all firmware versions
+        let version_data = self
+            .read_parameter(NetworkParameter::ProtocolVersion)
+            .await?;
+
+        tracing::debug!("ProtocolVersion data: {:02X?}", version_data);
+
+        // ProtocolVersion is 2 bytes, but we need to construct a FirmwareVersion
         // For now, use the protocol version as a simplified version
         if version_data.len() >= 2 {
             let protocol_version = u16::from_le_bytes([version_data[0], version_data[1]]);
@@ -373,7 +949,7 @@ impl DeconzTransport {
 
     /// Query device state
     #[allow(clippy::missing_errors_doc)]
-    pub async fn get_device_state(&self) -> Result<DeviceState, ProtocolError> {
+    async fn get_device_state(&self) -> Result<DeviceState, ProtocolError> {
         // DeviceState request with reserved byte (0x00) as per protocol spec
         let response = self.request(CommandId::DeviceState, vec![0x00]).await?;
 
@@ -389,20 +965,20 @@ impl DeconzTransport {
         Ok(DeviceState::from_byte(response.payload[0]))
     }
 
-    /// Read a network parameter
+    /// Change the network state (bring the coordinator online or offline)
     #[allow(clippy::missing_errors_doc)]
-    pub async fn read_parameter(&self, param: NetworkParameter) -> Result<Vec<u8>, ProtocolError> {
-        // Request format: payload_len(2 LE) + param_id(1)
-        // payload_len = 1 (just the param_id byte)
-        let mut payload = Vec::new();
-        payload.extend_from_slice(&1u16.to_le_bytes()); // payload_len = 1
-        payload.push(param as u8);
-
-        let response = self.request(CommandId::ReadParameter, payload).await?;
+    async fn change_network_state(
+        &self,
+        command: NetworkStateCommand,
+    ) -> Result<(), ProtocolError> {
+        // Request format: network_state(1) - no payload_len prefix for this command
+        let response = self
+            .request(CommandId::ChangeNetworkState, vec![command as u8])
+            .await?;
 
         tracing::debug!(
-            "ReadParameter({:?}) response: status={}, payload={:02X?}",
-            param,
+            "ChangeNetworkState({:?}) response: status={}, payload={:02X?}",
+            command,
             response.status,
             response.payload
         );
@@ -413,20 +989,252 @@ impl DeconzTransport {
             return Err(ProtocolError::DeviceError(status));
         }
 
-        // Response payload format: payload_len(2) + param_id(1) + value(N)
-        if response.payload.len() < 3 {
-            return Err(ProtocolError::InvalidFrame(
-                "Parameter response too short".to_string(),
-            ));
-        }
+        Ok(())
+    }
 
-        // Skip payload_len(2) + param_id(1) = 3 bytes to get value
-        Ok(response.payload[3..].to_vec())
+    /// Read a network parameter
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_parameter(&self, param: NetworkParameter) -> Result<Vec<u8>, ProtocolError> {
+        read_parameter_with_args(self, param, &[]).await
+    }
+
+    /// Read the coordinator MAC (IEEE) address
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_mac_address(&self) -> Result<[u8; 8], ProtocolError> {
+        read_fixed(self, NetworkParameter::MacAddress).await
+    }
+
+    /// Read the network PAN ID
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_pan_id(&self) -> Result<u16, ProtocolError> {
+        read_fixed(self, NetworkParameter::NwkPanId)
+            .await
+            .map(u16::from_le_bytes)
+    }
+
+    /// Read the coordinator network short address
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_nwk_address(&self) -> Result<u16, ProtocolError> {
+        read_fixed(self, NetworkParameter::NwkAddress)
+            .await
+            .map(u16::from_le_bytes)
+    }
+
+    /// Read the network extended PAN ID (little-endian bytes)
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_extended_pan_id(&self) -> Result<[u8; 8], ProtocolError> {
+        read_fixed(self, NetworkParameter::NwkExtendedPanId).await
+    }
+
+    /// Read the APS extended PAN ID (little-endian bytes, zero means "use any")
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_aps_extended_pan_id(&self) -> Result<[u8; 8], ProtocolError> {
+        read_fixed(self, NetworkParameter::ApsExtendedPanId).await
+    }
+
+    /// Read the trust center IEEE address
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_trust_center_address(&self) -> Result<[u8; 8], ProtocolError> {
+        read_fixed(self, NetworkParameter::TrustCenterAddress).await
+    }
+
+    /// Read the channel mask
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_channel_mask(&self) -> Result<u32, ProtocolError> {
+        read_fixed(self, NetworkParameter::ChannelMask)
+            .await
+            .map(u32::from_le_bytes)
+    }
+
+    /// Read the current operating channel
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_channel(&self) -> Result<u8, ProtocolError> {
+        read_fixed::<1, _>(self, NetworkParameter::CurrentChannel)
+            .await
+            .map(|[v]| v)
+    }
+
+    /// Read the security mode
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_security_mode(&self) -> Result<u8, ProtocolError> {
+        read_fixed::<1, _>(self, NetworkParameter::SecurityMode)
+            .await
+            .map(|[v]| v)
+    }
+
+    /// Read the remaining permit join duration in seconds
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_permit_join(&self) -> Result<u8, ProtocolError> {
+        read_fixed::<1, _>(self, NetworkParameter::PermitJoin)
+            .await
+            .map(|[v]| v)
+    }
+
+    /// Read the network update ID
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_nwk_update_id(&self) -> Result<u8, ProtocolError> {
+        read_fixed::<1, _>(self, NetworkParameter::NwkUpdateId)
+            .await
+            .map(|[v]| v)
+    }
+
+    /// Read the protocol version
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_protocol_version(&self) -> Result<u16, ProtocolError> {
+        read_fixed(self, NetworkParameter::ProtocolVersion)
+            .await
+            .map(u16::from_le_bytes)
+    }
+
+    /// Read whether the device is configured as coordinator
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_designed_coordinator(&self) -> Result<bool, ProtocolError> {
+        read_fixed::<1, _>(self, NetworkParameter::ApsDesignedCoordinator)
+            .await
+            .map(|[v]| v != 0)
+    }
+
+    /// Read whether the configured PAN ID is used as-is (predefined)
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_predefined_pan_id(&self) -> Result<bool, ProtocolError> {
+        read_fixed::<1, _>(self, NetworkParameter::PredefinedNwkPanId)
+            .await
+            .map(|[v]| v != 0)
+    }
+
+    /// Read the outgoing NWK frame counter
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_frame_counter(&self) -> Result<u32, ProtocolError> {
+        read_fixed(self, NetworkParameter::NwkFrameCounter)
+            .await
+            .map(u32::from_le_bytes)
+    }
+
+    /// Read the active network key (key index 0)
+    #[allow(clippy::missing_errors_doc)]
+    async fn read_network_key(&self) -> Result<[u8; 16], ProtocolError> {
+        // Network key reads take a key index; the response echoes it before the key
+        let value = read_parameter_with_args(self, NetworkParameter::NetworkKey, &[0x00]).await?;
+        let key = if value.len() == 17 {
+            &value[1..]
+        } else {
+            &value[..]
+        };
+        fixed_value(NetworkParameter::NetworkKey, key)
+    }
+
+    /// Write the coordinator MAC (IEEE) address
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_mac_address(&self, ieee: [u8; 8]) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::MacAddress, &ieee).await
+    }
+
+    /// Write the coordinator network short address
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_nwk_address(&self, nwk_address: u16) -> Result<(), ProtocolError> {
+        write_checked(
+            self,
+            NetworkParameter::NwkAddress,
+            &nwk_address.to_le_bytes(),
+        )
+        .await
+    }
+
+    /// Write the network PAN ID
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_pan_id(&self, pan_id: u16) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::NwkPanId, &pan_id.to_le_bytes()).await
+    }
+
+    /// Write the network extended PAN ID (little-endian bytes)
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_extended_pan_id(&self, ext_pan_id: [u8; 8]) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::NwkExtendedPanId, &ext_pan_id).await
+    }
+
+    /// Write the APS extended PAN ID (little-endian bytes)
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_aps_extended_pan_id(&self, ext_pan_id: [u8; 8]) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::ApsExtendedPanId, &ext_pan_id).await
+    }
+
+    /// Write the trust center IEEE address
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_trust_center_address(&self, ieee: [u8; 8]) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::TrustCenterAddress, &ieee).await
+    }
+
+    /// Write the channel mask (bit N set = channel N allowed)
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_channel_mask(&self, mask: u32) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::ChannelMask, &mask.to_le_bytes()).await
+    }
+
+    /// Write the security mode
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_security_mode(&self, mode: u8) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::SecurityMode, &[mode]).await
+    }
+
+    /// Open the network for joining for the given number of seconds (0 closes it)
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_permit_join(&self, duration_secs: u8) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::PermitJoin, &[duration_secs]).await
+    }
+
+    /// Write the network update ID
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_nwk_update_id(&self, update_id: u8) -> Result<(), ProtocolError> {
+        write_checked(self, NetworkParameter::NwkUpdateId, &[update_id]).await
+    }
+
+    /// Write whether the device acts as coordinator
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_designed_coordinator(&self, coordinator: bool) -> Result<(), ProtocolError> {
+        write_checked(
+            self,
+            NetworkParameter::ApsDesignedCoordinator,
+            &[u8::from(coordinator)],
+        )
+        .await
+    }
+
+    /// Write whether the configured PAN ID is used as-is (predefined)
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_predefined_pan_id(&self, predefined: bool) -> Result<(), ProtocolError> {
+        write_checked(
+            self,
+            NetworkParameter::PredefinedNwkPanId,
+            &[u8::from(predefined)],
+        )
+        .await
+    }
+
+    /// Write the outgoing NWK frame counter
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_frame_counter(&self, counter: u32) -> Result<(), ProtocolError> {
+        write_checked(
+            self,
+            NetworkParameter::NwkFrameCounter,
+            &counter.to_le_bytes(),
+        )
+        .await
+    }
+
+    /// Write the active network key (key index 0)
+    #[allow(clippy::missing_errors_doc)]
+    async fn write_network_key(&self, key: [u8; 16]) -> Result<(), ProtocolError> {
+        // Network key writes are prefixed with the key index
+        let mut value = Vec::with_capacity(17);
+        value.push(0x00);
+        value.extend_from_slice(&key);
+        self.write_parameter(NetworkParameter::NetworkKey, &value)
+            .await
     }
 
     /// Request APS data indication (fetch waiting APS data)
     #[allow(clippy::missing_errors_doc)]
-    pub async fn request_aps_data(&self) -> Result<Vec<u8>, ProtocolError> {
+    async fn request_aps_data(&self) -> Result<Vec<u8>, ProtocolError> {
         // APS_DATA_INDICATION request format: payload_len(2) + flags(1)
         // flags: 0x04 = request data
         let mut payload = Vec::new();
@@ -468,7 +1276,7 @@ impl DeconzTransport {
                         announce.is_router(),
                         announce.is_mains_powered()
                     );
-                    let _ = self.event_tx.send(DeconzEvent::DeviceAnnounced {
+                    let _ = self.event_sender().send(DeconzEvent::DeviceAnnounced {
                         ieee_addr: announce.ieee_addr,
                         short_addr: announce.short_addr,
                         capability: announce.capability,
@@ -476,44 +1284,46 @@ impl DeconzTransport {
                 }
             }
 
-            let _ = self.event_tx.send(DeconzEvent::ApsIndication(indication));
+            let _ = self
+                .event_sender()
+                .send(DeconzEvent::ApsIndication(indication));
         }
 
         Ok(response.payload)
     }
 
-    /// Send APS data request (send command to a device)
+    /// Fetch the oldest pending APS data confirm
+    ///
+    /// Call this when the device state has `aps_data_confirm` set; the
+    /// confirm is also broadcast as [`DeconzEvent::ApsConfirm`].
     #[allow(clippy::missing_errors_doc)]
-    pub async fn send_aps_request(&self, request: ApsDataRequest) -> Result<(), ProtocolError> {
-        let payload = request.serialize();
-
-        tracing::debug!(
-            "Sending APS request to {:#06x}:{} cluster={:#06x}",
-            request.dest_short_addr,
-            request.dest_endpoint,
-            request.cluster_id
-        );
-
-        let response = self.request(CommandId::ApsDataRequest, payload).await?;
-
-        tracing::debug!(
-            "ApsDataRequest response: status={}, payload={:02X?}",
-            response.status,
-            response.payload
-        );
+    async fn request_aps_confirm(&self) -> Result<ApsDataConfirm, ProtocolError> {
+        // APS_DATA_CONFIRM request format: payload_len(2), no payload
+        let response = self
+            .request(CommandId::ApsDataConfirm, 0u16.to_le_bytes().to_vec())
+            .await?;
 
-        // Check status
         let status = Status::try_from(response.status).unwrap_or(Status::Error);
         if status != Status::Success {
             return Err(ProtocolError::DeviceError(status));
         }
 
-        Ok(())
+        let confirm = ApsDataConfirm::parse(&response.payload)?;
+        tracing::debug!(
+            "APS Confirm: request={} dest={:?} status={}",
+            confirm.request_id,
+            confirm.destination,
+            confirm.status
+        );
+        let _ = self
+            .event_sender()
+            .send(DeconzEvent::ApsConfirm(confirm.clone()));
+        Ok(confirm)
     }
 
     #[allow(clippy::missing_panics_doc)] // Panic only on protocol-violating value size
     #[allow(clippy::missing_errors_doc)]
-    pub async fn write_parameter(
+    async fn write_parameter(
         &self,
         param: NetworkParameter,
         value: &[u8],
@@ -548,9 +1358,270 @@ impl DeconzTransport {
     }
 }
 
+/// Read a network parameter that takes extra request arguments (e.g. a key index)
+#[allow(clippy::missing_panics_doc)] // Panic only on protocol-violating argument size
+async fn read_parameter_with_args<T: Transport + ?Sized>(
+    transport: &T,
+    param: NetworkParameter,
+    args: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    // Request format: payload_len(2 LE) + param_id(1) + args(N)
+    let payload_len =
+        u16::try_from(1 + args.len()).expect("parameter arguments exceed protocol maximum");
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&payload_len.to_le_bytes());
+    payload.push(param as u8);
+    payload.extend_from_slice(args);
+
+    let response = transport.request(CommandId::ReadParameter, payload).await?;
+
+    tracing::debug!(
+        "ReadParameter({:?}) response: status={}, payload={:02X?}",
+        param,
+        response.status,
+        response.payload
+    );
+
+    // Check status from frame header
+    let status = Status::try_from(response.status).unwrap_or(Status::Error);
+    if status != Status::Success {
+        return Err(ProtocolError::DeviceError(status));
+    }
+
+    // Response payload format: payload_len(2) + param_id(1) + value(N)
+    if response.payload.len() < 3 {
+        return Err(ProtocolError::InvalidFrame(
+            "Parameter response too short".to_string(),
+        ));
+    }
+
+    // Skip payload_len(2) + param_id(1) = 3 bytes to get value
+    Ok(response.payload[3..].to_vec())
+}
+
+/// Read a fixed-size parameter, validating its length
+async fn read_fixed<const N: usize, T: Transport + ?Sized>(
+    transport: &T,
+    param: NetworkParameter,
+) -> Result<[u8; N], ProtocolError> {
+    let value = transport.read_parameter(param).await?;
+    fixed_value(param, &value)
+}
+
+/// Write a parameter after validating its length against the protocol definition
+async fn write_checked<T: Transport + ?Sized>(
+    transport: &T,
+    param: NetworkParameter,
+    value: &[u8],
+) -> Result<(), ProtocolError> {
+    if value.len() != param.value_length() {
+        return Err(ProtocolError::InvalidFrame(format!(
+            "{param:?} expects {} bytes, got {}",
+            param.value_length(),
+            value.len()
+        )));
+    }
+    transport.write_parameter(param, value).await
+}
+
+#[async_trait]
+impl Transport for DeconzTransport {
+    /// Send a request with custom timeout
+    #[allow(clippy::missing_errors_doc)]
+    async fn request_timeout(
+        &self,
+        command_id: CommandId,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Frame, ProtocolError> {
+        self.exclusive.check()?;
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        let frame = Frame::new(command_id, sequence, payload);
+        let raw = frame.serialize();
+        self.capture.record(Direction::Tx, &raw);
+        let data = SlipEncoder::encode(&raw);
+
+        // Set up response channel
+        let (response_tx, response_rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().await;
+            pending.insert(sequence, PendingRequest { response_tx });
+        }
+
+        // Send the frame
+        tracing::debug!("Sending raw data: {:02X?}", &data);
+
+        self.write_tx
+            .send(WriteCommand::Send(data))
+            .await
+            .map_err(|_| ProtocolError::NotConnected)?;
+
+        tracing::debug!(
+            "Sent frame: cmd={:?} seq={} payload_len={}",
+            command_id,
+            sequence,
+            frame.payload.len()
+        );
+
+        // Wait for response with timeout
+        match tokio::time::timeout(timeout, response_rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(ProtocolError::Timeout),
+            Err(_) => {
+                // Remove pending request on timeout
+                let mut pending = self.pending.lock().await;
+                pending.remove(&sequence);
+                Err(ProtocolError::Timeout)
+            }
+        }
+    }
+
+    fn event_sender(&self) -> &broadcast::Sender<DeconzEvent> {
+        &self.event_tx
+    }
+
+    /// Send APS data request (send command to a device)
+    ///
+    /// Requests are queued in order while the firmware has no free APS slots
+    /// and sent as soon as slots free up (up to [`APS_QUEUE_TIMEOUT`]).
+    /// Requests rejected as busy or that time out are requeued with
+    /// exponential backoff according to the [`ApsRetryPolicy`].
+    #[allow(clippy::missing_errors_doc)]
+    async fn send_aps_request(&self, request: ApsDataRequest) -> Result<(), ProtocolError> {
+        self.exclusive.check()?;
+        if request.dest_addr_mode == AddressMode::Nwk && request.dest_short_addr == NO_SHORT_ADDRESS
+        {
+            return Err(ProtocolError::InvalidFrame(
+                "Device has no short address".to_string(),
+            ));
+        }
+        self.aps_flow.queued.fetch_add(1, Ordering::Relaxed);
+        let result = self.send_aps_request_with_retry(&request).await;
+        self.aps_flow.queued.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    fn aps_queue_len(&self) -> usize {
+        self.aps_flow.queued.load(Ordering::Relaxed)
+    }
+
+    fn aps_counters(&self) -> ApsCounters {
+        ApsCounters {
+            attempts: self.aps_flow.attempts.load(Ordering::Relaxed),
+            failures: self.aps_flow.failures.load(Ordering::Relaxed),
+        }
+    }
+
+    fn capture(&self) -> &FrameCapture {
+        &self.capture
+    }
+
+    fn exclusive(&self) -> &ExclusiveAccess {
+        &self.exclusive
+    }
+
+    async fn send_raw(&self, mut frame: Vec<u8>) -> Result<(), ProtocolError> {
+        let crc = Frame::calculate_crc(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        self.capture.record(Direction::Tx, &frame);
+        self.write_tx
+            .send(WriteCommand::Send(SlipEncoder::encode(&frame)))
+            .await
+            .map_err(|_| ProtocolError::NotConnected)
+    }
+}
+
+/// Convert a parameter value into a fixed-size array, validating its length
+fn fixed_value<const N: usize>(
+    param: NetworkParameter,
+    value: &[u8],
+) -> Result<[u8; N], ProtocolError> {
+    value
+        .get(..N)
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| {
+            ProtocolError::InvalidFrame(format!(
+                "{param:?} value too short: expected {N} bytes, got {}",
+                value.len()
+            ))
+        })
+}
+
 impl Drop for DeconzTransport {
     fn drop(&mut self) {
         // Signal shutdown (best effort)
         let _ = self.write_tx.try_send(WriteCommand::Shutdown);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_value_decodes_little_endian() {
+        let value: [u8; 2] = fixed_value(NetworkParameter::NwkPanId, &[0x34, 0x12]).unwrap();
+        assert_eq!(u16::from_le_bytes(value), 0x1234);
+    }
+
+    #[tokio::test]
+    async fn test_aps_flow_control_wakes_waiters() {
+        let flow = Arc::new(ApsFlowControl::new());
+        flow.set_free_slots(false);
+
+        let waiter = {
+            let flow = flow.clone();
+            tokio::spawn(async move {
+                let notified = flow.slots_freed.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if !flow.free_slots.load(Ordering::Acquire) {
+                    notified.await;
+                }
+            })
+        };
+
+        tokio::task::yield_now().await;
+        flow.update(&DeviceState::from_byte(0x22));
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("waiter should be woken")
+            .unwrap();
+        assert!(flow.free_slots.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_aps_retry_backoff() {
+        let policy = ApsRetryPolicy {
+            max_attempts: 6,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+        let on = ApsDataRequest::new(1, 0x1234, 1, 0x0006, vec![0x01, 1, 0x01]);
+        let toggle = ApsDataRequest::new(1, 0x1234, 1, 0x0006, vec![0x01, 1, 0x02]);
+        assert!(ApsRetryPolicy::is_retryable(&ProtocolError::Timeout, &on));
+        assert!(!ApsRetryPolicy::is_retryable(
+            &ProtocolError::Timeout,
+            &toggle
+        ));
+        assert!(ApsRetryPolicy::is_retryable(
+            &ProtocolError::DeviceError(Status::Busy),
+            &toggle
+        ));
+        assert!(!ApsRetryPolicy::is_retryable(
+            &ProtocolError::DeviceError(Status::Error),
+            &on
+        ));
+    }
+
+    #[test]
+    fn test_fixed_value_too_short() {
+        let result: Result<[u8; 4], _> = fixed_value(NetworkParameter::ChannelMask, &[0x00, 0x08]);
+        assert!(matches!(result, Err(ProtocolError::InvalidFrame(_))));
+    }
+}
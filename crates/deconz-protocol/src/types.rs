@@ -28,6 +28,18 @@ pub enum ProtocolError {
 
     #[error("Device returned error status: {0:?}")]
     DeviceError(Status),
+
+    #[error("No deCONZ adapter found")]
+    AdapterNotFound,
+
+    #[error("Not supported by this transport: {0}")]
+    Unsupported(String),
+
+    #[error("Adapter is busy with a firmware update")]
+    Reserved,
+
+    #[error("Multiple deCONZ adapters found ({}), set the port explicitly", .0.join(", "))]
+    MultipleAdapters(Vec<String>),
 }
 
 /// Device status codes from deCONZ
@@ -189,6 +201,8 @@ impl TryFrom<u8> for AddressMode {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum ZdoCluster {
+    NwkAddrReq = 0x0000,
+    NwkAddrRsp = 0x8000,
     DeviceAnnce = 0x0013,
     NodeDescReq = 0x0002,
     NodeDescRsp = 0x8002,
@@ -196,6 +210,12 @@ pub enum ZdoCluster {
     SimpleDescRsp = 0x8004,
     ActiveEpReq = 0x0005,
     ActiveEpRsp = 0x8005,
+    MgmtLqiReq = 0x0031,
+    MgmtLqiRsp = 0x8031,
+    MgmtRtgReq = 0x0032,
+    MgmtRtgRsp = 0x8032,
+    MgmtPermitJoinReq = 0x0036,
+    MgmtPermitJoinRsp = 0x8036,
 }
 
 /// APS Data Indication - parsed incoming `ZigBee` message
@@ -343,6 +363,241 @@ impl ApsDataIndication {
     }
 }
 
+/// Neighbor relationship from a Mgmt_Lqi neighbor table entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborRelationship {
+    Parent,
+    Child,
+    Sibling,
+    None,
+    PreviousChild,
+    Unknown(u8),
+}
+
+impl From<u8> for NeighborRelationship {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => NeighborRelationship::Parent,
+            1 => NeighborRelationship::Child,
+            2 => NeighborRelationship::Sibling,
+            3 => NeighborRelationship::None,
+            4 => NeighborRelationship::PreviousChild,
+            v => NeighborRelationship::Unknown(v),
+        }
+    }
+}
+
+/// A single neighbor table entry from a Mgmt_Lqi response
+#[derive(Debug, Clone)]
+pub struct NeighborEntry {
+    pub extended_pan_id: [u8; 8],
+    pub ieee_addr: [u8; 8],
+    pub nwk_addr: u16,
+    /// 0 = coordinator, 1 = router, 2 = end device, 3 = unknown
+    pub device_type: u8,
+    /// 0 = off, 1 = on, 2 = unknown
+    pub rx_on_when_idle: u8,
+    pub relationship: NeighborRelationship,
+    /// 0 = not accepting, 1 = accepting, 2 = unknown
+    pub permit_joining: u8,
+    pub depth: u8,
+    pub lqi: u8,
+}
+
+impl NeighborEntry {
+    /// Size of one entry on the wire
+    pub const SIZE: usize = 22;
+
+    fn parse(data: &[u8]) -> Self {
+        let mut extended_pan_id = [0u8; 8];
+        extended_pan_id.copy_from_slice(&data[0..8]);
+        let mut ieee_addr = [0u8; 8];
+        ieee_addr.copy_from_slice(&data[8..16]);
+        Self {
+            extended_pan_id,
+            ieee_addr,
+            nwk_addr: u16::from_le_bytes([data[16], data[17]]),
+            device_type: data[18] & 0x03,
+            rx_on_when_idle: (data[18] >> 2) & 0x03,
+            relationship: NeighborRelationship::from((data[18] >> 4) & 0x07),
+            permit_joining: data[19] & 0x03,
+            depth: data[20],
+            lqi: data[21],
+        }
+    }
+}
+
+/// Mgmt_Lqi Response from ZDO cluster 0x8031
+#[derive(Debug, Clone)]
+pub struct MgmtLqiResponse {
+    pub tsn: u8,
+    pub status: u8,
+    /// Total number of entries in the remote neighbor table
+    pub total_entries: u8,
+    pub start_index: u8,
+    pub neighbors: Vec<NeighborEntry>,
+}
+
+impl MgmtLqiResponse {
+    /// Parse from ASDU
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(asdu: &[u8]) -> Result<Self, ProtocolError> {
+        if asdu.len() < 2 {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+
+        let tsn = asdu[0];
+        let status = asdu[1];
+        if status != 0 {
+            return Ok(Self {
+                tsn,
+                status,
+                total_entries: 0,
+                start_index: 0,
+                neighbors: Vec::new(),
+            });
+        }
+
+        if asdu.len() < 5 {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+        let total_entries = asdu[2];
+        let start_index = asdu[3];
+        let count = asdu[4] as usize;
+
+        let entries = &asdu[5..];
+        if entries.len() < count * NeighborEntry::SIZE {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+        let neighbors = entries
+            .chunks_exact(NeighborEntry::SIZE)
+            .take(count)
+            .map(NeighborEntry::parse)
+            .collect();
+
+        Ok(Self {
+            tsn,
+            status,
+            total_entries,
+            start_index,
+            neighbors,
+        })
+    }
+}
+
+/// Route status from a Mgmt_Rtg routing table entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteStatus {
+    Active,
+    DiscoveryUnderway,
+    DiscoveryFailed,
+    Inactive,
+    ValidationUnderway,
+    Unknown(u8),
+}
+
+impl From<u8> for RouteStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => RouteStatus::Active,
+            1 => RouteStatus::DiscoveryUnderway,
+            2 => RouteStatus::DiscoveryFailed,
+            3 => RouteStatus::Inactive,
+            4 => RouteStatus::ValidationUnderway,
+            v => RouteStatus::Unknown(v),
+        }
+    }
+}
+
+/// A single routing table entry from a Mgmt_Rtg response
+#[derive(Debug, Clone)]
+pub struct RoutingTableEntry {
+    pub destination: u16,
+    pub status: RouteStatus,
+    /// Next hop is a memory-constrained concentrator
+    pub memory_constrained: bool,
+    /// Destination is a many-to-one concentrator
+    pub many_to_one: bool,
+    /// A route record must be sent before the next data packet
+    pub route_record_required: bool,
+    pub next_hop: u16,
+}
+
+impl RoutingTableEntry {
+    /// Size of one entry on the wire
+    pub const SIZE: usize = 5;
+
+    fn parse(data: &[u8]) -> Self {
+        let flags = data[2];
+        Self {
+            destination: u16::from_le_bytes([data[0], data[1]]),
+            status: RouteStatus::from(flags & 0x07),
+            memory_constrained: flags & 0x08 != 0,
+            many_to_one: flags & 0x10 != 0,
+            route_record_required: flags & 0x20 != 0,
+            next_hop: u16::from_le_bytes([data[3], data[4]]),
+        }
+    }
+}
+
+/// Mgmt_Rtg Response from ZDO cluster 0x8032
+#[derive(Debug, Clone)]
+pub struct MgmtRtgResponse {
+    pub tsn: u8,
+    pub status: u8,
+    /// Total number of entries in the remote routing table
+    pub total_entries: u8,
+    pub start_index: u8,
+    pub routes: Vec<RoutingTableEntry>,
+}
+
+impl MgmtRtgResponse {
+    /// Parse from ASDU
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(asdu: &[u8]) -> Result<Self, ProtocolError> {
+        if asdu.len() < 2 {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+
+        let tsn = asdu[0];
+        let status = asdu[1];
+        if status != 0 {
+            return Ok(Self {
+                tsn,
+                status,
+                total_entries: 0,
+                start_index: 0,
+                routes: Vec::new(),
+            });
+        }
+
+        if asdu.len() < 5 {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+        let total_entries = asdu[2];
+        let start_index = asdu[3];
+        let count = asdu[4] as usize;
+
+        let entries = &asdu[5..];
+        if entries.len() < count * RoutingTableEntry::SIZE {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+        let routes = entries
+            .chunks_exact(RoutingTableEntry::SIZE)
+            .take(count)
+            .map(RoutingTableEntry::parse)
+            .collect();
+
+        Ok(Self {
+            tsn,
+            status,
+            total_entries,
+            start_index,
+            routes,
+        })
+    }
+}
+
 /// Device Announcement from ZDO cluster 0x0013
 #[derive(Debug, Clone)]
 pub struct DeviceAnnouncement {
@@ -393,6 +648,140 @@ impl DeviceAnnouncement {
     }
 }
 
+/// NWK Address Response from ZDO cluster 0x8000
+#[derive(Debug, Clone)]
+pub struct NwkAddressResponse {
+    pub tsn: u8,
+    pub status: u8,
+    pub ieee_addr: [u8; 8],
+    pub nwk_addr: u16,
+}
+
+impl NwkAddressResponse {
+    /// Parse from ASDU (associated device lists are ignored)
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(asdu: &[u8]) -> Result<Self, ProtocolError> {
+        if asdu.len() < 12 {
+            return Err(ProtocolError::FrameTooShort(asdu.len()));
+        }
+
+        let tsn = asdu[0];
+        let status = asdu[1];
+        let mut ieee_addr = [0u8; 8];
+        ieee_addr.copy_from_slice(&asdu[2..10]);
+        let nwk_addr = u16::from_le_bytes([asdu[10], asdu[11]]);
+
+        Ok(Self {
+            tsn,
+            status,
+            ieee_addr,
+            nwk_addr,
+        })
+    }
+}
+
+/// MAC beacon indication (unsolicited frame 0x1F)
+///
+/// Payload format:
+/// ```text
+/// [payload_len: 2] [src_addr: 2] [pan_id: 2] [channel: 1] [flags: 1] [update_id: 1]
+/// [beacon_len: 2]
+/// [beacon payload: 15] (protocol ID, stack profile, capacity, ext PAN ID, tx offset, update ID)
+/// [lqi: 1] [rssi: 1] (optional)
+/// ```
+#[derive(Debug, Clone)]
+pub struct MacBeaconIndication {
+    /// Short address of the beaconing router/coordinator
+    pub src_addr: u16,
+    pub pan_id: u16,
+    pub channel: u8,
+    /// Superframe flags (bit 7 = association permit)
+    pub flags: u8,
+    pub update_id: u8,
+    /// Extended PAN ID from the beacon payload (if present)
+    pub extended_pan_id: Option<[u8; 8]>,
+    /// Link quality of the received beacon (if reported by firmware)
+    pub lqi: Option<u8>,
+    /// Signal strength of the received beacon in dBm (if reported by firmware)
+    pub rssi: Option<i8>,
+}
+
+impl MacBeaconIndication {
+    /// Offset of the Zigbee beacon payload in the frame payload
+    const BEACON_PAYLOAD_OFFSET: usize = 11;
+    /// Length of the Zigbee beacon payload
+    const BEACON_PAYLOAD_LEN: usize = 15;
+
+    /// Parse a MAC beacon indication frame payload
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::BEACON_PAYLOAD_OFFSET {
+            return Err(ProtocolError::FrameTooShort(data.len()));
+        }
+
+        let src_addr = u16::from_le_bytes([data[2], data[3]]);
+        let pan_id = u16::from_le_bytes([data[4], data[5]]);
+        let channel = data[6];
+        let flags = data[7];
+        let update_id = data[8];
+
+        // data[9..11] is the length of the beacon payload
+        let beacon = &data[Self::BEACON_PAYLOAD_OFFSET..];
+        let extended_pan_id = beacon.get(3..11).map(|b| {
+            let mut ext = [0u8; 8];
+            ext.copy_from_slice(b);
+            ext
+        });
+        let lqi = beacon.get(Self::BEACON_PAYLOAD_LEN).copied();
+        #[allow(clippy::cast_possible_wrap)] // RSSI is signed dBm encoded as u8
+        let rssi = beacon.get(Self::BEACON_PAYLOAD_LEN + 1).map(|&b| b as i8);
+
+        Ok(Self {
+            src_addr,
+            pan_id,
+            channel,
+            flags,
+            update_id,
+            extended_pan_id,
+            lqi,
+            rssi,
+        })
+    }
+
+    /// Is the network accepting joins?
+    #[must_use]
+    pub fn permit_join(&self) -> bool {
+        (self.flags & 0x80) != 0
+    }
+}
+
+/// Inter-PAN frame (Touchlink), sent on a channel outside any network
+///
+/// Inter-PAN frames have no NWK header: they are addressed by IEEE address
+/// (or broadcast) to PAN `0xFFFF` on whichever channel the target listens.
+#[derive(Debug, Clone)]
+pub struct InterPanRequest {
+    pub channel: u8,
+    /// Destination, or `None` to broadcast
+    pub dest_ieee: Option<[u8; 8]>,
+    pub profile_id: u16,
+    pub cluster_id: u16,
+    pub asdu: Vec<u8>,
+}
+
+/// Inter-PAN frame received from a device outside the network
+#[derive(Debug, Clone)]
+pub struct InterPanIndication {
+    pub channel: u8,
+    pub src_ieee: [u8; 8],
+    pub src_pan_id: u16,
+    pub profile_id: u16,
+    pub cluster_id: u16,
+    pub asdu: Vec<u8>,
+    /// Signal strength in dBm, if reported
+    pub rssi: Option<i8>,
+}
+
 /// Active Endpoints Response from ZDO cluster 0x8005
 #[derive(Debug, Clone)]
 pub struct ActiveEndpointsResponse {
@@ -549,8 +938,13 @@ pub enum OnOffCommand {
 
 /// ZCL cluster IDs
 pub mod clusters {
+    pub const IDENTIFY: u16 = 0x0003;
+    pub const GROUPS: u16 = 0x0004;
+    pub const SCENES: u16 = 0x0005;
     pub const ON_OFF: u16 = 0x0006;
     pub const LEVEL_CONTROL: u16 = 0x0008;
+    pub const WINDOW_COVERING: u16 = 0x0102;
+    pub const THERMOSTAT: u16 = 0x0201;
     pub const COLOR_CONTROL: u16 = 0x0300;
 }
 
@@ -560,12 +954,31 @@ pub mod profiles {
     pub const HOME_AUTOMATION: u16 = 0x0104;
 }
 
+/// NWK broadcast addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Broadcast {
+    /// Every device, sleepy end devices included (through their parents)
+    AllDevices = 0xFFFF,
+    /// Devices with their receiver on when idle
+    RxOnWhenIdle = 0xFFFD,
+    /// Routers and the coordinator
+    Routers = 0xFFFC,
+}
+
+/// Short address standing in for "none" (Green Power devices); requests to
+/// it are refused rather than sent
+pub const NO_SHORT_ADDRESS: u16 = 0xFFFE;
+
 /// APS Data Request for sending commands to devices
 #[derive(Debug, Clone)]
 pub struct ApsDataRequest {
     pub request_id: u8,
     pub dest_addr_mode: AddressMode,
     pub dest_short_addr: u16,
+    /// Destination in [`AddressMode::Ieee`]; the short address is then only
+    /// the last known one
+    pub dest_ieee_addr: Option<[u8; 8]>,
     pub dest_endpoint: u8,
     pub profile_id: u16,
     pub cluster_id: u16,
@@ -589,6 +1002,7 @@ impl ApsDataRequest {
             request_id,
             dest_addr_mode: AddressMode::Nwk,
             dest_short_addr,
+            dest_ieee_addr: None,
             dest_endpoint,
             profile_id: profiles::HOME_AUTOMATION,
             cluster_id,
@@ -599,6 +1013,135 @@ impl ApsDataRequest {
         }
     }
 
+    /// Create a request to every member of a group
+    #[must_use]
+    pub fn group(request_id: u8, group_id: u16, cluster_id: u16, asdu: Vec<u8>) -> Self {
+        Self {
+            request_id,
+            dest_addr_mode: AddressMode::Group,
+            dest_short_addr: group_id,
+            dest_ieee_addr: None,
+            dest_endpoint: 0x00, // Not sent; members pick their endpoints
+            profile_id: profiles::HOME_AUTOMATION,
+            cluster_id,
+            src_endpoint: 0x01,
+            asdu,
+            tx_options: 0x00, // Group frames aren't acknowledged
+            radius: 0x00,
+        }
+    }
+
+    /// Create a request broadcast to `dest_endpoint` (0xFF for all) of the
+    /// devices addressed by `broadcast`
+    #[must_use]
+    pub fn broadcast(
+        request_id: u8,
+        broadcast: Broadcast,
+        dest_endpoint: u8,
+        cluster_id: u16,
+        asdu: Vec<u8>,
+    ) -> Self {
+        Self {
+            request_id,
+            dest_addr_mode: AddressMode::Nwk,
+            dest_short_addr: broadcast as u16,
+            dest_ieee_addr: None,
+            dest_endpoint,
+            profile_id: profiles::HOME_AUTOMATION,
+            cluster_id,
+            src_endpoint: 0x01,
+            asdu,
+            tx_options: 0x00, // Broadcasts aren't acknowledged
+            radius: 0x00,
+        }
+    }
+
+    /// Address the request to the device's IEEE address, for when its short
+    /// address may have changed; the adapter resolves the current one
+    #[must_use]
+    pub fn with_ieee_destination(mut self, ieee: [u8; 8]) -> Self {
+        self.dest_addr_mode = AddressMode::Ieee;
+        self.dest_ieee_addr = Some(ieee);
+        self
+    }
+
+    /// Whether the request goes to a broadcast address
+    #[must_use]
+    pub fn is_broadcast(&self) -> bool {
+        self.dest_addr_mode == AddressMode::Nwk && self.dest_short_addr >= 0xFFF8
+    }
+
+    /// Whether carrying the request out twice has the same effect as once,
+    /// so it may be sent again when it isn't known whether it went out
+    ///
+    /// ZDO requests and ZCL global commands (reads, writes, reporting setup)
+    /// are; cluster commands only when they set an absolute state. Toggles,
+    /// steps and anything not listed here are not.
+    #[must_use]
+    pub fn is_idempotent(&self) -> bool {
+        if self.profile_id == profiles::ZDO {
+            return true;
+        }
+        let Ok(frame) = ZclFrame::parse(&self.asdu) else {
+            return false;
+        };
+        if !frame.is_cluster_specific() {
+            return true;
+        }
+        if frame.manufacturer_code.is_some() {
+            return false;
+        }
+        let command = frame.command_id();
+        match self.cluster_id {
+            // Identify, trigger effect
+            clusters::IDENTIFY => matches!(command, 0x00 | 0x40),
+            // Add, view, get membership, remove, remove all, add if identifying
+            clusters::GROUPS => command <= 0x05,
+            // Add, view, remove, remove all, store, recall, get membership
+            // and their enhanced forms
+            clusters::SCENES => matches!(command, 0x00..=0x06 | 0x40..=0x42),
+            // Off, on, off with effect, on with recalled scene, on with timed off
+            clusters::ON_OFF => matches!(command, 0x00 | 0x01 | 0x40..=0x42),
+            // Move to level, move, stop (with and without on/off)
+            clusters::LEVEL_CONTROL => matches!(command, 0x00 | 0x01 | 0x03 | 0x04 | 0x05 | 0x07),
+            // Move to / move hue, saturation, color and temperature, color
+            // loop, stop
+            clusters::COLOR_CONTROL => matches!(
+                command,
+                0x00 | 0x01 | 0x03 | 0x04 | 0x06
+                    ..=0x08 | 0x0A | 0x40 | 0x41 | 0x43 | 0x44 | 0x47 | 0x4B
+            ),
+            // Up, down, stop, go to lift or tilt value / percentage
+            clusters::WINDOW_COVERING => matches!(command, 0x00..=0x02 | 0x04 | 0x05 | 0x07 | 0x08),
+            _ => false,
+        }
+    }
+
+    /// Create a ZDO NWK Address Request, asking the device with `ieee_addr`
+    /// (or its parent) for its current short address
+    #[must_use]
+    pub fn nwk_addr_request(request_id: u8, ieee_addr: [u8; 8], tsn: u8) -> Self {
+        // ASDU: TSN (1 byte) + IEEE address (8 bytes LE) + request type
+        // (single device) + start index
+        let mut asdu = vec![tsn];
+        asdu.extend_from_slice(&ieee_addr);
+        asdu.extend_from_slice(&[0x00, 0x00]);
+
+        Self {
+            request_id,
+            dest_addr_mode: AddressMode::Nwk,
+            dest_short_addr: Broadcast::RxOnWhenIdle as u16,
+            dest_ieee_addr: None,
+            dest_endpoint: 0x00, // ZDO endpoint
+            profile_id: profiles::ZDO,
+            cluster_id: ZdoCluster::NwkAddrReq as u16,
+            src_endpoint: 0x00, // ZDO endpoint
+            asdu,
+            tx_options: 0x00, // No ACK for ZDO
+            radius: 0x00,
+        }
+    }
+
     /// Create a ZDO Active Endpoints Request
     #[must_use]
     pub fn active_endpoints_request(request_id: u8, dest_short_addr: u16, tsn: u8) -> Self {
@@ -610,6 +1153,7 @@ impl ApsDataRequest {
             request_id,
             dest_addr_mode: AddressMode::Nwk,
             dest_short_addr,
+            dest_ieee_addr: None,
             dest_endpoint: 0x00, // ZDO endpoint
             profile_id: profiles::ZDO,
             cluster_id: ZdoCluster::ActiveEpReq as u16,
@@ -637,6 +1181,7 @@ impl ApsDataRequest {
             request_id,
             dest_addr_mode: AddressMode::Nwk,
             dest_short_addr,
+            dest_ieee_addr: None,
             dest_endpoint: 0x00, // ZDO endpoint
             profile_id: profiles::ZDO,
             cluster_id: ZdoCluster::SimpleDescReq as u16,
@@ -647,6 +1192,85 @@ impl ApsDataRequest {
         }
     }
 
+    /// Create a ZDO Mgmt_Lqi Request (neighbor table, starting at `start_index`)
+    #[must_use]
+    pub fn mgmt_lqi_request(
+        request_id: u8,
+        dest_short_addr: u16,
+        start_index: u8,
+        tsn: u8,
+    ) -> Self {
+        // ASDU: TSN (1 byte) + start index (1 byte)
+        let asdu = vec![tsn, start_index];
+
+        Self {
+            request_id,
+            dest_addr_mode: AddressMode::Nwk,
+            dest_short_addr,
+            dest_ieee_addr: None,
+            dest_endpoint: 0x00, // ZDO endpoint
+            profile_id: profiles::ZDO,
+            cluster_id: ZdoCluster::MgmtLqiReq as u16,
+            src_endpoint: 0x00, // ZDO endpoint
+            asdu,
+            tx_options: 0x00, // No ACK for ZDO
+            radius: 0x00,
+        }
+    }
+
+    /// Create a ZDO Mgmt_Rtg Request (routing table, starting at `start_index`)
+    #[must_use]
+    pub fn mgmt_rtg_request(
+        request_id: u8,
+        dest_short_addr: u16,
+        start_index: u8,
+        tsn: u8,
+    ) -> Self {
+        // ASDU: TSN (1 byte) + start index (1 byte)
+        let asdu = vec![tsn, start_index];
+
+        Self {
+            request_id,
+            dest_addr_mode: AddressMode::Nwk,
+            dest_short_addr,
+            dest_ieee_addr: None,
+            dest_endpoint: 0x00, // ZDO endpoint
+            profile_id: profiles::ZDO,
+            cluster_id: ZdoCluster::MgmtRtgReq as u16,
+            src_endpoint: 0x00, // ZDO endpoint
+            asdu,
+            tx_options: 0x00, // No ACK for ZDO
+            radius: 0x00,
+        }
+    }
+
+    /// Create a ZDO Mgmt_Permit_Joining Request, opening the receivers
+    /// (usually [`Broadcast::Routers`]) for `duration_secs`
+    #[must_use]
+    pub fn mgmt_permit_join_request(
+        request_id: u8,
+        broadcast: Broadcast,
+        duration_secs: u8,
+        tsn: u8,
+    ) -> Self {
+        // ASDU: TSN (1 byte) + duration (1 byte) + trust center significance (1 byte)
+        let asdu = vec![tsn, duration_secs, 0x01];
+
+        Self {
+            request_id,
+            dest_addr_mode: AddressMode::Nwk,
+            dest_short_addr: broadcast as u16,
+            dest_ieee_addr: None,
+            dest_endpoint: 0x00, // ZDO endpoint
+            profile_id: profiles::ZDO,
+            cluster_id: ZdoCluster::MgmtPermitJoinReq as u16,
+            src_endpoint: 0x00, // ZDO endpoint
+            asdu,
+            tx_options: 0x00, // No ACK for ZDO
+            radius: 0x00,
+        }
+    }
+
     /// Serialize to bytes for sending
     #[must_use]
     #[allow(clippy::missing_panics_doc)] // Panic only on protocol-violating payload size
@@ -666,11 +1290,21 @@ impl ApsDataRequest {
         // Destination address mode
         data.push(self.dest_addr_mode as u8);
 
-        // Destination address (short address for NWK mode)
-        data.extend_from_slice(&self.dest_short_addr.to_le_bytes());
-
-        // Destination endpoint
-        data.push(self.dest_endpoint);
+        // Destination address (group ID for group mode, IEEE address for
+        // IEEE mode, short address otherwise) and endpoint (not for groups)
+        match (self.dest_addr_mode, self.dest_ieee_addr) {
+            (AddressMode::Group, _) => {
+                data.extend_from_slice(&self.dest_short_addr.to_le_bytes());
+            }
+            (AddressMode::Ieee, Some(ieee)) => {
+                data.extend_from_slice(&ieee);
+                data.push(self.dest_endpoint);
+            }
+            _ => {
+                data.extend_from_slice(&self.dest_short_addr.to_le_bytes());
+                data.push(self.dest_endpoint);
+            }
+        }
 
         // Profile ID
         data.extend_from_slice(&self.profile_id.to_le_bytes());
@@ -773,6 +1407,12 @@ impl ZclFrame {
         (self.frame_control & 0x08) != 0
     }
 
+    /// Get the transaction sequence number
+    #[must_use]
+    pub fn transaction_seq(&self) -> u8 {
+        self.transaction_seq
+    }
+
     /// Get the command ID
     #[must_use]
     pub fn command_id(&self) -> u8 {
@@ -789,7 +1429,7 @@ impl ZclFrame {
     #[must_use]
     pub fn cluster_command(transaction_seq: u8, command_id: u8) -> Self {
         Self {
-            frame_control: 0x01, // Cluster-specific, client-to-server, disable default response
+            frame_control: 0x01, // Cluster-specific, client-to-server, with default response
             manufacturer_code: None,
             transaction_seq,
             command_id,
@@ -797,12 +1437,83 @@ impl ZclFrame {
         }
     }
 
+    /// Create a global (profile-wide) command frame (client to server)
+    #[must_use]
+    pub fn global_command(transaction_seq: u8, command_id: u8) -> Self {
+        Self {
+            frame_control: 0x00, // Global, client-to-server
+            manufacturer_code: None,
+            transaction_seq,
+            command_id,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Send this frame from the server side of the cluster (answering a
+    /// client, as the OTA Upgrade server does), without default response
+    #[must_use]
+    pub fn from_server(mut self) -> Self {
+        self.frame_control |= 0x08 | 0x10;
+        self
+    }
+
+    /// Make this a manufacturer-specific frame
+    #[must_use]
+    pub fn with_manufacturer_code(mut self, manufacturer_code: u16) -> Self {
+        self.frame_control |= 0x04;
+        self.manufacturer_code = Some(manufacturer_code);
+        self
+    }
+
+    /// Set the command payload
+    #[must_use]
+    pub fn with_payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
     /// Create an On/Off cluster command
     #[must_use]
     pub fn on_off_command(transaction_seq: u8, cmd: OnOffCommand) -> Self {
         Self::cluster_command(transaction_seq, cmd as u8)
     }
 
+    /// Create a Read Attributes command
+    #[must_use]
+    pub fn read_attributes(transaction_seq: u8, attribute_ids: &[u16]) -> Self {
+        Self {
+            frame_control: 0x00, // Global, client-to-server
+            manufacturer_code: None,
+            transaction_seq,
+            command_id: 0x00, // Read Attributes
+            payload: attribute_ids
+                .iter()
+                .flat_map(|id| id.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    /// Create a Write Attributes command for a single attribute
+    #[must_use]
+    pub fn write_attribute(
+        transaction_seq: u8,
+        attribute_id: u16,
+        data_type: u8,
+        value: &[u8],
+    ) -> Self {
+        let mut payload = Vec::with_capacity(3 + value.len());
+        payload.extend_from_slice(&attribute_id.to_le_bytes());
+        payload.push(data_type);
+        payload.extend_from_slice(value);
+        Self {
+            frame_control: 0x00, // Global, client-to-server
+            manufacturer_code: None,
+            transaction_seq,
+            command_id: 0x02, // Write Attributes
+            payload,
+        }
+    }
+
     /// Serialize to bytes
     #[must_use]
     pub fn serialize(&self) -> Vec<u8> {
@@ -817,3 +1528,112 @@ impl ZclFrame {
         data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_group_and_broadcast_requests() {
+        let off = ZclFrame::cluster_command(5, 0x00).serialize();
+
+        // Group 0x0007: no destination endpoint after the address
+        let group = ApsDataRequest::group(3, 0x0007, clusters::ON_OFF, off.clone()).serialize();
+        assert_eq!(
+            &group[..14],
+            &[0x11, 0x00, 3, 0x00, 0x01, 0x07, 0x00, 0x04, 0x01, 0x06, 0x00, 0x01, 0x03, 0x00]
+        );
+        assert_eq!(group.len(), 2 + 12 + off.len() + 2);
+
+        let broadcast =
+            ApsDataRequest::broadcast(4, Broadcast::RxOnWhenIdle, 0xFF, clusters::ON_OFF, off)
+                .serialize();
+        assert_eq!(&broadcast[4..8], &[0x02, 0xFD, 0xFF, 0xFF]);
+        assert_eq!(broadcast[broadcast.len() - 2], 0x00); // No APS ack
+
+        let by_ieee = ApsDataRequest::new(6, 0x1234, 1, clusters::ON_OFF, vec![0x01, 7, 0x01])
+            .with_ieee_destination([1, 2, 3, 4, 5, 6, 7, 8])
+            .serialize();
+        assert_eq!(&by_ieee[4..14], &[0x03, 1, 2, 3, 4, 5, 6, 7, 8, 0x01]);
+        assert_eq!(by_ieee.len(), 2 + 12 + 6 + 3 + 2 + 1);
+    }
+
+    #[test]
+    fn test_idempotent_requests() {
+        let command = |cluster, command| {
+            ApsDataRequest::new(
+                1,
+                0x1234,
+                1,
+                cluster,
+                ZclFrame::cluster_command(1, command).serialize(),
+            )
+        };
+        assert!(command(clusters::ON_OFF, 0x01).is_idempotent());
+        assert!(!command(clusters::ON_OFF, 0x02).is_idempotent()); // Toggle
+        assert!(command(clusters::LEVEL_CONTROL, 0x04).is_idempotent());
+        assert!(!command(clusters::LEVEL_CONTROL, 0x06).is_idempotent()); // Step
+        assert!(!command(clusters::COLOR_CONTROL, 0x4C).is_idempotent()); // Step temperature
+        assert!(!command(0xEF00, 0x00).is_idempotent()); // Unknown cluster
+        let read = ZclFrame::read_attributes(1, &[0x0000]).serialize();
+        assert!(ApsDataRequest::new(1, 0x1234, 1, 0xEF00, read).is_idempotent());
+        assert!(ApsDataRequest::nwk_addr_request(1, [1; 8], 1).is_idempotent());
+
+        let permit_join = ApsDataRequest::mgmt_permit_join_request(5, Broadcast::Routers, 60, 0x81);
+        assert_eq!(permit_join.dest_short_addr, 0xFFFC);
+        assert_eq!(permit_join.asdu, vec![0x81, 60, 0x01]);
+    }
+
+    #[test]
+    fn test_nwk_address_request_and_response() {
+        let ieee = [1, 2, 3, 4, 5, 6, 7, 8];
+        let request = ApsDataRequest::nwk_addr_request(9, ieee, 0x82);
+        assert!(request.is_broadcast());
+        assert_eq!(request.asdu, vec![0x82, 1, 2, 3, 4, 5, 6, 7, 8, 0x00, 0x00]);
+
+        let response =
+            NwkAddressResponse::parse(&[0x82, 0x00, 1, 2, 3, 4, 5, 6, 7, 8, 0x78, 0x56]).unwrap();
+        assert_eq!(response.status, 0);
+        assert_eq!(response.ieee_addr, ieee);
+        assert_eq!(response.nwk_addr, 0x5678);
+        assert!(NwkAddressResponse::parse(&[0x82, 0x81]).is_err());
+    }
+
+    #[test]
+    fn test_parse_mac_beacon_indication() {
+        let header = [
+            0x1A, 0x00, // payload length
+            0x34, 0x12, // source
+            0xCD, 0xAB, // PAN ID
+            15,   // channel
+            0x8F, // flags: permit join
+            3,    // update ID
+            0x0F, 0x00, // beacon payload length
+        ];
+        let beacon = [
+            0x00, 0x22, 0x84, // protocol ID, stack profile, capacity
+            1, 2, 3, 4, 5, 6, 7, 8, // extended PAN ID
+            0xFF, 0xFF, 0xFF, // tx offset
+            3,    // update ID
+        ];
+        let mut data = [header.as_slice(), beacon.as_slice()].concat();
+
+        let parsed = MacBeaconIndication::parse(&data).unwrap();
+        assert_eq!(parsed.src_addr, 0x1234);
+        assert_eq!(parsed.pan_id, 0xABCD);
+        assert_eq!(parsed.channel, 15);
+        assert!(parsed.permit_join());
+        assert_eq!(parsed.update_id, 3);
+        assert_eq!(parsed.extended_pan_id, Some([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!((parsed.lqi, parsed.rssi), (None, None));
+
+        data.extend_from_slice(&[0xB4, 0xC4]);
+        let parsed = MacBeaconIndication::parse(&data).unwrap();
+        assert_eq!((parsed.lqi, parsed.rssi), (Some(0xB4), Some(-60)));
+
+        // No beacon payload, so no extended PAN ID
+        let parsed = MacBeaconIndication::parse(&header).unwrap();
+        assert_eq!(parsed.extended_pan_id, None);
+        assert!(MacBeaconIndication::parse(&header[..10]).is_err());
+    }
+}
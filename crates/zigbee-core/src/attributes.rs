@@ -0,0 +1,189 @@
+//! Writing attributes
+//!
+//! A [`WriteAttributes`] frame carries one or more attribute records, each
+//! value encoded for its ZCL data type ([`ZclValue`]). [`ZigbeeNetwork::write_attribute`]
+//! sends one to a device and waits for the Write Attributes Response, for
+//! settings without a dedicated API: power-on behavior of Hue bulbs,
+//! Aqara sensitivity (manufacturer-specific), temperature calibration
+//! offsets and the like.
+
+use crate::cluster::{self, GlobalCommand};
+use crate::codec::ZclValue;
+use crate::network::{NetworkError, ZigbeeNetwork};
+use crate::poll_control::Dispatch;
+use deconz_protocol::{ApsDataRequest, ZclFrame};
+
+/// A Write Attributes command under construction
+#[derive(Debug, Clone, Default)]
+pub struct WriteAttributes {
+    manufacturer_code: Option<u16>,
+    records: Vec<(u16, ZclValue)>,
+}
+
+impl WriteAttributes {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an attribute to write
+    #[must_use]
+    pub fn attribute(mut self, attribute_id: u16, value: ZclValue) -> Self {
+        self.records.push((attribute_id, value));
+        self
+    }
+
+    /// Write manufacturer-specific attributes of this manufacturer
+    #[must_use]
+    pub fn manufacturer_code(mut self, manufacturer_code: u16) -> Self {
+        self.manufacturer_code = Some(manufacturer_code);
+        self
+    }
+
+    /// The ZCL frame (attribute ID, data type, value records)
+    #[must_use]
+    pub fn frame(&self, transaction_seq: u8) -> ZclFrame {
+        let mut payload = Vec::new();
+        for (attribute_id, value) in &self.records {
+            payload.extend_from_slice(&attribute_id.to_le_bytes());
+            payload.push(value.data_type() as u8);
+            payload.extend(value.encode());
+        }
+        let frame = ZclFrame::global_command(transaction_seq, GlobalCommand::WriteAttributes as u8)
+            .with_payload(payload);
+        match self.manufacturer_code {
+            Some(code) => frame.with_manufacturer_code(code),
+            None => frame,
+        }
+    }
+}
+
+impl ZigbeeNetwork {
+    /// Write one attribute of a device's cluster
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn write_attribute(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        cluster_id: u16,
+        attribute_id: u16,
+        value: ZclValue,
+    ) -> Result<Dispatch, NetworkError> {
+        self.write_attributes(
+            ieee,
+            endpoint,
+            cluster_id,
+            &WriteAttributes::new().attribute(attribute_id, value),
+        )
+        .await
+    }
+
+    /// Write attributes of a device's cluster
+    ///
+    /// Awake devices must accept the write: an error status in the Write
+    /// Attributes Response fails with [`NetworkError::Rejected`]. Writes to
+    /// sleepy devices are queued until they check in, and aren't confirmed.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn write_attributes(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        cluster_id: u16,
+        write: &WriteAttributes,
+    ) -> Result<Dispatch, NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        let zcl_frame = write.frame(cluster::next_tsn());
+        let request = ApsDataRequest::new(
+            1,
+            device.nwk_address,
+            endpoint,
+            cluster_id,
+            zcl_frame.serialize(),
+        );
+
+        tracing::info!(
+            "Writing {} attribute(s) of cluster {:#06x} on {:#06x}:{}",
+            write.records.len(),
+            cluster_id,
+            device.nwk_address,
+            endpoint
+        );
+        if self.queues_commands(&device) {
+            return self.send_or_queue(&device, request).await;
+        }
+        self.send_command(request).await?;
+        Ok(Dispatch::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ZclStatus;
+    use crate::device::ZigbeeDevice;
+    use deconz_protocol::mock::MockTransport;
+    use deconz_protocol::{clusters, DeconzEvent};
+    use std::sync::Arc;
+
+    const IEEE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    #[test]
+    fn test_write_attributes_frame() {
+        // Aqara motion sensitivity (manufacturer-specific uint8) and a
+        // temperature calibration offset of -1.5 °C (int16, centi-degrees)
+        let frame = WriteAttributes::new()
+            .attribute(0x010C, ZclValue::Uint8(1))
+            .attribute(0x0010, ZclValue::Int16(-150))
+            .manufacturer_code(0x115F)
+            .frame(7);
+        assert_eq!(
+            frame.serialize(),
+            vec![
+                0x04, 0x5F, 0x11, 0x07, 0x02, 0x0C, 0x01, 0x20, 0x01, 0x10, 0x00, 0x29, 0x6A, 0xFF
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refused_write_fails() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+
+        let write = tokio::spawn({
+            let network = network.clone();
+            async move {
+                network
+                    .write_attribute(&IEEE, 1, clusters::ON_OFF, 0x4003, ZclValue::Enum8(0x02))
+                    .await
+            }
+        });
+        while mock.aps_requests().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let frame = ZclFrame::parse(&mock.aps_requests()[0].asdu).unwrap();
+        assert_eq!(frame.command_id(), GlobalCommand::WriteAttributes as u8);
+        assert_eq!(frame.payload(), &[0x03, 0x40, 0x30, 0x02]);
+
+        // Write Attributes Response: invalid value for attribute 0x4003
+        mock.emit(DeconzEvent::ApsIndication(
+            deconz_protocol::mock::indication(
+                0x1234,
+                clusters::ON_OFF,
+                vec![0x18, frame.transaction_seq(), 0x04, 0x87, 0x03, 0x40],
+            ),
+        ));
+
+        let result = write.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(NetworkError::Rejected(ZclStatus::InvalidValue))
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+//! Device availability
+//!
+//! A device is available while it has been heard from recently. Any frame
+//! counts: reports, command responses, MAC data polls and announcements.
+//! Routers are mains powered and always reachable, so one that has been
+//! quiet for [`PING_AFTER`] is sent a cheap Basic cluster read and goes
+//! unavailable after [`ROUTER_TIMEOUT`]. Battery powered end devices sleep
+//! and are left alone; they go unavailable after [`END_DEVICE_TIMEOUT`]
+//! without a check-in. Green Power switches only transmit when pressed and
+//! never time out. Nothing is heard while the hub is down, so quiet time
+//! counts from startup at the earliest.
+//!
+//! Changes are broadcast as [`NetworkEvent::AvailabilityChanged`].
+//!
+//! `last_seen` changes with every frame, so it isn't saved each time; the
+//! monitor saves the devices every [`SAVE_SEEN_INTERVAL`] if any was heard
+//! from.
+
+use crate::cluster::{self, basic_attrs, id};
+use crate::device::{DeviceType, ZigbeeDevice};
+use crate::green_power;
+use crate::network::{self, DeviceStorage, NetworkEvent, ZigbeeNetwork};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataRequest, ZclFrame};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Routers quiet for this long are unavailable
+pub const ROUTER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// End devices quiet for this long are unavailable; most sensors report at
+/// least every few hours
+pub const END_DEVICE_TIMEOUT: Duration = Duration::from_secs(25 * 60 * 60);
+
+/// Routers quiet for this long are pinged
+pub const PING_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// How often devices are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often new `last_seen` times are saved
+pub const SAVE_SEEN_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a device may stay quiet, or `None` if it is never timed out
+#[must_use]
+pub fn timeout_for(device: &ZigbeeDevice) -> Option<Duration> {
+    match device.device_type {
+        DeviceType::Router => Some(ROUTER_TIMEOUT),
+        DeviceType::EndDevice if green_power::is_green_power(device) => None,
+        DeviceType::EndDevice => Some(END_DEVICE_TIMEOUT),
+        DeviceType::Coordinator => None,
+    }
+}
+
+fn set_available(
+    device: &mut ZigbeeDevice,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    available: bool,
+) {
+    if device.available == available {
+        return;
+    }
+    device.available = available;
+    tracing::info!(
+        "{} is {}",
+        device.display_name(),
+        if available {
+            "available"
+        } else {
+            "unavailable"
+        }
+    );
+    let _ = event_tx.send(NetworkEvent::AvailabilityChanged {
+        ieee_address: device.ieee_address,
+        available,
+    });
+}
+
+/// Record that a device was heard from
+pub(crate) fn mark_seen(device: &mut ZigbeeDevice, event_tx: &broadcast::Sender<NetworkEvent>) {
+    device.last_seen = Some(Utc::now());
+    set_available(device, event_tx, true);
+}
+
+/// Record that the device with a network address was heard from
+pub(crate) fn mark_seen_nwk(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    nwk_address: u16,
+) {
+    if let Some(ieee) = network::ieee_for_nwk(devices, nwk_address) {
+        network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+            mark_seen(device, event_tx);
+        });
+    }
+}
+
+/// Time out quiet devices, returning the routers to ping
+fn check(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    started: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<ApsDataRequest> {
+    let ieees: Vec<[u8; 8]> = devices.iter().map(|d| d.ieee_address).collect();
+    let mut pings = Vec::new();
+    for ieee in ieees {
+        let ping = network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+            let timeout = timeout_for(device)?;
+            let since = device.last_seen.map_or(started, |seen| seen.max(started));
+            let quiet = (now - since).to_std().unwrap_or_default();
+            if quiet >= timeout {
+                set_available(device, event_tx, false);
+            }
+            if device.device_type != DeviceType::Router || quiet < PING_AFTER {
+                return None;
+            }
+            let endpoint = device
+                .endpoints
+                .iter()
+                .find(|e| e.in_clusters.contains(&id::BASIC))
+                .map_or(1, |e| e.id);
+            Some(ApsDataRequest::new(
+                1,
+                device.nwk_address,
+                endpoint,
+                id::BASIC,
+                ZclFrame::read_attributes(cluster::next_tsn(), &[basic_attrs::ZCL_VERSION])
+                    .serialize(),
+            ))
+        });
+        pings.extend(ping.flatten());
+    }
+    pings
+}
+
+/// Whether any device was heard from after `since`
+fn seen_since(devices: &DashMap<[u8; 8], ZigbeeDevice>, since: DateTime<Utc>) -> bool {
+    devices
+        .iter()
+        .any(|d| d.last_seen.is_some_and(|seen| seen > since))
+}
+
+/// Check availability, and save `last_seen` times, in the background
+pub fn start_monitor(network: &Arc<ZigbeeNetwork>) {
+    let network = Arc::clone(network);
+    tokio::spawn(async move {
+        let started = Utc::now();
+        let mut saved = started;
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let now = Utc::now();
+            if (now - saved).to_std().unwrap_or_default() >= SAVE_SEEN_INTERVAL {
+                if seen_since(network.devices(), saved) {
+                    network.save_devices();
+                }
+                saved = now;
+            }
+            let pings = check(
+                network.devices(),
+                network.event_sender(),
+                network.storage(),
+                started,
+                now,
+            );
+            for request in pings {
+                if let Err(e) = network.transport().send_aps_request(request).await {
+                    tracing::debug!("Failed to ping router: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Endpoint;
+    use crate::green_power::GREEN_POWER_PROFILE;
+
+    #[test]
+    fn test_quiet_devices_time_out() {
+        let (event_tx, mut events) = broadcast::channel(8);
+        let devices = DashMap::new();
+        let started = Utc::now();
+        let after = |duration| chrono::Duration::from_std(duration).unwrap();
+
+        let mut router = ZigbeeDevice::new([1; 8], 0x0001);
+        router.device_type = DeviceType::Router;
+        devices.insert(router.ieee_address, router);
+        let sensor = ZigbeeDevice::new([2; 8], 0x0002);
+        devices.insert(sensor.ieee_address, sensor);
+        let mut switch = ZigbeeDevice::new([3; 8], deconz_protocol::NO_SHORT_ADDRESS);
+        switch.endpoints.push(Endpoint {
+            id: 242,
+            profile_id: GREEN_POWER_PROFILE,
+            device_id: 0x02,
+            in_clusters: Vec::new(),
+            out_clusters: vec![0x0021],
+        });
+        devices.insert(switch.ieee_address, switch);
+
+        assert!(check(&devices, &event_tx, None, started, started).is_empty());
+
+        // Quiet router is pinged before it times out
+        let pings = check(
+            &devices,
+            &event_tx,
+            None,
+            started,
+            started + after(PING_AFTER),
+        );
+        assert_eq!(pings.len(), 1);
+        assert!(events.try_recv().is_err());
+
+        check(
+            &devices,
+            &event_tx,
+            None,
+            started,
+            started + after(ROUTER_TIMEOUT),
+        );
+        assert!(!devices.get(&[1; 8]).unwrap().available);
+        assert!(devices.get(&[2; 8]).unwrap().available);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(NetworkEvent::AvailabilityChanged {
+                ieee_address: [1, ..],
+                available: false,
+            })
+        ));
+
+        check(
+            &devices,
+            &event_tx,
+            None,
+            started,
+            started + after(END_DEVICE_TIMEOUT),
+        );
+        assert!(!devices.get(&[2; 8]).unwrap().available);
+        assert!(devices.get(&[3; 8]).unwrap().available);
+
+        // Heard from again
+        assert!(!seen_since(&devices, started));
+        mark_seen_nwk(&devices, &event_tx, None, 0x0001);
+        assert!(devices.get(&[1; 8]).unwrap().available);
+        assert!(seen_since(&devices, started));
+
+        // Seen long ago, but the hub only just started
+        devices.get_mut(&[1; 8]).unwrap().last_seen = Some(started - after(ROUTER_TIMEOUT) * 2);
+        check(
+            &devices,
+            &event_tx,
+            None,
+            started,
+            started + after(PING_AFTER),
+        );
+        assert!(devices.get(&[1; 8]).unwrap().available);
+    }
+}
@@ -0,0 +1,399 @@
+//! Network backup and restore
+//!
+//! Backups use the open coordinator backup format (as used by zigpy and
+//! Zigbee2MQTT) so a network can be migrated to a new stick - or to another
+//! tool - without re-pairing every device. Casita's own device table is kept
+//! alongside in `metadata.internal`.
+
+use crate::device::{DeviceType, ZigbeeDevice};
+use crate::formation::{NetworkConfig, MAX_CHANNEL, MIN_CHANNEL};
+use crate::network::{NetworkError, NetworkStatus, ZigbeeNetwork};
+use deconz_protocol::NetworkState;
+use serde::{Deserialize, Serialize};
+
+/// Format identifier of the open coordinator backup format
+pub const BACKUP_FORMAT: &str = "zigpy/open-coordinator-backup";
+/// Supported version of the backup format
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Zigbee 3.0 NWK security level (AES-128 encryption + 32-bit MIC)
+const SECURITY_LEVEL: u8 = 5;
+/// deCONZ security mode used when the backup does not record one (trust center link key)
+const DEFAULT_SECURITY_MODE: u8 = 3;
+/// Added to the restored frame counter so devices never see a counter they already accepted
+const FRAME_COUNTER_MARGIN: u32 = 1000;
+
+/// A complete network backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkBackup {
+    pub metadata: BackupMetadata,
+    /// Coordinator IEEE address (big-endian hex)
+    pub coordinator_ieee: String,
+    /// PAN ID (big-endian hex)
+    pub pan_id: String,
+    /// Extended PAN ID (big-endian hex)
+    pub extended_pan_id: String,
+    pub nwk_update_id: u8,
+    pub security_level: u8,
+    pub channel: u8,
+    /// Allowed channels
+    pub channel_mask: Vec<u8>,
+    pub network_key: BackupNetworkKey,
+    pub devices: Vec<BackupDevice>,
+}
+
+/// Backup metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    pub format: String,
+    pub version: u32,
+    pub source: String,
+    #[serde(default)]
+    pub internal: BackupInternal,
+}
+
+/// Casita-specific backup data
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupInternal {
+    /// Backup creation time (unix seconds)
+    #[serde(default)]
+    pub created_at: u64,
+    /// deCONZ security mode of the source coordinator
+    #[serde(default)]
+    pub security_mode: Option<u8>,
+    /// Full device table (names, categories, endpoints)
+    #[serde(default)]
+    pub devices: Vec<ZigbeeDevice>,
+}
+
+/// Network key and its outgoing frame counter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupNetworkKey {
+    /// Key bytes (hex, transmission order)
+    pub key: String,
+    #[serde(default)]
+    pub sequence_number: u8,
+    pub frame_counter: u32,
+}
+
+/// A device entry in the backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupDevice {
+    /// Network short address (big-endian hex)
+    pub nwk_address: String,
+    /// IEEE address (big-endian hex)
+    pub ieee_address: String,
+    /// Whether the device is a direct child (end device) of the coordinator
+    #[serde(default)]
+    pub is_child: bool,
+}
+
+impl NetworkBackup {
+    /// Check the format header and decode the network parameters
+    #[allow(clippy::missing_errors_doc)]
+    pub fn decode(&self) -> Result<DecodedBackup, NetworkError> {
+        if self.metadata.format != BACKUP_FORMAT {
+            return Err(backup_error(format!(
+                "unsupported backup format '{}'",
+                self.metadata.format
+            )));
+        }
+        if self.metadata.version != BACKUP_VERSION {
+            return Err(backup_error(format!(
+                "unsupported backup version {}",
+                self.metadata.version
+            )));
+        }
+
+        let channel_bit = |channel: u8| {
+            if !(MIN_CHANNEL..=MAX_CHANNEL).contains(&channel) {
+                return Err(backup_error(format!(
+                    "channel {channel} is not between {MIN_CHANNEL} and {MAX_CHANNEL}"
+                )));
+            }
+            NetworkConfig::channel_to_mask(channel)
+                .ok_or_else(|| backup_error(format!("invalid channel {channel}")))
+        };
+        let mut channel_mask = 0;
+        for &channel in &self.channel_mask {
+            channel_mask |= channel_bit(channel)?;
+        }
+        if channel_mask == 0 {
+            channel_mask = channel_bit(self.channel)?;
+        }
+
+        Ok(DecodedBackup {
+            coordinator_ieee: hex_to_le(&self.coordinator_ieee)?,
+            pan_id: u16::from_le_bytes(hex_to_le(&self.pan_id)?),
+            extended_pan_id: hex_to_le(&self.extended_pan_id)?,
+            nwk_update_id: self.nwk_update_id,
+            channel_mask,
+            network_key: hex_to_bytes(&self.network_key.key)?,
+            frame_counter: self.network_key.frame_counter,
+            security_mode: self
+                .metadata
+                .internal
+                .security_mode
+                .unwrap_or(DEFAULT_SECURITY_MODE),
+        })
+    }
+
+    /// Device table to import: the full Casita table if present, otherwise
+    /// minimal entries built from the standard device list
+    #[allow(clippy::missing_errors_doc)]
+    pub fn device_table(&self) -> Result<Vec<ZigbeeDevice>, NetworkError> {
+        if !self.metadata.internal.devices.is_empty() {
+            return Ok(self.metadata.internal.devices.clone());
+        }
+        self.devices
+            .iter()
+            .map(|entry| {
+                let mut device = ZigbeeDevice::new(
+                    hex_to_le(&entry.ieee_address)?,
+                    u16::from_le_bytes(hex_to_le(&entry.nwk_address)?),
+                );
+                device.device_type = if entry.is_child {
+                    DeviceType::EndDevice
+                } else {
+                    DeviceType::Router
+                };
+                Ok(device)
+            })
+            .collect()
+    }
+}
+
+/// Network parameters decoded from a backup (little-endian, as written to the stick)
+#[derive(Debug, Clone)]
+pub struct DecodedBackup {
+    pub coordinator_ieee: [u8; 8],
+    pub pan_id: u16,
+    pub extended_pan_id: [u8; 8],
+    pub nwk_update_id: u8,
+    pub channel_mask: u32,
+    pub network_key: [u8; 16],
+    pub frame_counter: u32,
+    pub security_mode: u8,
+}
+
+impl ZigbeeNetwork {
+    /// Capture the network parameters, keys and device table
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn backup(&self) -> Result<NetworkBackup, NetworkError> {
+        let transport = self.transport();
+
+        let coordinator_ieee = transport.read_mac_address().await?;
+        let pan_id = transport.read_pan_id().await?;
+        let extended_pan_id = transport.read_extended_pan_id().await?;
+        let nwk_update_id = transport.read_nwk_update_id().await?;
+        let channel = transport.read_channel().await?;
+        let channel_mask = transport.read_channel_mask().await?;
+        let security_mode = transport.read_security_mode().await?;
+        let network_key = transport.read_network_key().await?;
+        let frame_counter = transport.read_frame_counter().await?;
+
+        let devices = self.get_devices();
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        tracing::info!("Created network backup with {} devices", devices.len());
+
+        Ok(NetworkBackup {
+            metadata: BackupMetadata {
+                format: BACKUP_FORMAT.to_string(),
+                version: BACKUP_VERSION,
+                source: concat!("casita-assistant@", env!("CARGO_PKG_VERSION")).to_string(),
+                internal: BackupInternal {
+                    created_at,
+                    security_mode: Some(security_mode),
+                    devices: devices.clone(),
+                },
+            },
+            coordinator_ieee: le_to_hex(&coordinator_ieee),
+            pan_id: le_to_hex(&pan_id.to_le_bytes()),
+            extended_pan_id: le_to_hex(&extended_pan_id),
+            nwk_update_id,
+            security_level: SECURITY_LEVEL,
+            channel,
+            channel_mask: (u32::from(MIN_CHANNEL)..=u32::from(MAX_CHANNEL))
+                .filter(|ch| channel_mask & (1 << ch) != 0)
+                .map(|ch| ch as u8)
+                .collect(),
+            network_key: BackupNetworkKey {
+                key: bytes_to_hex(&network_key),
+                sequence_number: 0,
+                frame_counter,
+            },
+            devices: devices
+                .iter()
+                .filter(|d| d.device_type != DeviceType::Coordinator)
+                .map(|d| BackupDevice {
+                    nwk_address: le_to_hex(&d.nwk_address.to_le_bytes()),
+                    ieee_address: le_to_hex(&d.ieee_address),
+                    is_child: d.device_type == DeviceType::EndDevice,
+                })
+                .collect(),
+        })
+    }
+
+    /// Restore a backup onto the connected stick
+    ///
+    /// Writes the coordinator address, network parameters, key and frame
+    /// counter, brings the network back online and imports the device table.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn restore(&self, backup: &NetworkBackup) -> Result<NetworkStatus, NetworkError> {
+        let decoded = backup.decode()?;
+        let devices = backup.device_table()?;
+        let transport = self.transport();
+
+        let config = NetworkConfig {
+            channel: None,
+            channel_mask: Some(decoded.channel_mask),
+            pan_id: Some(decoded.pan_id),
+            extended_pan_id: Some(decoded.extended_pan_id),
+            network_key: Some(decoded.network_key),
+            security_mode: Some(decoded.security_mode),
+        };
+        config.validate()?;
+
+        tracing::info!(
+            "Restoring network backup (PAN {:#06x}, {} devices)",
+            decoded.pan_id,
+            devices.len()
+        );
+
+        self.disconnect().await?;
+        self.wait_for_network_state(NetworkState::Offline).await?;
+
+        transport
+            .write_mac_address(decoded.coordinator_ieee)
+            .await?;
+        transport.write_nwk_address(0x0000).await?;
+        transport
+            .write_trust_center_address(decoded.coordinator_ieee)
+            .await?;
+        transport.write_nwk_update_id(decoded.nwk_update_id).await?;
+        transport
+            .write_frame_counter(decoded.frame_counter.saturating_add(FRAME_COUNTER_MARGIN))
+            .await?;
+        self.write_network_config(&config).await?;
+
+        self.connect().await?;
+        self.wait_for_network_state(NetworkState::Connected).await?;
+
+        self.verify_network_config(&config).await?;
+
+        for device in devices {
+            self.upsert_device(device);
+        }
+
+        tracing::info!("Network backup restored");
+        self.get_status().await
+    }
+}
+
+fn backup_error(message: String) -> NetworkError {
+    NetworkError::Configuration(format!("invalid backup: {message}"))
+}
+
+/// Format little-endian bytes as a big-endian hex string
+fn le_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().rev().map(|b| format!("{b:02x}")).collect()
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parse a big-endian hex string into little-endian bytes
+fn hex_to_le<const N: usize>(s: &str) -> Result<[u8; N], NetworkError> {
+    let mut bytes = hex_to_bytes::<N>(s)?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+fn hex_to_bytes<const N: usize>(s: &str) -> Result<[u8; N], NetworkError> {
+    let s: String = s.chars().filter(|c| *c != ':').collect();
+    if s.len() != N * 2 || !s.is_ascii() {
+        return Err(backup_error(format!(
+            "expected {} hex digits, got '{s}'",
+            N * 2
+        )));
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+            .map_err(|_| backup_error(format!("invalid hex '{s}'")))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_backup() -> NetworkBackup {
+        NetworkBackup {
+            metadata: BackupMetadata {
+                format: BACKUP_FORMAT.to_string(),
+                version: BACKUP_VERSION,
+                source: "test".to_string(),
+                internal: BackupInternal::default(),
+            },
+            coordinator_ieee: "00212effff0a1b2c".to_string(),
+            pan_id: "1a62".to_string(),
+            extended_pan_id: "dddddddddddddddd".to_string(),
+            nwk_update_id: 0,
+            security_level: SECURITY_LEVEL,
+            channel: 15,
+            channel_mask: vec![15],
+            network_key: BackupNetworkKey {
+                key: "01030507090b0d0f00020406080a0c0d".to_string(),
+                sequence_number: 0,
+                frame_counter: 42,
+            },
+            devices: vec![BackupDevice {
+                nwk_address: "4f2a".to_string(),
+                ieee_address: "00158d0001020304".to_string(),
+                is_child: true,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_decode_backup() {
+        let decoded = sample_backup().decode().unwrap();
+        assert_eq!(decoded.pan_id, 0x1a62);
+        assert_eq!(
+            decoded.coordinator_ieee,
+            [0x2c, 0x1b, 0x0a, 0xff, 0xff, 0x2e, 0x21, 0x00]
+        );
+        assert_eq!(decoded.channel_mask, 0x0000_8000);
+        assert_eq!(decoded.network_key[0], 0x01);
+        assert_eq!(decoded.security_mode, DEFAULT_SECURITY_MODE);
+
+        let devices = sample_backup().device_table().unwrap();
+        assert_eq!(devices[0].nwk_address, 0x4f2a);
+        assert_eq!(devices[0].ieee_address_string(), "00:15:8d:00:01:02:03:04");
+    }
+
+    #[test]
+    fn test_decode_rejects_unknown_format() {
+        let mut backup = sample_backup();
+        backup.metadata.format = "something-else".to_string();
+        assert!(backup.decode().is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_channels() {
+        let mut backup = sample_backup();
+        backup.channel_mask = vec![15, 40];
+        assert!(backup.decode().is_err());
+
+        let mut backup = sample_backup();
+        backup.channel_mask = Vec::new();
+        backup.channel = 255;
+        assert!(backup.decode().is_err());
+    }
+}
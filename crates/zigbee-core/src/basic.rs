@@ -0,0 +1,88 @@
+//! Basic cluster: manufacturer and model
+//!
+//! Read from every device that has a Basic cluster when its endpoints are
+//! discovered. Device-specific handling (see [`crate::tuya`]) keys on these.
+//!
+//! Tuya devices only start reporting after this exact set of attributes is
+//! read, so the list doubles as their "magic packet".
+
+use crate::cluster::{self, attribute_values, basic_attrs, id};
+use crate::device::{Endpoint, ZigbeeDevice};
+use crate::network::{self, DeviceStorage, NetworkEvent};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, ZclFrame};
+use tokio::sync::broadcast;
+
+/// Attributes read on discovery (Tuya requires 0xFFFE as well)
+const IDENTITY_ATTRIBUTES: [u16; 6] = [
+    basic_attrs::MANUFACTURER_NAME,
+    basic_attrs::ZCL_VERSION,
+    basic_attrs::APPLICATION_VERSION,
+    basic_attrs::MODEL_IDENTIFIER,
+    basic_attrs::POWER_SOURCE,
+    0xFFFE,
+];
+
+/// Read Attributes request for the manufacturer and model of an endpoint,
+/// if it has a Basic cluster
+pub(crate) fn identity_read(nwk_address: u16, endpoint: &Endpoint) -> Option<ApsDataRequest> {
+    if !endpoint.in_clusters.contains(&id::BASIC) {
+        return None;
+    }
+    Some(ApsDataRequest::new(
+        1,
+        nwk_address,
+        endpoint.id,
+        id::BASIC,
+        ZclFrame::read_attributes(cluster::next_tsn(), &IDENTITY_ATTRIBUTES).serialize(),
+    ))
+}
+
+/// Handle a Basic cluster frame, recording the manufacturer and model
+pub(crate) fn handle_frame(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    if zcl.is_cluster_specific() {
+        return;
+    }
+    let Some(ieee) = network::ieee_for_nwk(devices, indication.src_short_addr) else {
+        return;
+    };
+
+    network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+        let mut changed = false;
+        for attribute in attribute_values(zcl.command_id(), zcl.payload()) {
+            let field = match attribute.id {
+                basic_attrs::MANUFACTURER_NAME => &mut device.manufacturer,
+                basic_attrs::MODEL_IDENTIFIER => &mut device.model,
+                _ => continue,
+            };
+            let value = String::from_utf8_lossy(&attribute.value)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string();
+            if value.is_empty() || field.as_deref() == Some(value.as_str()) {
+                continue;
+            }
+            *field = Some(value);
+            changed = true;
+        }
+        if !changed {
+            return;
+        }
+
+        tracing::info!(
+            "Device {:#06x} is {} {}",
+            indication.src_short_addr,
+            device.manufacturer.as_deref().unwrap_or("?"),
+            device.model.as_deref().unwrap_or("?")
+        );
+        if let Some(quirk) = crate::quirks::quirk_for(device) {
+            tracing::info!("Using quirks for {}", quirk.description);
+        }
+    });
+}
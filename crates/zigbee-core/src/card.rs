@@ -0,0 +1,259 @@
+//! Recommended UI cards
+//!
+//! A card is the layout a client should draw for a device: its controls with
+//! ranges and units, then its readings. It is built from the device's
+//! [`Expose`]s so every frontend shows a device the same way and none of them
+//! needs its own table of what a cluster means.
+//!
+//! Control and reading names are the keys clients already know: a feature
+//! name for lights, switches and thermostats, a [`SensorKind`] for
+//! `sensor_values`, a data point name for `tuya_values`, or a device field
+//! (`occupancy`, `ias_zone`).
+
+use crate::device::{DeviceCategory, ZigbeeDevice};
+use crate::exposes::{Expose, Feature};
+use crate::sensor::SensorKind;
+use crate::tuya::{self, DpKind};
+use serde::Serialize;
+
+/// Mireds of the coolest white most color temperature lights support
+const COLOR_TEMP_MIN: f64 = 153.0;
+/// Mireds of the warmest white
+const COLOR_TEMP_MAX: f64 = 500.0;
+/// Heating setpoint range of thermostats and TRVs in °C
+const SETPOINT_MIN: f64 = 5.0;
+const SETPOINT_MAX: f64 = 30.0;
+
+/// Something the user can change
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    /// On/off switch
+    Toggle { endpoint: Option<u8>, name: String },
+    /// A number within a range
+    Slider {
+        endpoint: Option<u8>,
+        name: String,
+        min: f64,
+        max: f64,
+        step: f64,
+        unit: Option<&'static str>,
+    },
+    /// A number without a known range
+    Number {
+        endpoint: Option<u8>,
+        name: String,
+        step: f64,
+    },
+    /// One of a fixed set of options
+    Select {
+        endpoint: Option<u8>,
+        name: String,
+        options: Vec<&'static str>,
+    },
+    /// Hue and saturation picker
+    Color { endpoint: u8 },
+}
+
+/// A value that is only shown
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reading {
+    pub endpoint: Option<u8>,
+    pub name: String,
+    pub unit: Option<&'static str>,
+}
+
+/// Recommended UI card for a device
+#[derive(Debug, Clone, Serialize)]
+pub struct Card {
+    pub title: String,
+    pub category: DeviceCategory,
+    pub controls: Vec<Control>,
+    pub readings: Vec<Reading>,
+}
+
+fn reading(endpoint: Option<u8>, name: &str, unit: Option<&'static str>) -> Reading {
+    Reading {
+        endpoint,
+        name: name.to_string(),
+        unit,
+    }
+}
+
+/// Controls and readings of one light, switch or thermostat feature
+fn feature_card(id: u8, feature: Feature, card: &mut Card) {
+    let endpoint = Some(id);
+    let slider = |name: &str, min, max, step, unit| Control::Slider {
+        endpoint,
+        name: name.to_string(),
+        min,
+        max,
+        step,
+        unit,
+    };
+    match feature {
+        Feature::State => card.controls.push(Control::Toggle {
+            endpoint,
+            name: "state".to_string(),
+        }),
+        Feature::Brightness => {
+            card.controls
+                .push(slider("brightness", 1.0, 100.0, 1.0, Some("%")));
+        }
+        Feature::ColorTemp => card.controls.push(slider(
+            "color_temp",
+            COLOR_TEMP_MIN,
+            COLOR_TEMP_MAX,
+            1.0,
+            Some("mired"),
+        )),
+        Feature::Color => card.controls.push(Control::Color { endpoint: id }),
+        Feature::PowerOnBehavior => card.controls.push(Control::Select {
+            endpoint,
+            name: "power_on_behavior".to_string(),
+            options: vec!["off", "on", "toggle", "previous"],
+        }),
+        Feature::HeatingSetpoint => card.controls.push(slider(
+            "heating_setpoint",
+            SETPOINT_MIN,
+            SETPOINT_MAX,
+            0.5,
+            Some(SensorKind::Temperature.unit()),
+        )),
+        Feature::LocalTemperature => card.readings.push(reading(
+            endpoint,
+            "local_temperature",
+            Some(SensorKind::Temperature.unit()),
+        )),
+        // Configured through climate schedules, not on the card
+        Feature::ExternalTemperature => {}
+    }
+}
+
+impl ZigbeeDevice {
+    /// Recommended UI card, built from [`ZigbeeDevice::exposes`]
+    #[must_use]
+    pub fn card(&self) -> Card {
+        let mut card = Card {
+            title: self.display_name(),
+            category: self.category,
+            controls: Vec::new(),
+            readings: Vec::new(),
+        };
+        let tuya_model = self.manufacturer.as_deref().and_then(tuya::model_for);
+
+        for expose in self.exposes() {
+            match expose {
+                Expose::Switch { endpoint, features }
+                | Expose::Light { endpoint, features }
+                | Expose::Thermostat { endpoint, features } => {
+                    for feature in features {
+                        feature_card(endpoint, feature, &mut card);
+                    }
+                }
+                Expose::Sensor {
+                    endpoint,
+                    kind,
+                    unit,
+                } => {
+                    let name = serde_json::to_value(kind)
+                        .ok()
+                        .and_then(|v| v.as_str().map(str::to_string))
+                        .unwrap_or_default();
+                    card.readings.push(reading(endpoint, &name, Some(unit)));
+                }
+                Expose::Occupancy { endpoint } => {
+                    card.readings.push(reading(endpoint, "occupancy", None));
+                }
+                Expose::Alarm { endpoint, .. } => {
+                    card.readings
+                        .push(reading(Some(endpoint), "ias_zone", None));
+                }
+                Expose::Remote { .. } => {
+                    card.readings.push(reading(None, "button_event", None));
+                }
+                Expose::TuyaValue { name, .. } => {
+                    let Some(mapping) =
+                        tuya_model.and_then(|m| m.datapoints.iter().find(|d| d.name == name))
+                    else {
+                        continue;
+                    };
+                    let name = name.to_string();
+                    match mapping.kind {
+                        // Already shown from `sensor_values` and `occupancy`
+                        DpKind::Measurement(_) | DpKind::Occupancy => {}
+                        DpKind::Reading => card.readings.push(Reading {
+                            endpoint: None,
+                            name,
+                            unit: None,
+                        }),
+                        DpKind::Switch | DpKind::Flag => card.controls.push(Control::Toggle {
+                            endpoint: None,
+                            name,
+                        }),
+                        DpKind::Number | DpKind::Enum => card.controls.push(Control::Number {
+                            endpoint: None,
+                            name,
+                            step: 1.0 / mapping.divisor,
+                        }),
+                    }
+                }
+            }
+        }
+
+        card
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::id;
+    use crate::device::{DeviceType, Endpoint};
+
+    #[test]
+    fn test_card_from_exposes() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        device.device_type = DeviceType::Router;
+        device.friendly_name = Some("Desk lamp".to_string());
+        device.endpoints = vec![Endpoint {
+            id: 1,
+            profile_id: 0x0104,
+            device_id: 0,
+            in_clusters: vec![id::ON_OFF, id::LEVEL_CONTROL, id::TEMPERATURE_MEASUREMENT],
+            out_clusters: Vec::new(),
+        }];
+        let card = device.card();
+        assert_eq!(card.title, "Desk lamp");
+        assert_eq!(
+            card.controls[1],
+            Control::Slider {
+                endpoint: Some(1),
+                name: "brightness".to_string(),
+                min: 1.0,
+                max: 100.0,
+                step: 1.0,
+                unit: Some("%"),
+            }
+        );
+        assert_eq!(card.controls.len(), 3);
+        assert_eq!(
+            serde_json::to_value(&card.readings).unwrap(),
+            serde_json::json!([{"endpoint": 1, "name": "temperature", "unit": "°C"}])
+        );
+
+        // Measurement data points aren't shown twice
+        let mut device = ZigbeeDevice::new([2; 8], 0x5678);
+        device.manufacturer = Some("_TZE200_ztc6ggyl".to_string());
+        let card = device.card();
+        assert_eq!(
+            card.controls,
+            vec![Control::Number {
+                endpoint: None,
+                name: "sensitivity".to_string(),
+                step: 1.0,
+            }]
+        );
+        assert_eq!(card.readings.len(), 2);
+    }
+}
@@ -1,5 +1,8 @@
 //! ZCL (Zigbee Cluster Library) definitions
 
+use crate::codec::{self, ZclValue};
+use std::sync::atomic::{AtomicU8, Ordering};
+
 /// Common ZCL cluster IDs
 pub mod id {
     // General Clusters
@@ -14,6 +17,9 @@ pub mod id {
     pub const LEVEL_CONTROL: u16 = 0x0008;
     pub const ALARMS: u16 = 0x0009;
     pub const TIME: u16 = 0x000A;
+    pub const MULTISTATE_INPUT: u16 = 0x0012;
+    pub const OTA_UPGRADE: u16 = 0x0019;
+    pub const POLL_CONTROL: u16 = 0x0020;
 
     // Lighting Clusters
     pub const COLOR_CONTROL: u16 = 0x0300;
@@ -27,6 +33,9 @@ pub mod id {
     pub const FLOW_MEASUREMENT: u16 = 0x0404;
     pub const HUMIDITY_MEASUREMENT: u16 = 0x0405;
     pub const OCCUPANCY_SENSING: u16 = 0x0406;
+    pub const CARBON_DIOXIDE_MEASUREMENT: u16 = 0x040D;
+    pub const PM25_MEASUREMENT: u16 = 0x042A;
+    pub const FORMALDEHYDE_MEASUREMENT: u16 = 0x042B;
 
     // Security Clusters
     pub const IAS_ZONE: u16 = 0x0500;
@@ -59,6 +68,69 @@ pub mod basic_attrs {
     pub const SW_BUILD_ID: u16 = 0x4000;
 }
 
+/// Thermostat cluster attributes
+pub mod thermostat_attrs {
+    pub const LOCAL_TEMPERATURE: u16 = 0x0000;
+    pub const OCCUPIED_COOLING_SETPOINT: u16 = 0x0011;
+    pub const OCCUPIED_HEATING_SETPOINT: u16 = 0x0012;
+    pub const SYSTEM_MODE: u16 = 0x001C;
+}
+
+/// Measurement cluster attributes (temperature, humidity, pressure...)
+pub mod measurement_attrs {
+    pub const MEASURED_VALUE: u16 = 0x0000;
+    pub const MIN_MEASURED_VALUE: u16 = 0x0001;
+    pub const MAX_MEASURED_VALUE: u16 = 0x0002;
+}
+
+/// Occupancy Sensing cluster attributes
+pub mod occupancy_attrs {
+    /// Bitmap8; bit 0 is set while occupied
+    pub const OCCUPANCY: u16 = 0x0000;
+    pub const OCCUPANCY_SENSOR_TYPE: u16 = 0x0001;
+}
+
+/// IAS Zone cluster attributes
+pub mod ias_zone_attrs {
+    pub const ZONE_STATE: u16 = 0x0000;
+    pub const ZONE_TYPE: u16 = 0x0001;
+    pub const ZONE_STATUS: u16 = 0x0002;
+    pub const IAS_CIE_ADDRESS: u16 = 0x0010;
+    pub const ZONE_ID: u16 = 0x0011;
+}
+
+/// Multistate Input cluster attributes
+pub mod multistate_attrs {
+    /// Uint16; Aqara switches report button presses as a press count
+    pub const PRESENT_VALUE: u16 = 0x0055;
+}
+
+/// Power Configuration cluster attributes
+pub mod power_config_attrs {
+    /// Remaining battery in half-percent steps (uint8, 200 = 100%)
+    pub const BATTERY_PERCENTAGE_REMAINING: u16 = 0x0021;
+}
+
+/// Electrical Measurement cluster attributes
+pub mod electrical_attrs {
+    pub const RMS_VOLTAGE: u16 = 0x0505;
+    pub const RMS_CURRENT: u16 = 0x0508;
+    pub const ACTIVE_POWER: u16 = 0x050B;
+    pub const AC_VOLTAGE_MULTIPLIER: u16 = 0x0600;
+    pub const AC_VOLTAGE_DIVISOR: u16 = 0x0601;
+    pub const AC_CURRENT_MULTIPLIER: u16 = 0x0602;
+    pub const AC_CURRENT_DIVISOR: u16 = 0x0603;
+    pub const AC_POWER_MULTIPLIER: u16 = 0x0604;
+    pub const AC_POWER_DIVISOR: u16 = 0x0605;
+}
+
+/// Metering cluster attributes
+pub mod metering_attrs {
+    pub const CURRENT_SUMMATION_DELIVERED: u16 = 0x0000;
+    pub const MULTIPLIER: u16 = 0x0301;
+    pub const DIVISOR: u16 = 0x0302;
+}
+
 /// On/Off cluster commands
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -155,8 +227,159 @@ pub enum GlobalCommand {
     DiscoverAttributesResponse = 0x0D,
 }
 
+/// Status of a ZCL command, as carried by a Default Response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZclStatus {
+    Success,
+    Failure,
+    NotAuthorized,
+    MalformedCommand,
+    UnsupportedClusterCommand,
+    UnsupportedGeneralCommand,
+    UnsupportedManufacturerCommand,
+    InvalidField,
+    UnsupportedAttribute,
+    InvalidValue,
+    ReadOnly,
+    InsufficientSpace,
+    NotFound,
+    InvalidDataType,
+    Timeout,
+    HardwareFailure,
+    SoftwareFailure,
+    UnsupportedCluster,
+    Other(u8),
+}
+
+impl ZclStatus {
+    #[must_use]
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => Self::Success,
+            0x01 => Self::Failure,
+            0x7E => Self::NotAuthorized,
+            0x80 => Self::MalformedCommand,
+            0x81 => Self::UnsupportedClusterCommand,
+            0x82 => Self::UnsupportedGeneralCommand,
+            0x83 => Self::UnsupportedManufacturerCommand,
+            0x85 => Self::InvalidField,
+            0x86 => Self::UnsupportedAttribute,
+            0x87 => Self::InvalidValue,
+            0x88 => Self::ReadOnly,
+            0x89 => Self::InsufficientSpace,
+            0x8B => Self::NotFound,
+            0x8D => Self::InvalidDataType,
+            0x94 => Self::Timeout,
+            0xC0 => Self::HardwareFailure,
+            0xC1 => Self::SoftwareFailure,
+            0xC3 => Self::UnsupportedCluster,
+            other => Self::Other(other),
+        }
+    }
+
+    /// Status byte as sent by the device
+    #[must_use]
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Success => 0x00,
+            Self::Failure => 0x01,
+            Self::NotAuthorized => 0x7E,
+            Self::MalformedCommand => 0x80,
+            Self::UnsupportedClusterCommand => 0x81,
+            Self::UnsupportedGeneralCommand => 0x82,
+            Self::UnsupportedManufacturerCommand => 0x83,
+            Self::InvalidField => 0x85,
+            Self::UnsupportedAttribute => 0x86,
+            Self::InvalidValue => 0x87,
+            Self::ReadOnly => 0x88,
+            Self::InsufficientSpace => 0x89,
+            Self::NotFound => 0x8B,
+            Self::InvalidDataType => 0x8D,
+            Self::Timeout => 0x94,
+            Self::HardwareFailure => 0xC0,
+            Self::SoftwareFailure => 0xC1,
+            Self::UnsupportedCluster => 0xC3,
+            Self::Other(code) => code,
+        }
+    }
+
+    #[must_use]
+    pub fn is_success(self) -> bool {
+        self == Self::Success
+    }
+
+    /// What the device complained about
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "the command failed",
+            Self::NotAuthorized => "not authorized",
+            Self::MalformedCommand => "the command was malformed",
+            Self::UnsupportedClusterCommand => "the device doesn't support this command",
+            Self::UnsupportedGeneralCommand => "the device doesn't support this general command",
+            Self::UnsupportedManufacturerCommand => {
+                "the device doesn't support this manufacturer command"
+            }
+            Self::InvalidField => "a field of the command is invalid",
+            Self::UnsupportedAttribute => "the device doesn't have this attribute",
+            Self::InvalidValue => "the value is out of range",
+            Self::ReadOnly => "the attribute is read-only",
+            Self::InsufficientSpace => "the device is out of space",
+            Self::NotFound => "not found",
+            Self::InvalidDataType => "the data type is wrong for the attribute",
+            Self::Timeout => "the operation timed out",
+            Self::HardwareFailure => "hardware failure",
+            Self::SoftwareFailure => "software failure",
+            Self::UnsupportedCluster => "the device doesn't have this cluster",
+            Self::Other(_) => "unknown status",
+        }
+    }
+}
+
+impl std::fmt::Display for ZclStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:#04x})", self.description(), self.code())
+    }
+}
+
+static NEXT_TSN: AtomicU8 = AtomicU8::new(1);
+
+/// Allocate a transaction sequence number for an outgoing ZCL frame
+///
+/// Responses and Default Responses carry the number of the command they
+/// answer, so every command in flight needs its own.
+pub(crate) fn next_tsn() -> u8 {
+    NEXT_TSN.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Status a response frame gives for the command sent with `transaction_seq`
+///
+/// A Default Response carries the status, as does a Write Attributes
+/// Response (one success byte, or a status per failed attribute); any
+/// other response to the command means it was accepted. Frames of other
+/// transactions yield `None`.
+#[must_use]
+pub fn command_status(response: &[u8], transaction_seq: u8) -> Option<ZclStatus> {
+    let frame = deconz_protocol::ZclFrame::parse(response).ok()?;
+    if frame.transaction_seq() != transaction_seq || !frame.is_from_server() {
+        return None;
+    }
+    if !frame.is_cluster_specific() && frame.command_id() == GlobalCommand::DefaultResponse as u8 {
+        // Command ID, status
+        return frame.payload().get(1).copied().map(ZclStatus::from_code);
+    }
+    if !frame.is_cluster_specific()
+        && frame.command_id() == GlobalCommand::WriteAttributesResponse as u8
+    {
+        // First record's status
+        return frame.payload().first().copied().map(ZclStatus::from_code);
+    }
+    Some(ZclStatus::Success)
+}
+
 /// ZCL data types
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum DataType {
     NoData = 0x00,
@@ -173,17 +396,338 @@ pub enum DataType {
     Uint16 = 0x21,
     Uint24 = 0x22,
     Uint32 = 0x23,
+    Uint40 = 0x24,
+    Uint48 = 0x25,
+    Uint56 = 0x26,
+    Uint64 = 0x27,
     Int8 = 0x28,
     Int16 = 0x29,
     Int24 = 0x2A,
     Int32 = 0x2B,
+    Int40 = 0x2C,
+    Int48 = 0x2D,
     Enum8 = 0x30,
     Enum16 = 0x31,
     Float16 = 0x38,
     Float32 = 0x39,
     Float64 = 0x3A,
+    OctetString = 0x41,
     String = 0x42,
     Array = 0x48,
     Struct = 0x4C,
     Ieee = 0xF0,
 }
+
+impl DataType {
+    /// Data type of a type code
+    #[must_use]
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0x00 => Self::NoData,
+            0x08 => Self::Data8,
+            0x09 => Self::Data16,
+            0x0A => Self::Data24,
+            0x0B => Self::Data32,
+            0x10 => Self::Boolean,
+            0x18 => Self::Bitmap8,
+            0x19 => Self::Bitmap16,
+            0x1A => Self::Bitmap24,
+            0x1B => Self::Bitmap32,
+            0x20 => Self::Uint8,
+            0x21 => Self::Uint16,
+            0x22 => Self::Uint24,
+            0x23 => Self::Uint32,
+            0x24 => Self::Uint40,
+            0x25 => Self::Uint48,
+            0x26 => Self::Uint56,
+            0x27 => Self::Uint64,
+            0x28 => Self::Int8,
+            0x29 => Self::Int16,
+            0x2A => Self::Int24,
+            0x2B => Self::Int32,
+            0x2C => Self::Int40,
+            0x2D => Self::Int48,
+            0x30 => Self::Enum8,
+            0x31 => Self::Enum16,
+            0x38 => Self::Float16,
+            0x39 => Self::Float32,
+            0x3A => Self::Float64,
+            0x41 => Self::OctetString,
+            0x42 => Self::String,
+            0x48 => Self::Array,
+            0x4C => Self::Struct,
+            0xF0 => Self::Ieee,
+            _ => return None,
+        })
+    }
+
+    /// Data type by its lowercase name (`uint8`, `enum8`, `bool`, `string`)
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "bool" | "boolean" => Self::Boolean,
+            "bitmap8" => Self::Bitmap8,
+            "bitmap16" => Self::Bitmap16,
+            "bitmap24" => Self::Bitmap24,
+            "bitmap32" => Self::Bitmap32,
+            "uint8" => Self::Uint8,
+            "uint16" => Self::Uint16,
+            "uint24" => Self::Uint24,
+            "uint32" => Self::Uint32,
+            "uint40" => Self::Uint40,
+            "uint48" => Self::Uint48,
+            "int8" => Self::Int8,
+            "int16" => Self::Int16,
+            "int24" => Self::Int24,
+            "int32" => Self::Int32,
+            "int40" => Self::Int40,
+            "int48" => Self::Int48,
+            "enum8" => Self::Enum8,
+            "enum16" => Self::Enum16,
+            "float16" | "semi" => Self::Float16,
+            "float32" | "single" => Self::Float32,
+            "float64" | "double" => Self::Float64,
+            "octet_string" => Self::OctetString,
+            "string" => Self::String,
+            _ => return None,
+        })
+    }
+
+    /// Size of a fixed-length value of ZCL type `data_type` (`None` for
+    /// strings, collections and unknown types)
+    #[must_use]
+    pub fn fixed_size(data_type: u8) -> Option<usize> {
+        match data_type {
+            0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 => Some(1),
+            0x09 | 0x19 | 0x21 | 0x29 | 0x31 | 0x38 => Some(2),
+            0x0A | 0x1A | 0x22 | 0x2A => Some(3),
+            0x0B | 0x1B | 0x23 | 0x2B | 0x39 => Some(4),
+            0x0C | 0x1C | 0x24 | 0x2C => Some(5),
+            0x0D | 0x1D | 0x25 | 0x2D => Some(6),
+            0x0E | 0x1E | 0x26 | 0x2E => Some(7),
+            0x0F | 0x1F | 0x27 | 0x2F | 0x3A | 0xF0 => Some(8),
+            _ => None,
+        }
+    }
+}
+
+/// One attribute value from a Report Attributes or Read Attributes Response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeValue {
+    pub id: u16,
+    pub data_type: u8,
+    /// Raw little-endian value (string contents without the length byte)
+    pub value: Vec<u8>,
+}
+
+impl AttributeValue {
+    /// Value as an unsigned integer (bitmaps, enums, booleans, uintN)
+    #[must_use]
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.is_empty() || self.value.len() > 4 {
+            return None;
+        }
+        let mut bytes = [0u8; 4];
+        bytes[..self.value.len()].copy_from_slice(&self.value);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    /// Value as a wide unsigned integer (uint40/48/56/64)
+    #[must_use]
+    pub fn as_u64(&self) -> Option<u64> {
+        if self.value.is_empty() || self.value.len() > 8 {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..self.value.len()].copy_from_slice(&self.value);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    /// Value as a floating point number (half, single or double precision)
+    ///
+    /// NaN, which devices use as the invalid-value marker, yields `None`.
+    #[must_use]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.typed()? {
+            value @ (ZclValue::Float16(_) | ZclValue::Float32(_) | ZclValue::Float64(_)) => {
+                value.as_f64()
+            }
+            _ => None,
+        }
+    }
+
+    /// The value decoded for its data type
+    #[must_use]
+    pub fn typed(&self) -> Option<ZclValue> {
+        match self.data_type {
+            0x41 => Some(ZclValue::OctetString(self.value.clone())),
+            0x42 => Some(ZclValue::String(
+                String::from_utf8_lossy(&self.value).into_owned(),
+            )),
+            _ => ZclValue::decode(self.data_type, &self.value)
+                .ok()
+                .map(|(value, _)| value),
+        }
+    }
+
+    /// Value as a sign-extended integer (intN)
+    #[must_use]
+    pub fn as_i32(&self) -> Option<i32> {
+        let len = self.value.len();
+        let raw = self.as_u32()?;
+        let shift = 32 - 8 * u32::try_from(len).ok()?;
+        #[allow(clippy::cast_possible_wrap)]
+        Some(((raw << shift) as i32) >> shift)
+    }
+}
+
+/// Read one attribute value of `data_type` from the front of `data`,
+/// returning it and the number of bytes consumed
+fn take_value(data_type: u8, data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let size = codec::encoded_len(data_type, data)?;
+    let value = match data_type {
+        // Octet and character strings without their length byte
+        0x41 | 0x42 => &data[1..size],
+        _ => &data[..size],
+    };
+    Some((value.to_vec(), size))
+}
+
+/// Parse a Report Attributes payload (attribute ID, type, value records)
+///
+/// Parsing stops at the first value whose type can't be sized; the
+/// attributes before it are still returned.
+#[must_use]
+pub fn parse_attribute_report(payload: &[u8]) -> Vec<AttributeValue> {
+    let mut attributes = Vec::new();
+    let mut rest = payload;
+    while rest.len() >= 3 {
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        let data_type = rest[2];
+        let Some((value, used)) = take_value(data_type, &rest[3..]) else {
+            break;
+        };
+        attributes.push(AttributeValue {
+            id,
+            data_type,
+            value,
+        });
+        rest = &rest[3 + used..];
+    }
+    attributes
+}
+
+/// Parse a Read Attributes Response payload, skipping unsupported attributes
+#[must_use]
+pub fn parse_read_attributes_response(payload: &[u8]) -> Vec<AttributeValue> {
+    let mut attributes = Vec::new();
+    let mut rest = payload;
+    while rest.len() >= 3 {
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        if rest[2] != 0x00 {
+            // Non-success status has no type or value
+            rest = &rest[3..];
+            continue;
+        }
+        let Some(&data_type) = rest.get(3) else {
+            break;
+        };
+        let Some((value, used)) = take_value(data_type, &rest[4..]) else {
+            break;
+        };
+        attributes.push(AttributeValue {
+            id,
+            data_type,
+            value,
+        });
+        rest = &rest[4 + used..];
+    }
+    attributes
+}
+
+/// Attribute values carried by a global ZCL frame, if it is a report or a
+/// read response
+#[must_use]
+pub fn attribute_values(command_id: u8, payload: &[u8]) -> Vec<AttributeValue> {
+    match command_id {
+        x if x == GlobalCommand::ReportAttributes as u8 => parse_attribute_report(payload),
+        x if x == GlobalCommand::ReadAttributesResponse as u8 => {
+            parse_read_attributes_response(payload)
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_status() {
+        // Default Response to command 0x01 with UNSUP_CLUSTER_COMMAND
+        let rejected = [0x18, 0x07, 0x0B, 0x01, 0x81];
+        assert_eq!(
+            command_status(&rejected, 7),
+            Some(ZclStatus::UnsupportedClusterCommand)
+        );
+        assert_eq!(command_status(&rejected, 8), None);
+        // Default Response with SUCCESS
+        assert_eq!(
+            command_status(&[0x18, 0x07, 0x0B, 0x01, 0x00], 7),
+            Some(ZclStatus::Success)
+        );
+        // A command from a client with the same sequence number isn't a response
+        assert_eq!(command_status(&[0x01, 0x07, 0x02], 7), None);
+        assert_eq!(
+            ZclStatus::UnsupportedClusterCommand.to_string(),
+            "the device doesn't support this command (0x81)"
+        );
+    }
+
+    #[test]
+    fn test_parse_attribute_report() {
+        // Temperature (int16 -5.00) followed by a character string
+        let attributes = parse_attribute_report(&[
+            0x00, 0x00, 0x29, 0x0C, 0xFE, 0x05, 0x00, 0x42, 0x02, b'h', b'i',
+        ]);
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes[0].as_i32(), Some(-500));
+        assert_eq!(attributes[1].value, b"hi");
+    }
+
+    #[test]
+    fn test_report_steps_over_collections() {
+        // Array of uint16, a structure (uint8, string), then a float16
+        let attributes = parse_attribute_report(&[
+            0x01, 0x00, 0x48, 0x21, 0x01, 0x00, 0x34, 0x12, //
+            0x02, 0x00, 0x4C, 0x02, 0x00, 0x20, 0x07, 0x42, 0x01, b'x', //
+            0x03, 0x00, 0x38, 0x00, 0x3E,
+        ]);
+        assert_eq!(attributes.len(), 3);
+        assert_eq!(
+            attributes[0].typed(),
+            Some(ZclValue::Array {
+                element_type: DataType::Uint16,
+                items: vec![ZclValue::Uint16(0x1234)],
+            })
+        );
+        assert_eq!(attributes[1].typed(), None);
+        assert_eq!(attributes[2].as_f64(), Some(1.5));
+    }
+
+    #[test]
+    fn test_parse_read_attributes_response() {
+        // Unsupported attribute 0x0001, then occupancy bitmap8 = 1
+        let attributes =
+            parse_read_attributes_response(&[0x01, 0x00, 0x86, 0x00, 0x00, 0x00, 0x18, 0x01]);
+        assert_eq!(
+            attributes,
+            vec![AttributeValue {
+                id: 0x0000,
+                data_type: 0x18,
+                value: vec![0x01],
+            }]
+        );
+        assert_eq!(attributes[0].as_u32(), Some(1));
+    }
+}
@@ -0,0 +1,624 @@
+//! ZCL data type encoding
+//!
+//! Attribute values travel as a data type code followed by the value in
+//! little-endian order: fixed-size numbers, strings with a length byte and
+//! arrays with an element type and count. [`ZclValue`] is a value of a
+//! known type, decoded from a report or read response and encoded for
+//! writes; [`encoded_len`] sizes any value without decoding it, so parsing
+//! can step over types it has no use for.
+
+use crate::cluster::DataType;
+use thiserror::Error;
+
+/// Length byte of a string that holds no value
+const INVALID_LENGTH: u8 = 0xFF;
+/// Element count of an array that holds no value
+const INVALID_COUNT: u16 = 0xFFFF;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("value is truncated")]
+    Truncated,
+    #[error("unsupported data type {0:#04x}")]
+    UnsupportedType(u8),
+}
+
+/// An attribute value typed by its ZCL data type
+///
+/// 24-, 40- and 48-bit values keep their low bytes; strings are cut to 254
+/// bytes, the most a ZCL string can hold.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZclValue {
+    Bool(bool),
+    Bitmap8(u8),
+    Bitmap16(u16),
+    Bitmap24(u32),
+    Bitmap32(u32),
+    Uint8(u8),
+    Uint16(u16),
+    Uint24(u32),
+    Uint32(u32),
+    Uint40(u64),
+    Uint48(u64),
+    Int8(i8),
+    Int16(i16),
+    Int24(i32),
+    Int32(i32),
+    Int40(i64),
+    Int48(i64),
+    Enum8(u8),
+    Enum16(u16),
+    /// Half precision, widened to `f32`
+    Float16(f32),
+    Float32(f32),
+    Float64(f64),
+    OctetString(Vec<u8>),
+    String(String),
+    /// Items all of `element_type`
+    Array {
+        element_type: DataType,
+        items: Vec<ZclValue>,
+    },
+    Ieee([u8; 8]),
+}
+
+impl ZclValue {
+    #[must_use]
+    pub fn data_type(&self) -> DataType {
+        match self {
+            ZclValue::Bool(_) => DataType::Boolean,
+            ZclValue::Bitmap8(_) => DataType::Bitmap8,
+            ZclValue::Bitmap16(_) => DataType::Bitmap16,
+            ZclValue::Bitmap24(_) => DataType::Bitmap24,
+            ZclValue::Bitmap32(_) => DataType::Bitmap32,
+            ZclValue::Uint8(_) => DataType::Uint8,
+            ZclValue::Uint16(_) => DataType::Uint16,
+            ZclValue::Uint24(_) => DataType::Uint24,
+            ZclValue::Uint32(_) => DataType::Uint32,
+            ZclValue::Uint40(_) => DataType::Uint40,
+            ZclValue::Uint48(_) => DataType::Uint48,
+            ZclValue::Int8(_) => DataType::Int8,
+            ZclValue::Int16(_) => DataType::Int16,
+            ZclValue::Int24(_) => DataType::Int24,
+            ZclValue::Int32(_) => DataType::Int32,
+            ZclValue::Int40(_) => DataType::Int40,
+            ZclValue::Int48(_) => DataType::Int48,
+            ZclValue::Enum8(_) => DataType::Enum8,
+            ZclValue::Enum16(_) => DataType::Enum16,
+            ZclValue::Float16(_) => DataType::Float16,
+            ZclValue::Float32(_) => DataType::Float32,
+            ZclValue::Float64(_) => DataType::Float64,
+            ZclValue::OctetString(_) => DataType::OctetString,
+            ZclValue::String(_) => DataType::String,
+            ZclValue::Array { .. } => DataType::Array,
+            ZclValue::Ieee(_) => DataType::Ieee,
+        }
+    }
+
+    /// Little-endian encoding, with the length byte for strings and the
+    /// element type and count for arrays
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let length_prefixed = |bytes: &[u8]| {
+            let bytes = &bytes[..bytes.len().min(254)];
+            let mut data = vec![u8::try_from(bytes.len()).unwrap_or(254)];
+            data.extend_from_slice(bytes);
+            data
+        };
+        match self {
+            ZclValue::Bool(value) => vec![u8::from(*value)],
+            ZclValue::Bitmap8(value) | ZclValue::Uint8(value) | ZclValue::Enum8(value) => {
+                vec![*value]
+            }
+            ZclValue::Bitmap16(value) | ZclValue::Uint16(value) | ZclValue::Enum16(value) => {
+                value.to_le_bytes().to_vec()
+            }
+            ZclValue::Bitmap24(value) | ZclValue::Uint24(value) => {
+                value.to_le_bytes()[..3].to_vec()
+            }
+            ZclValue::Bitmap32(value) | ZclValue::Uint32(value) => value.to_le_bytes().to_vec(),
+            ZclValue::Uint40(value) => value.to_le_bytes()[..5].to_vec(),
+            ZclValue::Uint48(value) => value.to_le_bytes()[..6].to_vec(),
+            ZclValue::Int8(value) => value.to_le_bytes().to_vec(),
+            ZclValue::Int16(value) => value.to_le_bytes().to_vec(),
+            ZclValue::Int24(value) => value.to_le_bytes()[..3].to_vec(),
+            ZclValue::Int32(value) => value.to_le_bytes().to_vec(),
+            ZclValue::Int40(value) => value.to_le_bytes()[..5].to_vec(),
+            ZclValue::Int48(value) => value.to_le_bytes()[..6].to_vec(),
+            ZclValue::Float16(value) => f32_to_f16(*value).to_le_bytes().to_vec(),
+            ZclValue::Float32(value) => value.to_le_bytes().to_vec(),
+            ZclValue::Float64(value) => value.to_le_bytes().to_vec(),
+            ZclValue::OctetString(bytes) => length_prefixed(bytes),
+            ZclValue::String(text) => length_prefixed(text.as_bytes()),
+            ZclValue::Array {
+                element_type,
+                items,
+            } => {
+                let count = u16::try_from(items.len()).unwrap_or(INVALID_COUNT - 1);
+                let mut data = vec![*element_type as u8];
+                data.extend_from_slice(&count.to_le_bytes());
+                for item in items.iter().take(usize::from(count)) {
+                    data.extend(item.encode());
+                }
+                data
+            }
+            ZclValue::Ieee(ieee) => ieee.to_vec(),
+        }
+    }
+
+    /// Decode a value of type `data_type` from the front of `data`,
+    /// returning it and the number of bytes it took
+    ///
+    /// Strings and arrays marked invalid (length 0xFF, count 0xFFFF) decode
+    /// as empty.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn decode(data_type: u8, data: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let unsupported = DecodeError::UnsupportedType(data_type);
+        let kind = DataType::from_code(data_type).ok_or(unsupported.clone())?;
+        match kind {
+            DataType::OctetString | DataType::String => {
+                let len = *data.first().ok_or(DecodeError::Truncated)?;
+                let bytes = if len == INVALID_LENGTH {
+                    &[]
+                } else {
+                    data.get(1..=usize::from(len))
+                        .ok_or(DecodeError::Truncated)?
+                };
+                let value = if kind == DataType::String {
+                    ZclValue::String(String::from_utf8_lossy(bytes).into_owned())
+                } else {
+                    ZclValue::OctetString(bytes.to_vec())
+                };
+                return Ok((value, 1 + bytes.len()));
+            }
+            DataType::Array => {
+                let (&element_code, count) = data
+                    .first()
+                    .zip(data.get(1..3))
+                    .ok_or(DecodeError::Truncated)?;
+                let element_type = DataType::from_code(element_code)
+                    .ok_or(DecodeError::UnsupportedType(element_code))?;
+                let count = u16::from_le_bytes([count[0], count[1]]);
+                let mut used = 3;
+                let mut items = Vec::new();
+                if count != INVALID_COUNT {
+                    for _ in 0..count {
+                        let (item, size) = Self::decode(element_code, &data[used..])?;
+                        items.push(item);
+                        used += size;
+                    }
+                }
+                let value = ZclValue::Array {
+                    element_type,
+                    items,
+                };
+                return Ok((value, used));
+            }
+            _ => {}
+        }
+
+        let size = DataType::fixed_size(data_type).ok_or(unsupported.clone())?;
+        let bytes = data.get(..size).ok_or(DecodeError::Truncated)?;
+        let mut le = [0u8; 8];
+        le[..size].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(le);
+        // Sign-extend from the value's width
+        let shift = 64 - 8 * size as u32;
+        #[allow(clippy::cast_possible_wrap)]
+        let signed = ((raw << shift) as i64) >> shift;
+
+        // Widths come from the type, so the narrowing below is lossless
+        #[allow(clippy::cast_possible_truncation)]
+        let value = match kind {
+            DataType::Boolean => ZclValue::Bool(raw != 0),
+            DataType::Bitmap8 => ZclValue::Bitmap8(raw as u8),
+            DataType::Bitmap16 => ZclValue::Bitmap16(raw as u16),
+            DataType::Bitmap24 => ZclValue::Bitmap24(raw as u32),
+            DataType::Bitmap32 => ZclValue::Bitmap32(raw as u32),
+            DataType::Uint8 => ZclValue::Uint8(raw as u8),
+            DataType::Uint16 => ZclValue::Uint16(raw as u16),
+            DataType::Uint24 => ZclValue::Uint24(raw as u32),
+            DataType::Uint32 => ZclValue::Uint32(raw as u32),
+            DataType::Uint40 => ZclValue::Uint40(raw),
+            DataType::Uint48 => ZclValue::Uint48(raw),
+            DataType::Int8 => ZclValue::Int8(signed as i8),
+            DataType::Int16 => ZclValue::Int16(signed as i16),
+            DataType::Int24 => ZclValue::Int24(signed as i32),
+            DataType::Int32 => ZclValue::Int32(signed as i32),
+            DataType::Int40 => ZclValue::Int40(signed),
+            DataType::Int48 => ZclValue::Int48(signed),
+            DataType::Enum8 => ZclValue::Enum8(raw as u8),
+            DataType::Enum16 => ZclValue::Enum16(raw as u16),
+            DataType::Float16 => ZclValue::Float16(f16_to_f32(raw as u16)),
+            DataType::Float32 => ZclValue::Float32(f32::from_bits(raw as u32)),
+            DataType::Float64 => ZclValue::Float64(f64::from_bits(raw)),
+            DataType::Ieee => ZclValue::Ieee(le),
+            _ => return Err(unsupported),
+        };
+        Ok((value, size))
+    }
+
+    /// Value as a number, if it is one (booleans count as 0 and 1)
+    ///
+    /// NaN, which devices use as the invalid-value marker, yields `None`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_f64(&self) -> Option<f64> {
+        let value = match *self {
+            ZclValue::Bool(value) => f64::from(u8::from(value)),
+            ZclValue::Bitmap8(value) | ZclValue::Uint8(value) | ZclValue::Enum8(value) => {
+                f64::from(value)
+            }
+            ZclValue::Bitmap16(value) | ZclValue::Uint16(value) | ZclValue::Enum16(value) => {
+                f64::from(value)
+            }
+            ZclValue::Bitmap24(value)
+            | ZclValue::Bitmap32(value)
+            | ZclValue::Uint24(value)
+            | ZclValue::Uint32(value) => f64::from(value),
+            ZclValue::Uint40(value) | ZclValue::Uint48(value) => value as f64,
+            ZclValue::Int8(value) => f64::from(value),
+            ZclValue::Int16(value) => f64::from(value),
+            ZclValue::Int24(value) | ZclValue::Int32(value) => f64::from(value),
+            ZclValue::Int40(value) | ZclValue::Int48(value) => value as f64,
+            ZclValue::Float16(value) | ZclValue::Float32(value) => f64::from(value),
+            ZclValue::Float64(value) => value,
+            ZclValue::OctetString(_)
+            | ZclValue::String(_)
+            | ZclValue::Array { .. }
+            | ZclValue::Ieee(_) => return None,
+        };
+        (!value.is_nan()).then_some(value)
+    }
+
+    /// Value of `data_type` from JSON: a boolean, a number in range for
+    /// the type, or a string (octet strings as hex)
+    #[allow(clippy::missing_errors_doc)]
+    pub fn from_json(data_type: DataType, value: &serde_json::Value) -> Result<Self, String> {
+        let unsigned = |max: u64| {
+            value
+                .as_u64()
+                .filter(|v| *v <= max)
+                .ok_or_else(|| format!("{data_type:?} needs an integer from 0 to {max}"))
+        };
+        let signed = |min: i64, max: i64| {
+            value
+                .as_i64()
+                .filter(|v| (min..=max).contains(v))
+                .ok_or_else(|| format!("{data_type:?} needs an integer from {min} to {max}"))
+        };
+        let float = || {
+            value
+                .as_f64()
+                .ok_or_else(|| format!("{data_type:?} needs a number"))
+        };
+        let text = || {
+            value
+                .as_str()
+                .filter(|s| s.len() <= 254)
+                .ok_or_else(|| format!("{data_type:?} needs a string of at most 254 bytes"))
+        };
+        // The ranges above make the narrowing conversions below lossless
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Ok(match data_type {
+            DataType::Boolean => ZclValue::Bool(
+                value
+                    .as_bool()
+                    .ok_or_else(|| "Boolean needs true or false".to_string())?,
+            ),
+            DataType::Bitmap8 => ZclValue::Bitmap8(unsigned(0xFF)? as u8),
+            DataType::Bitmap16 => ZclValue::Bitmap16(unsigned(0xFFFF)? as u16),
+            DataType::Bitmap24 => ZclValue::Bitmap24(unsigned(0xFF_FFFF)? as u32),
+            DataType::Bitmap32 => ZclValue::Bitmap32(unsigned(0xFFFF_FFFF)? as u32),
+            DataType::Uint8 => ZclValue::Uint8(unsigned(0xFF)? as u8),
+            DataType::Uint16 => ZclValue::Uint16(unsigned(0xFFFF)? as u16),
+            DataType::Uint24 => ZclValue::Uint24(unsigned(0xFF_FFFF)? as u32),
+            DataType::Uint32 => ZclValue::Uint32(unsigned(0xFFFF_FFFF)? as u32),
+            DataType::Uint40 => ZclValue::Uint40(unsigned(0xFF_FFFF_FFFF)?),
+            DataType::Uint48 => ZclValue::Uint48(unsigned(0xFFFF_FFFF_FFFF)?),
+            DataType::Int8 => ZclValue::Int8(signed(-0x80, 0x7F)? as i8),
+            DataType::Int16 => ZclValue::Int16(signed(-0x8000, 0x7FFF)? as i16),
+            DataType::Int24 => ZclValue::Int24(signed(-0x80_0000, 0x7F_FFFF)? as i32),
+            DataType::Int32 => ZclValue::Int32(signed(-0x8000_0000, 0x7FFF_FFFF)? as i32),
+            DataType::Int40 => ZclValue::Int40(signed(-0x80_0000_0000, 0x7F_FFFF_FFFF)?),
+            DataType::Int48 => ZclValue::Int48(signed(-0x8000_0000_0000, 0x7FFF_FFFF_FFFF)?),
+            DataType::Enum8 => ZclValue::Enum8(unsigned(0xFF)? as u8),
+            DataType::Enum16 => ZclValue::Enum16(unsigned(0xFFFF)? as u16),
+            DataType::Float16 => ZclValue::Float16(float()? as f32),
+            DataType::Float32 => ZclValue::Float32(float()? as f32),
+            DataType::Float64 => ZclValue::Float64(float()?),
+            DataType::OctetString => {
+                let hex = text()?;
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        hex.get(i..i + 2)
+                            .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    })
+                    .collect::<Option<Vec<u8>>>()
+                    .filter(|bytes| bytes.len() <= 254)
+                    .ok_or_else(|| "OctetString needs hex bytes, e.g. \"0a1b\"".to_string())?;
+                ZclValue::OctetString(bytes)
+            }
+            DataType::String => ZclValue::String(text()?.to_string()),
+            other => return Err(format!("writing {other:?} values isn't supported")),
+        })
+    }
+}
+
+/// Size of the value of type `data_type` at the front of `data`, length
+/// bytes included, for any type with a defined layout
+#[must_use]
+pub fn encoded_len(data_type: u8, data: &[u8]) -> Option<usize> {
+    if let Some(size) = DataType::fixed_size(data_type) {
+        return (data.len() >= size).then_some(size);
+    }
+    let len = match data_type {
+        // Octet and character strings
+        0x41 | 0x42 => match *data.first()? {
+            INVALID_LENGTH => 1,
+            len => 1 + usize::from(len),
+        },
+        // Long octet and character strings
+        0x43 | 0x44 => match u16::from_le_bytes([*data.first()?, *data.get(1)?]) {
+            0xFFFF => 2,
+            len => 2 + usize::from(len),
+        },
+        // Array, set and bag: element type, count, elements
+        0x48 | 0x50 | 0x51 => {
+            let element_type = *data.first()?;
+            let count = u16::from_le_bytes([*data.get(1)?, *data.get(2)?]);
+            let mut used = 3;
+            if count != INVALID_COUNT {
+                for _ in 0..count {
+                    used += encoded_len(element_type, data.get(used..)?)?;
+                }
+            }
+            used
+        }
+        // Structure: count, then type and value of each member
+        0x4C => {
+            let count = u16::from_le_bytes([*data.first()?, *data.get(1)?]);
+            let mut used = 2;
+            if count != INVALID_COUNT {
+                for _ in 0..count {
+                    let member_type = *data.get(used)?;
+                    used += 1 + encoded_len(member_type, data.get(used + 1..)?)?;
+                }
+            }
+            used
+        }
+        _ => return None,
+    };
+    (data.len() >= len).then_some(len)
+}
+
+/// Half-precision bits to `f32`
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1F);
+    let fraction = f32::from(bits & 0x03FF);
+    match exponent {
+        0 => sign * fraction * 2f32.powi(-24),
+        0x1F if fraction == 0.0 => sign * f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => sign * (1.0 + fraction / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// `f32` to half-precision bits, rounding to the nearest value and
+/// saturating to infinity
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = u16::try_from((bits >> 16) & 0x8000).unwrap_or(0);
+    if value.is_nan() {
+        return 0x7E00;
+    }
+    let magnitude = value.abs();
+    if magnitude >= 65520.0 {
+        return sign | 0x7C00;
+    }
+    if magnitude < 2f32.powi(-14) {
+        // Subnormal: multiples of 2^-24
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        return sign | (magnitude * 2f32.powi(24)).round() as u16;
+    }
+    let exponent = magnitude.log2().floor();
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let fraction = ((magnitude / 2f32.powf(exponent) - 1.0) * 1024.0).round() as u16;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let exponent = (exponent as i32 + 15) as u16;
+    // A fraction rounded up to 1024 carries into the exponent
+    sign | ((exponent << 10) + fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(data_type: u8, data: &[u8]) -> ZclValue {
+        let (value, used) = ZclValue::decode(data_type, data).unwrap();
+        assert_eq!(used, data.len(), "{value:?} used {used} bytes");
+        assert_eq!(encoded_len(data_type, data), Some(used));
+        value
+    }
+
+    #[test]
+    fn test_round_trip_captured_values() {
+        // Values as sent by devices, decoded and encoded back unchanged
+        let cases: &[(u8, &[u8], ZclValue)] = &[
+            (0x10, &[0x01], ZclValue::Bool(true)),
+            (0x18, &[0x01], ZclValue::Bitmap8(1)), // occupancy
+            (0x19, &[0x00, 0x80], ZclValue::Bitmap16(0x8000)),
+            (0x1A, &[0x01, 0x02, 0x03], ZclValue::Bitmap24(0x03_0201)),
+            (0x1B, &[0x0F, 0, 0, 0], ZclValue::Bitmap32(0x0F)),
+            (0x20, &[0xC8], ZclValue::Uint8(200)), // battery 100%
+            (0x21, &[0xA0, 0x11], ZclValue::Uint16(4512)),
+            (0x22, &[0x10, 0x27, 0x00], ZclValue::Uint24(10_000)),
+            (0x23, &[0x40, 0x42, 0x0F, 0x00], ZclValue::Uint32(1_000_000)),
+            (
+                0x24,
+                &[0x01, 0, 0, 0, 0x01],
+                ZclValue::Uint40(0x01_0000_0001),
+            ),
+            (
+                0x25,
+                &[0x39, 0x30, 0, 0, 0, 0], // metering summation 12345
+                ZclValue::Uint48(12_345),
+            ),
+            (0x28, &[0xFE], ZclValue::Int8(-2)),
+            (0x29, &[0x0C, 0xFE], ZclValue::Int16(-500)), // -5.00 °C
+            (0x2A, &[0xFE, 0xFF, 0xFF], ZclValue::Int24(-2)),
+            (0x2B, &[0x00, 0x00, 0x00, 0x80], ZclValue::Int32(i32::MIN)),
+            (0x2C, &[0xFF; 5], ZclValue::Int40(-1)),
+            (
+                0x2D,
+                &[0x00, 0x00, 0x00, 0x00, 0x00, 0x80],
+                ZclValue::Int48(-0x8000_0000_0000),
+            ),
+            (0x30, &[0x02], ZclValue::Enum8(2)), // StartUpOnOff: toggle
+            (0x31, &[0x01, 0x01], ZclValue::Enum16(0x0101)),
+            (0x38, &[0x00, 0x3C], ZclValue::Float16(1.0)),
+            (0x38, &[0x00, 0xC0], ZclValue::Float16(-2.0)),
+            (0x38, &[0xFF, 0x7B], ZclValue::Float16(65504.0)),
+            (0x38, &[0x01, 0x00], ZclValue::Float16(2f32.powi(-24))),
+            (0x39, &[0x00, 0x00, 0xC8, 0x41], ZclValue::Float32(25.0)), // PM2.5
+            (
+                0x3A,
+                &[0, 0, 0, 0, 0, 0, 0xF8, 0x3F],
+                ZclValue::Float64(1.5),
+            ),
+            (
+                0x41,
+                &[0x02, 0x0A, 0x1B],
+                ZclValue::OctetString(vec![0x0A, 0x1B]),
+            ),
+            (
+                0x42,
+                &[
+                    0x0E, b'l', b'u', b'm', b'i', b'.', b's', b'e', b'n', b's', b'o', b'r', b'_',
+                    b'h', b't',
+                ],
+                ZclValue::String("lumi.sensor_ht".to_string()),
+            ),
+            (
+                0x48,
+                &[0x21, 0x02, 0x00, 0x34, 0x12, 0x78, 0x56],
+                ZclValue::Array {
+                    element_type: DataType::Uint16,
+                    items: vec![ZclValue::Uint16(0x1234), ZclValue::Uint16(0x5678)],
+                },
+            ),
+            (
+                0x48,
+                &[0x42, 0x02, 0x00, 0x01, b'a', 0x00],
+                ZclValue::Array {
+                    element_type: DataType::String,
+                    items: vec![
+                        ZclValue::String("a".to_string()),
+                        ZclValue::String(String::new()),
+                    ],
+                },
+            ),
+            (
+                0xF0,
+                &[1, 2, 3, 4, 5, 6, 7, 8],
+                ZclValue::Ieee([1, 2, 3, 4, 5, 6, 7, 8]),
+            ),
+        ];
+        for (data_type, data, value) in cases {
+            assert_eq!(&decoded(*data_type, data), value);
+            assert_eq!(value.data_type() as u8, *data_type);
+            assert_eq!(&value.encode(), data, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_and_malformed_values() {
+        // Unset string and array
+        assert_eq!(
+            ZclValue::decode(0x42, &[0xFF, 0x00]).unwrap(),
+            (ZclValue::String(String::new()), 1)
+        );
+        assert_eq!(
+            ZclValue::decode(0x48, &[0x20, 0xFF, 0xFF]).unwrap(),
+            (
+                ZclValue::Array {
+                    element_type: DataType::Uint8,
+                    items: vec![]
+                },
+                3
+            )
+        );
+        // Float16 NaN and infinity
+        assert!(decoded(0x38, &[0x00, 0x7E]).as_f64().is_none());
+        assert_eq!(
+            decoded(0x38, &[0x00, 0xFC]),
+            ZclValue::Float16(f32::NEG_INFINITY)
+        );
+        assert_eq!(ZclValue::Float16(1e6).encode(), vec![0x00, 0x7C]);
+        // Rounds to the nearest half: 0.1 is 0x2E66
+        assert_eq!(ZclValue::Float16(0.1).encode(), vec![0x66, 0x2E]);
+
+        assert_eq!(ZclValue::decode(0x21, &[0x01]), Err(DecodeError::Truncated));
+        assert_eq!(
+            ZclValue::decode(0x42, &[0x05, b'a']),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            ZclValue::decode(0x48, &[0x21, 0x02, 0x00, 0x34, 0x12]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            ZclValue::decode(0x4C, &[0x00, 0x00]),
+            Err(DecodeError::UnsupportedType(0x4C))
+        );
+        assert_eq!(
+            ZclValue::decode(0xE1, &[0x00]),
+            Err(DecodeError::UnsupportedType(0xE1))
+        );
+    }
+
+    #[test]
+    fn test_encoded_len_of_undecoded_types() {
+        // Structure of a uint8 and a string (Xiaomi-style), long string, set
+        assert_eq!(
+            encoded_len(0x4C, &[0x02, 0x00, 0x20, 0x07, 0x42, 0x01, b'x', 0xAA]),
+            Some(7)
+        );
+        assert_eq!(encoded_len(0x44, &[0x02, 0x00, b'h', b'i']), Some(4));
+        assert_eq!(encoded_len(0x50, &[0x08, 0x01, 0x00, 0x33]), Some(4));
+        assert_eq!(encoded_len(0x27, &[0; 8]), Some(8)); // uint64
+        assert_eq!(encoded_len(0x27, &[0; 7]), None);
+        assert_eq!(encoded_len(0xE1, &[0; 4]), None);
+    }
+
+    #[test]
+    fn test_value_from_json() {
+        use serde_json::json;
+        assert_eq!(
+            ZclValue::from_json(DataType::Int24, &json!(-2))
+                .unwrap()
+                .encode(),
+            vec![0xFE, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            ZclValue::from_json(DataType::String, &json!("hall"))
+                .unwrap()
+                .encode(),
+            vec![4, b'h', b'a', b'l', b'l']
+        );
+        assert_eq!(
+            ZclValue::from_json(DataType::OctetString, &json!("0a1b")).unwrap(),
+            ZclValue::OctetString(vec![0x0A, 0x1B])
+        );
+        assert_eq!(
+            ZclValue::from_json(DataType::Uint48, &json!(12_345)).unwrap(),
+            ZclValue::Uint48(12_345)
+        );
+        assert!(ZclValue::from_json(DataType::Uint8, &json!(256)).is_err());
+        assert!(ZclValue::from_json(DataType::Int8, &json!(-129)).is_err());
+        assert!(ZclValue::from_json(DataType::Boolean, &json!(1)).is_err());
+        assert!(ZclValue::from_json(DataType::Array, &json!([])).is_err());
+    }
+}
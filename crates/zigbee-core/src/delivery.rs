@@ -0,0 +1,287 @@
+//! Delivery results of commands
+//!
+//! The adapter reports whether each APS request reached its destination with
+//! an APS data confirm (see [`deconz_protocol::confirm`]). Confirms are
+//! fetched by the event listener and counted per device, so "why won't this
+//! bulb respond" has an answer at the diagnostics API. Commands sent on a
+//! user's behalf (on/off, light effects) wait for their confirm and fail with
+//! [`NetworkError::Delivery`] when the device didn't get them.
+//!
+//! Getting a frame isn't the same as carrying it out: a bulb answers a
+//! command it can't execute with a ZCL Default Response carrying an error
+//! status. Those commands then wait for the response too, matched by the
+//! frame's transaction sequence number, and fail with
+//! [`NetworkError::Rejected`].
+//!
+//! A device that rejoined may have a new short address we haven't heard of
+//! yet. Commands it doesn't get for that reason are sent again addressed by
+//! its IEEE address, which the adapter resolves itself. A missing
+//! acknowledgement doesn't prove the device didn't act on the command, so
+//! commands that aren't idempotent (toggles, steps) are only sent again when
+//! the frame never got there.
+
+use crate::cluster;
+use crate::device::ZigbeeDevice;
+use crate::network::{NetworkError, ZigbeeNetwork};
+use dashmap::DashMap;
+use deconz_protocol::{
+    AddressMode, ApsDataConfirm, ApsDataRequest, ConfirmDestination, ConfirmStatus, DeconzEvent,
+    Transport, ZclFrame,
+};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How long a command waits for its confirm; sleeping devices pick up
+/// frames on their next poll, which can take up to 7.5 seconds
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a delivered command waits for the device's response
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Confirms fetched in one go when several are pending
+const MAX_CONFIRMS_PER_FETCH: usize = 16;
+
+/// Most recent failed delivery to a device
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryFailure {
+    /// Status byte from the adapter
+    pub code: u8,
+    pub description: &'static str,
+    /// Unix seconds
+    pub timestamp: u64,
+}
+
+/// Delivery counts of one device since startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeliveryStats {
+    pub delivered: u64,
+    pub failed: u64,
+    pub last_failure: Option<DeliveryFailure>,
+    /// Commands the device received but answered with an error status
+    pub rejected: u64,
+}
+
+/// Per-device delivery counts and request IDs for confirmed sends
+#[derive(Debug, Default)]
+pub(crate) struct DeliveryLog {
+    stats: DashMap<[u8; 8], DeliveryStats>,
+    request_id: AtomicU8,
+}
+
+impl DeliveryLog {
+    /// Count a confirm against the device it was sent to
+    pub(crate) fn record(
+        &self,
+        devices: &DashMap<[u8; 8], ZigbeeDevice>,
+        confirm: &ApsDataConfirm,
+    ) {
+        let ieee_address = match confirm.destination {
+            ConfirmDestination::Ieee(ieee) => Some(ieee),
+            ConfirmDestination::Nwk(nwk) => devices
+                .iter()
+                .find(|d| d.nwk_address == nwk)
+                .map(|d| d.ieee_address),
+            ConfirmDestination::Group(_) => None,
+        };
+        let Some(ieee_address) = ieee_address else {
+            return;
+        };
+
+        let mut stats = self.stats.entry(ieee_address).or_default();
+        if confirm.status.is_success() {
+            stats.delivered += 1;
+            return;
+        }
+        tracing::info!(
+            "Delivery to {:?} failed: {}",
+            confirm.destination,
+            confirm.status
+        );
+        stats.failed += 1;
+        stats.last_failure = Some(DeliveryFailure {
+            code: confirm.status.code(),
+            description: confirm.status.description(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        });
+    }
+
+    /// Request ID for a confirmed send; plain sends all use 1
+    fn next_request_id(&self) -> u8 {
+        loop {
+            let id = self.request_id.fetch_add(1, Ordering::Relaxed);
+            if id > 1 {
+                return id;
+            }
+        }
+    }
+}
+
+/// Fetch every pending confirm (they are broadcast as
+/// [`DeconzEvent::ApsConfirm`])
+pub(crate) async fn fetch_confirms(transport: &dyn Transport) {
+    for _ in 0..MAX_CONFIRMS_PER_FETCH {
+        match transport.request_aps_confirm().await {
+            Ok(confirm) if confirm.device_state.aps_data_confirm => {}
+            Ok(_) => break,
+            Err(e) => {
+                tracing::debug!("Failed to fetch APS confirm: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+impl ZigbeeNetwork {
+    /// Delivery counts of a device since startup
+    #[must_use]
+    pub fn delivery_stats(&self, ieee: &[u8; 8]) -> DeliveryStats {
+        self.delivery()
+            .stats
+            .get(ieee)
+            .map(|s| s.clone())
+            .unwrap_or_default()
+    }
+
+    /// Send a request and wait until the adapter confirms delivery
+    ///
+    /// A device that can't be reached at its short address, which changes
+    /// when it rejoins, is tried once more by its IEEE address, unless the
+    /// command may have arrived and isn't idempotent. Failing to
+    /// get a confirm at all isn't an error; older firmware doesn't always
+    /// send one.
+    pub(crate) async fn send_confirmed(&self, request: ApsDataRequest) -> Result<(), NetworkError> {
+        let retry = request.clone();
+        match self.send_once(request).await {
+            Err(NetworkError::Delivery(status))
+                if status.is_unreachable()
+                    && (status.is_undelivered() || retry.is_idempotent()) =>
+            {
+                let Some(ieee) = self.unicast_ieee(&retry) else {
+                    return Err(NetworkError::Delivery(status));
+                };
+                tracing::info!(
+                    "Device {:#06x} unreachable ({}), retrying by IEEE address",
+                    retry.dest_short_addr,
+                    status
+                );
+                self.send_once(retry.with_ieee_destination(ieee)).await
+            }
+            result => result,
+        }
+    }
+
+    /// IEEE address of the known device a request is sent to by short
+    /// address (not to a group or broadcast)
+    fn unicast_ieee(&self, request: &ApsDataRequest) -> Option<[u8; 8]> {
+        if request.dest_addr_mode != AddressMode::Nwk || request.is_broadcast() {
+            return None;
+        }
+        self.devices()
+            .iter()
+            .find(|d| d.nwk_address == request.dest_short_addr)
+            .map(|d| d.ieee_address)
+    }
+
+    async fn send_once(&self, mut request: ApsDataRequest) -> Result<(), NetworkError> {
+        let request_id = self.delivery().next_request_id();
+        request.request_id = request_id;
+        let mut events = self.transport().subscribe();
+        self.transport().send_aps_request(request).await?;
+
+        let confirm = async {
+            loop {
+                match events.recv().await {
+                    Ok(DeconzEvent::ApsConfirm(confirm)) if confirm.request_id == request_id => {
+                        return Some(confirm.status);
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        };
+        match tokio::time::timeout(CONFIRM_TIMEOUT, confirm).await {
+            Ok(Some(status)) if status != ConfirmStatus::Success => {
+                Err(NetworkError::Delivery(status))
+            }
+            Ok(_) => Ok(()),
+            Err(_) => {
+                tracing::debug!("No APS confirm for request {}", request_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Send a ZCL command (built with a sequence number from
+    /// [`cluster::next_tsn`]) and wait until the device accepts it
+    ///
+    /// After delivery is confirmed, a Default Response with an error status
+    /// fails the command. No response at all isn't an error; devices may
+    /// only answer on failure.
+    pub(crate) async fn send_command(&self, request: ApsDataRequest) -> Result<(), NetworkError> {
+        let Ok(frame) = ZclFrame::parse(&request.asdu) else {
+            return self.send_confirmed(request).await;
+        };
+        let (nwk_address, cluster_id) = (request.dest_short_addr, request.cluster_id);
+        let transaction_seq = frame.transaction_seq();
+        let mut events = self.transport().subscribe();
+        self.send_confirmed(request).await?;
+
+        let response = async {
+            loop {
+                match events.recv().await {
+                    Ok(DeconzEvent::ApsIndication(indication))
+                        if indication.src_short_addr == nwk_address
+                            && indication.cluster_id == cluster_id =>
+                    {
+                        if let Some(status) =
+                            cluster::command_status(&indication.asdu, transaction_seq)
+                        {
+                            return Some(status);
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        };
+        match tokio::time::timeout(RESPONSE_TIMEOUT, response).await {
+            Ok(Some(status)) if !status.is_success() => {
+                tracing::info!(
+                    "Device {:#06x} rejected cluster {:#06x} command {}: {}",
+                    nwk_address,
+                    cluster_id,
+                    frame.command_id(),
+                    status
+                );
+                self.record_rejection(nwk_address);
+                Err(NetworkError::Rejected(status))
+            }
+            Ok(_) => Ok(()),
+            Err(_) => {
+                tracing::debug!("No response to ZCL transaction {}", transaction_seq);
+                Ok(())
+            }
+        }
+    }
+
+    fn record_rejection(&self, nwk_address: u16) {
+        let Some(ieee_address) = self
+            .devices()
+            .iter()
+            .find(|d| d.nwk_address == nwk_address)
+            .map(|d| d.ieee_address)
+        else {
+            return;
+        };
+        self.delivery()
+            .stats
+            .entry(ieee_address)
+            .or_default()
+            .rejected += 1;
+    }
+}
@@ -1,7 +1,8 @@
 //! Zigbee device representation
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
+use std::collections::BTreeMap;
 
 /// Zigbee device types (network role)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -13,7 +14,7 @@ pub enum DeviceType {
 }
 
 /// Device category for user classification
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DeviceCategory {
     Light,
@@ -24,17 +25,12 @@ pub enum DeviceCategory {
     Thermostat,
     Fan,
     Blinds,
+    #[default]
     Other,
 }
 
-impl Default for DeviceCategory {
-    fn default() -> Self {
-        Self::Other
-    }
-}
-
 /// A Zigbee device on the network
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ZigbeeDevice {
     /// IEEE address (EUI-64)
     pub ieee_address: [u8; 8],
@@ -53,9 +49,9 @@ pub struct ZigbeeDevice {
     pub friendly_name: Option<String>,
     /// Device endpoints
     pub endpoints: Vec<Endpoint>,
-    /// Last seen timestamp
-    #[serde(skip)]
-    pub last_seen: Option<Instant>,
+    /// When a frame from the device was last received
+    #[serde(default)]
+    pub last_seen: Option<DateTime<Utc>>,
     /// Link quality indicator (0-255)
     pub lqi: Option<u8>,
     /// Is device reachable
@@ -63,6 +59,58 @@ pub struct ZigbeeDevice {
     /// Current on/off state (if applicable)
     #[serde(default)]
     pub state_on: Option<bool>,
+    /// Current level (1-254) of a dimmable light, from commands and reports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    /// Current colour of a colour light, from commands and reports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<crate::light::LightColor>,
+    /// Occupancy reported by an occupancy (motion) sensor
+    #[serde(default)]
+    pub occupancy: Option<bool>,
+    /// Latest measurements (temperature, humidity, power...)
+    #[serde(default)]
+    pub sensor_values: BTreeMap<crate::sensor::SensorKind, f64>,
+    /// Multipliers and divisors reported by metering clusters
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sensor_scales: BTreeMap<crate::sensor::SensorKind, crate::sensor::Scale>,
+    /// IAS Zone enrollment and last zone status (security sensors)
+    #[serde(default)]
+    pub ias_zone: Option<crate::ias::IasZone>,
+    /// User-provided metadata (icon, image, notes...)
+    #[serde(default)]
+    pub metadata: DeviceMetadata,
+    /// Latest Tuya data point values by name (see [`crate::tuya`])
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tuya_values: BTreeMap<String, serde_json::Value>,
+    /// Power-on behavior last written to the device (lights)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startup: Option<crate::startup::StartupBehavior>,
+    /// Firmware image the device last reported to the OTA Upgrade server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<crate::ota::ImageId>,
+    /// Automations that keep failing to control the device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub health_notes: Vec<crate::health::HealthNote>,
+    /// Bumped on every user edit; clients send it back in `If-Match`
+    #[serde(default)]
+    pub revision: u64,
+}
+
+/// User-provided device metadata
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceMetadata {
+    /// Icon name for the UI (e.g. `mdi:lamp`)
+    pub icon: Option<String>,
+    /// Uploaded image file name (stored under `data/media/`)
+    pub image: Option<String>,
+    /// Purchase date (YYYY-MM-DD)
+    pub purchase_date: Option<String>,
+    /// Free-form notes
+    pub notes: Option<String>,
+    /// Arbitrary user-defined fields
+    pub custom: BTreeMap<String, serde_json::Value>,
 }
 
 impl ZigbeeDevice {
@@ -82,6 +130,28 @@ impl ZigbeeDevice {
             lqi: None,
             available: true,
             state_on: None,
+            brightness: None,
+            color: None,
+            occupancy: None,
+            sensor_values: BTreeMap::new(),
+            sensor_scales: BTreeMap::new(),
+            ias_zone: None,
+            metadata: DeviceMetadata::default(),
+            tuya_values: BTreeMap::new(),
+            startup: None,
+            firmware: None,
+            health_notes: Vec::new(),
+            revision: 0,
+        }
+    }
+
+    /// On/off, brightness and colour, as captured for a later restore
+    #[must_use]
+    pub fn light_state(&self) -> crate::light::LightState {
+        crate::light::LightState {
+            on: self.state_on,
+            brightness: self.brightness,
+            color: self.color,
         }
     }
 
@@ -107,7 +177,7 @@ impl ZigbeeDevice {
 }
 
 /// A device endpoint
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Endpoint {
     /// Endpoint ID (1-240)
     pub id: u8,
@@ -0,0 +1,314 @@
+//! What a device can do
+//!
+//! Derived from the clusters of a device's endpoints plus its quirks and
+//! Tuya data points, so clients (the frontend, the automation builder) don't
+//! have to guess which controls and readings a device has. Nothing here is
+//! stored; it is recomputed from the device whenever it is asked for.
+
+use crate::cluster::id;
+use crate::device::{DeviceCategory, DeviceType, Endpoint, ZigbeeDevice};
+use crate::ias::IasZone;
+use crate::quirks;
+use crate::sensor::SensorKind;
+use crate::tuya::{self, DpKind};
+use serde::Serialize;
+
+/// A controllable feature of a switch, light or thermostat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// On/off
+    State,
+    Brightness,
+    /// Colour temperature (mireds)
+    ColorTemp,
+    /// Hue and saturation
+    Color,
+    /// Behavior after a power cut (see [`crate::startup`])
+    PowerOnBehavior,
+    HeatingSetpoint,
+    LocalTemperature,
+    /// Regulates on a room sensor's temperature (see
+    /// [`quirks::Quirk::external_temperature`])
+    ExternalTemperature,
+}
+
+/// One capability of a device
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Expose {
+    /// On/off output without dimming (plugs, relays)
+    Switch {
+        endpoint: u8,
+        features: Vec<Feature>,
+    },
+    Light {
+        endpoint: u8,
+        features: Vec<Feature>,
+    },
+    Thermostat {
+        endpoint: u8,
+        features: Vec<Feature>,
+    },
+    /// A value in `sensor_values`
+    Sensor {
+        endpoint: Option<u8>,
+        kind: SensorKind,
+        unit: &'static str,
+    },
+    /// The `occupancy` field
+    Occupancy { endpoint: Option<u8> },
+    /// IAS Zone alarm (`ias_zone`); `zone` once the sensor has enrolled
+    Alarm {
+        endpoint: u8,
+        zone: Option<&'static str>,
+    },
+    /// Sends `button_event`s for buttons 1 to `buttons`
+    Remote { buttons: u8 },
+    /// A Tuya data point in `tuya_values`
+    TuyaValue { name: &'static str, writable: bool },
+}
+
+/// Measurements reported by a server cluster
+fn cluster_sensors(cluster_id: u16) -> &'static [SensorKind] {
+    match cluster_id {
+        id::TEMPERATURE_MEASUREMENT => &[SensorKind::Temperature],
+        id::HUMIDITY_MEASUREMENT => &[SensorKind::Humidity],
+        id::PRESSURE_MEASUREMENT => &[SensorKind::Pressure],
+        id::CARBON_DIOXIDE_MEASUREMENT => &[SensorKind::Co2],
+        id::PM25_MEASUREMENT => &[SensorKind::Pm25],
+        id::FORMALDEHYDE_MEASUREMENT => &[SensorKind::Formaldehyde],
+        id::ELECTRICAL_MEASUREMENT => {
+            &[SensorKind::Power, SensorKind::Voltage, SensorKind::Current]
+        }
+        id::METERING => &[SensorKind::Energy],
+        _ => &[],
+    }
+}
+
+/// Switch, light or thermostat on one endpoint
+fn control(device: &ZigbeeDevice, endpoint: &Endpoint) -> Option<Expose> {
+    let has = |cluster_id| endpoint.in_clusters.contains(&cluster_id);
+
+    if has(id::THERMOSTAT) {
+        let mut features = vec![Feature::HeatingSetpoint, Feature::LocalTemperature];
+        if quirks::quirk_for(device).is_some_and(|q| q.external_temperature.is_some()) {
+            features.push(Feature::ExternalTemperature);
+        }
+        return Some(Expose::Thermostat {
+            endpoint: endpoint.id,
+            features,
+        });
+    }
+    if !has(id::ON_OFF) {
+        return None;
+    }
+
+    let mut features = vec![Feature::State];
+    if has(id::LEVEL_CONTROL) {
+        features.push(Feature::Brightness);
+    }
+    if has(id::COLOR_CONTROL) {
+        features.extend([Feature::ColorTemp, Feature::Color]);
+    }
+    let is_light = features.len() > 1 || device.category == DeviceCategory::Light;
+    if is_light {
+        features.push(Feature::PowerOnBehavior);
+        Some(Expose::Light {
+            endpoint: endpoint.id,
+            features,
+        })
+    } else {
+        Some(Expose::Switch {
+            endpoint: endpoint.id,
+            features,
+        })
+    }
+}
+
+impl ZigbeeDevice {
+    /// What the device can do, from its clusters, quirks and Tuya data points
+    #[must_use]
+    pub fn exposes(&self) -> Vec<Expose> {
+        let mut exposes = Vec::new();
+        let mut push = |expose: Expose| {
+            if !exposes.contains(&expose) {
+                exposes.push(expose);
+            }
+        };
+
+        for endpoint in &self.endpoints {
+            if let Some(control) = control(self, endpoint) {
+                push(control);
+            }
+            for &cluster_id in &endpoint.in_clusters {
+                for &kind in cluster_sensors(cluster_id) {
+                    push(Expose::Sensor {
+                        endpoint: Some(endpoint.id),
+                        kind,
+                        unit: kind.unit(),
+                    });
+                }
+                match cluster_id {
+                    // Mains powered devices often have the cluster without a battery
+                    id::POWER_CONFIG if self.device_type == DeviceType::EndDevice => {
+                        push(Expose::Sensor {
+                            endpoint: Some(endpoint.id),
+                            kind: SensorKind::Battery,
+                            unit: SensorKind::Battery.unit(),
+                        });
+                    }
+                    id::OCCUPANCY_SENSING => push(Expose::Occupancy {
+                        endpoint: Some(endpoint.id),
+                    }),
+                    id::IAS_ZONE => push(Expose::Alarm {
+                        endpoint: endpoint.id,
+                        zone: self.ias_zone.as_ref().and_then(IasZone::zone_type_name),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(quirk) = quirks::quirk_for(self) {
+            // Switches with a button per endpoint
+            let endpoints = self.endpoints.iter().map(|e| e.id).max();
+            let buttons = quirk
+                .buttons
+                .iter()
+                .filter_map(|m| match m.button {
+                    quirks::Button::Number(n) => Some(n),
+                    quirks::Button::LastHeld => None,
+                    quirks::Button::Endpoint => endpoints,
+                })
+                .chain(endpoints.filter(|_| !quirk.press_values.is_empty()))
+                .max();
+            if let Some(buttons) = buttons {
+                push(Expose::Remote { buttons });
+            }
+            let has_power_config = self
+                .endpoints
+                .iter()
+                .any(|e| e.in_clusters.contains(&id::POWER_CONFIG));
+            if quirk.poll_battery && !has_power_config {
+                push(Expose::Sensor {
+                    endpoint: None,
+                    kind: SensorKind::Battery,
+                    unit: SensorKind::Battery.unit(),
+                });
+            }
+        }
+
+        if let Some(model) = self.manufacturer.as_deref().and_then(tuya::model_for) {
+            for mapping in model.datapoints {
+                match mapping.kind {
+                    DpKind::Measurement(kind) => push(Expose::Sensor {
+                        endpoint: None,
+                        kind,
+                        unit: kind.unit(),
+                    }),
+                    DpKind::Occupancy => push(Expose::Occupancy { endpoint: None }),
+                    _ => {}
+                }
+                push(Expose::TuyaValue {
+                    name: mapping.name,
+                    writable: mapping.kind.writable(),
+                });
+            }
+        }
+
+        exposes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(id: u8, in_clusters: &[u16]) -> Endpoint {
+        Endpoint {
+            id,
+            profile_id: 0x0104,
+            device_id: 0,
+            in_clusters: in_clusters.to_vec(),
+            out_clusters: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_exposes_from_clusters() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        device.device_type = DeviceType::Router;
+        device.endpoints = vec![
+            endpoint(
+                1,
+                &[
+                    id::BASIC,
+                    id::ON_OFF,
+                    id::LEVEL_CONTROL,
+                    id::COLOR_CONTROL,
+                    id::POWER_CONFIG,
+                ],
+            ),
+            endpoint(2, &[id::ON_OFF, id::ELECTRICAL_MEASUREMENT]),
+        ];
+        let exposes = device.exposes();
+        assert_eq!(
+            exposes[0],
+            Expose::Light {
+                endpoint: 1,
+                features: vec![
+                    Feature::State,
+                    Feature::Brightness,
+                    Feature::ColorTemp,
+                    Feature::Color,
+                    Feature::PowerOnBehavior,
+                ],
+            }
+        );
+        assert_eq!(
+            exposes[1],
+            Expose::Switch {
+                endpoint: 2,
+                features: vec![Feature::State],
+            }
+        );
+        // No battery on a mains powered router
+        assert_eq!(exposes.len(), 5);
+        assert_eq!(
+            serde_json::to_value(&exposes[2]).unwrap(),
+            serde_json::json!({"type": "sensor", "endpoint": 2, "kind": "power", "unit": "W"})
+        );
+    }
+
+    #[test]
+    fn test_exposes_from_quirks() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        device.manufacturer = Some("IKEA of Sweden".to_string());
+        device.model = Some("Remote Control N2".to_string());
+        assert_eq!(
+            device.exposes(),
+            vec![
+                Expose::Remote { buttons: 4 },
+                Expose::Sensor {
+                    endpoint: None,
+                    kind: SensorKind::Battery,
+                    unit: "%",
+                },
+            ]
+        );
+
+        device.manufacturer = Some("_TZE200_myd45weu".to_string());
+        device.model = Some("TS0601".to_string());
+        let exposes = device.exposes();
+        assert!(exposes.contains(&Expose::Sensor {
+            endpoint: None,
+            kind: SensorKind::SoilMoisture,
+            unit: "%",
+        }));
+        assert!(exposes.contains(&Expose::TuyaValue {
+            name: "battery",
+            writable: false,
+        }));
+    }
+}
@@ -0,0 +1,419 @@
+//! Network formation and configuration
+//!
+//! Guided flow for setting up a fresh coordinator: take the network offline,
+//! write the formation parameters, bring it back online and verify the result.
+//! If any step fails, the parameters it would have changed are written back
+//! so the stick isn't left half configured.
+
+use crate::network::{NetworkError, NetworkStatus, ZigbeeNetwork};
+use deconz_protocol::NetworkState;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Lowest and highest 2.4 GHz Zigbee channels
+pub const MIN_CHANNEL: u8 = 11;
+pub const MAX_CHANNEL: u8 = 26;
+
+/// How long to wait for the coordinator to reach a requested network state
+const STATE_CHANGE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Parameters for forming/reconfiguring the network
+///
+/// Fields left as `None` keep their current value on the coordinator.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NetworkConfig {
+    /// Single channel to operate on (11-26), converted to a channel mask
+    #[serde(default)]
+    pub channel: Option<u8>,
+    /// Explicit channel mask (takes precedence over `channel`)
+    #[serde(default)]
+    pub channel_mask: Option<u32>,
+    /// Network PAN ID
+    #[serde(default)]
+    pub pan_id: Option<u16>,
+    /// Extended PAN ID (little-endian bytes)
+    #[serde(default)]
+    pub extended_pan_id: Option<[u8; 8]>,
+    /// Network key
+    #[serde(default)]
+    pub network_key: Option<[u8; 16]>,
+    /// Security mode (0 = none, 1 = preconfigured network key, 2 = key from TC, 3 = TC link key)
+    #[serde(default)]
+    pub security_mode: Option<u8>,
+}
+
+impl NetworkConfig {
+    /// Build a channel mask with a single channel set, `None` if the
+    /// channel doesn't fit in a mask
+    #[must_use]
+    pub fn channel_to_mask(channel: u8) -> Option<u32> {
+        1u32.checked_shl(u32::from(channel))
+    }
+
+    /// Effective channel mask to write, if any
+    #[must_use]
+    pub fn effective_channel_mask(&self) -> Option<u32> {
+        self.channel_mask
+            .or_else(|| self.channel.and_then(Self::channel_to_mask))
+    }
+
+    /// Validate the configuration before touching the coordinator; errors
+    /// are [`NetworkError::InvalidConfig`]
+    #[allow(clippy::missing_errors_doc)]
+    pub fn validate(&self) -> Result<(), NetworkError> {
+        if let Some(channel) = self.channel {
+            if !(MIN_CHANNEL..=MAX_CHANNEL).contains(&channel) {
+                return Err(NetworkError::InvalidConfig(format!(
+                    "channel must be between {MIN_CHANNEL} and {MAX_CHANNEL}, got {channel}"
+                )));
+            }
+        }
+        if let Some(mask) = self.channel_mask {
+            let valid_bits = (u32::from(MIN_CHANNEL)..=u32::from(MAX_CHANNEL))
+                .fold(0u32, |acc, ch| acc | (1 << ch));
+            if mask == 0 || mask & !valid_bits != 0 {
+                return Err(NetworkError::InvalidConfig(format!(
+                    "channel mask {mask:#010x} must only contain channels {MIN_CHANNEL}-{MAX_CHANNEL}"
+                )));
+            }
+        }
+        if let Some(pan_id) = self.pan_id {
+            if pan_id == 0x0000 || pan_id == 0xFFFF {
+                return Err(NetworkError::InvalidConfig(format!(
+                    "PAN ID {pan_id:#06x} is reserved"
+                )));
+            }
+        }
+        if let Some(mode) = self.security_mode {
+            if mode > 3 {
+                return Err(NetworkError::InvalidConfig(format!(
+                    "security mode must be 0-3, got {mode}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ZigbeeNetwork {
+    /// Configure (or form) the network with the given parameters
+    ///
+    /// Takes the network offline, writes the parameters, brings it back online
+    /// and verifies that the coordinator reports the requested values. If that
+    /// fails, the previous values are written back before returning the error.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn configure_network(
+        &self,
+        config: NetworkConfig,
+    ) -> Result<NetworkStatus, NetworkError> {
+        config.validate()?;
+
+        tracing::info!("Configuring network: {:?}", redacted(&config));
+
+        let previous = self.snapshot_network_config(&config).await?;
+        if let Err(e) = self.apply_network_config(&config).await {
+            tracing::warn!("Network configuration failed, restoring previous parameters: {e}");
+            if let Err(e) = self.rollback_network_config(&previous).await {
+                tracing::error!("Failed to restore previous network parameters: {e}");
+            }
+            return Err(e);
+        }
+
+        tracing::info!("Network configuration applied and verified");
+        self.get_status().await
+    }
+
+    async fn apply_network_config(&self, config: &NetworkConfig) -> Result<(), NetworkError> {
+        self.disconnect().await?;
+        self.wait_for_network_state(NetworkState::Offline).await?;
+
+        self.write_network_config(config).await?;
+
+        self.connect().await?;
+        self.wait_for_network_state(NetworkState::Connected).await?;
+
+        self.verify_network_config(config).await
+    }
+
+    /// Read the parameters `config` would change
+    async fn snapshot_network_config(
+        &self,
+        config: &NetworkConfig,
+    ) -> Result<Snapshot, NetworkError> {
+        let transport = self.transport();
+        let mut snapshot = Snapshot {
+            designed_coordinator: transport.read_designed_coordinator().await?,
+            ..Snapshot::default()
+        };
+        if config.effective_channel_mask().is_some() {
+            snapshot.channel_mask = Some(transport.read_channel_mask().await?);
+        }
+        if config.pan_id.is_some() {
+            snapshot.pan_id = Some((
+                transport.read_pan_id().await?,
+                transport.read_predefined_pan_id().await?,
+            ));
+        }
+        if config.extended_pan_id.is_some() {
+            snapshot.extended_pan_id = Some((
+                transport.read_extended_pan_id().await?,
+                transport.read_aps_extended_pan_id().await?,
+            ));
+        }
+        if config.network_key.is_some() {
+            snapshot.network_key = Some(transport.read_network_key().await?);
+        }
+        if config.security_mode.is_some() {
+            snapshot.security_mode = Some(transport.read_security_mode().await?);
+        }
+        Ok(snapshot)
+    }
+
+    /// Write back the parameters from before a failed configuration
+    async fn rollback_network_config(&self, snapshot: &Snapshot) -> Result<(), NetworkError> {
+        let transport = self.transport();
+
+        self.disconnect().await?;
+        self.wait_for_network_state(NetworkState::Offline).await?;
+
+        transport
+            .write_designed_coordinator(snapshot.designed_coordinator)
+            .await?;
+        if let Some(mask) = snapshot.channel_mask {
+            transport.write_channel_mask(mask).await?;
+        }
+        if let Some((pan_id, predefined)) = snapshot.pan_id {
+            transport.write_pan_id(pan_id).await?;
+            transport.write_predefined_pan_id(predefined).await?;
+        }
+        if let Some((ext_pan_id, aps_ext_pan_id)) = snapshot.extended_pan_id {
+            transport.write_extended_pan_id(ext_pan_id).await?;
+            transport.write_aps_extended_pan_id(aps_ext_pan_id).await?;
+        }
+        if let Some(key) = snapshot.network_key {
+            transport.write_network_key(key).await?;
+        }
+        if let Some(mode) = snapshot.security_mode {
+            transport.write_security_mode(mode).await?;
+        }
+
+        self.connect().await?;
+        self.wait_for_network_state(NetworkState::Connected).await
+    }
+
+    /// Write the formation parameters (network must already be offline)
+    pub(crate) async fn write_network_config(
+        &self,
+        config: &NetworkConfig,
+    ) -> Result<(), NetworkError> {
+        let transport = self.transport();
+
+        transport.write_designed_coordinator(true).await?;
+
+        if let Some(mask) = config.effective_channel_mask() {
+            transport.write_channel_mask(mask).await?;
+        }
+        if let Some(pan_id) = config.pan_id {
+            transport.write_pan_id(pan_id).await?;
+            transport.write_predefined_pan_id(true).await?;
+        }
+        if let Some(ext_pan_id) = config.extended_pan_id {
+            transport.write_extended_pan_id(ext_pan_id).await?;
+            transport.write_aps_extended_pan_id(ext_pan_id).await?;
+        }
+        if let Some(key) = config.network_key {
+            transport.write_network_key(key).await?;
+        }
+        if let Some(mode) = config.security_mode {
+            transport.write_security_mode(mode).await?;
+        }
+        Ok(())
+    }
+
+    /// Poll the coordinator until it reports the given network state
+    pub(crate) async fn wait_for_network_state(
+        &self,
+        target: NetworkState,
+    ) -> Result<(), NetworkError> {
+        let deadline = tokio::time::Instant::now() + STATE_CHANGE_TIMEOUT;
+        loop {
+            let state = self.transport().get_device_state().await?;
+            if state.network_state == target {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(NetworkError::Configuration(format!(
+                    "timed out waiting for network state {target:?} (currently {:?})",
+                    state.network_state
+                )));
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Read back the written parameters and compare against the requested config
+    pub(crate) async fn verify_network_config(
+        &self,
+        config: &NetworkConfig,
+    ) -> Result<(), NetworkError> {
+        let transport = self.transport();
+        let mut mismatches = Vec::new();
+
+        if let Some(mask) = config.effective_channel_mask() {
+            let channel = transport.read_channel().await?;
+            if NetworkConfig::channel_to_mask(channel).is_none_or(|bit| mask & bit == 0) {
+                mismatches.push(format!("channel {channel} is not in mask {mask:#010x}"));
+            }
+        }
+        if let Some(pan_id) = config.pan_id {
+            let actual = transport.read_pan_id().await?;
+            if actual != pan_id {
+                mismatches.push(format!("PAN ID is {actual:#06x}, expected {pan_id:#06x}"));
+            }
+        }
+        if let Some(ext_pan_id) = config.extended_pan_id {
+            let actual = transport.read_extended_pan_id().await?;
+            if actual != ext_pan_id {
+                mismatches.push("extended PAN ID does not match".to_string());
+            }
+        }
+        if let Some(key) = config.network_key {
+            if transport.read_network_key().await? != key {
+                mismatches.push("network key does not match".to_string());
+            }
+        }
+        if let Some(mode) = config.security_mode {
+            let actual = transport.read_security_mode().await?;
+            if actual != mode {
+                mismatches.push(format!("security mode is {actual}, expected {mode}"));
+            }
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(NetworkError::Configuration(mismatches.join("; ")))
+        }
+    }
+}
+
+/// Coordinator parameters from before a configuration; `None` where the
+/// configuration doesn't touch them
+#[derive(Default)]
+struct Snapshot {
+    designed_coordinator: bool,
+    channel_mask: Option<u32>,
+    /// PAN ID and whether it is predefined
+    pan_id: Option<(u16, bool)>,
+    /// NWK and APS extended PAN IDs
+    extended_pan_id: Option<([u8; 8], [u8; 8])>,
+    network_key: Option<[u8; 16]>,
+    security_mode: Option<u8>,
+}
+
+/// Copy of the config safe for logging (network key removed)
+fn redacted(config: &NetworkConfig) -> NetworkConfig {
+    NetworkConfig {
+        network_key: None,
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::MockTransport;
+    use deconz_protocol::{CommandId, NetworkParameter, Status};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_channel_mask_from_channel() {
+        let config = NetworkConfig {
+            channel: Some(15),
+            ..Default::default()
+        };
+        assert_eq!(config.effective_channel_mask(), Some(0x0000_8000));
+    }
+
+    #[test]
+    fn test_validate_rejects_out_of_range() {
+        let config = NetworkConfig {
+            channel: Some(27),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = NetworkConfig {
+            channel_mask: Some(0x0000_0001),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = NetworkConfig {
+            pan_id: Some(0xFFFF),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(NetworkError::InvalidConfig(_))
+        ));
+    }
+
+    /// ReadParameter response carrying `value`
+    fn parameter(param: NetworkParameter, value: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(1 + value.len()).unwrap();
+        let mut payload = len.to_le_bytes().to_vec();
+        payload.push(param as u8);
+        payload.extend_from_slice(value);
+        payload
+    }
+
+    #[tokio::test]
+    async fn test_failed_key_check_restores_previous_key() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        let old_key = [0x11; 16];
+        let new_key = [0x22; 16];
+        let mut key_value = vec![0x00];
+        key_value.extend_from_slice(&old_key);
+
+        mock.respond_always(CommandId::ChangeNetworkState, Status::Success, Vec::new());
+        mock.respond_always(CommandId::WriteParameter, Status::Success, Vec::new());
+        for state in [0x00, 0x02, 0x00, 0x02] {
+            mock.respond(CommandId::DeviceState, Status::Success, vec![state]);
+        }
+        mock.respond(
+            CommandId::ReadParameter,
+            Status::Success,
+            parameter(NetworkParameter::ApsDesignedCoordinator, &[0x01]),
+        );
+        // Read before the change, then again after it: the stick kept the old key
+        for _ in 0..2 {
+            mock.respond(
+                CommandId::ReadParameter,
+                Status::Success,
+                parameter(NetworkParameter::NetworkKey, &key_value),
+            );
+        }
+
+        let config = NetworkConfig {
+            network_key: Some(new_key),
+            ..Default::default()
+        };
+        let result = network.configure_network(config).await;
+        assert!(matches!(result, Err(NetworkError::Configuration(_))));
+
+        let key_writes: Vec<Vec<u8>> = mock
+            .requests()
+            .into_iter()
+            .filter(|frame| {
+                frame.command_id == CommandId::WriteParameter
+                    && frame.payload.get(2) == Some(&(NetworkParameter::NetworkKey as u8))
+            })
+            .map(|frame| frame.payload[4..].to_vec())
+            .collect();
+        assert_eq!(key_writes, vec![new_key.to_vec(), old_key.to_vec()]);
+    }
+}
@@ -0,0 +1,563 @@
+//! Green Power device support
+//!
+//! Green Power devices (Hue Tap, battery-less kinetic switches) don't join the
+//! network and have no IEEE address. Each one is represented as a virtual
+//! [`ZigbeeDevice`] keyed by its 32-bit source ID, and its presses are emitted
+//! as [`NetworkEvent::ButtonEvent`] with the button number as endpoint.
+//!
+//! Anyone can transmit a GP frame, so devices are only taken on while the
+//! network is open for joining: from their commissioning frame, or for
+//! devices without security (Hue Tap) from their first press. The security
+//! level, key and frame counter they commission with are kept in
+//! `green_power.json`, separately from the device table the API serves.
+//! Frames of commissioned devices below that level, with an old frame
+//! counter or a bad MIC are dropped, as is everything from other sources.
+//! Unsecured devices repeat each frame several times, and a repeat within
+//! [`REPEAT_WINDOW`] is handled once.
+//!
+//! Virtual devices have [`NO_SHORT_ADDRESS`], so commands sent to them fail
+//! instead of going out as a broadcast.
+
+use crate::availability;
+use crate::device::{DeviceCategory, DeviceType, Endpoint, ZigbeeDevice};
+use crate::network::{self, ButtonAction, DeviceStorage, NetworkEvent};
+use chrono::Utc;
+use dashmap::DashMap;
+use deconz_protocol::green_power::{commands, device_ids};
+use deconz_protocol::{GreenPowerFrame, NO_SHORT_ADDRESS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Green Power endpoint on the coordinator
+pub const GREEN_POWER_ENDPOINT: u8 = 0xF2;
+/// Green Power cluster ID
+pub const GREEN_POWER_CLUSTER: u16 = 0x0021;
+/// Green Power profile ID
+pub const GREEN_POWER_PROFILE: u16 = 0xA1E0;
+
+/// How long an unsecured frame counts as a repeat of the previous one
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(2);
+
+/// Virtual IEEE address for a GP source ID (upper four bytes are zero)
+#[must_use]
+pub fn ieee_for_source_id(source_id: u32) -> [u8; 8] {
+    let mut ieee = [0u8; 8];
+    ieee[..4].copy_from_slice(&source_id.to_le_bytes());
+    ieee
+}
+
+/// Whether a device is a virtual GP device
+#[must_use]
+pub fn is_green_power(device: &ZigbeeDevice) -> bool {
+    device
+        .endpoints
+        .iter()
+        .any(|e| e.profile_id == GREEN_POWER_PROFILE)
+}
+
+/// Map a GP command to button presses as `(button, action)` pairs
+///
+/// Buttons are numbered from 1. The Hue Tap sends Toggle for button 1 and
+/// Recall Scene 0-2 for buttons 2-4; kinetic switches use the press/release
+/// commands.
+#[must_use]
+pub fn button_events(frame: &GreenPowerFrame) -> Vec<(u8, ButtonAction)> {
+    match frame.command_id {
+        commands::TOGGLE => vec![(1, ButtonAction::Single)],
+        cmd @ commands::RECALL_SCENE_0..=commands::RECALL_SCENE_7 => {
+            vec![(cmd - commands::RECALL_SCENE_0 + 2, ButtonAction::Single)]
+        }
+        commands::PRESS_1_OF_1 | commands::PRESS_1_OF_2 => vec![(1, ButtonAction::Single)],
+        commands::RELEASE_1_OF_1 | commands::RELEASE_1_OF_2 => vec![(1, ButtonAction::Release)],
+        commands::PRESS_2_OF_2 => vec![(2, ButtonAction::Single)],
+        commands::RELEASE_2_OF_2 => vec![(2, ButtonAction::Release)],
+        commands::PRESS_8BIT_VECTOR | commands::RELEASE_8BIT_VECTOR => {
+            let action = if frame.command_id == commands::PRESS_8BIT_VECTOR {
+                ButtonAction::Single
+            } else {
+                ButtonAction::Release
+            };
+            let bits = frame.payload.first().copied().unwrap_or(0);
+            (0..8u8)
+                .filter(|i| bits & (1 << i) != 0)
+                .map(|i| (i + 1, action))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Model name for a newly seen GP device
+fn model_for_frame(frame: &GreenPowerFrame) -> (&'static str, Option<&'static str>) {
+    match frame.commissioning_device_id() {
+        Some(device_ids::ON_OFF_SWITCH) => ("Green Power On/Off Switch", None),
+        Some(device_ids::GENERIC_SWITCH) => ("Green Power Generic Switch", None),
+        Some(_) => ("Green Power Device", None),
+        None => match frame.command_id {
+            commands::TOGGLE | commands::RECALL_SCENE_0..=commands::RECALL_SCENE_7 => {
+                ("Hue Tap", Some("Philips"))
+            }
+            _ => ("Green Power Generic Switch", None),
+        },
+    }
+}
+
+fn new_virtual_device(frame: &GreenPowerFrame) -> ZigbeeDevice {
+    let (model, manufacturer) = model_for_frame(frame);
+    let mut device = ZigbeeDevice::new(ieee_for_source_id(frame.source_id), NO_SHORT_ADDRESS);
+    device.device_type = DeviceType::EndDevice;
+    device.category = DeviceCategory::Switch;
+    device.model = Some(model.to_string());
+    device.manufacturer = manufacturer.map(ToString::to_string);
+    device.endpoints.push(Endpoint {
+        id: GREEN_POWER_ENDPOINT,
+        profile_id: GREEN_POWER_PROFILE,
+        device_id: u16::from(frame.commissioning_device_id().unwrap_or(0)),
+        in_clusters: Vec::new(),
+        out_clusters: vec![GREEN_POWER_CLUSTER],
+    });
+    device
+}
+
+/// How a commissioned GPD secures its frames
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct GpdSecurity {
+    pub(crate) security_level: u8,
+    pub(crate) key: Option<[u8; 16]>,
+    /// Highest frame counter seen; secured frames must exceed it
+    pub(crate) frame_counter: u32,
+}
+
+/// Commissioned GPDs by source ID, and the last unsecured frame of each
+#[derive(Debug, Default)]
+pub(crate) struct GreenPowerState {
+    /// `green_power.json`; nothing is saved without one
+    path: Option<PathBuf>,
+    commissioned: HashMap<u32, GpdSecurity>,
+    last_unsecured: HashMap<u32, (u8, Vec<u8>, Instant)>,
+}
+
+impl GreenPowerState {
+    /// Load the commissioned devices from `path`, if it exists
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let commissioned = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+        Self {
+            path: Some(path),
+            commissioned,
+            last_unsecured: HashMap::new(),
+        }
+    }
+
+    fn save_in_background(&self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let commissioned = self.commissioned.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = save(&path, &commissioned) {
+                tracing::warn!("Failed to save Green Power devices: {}", e);
+            }
+        });
+    }
+
+    /// Check a frame from a commissioned device, returning it decrypted;
+    /// `None` drops it
+    fn accept(&mut self, frame: &GreenPowerFrame) -> Option<GreenPowerFrame> {
+        let security = self.commissioned.get_mut(&frame.source_id)?;
+        if frame.security_level < security.security_level {
+            tracing::warn!(
+                "Dropping GP frame from {:#010x}: security level {} below {}",
+                frame.source_id,
+                frame.security_level,
+                security.security_level
+            );
+            return None;
+        }
+
+        if frame.security_level < 2 {
+            let now = Instant::now();
+            if let Some((command_id, payload, at)) = self.last_unsecured.get(&frame.source_id) {
+                if *command_id == frame.command_id
+                    && *payload == frame.payload
+                    && now.duration_since(*at) < REPEAT_WINDOW
+                {
+                    tracing::trace!("Dropping repeated GP frame from {:#010x}", frame.source_id);
+                    return None;
+                }
+            }
+            self.last_unsecured.insert(
+                frame.source_id,
+                (frame.command_id, frame.payload.clone(), now),
+            );
+            return Some(frame.clone());
+        }
+
+        let counter = frame.frame_counter?;
+        if counter <= security.frame_counter {
+            tracing::trace!(
+                "Dropping replayed GP frame from {:#010x} (counter {})",
+                frame.source_id,
+                counter
+            );
+            return None;
+        }
+        let Some(verified) = security.key.as_ref().and_then(|key| frame.verify(key)) else {
+            tracing::warn!(
+                "Dropping GP frame from {:#010x}: MIC check failed",
+                frame.source_id
+            );
+            return None;
+        };
+        security.frame_counter = counter;
+        self.save_in_background();
+        Some(verified)
+    }
+}
+
+/// Write the file readable by the owner only, as it holds keys
+fn save(path: &Path, commissioned: &HashMap<u32, GpdSecurity>) -> io::Result<()> {
+    use std::io::Write;
+
+    let json = serde_json::to_string_pretty(commissioned)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp_path = path.with_extension("json.tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&tmp_path)?.write_all(json.as_bytes())?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Security a frame commissions its device with, if it can commission one
+fn commissioning_security(frame: &GreenPowerFrame) -> Option<GpdSecurity> {
+    if !frame.is_commissioning() {
+        // Devices without security are taken on from a press
+        return (frame.security_level == 0 && !button_events(frame).is_empty()).then_some(
+            GpdSecurity {
+                security_level: 0,
+                key: None,
+                frame_counter: 0,
+            },
+        );
+    }
+    let commissioning = frame.commissioning()?;
+    let key = commissioning.gpd_key(frame.source_id);
+    if commissioning.security_level >= 2 && key.is_none() {
+        tracing::warn!(
+            "GP device {:#010x} commissioned without a usable key",
+            frame.source_id
+        );
+        return None;
+    }
+    Some(GpdSecurity {
+        security_level: commissioning.security_level,
+        key,
+        // The reported counter is the one the next frame uses
+        frame_counter: commissioning
+            .outgoing_counter
+            .map_or(0, |counter| counter.saturating_sub(1)),
+    })
+}
+
+/// Take a device on
+fn commission(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    state: &mut GreenPowerState,
+    frame: &GreenPowerFrame,
+    mut security: GpdSecurity,
+) {
+    let ieee = ieee_for_source_id(frame.source_id);
+    if let Some(previous) = state.commissioned.get(&frame.source_id) {
+        if devices.contains_key(&ieee) && security.security_level < previous.security_level {
+            tracing::warn!(
+                "Refusing to lower the security level of GP device {:#010x}; remove it first",
+                frame.source_id
+            );
+            return;
+        }
+        // A replayed commissioning frame mustn't reopen old frame counters
+        security.frame_counter = security.frame_counter.max(previous.frame_counter);
+    }
+    if state.commissioned.get(&frame.source_id) != Some(&security) {
+        state.commissioned.insert(frame.source_id, security);
+        state.save_in_background();
+    }
+
+    let known = network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+        device.nwk_address = NO_SHORT_ADDRESS;
+        availability::mark_seen(device, event_tx);
+    });
+    if known.is_some() {
+        return;
+    }
+    let mut device = new_virtual_device(frame);
+    device.last_seen = Some(Utc::now());
+    tracing::info!(
+        "Green Power device {:#010x} added as {}",
+        frame.source_id,
+        device.display_name()
+    );
+    devices.insert(ieee, device.clone());
+    let _ = event_tx.send(NetworkEvent::DeviceJoined(device));
+    network::save_in_background(devices, storage);
+}
+
+/// Handle a GP frame
+///
+/// Devices are only commissioned while `joining_open`.
+pub(crate) fn handle_frame(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    state: &mut GreenPowerState,
+    joining_open: bool,
+    frame: &GreenPowerFrame,
+) {
+    if frame.is_commissioning() || !state.commissioned.contains_key(&frame.source_id) {
+        if !joining_open {
+            tracing::debug!(
+                "Ignoring GP command {:#04x} from {:#010x}: not commissioned while joining was open",
+                frame.command_id,
+                frame.source_id
+            );
+            return;
+        }
+        let Some(security) = commissioning_security(frame) else {
+            tracing::debug!(
+                "GP command {:#04x} from unknown source {:#010x}",
+                frame.command_id,
+                frame.source_id
+            );
+            return;
+        };
+        commission(devices, event_tx, storage, state, frame, security);
+        if frame.is_commissioning() {
+            return;
+        }
+    }
+
+    let Some(frame) = state.accept(frame) else {
+        return;
+    };
+    let ieee = ieee_for_source_id(frame.source_id);
+
+    if frame.command_id == commands::DECOMMISSIONING {
+        state.commissioned.remove(&frame.source_id);
+        state.save_in_background();
+        if devices.remove(&ieee).is_some() {
+            tracing::info!(
+                "Green Power device {:#010x} decommissioned",
+                frame.source_id
+            );
+            let _ = event_tx.send(NetworkEvent::DeviceLeft { ieee_address: ieee });
+            network::save_in_background(devices, storage);
+        }
+        return;
+    }
+
+    network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+        availability::mark_seen(device, event_tx);
+    });
+    for (button, action) in button_events(&frame) {
+        tracing::info!(
+            "Green Power device {:#010x} button {} {:?}",
+            frame.source_id,
+            button,
+            action
+        );
+        let _ = event_tx.send(NetworkEvent::ButtonEvent {
+            ieee_address: ieee,
+            endpoint: button,
+            action,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(command_id: u8, payload: Vec<u8>) -> GreenPowerFrame {
+        GreenPowerFrame {
+            frame_type: 0,
+            auto_commissioning: false,
+            application_id: 0,
+            security_level: 0,
+            source_id: 0x0102_0304,
+            frame_counter: None,
+            command_id,
+            payload,
+            header: Vec::new(),
+            mic: None,
+        }
+    }
+
+    /// Key 00..0F, SrcID 0x01020304
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F,
+    ];
+
+    /// Commissioning of a generic switch at security level 2, with the key
+    /// in the clear and counter 9 next
+    fn commissioning_frame() -> GreenPowerFrame {
+        let mut data = vec![
+            0x00, 0x00, 0x0C, 0x04, 0x03, 0x02, 0x01, 0xE0, 0x07, 0x80, 0xA2,
+        ];
+        data.extend_from_slice(&KEY);
+        data.extend_from_slice(&9u32.to_le_bytes());
+        data[0] = u8::try_from(data.len() - 2).unwrap();
+        GreenPowerFrame::parse(&data).unwrap()
+    }
+
+    /// Button 1 pressed, frame counter 9, authenticated with [`KEY`]
+    const SECURED_PRESS: [u8; 18] = [
+        0x10, 0x00, 0x8C, 0x10, 0x04, 0x03, 0x02, 0x01, 0x09, 0x00, 0x00, 0x00, 0x69, 0x01, 0xC2,
+        0x84, 0x59, 0xCE,
+    ];
+
+    fn presses(events: &mut broadcast::Receiver<NetworkEvent>) -> usize {
+        std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|e| matches!(e, NetworkEvent::ButtonEvent { .. }))
+            .count()
+    }
+
+    #[test]
+    fn test_hue_tap_buttons() {
+        assert_eq!(
+            button_events(&frame(commands::TOGGLE, vec![])),
+            vec![(1, ButtonAction::Single)]
+        );
+        assert_eq!(
+            button_events(&frame(0x12, vec![])),
+            vec![(4, ButtonAction::Single)]
+        );
+    }
+
+    #[test]
+    fn test_vector_press() {
+        assert_eq!(
+            button_events(&frame(commands::RELEASE_8BIT_VECTOR, vec![0b0000_0101])),
+            vec![(1, ButtonAction::Release), (3, ButtonAction::Release)]
+        );
+    }
+
+    #[test]
+    fn test_unsecured_device_commissioned_while_joining() {
+        let (event_tx, mut events) = broadcast::channel(16);
+        let devices = DashMap::new();
+        let mut state = GreenPowerState::default();
+        let toggle = frame(commands::TOGGLE, vec![]);
+
+        handle_frame(&devices, &event_tx, None, &mut state, false, &toggle);
+        assert!(devices.is_empty());
+        assert_eq!(presses(&mut events), 0);
+
+        handle_frame(&devices, &event_tx, None, &mut state, true, &toggle);
+        let device = devices.get(&ieee_for_source_id(0x0102_0304)).unwrap();
+        assert_eq!(device.nwk_address, NO_SHORT_ADDRESS);
+        drop(device);
+        assert_eq!(presses(&mut events), 1);
+
+        // The same press repeated, then the next one with joining closed
+        handle_frame(&devices, &event_tx, None, &mut state, false, &toggle);
+        assert_eq!(presses(&mut events), 0);
+        handle_frame(
+            &devices,
+            &event_tx,
+            None,
+            &mut state,
+            false,
+            &frame(0x10, vec![]),
+        );
+        assert_eq!(presses(&mut events), 1);
+    }
+
+    #[test]
+    fn test_replayed_frame_dropped() {
+        let (event_tx, mut events) = broadcast::channel(16);
+        let devices = DashMap::new();
+        let mut state = GreenPowerState::default();
+        handle_frame(
+            &devices,
+            &event_tx,
+            None,
+            &mut state,
+            true,
+            &commissioning_frame(),
+        );
+
+        let press = GreenPowerFrame::parse(&SECURED_PRESS).unwrap();
+        handle_frame(&devices, &event_tx, None, &mut state, false, &press);
+        assert_eq!(presses(&mut events), 1);
+        handle_frame(&devices, &event_tx, None, &mut state, false, &press);
+        assert_eq!(presses(&mut events), 0);
+
+        // Recommissioning doesn't rewind the counter
+        handle_frame(
+            &devices,
+            &event_tx,
+            None,
+            &mut state,
+            true,
+            &commissioning_frame(),
+        );
+        handle_frame(&devices, &event_tx, None, &mut state, false, &press);
+        assert_eq!(presses(&mut events), 0);
+    }
+
+    #[test]
+    fn test_forged_frame_dropped() {
+        let (event_tx, mut events) = broadcast::channel(16);
+        let devices = DashMap::new();
+        let mut state = GreenPowerState::default();
+        handle_frame(
+            &devices,
+            &event_tx,
+            None,
+            &mut state,
+            true,
+            &commissioning_frame(),
+        );
+
+        // Button 2 instead of 1, with a newer counter but the old MIC
+        let mut forged = SECURED_PRESS;
+        forged[8] = 0x0A;
+        forged[13] = 0x02;
+        let forged = GreenPowerFrame::parse(&forged).unwrap();
+        handle_frame(&devices, &event_tx, None, &mut state, false, &forged);
+
+        // Unsecured frames from a secured device
+        handle_frame(
+            &devices,
+            &event_tx,
+            None,
+            &mut state,
+            true,
+            &frame(commands::TOGGLE, vec![]),
+        );
+        let decommission = frame(commands::DECOMMISSIONING, vec![]);
+        handle_frame(&devices, &event_tx, None, &mut state, false, &decommission);
+
+        assert_eq!(presses(&mut events), 0);
+        assert!(devices.contains_key(&ieee_for_source_id(0x0102_0304)));
+        assert_eq!(state.commissioned[&0x0102_0304].frame_counter, 8);
+    }
+}
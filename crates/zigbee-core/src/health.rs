@@ -0,0 +1,99 @@
+//! Device health notes
+//!
+//! When automation actions aimed at a device keep failing, the automation
+//! engine attaches a note to the device naming the automation and its last
+//! error. The note is saved with the device and shown with it, so a light
+//! that "stopped working" explains itself, and it is removed once the
+//! automation reaches the device again.
+
+use crate::network::ZigbeeNetwork;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// An automation that fails to control a device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthNote {
+    pub automation_id: String,
+    /// Failed runs in a row
+    pub failures: u32,
+    pub last_error: String,
+    /// When the note was attached
+    pub since: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ZigbeeNetwork {
+    /// Attach a note for a failing automation, or update its failure count
+    /// and error
+    pub fn note_automation_failure(
+        &self,
+        ieee: &[u8; 8],
+        automation_id: &str,
+        failures: u32,
+        error: &str,
+    ) {
+        let now = Utc::now();
+        self.update_device(ieee, |device| {
+            if let Some(note) = device
+                .health_notes
+                .iter_mut()
+                .find(|n| n.automation_id == automation_id)
+            {
+                note.failures = failures;
+                note.last_error = error.to_string();
+                note.updated_at = now;
+                return;
+            }
+            tracing::warn!(
+                "Automation {} keeps failing to control {}: {}",
+                automation_id,
+                device.display_name(),
+                error
+            );
+            device.health_notes.push(HealthNote {
+                automation_id: automation_id.to_string(),
+                failures,
+                last_error: error.to_string(),
+                since: now,
+                updated_at: now,
+            });
+        });
+    }
+
+    /// Remove an automation's note from a device, if it has one
+    pub fn clear_automation_failure(&self, ieee: &[u8; 8], automation_id: &str) {
+        self.update_device(ieee, |device| {
+            device
+                .health_notes
+                .retain(|n| n.automation_id != automation_id);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::ZigbeeDevice;
+    use crate::network::ZigbeeNetwork;
+    use deconz_protocol::mock::MockTransport;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_health_notes() {
+        let network = ZigbeeNetwork::with_transport(Arc::new(MockTransport::new()), None)
+            .await
+            .unwrap();
+        network.upsert_device(ZigbeeDevice::new([1; 8], 0x1234));
+
+        network.note_automation_failure(&[1; 8], "porch", 3, "no route");
+        network.note_automation_failure(&[1; 8], "porch", 4, "no ack");
+        let notes = network.get_device(&[1; 8]).unwrap().health_notes;
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].failures, 4);
+        assert_eq!(notes[0].last_error, "no ack");
+
+        network.clear_automation_failure(&[1; 8], "other");
+        assert_eq!(network.get_device(&[1; 8]).unwrap().health_notes.len(), 1);
+        network.clear_automation_failure(&[1; 8], "porch");
+        assert!(network.get_device(&[1; 8]).unwrap().health_notes.is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+//! IAS Zone support (contact, motion, water leak and smoke sensors)
+//!
+//! IAS Zone devices stay silent until they are enrolled with a CIE. The
+//! coordinator writes its IEEE address to the sensor's `IAS_CIE_Address`
+//! attribute, answers the Zone Enroll Request that follows with a zone ID,
+//! and from then on receives Zone Status Change Notifications, which are
+//! emitted as [`NetworkEvent::IasAlarm`].
+
+use crate::attributes::WriteAttributes;
+use crate::cluster::{
+    self, ias_zone_attrs, id, parse_attribute_report, AttributeValue, GlobalCommand,
+};
+use crate::codec::ZclValue;
+use crate::device::ZigbeeDevice;
+use crate::network::{self, DeviceStorage, NetworkError, NetworkEvent, ZigbeeNetwork};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, Transport, ZclFrame};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Zone Status Change Notification (server to client)
+const ZONE_STATUS_CHANGE_NOTIFICATION: u8 = 0x00;
+/// Zone Enroll Request (server to client)
+const ZONE_ENROLL_REQUEST: u8 = 0x01;
+/// Zone Enroll Response (client to server)
+const ZONE_ENROLL_RESPONSE: u8 = 0x00;
+/// Enroll response code: success
+const ENROLL_SUCCESS: u8 = 0x00;
+/// Highest valid zone ID (0xFF means "not enrolled")
+const MAX_ZONE_ID: u8 = 0xFE;
+
+/// Zone status bits
+pub mod zone_status {
+    pub const ALARM1: u16 = 0x0001;
+    pub const ALARM2: u16 = 0x0002;
+    pub const TAMPER: u16 = 0x0004;
+    pub const BATTERY_LOW: u16 = 0x0008;
+    pub const SUPERVISION_REPORTS: u16 = 0x0010;
+    pub const RESTORE_REPORTS: u16 = 0x0020;
+    pub const TROUBLE: u16 = 0x0040;
+    pub const AC_MAINS_FAULT: u16 = 0x0080;
+}
+
+/// Whether a zone status has either alarm bit set (open, motion, leak...)
+#[must_use]
+pub fn is_alarm(status: u16) -> bool {
+    status & (zone_status::ALARM1 | zone_status::ALARM2) != 0
+}
+
+/// IAS Zone state of a device
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IasZone {
+    /// Zone type from the enroll request (0x0015 contact, 0x000D motion...)
+    pub zone_type: u16,
+    /// Zone ID assigned on enrollment
+    pub zone_id: Option<u8>,
+    /// Last reported zone status bits
+    pub zone_status: u16,
+}
+
+impl IasZone {
+    /// Whether the zone currently reports an alarm
+    #[must_use]
+    pub fn alarm(&self) -> bool {
+        is_alarm(self.zone_status)
+    }
+
+    /// Name of the zone type, if it is a standard one
+    #[must_use]
+    pub fn zone_type_name(&self) -> Option<&'static str> {
+        Some(match self.zone_type {
+            0x0000 => "standard_cie",
+            0x000D => "motion",
+            0x0015 => "contact",
+            0x0028 => "fire",
+            0x002A => "water",
+            0x002B => "carbon_monoxide",
+            0x002C => "personal_emergency",
+            0x002D => "vibration",
+            0x010F => "remote_control",
+            0x0115 => "key_fob",
+            0x021D => "keypad",
+            0x0225 => "warning_device",
+            0x0226 => "glass_break",
+            0x0229 => "security_repeater",
+            _ => return None,
+        })
+    }
+}
+
+impl ZigbeeNetwork {
+    /// Enroll an IAS Zone endpoint with the coordinator
+    ///
+    /// Called automatically when a device advertises the IAS Zone cluster;
+    /// use it to re-enroll sensors that were paired before enrollment was
+    /// supported.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn enroll_ias_zone(&self, ieee: &[u8; 8], endpoint: u8) -> Result<(), NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        enroll(
+            self.transport(),
+            self.devices(),
+            self.event_sender(),
+            self.storage(),
+            device.nwk_address,
+            endpoint,
+        )
+        .await
+    }
+}
+
+/// Write our address to the zone's CIE attribute and send an unsolicited
+/// Enroll Response, which sensors that never send an Enroll Request accept
+pub(crate) async fn enroll(
+    transport: &dyn Transport,
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    nwk_address: u16,
+    endpoint: u8,
+) -> Result<(), NetworkError> {
+    let coordinator_ieee = transport.read_mac_address().await?;
+    let zcl_frame = WriteAttributes::new()
+        .attribute(
+            ias_zone_attrs::IAS_CIE_ADDRESS,
+            ZclValue::Ieee(coordinator_ieee),
+        )
+        .frame(cluster::next_tsn());
+    tracing::info!(
+        "Enrolling IAS zone {:#06x}:{} with the coordinator",
+        nwk_address,
+        endpoint
+    );
+    transport
+        .send_aps_request(ApsDataRequest::new(
+            1,
+            nwk_address,
+            endpoint,
+            id::IAS_ZONE,
+            zcl_frame.serialize(),
+        ))
+        .await?;
+
+    let Some(zone_id) = assign_zone_id(devices, event_tx, storage, nwk_address, None) else {
+        return Err(NetworkError::DeviceNotFound(format!("{nwk_address:#06x}")));
+    };
+    transport
+        .send_aps_request(enroll_response(
+            nwk_address,
+            endpoint,
+            cluster::next_tsn(),
+            zone_id,
+        ))
+        .await?;
+    Ok(())
+}
+
+fn enroll_response(
+    nwk_address: u16,
+    endpoint: u8,
+    transaction_seq: u8,
+    zone_id: u8,
+) -> ApsDataRequest {
+    let zcl_frame = ZclFrame::cluster_command(transaction_seq, ZONE_ENROLL_RESPONSE)
+        .with_payload(vec![ENROLL_SUCCESS, zone_id]);
+    ApsDataRequest::new(
+        1,
+        nwk_address,
+        endpoint,
+        id::IAS_ZONE,
+        zcl_frame.serialize(),
+    )
+}
+
+/// Give the device at `nwk_address` a zone ID, reusing the one it already has
+///
+/// Returns `None` if the device is unknown or all zone IDs are taken.
+fn assign_zone_id(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    nwk_address: u16,
+    zone_type: Option<u16>,
+) -> Option<u8> {
+    let used: Vec<u8> = devices
+        .iter()
+        .filter(|d| d.nwk_address != nwk_address)
+        .filter_map(|d| d.ias_zone.as_ref().and_then(|z| z.zone_id))
+        .collect();
+    let ieee = network::ieee_for_nwk(devices, nwk_address)?;
+    network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+        let zone = device.ias_zone.get_or_insert_with(IasZone::default);
+        if let Some(zone_type) = zone_type {
+            zone.zone_type = zone_type;
+        }
+        let zone_id = match zone.zone_id {
+            Some(id) if !used.contains(&id) => id,
+            _ => (0..=MAX_ZONE_ID).find(|id| !used.contains(id))?,
+        };
+        zone.zone_id = Some(zone_id);
+        Some(zone_id)
+    })
+    .flatten()
+}
+
+/// Zone status from an attribute report, if it contains one
+fn reported_zone_status(payload: &[u8]) -> Option<u16> {
+    parse_attribute_report(payload)
+        .iter()
+        .find(|a| a.id == ias_zone_attrs::ZONE_STATUS)
+        .and_then(AttributeValue::as_u32)
+        .and_then(|v| u16::try_from(v).ok())
+}
+
+/// Handle an IAS Zone frame
+pub(crate) fn handle_frame(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    transport: &Arc<dyn Transport>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    let src = indication.src_short_addr;
+    let Some(ieee_address) = network::ieee_for_nwk(devices, src) else {
+        tracing::debug!("IAS Zone frame from unknown device {:#06x}", src);
+        return;
+    };
+
+    let status = if zcl.is_cluster_specific() {
+        match zcl.command_id() {
+            ZONE_STATUS_CHANGE_NOTIFICATION if zcl.payload().len() >= 2 => {
+                u16::from_le_bytes([zcl.payload()[0], zcl.payload()[1]])
+            }
+            ZONE_ENROLL_REQUEST if zcl.payload().len() >= 2 => {
+                let zone_type = u16::from_le_bytes([zcl.payload()[0], zcl.payload()[1]]);
+                let Some(zone_id) =
+                    assign_zone_id(devices, event_tx, storage, src, Some(zone_type))
+                else {
+                    tracing::warn!("No free IAS zone ID for {:#06x}", src);
+                    return;
+                };
+                tracing::info!(
+                    "IAS zone {:#06x}:{} (type {:#06x}) enrolled as zone {}",
+                    src,
+                    indication.src_endpoint,
+                    zone_type,
+                    zone_id
+                );
+                let request =
+                    enroll_response(src, indication.src_endpoint, zcl.transaction_seq(), zone_id);
+                let transport = Arc::clone(transport);
+                tokio::spawn(async move {
+                    if let Err(e) = transport.send_aps_request(request).await {
+                        tracing::warn!("Failed to send zone enroll response: {}", e);
+                    }
+                });
+                return;
+            }
+            cmd => {
+                tracing::debug!("Unhandled IAS Zone command {:#04x} from {:#06x}", cmd, src);
+                return;
+            }
+        }
+    } else if zcl.command_id() == GlobalCommand::ReportAttributes as u8 {
+        let Some(status) = reported_zone_status(zcl.payload()) else {
+            return;
+        };
+        status
+    } else {
+        return;
+    };
+
+    network::update_in_place(devices, event_tx, storage, &ieee_address, |device| {
+        device
+            .ias_zone
+            .get_or_insert_with(IasZone::default)
+            .zone_status = status;
+    });
+    tracing::info!("IAS zone {:#06x} status {:#06x}", src, status);
+    let _ = event_tx.send(NetworkEvent::IasAlarm {
+        ieee_address,
+        zone_status: status,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reported_zone_status() {
+        // Zone state (enum8) followed by zone status (bitmap16)
+        assert_eq!(
+            reported_zone_status(&[0x00, 0x00, 0x30, 0x01, 0x02, 0x00, 0x19, 0x21, 0x00]),
+            Some(0x0021)
+        );
+        // Unknown data type stops parsing
+        assert_eq!(
+            reported_zone_status(&[0x05, 0x00, 0x4C, 0x01, b'x', 0x02, 0x00, 0x19, 0x01, 0x00]),
+            None
+        );
+        assert!(is_alarm(0x0021));
+        assert!(!is_alarm(zone_status::TAMPER | zone_status::BATTERY_LOW));
+    }
+
+    #[test]
+    fn test_assign_zone_id_reuses_and_skips_taken() {
+        let devices = DashMap::new();
+        let mut first = ZigbeeDevice::new([1; 8], 0x1111);
+        first.ias_zone = Some(IasZone {
+            zone_id: Some(0),
+            ..IasZone::default()
+        });
+        devices.insert(first.ieee_address, first);
+        devices.insert([2; 8], ZigbeeDevice::new([2; 8], 0x2222));
+        let (event_tx, _) = broadcast::channel(8);
+        let assign = |nwk, zone_type| assign_zone_id(&devices, &event_tx, None, nwk, zone_type);
+
+        assert_eq!(assign(0x2222, Some(0x0015)), Some(1));
+        assert_eq!(assign(0x2222, None), Some(1));
+        assert_eq!(assign(0x1111, None), Some(0));
+        assert_eq!(
+            devices
+                .get(&[2; 8])
+                .unwrap()
+                .ias_zone
+                .as_ref()
+                .unwrap()
+                .zone_type,
+            0x0015
+        );
+        assert_eq!(assign(0x3333, None), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Identify sweep
+//!
+//! The coordinator broadcasts an Identify Query and collects the answers
+//! with the link quality they arrived at. Only devices that are identifying
+//! answer, so a sweep can first broadcast Identify to set every device
+//! blinking: walking from room to room with the sweep results then shows
+//! which unlabeled device is which and how well each one reaches the
+//! coordinator. Devices that stay silent are listed too.
+//!
+//! ```text
+//! coordinator                        devices
+//!   | -- Identify (broadcast) -------> |   optional, every device blinks
+//!   | -- Identify Query (broadcast) -> |
+//!   | <-- Identify Query Response ---- |   from each identifying endpoint
+//! ```
+
+use crate::cluster;
+use crate::network::{NetworkError, ZigbeeNetwork};
+use deconz_protocol::{
+    clusters, ApsDataIndication, ApsDataRequest, Broadcast, DeconzEvent, ZclFrame,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Endpoint addressing every endpoint of a device
+const ALL_ENDPOINTS: u8 = 0xFF;
+
+/// Identify cluster commands
+mod command {
+    /// Client to server
+    pub const IDENTIFY: u8 = 0x00;
+    pub const IDENTIFY_QUERY: u8 = 0x01;
+    /// Server to client
+    pub const IDENTIFY_QUERY_RESPONSE: u8 = 0x00;
+}
+
+/// A device that answered the Identify Query
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentifyResponse {
+    /// Unknown for devices that aren't in the device list
+    pub ieee_address: Option<String>,
+    pub name: Option<String>,
+    pub nwk_address: u16,
+    pub endpoint: u8,
+    /// Link quality of the answer's last hop to the coordinator
+    pub lqi: u8,
+    pub rssi: i8,
+    /// Seconds the device will keep identifying
+    pub identify_secs: u16,
+}
+
+/// Result of an identify sweep
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentifySweep {
+    pub duration_secs: u64,
+    /// How long devices were asked to identify before the query (0 if not)
+    pub identify_secs: u16,
+    /// One entry per answering device, best link quality first
+    pub responses: Vec<IdentifyResponse>,
+    /// Known devices that didn't answer, when they were asked to identify
+    pub silent: Vec<String>,
+}
+
+fn broadcast_request(frame: &ZclFrame) -> ApsDataRequest {
+    ApsDataRequest::broadcast(
+        1,
+        Broadcast::AllDevices,
+        ALL_ENDPOINTS,
+        clusters::IDENTIFY,
+        frame.serialize(),
+    )
+}
+
+/// Identify time of an Identify Query Response to `transaction_seq`
+fn query_response(indication: &ApsDataIndication, transaction_seq: u8) -> Option<u16> {
+    if indication.cluster_id != clusters::IDENTIFY {
+        return None;
+    }
+    let zcl = ZclFrame::parse(&indication.asdu).ok()?;
+    if !zcl.is_cluster_specific()
+        || !zcl.is_from_server()
+        || zcl.transaction_seq() != transaction_seq
+        || zcl.command_id() != command::IDENTIFY_QUERY_RESPONSE
+    {
+        return None;
+    }
+    let payload = zcl.payload();
+    Some(u16::from_le_bytes([*payload.first()?, *payload.get(1)?]))
+}
+
+impl ZigbeeNetwork {
+    /// Broadcast an Identify Query and collect the answers for `duration`
+    ///
+    /// With `identify_secs` above 0, every device is first told to
+    /// identify for that long, so all of them answer.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn identify_sweep(
+        &self,
+        duration: Duration,
+        identify_secs: u16,
+    ) -> Result<IdentifySweep, NetworkError> {
+        let mut rx = self.transport().subscribe();
+
+        if identify_secs > 0 {
+            let identify = ZclFrame::cluster_command(cluster::next_tsn(), command::IDENTIFY)
+                .with_payload(identify_secs.to_le_bytes().to_vec());
+            self.transport()
+                .send_aps_request(broadcast_request(&identify))
+                .await?;
+        }
+        let transaction_seq = cluster::next_tsn();
+        let query = ZclFrame::cluster_command(transaction_seq, command::IDENTIFY_QUERY);
+        self.transport()
+            .send_aps_request(broadcast_request(&query))
+            .await?;
+        tracing::info!("Identify sweep for {:?}", duration);
+
+        let mut answers: BTreeMap<u16, (ApsDataIndication, u16)> = BTreeMap::new();
+        let deadline = tokio::time::Instant::now() + duration;
+        loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(DeconzEvent::ApsIndication(indication))) => {
+                    if let Some(time) = query_response(&indication, transaction_seq) {
+                        // Keep one answer per device, from its first endpoint
+                        answers
+                            .entry(indication.src_short_addr)
+                            .or_insert((indication, time));
+                    }
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                    tracing::warn!("Identify sweep lagged by {} events", n);
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => break,
+            }
+        }
+
+        let devices = self.get_devices();
+        let mut responses: Vec<IdentifyResponse> = answers
+            .into_values()
+            .map(|(indication, identify_secs)| {
+                let device = devices
+                    .iter()
+                    .find(|d| d.nwk_address == indication.src_short_addr);
+                IdentifyResponse {
+                    ieee_address: device.map(crate::ZigbeeDevice::ieee_address_string),
+                    name: device.map(crate::ZigbeeDevice::display_name),
+                    nwk_address: indication.src_short_addr,
+                    endpoint: indication.src_endpoint,
+                    lqi: indication.lqi,
+                    rssi: indication.rssi,
+                    identify_secs,
+                }
+            })
+            .collect();
+        responses.sort_by_key(|r| std::cmp::Reverse(r.lqi));
+
+        let silent = if identify_secs > 0 {
+            devices
+                .iter()
+                .filter(|d| !responses.iter().any(|r| r.nwk_address == d.nwk_address))
+                .map(crate::ZigbeeDevice::ieee_address_string)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(IdentifySweep {
+            duration_secs: duration.as_secs(),
+            identify_secs,
+            responses,
+            silent,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::ZigbeeDevice;
+    use deconz_protocol::mock::MockTransport;
+    use std::sync::Arc;
+
+    fn query_response_indication(src_short_addr: u16, tsn: u8, lqi: u8) -> ApsDataIndication {
+        let asdu = ZclFrame::cluster_command(tsn, command::IDENTIFY_QUERY_RESPONSE)
+            .from_server()
+            .with_payload(vec![58, 0])
+            .serialize();
+        ApsDataIndication {
+            src_endpoint: 11,
+            lqi,
+            rssi: -60,
+            ..deconz_protocol::mock::indication(src_short_addr, clusters::IDENTIFY, asdu)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_identify_sweep_collects_answers() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        let mut hall = ZigbeeDevice::new([1; 8], 0x1111);
+        hall.friendly_name = Some("Hall".to_string());
+        network.upsert_device(hall);
+        network.upsert_device(ZigbeeDevice::new([2; 8], 0x2222));
+
+        let sweep = tokio::spawn({
+            let network = network.clone();
+            async move { network.identify_sweep(Duration::from_secs(5), 60).await }
+        });
+        while mock.aps_requests().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let sent = mock.aps_requests();
+        assert!(sent
+            .iter()
+            .all(|r| r.dest_short_addr == Broadcast::AllDevices as u16));
+        let tsn = ZclFrame::parse(&sent[1].asdu).unwrap().transaction_seq();
+
+        // A second answer from the same device and an answer to another
+        // query are ignored
+        for (addr, tsn, lqi) in [(0x1111, tsn, 200), (0x1111, tsn, 90), (0x3333, tsn, 120)] {
+            mock.emit(DeconzEvent::ApsIndication(query_response_indication(
+                addr, tsn, lqi,
+            )));
+        }
+        mock.emit(DeconzEvent::ApsIndication(query_response_indication(
+            0x2222,
+            tsn.wrapping_add(1),
+            50,
+        )));
+
+        let sweep = sweep.await.unwrap().unwrap();
+        let answered: Vec<_> = sweep
+            .responses
+            .iter()
+            .map(|r| (r.nwk_address, r.lqi, r.name.clone()))
+            .collect();
+        assert_eq!(
+            answered,
+            vec![(0x1111, 200, Some("Hall".to_string())), (0x3333, 120, None)]
+        );
+        assert_eq!(sweep.responses[0].identify_secs, 58);
+        assert_eq!(
+            sweep.silent,
+            vec![ZigbeeDevice::new([2; 8], 0).ieee_address_string()]
+        );
+    }
+}
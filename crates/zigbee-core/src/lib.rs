@@ -3,10 +3,50 @@
 //! This crate provides high-level Zigbee device and network management
 //! on top of the low-level deCONZ protocol.
 
+pub mod attributes;
+pub mod availability;
+pub mod backup;
+pub mod basic;
+pub mod card;
 pub mod cluster;
+pub mod codec;
+pub mod delivery;
 pub mod device;
+pub mod exposes;
+pub mod formation;
+pub mod green_power;
+pub mod health;
+pub mod ias;
+pub mod identify;
+pub mod light;
 pub mod network;
-pub mod persistence;
+pub mod network_state;
+pub mod ota;
+pub mod poll_control;
+pub mod quirks;
+pub mod scan;
+pub mod sensor;
+pub mod startup;
+pub mod storage;
+pub mod thermostat;
+pub mod topology;
+pub mod touchlink;
+pub mod tuya;
+pub mod utilization;
+pub mod zdo;
 
-pub use device::{DeviceCategory, DeviceType, Endpoint, ZigbeeDevice};
-pub use network::{NetworkEvent, ZigbeeNetwork};
+pub use attributes::WriteAttributes;
+pub use backup::NetworkBackup;
+pub use card::Card;
+pub use codec::ZclValue;
+pub use device::{DeviceCategory, DeviceMetadata, DeviceType, Endpoint, ZigbeeDevice};
+pub use exposes::Expose;
+pub use formation::NetworkConfig;
+pub use health::HealthNote;
+pub use ias::IasZone;
+pub use light::{IdentifyEffect, LightColor, LightState};
+pub use network::{ButtonAction, NetworkEvent, ZigbeeNetwork};
+pub use ota::OtaStatus;
+pub use sensor::SensorKind;
+pub use startup::StartupBehavior;
+pub use utilization::ChannelMonitor;
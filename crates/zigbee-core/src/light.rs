@@ -0,0 +1,483 @@
+//! Lights: level, colour and Identify effects
+//!
+//! The brightness and colour a light was last set to, by a command from
+//! here or by its own attribute reports, are kept on the [`ZigbeeDevice`]
+//! so they can be shown, captured with [`ZigbeeDevice::light_state`] and
+//! put back with [`ZigbeeNetwork::restore_light_state`].
+
+use crate::cluster::{self, attribute_values, id, AttributeValue};
+use crate::device::ZigbeeDevice;
+use crate::network::{self, DeviceStorage, NetworkError, NetworkEvent, ZigbeeNetwork};
+use dashmap::DashMap;
+use deconz_protocol::{clusters, ApsDataIndication, ApsDataRequest, ZclFrame};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Identify cluster: Identify command
+const IDENTIFY_COMMAND: u8 = 0x00;
+/// Identify cluster: Trigger Effect command
+const TRIGGER_EFFECT_COMMAND: u8 = 0x40;
+/// Level Control cluster: Move to Level (with On/Off) command
+const MOVE_TO_LEVEL_WITH_ON_OFF: u8 = 0x04;
+/// Color Control cluster: Move to Hue and Saturation command
+const MOVE_TO_HUE_AND_SATURATION: u8 = 0x06;
+/// Color Control cluster: Move to Color command
+const MOVE_TO_COLOR: u8 = 0x07;
+/// Color Control cluster: Move to Color Temperature command
+const MOVE_TO_COLOR_TEMPERATURE: u8 = 0x0A;
+
+/// Level Control cluster attributes
+mod level_attrs {
+    pub const CURRENT_LEVEL: u16 = 0x0000;
+}
+
+/// Color Control cluster attributes
+mod color_attrs {
+    pub const CURRENT_HUE: u16 = 0x0000;
+    pub const CURRENT_SATURATION: u16 = 0x0001;
+    pub const CURRENT_X: u16 = 0x0003;
+    pub const CURRENT_Y: u16 = 0x0004;
+    pub const COLOR_TEMPERATURE: u16 = 0x0007;
+    pub const COLOR_MODE: u16 = 0x0008;
+}
+
+/// Colour of a light in the mode it was set or reported in (ZCL units)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum LightColor {
+    /// Hue and saturation (0-254)
+    Hs { hue: u8, saturation: u8 },
+    /// CIE 1931 chromaticity (0-65279, i.e. x and y times 65536)
+    Xy { x: u16, y: u16 },
+    /// Colour temperature in mireds
+    ColorTemp { mireds: u16 },
+}
+
+impl LightColor {
+    /// Value of the Color Mode attribute for this colour
+    fn mode(self) -> u32 {
+        match self {
+            LightColor::Hs { .. } => 0,
+            LightColor::Xy { .. } => 1,
+            LightColor::ColorTemp { .. } => 2,
+        }
+    }
+}
+
+/// What a light is showing (see [`ZigbeeDevice::light_state`]); unknown
+/// parts are `None` and left alone on restore
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightState {
+    pub on: Option<bool>,
+    /// Level (1-254)
+    pub brightness: Option<u8>,
+    pub color: Option<LightColor>,
+}
+
+/// Identify effects (Trigger Effect command)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdentifyEffect {
+    /// A single on/off blink
+    Blink = 0x00,
+    /// Fade up and down (about 15 seconds)
+    Breathe = 0x01,
+    /// Green/white flash to acknowledge
+    Okay = 0x02,
+    /// End the running effect after its current cycle
+    Finish = 0xFE,
+    /// End the running effect immediately
+    Stop = 0xFF,
+}
+
+impl ZigbeeNetwork {
+    /// Make a device identify itself (usually blinking) for the given time;
+    /// 0 stops identifying
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn identify(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        duration_secs: u16,
+    ) -> Result<(), NetworkError> {
+        let zcl_frame = ZclFrame::cluster_command(cluster::next_tsn(), IDENTIFY_COMMAND)
+            .with_payload(duration_secs.to_le_bytes().to_vec());
+        self.send_light_command(ieee, endpoint, clusters::IDENTIFY, zcl_frame)
+            .await
+    }
+
+    /// Run an Identify effect on a light
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn trigger_effect(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        effect: IdentifyEffect,
+    ) -> Result<(), NetworkError> {
+        // Effect variant 0 is the only one defined
+        let zcl_frame = ZclFrame::cluster_command(cluster::next_tsn(), TRIGGER_EFFECT_COMMAND)
+            .with_payload(vec![effect as u8, 0]);
+        self.send_light_command(ieee, endpoint, clusters::IDENTIFY, zcl_frame)
+            .await
+    }
+
+    /// Dim a light to `level` (ZCL units, 1-254), switching it on; 0
+    /// switches it off
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_level(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        level: u8,
+        transition_ds: u16,
+    ) -> Result<(), NetworkError> {
+        let level = level.min(254);
+        let mut payload = vec![level];
+        payload.extend_from_slice(&transition_ds.to_le_bytes());
+        let zcl_frame = ZclFrame::cluster_command(cluster::next_tsn(), MOVE_TO_LEVEL_WITH_ON_OFF)
+            .with_payload(payload);
+        self.send_light_command(ieee, endpoint, clusters::LEVEL_CONTROL, zcl_frame)
+            .await?;
+
+        let on = level > 0;
+        let was_on = self.update_device(ieee, |device| {
+            if on {
+                device.brightness = Some(level);
+            }
+            device.state_on.replace(on)
+        });
+        if was_on.is_some_and(|was_on| was_on != Some(on)) {
+            self.emit(NetworkEvent::DeviceStateChanged {
+                ieee_address: *ieee,
+                endpoint,
+                state_on: on,
+            });
+        }
+        Ok(())
+    }
+
+    /// Set the colour of a colour light
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_color(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        color: LightColor,
+        transition_ds: u16,
+    ) -> Result<(), NetworkError> {
+        let (command, mut payload) = match color {
+            LightColor::Hs { hue, saturation } => (
+                MOVE_TO_HUE_AND_SATURATION,
+                vec![hue.min(254), saturation.min(254)],
+            ),
+            LightColor::Xy { x, y } => {
+                let mut payload = x.to_le_bytes().to_vec();
+                payload.extend_from_slice(&y.to_le_bytes());
+                (MOVE_TO_COLOR, payload)
+            }
+            LightColor::ColorTemp { mireds } => {
+                (MOVE_TO_COLOR_TEMPERATURE, mireds.to_le_bytes().to_vec())
+            }
+        };
+        payload.extend_from_slice(&transition_ds.to_le_bytes());
+        let zcl_frame =
+            ZclFrame::cluster_command(cluster::next_tsn(), command).with_payload(payload);
+        self.send_light_command(ieee, endpoint, clusters::COLOR_CONTROL, zcl_frame)
+            .await?;
+
+        let color = match color {
+            LightColor::Hs { hue, saturation } => LightColor::Hs {
+                hue: hue.min(254),
+                saturation: saturation.min(254),
+            },
+            other => other,
+        };
+        self.update_device(ieee, |device| device.color = Some(color));
+        Ok(())
+    }
+
+    /// Set a colour light's hue and saturation (ZCL units, 0-254)
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_hue_saturation(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        hue: u8,
+        saturation: u8,
+        transition_ds: u16,
+    ) -> Result<(), NetworkError> {
+        self.set_color(
+            ieee,
+            endpoint,
+            LightColor::Hs { hue, saturation },
+            transition_ds,
+        )
+        .await
+    }
+
+    /// Put a light back into a state captured with
+    /// [`ZigbeeDevice::light_state`]
+    ///
+    /// The colour is set before the light is switched off, so it comes
+    /// back on in it; unknown parts of the state are left as they are.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn restore_light_state(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        state: &LightState,
+        transition_ds: u16,
+    ) -> Result<(), NetworkError> {
+        if let Some(color) = state.color {
+            self.set_color(ieee, endpoint, color, transition_ds).await?;
+        }
+        match (state.on, state.brightness) {
+            (Some(false), _) => self.turn_off(ieee, endpoint).await,
+            (_, Some(level)) => {
+                self.set_level(ieee, endpoint, level.max(1), transition_ds)
+                    .await
+            }
+            (Some(true), None) => self.turn_on(ieee, endpoint).await,
+            (None, None) => Ok(()),
+        }
+    }
+
+    async fn send_light_command(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        cluster_id: u16,
+        zcl_frame: ZclFrame,
+    ) -> Result<(), NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        tracing::debug!(
+            "Sending cluster {:#06x} command to {:#06x}:{}",
+            cluster_id,
+            device.nwk_address,
+            endpoint
+        );
+        let request = ApsDataRequest::new(
+            1,
+            device.nwk_address,
+            endpoint,
+            cluster_id,
+            zcl_frame.serialize(),
+        );
+        self.send_command(request).await
+    }
+}
+
+/// Handle a Level Control or Color Control report or read response,
+/// recording the device's light state
+///
+/// Commands on these clusters (from remotes) aren't handled here.
+pub(crate) fn handle_frame(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    if zcl.is_cluster_specific() {
+        return;
+    }
+    let attributes = attribute_values(zcl.command_id(), zcl.payload());
+    if attributes.is_empty() {
+        return;
+    }
+    let Some(ieee) = network::ieee_for_nwk(devices, indication.src_short_addr) else {
+        return;
+    };
+
+    network::update_in_place(devices, event_tx, storage, &ieee, |device| {
+        let (brightness, color) = match indication.cluster_id {
+            id::LEVEL_CONTROL => (
+                reported_level(&attributes).or(device.brightness),
+                device.color,
+            ),
+            id::COLOR_CONTROL => (device.brightness, reported_color(device.color, &attributes)),
+            _ => return,
+        };
+        if (brightness, color) == (device.brightness, device.color) {
+            return;
+        }
+        tracing::debug!(
+            "Device {:#06x} light state: level {:?}, colour {:?}",
+            indication.src_short_addr,
+            brightness,
+            color
+        );
+        device.brightness = brightness;
+        device.color = color;
+    });
+}
+
+/// Current level from Level Control attributes, if reported and not 0 (off)
+fn reported_level(attributes: &[AttributeValue]) -> Option<u8> {
+    attributes
+        .iter()
+        .find(|a| a.id == level_attrs::CURRENT_LEVEL)
+        .and_then(AttributeValue::as_u32)
+        .and_then(|level| u8::try_from(level).ok())
+        .filter(|level| (1..=254).contains(level))
+}
+
+/// Colour after applying Color Control attributes to `current`
+///
+/// Bulbs report every colour attribute whatever mode they're in, so when
+/// the Color Mode attribute is present only attributes of that mode count.
+/// One half of hue/saturation or x/y is merged with `current` if that is in
+/// the same mode; for another mode both halves are needed, since the other
+/// half isn't known.
+fn reported_color(
+    current: Option<LightColor>,
+    attributes: &[AttributeValue],
+) -> Option<LightColor> {
+    let value = |id| {
+        attributes
+            .iter()
+            .find(|a| a.id == id)
+            .and_then(AttributeValue::as_u32)
+    };
+    let byte = |id| value(id).map(|v| u8::try_from(v).unwrap_or(u8::MAX));
+    let word = |id| value(id).map(|v| u16::try_from(v).unwrap_or(u16::MAX));
+
+    let hs = match (
+        current,
+        byte(color_attrs::CURRENT_HUE),
+        byte(color_attrs::CURRENT_SATURATION),
+    ) {
+        (_, Some(hue), Some(saturation)) => Some(LightColor::Hs { hue, saturation }),
+        (Some(LightColor::Hs { hue, saturation }), reported_hue, reported_saturation)
+            if reported_hue.is_some() || reported_saturation.is_some() =>
+        {
+            Some(LightColor::Hs {
+                hue: reported_hue.unwrap_or(hue),
+                saturation: reported_saturation.unwrap_or(saturation),
+            })
+        }
+        _ => None,
+    };
+    let xy = match (
+        current,
+        word(color_attrs::CURRENT_X),
+        word(color_attrs::CURRENT_Y),
+    ) {
+        (_, Some(x), Some(y)) => Some(LightColor::Xy { x, y }),
+        (Some(LightColor::Xy { x, y }), reported_x, reported_y)
+            if reported_x.is_some() || reported_y.is_some() =>
+        {
+            Some(LightColor::Xy {
+                x: reported_x.unwrap_or(x),
+                y: reported_y.unwrap_or(y),
+            })
+        }
+        _ => None,
+    };
+    let temperature =
+        word(color_attrs::COLOR_TEMPERATURE).map(|mireds| LightColor::ColorTemp { mireds });
+
+    let mode = value(color_attrs::COLOR_MODE);
+    [hs, xy, temperature]
+        .into_iter()
+        .flatten()
+        .rfind(|color| mode.is_none_or(|mode| mode == color.mode()))
+        .or(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::parse_attribute_report;
+    use deconz_protocol::mock::MockTransport;
+    use std::sync::Arc;
+
+    const IEEE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    #[test]
+    fn test_reported_color_follows_color_mode() {
+        // Colour mode xy, hue 10, x 0x1234, y 0x5678, colour temperature 370
+        let attributes = parse_attribute_report(&[
+            0x08, 0x00, 0x30, 0x01, 0x00, 0x00, 0x20, 0x0A, 0x03, 0x00, 0x21, 0x34, 0x12, 0x04,
+            0x00, 0x21, 0x78, 0x56, 0x07, 0x00, 0x21, 0x72, 0x01,
+        ]);
+        assert_eq!(
+            reported_color(None, &attributes),
+            Some(LightColor::Xy {
+                x: 0x1234,
+                y: 0x5678
+            })
+        );
+
+        // Without a mode, a lone attribute updates its half of the colour
+        let attributes = parse_attribute_report(&[0x01, 0x00, 0x20, 0x80]);
+        assert_eq!(
+            reported_color(
+                Some(LightColor::Hs {
+                    hue: 10,
+                    saturation: 0
+                }),
+                &attributes
+            ),
+            Some(LightColor::Hs {
+                hue: 10,
+                saturation: 0x80
+            })
+        );
+
+        // A lone x is merged with the stored y, but can't start an xy colour
+        let attributes = parse_attribute_report(&[0x03, 0x00, 0x21, 0x34, 0x12]);
+        assert_eq!(
+            reported_color(Some(LightColor::Xy { x: 1, y: 0x5678 }), &attributes),
+            Some(LightColor::Xy {
+                x: 0x1234,
+                y: 0x5678
+            })
+        );
+        let temperature = Some(LightColor::ColorTemp { mireds: 370 });
+        assert_eq!(reported_color(temperature, &attributes), temperature);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_light_state_round_trips() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+
+        network.set_level(&IEEE, 1, 100, 0).await.unwrap();
+        network
+            .set_color(&IEEE, 1, LightColor::ColorTemp { mireds: 370 }, 0)
+            .await
+            .unwrap();
+        let captured = network.get_device(&IEEE).unwrap().light_state();
+        assert_eq!(
+            captured,
+            LightState {
+                on: Some(true),
+                brightness: Some(100),
+                color: Some(LightColor::ColorTemp { mireds: 370 }),
+            }
+        );
+
+        network
+            .set_color(&IEEE, 1, LightColor::Xy { x: 1, y: 2 }, 0)
+            .await
+            .unwrap();
+        network.turn_off(&IEEE, 1).await.unwrap();
+        let sent = mock.aps_requests().len();
+        network
+            .restore_light_state(&IEEE, 1, &captured, 0)
+            .await
+            .unwrap();
+        let requests = mock.aps_requests();
+        assert_eq!(requests.len(), sent + 2);
+        assert_eq!(requests[sent].cluster_id, clusters::COLOR_CONTROL);
+        assert_eq!(requests[sent + 1].cluster_id, clusters::LEVEL_CONTROL);
+        assert_eq!(network.get_device(&IEEE).unwrap().light_state(), captured);
+    }
+}
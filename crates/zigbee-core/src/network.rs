@@ -1,16 +1,24 @@
 //! Zigbee network management
 
+use crate::availability;
+use crate::delivery::{self, DeliveryLog};
 use crate::device::{DeviceCategory, DeviceType, ZigbeeDevice};
-use crate::persistence;
+use crate::green_power;
+use crate::network_state::NetworkStateTracker;
+use crate::ota::{self, OtaServer, OtaStatus};
+use crate::poll_control::{self, PendingCommands};
+use crate::sensor::{self, SensorKind};
+use crate::storage::{self, Storage};
+use crate::{basic, cluster, ias, light, quirks, tuya};
+use chrono::Utc;
 use dashmap::DashMap;
 use deconz_protocol::{
     clusters, profiles, ActiveEndpointsResponse, ApsDataIndication, ApsDataRequest, DeconzEvent,
-    DeconzTransport, NetworkParameter, OnOffCommand, SimpleDescriptorResponse, ZclFrame,
-    ZdoCluster,
+    DeconzTransport, NetworkStateCommand, OnOffCommand, SerialBackend, SimpleDescriptorResponse,
+    Transport, ZclFrame, ZdoCluster, NO_SHORT_ADDRESS,
 };
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
 use thiserror::Error;
 use tokio::sync::broadcast;
 
@@ -25,10 +33,29 @@ pub enum NetworkError {
 
     #[error("Network not connected")]
     NotConnected,
+
+    #[error("Network configuration error: {0}")]
+    Configuration(String),
+
+    #[error("Invalid network configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Device was modified (current revision {current}, expected {expected})")]
+    RevisionMismatch { expected: u64, current: u64 },
+
+    #[error("Device didn't receive the command: {0}")]
+    Delivery(deconz_protocol::ConfirmStatus),
+
+    #[error("Device rejected the command: {0}")]
+    Rejected(crate::cluster::ZclStatus),
+
+    #[error("Device storage error: {0}")]
+    Storage(#[from] std::io::Error),
 }
 
 /// Network events
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // Joins are rare; boxing would complicate every match
 pub enum NetworkEvent {
     /// A new device joined the network
     DeviceJoined(ZigbeeDevice),
@@ -38,16 +65,94 @@ pub enum NetworkEvent {
     DeviceUpdated { ieee_address: [u8; 8] },
     /// Network state changed
     NetworkStateChanged { connected: bool },
+    /// The serial link to the adapter was lost
+    AdapterDisconnected,
+    /// Joining was opened or closed (see [`crate::network_state`])
+    PermitJoinChanged {
+        permit_join: bool,
+        /// Seconds the network stays open, 0 when closed
+        duration_secs: u8,
+    },
+    /// A device went offline or came back (see [`crate::availability`])
+    AvailabilityChanged {
+        ieee_address: [u8; 8],
+        available: bool,
+    },
     /// Device on/off state changed
     DeviceStateChanged {
         ieee_address: [u8; 8],
         endpoint: u8,
         state_on: bool,
     },
+    /// A button on a remote or switch was pressed
+    ButtonEvent {
+        ieee_address: [u8; 8],
+        endpoint: u8,
+        action: ButtonAction,
+    },
+    /// An occupancy (motion) sensor reported a change
+    OccupancyChanged {
+        ieee_address: [u8; 8],
+        endpoint: u8,
+        occupied: bool,
+    },
+    /// A sensor reported a new measurement
+    SensorValue {
+        ieee_address: [u8; 8],
+        endpoint: u8,
+        kind: SensorKind,
+        /// Scaled value in the kind's unit (see [`SensorKind::unit`])
+        value: f64,
+    },
+    /// A smart plug or meter reported power, voltage, current or energy
+    PowerMeasurement {
+        ieee_address: [u8; 8],
+        endpoint: u8,
+        kind: SensorKind,
+        /// Scaled value in the kind's unit (see [`SensorKind::unit`])
+        value: f64,
+    },
+    /// An enrolled IAS Zone sensor reported a new zone status
+    IasAlarm {
+        ieee_address: [u8; 8],
+        /// Zone status bits (see [`crate::ias::zone_status`])
+        zone_status: u16,
+    },
+    /// Sustained congestion on the network's channel started or cleared
+    /// (see [`crate::utilization`])
+    ChannelCongestion {
+        channel: u8,
+        congested: bool,
+        /// Share of APS send attempts that failed recently
+        failure_rate: f64,
+    },
+    /// A firmware update of a device progressed (see [`crate::ota`])
+    OtaProgress {
+        ieee_address: [u8; 8],
+        status: OtaStatus,
+        /// Share of the image transferred
+        percent: u8,
+    },
+}
+
+/// Button actions reported by remotes and switches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    /// Button pressed once
+    Single,
+    /// Button pressed twice in quick succession
+    Double,
+    /// Button pressed three times in quick succession
+    Triple,
+    /// Button held down
+    Hold,
+    /// Button released
+    Release,
 }
 
 /// Network status information
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct NetworkStatus {
     pub connected: bool,
     pub channel: u8,
@@ -60,57 +165,235 @@ pub struct NetworkStatus {
 /// Zigbee network manager
 pub struct ZigbeeNetwork {
     /// Low-level transport
-    transport: Arc<DeconzTransport>,
+    transport: Arc<dyn Transport>,
     /// Known devices (keyed by IEEE address)
     devices: Arc<DashMap<[u8; 8], ZigbeeDevice>>,
     /// Event broadcaster
     event_tx: broadcast::Sender<NetworkEvent>,
-    /// Path to device data file for persistence
-    data_path: Option<PathBuf>,
+    /// Where devices are saved
+    storage: Option<DeviceStorage>,
+    /// Delivery results per device
+    delivery: Arc<DeliveryLog>,
+    /// Firmware updates being served
+    ota: Arc<OtaServer>,
+    /// Commands waiting for sleepy devices to check in
+    pending: Arc<PendingCommands>,
+    /// Last announced network state
+    network_state: Arc<NetworkStateTracker>,
+}
+
+pub(crate) type DeviceStorage = Arc<dyn Storage<ZigbeeDevice>>;
+
+/// Save the device table (if there is storage) without waiting
+pub(crate) fn save_in_background(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    storage: Option<&DeviceStorage>,
+) {
+    if let Some(storage) = storage {
+        let devices: Vec<ZigbeeDevice> = devices.iter().map(|r| r.value().clone()).collect();
+        let storage = Arc::clone(storage);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = storage.save(&devices) {
+                tracing::warn!("Failed to save devices: {}", e);
+            }
+        });
+    }
+}
+
+/// Change one device in place
+///
+/// `update` runs while the device is locked. If it changed anything,
+/// `DeviceUpdated` is sent and the table saved, once. A new `last_seen`
+/// alone is neither: it changes with every frame, and is saved with the
+/// next change or by the availability monitor. Returns `None` for an
+/// unknown device.
+pub(crate) fn update_in_place<R>(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    ieee: &[u8; 8],
+    update: impl FnOnce(&mut ZigbeeDevice) -> R,
+) -> Option<R> {
+    let mut device = devices.get_mut(ieee)?;
+    let mut before = device.clone();
+    let result = update(&mut device);
+    before.last_seen = device.last_seen;
+    let changed = *device != before;
+    drop(device);
+
+    if changed {
+        let _ = event_tx.send(NetworkEvent::DeviceUpdated {
+            ieee_address: *ieee,
+        });
+        save_in_background(devices, storage);
+    }
+    Some(result)
+}
+
+/// IEEE address of the device at a network address
+pub(crate) fn ieee_for_nwk(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    nwk_address: u16,
+) -> Option<[u8; 8]> {
+    devices
+        .iter()
+        .find(|d| d.nwk_address == nwk_address)
+        .map(|d| d.ieee_address)
+}
+
+/// Record an endpoint from a Simple Descriptor Response, then read the
+/// attributes it needs and enroll IAS zones
+fn handle_simple_descriptor(
+    devices: &Arc<DashMap<[u8; 8], ZigbeeDevice>>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    transport: &Arc<dyn Transport>,
+    resp: &SimpleDescriptorResponse,
+) {
+    let Some(ieee) = ieee_for_nwk(devices, resp.nwk_addr) else {
+        return;
+    };
+    let ep = crate::device::Endpoint {
+        id: resp.endpoint,
+        profile_id: resp.profile_id,
+        device_id: resp.device_id,
+        in_clusters: resp.in_clusters.clone(),
+        out_clusters: resp.out_clusters.clone(),
+    };
+    let mut reads = sensor::metering_reads(resp.nwk_addr, &ep);
+    // Identity first, some quirks depend on it
+    if let Some(read) = basic::identity_read(resp.nwk_addr, &ep) {
+        reads.insert(0, read);
+    }
+    update_in_place(devices, event_tx, storage, &ieee, |device| {
+        match device.endpoints.iter_mut().find(|e| e.id == ep.id) {
+            Some(existing) => *existing = ep,
+            None => device.endpoints.push(ep),
+        }
+    });
+
+    // Sensors only report once enrolled
+    if resp.in_clusters.contains(&cluster::id::IAS_ZONE) {
+        let tc = Arc::clone(transport);
+        let devices = Arc::clone(devices);
+        let event_tx = event_tx.clone();
+        let storage = storage.cloned();
+        let (nwk, ep) = (resp.nwk_addr, resp.endpoint);
+        tokio::spawn(async move {
+            if let Err(e) =
+                ias::enroll(tc.as_ref(), &devices, &event_tx, storage.as_ref(), nwk, ep).await
+            {
+                tracing::warn!("Failed to enroll IAS zone: {}", e);
+            }
+        });
+    }
+    // Identity, and the scaling metering values need
+    if !reads.is_empty() {
+        let tc = Arc::clone(transport);
+        tokio::spawn(async move {
+            for request in reads {
+                if let Err(e) = tc.send_aps_request(request).await {
+                    tracing::warn!("Failed to read attributes: {}", e);
+                }
+            }
+        });
+    }
 }
 
 impl ZigbeeNetwork {
     /// Create a new network manager
     #[allow(clippy::missing_errors_doc)]
     pub async fn new(serial_path: &str) -> Result<Self, NetworkError> {
+        Self::with_baud_rate(serial_path, deconz_protocol::transport::BAUD_RATE).await
+    }
+
+    /// Create a new network manager for an adapter running at `baud_rate`
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn with_baud_rate(serial_path: &str, baud_rate: u32) -> Result<Self, NetworkError> {
         // Determine data directory from env or use default
         let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "./data".to_string());
-        let data_path = PathBuf::from(data_dir).join("devices.json");
 
-        let transport = Arc::new(DeconzTransport::connect(serial_path)?);
+        let transport = Arc::new(DeconzTransport::connect_with_options(
+            serial_path,
+            SerialBackend::from_env(),
+            baud_rate,
+        )?);
+
+        Self::with_transport(transport, Some(PathBuf::from(data_dir))).await
+    }
 
+    /// Create a network manager on top of an existing transport
+    ///
+    /// Devices are loaded from and saved to `data_dir` if given, with the
+    /// [`storage::Backend`] from the environment. Storage that can't be
+    /// opened or read is an error rather than an empty network, which would
+    /// overwrite the stored devices on the next save. Tests use this with a
+    /// `MockTransport`.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn with_transport(
+        transport: Arc<dyn Transport>,
+        data_dir: Option<PathBuf>,
+    ) -> Result<Self, NetworkError> {
         let (event_tx, _) = broadcast::channel(64);
 
+        let storage = data_dir
+            .as_deref()
+            .map(|dir| storage::open::<ZigbeeDevice>(dir, storage::Backend::from_env()))
+            .transpose()?;
+
         // Load persisted devices
         let devices = Arc::new(DashMap::new());
-        let loaded = persistence::load_devices(&data_path).await;
-        for device in loaded {
-            devices.insert(device.ieee_address, device);
+        if let Some(storage) = &storage {
+            for mut device in storage.load()? {
+                // Stored with the broadcast address by older versions
+                if green_power::is_green_power(&device) {
+                    device.nwk_address = NO_SHORT_ADDRESS;
+                }
+                devices.insert(device.ieee_address, device);
+            }
         }
 
+        let green_power = data_dir
+            .as_deref()
+            .map(|dir| green_power::GreenPowerState::load(dir.join("green_power.json")))
+            .unwrap_or_default();
+        let ota_dir = data_dir.map(|dir| dir.join("ota"));
         let network = Self {
             transport: transport.clone(),
             devices,
             event_tx,
-            data_path: Some(data_path),
+            storage,
+            delivery: Arc::new(DeliveryLog::default()),
+            ota: Arc::new(OtaServer::new(ota_dir)),
+            pending: Arc::new(PendingCommands::default()),
+            network_state: Arc::new(NetworkStateTracker::default()),
         };
 
         // Start background task to listen for device events
-        network.start_event_listener(transport);
+        network.start_event_listener(transport, green_power);
 
         Ok(network)
     }
 
     #[allow(clippy::needless_pass_by_value)] // Arc is moved into spawned task
     #[allow(clippy::too_many_lines)] // Complex event handler for multiple event types
-    fn start_event_listener(&self, transport: Arc<DeconzTransport>) {
+    fn start_event_listener(
+        &self,
+        transport: Arc<dyn Transport>,
+        mut green_power: green_power::GreenPowerState,
+    ) {
         let devices = Arc::clone(&self.devices);
         let event_tx = self.event_tx.clone();
         let mut deconz_rx = transport.subscribe();
         let transport_clone = transport.clone();
-        let data_path = self.data_path.clone();
+        let storage = self.storage.clone();
+        let delivery = Arc::clone(&self.delivery);
+        let ota = Arc::clone(&self.ota);
+        let pending = Arc::clone(&self.pending);
+        let network_state = Arc::clone(&self.network_state);
 
         tokio::spawn(async move {
+            let mut quirk_state = quirks::QuirkState::default();
             loop {
                 match deconz_rx.recv().await {
                     Ok(DeconzEvent::ApsDataAvailable) => {
@@ -121,6 +404,7 @@ impl ZigbeeNetwork {
                         }
                     }
                     Ok(DeconzEvent::DeviceStateChanged(state)) => {
+                        network_state.observe(&state, &event_tx);
                         // If aps_data_indication is set, fetch the data
                         if state.aps_data_indication {
                             tracing::debug!(
@@ -130,7 +414,11 @@ impl ZigbeeNetwork {
                                 tracing::warn!("Failed to fetch APS data: {}", e);
                             }
                         }
+                        if state.aps_data_confirm {
+                            delivery::fetch_confirms(transport_clone.as_ref()).await;
+                        }
                     }
+                    Ok(DeconzEvent::ApsConfirm(confirm)) => delivery.record(&devices, &confirm),
                     Ok(DeconzEvent::DeviceAnnounced {
                         ieee_addr,
                         short_addr,
@@ -150,43 +438,24 @@ impl ZigbeeNetwork {
                             DeviceType::EndDevice
                         };
 
-                        let is_new = !devices.contains_key(&ieee_addr);
-
-                        // Create or update device
-                        let device = if let Some(mut existing) = devices.get_mut(&ieee_addr) {
-                            existing.nwk_address = short_addr;
-                            existing.last_seen = Some(Instant::now());
-                            existing.available = true;
-                            existing.clone()
-                        } else {
+                        let updated = update_in_place(
+                            &devices,
+                            &event_tx,
+                            storage.as_ref(),
+                            &ieee_addr,
+                            |existing| {
+                                existing.nwk_address = short_addr;
+                                availability::mark_seen(existing, &event_tx);
+                            },
+                        );
+                        let is_new = updated.is_none();
+                        if is_new {
                             let mut new_device = ZigbeeDevice::new(ieee_addr, short_addr);
                             new_device.device_type = device_type;
-                            new_device.last_seen = Some(Instant::now());
+                            new_device.last_seen = Some(Utc::now());
                             devices.insert(ieee_addr, new_device.clone());
-                            new_device
-                        };
-
-                        // Emit network event
-                        let event = if is_new {
-                            NetworkEvent::DeviceJoined(device)
-                        } else {
-                            NetworkEvent::DeviceUpdated {
-                                ieee_address: ieee_addr,
-                            }
-                        };
-                        let _ = event_tx.send(event);
-
-                        // Persist device changes
-                        if let Some(ref path) = data_path {
-                            let devices_vec: Vec<ZigbeeDevice> =
-                                devices.iter().map(|r| r.value().clone()).collect();
-                            let path = path.clone();
-                            tokio::spawn(async move {
-                                if let Err(e) = persistence::save_devices(&path, &devices_vec).await
-                                {
-                                    tracing::warn!("Failed to save devices: {}", e);
-                                }
-                            });
+                            let _ = event_tx.send(NetworkEvent::DeviceJoined(new_device));
+                            save_in_background(&devices, storage.as_ref());
                         }
 
                         // Auto-discover endpoints for new devices
@@ -203,22 +472,117 @@ impl ZigbeeNetwork {
                         }
                     }
                     Ok(DeconzEvent::MacPoll { short_addr }) => {
-                        // Update last_seen for device with this short address
-                        for mut entry in devices.iter_mut() {
-                            if entry.nwk_address == short_addr {
-                                entry.last_seen = Some(Instant::now());
-                                entry.available = true;
-                                break;
-                            }
-                        }
+                        availability::mark_seen_nwk(
+                            &devices,
+                            &event_tx,
+                            storage.as_ref(),
+                            short_addr,
+                        );
                     }
                     Ok(DeconzEvent::ApsIndication(indication)) => {
+                        // Frames that carry the sender's IEEE address keep
+                        // the short address of a rejoined device current
+                        if let Some(ieee) = indication.src_ieee_addr {
+                            update_in_place(&devices, &event_tx, storage.as_ref(), &ieee, |d| {
+                                d.nwk_address = indication.src_short_addr;
+                            });
+                        }
+                        availability::mark_seen_nwk(
+                            &devices,
+                            &event_tx,
+                            storage.as_ref(),
+                            indication.src_short_addr,
+                        );
                         // Handle Home Automation profile (button presses, device commands)
                         if indication.profile_id == profiles::HOME_AUTOMATION {
                             // Parse ZCL frame from ASDU
                             if let Ok(zcl) = ZclFrame::parse(&indication.asdu) {
+                                // Remote buttons of devices with quirks
+                                quirks::handle_command(
+                                    &mut quirk_state,
+                                    &devices,
+                                    &event_tx,
+                                    &transport_clone,
+                                    &indication,
+                                    &zcl,
+                                );
+                                // Handle IAS Zone enrollment and status changes
+                                if indication.cluster_id == cluster::id::IAS_ZONE {
+                                    ias::handle_frame(
+                                        &devices,
+                                        &event_tx,
+                                        storage.as_ref(),
+                                        &transport_clone,
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
+                                // Manufacturer and model
+                                else if indication.cluster_id == cluster::id::BASIC {
+                                    basic::handle_frame(
+                                        &devices,
+                                        &event_tx,
+                                        storage.as_ref(),
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
+                                // Firmware updates
+                                else if indication.cluster_id == ota::CLUSTER_ID {
+                                    ota::handle_frame(
+                                        &ota,
+                                        &devices,
+                                        &event_tx,
+                                        storage.as_ref(),
+                                        &transport_clone,
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
+                                // Sleepy devices checking in
+                                else if indication.cluster_id == poll_control::CLUSTER_ID {
+                                    poll_control::handle_frame(
+                                        &pending,
+                                        &devices,
+                                        &transport_clone,
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
+                                // Tuya data points
+                                else if indication.cluster_id == tuya::CLUSTER_ID {
+                                    tuya::handle_frame(
+                                        &devices,
+                                        &event_tx,
+                                        storage.as_ref(),
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
+                                // Level and colour of lights
+                                else if indication.cluster_id == clusters::LEVEL_CONTROL
+                                    || indication.cluster_id == clusters::COLOR_CONTROL
+                                {
+                                    light::handle_frame(
+                                        &devices,
+                                        &event_tx,
+                                        storage.as_ref(),
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
+                                // Handle sensor attribute reports
+                                else if sensor::is_sensor_cluster(indication.cluster_id) {
+                                    sensor::handle_frame(
+                                        &devices,
+                                        &event_tx,
+                                        storage.as_ref(),
+                                        &indication,
+                                        &zcl,
+                                    );
+                                }
                                 // Handle On/Off cluster commands
-                                if indication.cluster_id == clusters::ON_OFF
+                                else if indication.cluster_id == clusters::ON_OFF
                                     && zcl.is_cluster_specific()
                                 {
                                     let cmd_id = zcl.command_id();
@@ -328,57 +692,13 @@ impl ZigbeeNetwork {
                                                 resp.in_clusters,
                                                 resp.out_clusters
                                             );
-                                            // Update device with endpoint info
-                                            for mut entry in devices.iter_mut() {
-                                                if entry.nwk_address == resp.nwk_addr {
-                                                    let ep = crate::device::Endpoint {
-                                                        id: resp.endpoint,
-                                                        profile_id: resp.profile_id,
-                                                        device_id: resp.device_id,
-                                                        in_clusters: resp.in_clusters.clone(),
-                                                        out_clusters: resp.out_clusters.clone(),
-                                                    };
-                                                    // Add or update endpoint
-                                                    if let Some(existing) = entry
-                                                        .endpoints
-                                                        .iter_mut()
-                                                        .find(|e| e.id == resp.endpoint)
-                                                    {
-                                                        *existing = ep;
-                                                    } else {
-                                                        entry.endpoints.push(ep);
-                                                    }
-                                                    let _ = event_tx.send(
-                                                        NetworkEvent::DeviceUpdated {
-                                                            ieee_address: entry.ieee_address,
-                                                        },
-                                                    );
-                                                    // Persist
-                                                    if let Some(ref path) = data_path {
-                                                        let devices_vec: Vec<ZigbeeDevice> =
-                                                            devices
-                                                                .iter()
-                                                                .map(|r| r.value().clone())
-                                                                .collect();
-                                                        let path = path.clone();
-                                                        tokio::spawn(async move {
-                                                            if let Err(e) =
-                                                                persistence::save_devices(
-                                                                    &path,
-                                                                    &devices_vec,
-                                                                )
-                                                                .await
-                                                            {
-                                                                tracing::warn!(
-                                                                    "Failed to save devices: {}",
-                                                                    e
-                                                                );
-                                                            }
-                                                        });
-                                                    }
-                                                    break;
-                                                }
-                                            }
+                                            handle_simple_descriptor(
+                                                &devices,
+                                                &event_tx,
+                                                storage.as_ref(),
+                                                &transport_clone,
+                                                &resp,
+                                            );
                                         }
                                     }
                                 }
@@ -386,6 +706,20 @@ impl ZigbeeNetwork {
                             }
                         }
                     }
+                    Ok(DeconzEvent::GreenPower(frame)) => {
+                        green_power::handle_frame(
+                            &devices,
+                            &event_tx,
+                            storage.as_ref(),
+                            &mut green_power,
+                            network_state.joining_open(),
+                            &frame,
+                        );
+                    }
+                    Ok(DeconzEvent::PortClosed) => {
+                        tracing::error!("Lost the serial connection to the adapter");
+                        let _ = event_tx.send(NetworkEvent::AdapterDisconnected);
+                    }
                     Ok(_) => {} // Ignore other events
                     Err(broadcast::error::RecvError::Lagged(n)) => {
                         tracing::warn!("Event listener lagged by {} events", n);
@@ -399,10 +733,56 @@ impl ZigbeeNetwork {
         });
     }
 
+    /// Device table shared with the event listener
+    pub(crate) fn devices(&self) -> &DashMap<[u8; 8], ZigbeeDevice> {
+        &self.devices
+    }
+
+    pub(crate) fn delivery(&self) -> &DeliveryLog {
+        &self.delivery
+    }
+
+    pub(crate) fn ota(&self) -> &Arc<OtaServer> {
+        &self.ota
+    }
+
+    pub(crate) fn pending_commands(&self) -> &PendingCommands {
+        &self.pending
+    }
+
+    pub(crate) fn network_state(&self) -> &Arc<NetworkStateTracker> {
+        &self.network_state
+    }
+
+    pub(crate) fn shared_transport(&self) -> Arc<dyn Transport> {
+        Arc::clone(&self.transport)
+    }
+
+    pub(crate) fn storage(&self) -> Option<&DeviceStorage> {
+        self.storage.as_ref()
+    }
+
+    pub(crate) fn event_sender(&self) -> &broadcast::Sender<NetworkEvent> {
+        &self.event_tx
+    }
+
+    /// Send an event to subscribers
+    pub(crate) fn emit(&self, event: NetworkEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    /// Broadcast a synthetic event as if a device had sent it, to exercise
+    /// automations and clients without touching hardware; the device table
+    /// isn't changed
+    pub fn inject_event(&self, event: NetworkEvent) {
+        tracing::info!("Injecting synthetic event: {:?}", event);
+        self.emit(event);
+    }
+
     /// Get the underlying transport
     #[must_use]
-    pub fn transport(&self) -> &DeconzTransport {
-        &self.transport
+    pub fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
     }
 
     /// Subscribe to network events
@@ -417,47 +797,21 @@ impl ZigbeeNetwork {
         let state = self.transport.get_device_state().await?;
 
         // Read network parameters
-        let channel = self
-            .transport
-            .read_parameter(NetworkParameter::CurrentChannel)
-            .await
-            .map(|v| v.first().copied().unwrap_or(0))
-            .unwrap_or(0);
-
-        let pan_id = self
-            .transport
-            .read_parameter(NetworkParameter::NwkPanId)
-            .await
-            .map(|v| {
-                if v.len() >= 2 {
-                    u16::from_le_bytes([v[0], v[1]])
-                } else {
-                    0
-                }
-            })
-            .unwrap_or(0);
+        let channel = self.transport.read_channel().await.unwrap_or(0);
+        let pan_id = self.transport.read_pan_id().await.unwrap_or(0);
+
+        let extended_pan_id = self.transport.read_extended_pan_id().await.map_or_else(
+            |_| "unknown".to_string(),
+            |v| {
+                v.iter()
+                    .rev()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(":")
+            },
+        );
 
-        let extended_pan_id = self
-            .transport
-            .read_parameter(NetworkParameter::NwkExtendedPanId)
-            .await
-            .map_or_else(
-                |_| "unknown".to_string(),
-                |v| {
-                    v.iter()
-                        .rev()
-                        .map(|b| format!("{b:02x}"))
-                        .collect::<Vec<_>>()
-                        .join(":")
-                },
-            );
-
-        let permit_join = self
-            .transport
-            .read_parameter(NetworkParameter::PermitJoin)
-            .await
-            .map(|v| v.first().copied().unwrap_or(0) > 0)
-            .unwrap_or(false);
+        let permit_join = self.transport.read_permit_join().await.is_ok_and(|v| v > 0);
 
         Ok(NetworkStatus {
             connected: state.network_state == deconz_protocol::NetworkState::Connected,
@@ -469,27 +823,30 @@ impl ZigbeeNetwork {
         })
     }
 
-    /// Set permit join duration
+    /// Bring the network online (coordinator joins/forms its configured network)
     #[allow(clippy::missing_errors_doc)]
-    pub async fn permit_join(&self, duration_secs: u8) -> Result<(), NetworkError> {
-        self.transport
-            .write_parameter(NetworkParameter::PermitJoin, &[duration_secs])
-            .await?;
+    pub async fn connect(&self) -> Result<(), NetworkError> {
+        self.set_network_state(NetworkStateCommand::Online).await
+    }
+
+    /// Take the network offline
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn disconnect(&self) -> Result<(), NetworkError> {
+        self.set_network_state(NetworkStateCommand::Offline).await
+    }
+
+    async fn set_network_state(&self, command: NetworkStateCommand) -> Result<(), NetworkError> {
+        tracing::info!("Changing network state: {:?}", command);
+        self.transport.change_network_state(command).await?;
+
+        self.network_state
+            .set_connected(command == NetworkStateCommand::Online, &self.event_tx);
         Ok(())
     }
 
     /// Save devices to disk (spawns background task)
-    fn save_devices(&self) {
-        if let Some(path) = &self.data_path {
-            let devices: Vec<ZigbeeDevice> =
-                self.devices.iter().map(|r| r.value().clone()).collect();
-            let path = path.clone();
-            tokio::spawn(async move {
-                if let Err(e) = persistence::save_devices(&path, &devices).await {
-                    tracing::warn!("Failed to save devices: {}", e);
-                }
-            });
-        }
+    pub(crate) fn save_devices(&self) {
+        save_in_background(&self.devices, self.storage.as_ref());
     }
 
     /// Get all known devices
@@ -553,11 +910,11 @@ impl ZigbeeNetwork {
         drop(device); // Release the lock
 
         // Build ZCL frame
-        let zcl_frame = ZclFrame::on_off_command(1, command);
+        let zcl_frame = ZclFrame::on_off_command(cluster::next_tsn(), command);
         let asdu = zcl_frame.serialize();
 
         // Build APS request
-        let request = ApsDataRequest::new(1, short_addr, endpoint, clusters::ON_OFF, asdu);
+        let mut request = ApsDataRequest::new(1, short_addr, endpoint, clusters::ON_OFF, asdu);
 
         tracing::info!(
             "Sending {:?} command to device {:#06x}:{}",
@@ -566,7 +923,24 @@ impl ZigbeeNetwork {
             endpoint
         );
 
-        self.transport.send_aps_request(request).await?;
+        match self.send_command(request.clone()).await {
+            // The device may have rejoined with a new short address; look
+            // it up and try once more
+            Err(NetworkError::Delivery(status)) if status.is_unreachable() => {
+                match self.refresh_nwk_address(ieee).await {
+                    Ok(nwk_address) if nwk_address != short_addr => {
+                        request.dest_short_addr = nwk_address;
+                        self.send_command(request).await?;
+                    }
+                    Ok(_) => return Err(NetworkError::Delivery(status)),
+                    Err(e) => {
+                        tracing::debug!("NWK address of {:#06x} not refreshed: {}", short_addr, e);
+                        return Err(NetworkError::Delivery(status));
+                    }
+                }
+            }
+            result => result?,
+        }
 
         // Determine new state and emit event
         let new_state = match command {
@@ -576,20 +950,15 @@ impl ZigbeeNetwork {
         };
 
         if let Some(state_on) = new_state {
-            // Update device state
-            if let Some(mut device) = self.devices.get_mut(ieee) {
-                device.state_on = Some(state_on);
-            }
+            self.update_device(ieee, |device| device.state_on = Some(state_on));
 
-            // Emit state change event
+            // Emitted even if the state didn't change; automations react to
+            // the command
             let _ = self.event_tx.send(NetworkEvent::DeviceStateChanged {
                 ieee_address: *ieee,
                 endpoint,
                 state_on,
             });
-
-            // Persist
-            self.save_devices();
         }
 
         Ok(())
@@ -613,6 +982,24 @@ impl ZigbeeNetwork {
         self.send_on_off(ieee, endpoint, OnOffCommand::Off).await
     }
 
+    /// Send an On/Off command to every member of a group
+    ///
+    /// Members don't answer group frames, so device states follow from
+    /// their own reports.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn send_group_on_off(
+        &self,
+        group_id: u16,
+        command: OnOffCommand,
+    ) -> Result<(), NetworkError> {
+        let zcl_frame = ZclFrame::on_off_command(cluster::next_tsn(), command);
+        let request = ApsDataRequest::group(1, group_id, clusters::ON_OFF, zcl_frame.serialize());
+
+        tracing::info!("Sending {:?} command to group {:#06x}", command, group_id);
+        self.transport.send_aps_request(request).await?;
+        Ok(())
+    }
+
     /// Request endpoint discovery for a device
     /// Sends Active Endpoints Request, response handled in event listener
     #[allow(clippy::missing_errors_doc)]
@@ -671,29 +1058,439 @@ impl ZigbeeNetwork {
         friendly_name: Option<String>,
         category: Option<DeviceCategory>,
     ) -> Result<ZigbeeDevice, NetworkError> {
-        let mut device = self
+        self.update_device_with(ieee, |device| {
+            if let Some(name) = friendly_name {
+                device.friendly_name = if name.is_empty() { None } else { Some(name) };
+            }
+            if let Some(cat) = category {
+                device.category = cat;
+            }
+        })
+    }
+
+    /// Apply user edits to a device, then announce and persist the change
+    #[allow(clippy::missing_errors_doc)]
+    pub fn update_device_with(
+        &self,
+        ieee: &[u8; 8],
+        update: impl FnOnce(&mut ZigbeeDevice),
+    ) -> Result<ZigbeeDevice, NetworkError> {
+        self.update_device_checked(ieee, None, update)
+    }
+
+    /// Like [`Self::update_device_with`], but only if the device is still at
+    /// `expected_revision`
+    #[allow(clippy::missing_errors_doc)]
+    pub fn update_device_checked(
+        &self,
+        ieee: &[u8; 8],
+        expected_revision: Option<u64>,
+        update: impl FnOnce(&mut ZigbeeDevice),
+    ) -> Result<ZigbeeDevice, NetworkError> {
+        self.update_device(ieee, |device| {
+            if let Some(expected) = expected_revision {
+                if device.revision != expected {
+                    return Err(NetworkError::RevisionMismatch {
+                        expected,
+                        current: device.revision,
+                    });
+                }
+            }
+            update(device);
+            device.revision += 1;
+            Ok(device.clone())
+        })
+        .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?
+    }
+
+    /// Change a device in one step
+    ///
+    /// `update` runs while the device is locked, so concurrent changes don't
+    /// interleave. If it changed anything, `DeviceUpdated` is emitted and the
+    /// devices are saved, once. Returns `None` if the device is unknown.
+    pub fn update_device<R>(
+        &self,
+        ieee: &[u8; 8],
+        update: impl FnOnce(&mut ZigbeeDevice) -> R,
+    ) -> Option<R> {
+        update_in_place(
+            &self.devices,
+            &self.event_tx,
+            self.storage.as_ref(),
+            ieee,
+            update,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::{indication, MockTransport};
+    use std::time::Duration;
+
+    const IEEE: [u8; 8] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+
+    #[tokio::test(start_paused = true)]
+    async fn test_announced_device_joins_and_is_interviewed() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        let mut events = network.subscribe();
+
+        mock.emit(DeconzEvent::DeviceAnnounced {
+            ieee_addr: IEEE,
+            short_addr: 0x1234,
+            capability: 0x8e,
+        });
+
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(event, NetworkEvent::DeviceJoined(d) if d.ieee_address == IEEE));
+        assert_eq!(network.get_device(&IEEE).unwrap().nwk_address, 0x1234);
+
+        // Active endpoints are requested once the device has settled
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let requests = mock.aps_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].cluster_id, ZdoCluster::ActiveEpReq as u16);
+        assert_eq!(requests[0].dest_short_addr, 0x1234);
+    }
+
+    #[tokio::test]
+    async fn test_closed_port_reported() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        let mut events = network.subscribe();
+
+        mock.emit(DeconzEvent::PortClosed);
+
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(event, NetworkEvent::AdapterDisconnected));
+    }
+
+    #[tokio::test]
+    async fn test_occupancy_report_emits_change_once() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        network
             .devices
-            .get_mut(ieee)
-            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+            .insert(IEEE, ZigbeeDevice::new(IEEE, 0x1234));
+        let mut events = network.subscribe();
+
+        // Report Attributes: occupancy (bitmap8) = occupied, sent twice
+        let report = vec![0x18, 0x01, 0x0A, 0x00, 0x00, 0x18, 0x01];
+        for _ in 0..2 {
+            mock.emit(DeconzEvent::ApsIndication(indication(
+                0x1234,
+                cluster::id::OCCUPANCY_SENSING,
+                report.clone(),
+            )));
+        }
 
-        if let Some(name) = friendly_name {
-            device.friendly_name = if name.is_empty() { None } else { Some(name) };
+        let timeout = Duration::from_secs(1);
+        let event = tokio::time::timeout(timeout, events.recv()).await.unwrap();
+        assert!(matches!(
+            event,
+            Ok(NetworkEvent::DeviceUpdated { ieee_address: IEEE })
+        ));
+        let event = tokio::time::timeout(timeout, events.recv()).await.unwrap();
+        assert!(matches!(
+            event,
+            Ok(NetworkEvent::OccupancyChanged {
+                ieee_address: IEEE,
+                endpoint: 1,
+                occupied: true,
+            })
+        ));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
         }
-        if let Some(cat) = category {
-            device.category = cat;
+        assert!(events.try_recv().is_err());
+        assert_eq!(network.get_device(&IEEE).unwrap().occupancy, Some(true));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejected_command_fails() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+
+        let send = tokio::spawn({
+            let network = network.clone();
+            async move { network.turn_on(&IEEE, 1).await }
+        });
+        while mock.aps_requests().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let tsn = ZclFrame::parse(&mock.aps_requests()[0].asdu)
+            .unwrap()
+            .transaction_seq();
+
+        // A Default Response to another transaction is ignored
+        for (tsn, status) in [(tsn.wrapping_add(1), 0x00), (tsn, 0x81)] {
+            mock.emit(DeconzEvent::ApsIndication(indication(
+                0x1234,
+                clusters::ON_OFF,
+                vec![0x18, tsn, 0x0B, 0x01, status],
+            )));
         }
 
-        let updated_device = device.clone();
-        drop(device);
+        let result = send.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(NetworkError::Rejected(
+                cluster::ZclStatus::UnsupportedClusterCommand
+            ))
+        ));
+        assert_eq!(network.get_device(&IEEE).unwrap().state_on, None);
+        assert_eq!(network.delivery_stats(&IEEE).rejected, 1);
+    }
 
-        // Emit update event
-        let _ = self.event_tx.send(NetworkEvent::DeviceUpdated {
-            ieee_address: *ieee,
+    #[tokio::test(start_paused = true)]
+    async fn test_responses_matched_to_commands_in_flight() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+
+        let on = tokio::spawn({
+            let network = network.clone();
+            async move { network.turn_on(&IEEE, 1).await }
         });
+        let off = tokio::spawn({
+            let network = network.clone();
+            async move { network.turn_off(&IEEE, 2).await }
+        });
+        while mock.aps_requests().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let tsn_of = |endpoint| {
+            let request = mock
+                .aps_requests()
+                .into_iter()
+                .find(|r| r.dest_endpoint == endpoint)
+                .unwrap();
+            ZclFrame::parse(&request.asdu).unwrap().transaction_seq()
+        };
+        let (on_tsn, off_tsn) = (tsn_of(1), tsn_of(2));
+        assert_ne!(on_tsn, off_tsn);
+
+        // Only the second command is refused, and its answer comes first
+        for (tsn, command, status) in [(off_tsn, 0x00, 0x81), (on_tsn, 0x01, 0x00)] {
+            mock.emit(DeconzEvent::ApsIndication(indication(
+                0x1234,
+                clusters::ON_OFF,
+                vec![0x18, tsn, 0x0B, command, status],
+            )));
+        }
 
-        // Persist changes
-        self.save_devices();
+        assert!(on.await.unwrap().is_ok());
+        assert!(matches!(off.await.unwrap(), Err(NetworkError::Rejected(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unreachable_device_retried_by_ieee() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+
+        let send = tokio::spawn({
+            let network = network.clone();
+            async move { network.turn_on(&IEEE, 1).await }
+        });
+        while mock.aps_requests().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let first = mock.aps_requests()[0].clone();
+        assert_eq!(first.dest_addr_mode, deconz_protocol::AddressMode::Nwk);
+        mock.emit(DeconzEvent::ApsConfirm(deconz_protocol::ApsDataConfirm {
+            device_state: deconz_protocol::DeviceState::from_byte(0x02),
+            request_id: first.request_id,
+            destination: deconz_protocol::ConfirmDestination::Nwk(0x1234),
+            dest_endpoint: Some(1),
+            src_endpoint: 1,
+            status: deconz_protocol::ConfirmStatus::NoAck,
+        }));
+
+        while mock.aps_requests().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let retry = mock.aps_requests()[1].clone();
+        assert_eq!(retry.dest_addr_mode, deconz_protocol::AddressMode::Ieee);
+        assert_eq!(retry.dest_ieee_addr, Some(IEEE));
+        assert_eq!(retry.asdu, first.asdu);
+
+        // The device answers from its new short address
+        let mut answer = indication(0x5678, clusters::ON_OFF, vec![0x18, 0x00, 0x0A]);
+        answer.src_ieee_addr = Some(IEEE);
+        mock.emit(DeconzEvent::ApsIndication(answer));
+        assert!(send.await.unwrap().is_ok());
+        assert_eq!(network.get_device(&IEEE).unwrap().nwk_address, 0x5678);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unacknowledged_toggle_not_resent() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
 
-        Ok(updated_device)
+        let send = tokio::spawn({
+            let network = network.clone();
+            async move { network.toggle_device(&IEEE, 1).await }
+        });
+        while mock.aps_requests().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        // The bulb may have toggled without its acknowledgement getting back
+        mock.emit(DeconzEvent::ApsConfirm(deconz_protocol::ApsDataConfirm {
+            device_state: deconz_protocol::DeviceState::from_byte(0x02),
+            request_id: mock.aps_requests()[0].request_id,
+            destination: deconz_protocol::ConfirmDestination::Nwk(0x1234),
+            dest_endpoint: Some(1),
+            src_endpoint: 1,
+            status: deconz_protocol::ConfirmStatus::NoAck,
+        }));
+
+        assert!(matches!(
+            send.await.unwrap(),
+            Err(NetworkError::Delivery(
+                deconz_protocol::ConfirmStatus::NoAck
+            ))
+        ));
+        // Only its new short address would be tried, which it doesn't have
+        let toggles = mock
+            .aps_requests()
+            .iter()
+            .filter(|r| r.cluster_id == clusters::ON_OFF)
+            .count();
+        assert_eq!(toggles, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejoined_device_found_by_nwk_address_request() {
+        let mock = Arc::new(MockTransport::new());
+        let network = Arc::new(
+            ZigbeeNetwork::with_transport(mock.clone(), None)
+                .await
+                .unwrap(),
+        );
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+
+        let send = tokio::spawn({
+            let network = network.clone();
+            async move { network.turn_off(&IEEE, 1).await }
+        });
+        // Neither the stored short address nor the IEEE address gets through
+        for sent in 1..=2 {
+            while mock.aps_requests().len() < sent {
+                tokio::task::yield_now().await;
+            }
+            let request = mock.aps_requests()[sent - 1].clone();
+            mock.emit(DeconzEvent::ApsConfirm(deconz_protocol::ApsDataConfirm {
+                device_state: deconz_protocol::DeviceState::from_byte(0x02),
+                request_id: request.request_id,
+                destination: deconz_protocol::ConfirmDestination::Nwk(0x1234),
+                dest_endpoint: Some(1),
+                src_endpoint: 1,
+                status: deconz_protocol::ConfirmStatus::NoAck,
+            }));
+        }
+
+        while mock.aps_requests().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        let lookup = mock.aps_requests()[2].clone();
+        assert_eq!(lookup.cluster_id, ZdoCluster::NwkAddrReq as u16);
+        assert!(lookup.is_broadcast());
+        let mut asdu = vec![lookup.asdu[0], 0x00];
+        asdu.extend_from_slice(&IEEE);
+        asdu.extend_from_slice(&0x5678u16.to_le_bytes());
+        let mut response = indication(0x5678, ZdoCluster::NwkAddrRsp as u16, asdu);
+        response.profile_id = profiles::ZDO;
+        mock.emit(DeconzEvent::ApsIndication(response));
+
+        while mock.aps_requests().len() < 4 {
+            tokio::task::yield_now().await;
+        }
+        let retry = mock.aps_requests()[3].clone();
+        assert_eq!(retry.dest_short_addr, 0x5678);
+        assert_eq!(retry.cluster_id, clusters::ON_OFF);
+        assert!(send.await.unwrap().is_ok());
+        let device = network.get_device(&IEEE).unwrap();
+        assert_eq!(device.nwk_address, 0x5678);
+        assert_eq!(device.state_on, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_aps_data_available_fetches_indication() {
+        let mock = Arc::new(MockTransport::new());
+        let _network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+
+        mock.emit(DeconzEvent::ApsDataAvailable);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].command_id,
+            deconz_protocol::CommandId::ApsDataIndication
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_device_announces_changes_once() {
+        let network = ZigbeeNetwork::with_transport(Arc::new(MockTransport::new()), None)
+            .await
+            .unwrap();
+        network.upsert_device(ZigbeeDevice::new(IEEE, 0x1234));
+        let mut events = network.subscribe();
+
+        let name = network.update_device(&IEEE, |device| {
+            device.friendly_name = Some("Porch".to_string());
+            device.lqi = Some(200);
+            device.display_name()
+        });
+        assert_eq!(name.as_deref(), Some("Porch"));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(NetworkEvent::DeviceUpdated { ieee_address: IEEE })
+        ));
+        assert!(events.try_recv().is_err());
+
+        // Nothing changed, nothing announced
+        network.update_device(&IEEE, |device| device.lqi = Some(200));
+        assert!(events.try_recv().is_err());
+
+        assert!(network.update_device(&[9; 8], |_| ()).is_none());
     }
 }
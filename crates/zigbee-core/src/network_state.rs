@@ -0,0 +1,173 @@
+//! Network state pushed to subscribers
+//!
+//! The adapter notifies the host whenever its device state changes. When
+//! the coordinator goes online or offline that becomes a
+//! [`NetworkEvent::NetworkStateChanged`]. Opening the network for joining
+//! sends [`NetworkEvent::PermitJoinChanged`], and again when the window
+//! closes, so clients can follow the status without polling it.
+
+use crate::network::{NetworkError, NetworkEvent, ZigbeeNetwork};
+use deconz_protocol::{ApsDataRequest, Broadcast, DeviceState, NetworkState};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Permit join duration the firmware treats as "until closed"
+const PERMIT_JOIN_FOREVER: u8 = 0xFF;
+
+/// Last known network state, so only changes are announced
+#[derive(Debug, Default)]
+pub(crate) struct NetworkStateTracker {
+    connected: Mutex<Option<bool>>,
+    /// Bumped whenever joining is opened or closed, so only the timer of
+    /// the latest window announces its end
+    permit_join_window: AtomicU64,
+    joining_open: AtomicBool,
+}
+
+impl NetworkStateTracker {
+    /// Record whether the coordinator is on the network, announcing changes
+    pub(crate) fn set_connected(
+        &self,
+        connected: bool,
+        event_tx: &broadcast::Sender<NetworkEvent>,
+    ) {
+        let previous = self
+            .connected
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(connected);
+        if previous != Some(connected) {
+            tracing::info!("Network {}", if connected { "up" } else { "down" });
+            let _ = event_tx.send(NetworkEvent::NetworkStateChanged { connected });
+        }
+    }
+
+    /// Follow a device state notification from the adapter; joining and
+    /// leaving are passed through without an event
+    pub(crate) fn observe(&self, state: &DeviceState, event_tx: &broadcast::Sender<NetworkEvent>) {
+        match state.network_state {
+            NetworkState::Connected => self.set_connected(true, event_tx),
+            NetworkState::Offline => self.set_connected(false, event_tx),
+            NetworkState::Joining | NetworkState::Leaving => {}
+        }
+    }
+
+    /// Whether the network is open for joining
+    pub(crate) fn joining_open(&self) -> bool {
+        self.joining_open.load(Ordering::Relaxed)
+    }
+}
+
+impl ZigbeeNetwork {
+    /// Allow devices to join for `duration_secs` (0 closes, 255 keeps it
+    /// open until closed)
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn permit_join(&self, duration_secs: u8) -> Result<(), NetworkError> {
+        self.transport().write_permit_join(duration_secs).await?;
+        // The parameter only opens the coordinator; routers are told over
+        // the air
+        let request = ApsDataRequest::mgmt_permit_join_request(
+            1,
+            Broadcast::Routers,
+            duration_secs,
+            crate::zdo::next_tsn(),
+        );
+        if let Err(e) = self.transport().send_aps_request(request).await {
+            tracing::warn!("Failed to broadcast permit join to routers: {}", e);
+        }
+
+        let tracker = Arc::clone(self.network_state());
+        let window = tracker.permit_join_window.fetch_add(1, Ordering::Relaxed) + 1;
+        tracker
+            .joining_open
+            .store(duration_secs > 0, Ordering::Relaxed);
+        self.emit(NetworkEvent::PermitJoinChanged {
+            permit_join: duration_secs > 0,
+            duration_secs,
+        });
+
+        if duration_secs > 0 && duration_secs != PERMIT_JOIN_FOREVER {
+            let event_tx = self.event_sender().clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(u64::from(duration_secs))).await;
+                if tracker.permit_join_window.load(Ordering::Relaxed) == window {
+                    tracker.joining_open.store(false, Ordering::Relaxed);
+                    let _ = event_tx.send(NetworkEvent::PermitJoinChanged {
+                        permit_join: false,
+                        duration_secs: 0,
+                    });
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::MockTransport;
+    use deconz_protocol::{CommandId, DeconzEvent, Status};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_network_state_pushed_on_change() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        let mut events = network.subscribe();
+
+        // Connected twice (e.g. with the APS data flag), then offline
+        for byte in [0x02, 0x0A, 0x00] {
+            mock.emit(DeconzEvent::DeviceStateChanged(DeviceState::from_byte(
+                byte,
+            )));
+        }
+        for connected in [true, false] {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(matches!(
+                event,
+                NetworkEvent::NetworkStateChanged { connected: c } if c == connected
+            ));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_permit_join_announces_open_and_close() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond_always(CommandId::WriteParameter, Status::Success, vec![]);
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        let mut events = network.subscribe();
+
+        network.permit_join(60).await.unwrap();
+        // Reopening restarts the window; only the second one closes
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        network.permit_join(60).await.unwrap();
+
+        let mut changes = Vec::new();
+        while let Ok(Ok(event)) =
+            tokio::time::timeout(Duration::from_secs(120), events.recv()).await
+        {
+            if let NetworkEvent::PermitJoinChanged {
+                permit_join,
+                duration_secs,
+            } = event
+            {
+                changes.push((permit_join, duration_secs));
+            }
+        }
+        assert_eq!(changes, vec![(true, 60), (true, 60), (false, 0)]);
+        let broadcasts = mock.aps_requests();
+        assert_eq!(broadcasts.len(), 2);
+        assert!(broadcasts
+            .iter()
+            .all(|r| r.dest_short_addr == Broadcast::Routers as u16 && r.asdu[1] == 60));
+    }
+}
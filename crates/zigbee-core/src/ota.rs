@@ -0,0 +1,871 @@
+//! OTA Upgrade cluster server: firmware updates for devices
+//!
+//! Devices run the OTA client and pull their firmware from us. An update
+//! starts with [`ZigbeeNetwork::start_ota_update`], which notifies the device;
+//! sleeping devices may only ask on their next wake. The device then sends
+//! Query Next Image with its current firmware, and if a newer image for its
+//! manufacturer and image type is in the image directory (`DATA_DIR/ota`) it
+//! fetches it in blocks, one at a time or a page of them per request, and
+//! reports the end of the upgrade. An update the device hasn't asked for
+//! within [`WAIT_TIMEOUT`], or stops fetching for [`STALL_TIMEOUT`], fails.
+//!
+//! Images are standard Zigbee OTA files. Some vendors prefix them with a
+//! signature, so the header is searched for near the start of the file.
+//! Devices that ask without an update having been requested are told there
+//! is no image, so nothing is flashed behind the user's back.
+
+use crate::cluster::{self, id};
+use crate::device::ZigbeeDevice;
+use crate::network::{self, DeviceStorage, NetworkError, NetworkEvent, ZigbeeNetwork};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, Transport, ZclFrame};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+pub const CLUSTER_ID: u16 = id::OTA_UPGRADE;
+
+/// OTA Upgrade cluster commands
+mod command {
+    pub const IMAGE_NOTIFY: u8 = 0x00;
+    pub const QUERY_NEXT_IMAGE_REQUEST: u8 = 0x01;
+    pub const QUERY_NEXT_IMAGE_RESPONSE: u8 = 0x02;
+    pub const IMAGE_BLOCK_REQUEST: u8 = 0x03;
+    pub const IMAGE_PAGE_REQUEST: u8 = 0x04;
+    pub const IMAGE_BLOCK_RESPONSE: u8 = 0x05;
+    pub const UPGRADE_END_REQUEST: u8 = 0x06;
+    pub const UPGRADE_END_RESPONSE: u8 = 0x07;
+}
+
+/// ZCL statuses used by the cluster
+mod status {
+    pub const SUCCESS: u8 = 0x00;
+    pub const ABORT: u8 = 0x95;
+    pub const NO_IMAGE_AVAILABLE: u8 = 0x98;
+}
+
+/// OTA file identifier
+const FILE_MAGIC: [u8; 4] = 0x0BEE_F11Eu32.to_le_bytes();
+
+/// Fixed part of the OTA file header
+const HEADER_LEN: usize = 56;
+
+/// How far into a file the header is looked for
+const MAX_PREFIX_LEN: usize = 1024;
+
+/// Largest block sent at once; more doesn't fit an unfragmented APS frame
+const MAX_BLOCK_SIZE: u8 = 48;
+
+/// How long a requested update waits for the device to ask; sleeping
+/// devices may take hours to wake
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a device may go without fetching a block
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Firmware image identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageId {
+    pub manufacturer_code: u16,
+    pub image_type: u16,
+    pub file_version: u32,
+}
+
+impl ImageId {
+    /// Manufacturer code, image type and file version, as they appear in
+    /// most OTA commands
+    fn parse(data: &[u8]) -> Option<Self> {
+        let [m0, m1, t0, t1, v0, v1, v2, v3, ..] = *data else {
+            return None;
+        };
+        Some(Self {
+            manufacturer_code: u16::from_le_bytes([m0, m1]),
+            image_type: u16::from_le_bytes([t0, t1]),
+            file_version: u32::from_le_bytes([v0, v1, v2, v3]),
+        })
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8);
+        data.extend_from_slice(&self.manufacturer_code.to_le_bytes());
+        data.extend_from_slice(&self.image_type.to_le_bytes());
+        data.extend_from_slice(&self.file_version.to_le_bytes());
+        data
+    }
+
+    /// Whether `self` is a newer image for the same firmware as `current`
+    #[must_use]
+    pub fn updates(&self, current: &ImageId) -> bool {
+        self.manufacturer_code == current.manufacturer_code
+            && self.image_type == current.image_type
+            && self.file_version > current.file_version
+    }
+}
+
+/// Find the OTA image in a file, returning its identity and bytes (from the
+/// header to the end of the image)
+#[must_use]
+pub fn parse_image(data: &[u8]) -> Option<(ImageId, &[u8])> {
+    let search = &data[..data.len().min(MAX_PREFIX_LEN + FILE_MAGIC.len())];
+    let start = search.windows(4).position(|w| w == FILE_MAGIC)?;
+    let image = &data[start..];
+    if image.len() < HEADER_LEN {
+        return None;
+    }
+    let id = ImageId::parse(&image[10..18])?;
+    let size = u32::from_le_bytes([image[52], image[53], image[54], image[55]]) as usize;
+    if size < HEADER_LEN || size > image.len() {
+        return None;
+    }
+    Some((id, &image[..size]))
+}
+
+/// An image file in the image directory
+#[derive(Debug, Clone)]
+pub struct OtaImage {
+    pub id: ImageId,
+    /// Image size sent to devices
+    pub size: u32,
+    pub path: PathBuf,
+}
+
+/// Every valid image in `dir`
+pub async fn scan_images(dir: &Path) -> Vec<OtaImage> {
+    let mut images = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return images;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let Ok(data) = tokio::fs::read(&path).await else {
+            continue;
+        };
+        match parse_image(&data) {
+            Some((id, image)) => images.push(OtaImage {
+                id,
+                size: u32::try_from(image.len()).unwrap_or(u32::MAX),
+                path,
+            }),
+            None => tracing::debug!("Skipping {}: not an OTA image", path.display()),
+        }
+    }
+    images
+}
+
+/// State of a firmware update
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtaStatus {
+    /// Requested; waiting for the device to ask for the image
+    Waiting,
+    /// The device is fetching the image
+    Downloading,
+    /// The device accepted the image and is restarting into it
+    Finished,
+    /// No newer image, or the device aborted
+    Failed,
+}
+
+/// An update in progress
+#[derive(Debug)]
+struct Transfer {
+    /// Tells a repeated request for the same device apart
+    id: u64,
+    /// Image offered to the device, once it asked
+    image: Option<(ImageId, Arc<Vec<u8>>)>,
+    percent: u8,
+    /// When the device last asked for anything
+    last_heard: Instant,
+}
+
+impl Transfer {
+    /// When the transfer fails unless the device is heard from
+    fn deadline(&self) -> Instant {
+        let timeout = if self.image.is_some() {
+            STALL_TIMEOUT
+        } else {
+            WAIT_TIMEOUT
+        };
+        self.last_heard + timeout
+    }
+}
+
+/// Requested updates and the images being served
+#[derive(Debug)]
+pub(crate) struct OtaServer {
+    dir: Option<PathBuf>,
+    transfers: DashMap<[u8; 8], Transfer>,
+    next_id: AtomicU64,
+}
+
+impl OtaServer {
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            transfers: DashMap::new(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Serve an update to a device once it asks, failing it if the device
+    /// goes quiet
+    fn begin(self: &Arc<Self>, ieee_address: [u8; 8], event_tx: &broadcast::Sender<NetworkEvent>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.transfers.insert(
+            ieee_address,
+            Transfer {
+                id,
+                image: None,
+                percent: 0,
+                last_heard: Instant::now(),
+            },
+        );
+        progress(event_tx, ieee_address, OtaStatus::Waiting, 0);
+
+        let server = Arc::clone(self);
+        let event_tx = event_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let deadline = match server.transfers.get(&ieee_address) {
+                    Some(transfer) if transfer.id == id => transfer.deadline(),
+                    _ => return,
+                };
+                tokio::time::sleep_until(deadline).await;
+                let expired = server
+                    .transfers
+                    .remove_if(&ieee_address, |_, transfer| {
+                        transfer.id == id && transfer.deadline() <= Instant::now()
+                    })
+                    .is_some();
+                if expired {
+                    tracing::warn!(
+                        "Firmware update of {} timed out",
+                        ApsDataIndication::format_ieee(&ieee_address)
+                    );
+                    progress(&event_tx, ieee_address, OtaStatus::Failed, 0);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// The image being served to a device, if it is the one requested
+    fn image(&self, ieee_address: &[u8; 8], requested: ImageId) -> Option<Arc<Vec<u8>>> {
+        let transfer = self.transfers.get(ieee_address)?;
+        let (id, image) = transfer.image.as_ref()?;
+        (*id == requested).then(|| Arc::clone(image))
+    }
+
+    /// Report how far into the image a device has fetched
+    fn fetched(
+        &self,
+        event_tx: &broadcast::Sender<NetworkEvent>,
+        ieee_address: [u8; 8],
+        end: usize,
+        len: usize,
+    ) {
+        #[allow(clippy::cast_possible_truncation)] // At most 100
+        let percent = (end * 100 / len.max(1)) as u8;
+        let Some(mut transfer) = self.transfers.get_mut(&ieee_address) else {
+            return;
+        };
+        if percent != transfer.percent {
+            transfer.percent = percent;
+            drop(transfer);
+            progress(event_tx, ieee_address, OtaStatus::Downloading, percent);
+        }
+    }
+
+    /// Newest image in the directory that updates `current`, with its bytes
+    async fn find_update(&self, current: &ImageId) -> Option<(ImageId, Vec<u8>)> {
+        let dir = self.dir.as_deref()?;
+        let newest = scan_images(dir)
+            .await
+            .into_iter()
+            .filter(|image| image.id.updates(current))
+            .max_by_key(|image| image.id.file_version)?;
+        let data = tokio::fs::read(&newest.path).await.ok()?;
+        let (id, image) = parse_image(&data)?;
+        Some((id, image.to_vec()))
+    }
+}
+
+/// A server-to-client response to the device a request came from
+fn response(
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+    command_id: u8,
+    payload: Vec<u8>,
+) -> ApsDataRequest {
+    let frame = ZclFrame::cluster_command(zcl.transaction_seq(), command_id)
+        .from_server()
+        .with_payload(payload);
+    ApsDataRequest::new(
+        1,
+        indication.src_short_addr,
+        indication.src_endpoint,
+        CLUSTER_ID,
+        frame.serialize(),
+    )
+}
+
+/// Send a response to the device a request came from
+fn respond(
+    transport: &Arc<dyn Transport>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+    command_id: u8,
+    payload: Vec<u8>,
+) {
+    let request = response(indication, zcl, command_id, payload);
+    let transport = Arc::clone(transport);
+    tokio::spawn(async move {
+        if let Err(e) = transport.send_aps_request(request).await {
+            tracing::debug!("Failed to send OTA response: {}", e);
+        }
+    });
+}
+
+fn progress(
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    ieee_address: [u8; 8],
+    status: OtaStatus,
+    percent: u8,
+) {
+    let _ = event_tx.send(NetworkEvent::OtaProgress {
+        ieee_address,
+        status,
+        percent,
+    });
+}
+
+/// Image Block Response payload with the bytes from `offset`, and where
+/// they end
+fn image_block(id: ImageId, image: &[u8], offset: u32, max_size: u8) -> (Vec<u8>, usize) {
+    let start = usize::try_from(offset)
+        .unwrap_or(usize::MAX)
+        .min(image.len());
+    let end = start
+        .saturating_add(usize::from(max_size.min(MAX_BLOCK_SIZE)))
+        .min(image.len());
+    let block = &image[start..end];
+    let mut payload = vec![status::SUCCESS];
+    payload.extend(id.to_bytes());
+    payload.extend_from_slice(&offset.to_le_bytes());
+    #[allow(clippy::cast_possible_truncation)] // At most MAX_BLOCK_SIZE
+    payload.push(block.len() as u8);
+    payload.extend_from_slice(block);
+    (payload, end)
+}
+
+/// Handle an OTA Upgrade client command
+pub(crate) fn handle_frame(
+    server: &Arc<OtaServer>,
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    transport: &Arc<dyn Transport>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    if !zcl.is_cluster_specific() || zcl.is_from_server() {
+        return;
+    }
+    let Some(ieee_address) = network::ieee_for_nwk(devices, indication.src_short_addr) else {
+        return;
+    };
+    let payload = zcl.payload();
+    if let Some(mut transfer) = server.transfers.get_mut(&ieee_address) {
+        transfer.last_heard = Instant::now();
+    }
+
+    match zcl.command_id() {
+        command::QUERY_NEXT_IMAGE_REQUEST => {
+            let Some(current) = payload.get(1..).and_then(ImageId::parse) else {
+                return;
+            };
+            network::update_in_place(devices, event_tx, storage, &ieee_address, |device| {
+                device.firmware = Some(current);
+            });
+
+            if !server.transfers.contains_key(&ieee_address) {
+                respond(
+                    transport,
+                    indication,
+                    zcl,
+                    command::QUERY_NEXT_IMAGE_RESPONSE,
+                    vec![status::NO_IMAGE_AVAILABLE],
+                );
+                return;
+            }
+
+            let server = Arc::clone(server);
+            let event_tx = event_tx.clone();
+            let transport = Arc::clone(transport);
+            let indication = indication.clone();
+            let zcl = zcl.clone();
+            tokio::spawn(async move {
+                let Some((id, image)) = server.find_update(&current).await else {
+                    tracing::info!(
+                        "No firmware newer than {:#010x} for {}",
+                        current.file_version,
+                        ApsDataIndication::format_ieee(&ieee_address)
+                    );
+                    server.transfers.remove(&ieee_address);
+                    respond(
+                        &transport,
+                        &indication,
+                        &zcl,
+                        command::QUERY_NEXT_IMAGE_RESPONSE,
+                        vec![status::NO_IMAGE_AVAILABLE],
+                    );
+                    progress(&event_tx, ieee_address, OtaStatus::Failed, 0);
+                    return;
+                };
+
+                tracing::info!(
+                    "Offering firmware {:#010x} ({} bytes) to {}",
+                    id.file_version,
+                    image.len(),
+                    ApsDataIndication::format_ieee(&ieee_address)
+                );
+                let mut response = vec![status::SUCCESS];
+                response.extend(id.to_bytes());
+                response.extend_from_slice(
+                    &u32::try_from(image.len()).unwrap_or(u32::MAX).to_le_bytes(),
+                );
+                if let Some(mut transfer) = server.transfers.get_mut(&ieee_address) {
+                    transfer.image = Some((id, Arc::new(image)));
+                }
+                respond(
+                    &transport,
+                    &indication,
+                    &zcl,
+                    command::QUERY_NEXT_IMAGE_RESPONSE,
+                    response,
+                );
+            });
+        }
+        command::IMAGE_BLOCK_REQUEST => {
+            let (Some(requested), Some(offset), Some(&max_size)) = (
+                payload.get(1..).and_then(ImageId::parse),
+                payload.get(9..13),
+                payload.get(13),
+            ) else {
+                return;
+            };
+            let offset = u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]);
+
+            let Some(image) = server.image(&ieee_address, requested) else {
+                respond(
+                    transport,
+                    indication,
+                    zcl,
+                    command::IMAGE_BLOCK_RESPONSE,
+                    vec![status::ABORT],
+                );
+                return;
+            };
+            let (block, end) = image_block(requested, &image, offset, max_size);
+            respond(
+                transport,
+                indication,
+                zcl,
+                command::IMAGE_BLOCK_RESPONSE,
+                block,
+            );
+            server.fetched(event_tx, ieee_address, end, image.len());
+        }
+        command::IMAGE_PAGE_REQUEST => {
+            let (Some(requested), Some(offset), Some(&max_size), Some(page), Some(spacing)) = (
+                payload.get(1..).and_then(ImageId::parse),
+                payload.get(9..13),
+                payload.get(13),
+                payload.get(14..16),
+                payload.get(16..18),
+            ) else {
+                return;
+            };
+            let offset = u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]);
+            let page_end = u64::from(offset) + u64::from(u16::from_le_bytes([page[0], page[1]]));
+            let spacing =
+                Duration::from_millis(u64::from(u16::from_le_bytes([spacing[0], spacing[1]])));
+
+            let Some(image) = server.image(&ieee_address, requested) else {
+                respond(
+                    transport,
+                    indication,
+                    zcl,
+                    command::IMAGE_BLOCK_RESPONSE,
+                    vec![status::ABORT],
+                );
+                return;
+            };
+
+            // The whole page is sent unasked, a block every `spacing`
+            let server = Arc::clone(server);
+            let event_tx = event_tx.clone();
+            let transport = Arc::clone(transport);
+            let indication = indication.clone();
+            let zcl = zcl.clone();
+            tokio::spawn(async move {
+                let mut offset = offset;
+                loop {
+                    let left = u8::try_from(page_end - u64::from(offset)).unwrap_or(u8::MAX);
+                    let (block, end) = image_block(requested, &image, offset, max_size.min(left));
+                    let request = response(&indication, &zcl, command::IMAGE_BLOCK_RESPONSE, block);
+                    if let Err(e) = transport.send_aps_request(request).await {
+                        tracing::debug!("Failed to send OTA block: {}", e);
+                        return;
+                    }
+                    server.fetched(&event_tx, ieee_address, end, image.len());
+                    let Ok(next) = u32::try_from(end) else {
+                        return;
+                    };
+                    if u64::from(next) >= page_end
+                        || end >= image.len()
+                        || server.image(&ieee_address, requested).is_none()
+                    {
+                        return;
+                    }
+                    offset = next;
+                    tokio::time::sleep(spacing).await;
+                }
+            });
+        }
+        command::UPGRADE_END_REQUEST => {
+            let (Some(&result), Some(id)) =
+                (payload.first(), payload.get(1..).and_then(ImageId::parse))
+            else {
+                return;
+            };
+            if server.transfers.remove(&ieee_address).is_none() {
+                return;
+            }
+            if result != status::SUCCESS {
+                tracing::warn!(
+                    "{} aborted its firmware update (status {:#04x})",
+                    ApsDataIndication::format_ieee(&ieee_address),
+                    result
+                );
+                progress(event_tx, ieee_address, OtaStatus::Failed, 0);
+                return;
+            }
+
+            // Current and upgrade time 0: switch to the new image now
+            let mut response = id.to_bytes();
+            response.extend_from_slice(&0u32.to_le_bytes());
+            response.extend_from_slice(&0u32.to_le_bytes());
+            respond(
+                transport,
+                indication,
+                zcl,
+                command::UPGRADE_END_RESPONSE,
+                response,
+            );
+            tracing::info!(
+                "{} updated to firmware {:#010x}",
+                ApsDataIndication::format_ieee(&ieee_address),
+                id.file_version
+            );
+            progress(event_tx, ieee_address, OtaStatus::Finished, 100);
+            network::update_in_place(devices, event_tx, storage, &ieee_address, |device| {
+                device.firmware = Some(id);
+            });
+        }
+        other => {
+            tracing::debug!("Unhandled OTA command {:#04x}", other);
+        }
+    }
+}
+
+/// The endpoint a device runs the OTA client on, if it can be updated
+#[must_use]
+pub fn update_endpoint(device: &ZigbeeDevice) -> Option<&crate::device::Endpoint> {
+    device
+        .endpoints
+        .iter()
+        .find(|e| e.out_clusters.contains(&CLUSTER_ID))
+}
+
+impl ZigbeeNetwork {
+    /// Update a device to the newest image in the image directory
+    ///
+    /// Returns once the device has been notified; progress is reported as
+    /// [`NetworkEvent::OtaProgress`]. Sleeping devices start when they next
+    /// wake up and ask.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn start_ota_update(&self, ieee: &[u8; 8]) -> Result<(), NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        let endpoint = update_endpoint(&device).ok_or_else(|| {
+            NetworkError::Configuration("device doesn't support OTA updates".to_string())
+        })?;
+
+        self.ota().begin(*ieee, self.event_sender());
+
+        // Payload type 0 (jitter only), jitter 100: query right away
+        let frame = ZclFrame::cluster_command(cluster::next_tsn(), command::IMAGE_NOTIFY)
+            .from_server()
+            .with_payload(vec![0x00, 100]);
+        let request = ApsDataRequest::new(
+            1,
+            device.nwk_address,
+            endpoint.id,
+            CLUSTER_ID,
+            frame.serialize(),
+        );
+        self.transport().send_aps_request(request).await?;
+        Ok(())
+    }
+
+    /// Where firmware images are looked for (`DATA_DIR/ota`)
+    #[must_use]
+    pub fn ota_dir(&self) -> Option<&Path> {
+        self.ota().dir.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::{indication, MockTransport};
+
+    const ID: ImageId = ImageId {
+        manufacturer_code: 0x117C,
+        image_type: 0x11C5,
+        file_version: 0x0100_0024,
+    };
+
+    fn image_file(prefix: &[u8], id: ImageId, body_len: usize) -> Vec<u8> {
+        let size = u32::try_from(HEADER_LEN + body_len).unwrap();
+        let mut data = prefix.to_vec();
+        data.extend_from_slice(&FILE_MAGIC);
+        data.extend_from_slice(&0x0100u16.to_le_bytes()); // header version
+        data.extend_from_slice(&56u16.to_le_bytes()); // header length
+        data.extend_from_slice(&0u16.to_le_bytes()); // field control
+        data.extend(id.to_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes()); // stack version
+        data.extend_from_slice(&[0; 32]); // header string
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend(std::iter::repeat_n(0xAB, body_len));
+        data
+    }
+
+    #[test]
+    fn test_parse_image() {
+        let id = ImageId {
+            manufacturer_code: 0x117C,
+            image_type: 0x11C5,
+            file_version: 0x0100_0024,
+        };
+        let data = image_file(&[], id, 100);
+        let (parsed, image) = parse_image(&data).unwrap();
+        assert_eq!(parsed, id);
+        assert_eq!(image.len(), HEADER_LEN + 100);
+
+        // Signed images have a prefix; trailing bytes aren't served
+        let mut data = image_file(b"NGIS\x00\x00", id, 10);
+        data.extend_from_slice(b"signature");
+        let (parsed, image) = parse_image(&data).unwrap();
+        assert_eq!(parsed, id);
+        assert_eq!(image.len(), HEADER_LEN + 10);
+
+        // Truncated
+        assert!(parse_image(&data[..40]).is_none());
+
+        let older = ImageId {
+            file_version: 0x0100_0020,
+            ..id
+        };
+        assert!(id.updates(&older));
+        assert!(!older.updates(&id));
+        assert!(!ImageId {
+            image_type: 0x11C6,
+            ..id
+        }
+        .updates(&older));
+    }
+
+    /// A client command from the device at 0x1234
+    fn request(
+        server: &Arc<OtaServer>,
+        devices: &DashMap<[u8; 8], ZigbeeDevice>,
+        event_tx: &broadcast::Sender<NetworkEvent>,
+        transport: &Arc<dyn Transport>,
+        command_id: u8,
+        payload: Vec<u8>,
+    ) {
+        let frame = ZclFrame::cluster_command(7, command_id).with_payload(payload);
+        let indication = indication(0x1234, CLUSTER_ID, frame.serialize());
+        let zcl = ZclFrame::parse(&indication.asdu).unwrap();
+        handle_frame(
+            server,
+            devices,
+            event_tx,
+            None,
+            transport,
+            &indication,
+            &zcl,
+        );
+    }
+
+    /// Wait for the `count`th frame sent, and parse it
+    async fn sent(mock: &MockTransport, count: usize) -> ZclFrame {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while mock.aps_requests().len() < count {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        ZclFrame::parse(&mock.aps_requests()[count - 1].asdu).unwrap()
+    }
+
+    fn block_request(id: ImageId, offset: u32) -> Vec<u8> {
+        let mut payload = vec![0x00];
+        payload.extend(id.to_bytes());
+        payload.extend_from_slice(&offset.to_le_bytes());
+        payload.push(MAX_BLOCK_SIZE);
+        payload
+    }
+
+    #[tokio::test]
+    async fn test_serves_requested_update() {
+        let dir = std::env::temp_dir().join(format!("casita-ota-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("update.ota"), image_file(&[], ID, 200)).unwrap();
+        let server = Arc::new(OtaServer::new(Some(dir.clone())));
+        let devices = DashMap::new();
+        devices.insert([1; 8], ZigbeeDevice::new([1; 8], 0x1234));
+        let (event_tx, mut events) = broadcast::channel(64);
+        let mock = Arc::new(MockTransport::new());
+        let transport: Arc<dyn Transport> = mock.clone();
+        let current = ImageId {
+            file_version: 0x0100_0020,
+            ..ID
+        };
+        let mut query = vec![0x00];
+        query.extend(current.to_bytes());
+
+        // Not requested: nothing is offered
+        let ask = |command_id, payload| {
+            request(
+                &server, &devices, &event_tx, &transport, command_id, payload,
+            );
+        };
+        ask(command::QUERY_NEXT_IMAGE_REQUEST, query.clone());
+        let frame = sent(&mock, 1).await;
+        assert_eq!(frame.payload(), [status::NO_IMAGE_AVAILABLE]);
+        assert_eq!(devices.get(&[1; 8]).unwrap().firmware, Some(current));
+
+        server.begin([1; 8], &event_tx);
+        ask(command::QUERY_NEXT_IMAGE_REQUEST, query);
+        let frame = sent(&mock, 2).await;
+        assert_eq!(frame.command_id(), command::QUERY_NEXT_IMAGE_RESPONSE);
+        assert_eq!(frame.payload()[0], status::SUCCESS);
+        assert_eq!(ImageId::parse(&frame.payload()[1..]), Some(ID));
+        assert_eq!(frame.payload()[9..], 256u32.to_le_bytes());
+
+        ask(command::IMAGE_BLOCK_REQUEST, block_request(ID, 0));
+        let frame = sent(&mock, 3).await;
+        assert_eq!(frame.command_id(), command::IMAGE_BLOCK_RESPONSE);
+        assert_eq!(frame.payload()[13], MAX_BLOCK_SIZE);
+        assert_eq!(frame.payload()[14..18], FILE_MAGIC);
+
+        // A page of 100 bytes comes as blocks of 48, 48 and 4
+        let mut page = block_request(ID, 48);
+        page.extend_from_slice(&100u16.to_le_bytes());
+        page.extend_from_slice(&0u16.to_le_bytes());
+        ask(command::IMAGE_PAGE_REQUEST, page);
+        sent(&mock, 6).await;
+        let blocks: Vec<(u32, u8)> = mock.aps_requests()[3..]
+            .iter()
+            .map(|request| {
+                let frame = ZclFrame::parse(&request.asdu).unwrap();
+                let payload = frame.payload();
+                let offset = u32::from_le_bytes(payload[9..13].try_into().unwrap());
+                (offset, payload[13])
+            })
+            .collect();
+        assert_eq!(blocks, [(48, 48), (96, 48), (144, 4)]);
+
+        // Some other image
+        let other = ImageId {
+            image_type: 0x11C6,
+            ..ID
+        };
+        ask(command::IMAGE_BLOCK_REQUEST, block_request(other, 0));
+        assert_eq!(sent(&mock, 7).await.payload(), [status::ABORT]);
+
+        let mut end = vec![status::SUCCESS];
+        end.extend(ID.to_bytes());
+        ask(command::UPGRADE_END_REQUEST, end);
+        assert_eq!(
+            sent(&mock, 8).await.command_id(),
+            command::UPGRADE_END_RESPONSE
+        );
+        assert_eq!(devices.get(&[1; 8]).unwrap().firmware, Some(ID));
+        let mut last = None;
+        while let Ok(event) = events.try_recv() {
+            if let NetworkEvent::OtaProgress {
+                status, percent, ..
+            } = event
+            {
+                last = Some((status, percent));
+            }
+        }
+        assert_eq!(last, Some((OtaStatus::Finished, 100)));
+        assert!(server.transfers.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quiet_transfers_expire() {
+        let server = Arc::new(OtaServer::new(None));
+        let devices = DashMap::new();
+        devices.insert([1; 8], ZigbeeDevice::new([1; 8], 0x1234));
+        let (event_tx, mut events) = broadcast::channel(64);
+        let transport: Arc<dyn Transport> = Arc::new(MockTransport::new());
+        server.begin([1; 8], &event_tx);
+        assert!(matches!(
+            events.recv().await,
+            Ok(NetworkEvent::OtaProgress {
+                status: OtaStatus::Waiting,
+                ..
+            })
+        ));
+
+        // Hearing from the device puts off the deadline
+        tokio::time::sleep(WAIT_TIMEOUT / 2).await;
+        request(
+            &server,
+            &devices,
+            &event_tx,
+            &transport,
+            command::IMAGE_BLOCK_REQUEST,
+            block_request(ID, 0),
+        );
+        tokio::time::sleep(WAIT_TIMEOUT * 3 / 4).await;
+        assert!(server.transfers.contains_key(&[1; 8]));
+
+        tokio::time::sleep(WAIT_TIMEOUT / 2).await;
+        assert!(server.transfers.is_empty());
+        let failed = std::iter::from_fn(|| events.try_recv().ok()).any(|event| {
+            matches!(
+                event,
+                NetworkEvent::OtaProgress {
+                    status: OtaStatus::Failed,
+                    ..
+                }
+            )
+        });
+        assert!(failed);
+    }
+}
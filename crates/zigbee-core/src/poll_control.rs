@@ -0,0 +1,401 @@
+//! Poll Control for sleepy end devices
+//!
+//! Battery devices with the Poll Control cluster (radiator valves, some
+//! sensors) sleep between MAC data polls and only check in every few
+//! minutes to an hour. A command sent while they sleep waits in the
+//! adapter's indirect queue and usually expires before they wake, so
+//! commands for them are queued here instead. When the device checks in it
+//! is told to fast poll, the queue is sent, and fast polling is stopped
+//! again.
+//!
+//! ```text
+//! device                      coordinator
+//!   | -- Check-in --------------> |
+//!   | <-- Check-in Response ----- |  start fast polling (if anything queued)
+//!   | <-- queued commands ------- |
+//!   | <-- Fast Poll Stop -------- |
+//! ```
+//!
+//! Devices only check in with the coordinator if they do so by default or
+//! have been bound to it, so commands are only queued for a device that has
+//! checked in within [`CHECK_IN_TIMEOUT`]; others keep getting their
+//! commands right away. Queued commands older than [`QUEUE_TIMEOUT`] are
+//! dropped rather than sent to a device that wakes up much later.
+
+use crate::cluster::{self, id};
+use crate::device::{DeviceType, ZigbeeDevice};
+use crate::network::{NetworkError, ZigbeeNetwork};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, Transport, ZclFrame};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Poll Control cluster
+pub const CLUSTER_ID: u16 = id::POLL_CONTROL;
+
+/// Poll Control cluster commands
+mod command {
+    /// Server to client
+    pub const CHECK_IN: u8 = 0x00;
+    /// Client to server
+    pub const CHECK_IN_RESPONSE: u8 = 0x00;
+    pub const FAST_POLL_STOP: u8 = 0x01;
+}
+
+/// ZCL Write Attributes command
+const WRITE_ATTRIBUTES: u8 = 0x02;
+
+/// How long a device fast polls at most, in quarter seconds
+const FAST_POLL_TIMEOUT: u16 = 10 * 4;
+
+/// Most commands kept for one device; the oldest are dropped
+const MAX_QUEUED: usize = 16;
+
+/// Commands are queued for devices that checked in this recently; the
+/// default check-in interval is an hour
+pub const CHECK_IN_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Queued commands are dropped after this long
+pub const QUEUE_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// What became of a command for a device that may be asleep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    Sent,
+    /// Waiting for the device's next check-in
+    Queued,
+}
+
+/// Whether a device is a sleepy end device with the Poll Control cluster
+#[must_use]
+pub fn is_sleepy(device: &ZigbeeDevice) -> bool {
+    device.device_type == DeviceType::EndDevice
+        && device
+            .endpoints
+            .iter()
+            .any(|e| e.in_clusters.contains(&CLUSTER_ID))
+}
+
+/// Whether `new` makes the queued `old` pointless: the same cluster command
+/// or a write of the same attribute, to the same endpoint
+fn replaces(new: &ApsDataRequest, old: &ApsDataRequest) -> bool {
+    if new.cluster_id != old.cluster_id || new.dest_endpoint != old.dest_endpoint {
+        return false;
+    }
+    let (Ok(new), Ok(old)) = (ZclFrame::parse(&new.asdu), ZclFrame::parse(&old.asdu)) else {
+        return false;
+    };
+    if new.frame_control() != old.frame_control() || new.command_id() != old.command_id() {
+        return false;
+    }
+    new.is_cluster_specific()
+        || (new.command_id() == WRITE_ATTRIBUTES
+            && new.payload().get(..2) == old.payload().get(..2))
+}
+
+/// Commands waiting for sleepy devices to check in
+#[derive(Default)]
+pub(crate) struct PendingCommands {
+    /// Commands and when they were queued
+    queues: DashMap<[u8; 8], VecDeque<(Instant, ApsDataRequest)>>,
+    /// Last check-in of each device since startup
+    check_ins: DashMap<[u8; 8], Instant>,
+}
+
+fn expired(queued_at: Instant, now: Instant) -> bool {
+    now.saturating_duration_since(queued_at) >= QUEUE_TIMEOUT
+}
+
+impl PendingCommands {
+    /// Whether a device has been checking in, so commands can wait for it
+    fn checks_in(&self, ieee: &[u8; 8], now: Instant) -> bool {
+        self.check_ins
+            .get(ieee)
+            .is_some_and(|at| now.saturating_duration_since(*at) < CHECK_IN_TIMEOUT)
+    }
+
+    /// Queue a command, replacing an older one it supersedes
+    fn push(&self, ieee: [u8; 8], request: ApsDataRequest, now: Instant) {
+        let mut queue = self.queues.entry(ieee).or_default();
+        queue.retain(|(queued_at, old)| !expired(*queued_at, now) && !replaces(&request, old));
+        if queue.len() == MAX_QUEUED {
+            queue.pop_front();
+        }
+        queue.push_back((now, request));
+    }
+
+    /// Record a check-in and take the commands still worth sending
+    fn check_in(&self, ieee: [u8; 8], now: Instant) -> Vec<ApsDataRequest> {
+        self.check_ins.insert(ieee, now);
+        let Some((_, queue)) = self.queues.remove(&ieee) else {
+            return Vec::new();
+        };
+        let total = queue.len();
+        let fresh: Vec<ApsDataRequest> = queue
+            .into_iter()
+            .filter(|(queued_at, _)| !expired(*queued_at, now))
+            .map(|(_, request)| request)
+            .collect();
+        if fresh.len() < total {
+            tracing::info!(
+                "Dropped {} expired command(s) for {:02X?}",
+                total - fresh.len(),
+                ieee
+            );
+        }
+        fresh
+    }
+
+    fn len(&self, ieee: &[u8; 8], now: Instant) -> usize {
+        self.queues.get(ieee).map_or(0, |queue| {
+            queue
+                .iter()
+                .filter(|(queued_at, _)| !expired(*queued_at, now))
+                .count()
+        })
+    }
+}
+
+/// Answer a check-in, sending the commands queued for the device
+pub(crate) fn handle_frame(
+    pending: &PendingCommands,
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    transport: &Arc<dyn Transport>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    if !zcl.is_cluster_specific() || !zcl.is_from_server() || zcl.command_id() != command::CHECK_IN
+    {
+        return;
+    }
+    let Some(ieee_address) = devices
+        .iter()
+        .find(|d| d.nwk_address == indication.src_short_addr)
+        .map(|d| d.ieee_address)
+    else {
+        return;
+    };
+    let queued = pending.check_in(ieee_address, Instant::now());
+
+    let reply = |seq, command_id, payload| {
+        ApsDataRequest::new(
+            1,
+            indication.src_short_addr,
+            indication.src_endpoint,
+            CLUSTER_ID,
+            ZclFrame::cluster_command(seq, command_id)
+                .with_payload(payload)
+                .serialize(),
+        )
+    };
+    let fast_poll = u8::from(!queued.is_empty());
+    let mut payload = vec![fast_poll];
+    payload.extend_from_slice(&FAST_POLL_TIMEOUT.to_le_bytes());
+    let response = reply(zcl.transaction_seq(), command::CHECK_IN_RESPONSE, payload);
+    let stop = reply(cluster::next_tsn(), command::FAST_POLL_STOP, Vec::new());
+
+    if !queued.is_empty() {
+        tracing::info!(
+            "{:#06x} checked in, sending {} queued command(s)",
+            indication.src_short_addr,
+            queued.len()
+        );
+    }
+    let nwk_address = indication.src_short_addr;
+    let transport = Arc::clone(transport);
+    tokio::spawn(async move {
+        if let Err(e) = transport.send_aps_request(response).await {
+            tracing::debug!("Failed to answer check-in: {}", e);
+            return;
+        }
+        if queued.is_empty() {
+            return;
+        }
+        for mut request in queued {
+            // The device may have rejoined with a new address since
+            request.dest_short_addr = nwk_address;
+            if let Err(e) = transport.send_aps_request(request).await {
+                tracing::warn!(
+                    "Failed to send queued command to {:#06x}: {}",
+                    nwk_address,
+                    e
+                );
+            }
+        }
+        if let Err(e) = transport.send_aps_request(stop).await {
+            tracing::debug!("Failed to stop fast polling: {}", e);
+        }
+    });
+}
+
+impl ZigbeeNetwork {
+    /// Whether commands for a device wait for its next check-in: it is a
+    /// sleepy Poll Control device that has been checking in
+    #[must_use]
+    pub fn queues_commands(&self, device: &ZigbeeDevice) -> bool {
+        is_sleepy(device)
+            && self
+                .pending_commands()
+                .checks_in(&device.ieee_address, Instant::now())
+    }
+
+    /// Send a command to a device, or queue it until the device's next
+    /// check-in (see [`Self::queues_commands`])
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn send_or_queue(
+        &self,
+        device: &ZigbeeDevice,
+        request: ApsDataRequest,
+    ) -> Result<Dispatch, NetworkError> {
+        if self.queues_commands(device) {
+            tracing::debug!(
+                "Queueing command for {} until it checks in",
+                device.display_name()
+            );
+            self.pending_commands()
+                .push(device.ieee_address, request, Instant::now());
+            return Ok(Dispatch::Queued);
+        }
+        self.transport().send_aps_request(request).await?;
+        Ok(Dispatch::Sent)
+    }
+
+    /// Number of commands waiting for a device to check in
+    #[must_use]
+    pub fn queued_commands(&self, ieee: &[u8; 8]) -> usize {
+        self.pending_commands().len(ieee, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::{thermostat_attrs, DataType};
+    use crate::device::Endpoint;
+    use deconz_protocol::mock::MockTransport;
+
+    fn setpoint(celsius: i16) -> ApsDataRequest {
+        let frame = ZclFrame::write_attribute(
+            1,
+            thermostat_attrs::OCCUPIED_HEATING_SETPOINT,
+            DataType::Int16 as u8,
+            &(celsius * 100).to_le_bytes(),
+        );
+        ApsDataRequest::new(1, 0x1234, 1, 0x0201, frame.serialize())
+    }
+
+    #[test]
+    fn test_newer_commands_replace_queued() {
+        let pending = PendingCommands::default();
+        let now = Instant::now();
+        pending.push([1; 8], setpoint(20), now);
+        pending.push([1; 8], setpoint(21), now);
+        let identify = ZclFrame::cluster_command(1, 0x00).with_payload(vec![5, 0]);
+        pending.push(
+            [1; 8],
+            ApsDataRequest::new(1, 0x1234, 1, 0x0003, identify.serialize()),
+            now,
+        );
+        assert_eq!(pending.len(&[1; 8], now), 2);
+
+        let queued = pending.check_in([1; 8], now);
+        assert_eq!(queued[0].asdu, setpoint(21).asdu);
+        assert_eq!(pending.len(&[1; 8], now), 0);
+    }
+
+    #[test]
+    fn test_queued_commands_expire() {
+        let pending = PendingCommands::default();
+        let now = Instant::now();
+        pending.push([1; 8], setpoint(20), now);
+        let identify = ZclFrame::cluster_command(1, 0x00).with_payload(vec![5, 0]);
+        pending.push(
+            [1; 8],
+            ApsDataRequest::new(1, 0x1234, 1, 0x0003, identify.serialize()),
+            now + QUEUE_TIMEOUT / 2,
+        );
+
+        let later = now + QUEUE_TIMEOUT;
+        assert_eq!(pending.len(&[1; 8], later), 1);
+        let queued = pending.check_in([1; 8], later);
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].cluster_id, 0x0003);
+    }
+
+    #[test]
+    fn test_only_devices_checking_in_queue() {
+        let pending = PendingCommands::default();
+        let now = Instant::now();
+        assert!(!pending.checks_in(&[1; 8], now));
+        pending.check_in([1; 8], now);
+        assert!(pending.checks_in(&[1; 8], now + CHECK_IN_TIMEOUT / 2));
+        // Stopped checking in, e.g. unbound or a flat battery
+        assert!(!pending.checks_in(&[1; 8], now + CHECK_IN_TIMEOUT));
+    }
+
+    #[tokio::test]
+    async fn test_commands_wait_for_check_in() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        device.endpoints.push(Endpoint {
+            id: 1,
+            profile_id: deconz_protocol::profiles::HOME_AUTOMATION,
+            device_id: 0x0301,
+            in_clusters: vec![CLUSTER_ID, 0x0201],
+            out_clusters: Vec::new(),
+        });
+        network.upsert_device(device.clone());
+
+        // Never checked in, so sent right away
+        assert_eq!(
+            network.send_or_queue(&device, setpoint(20)).await.unwrap(),
+            Dispatch::Sent
+        );
+        assert_eq!(mock.aps_requests().len(), 1);
+
+        let check_in = deconz_protocol::mock::indication(
+            0x1234,
+            CLUSTER_ID,
+            vec![0x19, 0x05, command::CHECK_IN],
+        );
+        let zcl = ZclFrame::parse(&check_in.asdu).unwrap();
+        let transport: Arc<dyn Transport> = mock.clone();
+        handle_frame(
+            network.pending_commands(),
+            network.devices(),
+            &transport,
+            &check_in,
+            &zcl,
+        );
+        while mock.aps_requests().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(
+            network.send_or_queue(&device, setpoint(21)).await.unwrap(),
+            Dispatch::Queued
+        );
+        assert_eq!(network.queued_commands(&[1; 8]), 1);
+        assert_eq!(mock.aps_requests().len(), 2);
+
+        // Next check-in: response with fast poll, the setpoint, fast poll stop
+        handle_frame(
+            network.pending_commands(),
+            network.devices(),
+            &transport,
+            &check_in,
+            &zcl,
+        );
+        while mock.aps_requests().len() < 5 {
+            tokio::task::yield_now().await;
+        }
+        let requests = mock.aps_requests();
+        assert_eq!(ZclFrame::parse(&requests[2].asdu).unwrap().payload()[0], 1);
+        assert_eq!(requests[3].asdu, setpoint(21).asdu);
+        assert_eq!(network.queued_commands(&[1; 8]), 0);
+    }
+}
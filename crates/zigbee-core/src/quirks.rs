@@ -0,0 +1,613 @@
+//! Per-device quirks
+//!
+//! Some devices need handling the clusters they advertise don't describe:
+//! remotes send plain On/Off, Level Control and (IKEA) proprietary Scenes
+//! commands that only mean "button N" once you know the model, Tuya scene
+//! switches a vendor On/Off command carrying single, double or long press,
+//! and Aqara switches a press count as a Multistate Input report. A [`Quirk`]
+//! is matched on the Basic cluster manufacturer and model (see
+//! [`crate::basic`]) and says how to turn commands into
+//! [`NetworkEvent::ButtonEvent`]s, where the vendor publishes firmware,
+//! whether the battery has to be polled and where a radiator valve takes the
+//! temperature of a separate room sensor.
+//!
+//! Buttons are numbered from 1 in the order listed for each model, or by
+//! endpoint on switches with one endpoint per button.
+
+use crate::cluster::{
+    self, id, multistate_attrs, parse_attribute_report, power_config_attrs, AttributeValue,
+    GlobalCommand,
+};
+use crate::device::ZigbeeDevice;
+use crate::network::{ButtonAction, NetworkEvent};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, Transport, ZclFrame};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// How often a polled battery is read at most
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Danfoss manufacturer code
+const DANFOSS: u16 = 0x1246;
+
+/// IKEA firmware index
+const IKEA_OTA_INDEX: &str = "https://fw.ota.homesmart.ikea.com/DIRIGERA/version_info.json";
+
+/// On/Off cluster commands
+mod on_off {
+    pub const OFF: u8 = 0x00;
+    pub const ON: u8 = 0x01;
+    pub const TOGGLE: u8 = 0x02;
+}
+
+/// Level Control cluster commands
+mod level {
+    pub const MOVE: u8 = 0x01;
+    pub const STEP: u8 = 0x02;
+    pub const STOP: u8 = 0x03;
+    pub const MOVE_WITH_ON_OFF: u8 = 0x05;
+    pub const STEP_WITH_ON_OFF: u8 = 0x06;
+    pub const STOP_WITH_ON_OFF: u8 = 0x07;
+}
+
+/// Tuya scene switch On/Off cluster command; the first payload byte is
+/// 0x00 for a single press, 0x01 for double and 0x02 for long
+mod tuya_scene {
+    pub const PRESS: u8 = 0xFD;
+}
+
+/// IKEA's proprietary Scenes cluster commands (arrow buttons); the first
+/// payload byte is 0x01 for left and 0x00 for right
+mod ikea_scenes {
+    pub const PRESS: u8 = 0x07;
+    pub const HOLD: u8 = 0x08;
+    pub const RELEASE: u8 = 0x09;
+}
+
+/// Which button a command stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Number(u8),
+    /// The button last held on this device (stop commands don't say which)
+    LastHeld,
+    /// The endpoint the command came from
+    Endpoint,
+}
+
+/// A command that means a button action
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonMapping {
+    pub cluster_id: u16,
+    pub command_id: u8,
+    /// Required first payload byte (direction), if any
+    pub first_byte: Option<u8>,
+    pub button: Button,
+    pub action: ButtonAction,
+}
+
+const fn button(
+    cluster_id: u16,
+    command_id: u8,
+    first_byte: Option<u8>,
+    button: Button,
+    action: ButtonAction,
+) -> ButtonMapping {
+    ButtonMapping {
+        cluster_id,
+        command_id,
+        first_byte,
+        button,
+        action,
+    }
+}
+
+/// Manufacturer-specific attribute a radiator valve reads the room
+/// temperature from (int16, hundredths of a degree)
+#[derive(Debug, Clone, Copy)]
+pub struct ExternalTemperature {
+    pub cluster_id: u16,
+    pub attribute_id: u16,
+    pub manufacturer_code: u16,
+}
+
+/// Handling for one manufacturer's models
+#[derive(Debug)]
+pub struct Quirk {
+    pub description: &'static str,
+    /// Basic cluster manufacturer name
+    pub manufacturer: &'static str,
+    /// Basic cluster model identifiers
+    pub models: &'static [&'static str],
+    pub buttons: &'static [ButtonMapping],
+    /// Where the vendor lists firmware images
+    pub ota_index_url: Option<&'static str>,
+    /// The battery isn't reported on its own; read it (at most daily) while
+    /// the device is awake after sending a command
+    pub poll_battery: bool,
+    /// The valve can regulate on a room sensor's temperature instead of its
+    /// own, which sits next to the radiator
+    pub external_temperature: Option<ExternalTemperature>,
+    /// Multistate Input present values the switch reports for the button
+    /// at the reporting endpoint
+    pub press_values: &'static [(u16, ButtonAction)],
+}
+
+use Button::{Endpoint, LastHeld, Number};
+use ButtonAction::{Double, Hold, Release, Single, Triple};
+
+/// Buttons: 1 on, 2 off; holding dims up or down
+const IKEA_ON_OFF: &[ButtonMapping] = &[
+    button(id::ON_OFF, on_off::ON, None, Number(1), Single),
+    button(id::ON_OFF, on_off::OFF, None, Number(2), Single),
+    button(
+        id::LEVEL_CONTROL,
+        level::MOVE_WITH_ON_OFF,
+        None,
+        Number(1),
+        Hold,
+    ),
+    button(id::LEVEL_CONTROL, level::MOVE, None, Number(2), Hold),
+    button(id::LEVEL_CONTROL, level::STOP, None, LastHeld, Release),
+    button(
+        id::LEVEL_CONTROL,
+        level::STOP_WITH_ON_OFF,
+        None,
+        LastHeld,
+        Release,
+    ),
+];
+
+/// Buttons: 1 on, 2 off, 3 left arrow, 4 right arrow
+const IKEA_STYRBAR: &[ButtonMapping] = &[
+    button(id::ON_OFF, on_off::ON, None, Number(1), Single),
+    button(id::ON_OFF, on_off::OFF, None, Number(2), Single),
+    button(
+        id::LEVEL_CONTROL,
+        level::MOVE_WITH_ON_OFF,
+        None,
+        Number(1),
+        Hold,
+    ),
+    button(id::LEVEL_CONTROL, level::MOVE, None, Number(2), Hold),
+    button(id::LEVEL_CONTROL, level::STOP, None, LastHeld, Release),
+    button(
+        id::LEVEL_CONTROL,
+        level::STOP_WITH_ON_OFF,
+        None,
+        LastHeld,
+        Release,
+    ),
+    button(
+        id::SCENES,
+        ikea_scenes::PRESS,
+        Some(0x01),
+        Number(3),
+        Single,
+    ),
+    button(
+        id::SCENES,
+        ikea_scenes::PRESS,
+        Some(0x00),
+        Number(4),
+        Single,
+    ),
+    button(id::SCENES, ikea_scenes::HOLD, Some(0x01), Number(3), Hold),
+    button(id::SCENES, ikea_scenes::HOLD, Some(0x00), Number(4), Hold),
+    button(id::SCENES, ikea_scenes::RELEASE, None, LastHeld, Release),
+];
+
+/// Buttons: 1 toggle, 2 brighter, 3 dimmer, 4 left arrow, 5 right arrow
+const IKEA_FIVE_BUTTON: &[ButtonMapping] = &[
+    button(id::ON_OFF, on_off::TOGGLE, None, Number(1), Single),
+    button(
+        id::LEVEL_CONTROL,
+        level::STEP_WITH_ON_OFF,
+        None,
+        Number(2),
+        Single,
+    ),
+    button(id::LEVEL_CONTROL, level::STEP, None, Number(3), Single),
+    button(
+        id::LEVEL_CONTROL,
+        level::MOVE_WITH_ON_OFF,
+        None,
+        Number(2),
+        Hold,
+    ),
+    button(id::LEVEL_CONTROL, level::MOVE, None, Number(3), Hold),
+    button(id::LEVEL_CONTROL, level::STOP, None, LastHeld, Release),
+    button(
+        id::LEVEL_CONTROL,
+        level::STOP_WITH_ON_OFF,
+        None,
+        LastHeld,
+        Release,
+    ),
+    button(
+        id::SCENES,
+        ikea_scenes::PRESS,
+        Some(0x01),
+        Number(4),
+        Single,
+    ),
+    button(
+        id::SCENES,
+        ikea_scenes::PRESS,
+        Some(0x00),
+        Number(5),
+        Single,
+    ),
+    button(id::SCENES, ikea_scenes::HOLD, Some(0x01), Number(4), Hold),
+    button(id::SCENES, ikea_scenes::HOLD, Some(0x00), Number(5), Hold),
+    button(id::SCENES, ikea_scenes::RELEASE, None, LastHeld, Release),
+];
+
+/// Buttons by endpoint; no release is sent after a long press
+const TUYA_SCENE_SWITCH: &[ButtonMapping] = &[
+    button(id::ON_OFF, tuya_scene::PRESS, Some(0x00), Endpoint, Single),
+    button(id::ON_OFF, tuya_scene::PRESS, Some(0x01), Endpoint, Double),
+    button(id::ON_OFF, tuya_scene::PRESS, Some(0x02), Endpoint, Hold),
+];
+
+/// Aqara wireless switches: 0 long press, then the number of presses
+const AQARA_PRESSES: &[(u16, ButtonAction)] = &[
+    (0, Hold),
+    (1, Single),
+    (2, Double),
+    (3, Triple),
+    (255, Release),
+];
+
+/// Aqara mini switch (WXKG12LM), which can also be shaken
+const AQARA_MINI_PRESSES: &[(u16, ButtonAction)] =
+    &[(1, Single), (2, Double), (16, Hold), (17, Release)];
+
+/// Known quirks
+pub const QUIRKS: &[Quirk] = &[
+    Quirk {
+        description: "IKEA TRÅDFRI remote control (E1524/E1810)",
+        manufacturer: "IKEA of Sweden",
+        models: &["TRADFRI remote control"],
+        buttons: IKEA_FIVE_BUTTON,
+        ota_index_url: Some(IKEA_OTA_INDEX),
+        poll_battery: true,
+        external_temperature: None,
+        press_values: &[],
+    },
+    Quirk {
+        description: "IKEA STYRBAR remote (E2001/E2002)",
+        manufacturer: "IKEA of Sweden",
+        models: &["Remote Control N2"],
+        buttons: IKEA_STYRBAR,
+        ota_index_url: Some(IKEA_OTA_INDEX),
+        poll_battery: true,
+        external_temperature: None,
+        press_values: &[],
+    },
+    Quirk {
+        description: "IKEA RODRET dimmer / TRÅDFRI on/off switch",
+        manufacturer: "IKEA of Sweden",
+        models: &["RODRET Dimmer", "TRADFRI on/off switch"],
+        buttons: IKEA_ON_OFF,
+        ota_index_url: Some(IKEA_OTA_INDEX),
+        poll_battery: true,
+        external_temperature: None,
+        press_values: &[],
+    },
+    Quirk {
+        description: "Tuya wireless scene switch (TS0041-TS0044)",
+        manufacturer: "_TZ3000_*",
+        models: &["TS0041", "TS0042", "TS0043", "TS0044"],
+        buttons: TUYA_SCENE_SWITCH,
+        ota_index_url: None,
+        poll_battery: false,
+        external_temperature: None,
+        press_values: &[],
+    },
+    // Double rockers report the left button on endpoint 1, the right one on
+    // 2 and both at once on 3.
+    Quirk {
+        description: "Aqara wireless remote switch (WXKG06LM/WXKG07LM/WXKG15LM)",
+        manufacturer: "LUMI",
+        models: &[
+            "lumi.remote.b1acn01",
+            "lumi.remote.b186acn01",
+            "lumi.remote.b286acn01",
+            "lumi.remote.b186acn02",
+            "lumi.remote.b286acn02",
+            "lumi.remote.b18ac1",
+            "lumi.remote.b28ac1",
+        ],
+        buttons: &[],
+        ota_index_url: None,
+        poll_battery: false,
+        external_temperature: None,
+        press_values: AQARA_PRESSES,
+    },
+    Quirk {
+        description: "Aqara wireless mini switch (WXKG12LM)",
+        manufacturer: "LUMI",
+        models: &["lumi.sensor_switch.aq3"],
+        buttons: &[],
+        ota_index_url: None,
+        poll_battery: false,
+        external_temperature: None,
+        press_values: AQARA_MINI_PRESSES,
+    },
+    // The valve falls back to its own sensor if no room temperature arrives
+    // for 3 hours.
+    Quirk {
+        description: "Danfoss Ally radiator thermostat",
+        manufacturer: "Danfoss",
+        models: &["eTRV0100", "eTRV0101", "eTRV0103"],
+        buttons: &[],
+        ota_index_url: None,
+        poll_battery: false,
+        external_temperature: Some(ExternalTemperature {
+            cluster_id: id::THERMOSTAT,
+            attribute_id: 0x4015,
+            manufacturer_code: DANFOSS,
+        }),
+        press_values: &[],
+    },
+];
+
+/// Quirk for a device, once its manufacturer and model are known
+///
+/// A manufacturer ending in `*` matches any name starting with the rest
+/// (Tuya puts a batch code in it).
+#[must_use]
+pub fn quirk_for(device: &ZigbeeDevice) -> Option<&'static Quirk> {
+    let manufacturer = device.manufacturer.as_deref()?;
+    let model = device.model.as_deref()?;
+    QUIRKS.iter().find(|q| {
+        let vendor = match q.manufacturer.strip_suffix('*') {
+            Some(prefix) => manufacturer.starts_with(prefix),
+            None => q.manufacturer == manufacturer,
+        };
+        vendor && q.models.contains(&model)
+    })
+}
+
+/// Button press for an attribute report, if the quirk maps it
+fn reported_press(quirk: &Quirk, cluster_id: u16, payload: &[u8]) -> Option<ButtonAction> {
+    if cluster_id != id::MULTISTATE_INPUT || quirk.press_values.is_empty() {
+        return None;
+    }
+    let value = parse_attribute_report(payload)
+        .iter()
+        .find(|a| a.id == multistate_attrs::PRESENT_VALUE)
+        .and_then(AttributeValue::as_u32)?;
+    quirk
+        .press_values
+        .iter()
+        .find(|(v, _)| u32::from(*v) == value)
+        .map(|(_, action)| *action)
+}
+
+/// Button press for a command, if the quirk maps it
+fn button_action(
+    quirk: &Quirk,
+    cluster_id: u16,
+    src_endpoint: u8,
+    zcl: &ZclFrame,
+    last_held: Option<u8>,
+) -> Option<(u8, ButtonAction)> {
+    let mapping = quirk.buttons.iter().find(|m| {
+        m.cluster_id == cluster_id
+            && m.command_id == zcl.command_id()
+            && m.first_byte
+                .is_none_or(|b| zcl.payload().first() == Some(&b))
+    })?;
+    let number = match mapping.button {
+        Number(n) => n,
+        LastHeld => last_held?,
+        Endpoint => src_endpoint,
+    };
+    Some((number, mapping.action))
+}
+
+/// Per-device state kept by the event listener
+#[derive(Debug, Default)]
+pub(crate) struct QuirkState {
+    last_held: HashMap<[u8; 8], u8>,
+    battery_read: HashMap<[u8; 8], Instant>,
+}
+
+/// Turn a command from a quirked remote into a button event
+///
+/// The command is still handled as usual afterwards (On/Off commands also
+/// update the remote's `state_on`).
+pub(crate) fn handle_command(
+    state: &mut QuirkState,
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    transport: &Arc<dyn Transport>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    let Some((ieee_address, quirk)) = devices
+        .iter()
+        .find(|d| d.nwk_address == indication.src_short_addr)
+        .and_then(|d| Some((d.ieee_address, quirk_for(&d)?)))
+    else {
+        return;
+    };
+
+    let last_held = state.last_held.get(&ieee_address).copied();
+    let pressed = if zcl.is_cluster_specific() {
+        button_action(
+            quirk,
+            indication.cluster_id,
+            indication.src_endpoint,
+            zcl,
+            last_held,
+        )
+    } else if zcl.command_id() == GlobalCommand::ReportAttributes as u8 {
+        reported_press(quirk, indication.cluster_id, zcl.payload())
+            .map(|action| (indication.src_endpoint, action))
+    } else {
+        return;
+    };
+    if let Some((button, action)) = pressed {
+        match action {
+            Hold => {
+                state.last_held.insert(ieee_address, button);
+            }
+            Release => {
+                state.last_held.remove(&ieee_address);
+            }
+            Single | Double | Triple => {}
+        }
+        tracing::debug!(
+            "{} {:#06x}: button {} {:?}",
+            quirk.description,
+            indication.src_short_addr,
+            button,
+            action
+        );
+        let _ = event_tx.send(NetworkEvent::ButtonEvent {
+            ieee_address,
+            endpoint: button,
+            action,
+        });
+    }
+
+    // Sleepy remotes only listen right after sending something
+    let due = state
+        .battery_read
+        .get(&ieee_address)
+        .is_none_or(|at| at.elapsed() >= BATTERY_POLL_INTERVAL);
+    if quirk.poll_battery && due {
+        state.battery_read.insert(ieee_address, Instant::now());
+        let request = ApsDataRequest::new(
+            1,
+            indication.src_short_addr,
+            indication.src_endpoint,
+            id::POWER_CONFIG,
+            ZclFrame::read_attributes(
+                cluster::next_tsn(),
+                &[power_config_attrs::BATTERY_PERCENTAGE_REMAINING],
+            )
+            .serialize(),
+        );
+        let transport = Arc::clone(transport);
+        tokio::spawn(async move {
+            if let Err(e) = transport.send_aps_request(request).await {
+                tracing::debug!("Failed to read remote battery: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(command_id: u8, payload: &[u8]) -> ZclFrame {
+        ZclFrame::cluster_command(cluster::next_tsn(), command_id).with_payload(payload.to_vec())
+    }
+
+    #[test]
+    fn test_styrbar_buttons() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        device.manufacturer = Some("IKEA of Sweden".to_string());
+        device.model = Some("Remote Control N2".to_string());
+        let quirk = quirk_for(&device).unwrap();
+
+        assert_eq!(
+            button_action(quirk, id::ON_OFF, 1, &frame(on_off::OFF, &[]), None),
+            Some((2, Single))
+        );
+        assert_eq!(
+            button_action(
+                quirk,
+                id::SCENES,
+                1,
+                &frame(ikea_scenes::PRESS, &[0x00, 0x01, 0x0D, 0x00]),
+                None
+            ),
+            Some((4, Single))
+        );
+        assert_eq!(
+            button_action(
+                quirk,
+                id::SCENES,
+                1,
+                &frame(ikea_scenes::HOLD, &[0x01]),
+                None
+            ),
+            Some((3, Hold))
+        );
+        // Release goes to whichever button was held
+        assert_eq!(
+            button_action(
+                quirk,
+                id::SCENES,
+                1,
+                &frame(ikea_scenes::RELEASE, &[0x00, 0x00]),
+                Some(3)
+            ),
+            Some((3, Release))
+        );
+        assert_eq!(
+            button_action(
+                quirk,
+                id::SCENES,
+                1,
+                &frame(ikea_scenes::RELEASE, &[]),
+                None
+            ),
+            None
+        );
+
+        device.model = Some("TRADFRI bulb E27".to_string());
+        assert!(quirk_for(&device).is_none());
+    }
+
+    #[test]
+    fn test_multi_press_buttons() {
+        let mut device = ZigbeeDevice::new([1; 8], 0x1234);
+        device.manufacturer = Some("_TZ3000_xabckq1v".to_string());
+        device.model = Some("TS0044".to_string());
+        let tuya = quirk_for(&device).unwrap();
+        assert_eq!(
+            button_action(
+                tuya,
+                id::ON_OFF,
+                3,
+                &frame(tuya_scene::PRESS, &[0x01]),
+                None
+            ),
+            Some((3, Double))
+        );
+        assert_eq!(
+            button_action(
+                tuya,
+                id::ON_OFF,
+                2,
+                &frame(tuya_scene::PRESS, &[0x02]),
+                None
+            ),
+            Some((2, Hold))
+        );
+
+        device.manufacturer = Some("LUMI".to_string());
+        device.model = Some("lumi.remote.b28ac1".to_string());
+        let aqara = quirk_for(&device).unwrap();
+        // Present value (uint16) 3
+        let report = [0x55, 0x00, 0x21, 0x03, 0x00];
+        assert_eq!(
+            reported_press(aqara, id::MULTISTATE_INPUT, &report),
+            Some(Triple)
+        );
+        assert_eq!(reported_press(aqara, id::ON_OFF, &report), None);
+        assert_eq!(
+            reported_press(aqara, id::MULTISTATE_INPUT, &[0x55, 0x00, 0x21, 0x07, 0x00]),
+            None
+        );
+    }
+}
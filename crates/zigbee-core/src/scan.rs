@@ -0,0 +1,262 @@
+//! Channel scanning
+//!
+//! Collects MAC beacons from neighboring networks so users can pick an
+//! uncongested channel. Beacons are only reported while the firmware scans,
+//! which it does when the network is brought online; an active scan therefore
+//! briefly cycles the network (offline, then online again). The network is
+//! brought back online however the scan ends.
+
+use crate::formation::{MAX_CHANNEL, MIN_CHANNEL};
+use crate::network::{NetworkError, ZigbeeNetwork};
+use deconz_protocol::{
+    DeconzEvent, MacBeaconIndication, NetworkState, NetworkStateCommand, Transport,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// A neighboring PAN seen during a scan
+#[derive(Debug, Clone, Serialize)]
+pub struct NeighborPan {
+    pub pan_id: u16,
+    /// Extended PAN ID (displayed byte order), if the beacon carried one
+    pub extended_pan_id: Option<String>,
+    /// Best link quality seen from any router of this PAN
+    pub lqi: Option<u8>,
+    /// Strongest signal seen from any router of this PAN (dBm)
+    pub rssi: Option<i8>,
+    /// Number of beacons received
+    pub beacons: u32,
+    pub permit_join: bool,
+    pub update_id: u8,
+}
+
+/// Scan results for a single channel
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelScanResult {
+    pub channel: u8,
+    pub networks: Vec<NeighborPan>,
+}
+
+/// Result of a channel scan
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelScan {
+    pub duration_secs: u64,
+    /// Whether the network was cycled to force an active scan
+    pub active: bool,
+    /// One entry per channel 11-26 (empty list means no neighbors seen)
+    pub channels: Vec<ChannelScanResult>,
+}
+
+impl ZigbeeNetwork {
+    /// Collect beacons for `duration` and group them by channel and PAN
+    ///
+    /// With `active` set, the network is taken offline and brought back
+    /// online to make the firmware scan; otherwise beacons are only collected
+    /// passively.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn scan_channels(
+        &self,
+        duration: Duration,
+        active: bool,
+    ) -> Result<ChannelScan, NetworkError> {
+        tracing::info!(
+            "Scanning channels for {:?} ({})",
+            duration,
+            if active { "active" } else { "passive" }
+        );
+
+        // Dropped scans (e.g. the client went away) still restore the network
+        let guard = active.then(|| OnlineGuard(Some(self.shared_transport())));
+        let result = self.collect_beacons(duration, active).await;
+        if let Some(guard) = guard {
+            guard.disarm();
+            if result.is_err() {
+                self.bring_online().await;
+            }
+        }
+        let channels = result?
+            .into_iter()
+            .map(|(channel, networks)| ChannelScanResult {
+                channel,
+                networks: networks.into_values().collect(),
+            })
+            .collect();
+
+        Ok(ChannelScan {
+            duration_secs: duration.as_secs(),
+            active,
+            channels,
+        })
+    }
+
+    async fn collect_beacons(
+        &self,
+        duration: Duration,
+        active: bool,
+    ) -> Result<BTreeMap<u8, BTreeMap<u16, NeighborPan>>, NetworkError> {
+        let mut rx = self.transport().subscribe();
+
+        if active {
+            self.disconnect().await?;
+            self.wait_for_network_state(NetworkState::Offline).await?;
+            self.connect().await?;
+        }
+
+        let mut channels: BTreeMap<u8, BTreeMap<u16, NeighborPan>> = (MIN_CHANNEL..=MAX_CHANNEL)
+            .map(|ch| (ch, BTreeMap::new()))
+            .collect();
+
+        let deadline = tokio::time::Instant::now() + duration;
+        loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(DeconzEvent::Beacon(beacon))) => record_beacon(&mut channels, &beacon),
+                Ok(Ok(_)) => {}
+                Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                    tracing::warn!("Channel scan lagged by {} events", n);
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => break,
+            }
+        }
+
+        if active {
+            self.wait_for_network_state(NetworkState::Connected).await?;
+        }
+        Ok(channels)
+    }
+
+    /// Best-effort recovery after a failed active scan
+    async fn bring_online(&self) {
+        let result = match self.connect().await {
+            Ok(()) => self.wait_for_network_state(NetworkState::Connected).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to bring the network back online after a scan: {e}");
+        }
+    }
+}
+
+/// Brings the network online if an active scan is dropped before it ends
+struct OnlineGuard(Option<Arc<dyn Transport>>);
+
+impl OnlineGuard {
+    /// The scan ended normally
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for OnlineGuard {
+    fn drop(&mut self) {
+        let Some(transport) = self.0.take() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(e) = transport
+                .change_network_state(NetworkStateCommand::Online)
+                .await
+            {
+                tracing::error!("Failed to bring the network back online after a scan: {e}");
+            }
+        });
+    }
+}
+
+fn record_beacon(
+    channels: &mut BTreeMap<u8, BTreeMap<u16, NeighborPan>>,
+    beacon: &MacBeaconIndication,
+) {
+    let Some(networks) = channels.get_mut(&beacon.channel) else {
+        tracing::debug!("Beacon on unexpected channel {}", beacon.channel);
+        return;
+    };
+    let entry = networks
+        .entry(beacon.pan_id)
+        .or_insert_with(|| NeighborPan {
+            pan_id: beacon.pan_id,
+            extended_pan_id: beacon.extended_pan_id.map(|ext| {
+                ext.iter()
+                    .rev()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(":")
+            }),
+            lqi: None,
+            rssi: None,
+            beacons: 0,
+            permit_join: false,
+            update_id: beacon.update_id,
+        });
+    entry.beacons += 1;
+    entry.lqi = entry.lqi.max(beacon.lqi);
+    entry.rssi = entry.rssi.max(beacon.rssi);
+    entry.permit_join |= beacon.permit_join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::MockTransport;
+    use deconz_protocol::{CommandId, Status};
+
+    fn beacon(pan_id: u16, lqi: u8, rssi: i8) -> MacBeaconIndication {
+        MacBeaconIndication {
+            src_addr: 0x1234,
+            pan_id,
+            channel: 15,
+            flags: 0x00,
+            update_id: 0,
+            extended_pan_id: Some([1, 2, 3, 4, 5, 6, 7, 8]),
+            lqi: Some(lqi),
+            rssi: Some(rssi),
+        }
+    }
+
+    #[test]
+    fn test_beacons_grouped_by_pan() {
+        let mut channels: BTreeMap<u8, BTreeMap<u16, NeighborPan>> = (MIN_CHANNEL..=MAX_CHANNEL)
+            .map(|ch| (ch, BTreeMap::new()))
+            .collect();
+        record_beacon(&mut channels, &beacon(0xABCD, 100, -70));
+        record_beacon(&mut channels, &beacon(0xABCD, 80, -55));
+        record_beacon(&mut channels, &beacon(0x1111, 50, -90));
+
+        let pan = &channels[&15][&0xABCD];
+        assert_eq!(pan.beacons, 2);
+        assert_eq!(pan.lqi, Some(100));
+        assert_eq!(pan.rssi, Some(-55));
+        assert_eq!(
+            pan.extended_pan_id.as_deref(),
+            Some("08:07:06:05:04:03:02:01")
+        );
+        assert_eq!(channels[&15].len(), 2);
+        assert!(channels[&11].is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_active_scan_brings_network_online() {
+        let mock = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(mock.clone(), None)
+            .await
+            .unwrap();
+        mock.respond_always(CommandId::ChangeNetworkState, Status::Success, Vec::new());
+        // The device state can't be read, so the scan fails before it starts
+
+        let result = network.scan_channels(Duration::from_secs(1), true).await;
+        assert!(result.is_err());
+
+        let commands: Vec<u8> = mock
+            .requests()
+            .into_iter()
+            .filter(|frame| frame.command_id == CommandId::ChangeNetworkState)
+            .map(|frame| frame.payload[0])
+            .collect();
+        assert_eq!(
+            commands,
+            [NetworkStateCommand::Offline, NetworkStateCommand::Online].map(|c| c as u8)
+        );
+    }
+}
@@ -0,0 +1,483 @@
+//! Sensor clusters
+//!
+//! Decodes measurement attributes from reports and read responses, keeps
+//! the latest value on the [`ZigbeeDevice`] and emits a [`NetworkEvent`]
+//! when it changes.
+//!
+//! Electrical Measurement and Metering values (smart plugs) are raw
+//! integers that the device scales with its own multiplier and divisor
+//! attributes. Those are read when the cluster is discovered and kept in
+//! [`ZigbeeDevice::sensor_scales`].
+
+use crate::cluster::{
+    self, attribute_values, electrical_attrs, id, measurement_attrs, metering_attrs,
+    occupancy_attrs, power_config_attrs, AttributeValue,
+};
+use crate::device::{Endpoint, ZigbeeDevice};
+use crate::network::{self, DeviceStorage, NetworkEvent};
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, ZclFrame};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Kind of measured value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorKind {
+    /// Degrees Celsius
+    Temperature,
+    /// Relative humidity in percent
+    Humidity,
+    /// Pressure in hPa
+    Pressure,
+    /// Active power in W
+    Power,
+    /// RMS voltage in V
+    Voltage,
+    /// RMS current in A
+    Current,
+    /// Energy delivered in kWh
+    Energy,
+    /// Carbon dioxide in ppm
+    Co2,
+    /// PM2.5 particulate matter in µg/m³
+    Pm25,
+    /// Formaldehyde in ppm
+    Formaldehyde,
+    /// Soil moisture in percent
+    SoilMoisture,
+    /// Battery remaining in percent
+    Battery,
+}
+
+impl SensorKind {
+    /// Unit of the scaled value
+    #[must_use]
+    pub fn unit(self) -> &'static str {
+        match self {
+            SensorKind::Temperature => "°C",
+            SensorKind::Humidity | SensorKind::SoilMoisture | SensorKind::Battery => "%",
+            SensorKind::Pressure => "hPa",
+            SensorKind::Power => "W",
+            SensorKind::Voltage => "V",
+            SensorKind::Current => "A",
+            SensorKind::Energy => "kWh",
+            SensorKind::Co2 | SensorKind::Formaldehyde => "ppm",
+            SensorKind::Pm25 => "µg/m³",
+        }
+    }
+
+    /// Whether this is a power metering value, reported as
+    /// [`NetworkEvent::PowerMeasurement`]
+    #[must_use]
+    pub fn is_electrical(self) -> bool {
+        matches!(
+            self,
+            SensorKind::Power | SensorKind::Voltage | SensorKind::Current | SensorKind::Energy
+        )
+    }
+}
+
+/// Multiplier and divisor a device applies to a raw electrical value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scale {
+    pub multiplier: u32,
+    pub divisor: u32,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self {
+            multiplier: 1,
+            divisor: 1,
+        }
+    }
+}
+
+impl Scale {
+    /// Apply to a raw value
+    #[must_use]
+    pub fn apply(self, raw: f64) -> f64 {
+        raw * f64::from(self.multiplier) / f64::from(self.divisor.max(1))
+    }
+}
+
+/// Which half of a [`Scale`] an attribute sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalePart {
+    Multiplier,
+    Divisor,
+}
+
+/// Whether frames from `cluster_id` are handled here
+#[must_use]
+pub fn is_sensor_cluster(cluster_id: u16) -> bool {
+    matches!(
+        cluster_id,
+        id::POWER_CONFIG
+            | id::OCCUPANCY_SENSING
+            | id::TEMPERATURE_MEASUREMENT
+            | id::HUMIDITY_MEASUREMENT
+            | id::PRESSURE_MEASUREMENT
+            | id::CARBON_DIOXIDE_MEASUREMENT
+            | id::PM25_MEASUREMENT
+            | id::FORMALDEHYDE_MEASUREMENT
+            | id::ELECTRICAL_MEASUREMENT
+            | id::METERING
+    )
+}
+
+/// Scaled measurement carried by an attribute, if it is one we decode
+///
+/// Invalid-value markers (0x8000 for signed, 0xFFFF for unsigned, NaN for
+/// floats) yield `None`.
+#[must_use]
+pub fn measurement(cluster_id: u16, attribute: &AttributeValue) -> Option<(SensorKind, f64)> {
+    if cluster_id == id::POWER_CONFIG {
+        if attribute.id != power_config_attrs::BATTERY_PERCENTAGE_REMAINING {
+            return None;
+        }
+        let raw = attribute.as_u32().filter(|v| *v != 0xFF)?;
+        return Some((SensorKind::Battery, f64::from(raw.min(200)) / 2.0));
+    }
+    if attribute.id != measurement_attrs::MEASURED_VALUE {
+        return None;
+    }
+    match cluster_id {
+        id::TEMPERATURE_MEASUREMENT => {
+            let raw = attribute.as_i32().filter(|v| *v != -0x8000)?;
+            Some((SensorKind::Temperature, f64::from(raw) / 100.0))
+        }
+        id::HUMIDITY_MEASUREMENT => {
+            let raw = attribute.as_u32().filter(|v| *v != 0xFFFF)?;
+            Some((SensorKind::Humidity, f64::from(raw) / 100.0))
+        }
+        id::PRESSURE_MEASUREMENT => {
+            // 0.1 kPa units, i.e. hPa
+            let raw = attribute.as_i32().filter(|v| *v != -0x8000)?;
+            Some((SensorKind::Pressure, f64::from(raw)))
+        }
+        id::CARBON_DIOXIDE_MEASUREMENT => {
+            Some((SensorKind::Co2, parts_per_million(attribute.as_f64()?)))
+        }
+        id::PM25_MEASUREMENT => Some((SensorKind::Pm25, attribute.as_f64()?)),
+        id::FORMALDEHYDE_MEASUREMENT => Some((
+            SensorKind::Formaldehyde,
+            parts_per_million(attribute.as_f64()?),
+        )),
+        _ => None,
+    }
+}
+
+/// Concentrations are specified as a fraction (0.0004 = 400 ppm), but some
+/// sensors send ppm directly; a fraction can't be above 1, so those pass
+/// through unchanged
+fn parts_per_million(value: f64) -> f64 {
+    if value > 1.0 {
+        value
+    } else {
+        value * 1_000_000.0
+    }
+}
+
+/// Raw (unscaled) electrical value carried by an attribute
+///
+/// Energy is returned in the device's units; the Metering divisor usually
+/// converts Wh to kWh.
+#[must_use]
+pub fn electrical_measurement(
+    cluster_id: u16,
+    attribute: &AttributeValue,
+) -> Option<(SensorKind, f64)> {
+    match (cluster_id, attribute.id) {
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::ACTIVE_POWER) => {
+            let raw = attribute.as_i32().filter(|v| *v != -0x8000)?;
+            Some((SensorKind::Power, f64::from(raw)))
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::RMS_VOLTAGE) => {
+            let raw = attribute.as_u32().filter(|v| *v != 0xFFFF)?;
+            Some((SensorKind::Voltage, f64::from(raw)))
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::RMS_CURRENT) => {
+            let raw = attribute.as_u32().filter(|v| *v != 0xFFFF)?;
+            Some((SensorKind::Current, f64::from(raw)))
+        }
+        (id::METERING, metering_attrs::CURRENT_SUMMATION_DELIVERED) => {
+            #[allow(clippy::cast_precision_loss)] // 48-bit counter fits in f64
+            Some((SensorKind::Energy, attribute.as_u64()? as f64))
+        }
+        _ => None,
+    }
+}
+
+/// Multiplier or divisor carried by an attribute
+fn scale_attribute(
+    cluster_id: u16,
+    attribute: &AttributeValue,
+) -> Option<(SensorKind, ScalePart, u32)> {
+    use ScalePart::{Divisor, Multiplier};
+    let (kind, part) = match (cluster_id, attribute.id) {
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::AC_VOLTAGE_MULTIPLIER) => {
+            (SensorKind::Voltage, Multiplier)
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::AC_VOLTAGE_DIVISOR) => {
+            (SensorKind::Voltage, Divisor)
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::AC_CURRENT_MULTIPLIER) => {
+            (SensorKind::Current, Multiplier)
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::AC_CURRENT_DIVISOR) => {
+            (SensorKind::Current, Divisor)
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::AC_POWER_MULTIPLIER) => {
+            (SensorKind::Power, Multiplier)
+        }
+        (id::ELECTRICAL_MEASUREMENT, electrical_attrs::AC_POWER_DIVISOR) => {
+            (SensorKind::Power, Divisor)
+        }
+        (id::METERING, metering_attrs::MULTIPLIER) => (SensorKind::Energy, Multiplier),
+        (id::METERING, metering_attrs::DIVISOR) => (SensorKind::Energy, Divisor),
+        _ => return None,
+    };
+    // Zero would make every value 0 or infinite
+    let value = attribute.as_u32().filter(|v| *v != 0)?;
+    Some((kind, part, value))
+}
+
+/// Read Attributes requests for the scaling factors and current values of
+/// an endpoint's metering clusters
+pub(crate) fn metering_reads(nwk_address: u16, endpoint: &Endpoint) -> Vec<ApsDataRequest> {
+    let mut reads = Vec::new();
+    if endpoint.in_clusters.contains(&id::ELECTRICAL_MEASUREMENT) {
+        reads.push((
+            id::ELECTRICAL_MEASUREMENT,
+            vec![
+                electrical_attrs::AC_VOLTAGE_MULTIPLIER,
+                electrical_attrs::AC_VOLTAGE_DIVISOR,
+                electrical_attrs::AC_CURRENT_MULTIPLIER,
+                electrical_attrs::AC_CURRENT_DIVISOR,
+                electrical_attrs::AC_POWER_MULTIPLIER,
+                electrical_attrs::AC_POWER_DIVISOR,
+                electrical_attrs::RMS_VOLTAGE,
+                electrical_attrs::RMS_CURRENT,
+                electrical_attrs::ACTIVE_POWER,
+            ],
+        ));
+    }
+    if endpoint.in_clusters.contains(&id::METERING) {
+        reads.push((
+            id::METERING,
+            vec![
+                metering_attrs::MULTIPLIER,
+                metering_attrs::DIVISOR,
+                metering_attrs::CURRENT_SUMMATION_DELIVERED,
+            ],
+        ));
+    }
+    reads
+        .into_iter()
+        .map(|(cluster_id, attributes)| {
+            ApsDataRequest::new(
+                1,
+                nwk_address,
+                endpoint.id,
+                cluster_id,
+                ZclFrame::read_attributes(cluster::next_tsn(), &attributes).serialize(),
+            )
+        })
+        .collect()
+}
+
+/// Handle a sensor cluster frame, recording the values and scaling factors
+pub(crate) fn handle_frame(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    if zcl.is_cluster_specific() {
+        return;
+    }
+    let attributes = attribute_values(zcl.command_id(), zcl.payload());
+    if attributes.is_empty() {
+        return;
+    }
+    let Some(ieee_address) = network::ieee_for_nwk(devices, indication.src_short_addr) else {
+        tracing::debug!(
+            "Sensor report from unknown device {:#06x}",
+            indication.src_short_addr
+        );
+        return;
+    };
+
+    let events = network::update_in_place(devices, event_tx, storage, &ieee_address, |device| {
+        device.last_seen = Some(chrono::Utc::now());
+        let endpoint = indication.src_endpoint;
+
+        // Scaling factors first, since read responses carry them with the values
+        for attribute in &attributes {
+            if let Some((kind, part, value)) = scale_attribute(indication.cluster_id, attribute) {
+                let scale = device.sensor_scales.entry(kind).or_default();
+                let field = match part {
+                    ScalePart::Multiplier => &mut scale.multiplier,
+                    ScalePart::Divisor => &mut scale.divisor,
+                };
+                *field = value;
+            }
+        }
+
+        let mut events = Vec::new();
+        for attribute in &attributes {
+            if indication.cluster_id == id::OCCUPANCY_SENSING {
+                if attribute.id != occupancy_attrs::OCCUPANCY {
+                    continue;
+                }
+                let Some(bits) = attribute.as_u32() else {
+                    continue;
+                };
+                let occupied = bits & 0x01 != 0;
+                if device.occupancy != Some(occupied) {
+                    device.occupancy = Some(occupied);
+                    tracing::info!(
+                        "Device {:#06x} occupancy: {}",
+                        indication.src_short_addr,
+                        occupied
+                    );
+                    events.push(NetworkEvent::OccupancyChanged {
+                        ieee_address,
+                        endpoint,
+                        occupied,
+                    });
+                }
+            } else if let Some((kind, value)) = measurement(indication.cluster_id, attribute)
+                .or_else(|| {
+                    let (kind, raw) = electrical_measurement(indication.cluster_id, attribute)?;
+                    let scale = device.sensor_scales.get(&kind).copied().unwrap_or_default();
+                    Some((kind, scale.apply(raw)))
+                })
+            {
+                if device.sensor_values.get(&kind) != Some(&value) {
+                    device.sensor_values.insert(kind, value);
+                    tracing::debug!(
+                        "Device {:#06x} {:?}: {}{}",
+                        indication.src_short_addr,
+                        kind,
+                        value,
+                        kind.unit()
+                    );
+                    events.push(if kind.is_electrical() {
+                        NetworkEvent::PowerMeasurement {
+                            ieee_address,
+                            endpoint,
+                            kind,
+                            value,
+                        }
+                    } else {
+                        NetworkEvent::SensorValue {
+                            ieee_address,
+                            endpoint,
+                            kind,
+                            value,
+                        }
+                    });
+                }
+            }
+        }
+        events
+    })
+    .unwrap_or_default();
+    for event in events {
+        let _ = event_tx.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::parse_attribute_report;
+
+    #[test]
+    fn test_measurement_scaling() {
+        // 21.5 °C
+        let attr = &parse_attribute_report(&[0x00, 0x00, 0x29, 0x66, 0x08])[0];
+        assert_eq!(
+            measurement(id::TEMPERATURE_MEASUREMENT, attr),
+            Some((SensorKind::Temperature, 21.5))
+        );
+        // 45.12 %
+        let attr = &parse_attribute_report(&[0x00, 0x00, 0x21, 0xA0, 0x11])[0];
+        assert_eq!(
+            measurement(id::HUMIDITY_MEASUREMENT, attr),
+            Some((SensorKind::Humidity, 45.12))
+        );
+        // 1013 hPa
+        let attr = &parse_attribute_report(&[0x00, 0x00, 0x29, 0xF5, 0x03])[0];
+        assert_eq!(
+            measurement(id::PRESSURE_MEASUREMENT, attr),
+            Some((SensorKind::Pressure, 1013.0))
+        );
+        // Invalid temperature marker
+        let attr = &parse_attribute_report(&[0x00, 0x00, 0x29, 0x00, 0x80])[0];
+        assert_eq!(measurement(id::TEMPERATURE_MEASUREMENT, attr), None);
+    }
+
+    #[test]
+    fn test_air_quality_measurements() {
+        let float = |value: f32| {
+            let mut payload = vec![0x00, 0x00, 0x39];
+            payload.extend_from_slice(&value.to_le_bytes());
+            parse_attribute_report(&payload).remove(0)
+        };
+        // 612 ppm as a fraction, and as ppm from a sensor that skips the conversion
+        let (kind, value) = measurement(id::CARBON_DIOXIDE_MEASUREMENT, &float(0.000_612)).unwrap();
+        assert_eq!(kind, SensorKind::Co2);
+        assert!((value - 612.0).abs() < 0.01);
+        assert_eq!(
+            measurement(id::CARBON_DIOXIDE_MEASUREMENT, &float(612.0)),
+            Some((SensorKind::Co2, 612.0))
+        );
+        assert_eq!(
+            measurement(id::PM25_MEASUREMENT, &float(12.5)),
+            Some((SensorKind::Pm25, 12.5))
+        );
+        assert_eq!(measurement(id::PM25_MEASUREMENT, &float(f32::NAN)), None);
+    }
+
+    #[test]
+    fn test_read_response_applies_scale() {
+        let devices = DashMap::new();
+        devices.insert([1; 8], ZigbeeDevice::new([1; 8], 0x1234));
+        let (event_tx, mut events) = broadcast::channel(8);
+        let indication =
+            deconz_protocol::mock::indication(0x1234, id::ELECTRICAL_MEASUREMENT, Vec::new());
+        // Read response: current divisor 1000, then RMS current 1520 mA
+        let zcl = ZclFrame::parse(&[
+            0x18, 0x01, 0x01, // global, server to client, read attributes response
+            0x03, 0x06, 0x00, 0x21, 0xE8, 0x03, // AC current divisor = 1000
+            0x08, 0x05, 0x00, 0x21, 0xF0, 0x05, // RMS current = 1520
+        ])
+        .unwrap();
+        handle_frame(&devices, &event_tx, None, &indication, &zcl);
+
+        assert!(matches!(
+            events.try_recv(),
+            Ok(NetworkEvent::DeviceUpdated { .. })
+        ));
+        match events.try_recv().unwrap() {
+            NetworkEvent::PowerMeasurement { kind, value, .. } => {
+                assert_eq!(kind, SensorKind::Current);
+                assert!((value - 1.52).abs() < 1e-9);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+        let device = devices.get(&[1; 8]).unwrap();
+        assert_eq!(
+            device.sensor_scales.get(&SensorKind::Current),
+            Some(&Scale {
+                multiplier: 1,
+                divisor: 1000
+            })
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Power-on behavior of lights
+//!
+//! Most bulbs come back on at full brightness after every power cut. The
+//! On/Off cluster's `StartUpOnOff` and Level Control's `StartUpCurrentLevel`
+//! attributes choose what they do instead. Bulbs made before these were
+//! added to the ZCL ignore the writes.
+
+use crate::attributes::WriteAttributes;
+use crate::cluster;
+use crate::codec::ZclValue;
+use crate::network::{NetworkError, ZigbeeNetwork};
+use deconz_protocol::{clusters, ApsDataRequest};
+use serde::{Deserialize, Serialize};
+
+/// On/Off cluster: `StartUpOnOff` attribute (enum8)
+const START_UP_ON_OFF: u16 = 0x4003;
+/// Level Control cluster: `StartUpCurrentLevel` attribute (uint8)
+const START_UP_CURRENT_LEVEL: u16 = 0x4000;
+
+/// Whether a light is on after power returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerOnBehavior {
+    Off,
+    On,
+    /// The opposite of the state before the power cut
+    Toggle,
+    /// The state before the power cut
+    Previous,
+}
+
+impl PowerOnBehavior {
+    fn value(self) -> u8 {
+        match self {
+            PowerOnBehavior::Off => 0x00,
+            PowerOnBehavior::On => 0x01,
+            PowerOnBehavior::Toggle => 0x02,
+            PowerOnBehavior::Previous => 0xFF,
+        }
+    }
+}
+
+/// Brightness after power returns: `"previous"` or a percentage (1-100)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "BrightnessValue", into = "BrightnessValue")]
+pub enum StartupBrightness {
+    Previous,
+    Percent(u8),
+}
+
+/// How [`StartupBrightness`] is written in JSON
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum BrightnessValue {
+    Percent(u8),
+    Named(String),
+}
+
+impl TryFrom<BrightnessValue> for StartupBrightness {
+    type Error = String;
+
+    fn try_from(value: BrightnessValue) -> Result<Self, Self::Error> {
+        match value {
+            BrightnessValue::Percent(percent @ 1..=100) => Ok(Self::Percent(percent)),
+            BrightnessValue::Named(name) if name == "previous" => Ok(Self::Previous),
+            _ => Err("brightness must be \"previous\" or 1-100".to_string()),
+        }
+    }
+}
+
+impl From<StartupBrightness> for BrightnessValue {
+    fn from(brightness: StartupBrightness) -> Self {
+        match brightness {
+            StartupBrightness::Previous => Self::Named("previous".to_string()),
+            StartupBrightness::Percent(percent) => Self::Percent(percent),
+        }
+    }
+}
+
+impl StartupBrightness {
+    /// `StartUpCurrentLevel` value (0xFF restores the previous level)
+    fn level(self) -> u8 {
+        match self {
+            StartupBrightness::Previous => 0xFF,
+            StartupBrightness::Percent(percent) => {
+                let level = (u16::from(percent.min(100)) * 254 + 50) / 100;
+                u8::try_from(level).unwrap_or(254).max(1)
+            }
+        }
+    }
+}
+
+/// What a light does when power returns; unset fields are left alone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartupBehavior {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_on: Option<PowerOnBehavior>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<StartupBrightness>,
+}
+
+impl ZigbeeNetwork {
+    /// Write a power-on behavior to every On/Off and Level Control endpoint
+    /// of a device
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn apply_startup_behavior(
+        &self,
+        ieee: &[u8; 8],
+        behavior: StartupBehavior,
+    ) -> Result<(), NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+
+        let mut writes = Vec::new();
+        for endpoint in &device.endpoints {
+            if let Some(power_on) = behavior.power_on {
+                if endpoint.in_clusters.contains(&clusters::ON_OFF) {
+                    writes.push((
+                        endpoint.id,
+                        clusters::ON_OFF,
+                        WriteAttributes::new()
+                            .attribute(START_UP_ON_OFF, ZclValue::Enum8(power_on.value()))
+                            .frame(cluster::next_tsn()),
+                    ));
+                }
+            }
+            if let Some(brightness) = behavior.brightness {
+                if endpoint.in_clusters.contains(&clusters::LEVEL_CONTROL) {
+                    writes.push((
+                        endpoint.id,
+                        clusters::LEVEL_CONTROL,
+                        WriteAttributes::new()
+                            .attribute(START_UP_CURRENT_LEVEL, ZclValue::Uint8(brightness.level()))
+                            .frame(cluster::next_tsn()),
+                    ));
+                }
+            }
+        }
+        if writes.is_empty() && behavior != StartupBehavior::default() {
+            return Err(NetworkError::Configuration(
+                "device has no on/off or level control endpoint".to_string(),
+            ));
+        }
+
+        tracing::info!(
+            "Setting power-on behavior of {:#06x}: {:?}",
+            device.nwk_address,
+            behavior
+        );
+        for (endpoint, cluster_id, zcl_frame) in writes {
+            let request = ApsDataRequest::new(
+                1,
+                device.nwk_address,
+                endpoint,
+                cluster_id,
+                zcl_frame.serialize(),
+            );
+            self.transport().send_aps_request(request).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_behavior_json() {
+        let behavior: StartupBehavior =
+            serde_json::from_str(r#"{"power_on": "previous", "brightness": 40}"#).unwrap();
+        assert_eq!(behavior.power_on, Some(PowerOnBehavior::Previous));
+        assert_eq!(behavior.brightness, Some(StartupBrightness::Percent(40)));
+        assert_eq!(StartupBrightness::Percent(40).level(), 102);
+        assert_eq!(StartupBrightness::Percent(1).level(), 3);
+        assert_eq!(StartupBrightness::Percent(100).level(), 254);
+
+        let behavior: StartupBehavior =
+            serde_json::from_str(r#"{"brightness": "previous"}"#).unwrap();
+        assert_eq!(behavior.brightness, Some(StartupBrightness::Previous));
+        assert_eq!(
+            serde_json::to_string(&behavior).unwrap(),
+            r#"{"brightness":"previous"}"#
+        );
+
+        assert!(serde_json::from_str::<StartupBehavior>(r#"{"brightness": 0}"#).is_err());
+        assert!(serde_json::from_str::<StartupBehavior>(r#"{"brightness": "max"}"#).is_err());
+    }
+}
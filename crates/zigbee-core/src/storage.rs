@@ -0,0 +1,429 @@
+//! Storage backends for devices and automations
+//!
+//! Records are kept either as one JSON file per kind (`devices.json`, the
+//! default) or, with the `sqlite` feature and `STORAGE_BACKEND=sqlite`, as
+//! rows of `casita.db`. Saving rewrites a whole JSON file on every change;
+//! SQLite only writes the rows that changed, which spares SD cards.
+//!
+//! Switching an install to SQLite imports the JSON files, which are then
+//! renamed to `<kind>.json.imported` (the API runs this as the
+//! `json_to_sqlite` migration). Until then opening SQLite storage for a kind
+//! with a JSON file fails, rather than starting without its records.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Something kept by a [`Storage`]
+pub trait Record: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Kind of record, naming its JSON file (`devices.json`)
+    const KIND: &'static str;
+
+    /// Unique key of the record
+    fn key(&self) -> String;
+}
+
+impl Record for crate::device::ZigbeeDevice {
+    const KIND: &'static str = "devices";
+
+    fn key(&self) -> String {
+        self.ieee_address_string()
+    }
+}
+
+/// Where records of one kind are kept
+///
+/// Calls block; run them with `spawn_blocking` from async code.
+pub trait Storage<T: Record>: Send + Sync {
+    /// Load all records
+    #[allow(clippy::missing_errors_doc)]
+    fn load(&self) -> io::Result<Vec<T>>;
+
+    /// Store `records` as the complete set, dropping records not in it
+    #[allow(clippy::missing_errors_doc)]
+    fn save(&self, records: &[T]) -> io::Result<()>;
+}
+
+/// Which backend [`open`] uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl Backend {
+    /// Backend from the `STORAGE_BACKEND` env var (`json` or `sqlite`),
+    /// JSON by default
+    #[must_use]
+    pub fn from_env() -> Self {
+        match std::env::var("STORAGE_BACKEND").as_deref() {
+            Ok("sqlite") => Self::Sqlite,
+            Ok("json") | Err(_) => Self::Json,
+            Ok(other) => {
+                tracing::warn!("Unknown storage backend '{}', using json", other);
+                Self::Json
+            }
+        }
+    }
+}
+
+/// Open the storage for records of kind `T` in `data_dir`
+#[allow(clippy::missing_errors_doc)]
+pub fn open<T: Record>(data_dir: &Path, backend: Backend) -> io::Result<Arc<dyn Storage<T>>> {
+    let json = JsonFile::new(data_dir.join(format!("{}.json", T::KIND)));
+    match backend {
+        Backend::Json => Ok(Arc::new(json)),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => {
+            if json.path.exists() {
+                return Err(io::Error::other(format!(
+                    "{:?} hasn't been imported into {}",
+                    json.path,
+                    sqlite::DB_FILE
+                )));
+            }
+            Ok(Arc::new(sqlite::SqliteStorage::open(
+                &data_dir.join(sqlite::DB_FILE),
+            )?))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => {
+            tracing::warn!("Built without SQLite support, storing {} as json", T::KIND);
+            Ok(Arc::new(json))
+        }
+    }
+}
+
+/// All records of a kind in one JSON file, rewritten atomically on save
+pub struct JsonFile {
+    path: PathBuf,
+}
+
+impl JsonFile {
+    #[must_use]
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<T: Record> Storage<T> for JsonFile {
+    fn load(&self) -> io::Result<Vec<T>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                tracing::debug!("No {} file at {:?}, starting fresh", T::KIND, self.path);
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e),
+        };
+        let records: Vec<T> = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        tracing::info!("Loaded {} {} from {:?}", records.len(), T::KIND, self.path);
+        Ok(records)
+    }
+
+    fn save(&self, records: &[T]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(records)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // Write atomically: write to temp file, then rename
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, &json)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        tracing::debug!("Saved {} {} to {:?}", records.len(), T::KIND, self.path);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    //! Records as JSON documents in SQLite rows
+
+    use super::{JsonFile, Record, Storage};
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::collections::HashSet;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    /// Database file in the data directory
+    pub const DB_FILE: &str = "casita.db";
+
+    /// Schema changes, applied in order; `PRAGMA user_version` counts the
+    /// ones already applied
+    const MIGRATIONS: &[&str] = &["CREATE TABLE records (
+            kind TEXT NOT NULL,
+            key TEXT NOT NULL,
+            data TEXT NOT NULL,
+            PRIMARY KEY (kind, key)
+        );"];
+
+    fn to_io(e: rusqlite::Error) -> io::Error {
+        io::Error::other(e)
+    }
+
+    /// Bring the schema up to date
+    fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+            tracing::info!("Storage schema migrated to version {}", i + 1);
+        }
+        Ok(())
+    }
+
+    /// Records of every kind in one database
+    pub struct SqliteStorage {
+        conn: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        /// Open (or create) the database and migrate its schema
+        #[allow(clippy::missing_errors_doc)]
+        pub fn open(path: &Path) -> io::Result<Self> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut conn = Connection::open(path).map_err(to_io)?;
+            conn.pragma_update(None, "journal_mode", "WAL")
+                .map_err(to_io)?;
+            conn.pragma_update(None, "synchronous", "NORMAL")
+                .map_err(to_io)?;
+            migrate(&mut conn).map_err(to_io)?;
+            Ok(Self {
+                conn: Mutex::new(conn),
+            })
+        }
+
+        /// Whether any records of kind `T` are stored
+        #[allow(clippy::missing_errors_doc)]
+        #[allow(clippy::missing_panics_doc)] // Panics only if the lock is poisoned
+        pub fn has_records<T: Record>(&self) -> io::Result<bool> {
+            let stored: Option<i64> = self
+                .conn
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT 1 FROM records WHERE kind = ?1 LIMIT 1",
+                    [T::KIND],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(to_io)?;
+            Ok(stored.is_some())
+        }
+
+        /// Import records from their JSON file, then set the file aside;
+        /// returns how many there were
+        ///
+        /// The file is only renamed once the import has committed. Kinds
+        /// that already have records aren't imported over.
+        #[allow(clippy::missing_errors_doc)]
+        pub fn import<T: Record>(&self, json: &JsonFile) -> io::Result<usize> {
+            if !json.path.exists() {
+                return Ok(0);
+            }
+            if self.has_records::<T>()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already has {}", DB_FILE, T::KIND),
+                ));
+            }
+            let records: Vec<T> = json.load()?;
+            self.save(&records)?;
+            std::fs::rename(&json.path, json.path.with_extension("json.imported"))?;
+            tracing::info!(
+                "Imported {} {} into SQLite from {:?}",
+                records.len(),
+                T::KIND,
+                json.path
+            );
+            Ok(records.len())
+        }
+
+        /// Undo [`Self::import`]: write the records back to their JSON file
+        /// and remove them here
+        #[allow(clippy::missing_errors_doc)]
+        pub fn export<T: Record>(&self, json: &JsonFile) -> io::Result<usize> {
+            let records: Vec<T> = self.load()?;
+            json.save(&records)?;
+            self.save(&[] as &[T])?;
+            let imported = json.path.with_extension("json.imported");
+            if imported.exists() {
+                std::fs::remove_file(imported)?;
+            }
+            tracing::info!(
+                "Exported {} {} from SQLite to {:?}",
+                records.len(),
+                T::KIND,
+                json.path
+            );
+            Ok(records.len())
+        }
+    }
+
+    impl<T: Record> Storage<T> for SqliteStorage {
+        fn load(&self) -> io::Result<Vec<T>> {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT key, data FROM records WHERE kind = ?1")
+                .map_err(to_io)?;
+            let rows = stmt
+                .query_map([T::KIND], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })
+                .map_err(to_io)?;
+            let mut records = Vec::new();
+            for row in rows {
+                let (key, data) = row.map_err(to_io)?;
+                match serde_json::from_str(&data) {
+                    Ok(record) => records.push(record),
+                    Err(e) => tracing::warn!("Skipping unreadable {} {}: {}", T::KIND, key, e),
+                }
+            }
+            tracing::info!("Loaded {} {} from SQLite", records.len(), T::KIND);
+            Ok(records)
+        }
+
+        fn save(&self, records: &[T]) -> io::Result<()> {
+            let rows = records
+                .iter()
+                .map(|record| {
+                    serde_json::to_string(record)
+                        .map(|data| (record.key(), data))
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction().map_err(to_io)?;
+            let mut written = 0;
+            {
+                // Unchanged rows are left alone, so nothing is written for them
+                let mut upsert = tx
+                    .prepare_cached(
+                        "INSERT INTO records (kind, key, data) VALUES (?1, ?2, ?3)
+                         ON CONFLICT (kind, key) DO UPDATE SET data = excluded.data
+                         WHERE data != excluded.data",
+                    )
+                    .map_err(to_io)?;
+                for (key, data) in &rows {
+                    written += upsert.execute(params![T::KIND, key, data]).map_err(to_io)?;
+                }
+
+                let keep: HashSet<&str> = rows.iter().map(|(key, _)| key.as_str()).collect();
+                let mut keys = tx
+                    .prepare_cached("SELECT key FROM records WHERE kind = ?1")
+                    .map_err(to_io)?;
+                let stale = keys
+                    .query_map([T::KIND], |row| row.get::<_, String>(0))
+                    .map_err(to_io)?
+                    .filter_map(Result::ok)
+                    .filter(|key| !keep.contains(key.as_str()))
+                    .collect::<Vec<_>>();
+                let mut delete = tx
+                    .prepare_cached("DELETE FROM records WHERE kind = ?1 AND key = ?2")
+                    .map_err(to_io)?;
+                for key in stale {
+                    written += delete.execute(params![T::KIND, key]).map_err(to_io)?;
+                }
+            }
+            tx.commit().map_err(to_io)?;
+
+            tracing::debug!(
+                "Saved {} {} ({} rows written)",
+                rows.len(),
+                T::KIND,
+                written
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::ZigbeeDevice;
+
+    fn roundtrip(storage: &dyn Storage<ZigbeeDevice>) {
+        let mut porch = ZigbeeDevice::new([1; 8], 0x1234);
+        porch.friendly_name = Some("Porch".to_string());
+        storage
+            .save(&[porch.clone(), ZigbeeDevice::new([2; 8], 0x5678)])
+            .unwrap();
+        assert_eq!(storage.load().unwrap().len(), 2);
+
+        // Removed records are dropped
+        storage.save(std::slice::from_ref(&porch)).unwrap();
+        assert_eq!(storage.load().unwrap(), vec![porch]);
+    }
+
+    #[test]
+    fn test_json_storage() {
+        let dir = std::env::temp_dir().join(format!("casita-storage-json-{}", std::process::id()));
+        let storage = open::<ZigbeeDevice>(&dir, Backend::Json).unwrap();
+        assert!(storage.load().unwrap().is_empty());
+        roundtrip(storage.as_ref());
+        assert!(dir.join("devices.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_storage_imports_json() {
+        let dir =
+            std::env::temp_dir().join(format!("casita-storage-sqlite-{}", std::process::id()));
+        let json = open::<ZigbeeDevice>(&dir, Backend::Json).unwrap();
+        json.save(&[ZigbeeDevice::new([3; 8], 0x0001)]).unwrap();
+
+        // Not imported yet
+        assert!(open::<ZigbeeDevice>(&dir, Backend::Sqlite).is_err());
+        let db = sqlite::SqliteStorage::open(&dir.join(sqlite::DB_FILE)).unwrap();
+        let json = JsonFile::new(dir.join("devices.json"));
+        assert_eq!(db.import::<ZigbeeDevice>(&json).unwrap(), 1);
+        assert!(!dir.join("devices.json").exists());
+        assert!(dir.join("devices.json.imported").exists());
+
+        let storage = open::<ZigbeeDevice>(&dir, Backend::Sqlite).unwrap();
+        assert_eq!(storage.load().unwrap()[0].ieee_address, [3; 8]);
+        roundtrip(storage.as_ref());
+
+        // And back
+        assert_eq!(db.export::<ZigbeeDevice>(&json).unwrap(), 1);
+        assert!(!db.has_records::<ZigbeeDevice>().unwrap());
+        assert!(!dir.join("devices.json.imported").exists());
+        assert_eq!(Storage::<ZigbeeDevice>::load(&json).unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_failed_import_keeps_json() {
+        let dir =
+            std::env::temp_dir().join(format!("casita-storage-bad-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("devices.json"), "[{").unwrap();
+
+        let db = sqlite::SqliteStorage::open(&dir.join(sqlite::DB_FILE)).unwrap();
+        let json = JsonFile::new(dir.join("devices.json"));
+        assert!(db.import::<ZigbeeDevice>(&json).is_err());
+        assert!(dir.join("devices.json").exists());
+        assert!(!dir.join("devices.json.imported").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
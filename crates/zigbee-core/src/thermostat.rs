@@ -0,0 +1,109 @@
+//! Thermostat (HVAC) control
+
+use crate::attributes::WriteAttributes;
+use crate::cluster::{self, thermostat_attrs};
+use crate::codec::ZclValue;
+use crate::network::{NetworkError, ZigbeeNetwork};
+use crate::poll_control::Dispatch;
+use crate::{quirks, tuya};
+use deconz_protocol::{clusters, ApsDataRequest};
+
+impl ZigbeeNetwork {
+    /// Write the occupied heating setpoint of a thermostat or TRV
+    ///
+    /// The setpoint is sent in hundredths of a degree, as the Thermostat
+    /// cluster expects. Tuya valves get their setpoint data point instead.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_heating_setpoint(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        celsius: f32,
+    ) -> Result<Dispatch, NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        if device
+            .manufacturer
+            .as_deref()
+            .and_then(tuya::model_for)
+            .is_some_and(|m| m.datapoints.iter().any(|d| d.name == "heating_setpoint"))
+        {
+            self.set_tuya_value(ieee, "heating_setpoint", &f64::from(celsius).into())
+                .await?;
+            return Ok(Dispatch::Sent);
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let centi_degrees = (celsius * 100.0).round() as i16;
+        let zcl_frame = WriteAttributes::new()
+            .attribute(
+                thermostat_attrs::OCCUPIED_HEATING_SETPOINT,
+                ZclValue::Int16(centi_degrees),
+            )
+            .frame(cluster::next_tsn());
+        let request = ApsDataRequest::new(
+            1,
+            device.nwk_address,
+            endpoint,
+            clusters::THERMOSTAT,
+            zcl_frame.serialize(),
+        );
+
+        tracing::info!(
+            "Setting heating setpoint of {:#06x}:{} to {:.1}°C",
+            device.nwk_address,
+            endpoint,
+            celsius
+        );
+
+        self.send_or_queue(&device, request).await
+    }
+
+    /// Tell a radiator valve the temperature measured elsewhere in the room
+    ///
+    /// Only valves with an external temperature quirk (see
+    /// [`quirks::Quirk::external_temperature`]) accept it; the value has to
+    /// be sent again regularly or the valve goes back to its own sensor.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_external_temperature(
+        &self,
+        ieee: &[u8; 8],
+        endpoint: u8,
+        celsius: f64,
+    ) -> Result<Dispatch, NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        let external = quirks::quirk_for(&device)
+            .and_then(|q| q.external_temperature)
+            .ok_or_else(|| {
+                NetworkError::Configuration(
+                    "device doesn't accept an external temperature".to_string(),
+                )
+            })?;
+
+        #[allow(clippy::cast_possible_truncation)]
+        let centi_degrees = (celsius * 100.0).round() as i16;
+        let zcl_frame = WriteAttributes::new()
+            .attribute(external.attribute_id, ZclValue::Int16(centi_degrees))
+            .manufacturer_code(external.manufacturer_code)
+            .frame(cluster::next_tsn());
+        let request = ApsDataRequest::new(
+            1,
+            device.nwk_address,
+            endpoint,
+            external.cluster_id,
+            zcl_frame.serialize(),
+        );
+
+        tracing::debug!(
+            "Sending room temperature {:.1}°C to {:#06x}:{}",
+            celsius,
+            device.nwk_address,
+            endpoint
+        );
+
+        self.send_or_queue(&device, request).await
+    }
+}
@@ -0,0 +1,308 @@
+//! Network topology crawler
+//!
+//! Walks the coordinator and every reachable router with ZDO Mgmt_Lqi
+//! requests and builds a neighbor graph with link quality per edge. Routing
+//! tables of individual routers are read with Mgmt_Rtg.
+
+use crate::network::{NetworkError, ZigbeeNetwork};
+use crate::zdo::next_tsn;
+use deconz_protocol::{
+    ApsDataIndication, ApsDataRequest, MgmtLqiResponse, MgmtRtgResponse, NeighborEntry,
+    NeighborRelationship, RouteStatus, RoutingTableEntry, ZdoCluster,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Upper bound on routers crawled in one pass
+const MAX_ROUTERS: usize = 128;
+/// Upper bound on neighbor table pages requested from a single router
+const MAX_PAGES: usize = 16;
+
+/// A node in the topology graph
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyNode {
+    pub ieee_address: String,
+    pub nwk_address: u16,
+    /// `coordinator`, `router`, `end_device` or `unknown`
+    pub device_type: &'static str,
+    pub name: Option<String>,
+    /// Whether this router answered its Mgmt_Lqi request (`None` if not queried)
+    pub reachable: Option<bool>,
+}
+
+/// A neighbor link reported by `source`
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyEdge {
+    pub source: String,
+    pub target: String,
+    pub lqi: u8,
+    pub relationship: &'static str,
+    pub depth: u8,
+}
+
+/// A routing table entry with addresses resolved to known devices
+#[derive(Debug, Clone, Serialize)]
+pub struct Route {
+    pub destination: u16,
+    pub destination_ieee: Option<String>,
+    pub destination_name: Option<String>,
+    pub next_hop: u16,
+    pub next_hop_ieee: Option<String>,
+    pub next_hop_name: Option<String>,
+    /// `active`, `discovery_underway`, `discovery_failed`, `inactive`,
+    /// `validation_underway` or `unknown`
+    pub status: &'static str,
+    pub memory_constrained: bool,
+    pub many_to_one: bool,
+    pub route_record_required: bool,
+}
+
+/// Network topology graph
+#[derive(Debug, Clone, Serialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<TopologyEdge>,
+}
+
+impl ZigbeeNetwork {
+    /// Fetch the complete neighbor table of a single node
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn neighbor_table(
+        &self,
+        nwk_address: u16,
+    ) -> Result<Vec<NeighborEntry>, NetworkError> {
+        let mut neighbors = Vec::new();
+        let mut start_index = 0u8;
+
+        for _ in 0..MAX_PAGES {
+            let tsn = next_tsn();
+            let request = ApsDataRequest::mgmt_lqi_request(tsn, nwk_address, start_index, tsn);
+            let asdu = self
+                .zdo_request(request, ZdoCluster::MgmtLqiRsp as u16, tsn)
+                .await?;
+            let response = MgmtLqiResponse::parse(&asdu)?;
+            if response.status != 0 {
+                return Err(NetworkError::Configuration(format!(
+                    "Mgmt_Lqi request to {nwk_address:#06x} failed with status {:#04x}",
+                    response.status
+                )));
+            }
+
+            let received = u8::try_from(response.neighbors.len()).unwrap_or(u8::MAX);
+            neighbors.extend(response.neighbors);
+            start_index = start_index.saturating_add(received);
+            if received == 0 || start_index >= response.total_entries {
+                break;
+            }
+        }
+
+        Ok(neighbors)
+    }
+
+    /// Fetch the complete routing table of a single router
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn routing_table(
+        &self,
+        nwk_address: u16,
+    ) -> Result<Vec<RoutingTableEntry>, NetworkError> {
+        let mut routes = Vec::new();
+        let mut start_index = 0u8;
+
+        for _ in 0..MAX_PAGES {
+            let tsn = next_tsn();
+            let request = ApsDataRequest::mgmt_rtg_request(tsn, nwk_address, start_index, tsn);
+            let asdu = self
+                .zdo_request(request, ZdoCluster::MgmtRtgRsp as u16, tsn)
+                .await?;
+            let response = MgmtRtgResponse::parse(&asdu)?;
+            if response.status != 0 {
+                return Err(NetworkError::Configuration(format!(
+                    "Mgmt_Rtg request to {nwk_address:#06x} failed with status {:#04x}",
+                    response.status
+                )));
+            }
+
+            let received = u8::try_from(response.routes.len()).unwrap_or(u8::MAX);
+            routes.extend(response.routes);
+            start_index = start_index.saturating_add(received);
+            if received == 0 || start_index >= response.total_entries {
+                break;
+            }
+        }
+
+        Ok(routes)
+    }
+
+    /// Fetch a device's routing table and resolve addresses to known devices
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn device_routes(&self, ieee: &[u8; 8]) -> Result<Vec<Route>, NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        let table = self.routing_table(device.nwk_address).await?;
+
+        let devices: HashMap<u16, crate::ZigbeeDevice> = self
+            .get_devices()
+            .into_iter()
+            .map(|d| (d.nwk_address, d))
+            .collect();
+        let resolve = |nwk: u16| {
+            devices
+                .get(&nwk)
+                .map(|d| (d.ieee_address_string(), d.display_name()))
+                .unzip()
+        };
+
+        Ok(table
+            .into_iter()
+            .map(|entry| {
+                let (destination_ieee, destination_name) = resolve(entry.destination);
+                let (next_hop_ieee, next_hop_name) = resolve(entry.next_hop);
+                Route {
+                    destination: entry.destination,
+                    destination_ieee,
+                    destination_name,
+                    next_hop: entry.next_hop,
+                    next_hop_ieee,
+                    next_hop_name,
+                    status: route_status_name(entry.status),
+                    memory_constrained: entry.memory_constrained,
+                    many_to_one: entry.many_to_one,
+                    route_record_required: entry.route_record_required,
+                }
+            })
+            .collect())
+    }
+
+    /// Crawl the network starting at the coordinator
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn crawl_topology(&self) -> Result<Topology, NetworkError> {
+        let coordinator_ieee = self.transport().read_mac_address().await?;
+
+        let mut nodes: HashMap<[u8; 8], TopologyNode> = HashMap::new();
+        let mut edges = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([(coordinator_ieee, 0x0000u16)]);
+
+        nodes.insert(
+            coordinator_ieee,
+            TopologyNode {
+                ieee_address: ApsDataIndication::format_ieee(&coordinator_ieee),
+                nwk_address: 0x0000,
+                device_type: "coordinator",
+                name: Some("Coordinator".to_string()),
+                reachable: None,
+            },
+        );
+
+        while let Some((ieee, nwk_address)) = queue.pop_front() {
+            if !visited.insert(ieee) || visited.len() > MAX_ROUTERS {
+                continue;
+            }
+
+            let result = self.neighbor_table(nwk_address).await;
+            if let Some(node) = nodes.get_mut(&ieee) {
+                node.reachable = Some(result.is_ok());
+            }
+            let neighbors = match result {
+                Ok(neighbors) => neighbors,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to read neighbor table of {:#06x}: {}",
+                        nwk_address,
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            let source = ApsDataIndication::format_ieee(&ieee);
+            for neighbor in neighbors {
+                let node = nodes
+                    .entry(neighbor.ieee_addr)
+                    .or_insert_with(|| self.topology_node(&neighbor));
+                // Prefer the freshest short address reported by a neighbor
+                node.nwk_address = neighbor.nwk_addr;
+
+                edges.push(TopologyEdge {
+                    source: source.clone(),
+                    target: node.ieee_address.clone(),
+                    lqi: neighbor.lqi,
+                    relationship: relationship_name(neighbor.relationship),
+                    depth: neighbor.depth,
+                });
+
+                if neighbor.device_type == 1 && !visited.contains(&neighbor.ieee_addr) {
+                    queue.push_back((neighbor.ieee_addr, neighbor.nwk_addr));
+                }
+            }
+        }
+
+        // Include known devices that no router reported
+        for device in self.get_devices() {
+            nodes
+                .entry(device.ieee_address)
+                .or_insert_with(|| TopologyNode {
+                    ieee_address: device.ieee_address_string(),
+                    nwk_address: device.nwk_address,
+                    device_type: match device.device_type {
+                        crate::DeviceType::Coordinator => "coordinator",
+                        crate::DeviceType::Router => "router",
+                        crate::DeviceType::EndDevice => "end_device",
+                    },
+                    name: Some(device.display_name()),
+                    reachable: None,
+                });
+        }
+
+        tracing::info!(
+            "Topology crawl finished: {} nodes, {} links",
+            nodes.len(),
+            edges.len()
+        );
+
+        Ok(Topology {
+            nodes: nodes.into_values().collect(),
+            edges,
+        })
+    }
+
+    fn topology_node(&self, neighbor: &NeighborEntry) -> TopologyNode {
+        TopologyNode {
+            ieee_address: ApsDataIndication::format_ieee(&neighbor.ieee_addr),
+            nwk_address: neighbor.nwk_addr,
+            device_type: match neighbor.device_type {
+                0 => "coordinator",
+                1 => "router",
+                2 => "end_device",
+                _ => "unknown",
+            },
+            name: self
+                .get_device(&neighbor.ieee_addr)
+                .map(|device| device.display_name()),
+            reachable: None,
+        }
+    }
+}
+
+fn relationship_name(relationship: NeighborRelationship) -> &'static str {
+    match relationship {
+        NeighborRelationship::Parent => "parent",
+        NeighborRelationship::Child => "child",
+        NeighborRelationship::Sibling => "sibling",
+        NeighborRelationship::None => "none",
+        NeighborRelationship::PreviousChild => "previous_child",
+        NeighborRelationship::Unknown(_) => "unknown",
+    }
+}
+
+fn route_status_name(status: RouteStatus) -> &'static str {
+    match status {
+        RouteStatus::Active => "active",
+        RouteStatus::DiscoveryUnderway => "discovery_underway",
+        RouteStatus::DiscoveryFailed => "discovery_failed",
+        RouteStatus::Inactive => "inactive",
+        RouteStatus::ValidationUnderway => "validation_underway",
+        RouteStatus::Unknown(_) => "unknown",
+    }
+}
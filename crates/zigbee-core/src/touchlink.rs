@@ -0,0 +1,348 @@
+//! Touchlink (ZLL commissioning)
+//!
+//! Touchlink talks to lights with inter-PAN frames on the ZLL primary
+//! channels, whatever network they are in. A scan finds lights within a
+//! meter or so of the adapter; within the same transaction a found light can
+//! be told to blink or to reset to factory new, which frees a Hue bulb
+//! paired to another bridge without power-cycling it. After the reset it
+//! searches for a network to join, so permit joining to take it over.
+//!
+//! ```text
+//! coordinator                       light
+//!   | -- Scan Request (broadcast) --> |   on channels 11, 15, 20, 25
+//!   | <-- Scan Response ------------- |
+//!   | -- Identify Request ----------> |   same transaction ID
+//!   | -- Reset to Factory New ------> |
+//! ```
+//!
+//! Inter-PAN frames need transport support ([`Transport::send_inter_pan`]);
+//! without it every call fails with [`ProtocolError::Unsupported`].
+//!
+//! [`Transport::send_inter_pan`]: deconz_protocol::Transport::send_inter_pan
+//! [`ProtocolError::Unsupported`]: deconz_protocol::ProtocolError::Unsupported
+
+use crate::network::{NetworkError, ZigbeeNetwork};
+use deconz_protocol::{
+    ApsDataIndication, DeconzEvent, InterPanIndication, InterPanRequest, ZclFrame,
+};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// ZLL profile used by the commissioning cluster
+pub const PROFILE_ID: u16 = 0xC05E;
+
+/// ZLL commissioning cluster
+pub const CLUSTER_ID: u16 = 0x1000;
+
+/// Channels touchlink scans, in order
+pub const PRIMARY_CHANNELS: [u8; 4] = [11, 15, 20, 25];
+
+/// ZLL commissioning cluster commands
+mod command {
+    /// Client to server
+    pub const SCAN_REQUEST: u8 = 0x00;
+    pub const IDENTIFY_REQUEST: u8 = 0x06;
+    pub const RESET_TO_FACTORY_NEW_REQUEST: u8 = 0x07;
+    /// Server to client
+    pub const SCAN_RESPONSE: u8 = 0x01;
+}
+
+/// How long to listen for scan responses on each channel
+const SCAN_WINDOW: Duration = Duration::from_millis(250);
+
+/// Zigbee information of the scan request: router, receiver on when idle
+const ZIGBEE_INFO: u8 = 0x05;
+/// ZLL information of the scan request: link initiator, address assignment
+const ZLL_INFO: u8 = 0x12;
+
+/// Identify duration asking the light to use its default time
+pub const IDENTIFY_DEFAULT: u16 = 0xFFFF;
+
+/// A light that answered a touchlink scan
+#[derive(Debug, Clone, Serialize)]
+pub struct TouchlinkDevice {
+    pub ieee_address: String,
+    pub channel: u8,
+    /// Signal strength in dBm; the closest light is the strongest
+    pub rssi: Option<i8>,
+    pub factory_new: bool,
+    /// PAN the light currently belongs to
+    pub pan_id: u16,
+    pub network_address: u16,
+    /// Endpoint and ZLL device ID, when the light has a single endpoint
+    pub endpoint: Option<u8>,
+    pub device_id: Option<u16>,
+    #[serde(skip)]
+    pub ieee: [u8; 8],
+    #[serde(skip)]
+    transaction_id: u32,
+}
+
+/// Fields of a Scan Response used here
+#[derive(Debug, Clone, PartialEq)]
+struct ScanResponse {
+    transaction_id: u32,
+    factory_new: bool,
+    pan_id: u16,
+    network_address: u16,
+    endpoint: Option<u8>,
+    device_id: Option<u16>,
+}
+
+impl ScanResponse {
+    /// Parse the payload of a Scan Response
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| Some(u16::from_le_bytes([*payload.get(i)?, *payload.get(i + 1)?]));
+        let transaction_id = u32::from_le_bytes(payload.get(..4)?.try_into().ok()?);
+        // rssi correction (4), zigbee info (5), zll info (6), key bitmask
+        // (7..9), response ID (9..13), extended PAN ID (13..21), update ID
+        // (21), channel (22)
+        let zll_info = *payload.get(6)?;
+        let pan_id = u16_at(23)?;
+        let network_address = u16_at(25)?;
+        let sub_devices = *payload.get(27)?;
+        // Endpoint information is only present for a single sub-device:
+        // endpoint (29), profile (30..32), device ID (32..34)
+        let (endpoint, device_id) = if sub_devices == 1 {
+            (payload.get(29).copied(), u16_at(32))
+        } else {
+            (None, None)
+        };
+        Some(Self {
+            transaction_id,
+            factory_new: zll_info & 0x01 != 0,
+            pan_id,
+            network_address,
+            endpoint,
+            device_id,
+        })
+    }
+}
+
+/// A random, non-zero inter-PAN transaction ID
+fn new_transaction_id() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    nanos.wrapping_mul(0x9E37_79B9) | 1
+}
+
+fn request(
+    channel: u8,
+    dest_ieee: Option<[u8; 8]>,
+    command_id: u8,
+    payload: Vec<u8>,
+) -> InterPanRequest {
+    InterPanRequest {
+        channel,
+        dest_ieee,
+        profile_id: PROFILE_ID,
+        cluster_id: CLUSTER_ID,
+        asdu: ZclFrame::cluster_command(crate::cluster::next_tsn(), command_id)
+            .with_payload(payload)
+            .serialize(),
+    }
+}
+
+fn scan_request(channel: u8, transaction_id: u32) -> InterPanRequest {
+    let mut payload = transaction_id.to_le_bytes().to_vec();
+    payload.extend_from_slice(&[ZIGBEE_INFO, ZLL_INFO]);
+    request(channel, None, command::SCAN_REQUEST, payload)
+}
+
+/// Scan response of the current transaction, if the frame is one
+fn scan_response(indication: &InterPanIndication, transaction_id: u32) -> Option<TouchlinkDevice> {
+    if indication.profile_id != PROFILE_ID || indication.cluster_id != CLUSTER_ID {
+        return None;
+    }
+    let zcl = ZclFrame::parse(&indication.asdu).ok()?;
+    if !zcl.is_cluster_specific()
+        || !zcl.is_from_server()
+        || zcl.command_id() != command::SCAN_RESPONSE
+    {
+        return None;
+    }
+    let response = ScanResponse::parse(zcl.payload())?;
+    if response.transaction_id != transaction_id {
+        return None;
+    }
+    Some(TouchlinkDevice {
+        ieee_address: ApsDataIndication::format_ieee(&indication.src_ieee),
+        channel: indication.channel,
+        rssi: indication.rssi,
+        factory_new: response.factory_new,
+        pan_id: response.pan_id,
+        network_address: response.network_address,
+        endpoint: response.endpoint,
+        device_id: response.device_id,
+        ieee: indication.src_ieee,
+        transaction_id,
+    })
+}
+
+impl ZigbeeNetwork {
+    /// Find touchlink lights near the adapter, strongest signal first
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn touchlink_scan(&self) -> Result<Vec<TouchlinkDevice>, NetworkError> {
+        let transaction_id = new_transaction_id();
+        let mut rx = self.transport().subscribe();
+        let mut found: Vec<TouchlinkDevice> = Vec::new();
+
+        tracing::info!("Touchlink scan (transaction {:#010x})", transaction_id);
+        for channel in PRIMARY_CHANNELS {
+            self.transport()
+                .send_inter_pan(scan_request(channel, transaction_id))
+                .await?;
+
+            let deadline = tokio::time::Instant::now() + SCAN_WINDOW;
+            loop {
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Ok(DeconzEvent::InterPan(indication))) => {
+                        if let Some(device) = scan_response(&indication, transaction_id) {
+                            if !found.iter().any(|d| d.ieee == device.ieee) {
+                                tracing::info!(
+                                    "Touchlink: {} on channel {} (rssi {:?})",
+                                    device.ieee_address,
+                                    device.channel,
+                                    device.rssi
+                                );
+                                found.push(device);
+                            }
+                        }
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                        tracing::warn!("Touchlink scan lagged by {} events", n);
+                    }
+                    Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => break,
+                }
+            }
+        }
+
+        found.sort_by_key(|d| std::cmp::Reverse(d.rssi.unwrap_or(i8::MIN)));
+        Ok(found)
+    }
+
+    /// Make a light found by [`ZigbeeNetwork::touchlink_scan`] blink for
+    /// `seconds` ([`IDENTIFY_DEFAULT`] for its default time, 0 to stop)
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn touchlink_identify(
+        &self,
+        device: &TouchlinkDevice,
+        seconds: u16,
+    ) -> Result<(), NetworkError> {
+        let mut payload = device.transaction_id.to_le_bytes().to_vec();
+        payload.extend_from_slice(&seconds.to_le_bytes());
+        self.transport()
+            .send_inter_pan(request(
+                device.channel,
+                Some(device.ieee),
+                command::IDENTIFY_REQUEST,
+                payload,
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Scan, then reset a light to factory new so it can join this network
+    ///
+    /// Resets the light with `ieee`, or the closest one if `None`. Returns
+    /// the light that was reset.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn touchlink_factory_reset(
+        &self,
+        ieee: Option<[u8; 8]>,
+    ) -> Result<TouchlinkDevice, NetworkError> {
+        // Lights only accept the reset within the transaction of a scan
+        let found = self.touchlink_scan().await?;
+        let device = match ieee {
+            Some(ieee) => found.into_iter().find(|d| d.ieee == ieee),
+            None => found.into_iter().next(),
+        }
+        .ok_or_else(|| {
+            NetworkError::DeviceNotFound(ieee.map_or_else(
+                || "no touchlink light in range".to_string(),
+                |ieee| ApsDataIndication::format_ieee(&ieee),
+            ))
+        })?;
+
+        tracing::info!(
+            "Touchlink: resetting {} to factory new",
+            device.ieee_address
+        );
+        self.transport()
+            .send_inter_pan(request(
+                device.channel,
+                Some(device.ieee),
+                command::RESET_TO_FACTORY_NEW_REQUEST,
+                device.transaction_id.to_le_bytes().to_vec(),
+            ))
+            .await?;
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::MockTransport;
+    use std::sync::Arc;
+
+    fn scan_response_frame(transaction_id: u32) -> Vec<u8> {
+        let mut payload = transaction_id.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0x00, 0x05, 0x01]); // rssi correction, zigbee/zll info
+        payload.extend_from_slice(&[0; 6]); // key bitmask, response ID
+        payload.extend_from_slice(&[0xAA; 8]); // extended PAN ID
+        payload.extend_from_slice(&[0, 15]); // update ID, channel
+        payload.extend_from_slice(&0x1A62_u16.to_le_bytes());
+        payload.extend_from_slice(&0x0001_u16.to_le_bytes());
+        payload.extend_from_slice(&[1, 0]); // sub-devices, group IDs
+        payload.extend_from_slice(&[11, 0x5E, 0xC0, 0x00, 0x01, 2, 0]);
+        ZclFrame::cluster_command(1, command::SCAN_RESPONSE)
+            .from_server()
+            .with_payload(payload)
+            .serialize()
+    }
+
+    #[test]
+    fn test_parse_scan_response() {
+        let indication = InterPanIndication {
+            channel: 15,
+            src_ieee: [1, 2, 3, 4, 5, 6, 7, 8],
+            src_pan_id: 0x1A62,
+            profile_id: PROFILE_ID,
+            cluster_id: CLUSTER_ID,
+            asdu: scan_response_frame(0x1234_5678),
+            rssi: Some(-40),
+        };
+        assert!(scan_response(&indication, 0x1111_1111).is_none());
+
+        let device = scan_response(&indication, 0x1234_5678).unwrap();
+        assert_eq!(device.ieee_address, "08:07:06:05:04:03:02:01");
+        assert!(device.factory_new);
+        assert_eq!(device.pan_id, 0x1A62);
+        assert_eq!(device.network_address, 0x0001);
+        assert_eq!(device.endpoint, Some(11));
+        assert_eq!(device.device_id, Some(0x0100));
+    }
+
+    #[tokio::test]
+    async fn test_factory_reset_requires_light_in_range() {
+        let transport = Arc::new(MockTransport::new());
+        let network = ZigbeeNetwork::with_transport(transport.clone(), None)
+            .await
+            .unwrap();
+
+        let result = network.touchlink_factory_reset(None).await;
+        assert!(matches!(result, Err(NetworkError::DeviceNotFound(_))));
+
+        // A scan request on every primary channel and no reset
+        let sent = transport.inter_pan_requests();
+        assert_eq!(
+            sent.iter().map(|r| r.channel).collect::<Vec<_>>(),
+            PRIMARY_CHANNELS
+        );
+        assert!(sent.iter().all(|r| r.dest_ieee.is_none()));
+    }
+}
@@ -0,0 +1,602 @@
+//! Tuya private cluster (0xEF00)
+//!
+//! Many cheap Tuya devices (model `TS0601`: TRVs, soil sensors, presence
+//! radars) don't use standard ZCL clusters. Everything goes through
+//! cluster-specific commands on 0xEF00 carrying "data points" (DPs): a
+//! number, a type and a big-endian value. What each DP means depends on the
+//! device, so [`MODELS`] maps them per manufacturer name.
+//!
+//! Decoded values are kept in [`ZigbeeDevice::tuya_values`] by name;
+//! measurements also go to `sensor_values`, switches to `state_on` and
+//! presence to `occupancy`, with the usual events. DPs of unknown devices are
+//! kept as `dp_<number>` so they can be mapped later.
+
+use crate::cluster;
+use crate::device::ZigbeeDevice;
+use crate::network::{self, DeviceStorage, NetworkError, NetworkEvent, ZigbeeNetwork};
+use crate::sensor::SensorKind;
+use dashmap::DashMap;
+use deconz_protocol::{ApsDataIndication, ApsDataRequest, ZclFrame};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU16, Ordering};
+use tokio::sync::broadcast;
+
+/// Tuya private cluster ID
+pub const CLUSTER_ID: u16 = 0xEF00;
+
+/// Commands (cluster-specific)
+mod command {
+    /// Set data points (hub to device)
+    pub const DATA_REQUEST: u8 = 0x00;
+    /// Answer to a data request
+    pub const DATA_RESPONSE: u8 = 0x01;
+    /// Unsolicited report
+    pub const DATA_REPORT: u8 = 0x02;
+}
+
+/// Sequence number of data requests
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// Data point value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DpValue {
+    Raw(Vec<u8>),
+    Bool(bool),
+    /// 32-bit signed value
+    Value(i32),
+    String(String),
+    Enum(u8),
+    Bitmap(u32),
+}
+
+impl DpValue {
+    fn type_id(&self) -> u8 {
+        match self {
+            DpValue::Raw(_) => 0x00,
+            DpValue::Bool(_) => 0x01,
+            DpValue::Value(_) => 0x02,
+            DpValue::String(_) => 0x03,
+            DpValue::Enum(_) => 0x04,
+            DpValue::Bitmap(_) => 0x05,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            DpValue::Raw(bytes) => bytes.clone(),
+            DpValue::Bool(b) => vec![u8::from(*b)],
+            DpValue::Value(v) => v.to_be_bytes().to_vec(),
+            DpValue::String(s) => s.as_bytes().to_vec(),
+            DpValue::Enum(e) => vec![*e],
+            DpValue::Bitmap(b) => b.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn decode(type_id: u8, data: &[u8]) -> Option<Self> {
+        let be_u32 = || {
+            if data.is_empty() || data.len() > 4 {
+                return None;
+            }
+            let mut bytes = [0u8; 4];
+            bytes[4 - data.len()..].copy_from_slice(data);
+            Some(u32::from_be_bytes(bytes))
+        };
+        Some(match type_id {
+            0x00 => DpValue::Raw(data.to_vec()),
+            0x01 => DpValue::Bool(*data.first()? != 0),
+            #[allow(clippy::cast_possible_wrap)]
+            0x02 => DpValue::Value(be_u32()? as i32),
+            0x03 => DpValue::String(String::from_utf8_lossy(data).into_owned()),
+            0x04 => DpValue::Enum(*data.first()?),
+            0x05 => DpValue::Bitmap(be_u32()?),
+            _ => return None,
+        })
+    }
+
+    /// Value as a number (booleans and enums included)
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            DpValue::Bool(b) => Some(f64::from(u8::from(*b))),
+            DpValue::Value(v) => Some(f64::from(*v)),
+            DpValue::Enum(e) => Some(f64::from(*e)),
+            DpValue::Bitmap(b) => Some(f64::from(*b)),
+            DpValue::Raw(_) | DpValue::String(_) => None,
+        }
+    }
+}
+
+/// One data point of a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPoint {
+    pub id: u8,
+    pub value: DpValue,
+}
+
+/// Parse the data points of a data response or report
+///
+/// The payload starts with a two-byte sequence number; parsing stops at the
+/// first malformed record.
+#[must_use]
+pub fn parse_data_points(payload: &[u8]) -> Vec<DataPoint> {
+    let mut points = Vec::new();
+    let mut rest = payload.get(2..).unwrap_or_default();
+    while rest.len() >= 4 {
+        let (id, type_id) = (rest[0], rest[1]);
+        let len = usize::from(u16::from_be_bytes([rest[2], rest[3]]));
+        let Some(data) = rest.get(4..4 + len) else {
+            break;
+        };
+        let Some(value) = DpValue::decode(type_id, data) else {
+            break;
+        };
+        points.push(DataPoint { id, value });
+        rest = &rest[4 + len..];
+    }
+    points
+}
+
+/// Build a data request payload setting `points`
+#[must_use]
+pub fn encode_data_request(sequence: u16, points: &[DataPoint]) -> Vec<u8> {
+    let mut payload = sequence.to_be_bytes().to_vec();
+    for point in points {
+        let data = point.value.encode();
+        payload.push(point.id);
+        payload.push(point.value.type_id());
+        payload.extend_from_slice(&u16::try_from(data.len()).unwrap_or(0).to_be_bytes());
+        payload.extend_from_slice(&data);
+    }
+    payload
+}
+
+/// What a data point means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DpKind {
+    /// Read-only measurement, also kept in `sensor_values`
+    Measurement(SensorKind),
+    /// Read-only number (battery, distance...)
+    Reading,
+    /// On/off state of a switch or valve (writable)
+    Switch,
+    /// Presence or motion
+    Occupancy,
+    /// Writable number (setpoints, sensitivity)
+    Number,
+    /// Writable flag (child lock, window detection)
+    Flag,
+    /// Writable enum (modes)
+    Enum,
+}
+
+impl DpKind {
+    pub(crate) fn writable(self) -> bool {
+        matches!(
+            self,
+            DpKind::Switch | DpKind::Number | DpKind::Flag | DpKind::Enum
+        )
+    }
+}
+
+/// Mapping of one data point
+#[derive(Debug, Clone, Copy)]
+pub struct DpMapping {
+    pub dp: u8,
+    pub name: &'static str,
+    pub kind: DpKind,
+    /// Raw values are divided by this (numbers only)
+    pub divisor: f64,
+}
+
+const fn dp(dp: u8, name: &'static str, kind: DpKind, divisor: f64) -> DpMapping {
+    DpMapping {
+        dp,
+        name,
+        kind,
+        divisor,
+    }
+}
+
+/// Data point layout shared by a group of devices
+#[derive(Debug)]
+pub struct TuyaModel {
+    pub description: &'static str,
+    /// Manufacturer names (Basic cluster), e.g. `_TZE200_hue3yfsn`
+    pub manufacturers: &'static [&'static str],
+    pub datapoints: &'static [DpMapping],
+}
+
+/// Known devices
+pub const MODELS: &[TuyaModel] = &[
+    TuyaModel {
+        description: "Radiator valve (TV02)",
+        manufacturers: &[
+            "_TZE200_hue3yfsn",
+            "_TZE200_e9ba97vf",
+            "_TZE200_husqqvux",
+            "_TZE200_kly8gjlz",
+            "_TZE200_lnbfnyxd",
+            "_TZE200_mudxchsu",
+        ],
+        datapoints: &[
+            dp(2, "preset", DpKind::Enum, 1.0),
+            dp(16, "heating_setpoint", DpKind::Number, 10.0),
+            dp(
+                24,
+                "local_temperature",
+                DpKind::Measurement(SensorKind::Temperature),
+                10.0,
+            ),
+            dp(40, "child_lock", DpKind::Flag, 1.0),
+        ],
+    },
+    TuyaModel {
+        description: "Soil moisture sensor",
+        manufacturers: &["_TZE200_myd45weu", "_TZE200_ga1maeof"],
+        datapoints: &[
+            dp(
+                3,
+                "soil_moisture",
+                DpKind::Measurement(SensorKind::SoilMoisture),
+                1.0,
+            ),
+            dp(
+                5,
+                "temperature",
+                DpKind::Measurement(SensorKind::Temperature),
+                10.0,
+            ),
+            dp(15, "battery", DpKind::Reading, 1.0),
+        ],
+    },
+    TuyaModel {
+        description: "mmWave presence sensor",
+        manufacturers: &["_TZE200_ztc6ggyl", "_TZE200_ikvncluo", "_TZE204_ztc6ggyl"],
+        datapoints: &[
+            dp(1, "presence", DpKind::Occupancy, 1.0),
+            dp(2, "sensitivity", DpKind::Number, 1.0),
+            dp(9, "target_distance", DpKind::Reading, 100.0),
+        ],
+    },
+];
+
+/// Data point layout for a manufacturer name
+#[must_use]
+pub fn model_for(manufacturer: &str) -> Option<&'static TuyaModel> {
+    MODELS
+        .iter()
+        .find(|model| model.manufacturers.contains(&manufacturer))
+}
+
+/// Scaled JSON value of a data point
+fn json_value(mapping: Option<&DpMapping>, value: &DpValue) -> Value {
+    match (mapping.map(|m| m.kind), value) {
+        (Some(DpKind::Switch | DpKind::Occupancy | DpKind::Flag), v) => {
+            Value::Bool(v.as_f64().is_some_and(|n| n != 0.0))
+        }
+        (_, DpValue::String(s)) => Value::String(s.clone()),
+        (_, DpValue::Raw(bytes)) => {
+            Value::String(bytes.iter().map(|b| format!("{b:02x}")).collect::<String>())
+        }
+        (_, v) => {
+            let divisor = mapping.map_or(1.0, |m| m.divisor);
+            v.as_f64().map_or(Value::Null, |n| (n / divisor).into())
+        }
+    }
+}
+
+/// Handle a frame on the Tuya cluster
+pub(crate) fn handle_frame(
+    devices: &DashMap<[u8; 8], ZigbeeDevice>,
+    event_tx: &broadcast::Sender<NetworkEvent>,
+    storage: Option<&DeviceStorage>,
+    indication: &ApsDataIndication,
+    zcl: &ZclFrame,
+) {
+    if !zcl.is_cluster_specific()
+        || !matches!(
+            zcl.command_id(),
+            command::DATA_RESPONSE | command::DATA_REPORT
+        )
+    {
+        return;
+    }
+    let Some(ieee_address) = network::ieee_for_nwk(devices, indication.src_short_addr) else {
+        tracing::debug!(
+            "Tuya frame from unknown device {:#06x}",
+            indication.src_short_addr
+        );
+        return;
+    };
+
+    let events = network::update_in_place(devices, event_tx, storage, &ieee_address, |device| {
+        device.last_seen = Some(chrono::Utc::now());
+        let endpoint = indication.src_endpoint;
+        let model = device.manufacturer.as_deref().and_then(model_for);
+
+        let mut events = Vec::new();
+        for point in parse_data_points(zcl.payload()) {
+            let mapping = model.and_then(|m| m.datapoints.iter().find(|d| d.dp == point.id));
+            let name = mapping.map_or_else(|| format!("dp_{}", point.id), |m| m.name.to_string());
+            let value = json_value(mapping, &point.value);
+            tracing::debug!(
+                "Device {:#06x} Tuya DP {} ({}): {}",
+                indication.src_short_addr,
+                point.id,
+                name,
+                value
+            );
+
+            match (mapping.map(|m| m.kind), &value) {
+                (Some(DpKind::Measurement(kind)), Value::Number(n)) => {
+                    let value = n.as_f64().unwrap_or_default();
+                    if device.sensor_values.get(&kind) != Some(&value) {
+                        device.sensor_values.insert(kind, value);
+                        events.push(NetworkEvent::SensorValue {
+                            ieee_address,
+                            endpoint,
+                            kind,
+                            value,
+                        });
+                    }
+                }
+                (Some(DpKind::Switch), Value::Bool(on)) if device.state_on != Some(*on) => {
+                    device.state_on = Some(*on);
+                    events.push(NetworkEvent::DeviceStateChanged {
+                        ieee_address,
+                        endpoint,
+                        state_on: *on,
+                    });
+                }
+                (Some(DpKind::Occupancy), Value::Bool(occupied))
+                    if device.occupancy != Some(*occupied) =>
+                {
+                    device.occupancy = Some(*occupied);
+                    events.push(NetworkEvent::OccupancyChanged {
+                        ieee_address,
+                        endpoint,
+                        occupied: *occupied,
+                    });
+                }
+                _ => {}
+            }
+            device.tuya_values.insert(name, value);
+        }
+        events
+    })
+    .unwrap_or_default();
+    for event in events {
+        let _ = event_tx.send(event);
+    }
+}
+
+impl ZigbeeNetwork {
+    /// Set a named data point of a Tuya device
+    ///
+    /// Numbers are scaled by the data point's divisor and must fit the
+    /// device's 32-bit value; switches and flags take booleans. The value
+    /// sent is kept in `tuya_values`.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_tuya_value(
+        &self,
+        ieee: &[u8; 8],
+        name: &str,
+        value: &Value,
+    ) -> Result<(), NetworkError> {
+        let device = self
+            .get_device(ieee)
+            .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        let model = device
+            .manufacturer
+            .as_deref()
+            .and_then(model_for)
+            .ok_or_else(|| {
+                NetworkError::Configuration("device is not a known Tuya device".to_string())
+            })?;
+        let mapping = model
+            .datapoints
+            .iter()
+            .find(|d| d.name == name && d.kind.writable())
+            .ok_or_else(|| {
+                NetworkError::Configuration(format!("'{name}' is not a writable data point"))
+            })?;
+
+        let invalid = || NetworkError::Configuration(format!("invalid value for '{name}'"));
+        let dp_value = match mapping.kind {
+            DpKind::Switch | DpKind::Flag => DpValue::Bool(value.as_bool().ok_or_else(invalid)?),
+            DpKind::Enum => DpValue::Enum(
+                value
+                    .as_u64()
+                    .and_then(|v| u8::try_from(v).ok())
+                    .ok_or_else(invalid)?,
+            ),
+            _ => {
+                let scaled = (value.as_f64().ok_or_else(invalid)? * mapping.divisor).round();
+                if !scaled.is_finite() {
+                    return Err(invalid());
+                }
+                // Saturates, so anything out of range fails the conversion
+                #[allow(clippy::cast_possible_truncation)]
+                let raw = scaled as i64;
+                DpValue::Value(i32::try_from(raw).map_err(|_| invalid())?)
+            }
+        };
+        let stored = json_value(Some(mapping), &dp_value);
+
+        let endpoint = device
+            .endpoints
+            .iter()
+            .find(|e| e.in_clusters.contains(&CLUSTER_ID))
+            .map_or(1, |e| e.id);
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let payload = encode_data_request(
+            sequence,
+            &[DataPoint {
+                id: mapping.dp,
+                value: dp_value,
+            }],
+        );
+        let zcl_frame = ZclFrame::cluster_command(cluster::next_tsn(), command::DATA_REQUEST)
+            .with_payload(payload);
+        let request = ApsDataRequest::new(
+            1,
+            device.nwk_address,
+            endpoint,
+            CLUSTER_ID,
+            zcl_frame.serialize(),
+        );
+
+        tracing::info!(
+            "Setting Tuya DP {} ({}) of {:#06x} to {}",
+            mapping.dp,
+            name,
+            device.nwk_address,
+            value
+        );
+        self.transport().send_aps_request(request).await?;
+        self.update_device(ieee, |device| {
+            device.tuya_values.insert(name.to_string(), stored);
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deconz_protocol::mock::MockTransport;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_data_point_round_trip() {
+        let points = vec![
+            DataPoint {
+                id: 16,
+                value: DpValue::Value(215),
+            },
+            DataPoint {
+                id: 40,
+                value: DpValue::Bool(true),
+            },
+            DataPoint {
+                id: 2,
+                value: DpValue::Enum(1),
+            },
+        ];
+        let payload = encode_data_request(7, &points);
+        assert_eq!(
+            &payload[..10],
+            &[0x00, 0x07, 16, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0xD7]
+        );
+        assert_eq!(parse_data_points(&payload), points);
+
+        // Negative values and a truncated trailing record
+        let payload = [
+            0x00, 0x01, 24, 0x02, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xF6, 1, 1, 0, 1,
+        ];
+        assert_eq!(
+            parse_data_points(&payload),
+            vec![DataPoint {
+                id: 24,
+                value: DpValue::Value(-10),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_report_updates_mapped_values() {
+        let devices = DashMap::new();
+        let mut trv = ZigbeeDevice::new([1; 8], 0x1234);
+        trv.manufacturer = Some("_TZE200_hue3yfsn".to_string());
+        devices.insert([1; 8], trv);
+        let (event_tx, mut events) = broadcast::channel(8);
+        let indication = deconz_protocol::mock::indication(0x1234, CLUSTER_ID, Vec::new());
+        let mut asdu = vec![0x09, 0x10, command::DATA_REPORT];
+        asdu.extend(encode_data_request(
+            3,
+            &[
+                DataPoint {
+                    id: 24,
+                    value: DpValue::Value(205),
+                },
+                DataPoint {
+                    id: 99,
+                    value: DpValue::Enum(4),
+                },
+            ],
+        ));
+        let zcl = ZclFrame::parse(&asdu).unwrap();
+        handle_frame(&devices, &event_tx, None, &indication, &zcl);
+
+        assert!(matches!(
+            events.try_recv(),
+            Ok(NetworkEvent::DeviceUpdated { .. })
+        ));
+        match events.try_recv().unwrap() {
+            NetworkEvent::SensorValue { kind, value, .. } => {
+                assert_eq!(kind, SensorKind::Temperature);
+                assert!((value - 20.5).abs() < 1e-9);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+        let device = devices.get(&[1; 8]).unwrap();
+        assert_eq!(
+            device.tuya_values["local_temperature"],
+            serde_json::json!(20.5)
+        );
+        assert_eq!(device.tuya_values["dp_99"], serde_json::json!(4.0));
+    }
+
+    #[tokio::test]
+    async fn test_set_values_are_kept() {
+        let dir = std::env::temp_dir().join(format!("casita-tuya-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let open = || async {
+            let mock = Arc::new(MockTransport::new());
+            let network = ZigbeeNetwork::with_transport(mock.clone(), Some(dir.clone()))
+                .await
+                .unwrap();
+            (network, mock)
+        };
+        let (network, mock) = open().await;
+        let mut trv = ZigbeeDevice::new([1; 8], 0x1234);
+        trv.manufacturer = Some("_TZE200_hue3yfsn".to_string());
+        network.upsert_device(trv);
+
+        network
+            .set_tuya_value(&[1; 8], "heating_setpoint", &serde_json::json!(21.5))
+            .await
+            .unwrap();
+        let sent = ZclFrame::parse(&mock.aps_requests()[0].asdu).unwrap();
+        let points = parse_data_points(sent.payload());
+        assert_eq!(points[0].value, DpValue::Value(215));
+
+        // Too big for the device's 32-bit value
+        for bad in [serde_json::json!(3e8), serde_json::json!(-3e8)] {
+            assert!(matches!(
+                network
+                    .set_tuya_value(&[1; 8], "heating_setpoint", &bad)
+                    .await,
+                Err(NetworkError::Configuration(_))
+            ));
+        }
+        assert_eq!(mock.aps_requests().len(), 1);
+
+        // Saved with the device
+        let stored = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let (reopened, _) = open().await;
+                if let Some(value) = reopened
+                    .get_device(&[1; 8])
+                    .and_then(|d| d.tuya_values.get("heating_setpoint").cloned())
+                {
+                    break value;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(stored, serde_json::json!(21.5));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//! Channel utilization monitor
+//!
+//! The coordinator can't measure airtime, so the monitor samples what it can
+//! see every [`SAMPLE_INTERVAL`]: frames received, APS send attempts and
+//! failures (see [`ApsCounters`]) and how many neighbors came and went in the
+//! coordinator's neighbor table. A sample is busy when the failure share or
+//! the neighbor churn is over its threshold. After [`SUSTAINED_SAMPLES`] busy
+//! samples in a row the channel is flagged as congested and a
+//! [`NetworkEvent::ChannelCongestion`] is sent, and again once a quiet sample
+//! clears it. A day of samples is kept for the API.
+//!
+//! Congestion that doesn't go away is usually Wi-Fi on an overlapping
+//! channel; a channel scan (see [`crate::scan`]) shows which channels are
+//! quieter to move to.
+
+use crate::network::{NetworkEvent, ZigbeeNetwork};
+use deconz_protocol::{ApsCounters, DeconzEvent};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How often a sample is taken
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Busy samples in a row before the channel counts as congested (30 minutes)
+pub const SUSTAINED_SAMPLES: usize = 6;
+
+/// Samples kept (a day)
+const HISTORY_LEN: usize = 288;
+
+/// Share of failed APS attempts that makes a sample busy
+const FAILURE_THRESHOLD: f64 = 0.2;
+
+/// Neighbors joining or leaving, relative to the table size, that makes a
+/// sample busy
+const CHURN_THRESHOLD: f64 = 0.25;
+
+/// Below this many attempts the failure share is noise
+const MIN_ATTEMPTS: u64 = 10;
+
+/// Traffic seen during one sample interval
+#[derive(Debug, Clone, Serialize)]
+pub struct UtilizationSample {
+    /// Unix seconds
+    pub timestamp: u64,
+    pub channel: u8,
+    /// APS indications and MAC polls received
+    pub frames_received: u64,
+    pub aps_attempts: u64,
+    pub aps_failures: u64,
+    /// Entries in the coordinator's neighbor table (`None` if it couldn't be
+    /// read)
+    pub neighbors: Option<usize>,
+    /// Neighbors that appeared or dropped out since the previous sample
+    pub neighbor_churn: usize,
+    pub busy: bool,
+}
+
+impl UtilizationSample {
+    /// Share of APS attempts that failed
+    #[must_use]
+    pub fn failure_rate(&self) -> f64 {
+        failure_rate(self.aps_attempts, self.aps_failures)
+    }
+
+    fn is_busy(&self) -> bool {
+        let failing = self.aps_attempts >= MIN_ATTEMPTS && self.failure_rate() > FAILURE_THRESHOLD;
+        #[allow(clippy::cast_precision_loss)]
+        let churning = self.neighbors.is_some_and(|n| {
+            self.neighbor_churn > 1
+                && self.neighbor_churn as f64 / n.max(1) as f64 > CHURN_THRESHOLD
+        });
+        failing || churning
+    }
+}
+
+/// Rolling utilization history of the network's channel
+#[derive(Debug, Clone, Serialize)]
+pub struct UtilizationReport {
+    pub congested: bool,
+    /// Unix seconds
+    pub congested_since: Option<u64>,
+    /// Oldest first
+    pub samples: Vec<UtilizationSample>,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    samples: VecDeque<UtilizationSample>,
+    congested_since: Option<u64>,
+}
+
+impl History {
+    /// Add a sample; returns the new congestion state when it changed
+    fn push(&mut self, mut sample: UtilizationSample) -> Option<bool> {
+        sample.busy = sample.is_busy();
+        let busy = sample.busy;
+        let timestamp = sample.timestamp;
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        match self.congested_since {
+            Some(_) if !busy => {
+                self.congested_since = None;
+                Some(false)
+            }
+            None if self.recent().len() == SUSTAINED_SAMPLES
+                && self.recent().iter().all(|s| s.busy) =>
+            {
+                let first = self.recent().first().map_or(timestamp, |s| s.timestamp);
+                self.congested_since = Some(first);
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    /// The last [`SUSTAINED_SAMPLES`] samples
+    fn recent(&self) -> Vec<&UtilizationSample> {
+        self.samples
+            .iter()
+            .skip(self.samples.len().saturating_sub(SUSTAINED_SAMPLES))
+            .collect()
+    }
+
+    /// Failure share over the last [`SUSTAINED_SAMPLES`] samples
+    fn recent_failure_rate(&self) -> f64 {
+        let (attempts, failures) = self
+            .recent()
+            .iter()
+            .fold((0, 0), |(a, f), s| (a + s.aps_attempts, f + s.aps_failures));
+        failure_rate(attempts, failures)
+    }
+}
+
+#[allow(clippy::cast_precision_loss)] // counts stay far below 2^52
+fn failure_rate(attempts: u64, failures: u64) -> f64 {
+    if attempts == 0 {
+        0.0
+    } else {
+        failures as f64 / attempts as f64
+    }
+}
+
+/// Samples channel utilization in the background
+pub struct ChannelMonitor {
+    history: Mutex<History>,
+    /// Frames received since the last sample
+    frames: AtomicU64,
+}
+
+impl ChannelMonitor {
+    /// Start sampling `network`
+    #[must_use]
+    pub fn start(network: &Arc<ZigbeeNetwork>) -> Arc<Self> {
+        let monitor = Arc::new(Self {
+            history: Mutex::new(History::default()),
+            frames: AtomicU64::new(0),
+        });
+
+        let counter = Arc::clone(&monitor);
+        let mut rx = network.transport().subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(DeconzEvent::ApsIndication(_) | DeconzEvent::MacPoll { .. }) => {
+                        counter.frames.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let sampler = Arc::clone(&monitor);
+        let network = Arc::clone(network);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            let mut counters = network.transport().aps_counters();
+            let mut neighbors: Option<HashSet<[u8; 8]>> = None;
+            loop {
+                ticker.tick().await;
+                sampler
+                    .sample(&network, &mut counters, &mut neighbors)
+                    .await;
+            }
+        });
+
+        monitor
+    }
+
+    async fn sample(
+        &self,
+        network: &ZigbeeNetwork,
+        counters: &mut ApsCounters,
+        neighbors: &mut Option<HashSet<[u8; 8]>>,
+    ) {
+        let table = match network.neighbor_table(0x0000).await {
+            Ok(table) => Some(table.iter().map(|n| n.ieee_addr).collect::<HashSet<_>>()),
+            Err(e) => {
+                tracing::debug!("Failed to read coordinator neighbor table: {}", e);
+                None
+            }
+        };
+        let neighbor_churn = match (neighbors.as_ref(), table.as_ref()) {
+            (Some(before), Some(now)) => before.symmetric_difference(now).count(),
+            _ => 0,
+        };
+        let channel = network.transport().read_channel().await.unwrap_or(0);
+
+        // Read after the neighbor table request so its own traffic counts
+        let now = network.transport().aps_counters();
+        let sample = UtilizationSample {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            channel,
+            frames_received: self.frames.swap(0, Ordering::Relaxed),
+            aps_attempts: now.attempts.saturating_sub(counters.attempts),
+            aps_failures: now.failures.saturating_sub(counters.failures),
+            neighbors: table.as_ref().map(HashSet::len),
+            neighbor_churn,
+            busy: false,
+        };
+        *counters = now;
+        if table.is_some() {
+            *neighbors = table;
+        }
+
+        let (changed, failure_rate) = {
+            let mut history = self.lock();
+            let changed = history.push(sample);
+            (changed, history.recent_failure_rate())
+        };
+        match changed {
+            Some(true) => tracing::warn!(
+                "Channel {} looks congested ({:.0}% of APS sends failing); consider a channel scan",
+                channel,
+                failure_rate * 100.0
+            ),
+            Some(false) => tracing::info!("Channel {} is no longer congested", channel),
+            None => return,
+        }
+        network.emit(NetworkEvent::ChannelCongestion {
+            channel,
+            congested: changed == Some(true),
+            failure_rate,
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, History> {
+        self.history
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Current congestion state and sample history
+    #[must_use]
+    pub fn report(&self) -> UtilizationReport {
+        let history = self.lock();
+        UtilizationReport {
+            congested: history.congested_since.is_some(),
+            congested_since: history.congested_since,
+            samples: history.samples.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64, attempts: u64, failures: u64) -> UtilizationSample {
+        UtilizationSample {
+            timestamp,
+            channel: 15,
+            frames_received: 100,
+            aps_attempts: attempts,
+            aps_failures: failures,
+            neighbors: Some(8),
+            neighbor_churn: 0,
+            busy: false,
+        }
+    }
+
+    #[test]
+    fn test_congestion_needs_sustained_busy_samples() {
+        let mut history = History::default();
+        for t in 0..SUSTAINED_SAMPLES as u64 - 1 {
+            assert_eq!(history.push(sample(t, 50, 20)), None);
+        }
+        // A quiet sample resets the run
+        assert_eq!(history.push(sample(10, 50, 1)), None);
+        // Too few attempts to judge
+        assert_eq!(history.push(sample(11, 4, 4)), None);
+
+        let start = 20;
+        for t in start..start + SUSTAINED_SAMPLES as u64 - 1 {
+            assert_eq!(history.push(sample(t, 50, 20)), None);
+        }
+        assert_eq!(history.push(sample(30, 50, 20)), Some(true));
+        assert_eq!(history.congested_since, Some(start));
+        assert!((history.recent_failure_rate() - 0.4).abs() < f64::EPSILON);
+        assert_eq!(history.push(sample(31, 50, 20)), None);
+
+        // Neighbors dropping in and out also counts as busy
+        let mut churn = sample(32, 50, 0);
+        churn.neighbor_churn = 3;
+        assert_eq!(history.push(churn), None);
+
+        assert_eq!(history.push(sample(33, 50, 0)), Some(false));
+        assert_eq!(history.congested_since, None);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut history = History::default();
+        for t in 0..HISTORY_LEN as u64 + 10 {
+            history.push(sample(t, 0, 0));
+        }
+        assert_eq!(history.samples.len(), HISTORY_LEN);
+        assert_eq!(history.samples.front().map(|s| s.timestamp), Some(10));
+    }
+}
@@ -0,0 +1,93 @@
+//! Request/response helpers for ZDO commands
+//!
+//! ZDO responses arrive as regular APS indications; these helpers send a
+//! request and wait for the matching response (by cluster, source and TSN).
+//! Responses to broadcast requests are accepted from any source.
+
+use crate::network::{NetworkError, ZigbeeNetwork};
+use deconz_protocol::{
+    profiles, ApsDataRequest, DeconzEvent, NwkAddressResponse, ProtocolError, ZdoCluster,
+};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How long to wait for a ZDO response
+pub const ZDO_TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_TSN: AtomicU8 = AtomicU8::new(0x80);
+
+/// Allocate a transaction sequence number for a ZDO request
+pub(crate) fn next_tsn() -> u8 {
+    NEXT_TSN.fetch_add(1, Ordering::Relaxed)
+}
+
+impl ZigbeeNetwork {
+    /// Send a ZDO request and wait for the response ASDU
+    pub(crate) async fn zdo_request(
+        &self,
+        request: ApsDataRequest,
+        response_cluster: u16,
+        tsn: u8,
+    ) -> Result<Vec<u8>, NetworkError> {
+        let target = request.dest_short_addr;
+        let any_source = request.is_broadcast();
+        // Subscribe before sending so a fast response isn't missed
+        let mut rx = self.transport().subscribe();
+        self.transport().send_aps_request(request).await?;
+
+        let deadline = tokio::time::Instant::now() + ZDO_TIMEOUT;
+        loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(DeconzEvent::ApsIndication(indication)))
+                    if indication.profile_id == profiles::ZDO
+                        && indication.cluster_id == response_cluster
+                        && (any_source || indication.src_short_addr == target)
+                        && indication.asdu.first() == Some(&tsn) =>
+                {
+                    return Ok(indication.asdu);
+                }
+                Ok(Ok(_) | Err(broadcast::error::RecvError::Lagged(_))) => {}
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    return Err(NetworkError::NotConnected);
+                }
+                Err(_) => return Err(ProtocolError::Timeout.into()),
+            }
+        }
+    }
+
+    /// Look up the current short address of a device with a NWK Address
+    /// Request and store it
+    ///
+    /// Devices get a new short address when they rejoin; until they talk to
+    /// us again, commands to the stored one go nowhere.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn refresh_nwk_address(&self, ieee: &[u8; 8]) -> Result<u16, NetworkError> {
+        let tsn = next_tsn();
+        let request = ApsDataRequest::nwk_addr_request(tsn, *ieee, tsn);
+        let asdu = self
+            .zdo_request(request, ZdoCluster::NwkAddrRsp as u16, tsn)
+            .await?;
+        let response = NwkAddressResponse::parse(&asdu)?;
+        if response.status != 0 || response.ieee_addr != *ieee {
+            return Err(NetworkError::DeviceNotFound(format!(
+                "{ieee:02X?} (NWK_addr_req status {:#04x})",
+                response.status
+            )));
+        }
+
+        let nwk_address = response.nwk_addr;
+        self.update_device(ieee, |device| {
+            if device.nwk_address != nwk_address {
+                tracing::info!(
+                    "Device {:#06x} now has short address {:#06x}",
+                    device.nwk_address,
+                    nwk_address
+                );
+                device.nwk_address = nwk_address;
+            }
+        })
+        .ok_or_else(|| NetworkError::DeviceNotFound(format!("{ieee:02X?}")))?;
+        Ok(nwk_address)
+    }
+}
@@ -3,6 +3,7 @@
 import type {
   Device, NetworkStatus, SystemInfo, Camera, Automation, CreateAutomationRequest
 } from './types';
+import { getToken, promptForToken, withToken } from './auth';
 
 interface ApiResponse<T> {
   success: boolean;
@@ -18,17 +19,22 @@ class ApiClient {
     this.baseUrl = baseUrl ?? (import.meta.env.VITE_API_URL || '');
   }
 
-  private async request<T>(method: string, path: string, body?: unknown): Promise<T> {
-    const options: RequestInit = {
-      method,
-      headers: { 'Content-Type': 'application/json' },
-    };
+  private async request<T>(method: string, path: string, body?: unknown, retried = false): Promise<T> {
+    const headers: Record<string, string> = { 'Content-Type': 'application/json' };
+    const token = getToken();
+    if (token) {
+      headers['Authorization'] = `Bearer ${token}`;
+    }
+    const options: RequestInit = { method, headers };
 
     if (body) {
       options.body = JSON.stringify(body);
     }
 
     const response = await fetch(`${this.baseUrl}${path}`, options);
+    if (response.status === 401 && !retried && promptForToken()) {
+      return this.request(method, path, body, true);
+    }
     const data: ApiResponse<T> = await response.json();
 
     if (!data.success) {
@@ -124,7 +130,7 @@ class ApiClient {
   }
 
   getCameraStreamUrl(id: string): string {
-    return `${this.baseUrl}/api/v1/cameras/${id}/stream`;
+    return withToken(`${this.baseUrl}/api/v1/cameras/${id}/stream`);
   }
 
   // Automations
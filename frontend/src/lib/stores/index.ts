@@ -205,22 +205,36 @@ let permitJoinTimer: ReturnType<typeof setInterval> | null = null;
 export async function startPermitJoin(duration = 60): Promise<void> {
   try {
     await api.permitJoin(duration);
-    permitJoinActive.set(true);
-    permitJoinRemaining.set(duration);
-
-    if (permitJoinTimer) clearInterval(permitJoinTimer);
-    permitJoinTimer = setInterval(() => {
-      permitJoinRemaining.update(r => {
-        if (r <= 1) {
-          clearInterval(permitJoinTimer!);
-          permitJoinTimer = null;
-          permitJoinActive.set(false);
-          return 0;
-        }
-        return r - 1;
-      });
-    }, 1000);
+    applyPermitJoin(duration > 0, duration);
   } catch (e) {
     lastError.set(`Failed to permit join: ${e}`);
   }
 }
+
+// Pushed over the WebSocket when joining opens or closes
+export function applyPermitJoin(open: boolean, duration: number): void {
+  networkStatus.update(s => (s ? { ...s, permit_join: open } : s));
+  permitJoinActive.set(open);
+  permitJoinRemaining.set(open ? duration : 0);
+
+  if (permitJoinTimer) clearInterval(permitJoinTimer);
+  permitJoinTimer = null;
+  // 255 keeps the network open until it is closed
+  if (!open || duration >= 255) return;
+  permitJoinTimer = setInterval(() => {
+    permitJoinRemaining.update(r => {
+      if (r <= 1) {
+        clearInterval(permitJoinTimer!);
+        permitJoinTimer = null;
+        permitJoinActive.set(false);
+        return 0;
+      }
+      return r - 1;
+    });
+  }, 1000);
+}
+
+// Pushed over the WebSocket when the coordinator goes online or offline
+export function applyNetworkState(connected: boolean): void {
+  networkStatus.update(s => (s ? { ...s, connected } : s));
+}
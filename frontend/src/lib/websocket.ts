@@ -2,6 +2,7 @@
 
 import { writable } from 'svelte/store';
 import type { WsEvent } from './types';
+import { withToken } from './auth';
 
 export const wsConnected = writable(false);
 
@@ -29,7 +30,7 @@ class WebSocketManager {
 
   connect(): void {
     console.log('[WS] Connecting to:', this.url);
-    this.ws = new WebSocket(this.url);
+    this.ws = new WebSocket(withToken(this.url));
 
     this.ws.onopen = () => {
       console.log('[WS] Connected');
@@ -1,23 +1,30 @@
 // API client for Casita Assistant
 
+import { getToken, promptForToken, withToken } from './auth.js';
+
 export class Api {
     constructor(baseUrl = '') {
         this.baseUrl = baseUrl;
     }
 
-    async request(method, path, body = null) {
-        const options = {
-            method,
-            headers: {
-                'Content-Type': 'application/json',
-            },
+    async request(method, path, body = null, retried = false) {
+        const headers = {
+            'Content-Type': 'application/json',
         };
+        const token = getToken();
+        if (token) {
+            headers['Authorization'] = `Bearer ${token}`;
+        }
+        const options = { method, headers };
 
         if (body) {
             options.body = JSON.stringify(body);
         }
 
         const response = await fetch(`${this.baseUrl}${path}`, options);
+        if (response.status === 401 && !retried && promptForToken()) {
+            return this.request(method, path, body, true);
+        }
         const data = await response.json();
 
         if (!data.success) {
@@ -118,7 +125,7 @@ export class Api {
     }
 
     getCameraStreamUrl(id) {
-        return `${this.baseUrl}/api/v1/cameras/${id}/stream`;
+        return withToken(`${this.baseUrl}/api/v1/cameras/${id}/stream`);
     }
 
     // Automation endpoints
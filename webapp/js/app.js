@@ -129,6 +129,11 @@ class App {
             this.updateNetworkStatus(event.connected);
         });
 
+        this.ws.on('permit_join_changed', (event) => {
+            document.getElementById('net-permitjoin').textContent =
+                event.permit_join ? 'Yes' : 'No';
+        });
+
         // Automation events
         this.ws.on('automation_triggered', (event) => {
             console.log('Automation triggered:', event.automation_id, event.trigger_reason);
@@ -0,0 +1,34 @@
+// Access token storage for Casita Assistant
+//
+// When the hub runs with API_TOKEN set, every API call needs the token. It is
+// sent as a bearer token, or as `?token=` where headers can't be set
+// (WebSocket upgrades and camera streams).
+
+const STORAGE_KEY = 'casita_api_token';
+
+export function getToken() {
+    return localStorage.getItem(STORAGE_KEY);
+}
+
+export function setToken(token) {
+    if (token) {
+        localStorage.setItem(STORAGE_KEY, token);
+    } else {
+        localStorage.removeItem(STORAGE_KEY);
+    }
+}
+
+// Add the stored token to a URL as `?token=`
+export function withToken(url) {
+    const token = getToken();
+    if (!token) return url;
+    return `${url}${url.includes('?') ? '&' : '?'}token=${token}`;
+}
+
+// Ask for a token after the hub rejected the stored one; false if none was given
+export function promptForToken() {
+    const token = window.prompt('This hub requires an access token (its API_TOKEN):');
+    if (!token || !token.trim()) return false;
+    setToken(token.trim());
+    return true;
+}
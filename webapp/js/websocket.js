@@ -1,5 +1,7 @@
 // WebSocket manager for Casita Assistant
 
+import { withToken } from './auth.js';
+
 export class WebSocketManager {
     constructor(url) {
         this.url = url;
@@ -12,7 +14,7 @@ export class WebSocketManager {
 
     connect() {
         console.log('Connecting to WebSocket:', this.url);
-        this.ws = new WebSocket(this.url);
+        this.ws = new WebSocket(withToken(this.url));
 
         this.ws.onopen = () => {
             console.log('WebSocket connected');